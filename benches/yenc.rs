@@ -0,0 +1,97 @@
+//! Throughput of the yEnc decoder on article bodies
+//!
+//! Run with `cargo bench --bench yenc`; criterion reports MB/s of encoded input.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use dl_nzb::nntp::decode_yenc;
+
+/// Encoded size of a typical article's data
+const SEGMENT: usize = 750 * 1024;
+const LINE: usize = 128;
+
+/// yEnc-encode `data` as one part of a post, wrapped at 128 columns
+fn encode(data: &[u8]) -> Vec<u8> {
+    let mut out = format!(
+        "=ybegin part=1 line={} size={} name=bench.bin\r\n=ypart begin=1 end={}\r\n",
+        LINE,
+        data.len(),
+        data.len()
+    )
+    .into_bytes();
+    let mut column = 0;
+    for &byte in data {
+        let encoded = byte.wrapping_add(42);
+        if matches!(encoded, 0 | b'\n' | b'\r' | b'=')
+            || (column == 0 && matches!(encoded, b'\t' | b' ' | b'.'))
+        {
+            out.extend_from_slice(&[b'=', encoded.wrapping_add(64)]);
+            column += 2;
+        } else {
+            out.push(encoded);
+            column += 1;
+        }
+        if column >= LINE {
+            out.extend_from_slice(b"\r\n");
+            column = 0;
+        }
+    }
+    out.extend_from_slice(
+        format!(
+            "\r\n=yend size={} part=1 pcrc32={:08x}\r\n",
+            data.len(),
+            crc32fast::hash(data)
+        )
+        .as_bytes(),
+    );
+    out
+}
+
+/// Bytes that look like compressed data: few escapes, about 1 in 64
+fn clean() -> Vec<u8> {
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    (0..SEGMENT)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
+
+/// Bytes that mostly need escaping (zeros, CR, LF and '=' once encoded)
+fn escaped() -> Vec<u8> {
+    let critical = [214u8, 224, 227, 19];
+    clean()
+        .into_iter()
+        .enumerate()
+        .map(|(i, b)| if i % 4 == 3 { b } else { critical[i % 4] })
+        .collect()
+}
+
+/// Nothing but escapes, hand-built so every line is `=` pairs
+fn all_escapes() -> Vec<u8> {
+    let mut body = b"=ybegin line=128 size=0 name=bench.bin\r\n".to_vec();
+    while body.len() < SEGMENT {
+        body.extend_from_slice(&b"=@".repeat(LINE / 2));
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(b"=yend size=0\r\n");
+    body
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode_yenc");
+    for (name, body) in [
+        ("clean", encode(&clean())),
+        ("escaped", encode(&escaped())),
+        ("all_escapes", all_escapes()),
+    ] {
+        group.throughput(Throughput::Bytes(body.len() as u64));
+        group.bench_function(name, |b| b.iter(|| decode_yenc(black_box(&body))));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_decode);
+criterion_main!(benches);
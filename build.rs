@@ -0,0 +1,39 @@
+//! Writes the cargo features declared in Cargo.toml to `$OUT_DIR/features.rs`,
+//! so a test can check that `capabilities()` reports every one of them
+
+use std::path::Path;
+
+fn main() {
+    println!("cargo:rerun-if-changed=Cargo.toml");
+    let dir = std::env::var_os("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR is set by cargo");
+    let manifest = std::fs::read_to_string(Path::new(&dir).join("Cargo.toml"))
+        .expect("Cargo.toml is readable");
+
+    let mut features = Vec::new();
+    let mut in_features = false;
+    for line in manifest.lines().map(str::trim) {
+        if line.starts_with('[') && !line.starts_with("[\"") {
+            in_features = line == "[features]";
+            continue;
+        }
+        // Feature names start a line; the lists they enable may span several
+        let Some((name, _)) = line.split_once('=').filter(|_| in_features) else {
+            continue;
+        };
+        let name = name.trim();
+        if !name.starts_with(['#', '"']) && name != "default" {
+            features.push(name.to_string());
+        }
+    }
+
+    let out = Path::new(&std::env::var_os("OUT_DIR").expect("OUT_DIR is set by cargo"))
+        .join("features.rs");
+    std::fs::write(
+        out,
+        format!(
+            "/// Features declared in Cargo.toml, besides `default`\npub const CARGO_FEATURES: &[&str] = &{:?};\n",
+            features
+        ),
+    )
+    .expect("OUT_DIR is writable");
+}
@@ -0,0 +1,48 @@
+//! Download one NZB with the settings from the usual config file
+//!
+//! ```sh
+//! cargo run --example simple -- some.release.nzb
+//! ```
+
+use dl_nzb::DownloadOptions;
+
+#[tokio::main]
+async fn main() -> dl_nzb::Result<()> {
+    let Some(nzb) = std::env::args().nth(1) else {
+        eprintln!("usage: simple <file.nzb | url>");
+        std::process::exit(2);
+    };
+
+    let outcome = dl_nzb::download(&nzb, DownloadOptions::new()).await?;
+
+    for result in &outcome.download.results {
+        println!(
+            "{} ({} of {} segments)",
+            result.path.display(),
+            result.segments_downloaded,
+            result.segments_downloaded + result.segments_failed
+        );
+    }
+    if let Some(post) = &outcome.post_processing {
+        println!(
+            "PAR2 {}, RAR {}",
+            if post.par2_repaired {
+                "repaired"
+            } else if post.par2_verified {
+                "verified"
+            } else {
+                "not used"
+            },
+            if post.rar_extracted {
+                "extracted"
+            } else {
+                "not extracted"
+            }
+        );
+    }
+    if !outcome.is_complete() {
+        eprintln!("{} is incomplete", outcome.name);
+        std::process::exit(1);
+    }
+    Ok(())
+}
@@ -0,0 +1,14 @@
+//! Feed arbitrary article bodies through the yEnc decoder
+//!
+//! Run with `cargo +nightly fuzz run decode_yenc` from the repository root.
+
+#![no_main]
+
+use dl_nzb::nntp::{decode_yenc, yenc_checksum_matches};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let decoded = decode_yenc(data);
+    assert!(decoded.len() <= data.len());
+    let _ = yenc_checksum_matches(data, &decoded);
+});
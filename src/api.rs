@@ -0,0 +1,578 @@
+//! JSON HTTP API for `watch`
+//!
+//! With `[api] listen` set and the `server` feature, `watch` answers on that
+//! address:
+//!
+//! - `GET /status`: the NZB downloading, how far it has got and how fast,
+//!   how many NZBs wait behind it and the last few that finished
+//! - `GET /queue`: every queued NZB
+//! - `POST /queue`: queue the NZB path or URL in the body (503 once `watch`
+//!   is shutting down)
+//! - `DELETE /queue/{id}`: take a waiting NZB off the queue
+//! - `GET /history`: finished NZBs, newest first
+//! - `POST /limits`: change the speed cap and connection count while
+//!   downloading, with a JSON body such as
+//!   `{"max_speed": 5000000, "connections": 8}`; either may be left out,
+//!   `max_speed` is in bytes per second (0 = unlimited) and `null` goes back
+//!   to the configured cap. The limits in force come back, and are also in
+//!   `GET /status`
+//!
+//! Nothing is kept here: the queue and progress come from the run's
+//! [`AppState`], and the history is the history file, so the API sees what
+//! the terminal and `dl-nzb history` see. NZBs queued here are
+//! picked up at the next poll of the watched folder. With `api.token` set,
+//! every request needs `Authorization: Bearer <token>`.
+
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::json;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::config::{ApiConfig, Secret};
+use crate::download::NzbSource;
+use crate::error::DlNzbError;
+use crate::history::History;
+use crate::queue::{Queue, QueueStatus};
+use crate::state::AppState;
+
+type Result<T> = std::result::Result<T, DlNzbError>;
+
+/// Largest request body read (an NZB path or URL)
+#[cfg(feature = "server")]
+const MAX_BODY: usize = 64 * 1024;
+
+/// What the handlers read and change
+pub struct ApiState {
+    state: Arc<AppState>,
+    history: History,
+    token: Secret,
+}
+
+/// Body of `POST /limits`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LimitsRequest {
+    /// Absent: unchanged; `null`: back to the configured cap
+    #[serde(default, deserialize_with = "present")]
+    max_speed: Option<Option<u64>>,
+    connections: Option<usize>,
+}
+
+/// Tell a field sent as `null` from one left out
+fn present<'de, D: Deserializer<'de>>(
+    value: D,
+) -> std::result::Result<Option<Option<u64>>, D::Error> {
+    Option::deserialize(value).map(Some)
+}
+
+/// Status code and JSON body of a response
+#[derive(Debug)]
+pub struct Reply {
+    pub status: u16,
+    pub body: serde_json::Value,
+}
+
+impl Reply {
+    fn ok(status: u16, body: impl Serialize) -> Self {
+        match serde_json::to_value(body) {
+            Ok(body) => Self { status, body },
+            Err(e) => Self::error(500, e),
+        }
+    }
+
+    fn error(status: u16, message: impl std::fmt::Display) -> Self {
+        Self {
+            status,
+            body: json!({ "error": message.to_string() }),
+        }
+    }
+}
+
+impl ApiState {
+    pub fn new(state: Arc<AppState>, history: History, token: Secret) -> Self {
+        Self {
+            state,
+            history,
+            token,
+        }
+    }
+
+    /// Answer one request (blocking: the queue and history are files)
+    ///
+    /// `authorization` is the header as sent, `body` the request body.
+    pub fn handle(
+        &self,
+        method: &str,
+        path: &str,
+        authorization: Option<&str>,
+        body: &str,
+    ) -> Reply {
+        if !self.token.is_empty()
+            && authorization.and_then(|h| h.strip_prefix("Bearer ")) != Some(self.token.expose())
+        {
+            return Reply::error(401, "missing or wrong bearer token");
+        }
+        let Some(queue) = self.state.queue() else {
+            return Reply::error(503, "the download queue is unavailable");
+        };
+        let reply = match (method, path.trim_end_matches('/')) {
+            ("GET", "/status") => self.status(&queue),
+            ("GET", "/queue") => queue.entries().map(|e| Reply::ok(200, e)),
+            ("POST", "/queue") if self.state.shutdown().is_triggered() => {
+                Ok(Reply::error(503, "shutting down"))
+            }
+            ("POST", "/queue") => Self::enqueue(&queue, body.trim()),
+            ("DELETE", path) if path.starts_with("/queue/") => {
+                Self::remove(&queue, &path["/queue/".len()..])
+            }
+            ("GET", "/history") => self.history(),
+            ("POST", "/limits") => Ok(self.set_limits(body)),
+            (_, "/status" | "/queue" | "/history" | "/limits") => {
+                Ok(Reply::error(405, "method not allowed"))
+            }
+            (_, path) if path.starts_with("/queue/") => Ok(Reply::error(405, "method not allowed")),
+            _ => Ok(Reply::error(404, "not found")),
+        };
+        reply.unwrap_or_else(|e| Reply::error(500, e))
+    }
+
+    fn status(&self, queue: &Queue) -> Result<Reply> {
+        let entries = queue.entries()?;
+        let ours = entries.iter().filter(|e| e.owner == std::process::id());
+        let current = ours.clone().find(|e| e.status == QueueStatus::Active);
+        let queued = ours.filter(|e| e.status == QueueStatus::Pending).count();
+        Ok(Reply::ok(
+            200,
+            json!({
+                "current": current,
+                "progress": current.and_then(|_| self.state.snapshot()),
+                "queued": queued,
+                "recent": self.state.recent(),
+                "limits": self.state.limits_snapshot(),
+            }),
+        ))
+    }
+
+    fn set_limits(&self, body: &str) -> Reply {
+        let Some(limits) = self.state.limits() else {
+            return Reply::error(503, "no download is running");
+        };
+        let request: LimitsRequest = match serde_json::from_str(body) {
+            Ok(request) => request,
+            Err(e) => return Reply::error(400, format!("invalid limits: {}", e)),
+        };
+        match request.connections {
+            Some(0) => return Reply::error(400, "connections must be at least 1"),
+            Some(_) if limits.snapshot().connections.is_none() => {
+                return Reply::error(409, "the connection count can't be changed")
+            }
+            Some(connections) => {
+                limits.set_connections(connections);
+            }
+            None => {}
+        }
+        if let Some(max_speed) = request.max_speed {
+            limits.set_max_speed(max_speed);
+        }
+        let snapshot = limits.snapshot();
+        tracing::info!(
+            max_speed = snapshot.max_speed,
+            connections = ?snapshot.connections,
+            "Limits changed through the API"
+        );
+        Reply::ok(200, snapshot)
+    }
+
+    fn enqueue(queue: &Queue, nzb: &str) -> Result<Reply> {
+        if nzb.is_empty() {
+            return Ok(Reply::error(400, "expected an NZB path or URL as the body"));
+        }
+        let nzb = PathBuf::from(nzb);
+        match NzbSource::from_arg(&nzb) {
+            NzbSource::Stdin => return Ok(Reply::error(400, "stdin can't be queued")),
+            NzbSource::File(path) if !path.is_file() => {
+                return Ok(Reply::error(
+                    400,
+                    format!("{} is not a file", path.display()),
+                ))
+            }
+            _ => {}
+        }
+        let entry = queue.push(&[nzb])?.remove(0);
+        Ok(Reply::ok(201, entry))
+    }
+
+    fn remove(queue: &Queue, id: &str) -> Result<Reply> {
+        let Ok(id) = id.parse() else {
+            return Ok(Reply::error(400, format!("'{}' is not a queue id", id)));
+        };
+        Ok(match queue.remove(id)? {
+            None => Reply::error(404, format!("no queue entry {}", id)),
+            Some(entry) if entry.status == QueueStatus::Active => {
+                Reply::error(409, format!("{} is downloading", entry.display_name()))
+            }
+            Some(entry) => Reply::ok(200, entry),
+        })
+    }
+
+    fn history(&self) -> Result<Reply> {
+        let entries: Vec<_> = self.history.entries()?.into_iter().rev().collect();
+        Ok(Reply::ok(200, entries))
+    }
+}
+
+/// A running API listener; stops when dropped
+pub struct ApiServer {
+    addr: SocketAddr,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl ApiServer {
+    /// Start the API when `[api] listen` is set
+    ///
+    /// A build without the `server` feature only warns that it is unavailable.
+    pub async fn start(config: &ApiConfig, state: ApiState) -> Result<Option<Self>> {
+        if config.listen.is_empty() {
+            return Ok(None);
+        }
+        if !cfg!(feature = "server") {
+            tracing::warn!("api.listen is set, but dl-nzb was built without the server feature");
+            return Ok(None);
+        }
+
+        let invalid = |reason: String| crate::error::ConfigError::Invalid {
+            field: "api.listen".to_string(),
+            reason,
+        };
+        let addr: SocketAddr = config
+            .listen
+            .parse()
+            .map_err(|e| invalid(format!("{}: {}", config.listen, e)))?;
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .map_err(|e| invalid(format!("cannot listen on {}: {}", addr, e)))?;
+        let addr = listener.local_addr()?;
+
+        let task = tokio::spawn(serve(listener, Arc::new(state)));
+        tracing::info!("Serving the API on http://{}/", addr);
+        Ok(Some(Self { addr, task }))
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for ApiServer {
+    fn drop(&mut self) {
+        // Closes the listener and every open connection
+        self.task.abort();
+    }
+}
+
+/// Answer requests until aborted
+#[cfg(feature = "server")]
+async fn serve(listener: tokio::net::TcpListener, state: Arc<ApiState>) {
+    use bytes::Bytes;
+    use http_body_util::{BodyExt, Full, LengthLimitError, Limited};
+    use hyper::{header, Request, Response};
+
+    let mut connections = tokio::task::JoinSet::new();
+
+    loop {
+        tokio::select! {
+            Some(_) = connections.join_next(), if !connections.is_empty() => {}
+            accepted = listener.accept() => {
+                let stream = match accepted {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        tracing::debug!("API accept failed: {}", e);
+                        continue;
+                    }
+                };
+                let state = state.clone();
+                let service = hyper::service::service_fn(move |request: Request<_>| {
+                    let state = state.clone();
+                    async move {
+                        let (parts, body) = request.into_parts();
+                        let reply = match Limited::new(body, MAX_BODY).collect().await {
+                            Ok(body) => {
+                                let body = String::from_utf8_lossy(&body.to_bytes()).into_owned();
+                                let authorization = parts
+                                    .headers
+                                    .get(header::AUTHORIZATION)
+                                    .and_then(|value| value.to_str().ok())
+                                    .map(str::to_string);
+                                tokio::task::spawn_blocking(move || {
+                                    state.handle(
+                                        parts.method.as_str(),
+                                        parts.uri.path(),
+                                        authorization.as_deref(),
+                                        &body,
+                                    )
+                                })
+                                .await
+                                .unwrap_or_else(|e| Reply::error(500, e))
+                            }
+                            Err(e) if e.is::<LengthLimitError>() => {
+                                Reply::error(413, "request body too large")
+                            }
+                            Err(e) => Reply::error(400, e),
+                        };
+                        Response::builder()
+                            .status(reply.status)
+                            .header(header::CONTENT_TYPE, "application/json")
+                            .body(Full::new(Bytes::from(format!("{}\n", reply.body))))
+                    }
+                });
+                connections.spawn(async move {
+                    let io = hyper_util::rt::TokioIo::new(stream);
+                    if let Err(e) = hyper::server::conn::http1::Builder::new()
+                        .serve_connection(io, service)
+                        .await
+                    {
+                        tracing::debug!("API connection error: {}", e);
+                    }
+                });
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "server"))]
+async fn serve(_listener: tokio::net::TcpListener, _state: Arc<ApiState>) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::download::{DownloadEvent, Limits, Throttle};
+    use crate::error::RunStatus;
+    use crate::state::FinishedNzb;
+
+    /// State halfway through an NZB, after one other finished
+    fn halfway() -> AppState {
+        let state = AppState::new();
+        state.finish_nzb(FinishedNzb {
+            nzb: PathBuf::from("earlier.nzb"),
+            status: RunStatus::Success,
+            total_size: 1000,
+        });
+        for event in [
+            DownloadEvent::NzbStarted {
+                nzb: PathBuf::from("show.nzb"),
+            },
+            DownloadEvent::QueueStarted {
+                files: 4,
+                total_bytes: 1000,
+            },
+            DownloadEvent::Progress {
+                bytes: 500,
+                transferred: 520,
+            },
+        ] {
+            state.record(&event);
+        }
+        state
+    }
+
+    fn state(dir: &std::path::Path, state: AppState, token: &str) -> ApiState {
+        state.set_queue(Arc::new(Queue::open(dir.join("queue.jsonl")).unwrap()));
+        ApiState::new(
+            Arc::new(state),
+            History::new(dir.join("history.jsonl")),
+            Secret::new(token),
+        )
+    }
+
+    #[test]
+    fn test_queue_and_status() {
+        let dir = tempfile::tempdir().unwrap();
+        let nzb = dir.path().join("show.nzb");
+        std::fs::write(&nzb, "<nzb/>").unwrap();
+        let api = state(dir.path(), halfway(), "");
+
+        let added = api.handle("POST", "/queue", None, &format!("{}\n", nzb.display()));
+        assert_eq!(added.status, 201);
+        assert_eq!(added.body["id"], 1);
+        let added = api.handle("POST", "/queue", None, "https://indexer.example/getnzb/2");
+        assert_eq!(added.body["id"], 2);
+        assert_eq!(api.handle("POST", "/queue", None, "-").status, 400);
+        assert_eq!(
+            api.handle("POST", "/queue", None, "/no/such.nzb").status,
+            400
+        );
+
+        // Nothing downloading yet: no progress either
+        let status = api.handle("GET", "/status", None, "");
+        assert_eq!(status.body["queued"], 2);
+        assert!(status.body["progress"].is_null());
+        assert_eq!(status.body["recent"][0]["nzb"], "earlier.nzb");
+        assert_eq!(status.body["recent"][0]["status"], "success");
+
+        api.state
+            .queue()
+            .unwrap()
+            .update(1, |e| e.status = QueueStatus::Active)
+            .unwrap();
+        let status = api.handle("GET", "/status", None, "");
+        assert_eq!(status.body["current"]["id"], 1);
+        assert_eq!(status.body["progress"]["files"], 4);
+        assert_eq!(status.body["progress"]["bytes_done"], 500);
+        assert_eq!(status.body["queued"], 1);
+
+        assert_eq!(api.handle("DELETE", "/queue/1", None, "").status, 409);
+        assert_eq!(api.handle("DELETE", "/queue/2", None, "").status, 200);
+        assert_eq!(api.handle("DELETE", "/queue/2", None, "").status, 404);
+        assert_eq!(api.handle("DELETE", "/queue/two", None, "").status, 400);
+        let queue = api.handle("GET", "/queue", None, "");
+        assert_eq!(queue.body.as_array().unwrap().len(), 1);
+
+        assert_eq!(api.handle("GET", "/history", None, "").body, json!([]));
+        assert_eq!(api.handle("PUT", "/queue", None, "").status, 405);
+        assert_eq!(api.handle("GET", "/nzbs", None, "").status, 404);
+
+        // Draining: nothing new is taken, the rest still answers
+        api.state.shutdown().trigger();
+        assert_eq!(
+            api.handle("POST", "/queue", None, "https://x/1").status,
+            503
+        );
+        assert_eq!(api.handle("GET", "/status", None, "").status, 200);
+    }
+
+    #[test]
+    fn test_bearer_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let api = state(dir.path(), AppState::new(), "t0ken");
+
+        assert_eq!(api.handle("GET", "/status", None, "").status, 401);
+        assert_eq!(
+            api.handle("GET", "/status", Some("Bearer wrong"), "")
+                .status,
+            401
+        );
+        assert_eq!(
+            api.handle("GET", "/status", Some("Bearer t0ken"), "")
+                .status,
+            200
+        );
+    }
+
+    #[test]
+    fn test_limits_change_at_runtime() {
+        let dir = tempfile::tempdir().unwrap();
+        let throttle = Throttle::new(0);
+        throttle.set_limit(5_000_000, false);
+        let limits = Limits::new(throttle.clone(), None);
+        let api = state(dir.path(), AppState::new().with_limits(limits), "");
+
+        let status = api.handle("GET", "/status", None, "");
+        assert_eq!(status.body["limits"]["max_speed"], 5_000_000);
+        assert_eq!(status.body["limits"]["speed_overridden"], false);
+
+        let changed = api.handle("POST", "/limits", None, r#"{"max_speed": 0}"#);
+        assert_eq!(changed.status, 200);
+        assert_eq!(changed.body["max_speed"], 0);
+        assert_eq!(throttle.limit(), 0);
+        let status = api.handle("GET", "/status", None, "");
+        assert_eq!(status.body["limits"]["speed_overridden"], true);
+
+        // Leaving it out changes nothing, null restores the configured cap
+        assert_eq!(
+            api.handle("POST", "/limits", None, "{}").body["max_speed"],
+            0
+        );
+        let restored = api.handle("POST", "/limits", None, r#"{"max_speed": null}"#);
+        assert_eq!(restored.body["max_speed"], 5_000_000);
+        assert!(!throttle.is_overridden());
+
+        for bad in [
+            "",
+            "5",
+            r#"{"max_speed": -1}"#,
+            r#"{"speed": 1}"#,
+            r#"{"connections": 0}"#,
+        ] {
+            assert_eq!(
+                api.handle("POST", "/limits", None, bad).status,
+                400,
+                "{}",
+                bad
+            );
+        }
+        // No connection limit to change: the speed stays as it was too
+        let refused = api.handle(
+            "POST",
+            "/limits",
+            None,
+            r#"{"max_speed": 1, "connections": 4}"#,
+        );
+        assert_eq!(refused.status, 409);
+        assert_eq!(throttle.limit(), 5_000_000);
+        assert_eq!(api.handle("GET", "/limits", None, "").status, 405);
+
+        let idle = state(dir.path(), AppState::new(), "");
+        assert_eq!(idle.handle("POST", "/limits", None, "{}").status, 503);
+        assert!(idle.handle("GET", "/status", None, "").body["limits"].is_null());
+    }
+
+    #[test]
+    fn test_no_queue_is_unavailable() {
+        let api = ApiState::new(
+            Arc::new(AppState::new()),
+            History::in_memory(),
+            Secret::new(""),
+        );
+        assert_eq!(api.handle("GET", "/status", None, "").status, 503);
+    }
+
+    #[cfg(feature = "server")]
+    #[tokio::test]
+    async fn test_api_endpoint() {
+        use std::io::{Read, Write};
+
+        let dir = tempfile::tempdir().unwrap();
+        let config = ApiConfig {
+            listen: "127.0.0.1:0".to_string(),
+            ..Default::default()
+        };
+        let server = ApiServer::start(&config, state(dir.path(), AppState::new(), ""))
+            .await
+            .unwrap()
+            .unwrap();
+
+        let addr = server.addr();
+        let request = |request: String| {
+            tokio::task::spawn_blocking(move || {
+                let mut stream = std::net::TcpStream::connect(addr).unwrap();
+                stream.write_all(request.as_bytes()).unwrap();
+                let mut response = String::new();
+                stream.read_to_string(&mut response).unwrap();
+                response
+            })
+        };
+        let url = "https://indexer.example/getnzb/1";
+        let posted = request(format!(
+            "POST /queue HTTP/1.1\r\nHost: x\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            url.len(),
+            url
+        ))
+        .await
+        .unwrap();
+        assert!(posted.starts_with("HTTP/1.1 201"), "{}", posted);
+
+        let queue =
+            request("GET /queue HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n".to_string())
+                .await
+                .unwrap();
+        assert!(queue.starts_with("HTTP/1.1 200"), "{}", queue);
+        assert!(queue.contains("application/json"));
+        assert!(queue.contains(url));
+
+        drop(server);
+        tokio::task::yield_now().await;
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(std::net::TcpStream::connect(addr).is_err());
+    }
+}
@@ -0,0 +1,126 @@
+//! What this build of dl-nzb can do
+//!
+//! Frontends that link the library adapt to the build they got: a GUI hides
+//! its keyring option without the `keyring` feature, or tells the user to
+//! install `par2` without the builtin backend. Every cargo feature is listed
+//! in [`FEATURES`], driven by `cfg!`; a test checks it against the features
+//! declared in Cargo.toml, so a new one can't be added without showing up
+//! here. `dl-nzb version --detailed` prints the same.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::json_output::{EVENT_SCHEMA_VERSION, LIST_SCHEMA_VERSION, RUN_SCHEMA_VERSION};
+
+/// Every cargo feature and whether this build has it
+pub const FEATURES: &[(&str, bool)] = &[
+    ("cli", cfg!(feature = "cli")),
+    ("builtin-par2", cfg!(feature = "builtin-par2")),
+    ("vendored-openssl", cfg!(feature = "vendored-openssl")),
+    ("keyring", cfg!(feature = "keyring")),
+    (
+        "desktop-notifications",
+        cfg!(feature = "desktop-notifications"),
+    ),
+    ("metrics", cfg!(feature = "metrics")),
+    ("server", cfg!(feature = "server")),
+];
+
+/// Compile-time features of this build, for frontends
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities {
+    /// Crate version, e.g. `0.2.0`
+    pub version: String,
+    /// Operating system and CPU architecture it was built for
+    pub os: String,
+    pub arch: String,
+    /// TLS implementation: `native-tls` (OpenSSL on Linux, the system's
+    /// elsewhere), with `vendored-openssl` built from source
+    pub tls_backend: String,
+    /// `builtin` (par2-rs, in process) or `external` (a `par2` binary on
+    /// PATH, when there is one)
+    pub par2_backend: String,
+    /// Every cargo feature, enabled or not
+    pub features: BTreeMap<String, bool>,
+    /// JSON schema versions this build writes
+    pub schemas: SchemaVersions,
+}
+
+/// `version` of each JSON document
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SchemaVersions {
+    /// Download runs' `--json` summary
+    pub run: u32,
+    /// `list --json`
+    pub list: u32,
+    /// `--json-events` lines
+    pub events: u32,
+}
+
+impl Capabilities {
+    /// Whether this build has cargo feature `name`
+    pub fn has(&self, name: &str) -> bool {
+        self.features.get(name).copied().unwrap_or(false)
+    }
+
+    /// Names of the enabled features, in [`FEATURES`] order
+    pub fn enabled(&self) -> Vec<&'static str> {
+        FEATURES
+            .iter()
+            .filter(|(name, _)| self.has(name))
+            .map(|(name, _)| *name)
+            .collect()
+    }
+}
+
+/// What this build of the library was compiled with
+pub fn capabilities() -> Capabilities {
+    Capabilities {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        os: std::env::consts::OS.to_string(),
+        arch: std::env::consts::ARCH.to_string(),
+        tls_backend: "native-tls".to_string(),
+        par2_backend: if cfg!(feature = "builtin-par2") {
+            "builtin"
+        } else {
+            "external"
+        }
+        .to_string(),
+        features: FEATURES
+            .iter()
+            .map(|&(name, enabled)| (name.to_string(), enabled))
+            .collect(),
+        schemas: SchemaVersions {
+            run: RUN_SCHEMA_VERSION,
+            list: LIST_SCHEMA_VERSION,
+            events: EVENT_SCHEMA_VERSION,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    include!(concat!(env!("OUT_DIR"), "/features.rs"));
+
+    #[test]
+    fn test_every_cargo_feature_is_listed() {
+        let mut listed: Vec<&str> = FEATURES.iter().map(|(name, _)| *name).collect();
+        let mut declared = CARGO_FEATURES.to_vec();
+        listed.sort_unstable();
+        declared.sort_unstable();
+        assert_eq!(
+            listed, declared,
+            "FEATURES must list the features in Cargo.toml"
+        );
+
+        let caps = capabilities();
+        assert_eq!(caps.has("builtin-par2"), caps.par2_backend == "builtin");
+        assert_eq!(caps.has("cli"), cfg!(feature = "cli"));
+        assert!(!caps.has("no-such-feature"));
+        let json = serde_json::to_value(&caps).unwrap();
+        assert_eq!(json["schemas"]["run"], RUN_SCHEMA_VERSION);
+        assert_eq!(json["features"].as_object().unwrap().len(), FEATURES.len());
+    }
+}
@@ -0,0 +1,151 @@
+//! CRC32s of files on disk, several at a time
+//!
+//! SFV checks in `verify` and the quick hashes behind
+//! `download.skip_verification = "quick-hash"` all go through here. Files
+//! are read in [`READ_BUFFER_SIZE`] chunks and hashed with `crc32fast`,
+//! which picks the CPU's CRC instructions where there are any; a run of
+//! files is shared out between up to `post_processing.checksum_threads`
+//! threads, so one slow multi-gigabyte file doesn't hold up the rest.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Bytes read from a file at a time
+pub const READ_BUFFER_SIZE: usize = 4 * 1024 * 1024;
+
+/// CRC32 of up to `len` bytes of `file` from `start`, calling `on_bytes`
+/// with each chunk's size as it's hashed (blocking)
+pub fn crc32(file: &mut File, start: u64, len: u64, on_bytes: &dyn Fn(u64)) -> io::Result<u32> {
+    file.seek(SeekFrom::Start(start))?;
+    let mut reader = file.take(len);
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buf = vec![0u8; len.clamp(1, READ_BUFFER_SIZE as u64) as usize];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                hasher.update(&buf[..n]);
+                on_bytes(n as u64);
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(hasher.finalize())
+}
+
+/// CRC32 of the whole file at `path` (blocking)
+pub fn crc32_file(path: &Path, on_bytes: &dyn Fn(u64)) -> io::Result<u32> {
+    crc32(&mut File::open(path)?, 0, u64::MAX, on_bytes)
+}
+
+/// CRC32s of the files at `paths`, in the same order, hashed on up to
+/// `threads` threads (blocking)
+pub fn crc32_files(
+    paths: &[PathBuf],
+    threads: usize,
+    on_bytes: &(dyn Fn(u64) + Sync),
+) -> Vec<io::Result<u32>> {
+    in_parallel(paths, threads, |path| crc32_file(path, on_bytes))
+}
+
+/// `job` run on each of `items` by up to `threads` threads, with the results
+/// in the order of `items` (blocking)
+///
+/// Threads take the next item as they finish one, so a few big files and
+/// many small ones even out.
+pub fn in_parallel<T, R, F>(items: &[T], threads: usize, job: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let threads = threads.clamp(1, items.len().max(1));
+    if threads == 1 {
+        return items.iter().map(job).collect();
+    }
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<R>>> = Mutex::new((0..items.len()).map(|_| None).collect());
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(index) else {
+                    break;
+                };
+                let result = job(item);
+                if let Ok(mut results) = results.lock() {
+                    results[index] = Some(result);
+                }
+            });
+        }
+    });
+    results
+        .into_inner()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .into_iter()
+        .map(|result| result.expect("every item is taken by a thread"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicU64;
+
+    #[test]
+    fn test_crc32_of_range() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.bin");
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&path, &data).unwrap();
+
+        let mut file = File::open(&path).unwrap();
+        let read = AtomicU64::new(0);
+        let on_bytes = |n| {
+            read.fetch_add(n, Ordering::Relaxed);
+        };
+        assert_eq!(
+            crc32(&mut file, 100, 500, &on_bytes).unwrap(),
+            crc32fast::hash(&data[100..600])
+        );
+        assert_eq!(read.load(Ordering::Relaxed), 500);
+        // Past the end is cut short
+        assert_eq!(
+            crc32(&mut file, 9_000, 5_000, &|_| {}).unwrap(),
+            crc32fast::hash(&data[9_000..])
+        );
+        assert_eq!(crc32_file(&path, &|_| {}).unwrap(), crc32fast::hash(&data));
+    }
+
+    #[test]
+    fn test_crc32_files_keeps_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut paths: Vec<PathBuf> = (0..7u8)
+            .map(|i| {
+                let path = dir.path().join(format!("{}.bin", i));
+                std::fs::write(&path, vec![i; 1000 * (i as usize + 1)]).unwrap();
+                path
+            })
+            .collect();
+        paths.push(dir.path().join("missing.bin"));
+
+        let read = AtomicU64::new(0);
+        let on_bytes = |n| {
+            read.fetch_add(n, Ordering::Relaxed);
+        };
+        let crcs = crc32_files(&paths, 3, &on_bytes);
+        assert_eq!(crcs.len(), 8);
+        for (i, crc) in crcs[..7].iter().enumerate() {
+            assert_eq!(
+                *crc.as_ref().unwrap(),
+                crc32fast::hash(&vec![i as u8; 1000 * (i + 1)])
+            );
+        }
+        assert!(crcs[7].is_err());
+        assert_eq!(read.load(Ordering::Relaxed), 28_000);
+    }
+}
@@ -1,6 +1,13 @@
-use clap::{Parser, Subcommand};
+use clap::builder::{PossibleValue, PossibleValuesParser};
+use clap::{Parser, Subcommand, ValueHint};
+use clap_complete::Shell;
 use std::path::PathBuf;
 
+use crate::config::{LogFormat, UsenetConfig, CONFIG_KEYS};
+use crate::download::FileSelection;
+use crate::error::{ConfigError, DlNzbError};
+use crate::progress::OutputMode;
+
 /// Fast NZB downloader for Usenet
 #[derive(Parser, Debug)]
 #[command(name = "dl-nzb")]
@@ -15,31 +22,72 @@ use std::path::PathBuf;
     List contents without downloading:
         dl-nzb -l file.nzb
 
+    Check an NZB can be downloaded before starting:
+        dl-nzb --dry-run file.nzb
+
     Show configuration:
         dl-nzb config
 
+    Change a setting:
+        dl-nzb config set usenet.server news.example.com
+
     Test connection:
         dl-nzb test
 
+    Check the whole setup:
+        dl-nzb config --check
+
+    Extract archives already downloaded:
+        dl-nzb extract ~/downloads/Some.Release
+
+    Protect a finished download with 10% PAR2 recovery data:
+        dl-nzb par2-create ~/downloads/Some.Release --redundancy 10
+
+    Install bash completions:
+        dl-nzb completions bash > ~/.local/share/bash-completion/completions/dl-nzb
+
+EXIT CODES:
+    0  Everything downloaded and post-processed
+    1  Completed with failed segments or files
+    2  Download aborted (server down, removed post, unreadable or empty NZB,
+       NZB of PAR2 files only)
+    3  Configuration or authentication error
+    4  Post-processing failed, download otherwise fine
+  130  Stopped by Ctrl-C or SIGTERM; unfinished NZBs stay queued
+    With several NZBs the most severe applies (130, 3, then 2, 1, 4).
+
 For advanced options, edit ~/.config/dl-nzb/config.toml")]
 pub struct Cli {
-    /// NZB files to download
-    #[arg(value_name = "FILE")]
+    /// NZB files, directories or quoted glob patterns to download
+    #[arg(value_name = "FILE", value_hint = ValueHint::AnyPath)]
     pub files: Vec<PathBuf>,
 
     /// Output directory
-    #[arg(short, long, value_name = "DIR")]
+    #[arg(short, long, value_name = "DIR", value_hint = ValueHint::DirPath)]
     pub output: Option<PathBuf>,
 
     /// List contents without downloading
     #[arg(short, long)]
     pub list: bool,
 
+    /// With -l, pick files from a list in the terminal and download them
+    #[arg(long, requires = "list")]
+    pub interactive: bool,
+
+    /// With -l, list files in the order they would download (download.file_order)
+    #[arg(long, requires = "list")]
+    pub show_order: bool,
+
+    /// With -l, STAT a sample of segments on the servers and estimate how
+    /// complete each file is
+    #[arg(long, requires = "list")]
+    pub check_availability: bool,
+
     /// Quiet mode (errors only)
     #[arg(short, long)]
     pub quiet: bool,
 
-    /// Verbose output (-vv for debug)
+    /// Verbose output (-v debug, -vv trace, -vvv also every NNTP command and reply)
     #[arg(short, long, action = clap::ArgAction::Count)]
     pub verbose: u8,
 
@@ -47,14 +95,154 @@ pub struct Cli {
     #[arg(long)]
     pub json: bool,
 
+    /// Stream progress as newline-delimited JSON events (implies --json)
+    #[arg(long)]
+    pub json_progress: bool,
+
+    /// Write download speed over time to FILE as CSV, one row per sample
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    pub speed_log: Option<PathBuf>,
+
+    /// Log plain progress lines instead of drawing progress bars
+    #[arg(long, conflicts_with = "progress")]
+    pub no_progress: bool,
+
+    /// Draw progress bars even when stderr is not a terminal
+    #[arg(long)]
+    pub progress: bool,
+
+    /// Write no color codes (as does setting NO_COLOR)
+    #[arg(long)]
+    pub no_color: bool,
+
+    /// Also draw a progress bar for each file being downloaded (overrides config)
+    #[arg(long)]
+    pub progress_per_file: bool,
+
+    /// Milliseconds between --json-progress progress events
+    #[arg(long, value_name = "MS", default_value_t = 1000)]
+    pub progress_interval: u64,
+
+    /// Log line format; json also turns progress bars into log lines (overrides config)
+    #[arg(long, value_name = "FORMAT")]
+    pub log_format: Option<LogFormat>,
+
     /// Config file path
-    #[arg(long, value_name = "FILE")]
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
     pub config: Option<PathBuf>,
 
-    /// Force re-download (overwrite existing files)
+    /// Upgrade an older config file to the current layout (keeps a .bak copy)
+    #[arg(long)]
+    pub migrate_config: bool,
+
+    /// Only download files matching a glob (or `re:<regex>`); repeatable
+    #[arg(long, value_name = "PATTERN")]
+    pub only: Vec<String>,
+
+    /// Skip files matching a glob (or `re:<regex>`); repeatable
+    #[arg(long, value_name = "PATTERN")]
+    pub exclude: Vec<String>,
+
+    /// Only download these files, numbered as in `-l` (e.g. 1,4-7)
+    #[arg(long, value_name = "LIST", value_parser = parse_selection)]
+    pub select: Option<FileSelection>,
+
+    /// Output names for files, as JSON mapping list number or subject to name
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    pub name_map: Option<PathBuf>,
+
+    /// Match --only/--exclude patterns case-sensitively
+    #[arg(long)]
+    pub case_sensitive: bool,
+
+    /// Write the NZB, cut down to the files --only/--exclude/--select or the
+    /// picker keep, to FILE instead of downloading it
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    pub save_nzb: Option<PathBuf>,
+
+    /// Report what downloading would do (output folder, files already there,
+    /// segment availability, time and disk space) without downloading; exits
+    /// non-zero when a check fails
+    #[arg(long, conflicts_with_all = ["list", "save_nzb"])]
+    pub dry_run: bool,
+
+    /// Fetch just the NFO, SFV and the start of the first archive volume, up
+    /// to SIZE in all (default 20M), show what they hold and stop without
+    /// post-processing; the fetched parts are deleted unless --keep-partial
+    #[arg(
+        long,
+        value_name = "SIZE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "20M",
+        value_parser = parse_size,
+        conflicts_with_all = ["list", "save_nzb", "dry_run"]
+    )]
+    pub preview: Option<u64>,
+
+    /// Post-process the files already in DIR (PAR2, extraction,
+    /// deobfuscation) without downloading; a download only ever processes
+    /// the files it produced
+    #[arg(
+        long,
+        value_name = "DIR",
+        value_hint = ValueHint::DirPath,
+        conflicts_with_all = ["list", "save_nzb", "dry_run"]
+    )]
+    pub process_dir: Option<PathBuf>,
+
+    /// Force re-download (overwrite existing files, even of an NZB already in the history)
     #[arg(short, long)]
     pub force: bool,
 
+    /// Download an NZB that holds only PAR2 files without asking
+    #[arg(long)]
+    pub allow_par2_only: bool,
+
+    /// Wait for another dl-nzb instance using the same output or watch folder
+    /// to finish, instead of failing
+    #[arg(long)]
+    pub wait_lock: bool,
+
+    /// Also look for NZBs in subdirectories of directory arguments
+    #[arg(short, long)]
+    pub recursive: bool,
+
+    /// Move each downloaded NZB into a `.done` folder next to it
+    #[arg(long)]
+    pub move_processed: bool,
+
+    /// Script to run after each download (overrides config)
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::ExecutablePath)]
+    pub script: Option<PathBuf>,
+
+    /// Scratch directory for partial downloads and extraction (overrides config)
+    #[arg(long, value_name = "DIR", value_hint = ValueHint::DirPath)]
+    pub temp_dir: Option<PathBuf>,
+
+    /// Keep temp directories after downloads and skip stale temp cleanup
+    #[arg(long)]
+    pub keep_temp: bool,
+
+    /// Keep files still missing segments after PAR2 instead of deleting them,
+    /// listing what they lack in a .dlnzb-failed marker
+    #[arg(long)]
+    pub keep_partial: bool,
+
+    /// Print the release's NFO after it downloads, when it has exactly one
+    #[arg(long)]
+    pub show_nfo: bool,
+
+    /// Fail an NZB for anything short of perfect (unrepaired segments, archives
+    /// not extracted, failed media checks, post-processing errors) and mark its
+    /// folder as failed
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Finish NZBs an earlier run left unfinished without asking first
+    #[arg(long)]
+    pub resume_queue: bool,
+
     /// Subcommands
     #[command(subcommand)]
     pub command: Option<Commands>,
@@ -70,9 +258,6 @@ pub struct Cli {
     #[arg(long = "no-directories", hide = true)]
     pub no_directories: bool,
 
-    #[arg(long = "keep-partial", hide = true)]
-    pub keep_partial: bool,
-
     #[arg(long = "no-par2", hide = true)]
     pub no_par2: bool,
 
@@ -122,13 +307,292 @@ pub struct Cli {
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     /// Test connection to Usenet server
-    Test,
+    Test {
+        /// Test this server instead of the configured one (port 563 implies
+        /// SSL, 119 plain)
+        #[arg(long, value_name = "HOST[:PORT]", value_parser = parse_server_address)]
+        server: Option<ServerAddress>,
+
+        /// Also measure download speed over all connections for SECONDS
+        /// (default 10); articles come from [speed_test] in the config
+        #[arg(long, value_name = "SECONDS", num_args = 0..=1, default_missing_value = "10")]
+        speed: Option<u64>,
+    },
+
+    /// Show or edit configuration
+    Config {
+        /// Show passwords instead of masking them
+        #[arg(long)]
+        show_secrets: bool,
+
+        /// Check the whole setup (servers, login, directories, PAR2) and exit
+        /// non-zero if anything fails
+        #[arg(long)]
+        check: bool,
+
+        #[command(subcommand)]
+        action: Option<ConfigAction>,
+    },
+
+    /// List past downloads, or show, remove or clear entries
+    History {
+        /// Show one entry in full, including per-file results
+        #[arg(long, value_name = "ID", conflicts_with_all = ["remove", "clear"])]
+        show: Option<u64>,
+
+        /// Delete one entry
+        #[arg(long, value_name = "ID", conflicts_with = "clear")]
+        remove: Option<u64>,
+
+        /// Delete every entry
+        #[arg(long)]
+        clear: bool,
+
+        /// Number of recent entries to list (0 for all)
+        #[arg(long, value_name = "N", default_value_t = 20)]
+        limit: usize,
+    },
+
+    /// Download a failed or partial history entry again into its original folder
+    ///
+    /// Complete files are kept, so only what's missing is fetched before
+    /// post-processing runs again.
+    Retry {
+        /// History entry to retry (see `dl-nzb history`)
+        #[arg(
+            value_name = "ID",
+            required_unless_present = "last_failed",
+            conflicts_with = "last_failed"
+        )]
+        id: Option<u64>,
+
+        /// Retry the most recent download that didn't succeed
+        #[arg(long)]
+        last_failed: bool,
+
+        /// Retry even though the entry already completed successfully
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Download NZBs dropped into a folder until stopped with Ctrl-C
+    ///
+    /// Each NZB is picked up once its size stops changing, downloaded and
+    /// post-processed, then moved to `completed/` or `failed/` inside the
+    /// folder. Server outages are waited out rather than ending the run.
+    Watch {
+        /// Folder to watch
+        #[arg(value_name = "DIR", value_hint = ValueHint::DirPath)]
+        dir: PathBuf,
+
+        /// Seconds between scans of the folder
+        #[arg(long, value_name = "SECONDS", default_value_t = 5)]
+        poll_interval: u64,
+    },
+
+    /// Check that a folder holds an NZB's files, complete, without downloading
+    ///
+    /// Exits 0 when every file is there at the right size (and checksum, with
+    /// --checksums), 1 when any is missing or damaged.
+    Verify {
+        /// The NZB the folder was downloaded from
+        #[arg(value_name = "FILE", value_hint = ValueHint::FilePath)]
+        nzb: PathBuf,
+
+        /// Folder to check (default: the one a download of the NZB uses)
+        #[arg(long, value_name = "DIR", value_hint = ValueHint::DirPath)]
+        dir: Option<PathBuf>,
+
+        /// Also hash the files against the PAR2 set, or an SFV file
+        #[arg(long)]
+        checksums: bool,
+
+        /// STAT a sample of the missing or damaged files' segments on the servers
+        #[arg(long)]
+        check_server: bool,
+    },
+
+    /// Extract the RAR archives already in a folder, without downloading
+    ///
+    /// Runs the extraction step a download ends with (passwords, free space
+    /// check, staging, deleting archives) on every archive in the folder.
+    /// Exits 0 when something was extracted, 1 when there was nothing to
+    /// extract and 4 when an archive failed.
+    Extract {
+        /// Folder holding the archives
+        #[arg(value_name = "DIR", value_hint = ValueHint::DirPath)]
+        dir: PathBuf,
 
-    /// Show configuration
-    Config,
+        /// Archive password to try, before the password file's; repeatable
+        #[arg(long, value_name = "PASSWORD")]
+        password: Vec<String>,
+
+        /// Delete the archives and their PAR2 files once extracted
+        /// (post_processing.delete_rar_after_extract)
+        #[arg(long)]
+        delete_archives: bool,
+    },
+
+    /// Make a PAR2 recovery set for the files in a folder
+    ///
+    /// Covers every file, those in subfolders included, except PAR2 files
+    /// already there; the set is named after the folder. Needs a par2
+    /// (par2cmdline) binary on PATH. Exits 0 when the set was made and 4
+    /// when it couldn't be.
+    Par2Create {
+        /// Folder holding the files
+        #[arg(value_name = "DIR", value_hint = ValueHint::DirPath)]
+        dir: PathBuf,
+
+        /// Recovery data in percent of the files' size
+        /// (post_processing.par2_redundancy)
+        #[arg(long, value_name = "PERCENT", value_parser = clap::value_parser!(u32).range(1..=100))]
+        redundancy: Option<u32>,
+
+        /// Block size in bytes, a multiple of 4 (post_processing.par2_block_size)
+        #[arg(long, value_name = "BYTES")]
+        block_size: Option<u64>,
+    },
+
+    /// Combine several NZBs into one, dropping files listed more than once
+    Merge {
+        /// NZB files, directories or quoted glob patterns to combine
+        #[arg(value_name = "FILE", required = true, value_hint = ValueHint::AnyPath)]
+        files: Vec<PathBuf>,
+
+        /// Where to write the combined NZB
+        #[arg(short, long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+        output: PathBuf,
+    },
 
     /// Show version information
-    Version,
+    Version {
+        /// Also show the platform, enabled features, TLS and PAR2 backends,
+        /// JSON schema versions and config location (with --json, as JSON)
+        #[arg(long)]
+        detailed: bool,
+
+        /// Run yEnc decoding, file assembly and PAR2 on generated data,
+        /// without a network; exits non-zero when a stage fails
+        #[arg(long)]
+        self_test: bool,
+    },
+
+    /// Print a shell completion script to stdout
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigAction {
+    /// Print a setting (e.g. `usenet.connections`)
+    Get {
+        /// Dotted key: <section>.<key>
+        #[arg(value_parser = config_key_parser(), hide_possible_values = true)]
+        key: String,
+    },
+
+    /// Change a setting and save the config file
+    Set {
+        /// Dotted key: <section>.<key>
+        #[arg(value_parser = config_key_parser(), hide_possible_values = true)]
+        key: String,
+
+        /// New value (`-` reads it from stdin, e.g. for usenet.password)
+        value: String,
+    },
+}
+
+/// A `host[:port]` server given on the command line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerAddress {
+    pub host: String,
+    pub port: Option<u16>,
+}
+
+impl ServerAddress {
+    /// SSL setting implied by a well-known NNTP port
+    pub fn implied_ssl(&self) -> Option<bool> {
+        match self.port {
+            Some(563) => Some(true),
+            Some(119) => Some(false),
+            _ => None,
+        }
+    }
+
+    /// Point a server config at this address, keeping its credentials
+    pub fn apply(&self, config: &mut UsenetConfig) {
+        config.server = self.host.clone();
+        if let Some(port) = self.port {
+            config.port = port;
+        }
+        if let Some(ssl) = self.implied_ssl() {
+            config.ssl = ssl;
+        }
+    }
+}
+
+fn parse_server_address(spec: &str) -> Result<ServerAddress, String> {
+    let (host, port) = match spec.rsplit_once(':') {
+        Some((host, port)) => {
+            let port = port
+                .parse::<u16>()
+                .ok()
+                .filter(|&p| p > 0)
+                .ok_or_else(|| format!("invalid port {:?}", port))?;
+            (host, Some(port))
+        }
+        None => (spec, None),
+    };
+    if host.is_empty() {
+        return Err("missing host name".to_string());
+    }
+    if host.contains(':') || host.contains(char::is_whitespace) || host.contains('/') {
+        return Err(format!("{:?} is not a host name", host));
+    }
+    Ok(ServerAddress {
+        host: host.to_string(),
+        port,
+    })
+}
+
+/// Bytes from `1048576`, `512K`, `20M` or `1G` (binary units, optional `B` or `iB`)
+fn parse_size(spec: &str) -> Result<u64, String> {
+    let upper = spec.trim().to_ascii_uppercase();
+    let number = upper
+        .strip_suffix("IB")
+        .or_else(|| upper.strip_suffix('B'))
+        .unwrap_or(&upper);
+    let (digits, shift) = match number.char_indices().last() {
+        Some((i, 'K')) => (&number[..i], 10),
+        Some((i, 'M')) => (&number[..i], 20),
+        Some((i, 'G')) => (&number[..i], 30),
+        _ => (number, 0),
+    };
+    digits
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(1 << shift))
+        .filter(|&n| n > 0)
+        .ok_or_else(|| format!("{:?} is not a size like 512K, 20M or 1G", spec))
+}
+
+fn parse_selection(spec: &str) -> Result<FileSelection, String> {
+    FileSelection::parse(spec).map_err(|e| match e {
+        DlNzbError::Config(ConfigError::Invalid { reason, .. }) => reason,
+        e => e.to_string(),
+    })
+}
+
+/// Accepts the dotted `section.key` names, so shells can complete them
+fn config_key_parser() -> PossibleValuesParser {
+    PossibleValuesParser::new(CONFIG_KEYS.iter().flat_map(|(section, keys)| {
+        keys.iter()
+            .map(move |key| PossibleValue::new(format!("{}.{}", section, key)))
+    }))
 }
 
 impl Cli {
@@ -164,6 +628,11 @@ impl Cli {
             eprintln!("Warning: --no-extract-rar is deprecated, set 'auto_extract_rar = false' in config file");
         }
 
+        // The event stream replaces the final document but keeps its rules
+        if cli.json_progress {
+            cli.json = true;
+        }
+
         // Adjust verbosity based on quiet flag
         if cli.quiet {
             cli.verbose = 0;
@@ -186,6 +655,28 @@ impl Cli {
         }
     }
 
+    /// Output policy for progress and status output (`--json` wins over `--quiet`)
+    pub fn output_mode(&self) -> OutputMode {
+        if self.json {
+            OutputMode::Json
+        } else if self.quiet {
+            OutputMode::Quiet
+        } else if self.verbose > 0 {
+            OutputMode::Verbose
+        } else {
+            OutputMode::Normal
+        }
+    }
+
+    /// Whether progress is logged as plain lines rather than drawn as bars
+    ///
+    /// Bars draw on stderr, so they are replaced when it isn't a terminal
+    /// unless `--progress` insists.
+    pub fn plain_progress(&self) -> bool {
+        use std::io::IsTerminal;
+        self.no_progress || (!self.progress && !std::io::stderr().is_terminal())
+    }
+
     /// Get configuration overrides from CLI arguments
     pub fn get_config_overrides(&self) -> crate::config::ConfigOverrides {
         crate::config::ConfigOverrides {
@@ -194,16 +685,59 @@ impl Cli {
             connections: self.connections,
             ssl: self.ssl,
             download_dir: self.output.clone(),
+            temp_dir: self.temp_dir.clone(),
             log_level: self.log_level.clone(),
+            log_format: self.log_format,
+            log_file: self.log_file.clone(),
+            progress_per_file: self.progress_per_file,
         }
     }
 
+    /// Dotted config keys the command-line flags override
+    pub fn overridden_keys(&self) -> Vec<String> {
+        [
+            ("usenet.server", self.server.is_some()),
+            ("usenet.port", self.port.is_some()),
+            ("usenet.ssl", self.ssl.is_some()),
+            ("usenet.username", self.username.is_some()),
+            ("usenet.password", self.password.is_some()),
+            ("usenet.connections", self.connections.is_some()),
+            ("download.dir", self.output.is_some()),
+            ("download.temp_dir", self.temp_dir.is_some()),
+            ("download.create_subfolders", self.no_directories),
+            ("download.keep_partial", self.keep_partial),
+            ("download.show_nfo", self.show_nfo),
+            ("download.strict", self.strict),
+            ("memory.max_buffer_mb", self.memory_limit.is_some()),
+            ("memory.io_buffer_size", self.buffer_size.is_some()),
+            (
+                "memory.max_concurrent_files",
+                self.max_concurrent_files.is_some(),
+            ),
+            ("post_processing.auto_par2_repair", self.no_par2),
+            ("post_processing.auto_extract_rar", self.no_extract_rar),
+            (
+                "post_processing.delete_rar_after_extract",
+                self.delete_rar_after_extract,
+            ),
+            ("post_processing.delete_par2_after_repair", self.delete_par2),
+            ("post_processing.script", self.script.is_some()),
+            ("logging.level", self.log_level.is_some()),
+            ("logging.format", self.log_format.is_some()),
+            ("logging.file", self.log_file.is_some()),
+            ("logging.progress", self.progress_per_file),
+        ]
+        .into_iter()
+        .filter(|(_, set)| *set)
+        .map(|(key, _)| key.to_string())
+        .collect()
+    }
+
     /// Check if deprecated flags are used
     pub fn has_deprecated_flags(&self) -> bool {
         self.connections.is_some()
             || self.output_dir.is_some()
             || self.no_directories
-            || self.keep_partial
             || self.no_par2
             || self.no_extract_rar
             || self.delete_rar_after_extract
@@ -224,3 +758,271 @@ impl Cli {
 pub mod messages {
     pub const NO_FILES: &str = "No NZB files specified. Use 'dl-nzb --help' for usage information.";
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_completions_cover_subcommands_and_keys() {
+        Cli::command().debug_assert();
+
+        let mut script = Vec::new();
+        clap_complete::generate(Shell::Bash, &mut Cli::command(), "dl-nzb", &mut script);
+        let script = String::from_utf8(script).unwrap();
+        assert!(script.contains("completions"));
+        assert!(script.contains("usenet.connections"));
+    }
+
+    #[test]
+    fn test_subcommand_fields() {
+        let cli =
+            Cli::try_parse_from(["dl-nzb", "test", "--server", "news.other.com:563"]).unwrap();
+        let Some(Commands::Test {
+            server: Some(server),
+            speed: None,
+        }) = cli.command
+        else {
+            panic!("expected test --server, got {:?}", cli.command);
+        };
+        assert_eq!(server.host, "news.other.com");
+        assert_eq!(server.port, Some(563));
+        assert_eq!(server.implied_ssl(), Some(true));
+
+        let cli = Cli::try_parse_from(["dl-nzb", "test"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Test {
+                server: None,
+                speed: None
+            })
+        ));
+        let cli = Cli::try_parse_from(["dl-nzb", "test", "--speed"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Test {
+                speed: Some(10),
+                ..
+            })
+        ));
+        let cli = Cli::try_parse_from(["dl-nzb", "test", "--speed", "30"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Test {
+                speed: Some(30),
+                ..
+            })
+        ));
+
+        let cli = Cli::try_parse_from(["dl-nzb", "version", "--detailed"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Version { detailed: true, .. })
+        ));
+        let cli = Cli::try_parse_from(["dl-nzb", "version"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Version {
+                detailed: false,
+                self_test: false
+            })
+        ));
+        let cli = Cli::try_parse_from(["dl-nzb", "version", "--self-test"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Version {
+                self_test: true,
+                ..
+            })
+        ));
+
+        let cli = Cli::try_parse_from(["dl-nzb", "history", "--show", "3"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::History {
+                show: Some(3),
+                remove: None,
+                clear: false,
+                limit: 20,
+            })
+        ));
+        assert!(Cli::try_parse_from(["dl-nzb", "history", "--show", "3", "--clear"]).is_err());
+
+        let cli = Cli::try_parse_from(["dl-nzb", "retry", "--last-failed"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Retry {
+                id: None,
+                last_failed: true,
+                force: false,
+            })
+        ));
+        assert!(Cli::try_parse_from(["dl-nzb", "retry"]).is_err());
+        assert!(Cli::try_parse_from(["dl-nzb", "retry", "3", "--last-failed"]).is_err());
+
+        let cli = Cli::try_parse_from(["dl-nzb", "watch", "/srv/nzbs"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Watch { ref dir, poll_interval: 5 }) if dir == &PathBuf::from("/srv/nzbs")
+        ));
+
+        let cli =
+            Cli::try_parse_from(["dl-nzb", "merge", "a.nzb", "b.nzb", "-o", "all.nzb"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Merge { ref files, ref output })
+                if files.len() == 2 && output == &PathBuf::from("all.nzb")
+        ));
+        assert!(Cli::try_parse_from(["dl-nzb", "merge", "a.nzb"]).is_err());
+
+        let cli = Cli::try_parse_from([
+            "dl-nzb",
+            "extract",
+            "/srv/done/x",
+            "--password",
+            "a",
+            "--password",
+            "b",
+        ])
+        .unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Extract { ref dir, ref password, delete_archives: false })
+                if dir == &PathBuf::from("/srv/done/x") && password == &["a", "b"]
+        ));
+
+        let cli =
+            Cli::try_parse_from(["dl-nzb", "par2-create", "/srv/done/x", "--redundancy", "15"])
+                .unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Par2Create { ref dir, redundancy: Some(15), block_size: None })
+                if dir == &PathBuf::from("/srv/done/x")
+        ));
+        assert!(Cli::try_parse_from(["dl-nzb", "par2-create", "x", "--redundancy", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_server_address_parsing() {
+        let plain = parse_server_address("news.example.com:119").unwrap();
+        assert_eq!(plain.implied_ssl(), Some(false));
+        let bare = parse_server_address("news.example.com").unwrap();
+        assert_eq!((bare.port, bare.implied_ssl()), (None, None));
+        assert_eq!(
+            parse_server_address("news.example.com:8443")
+                .unwrap()
+                .implied_ssl(),
+            None
+        );
+
+        for bad in [
+            "",
+            ":563",
+            "news.example.com:",
+            "news.example.com:0",
+            "host:99999",
+            "::1",
+            "a b:119",
+        ] {
+            assert!(parse_server_address(bad).is_err(), "{:?}", bad);
+        }
+        assert!(Cli::try_parse_from(["dl-nzb", "test", "--server", "host:abc"]).is_err());
+
+        let mut config = UsenetConfig {
+            ssl: false,
+            ..Default::default()
+        };
+        parse_server_address("alt.example.com:563")
+            .unwrap()
+            .apply(&mut config);
+        assert_eq!(
+            (config.server.as_str(), config.port, config.ssl),
+            ("alt.example.com", 563, true)
+        );
+    }
+
+    #[test]
+    fn test_size_parsing() {
+        assert_eq!(parse_size("1048576"), Ok(1 << 20));
+        assert_eq!(parse_size("512K"), Ok(512 << 10));
+        assert_eq!(parse_size("20m"), Ok(20 << 20));
+        assert_eq!(parse_size("20MiB"), Ok(20 << 20));
+        assert_eq!(parse_size("1GB"), Ok(1 << 30));
+        for bad in ["", "M", "0", "-1M", "1T", "lots"] {
+            assert!(parse_size(bad).is_err(), "{:?}", bad);
+        }
+
+        let cli = Cli::try_parse_from(["dl-nzb", "--preview", "a.nzb"]).unwrap();
+        assert_eq!(cli.preview, Some(20 << 20));
+        assert_eq!(cli.files, [PathBuf::from("a.nzb")]);
+        let cli = Cli::try_parse_from(["dl-nzb", "--preview=5M", "a.nzb"]).unwrap();
+        assert_eq!(cli.preview, Some(5 << 20));
+    }
+
+    #[test]
+    fn test_config_keys_are_validated() {
+        assert!(Cli::try_parse_from(["dl-nzb", "config", "get", "usenet.server"]).is_ok());
+        assert!(Cli::try_parse_from(["dl-nzb", "config", "get", "usenet.nope"]).is_err());
+    }
+
+    #[test]
+    fn test_overridden_keys() {
+        let cli = Cli::try_parse_from([
+            "dl-nzb",
+            "-o",
+            "/downloads",
+            "--no-par2",
+            "--memory-limit",
+            "256",
+            "a.nzb",
+        ])
+        .unwrap();
+        assert_eq!(
+            cli.overridden_keys(),
+            [
+                "download.dir",
+                "memory.max_buffer_mb",
+                "post_processing.auto_par2_repair"
+            ]
+        );
+
+        // Every key a flag can set is a real setting
+        let everything = Cli::try_parse_from([
+            "dl-nzb",
+            "--server=news.example.org",
+            "--port=563",
+            "--ssl=true",
+            "-u=user",
+            "-p=pass",
+            "-c=10",
+            "-o=/downloads",
+            "--temp-dir=/tmp",
+            "--no-directories",
+            "--keep-partial",
+            "--strict",
+            "--memory-limit=64",
+            "--buffer-size=64",
+            "--max-concurrent-files=2",
+            "--no-par2",
+            "--no-extract-rar",
+            "--delete-rar-after-extract",
+            "--delete-par2",
+            "--script=/bin/true",
+            "--log-level=debug",
+            "--log-format=json",
+            "--log-file=/tmp/log",
+            "--progress-per-file",
+        ])
+        .unwrap();
+        let keys = everything.overridden_keys();
+        assert_eq!(keys.len(), 23);
+        for key in keys {
+            assert!(
+                Cli::try_parse_from(["dl-nzb", "config", "get", key.as_str()]).is_ok(),
+                "{}",
+                key
+            );
+        }
+    }
+}
@@ -0,0 +1,244 @@
+//! How complete each newsgroup's and poster's articles came down
+//!
+//! Over many runs the same groups and posters turn up again and again, and
+//! some are reliably complete while others lose articles to takedowns.
+//! Segment counts are added up per newsgroup (lowercased, so `A.B.Foo` and
+//! `a.b.foo` are one group) and per poster (trimmed) for each NZB and across
+//! the run; the JSON summary and the history keep them for whatever picks
+//! NZBs to grab. A file counts towards every group it was posted to. Files
+//! skipped as already complete weren't fetched and aren't counted.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::download::DownloadResult;
+
+/// Rows [`CompletionStats::lines`] shows of each kind
+const SHOWN: usize = 5;
+
+/// Posters longer than this are cut short in [`CompletionStats::lines`]
+const POSTER_WIDTH: usize = 32;
+
+/// Files and segments fetched for one group or poster
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Completion {
+    pub files: usize,
+    pub segments: usize,
+    pub segments_failed: usize,
+}
+
+impl Completion {
+    /// Share of segments that came down, in percent (100 with none)
+    pub fn percent(&self) -> f64 {
+        if self.segments == 0 {
+            return 100.0;
+        }
+        (self.segments - self.segments_failed) as f64 * 100.0 / self.segments as f64
+    }
+
+    fn add(&mut self, other: &Completion) {
+        self.files += other.files;
+        self.segments += other.segments;
+        self.segments_failed += other.segments_failed;
+    }
+
+    /// `100%`, or `99.8%` rounded down so a lost segment never shows as 100
+    fn describe_percent(&self) -> String {
+        if self.segments_failed == 0 {
+            "100%".to_string()
+        } else {
+            format!("{:.1}%", (self.percent() * 10.0).floor() / 10.0)
+        }
+    }
+}
+
+/// Completion by newsgroup and by poster
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompletionStats {
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub groups: BTreeMap<String, Completion>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub posters: BTreeMap<String, Completion>,
+}
+
+impl CompletionStats {
+    /// Counts for the files downloaded for one NZB
+    pub fn of_download(results: &[DownloadResult]) -> Self {
+        let mut stats = Self::default();
+        for result in results.iter().filter(|r| r.skipped.is_none()) {
+            let file = Completion {
+                files: 1,
+                segments: result.segments_downloaded + result.segments_failed,
+                segments_failed: result.segments_failed,
+            };
+            let mut groups: Vec<String> = result.groups.iter().map(|g| group_key(g)).collect();
+            if groups.is_empty() {
+                groups.push(group_key(&result.group));
+            }
+            groups.sort_unstable();
+            groups.dedup();
+            for group in groups {
+                stats.groups.entry(group).or_default().add(&file);
+            }
+            stats
+                .posters
+                .entry(poster_key(&result.poster))
+                .or_default()
+                .add(&file);
+        }
+        stats
+    }
+
+    /// Add `other`'s counts to these
+    pub fn merge(&mut self, other: &CompletionStats) {
+        for (group, completion) in &other.groups {
+            self.groups
+                .entry(group.clone())
+                .or_default()
+                .add(completion);
+        }
+        for (poster, completion) in &other.posters {
+            self.posters
+                .entry(poster.clone())
+                .or_default()
+                .add(completion);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty() && self.posters.is_empty()
+    }
+
+    /// The busiest groups and posters, e.g. `alt.binaries.foo: 99.8% of
+    /// 12,400 segments` and `poster xyz@example.com: 3 files, 100%`
+    pub fn lines(&self) -> Vec<String> {
+        let groups = busiest(&self.groups).map(|(group, c)| {
+            format!(
+                "{}: {} of {} segments",
+                group,
+                c.describe_percent(),
+                separated(c.segments)
+            )
+        });
+        let posters = busiest(&self.posters).map(|(poster, c)| {
+            format!(
+                "poster {}: {} file{}, {}",
+                shorten(poster, POSTER_WIDTH),
+                separated(c.files),
+                if c.files == 1 { "" } else { "s" },
+                c.describe_percent()
+            )
+        });
+        groups.chain(posters).collect()
+    }
+}
+
+/// Key for newsgroup `group`
+pub fn group_key(group: &str) -> String {
+    group.trim().to_lowercase()
+}
+
+/// Key for poster `poster`; an NZB without one counts as `unknown`
+pub fn poster_key(poster: &str) -> String {
+    match poster.trim() {
+        "" => "unknown".to_string(),
+        poster => poster.to_string(),
+    }
+}
+
+/// The [`SHOWN`] entries with the most segments
+fn busiest(entries: &BTreeMap<String, Completion>) -> impl Iterator<Item = (&String, &Completion)> {
+    let mut sorted: Vec<_> = entries.iter().collect();
+    sorted.sort_by(|a, b| b.1.segments.cmp(&a.1.segments));
+    sorted.into_iter().take(SHOWN)
+}
+
+/// `12400` as `12,400`
+fn separated(count: usize) -> String {
+    let digits = count.to_string();
+    let mut out = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            out.push(',');
+        }
+        out.push(digit);
+    }
+    out
+}
+
+fn shorten(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        text.to_string()
+    } else {
+        let kept: String = text.chars().take(width - 1).collect();
+        format!("{}…", kept)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn result(groups: &[&str], poster: &str, segments: usize, failed: usize) -> DownloadResult {
+        DownloadResult {
+            filename: "file.bin".to_string(),
+            path: PathBuf::from("file.bin"),
+            size: 100,
+            segments_downloaded: segments - failed,
+            segments_failed: failed,
+            segments_missing: failed,
+            group: groups[0].to_string(),
+            groups: groups.iter().map(|g| g.to_string()).collect(),
+            poster: poster.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_counts_by_group_and_poster() {
+        let mut skipped = result(&["alt.binaries.foo"], "xyz@example.com", 50, 0);
+        skipped.skipped = Some(crate::config::SkipVerification::Size);
+        let results = [
+            result(
+                &["Alt.Binaries.Foo", "alt.binaries.bar"],
+                " xyz@example.com ",
+                12_000,
+                25,
+            ),
+            result(&["alt.binaries.foo"], "xyz@example.com", 400, 0),
+            result(&["alt.binaries.bar"], "", 10, 0),
+            skipped,
+        ];
+        let nzb = CompletionStats::of_download(&results);
+        assert_eq!(
+            nzb.groups["alt.binaries.foo"],
+            Completion {
+                files: 2,
+                segments: 12_400,
+                segments_failed: 25
+            }
+        );
+        assert_eq!(nzb.groups["alt.binaries.bar"].files, 2);
+        assert_eq!(nzb.posters["xyz@example.com"].files, 2);
+        assert_eq!(nzb.posters["unknown"].segments, 10);
+
+        let mut run = CompletionStats::default();
+        run.merge(&nzb);
+        run.merge(&CompletionStats::of_download(&[result(
+            &["alt.binaries.foo"],
+            "xyz@example.com",
+            100,
+            0,
+        )]));
+        assert_eq!(run.groups["alt.binaries.foo"].segments, 12_500);
+        assert_eq!(run.posters["xyz@example.com"].files, 3);
+
+        let lines = run.lines();
+        assert_eq!(lines[0], "alt.binaries.foo: 99.8% of 12,500 segments");
+        assert!(lines.contains(&"poster unknown: 1 file, 100%".to_string()));
+
+        let json = serde_json::to_value(&CompletionStats::default()).unwrap();
+        assert_eq!(json, serde_json::json!({}));
+    }
+}
@@ -1,8 +1,10 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::env;
 use std::path::{Path, PathBuf};
 
-use crate::error::{ConfigError, DlNzbError};
+use crate::credentials::{self, PasswordSource};
+use crate::error::{ConfigError, DlNzbError, RunStatus};
 
 type Result<T> = std::result::Result<T, DlNzbError>;
 
@@ -23,11 +25,23 @@ fn expand_tilde(path: &Path) -> PathBuf {
 }
 
 /// Main configuration structure with builder pattern support
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
+    /// Layout version of the config file (see [`CONFIG_VERSION`])
+    #[serde(default = "legacy_version")]
+    pub version: u32,
+
     #[serde(default)]
     pub usenet: UsenetConfig,
 
+    /// Additional servers, tried after `usenet` in the order listed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub servers: Vec<UsenetConfig>,
+
+    /// NZB indexers, for `indexer:<name>:<id>` arguments
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub indexers: Vec<IndexerConfig>,
+
     #[serde(default)]
     pub download: DownloadConfig,
 
@@ -42,20 +56,132 @@ pub struct Config {
 
     #[serde(default)]
     pub tuning: TuningConfig,
+
+    #[serde(default)]
+    pub notifications: NotificationsConfig,
+
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+
+    #[serde(default)]
+    pub api: ApiConfig,
+
+    #[serde(default)]
+    pub speed_test: SpeedTestConfig,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct UsenetConfig {
     pub server: String,
     pub port: u16,
     pub username: String,
-    pub password: String,
+    pub password: Secret,
     pub ssl: bool,
     pub verify_ssl_certs: bool,
     pub connections: u16,
     pub timeout: u64, // seconds
     pub retry_attempts: u8,
     pub retry_delay: u64, // milliseconds
+    /// How many days of articles the server keeps (0 = unlimited)
+    pub retention_days: u32,
+    /// Shell command whose output is the password (e.g. `pass show usenet`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password_cmd: Option<String>,
+    /// Read the password from the OS keyring entry `dl-nzb/<server>/<username>`
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub password_keyring: bool,
+    /// Find the connection count where throughput stops growing instead of
+    /// always using `connections`, which becomes the ceiling (primary server only)
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub auto_connections: bool,
+    /// Where `password` came from once resolved
+    #[serde(skip)]
+    pub password_source: PasswordSource,
+}
+
+impl UsenetConfig {
+    /// Fill in the password from the keyring or `password_cmd` if configured
+    ///
+    /// A password already supplied on the command line is kept.
+    pub fn resolve_password(&mut self) -> Result<()> {
+        if self.password_source == PasswordSource::Cli {
+            return Ok(());
+        }
+
+        if self.password_keyring {
+            self.password =
+                credentials::password_from_keyring(&self.server, &self.username)?.into();
+            self.password_source = PasswordSource::Keyring;
+        } else if let Some(command) = &self.password_cmd {
+            self.password = credentials::password_from_command(command)?.into();
+            self.password_source = PasswordSource::Command;
+        }
+        Ok(())
+    }
+
+    /// Whether a post of the given age (in days) is within this server's retention
+    pub fn retains(&self, age_days: f64) -> bool {
+        self.retention_days == 0 || age_days <= self.retention_days as f64
+    }
+}
+
+/// A credential that never shows up in Debug/Display output
+///
+/// Serializes as the plain string so config files round-trip; use
+/// [`Secret::expose`] where the real value is needed.
+#[derive(Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub const MASK: &'static str = "********";
+
+    pub fn new(value: impl Into<String>) -> Self {
+        Self(value.into())
+    }
+
+    /// The actual secret value
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The mask, or empty when unset so a missing value is still visible
+    fn masked(&self) -> Self {
+        if self.0.is_empty() {
+            Self::default()
+        } else {
+            Self::new(Self::MASK)
+        }
+    }
+}
+
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Self(value)
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(value: &str) -> Self {
+        Self(value.to_string())
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(Self::MASK)
+    }
+}
+
+impl std::fmt::Display for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(Self::MASK)
+    }
 }
 
 // Custom Debug implementation to hide sensitive data
@@ -65,13 +191,17 @@ impl std::fmt::Debug for UsenetConfig {
             .field("server", &self.server)
             .field("port", &self.port)
             .field("username", &self.username)
-            .field("password", &"<REDACTED>")
+            .field("password", &self.password)
             .field("ssl", &self.ssl)
             .field("verify_ssl_certs", &self.verify_ssl_certs)
             .field("connections", &self.connections)
             .field("timeout", &self.timeout)
             .field("retry_attempts", &self.retry_attempts)
             .field("retry_delay", &self.retry_delay)
+            .field("retention_days", &self.retention_days)
+            .field("password_cmd", &self.password_cmd)
+            .field("password_keyring", &self.password_keyring)
+            .field("password_source", &self.password_source)
             .finish()
     }
 }
@@ -80,9 +210,124 @@ impl std::fmt::Debug for UsenetConfig {
 pub struct DownloadConfig {
     pub dir: PathBuf,
     pub create_subfolders: bool,
+    /// Name of each NZB's folder under `dir` when `create_subfolders` is on,
+    /// e.g. `{category}/{title}`
+    #[serde(default = "default_folder_template")]
+    pub folder_template: String,
     pub user_agent: String,
     #[serde(default)]
     pub force_redownload: bool,
+    /// Global download speed cap in bytes per second (0 = unlimited)
+    #[serde(default)]
+    pub max_speed: u64,
+    /// Time-of-day speed caps; overlapping windows use the lowest cap
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub speed_schedule: Vec<SpeedWindow>,
+    /// Scratch space for partial downloads and extraction staging
+    /// (defaults to working in the download directory)
+    ///
+    /// Each NZB gets its own subdirectory; while an NZB is processed this
+    /// points at that subdirectory.
+    #[serde(default)]
+    pub temp_dir: Option<PathBuf>,
+    /// Remove leftover per-NZB temp directories older than this many hours (0 = never)
+    #[serde(default = "default_temp_max_age_hours")]
+    pub temp_max_age_hours: u64,
+    /// Save NZBs read from a URL or stdin into their download folder
+    #[serde(default)]
+    pub keep_nzb: bool,
+    /// Queue NZBs found in a finished download, into a folder inside it
+    #[serde(default)]
+    pub follow_nested_nzbs: bool,
+    /// When finished files are synced to disk
+    #[serde(default)]
+    pub fsync: FsyncPolicy,
+    /// Order in which an NZB's files are downloaded
+    #[serde(default)]
+    pub file_order: FileOrder,
+    /// Files up to this many bytes count as metadata for `metadata-first`
+    #[serde(default = "default_metadata_size")]
+    pub metadata_size: u64,
+    /// Keep files still missing segments after PAR2 (listed in a
+    /// `.dlnzb-failed` marker) instead of deleting them
+    #[serde(default)]
+    pub keep_partial: bool,
+    /// How a file already there at the right size is checked before it's skipped
+    #[serde(default)]
+    pub skip_verification: SkipVerification,
+    /// What happens to a file already there at another size, with no record
+    /// of an earlier run having kept it incomplete
+    #[serde(default)]
+    pub existing_files: ExistingFiles,
+    /// Skip files whose newsgroups no server carries instead of trying them
+    #[serde(default)]
+    pub skip_unavailable_groups: bool,
+    /// Skip files the NZB declares smaller than this many bytes (0 = keep
+    /// everything); PAR2 files are always kept
+    #[serde(default)]
+    pub min_file_size: u64,
+    /// Print the NFO after a download when there is exactly one
+    #[serde(default)]
+    pub show_nfo: bool,
+    /// Octal mode for created folders, e.g. "0775" (Unix only; default: umask)
+    #[serde(default)]
+    pub dir_mode: Option<String>,
+    /// Octal mode for finished files, e.g. "0664" (Unix only; default: umask)
+    #[serde(default)]
+    pub file_mode: Option<String>,
+    /// "user:group", "user" or ":group" to own finished files and folders
+    /// (Unix only; needs the privilege to change ownership)
+    #[serde(default)]
+    pub chown: Option<String>,
+    /// Fail an NZB for anything short of perfect: unrepaired segments,
+    /// archives not extracted, failed media checks or post-processing errors
+    #[serde(default)]
+    pub strict: bool,
+    /// How a strict failure marks the output folder
+    #[serde(default)]
+    pub strict_mark: StrictMark,
+    /// Seconds an NZB may spend on retries, fill servers and re-fetches
+    /// before remaining failures are final (0 = unlimited)
+    #[serde(default)]
+    pub max_retry_time: u64,
+    /// Bytes an NZB may fetch on retries, fill servers and re-fetches
+    /// (0 = unlimited)
+    #[serde(default)]
+    pub max_retry_bytes: u64,
+    /// Seconds a file may go without finishing a batch, once another file
+    /// has finished, before the rest of it is given up on (0 = never)
+    #[serde(default = "default_stall_timeout")]
+    pub stall_timeout: u64,
+}
+
+fn default_folder_template() -> String {
+    crate::download::DEFAULT_FOLDER_TEMPLATE.to_string()
+}
+
+fn default_temp_max_age_hours() -> u64 {
+    72
+}
+
+fn default_stall_timeout() -> u64 {
+    300
+}
+
+fn default_metadata_size() -> u64 {
+    2 * 1024 * 1024
+}
+
+/// A `[[download.speed_schedule]]` entry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpeedWindow {
+    /// Days the window starts on ("mon".."sun"); empty means every day
+    #[serde(default)]
+    pub days: Vec<String>,
+    /// Local start time, `HH:MM`
+    pub from: String,
+    /// Local end time, `HH:MM` (`24:00` for midnight; earlier than `from` wraps)
+    pub to: String,
+    /// Cap in bytes per second while active (0 = unlimited)
+    pub max_speed: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -90,6 +335,10 @@ pub struct MemoryConfig {
     pub max_segments_in_memory: usize,
     pub io_buffer_size: usize,
     pub max_concurrent_files: usize,
+    /// Cap on decoded segment data held at once, in MiB
+    /// (0 = one MiB per `max_segments_in_memory`)
+    #[serde(default)]
+    pub max_buffer_mb: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -98,14 +347,400 @@ pub struct PostProcessingConfig {
     pub auto_extract_rar: bool,
     pub delete_rar_after_extract: bool,
     pub delete_par2_after_repair: bool,
+    /// Delete PAR2/.rev volumes of successfully extracted sets
+    /// (defaults to following `delete_rar_after_extract`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delete_par2_after_extract: Option<bool>,
     pub deobfuscate_file_names: bool,
+    /// Re-download segments covering PAR2-damaged blocks before repairing
+    #[serde(default)]
+    pub refetch_damaged_segments: bool,
+    /// Check MKV/MP4/AVI containers for truncation after extraction
+    #[serde(default)]
+    pub verify_media: bool,
+    /// Extract into a staging directory and move files into place only on success
+    #[serde(default = "default_true")]
+    pub extract_to_temp: bool,
+    /// Newline-separated list of archive passwords to try
+    #[serde(default)]
+    pub password_file: Option<PathBuf>,
+    /// External script to run after all built-in steps
+    #[serde(default)]
+    pub script: Option<PathBuf>,
+    /// Maximum time (seconds) the script may run before it is killed
+    #[serde(default = "default_script_timeout")]
+    pub script_timeout: u64,
+    /// Library folder completed files are linked or copied into
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link_dir: Option<PathBuf>,
+    /// How files get into `link_dir`
+    #[serde(default)]
+    pub link_mode: LinkMode,
+    /// What to do when a file is already in `link_dir`
+    #[serde(default)]
+    pub link_collision: LinkCollision,
+    /// Whether downloads keep writing to a disk PAR2 or extraction is working on
+    #[serde(default)]
+    pub io_priority: IoPriority,
+    /// Files hashed at once by SFV checks and quick-hash records
+    #[serde(default = "default_checksum_threads")]
+    pub checksum_threads: usize,
+    /// Keep the names files were posted under: undo PAR2's renames, skip
+    /// deobfuscation and list the real names in `names.json`
+    #[serde(default)]
+    pub preserve_names: bool,
+    /// Files deleted once post-processing is done: globs (or `re:` regexes)
+    /// on the name, ignoring case
+    #[serde(default = "default_cleanup_patterns")]
+    pub cleanup_patterns: Vec<String>,
+    /// Make a new PAR2 set for the finished files, when nothing failed
+    /// verification (needs a `par2` binary)
+    #[serde(default)]
+    pub create_par2_after_extract: bool,
+    /// Recovery data of created PAR2 sets, as a percentage of the files' size
+    #[serde(default = "default_par2_redundancy")]
+    pub par2_redundancy: u32,
+    /// Block size of created PAR2 sets in bytes (0 = par2 picks one)
+    #[serde(default)]
+    pub par2_block_size: u64,
+}
+
+fn default_script_timeout() -> u64 {
+    300
+}
+
+fn default_checksum_threads() -> usize {
+    4
+}
+
+fn default_par2_redundancy() -> u32 {
+    10
+}
+
+fn default_cleanup_patterns() -> Vec<String> {
+    crate::processing::DEFAULT_CLEANUP_PATTERNS
+        .iter()
+        .map(|pattern| pattern.to_string())
+        .collect()
+}
+
+impl PostProcessingConfig {
+    /// Whether PAR2 volumes should be removed once their set has been extracted
+    pub fn should_delete_par2_after_extract(&self) -> bool {
+        self.delete_par2_after_extract
+            .unwrap_or(self.delete_rar_after_extract)
+    }
+}
+
+fn default_true() -> bool {
+    true
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LoggingConfig {
     pub level: String,
     pub file: Option<PathBuf>,
-    pub format: String,
+    /// Rotate the log file once it reaches this many MiB (0 = never)
+    #[serde(default)]
+    pub rotate_size_mb: u64,
+    /// Rotated log files kept as `<file>.1` … `<file>.N`
+    #[serde(default = "default_keep_files")]
+    pub keep_files: usize,
+    #[serde(default)]
+    pub format: LogFormat,
+    /// Seconds between plain-text progress lines when bars aren't drawn
+    #[serde(default = "default_progress_interval")]
+    pub progress_interval: u64,
+    #[serde(default)]
+    pub progress: ProgressLayout,
+    /// Seconds of history behind the displayed speed and ETA
+    #[serde(default = "default_speed_window")]
+    pub speed_window: u64,
+    /// Log every NNTP command and reply line at trace level (like `-vvv`)
+    #[serde(default)]
+    pub protocol_trace: bool,
+}
+
+/// How log lines are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// One readable line per event
+    #[default]
+    Pretty,
+    /// Shorter lines, span fields after the message
+    Compact,
+    /// One JSON object per event, fields flattened
+    Json,
+}
+
+/// When downloaded files are synced to disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FsyncPolicy {
+    /// Leave it to the OS; fastest, but a crash can lose "complete" files
+    Never,
+    /// Sync each file before it counts as complete
+    #[default]
+    PerFile,
+    /// Sync every file once the whole NZB has downloaded
+    PerNzb,
+}
+
+/// How a file already in the download folder is checked before it's skipped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SkipVerification {
+    /// The expected size is enough
+    #[default]
+    Size,
+    /// The size plus CRC32s of the first and last MiB, against the ones
+    /// recorded when the file was downloaded
+    QuickHash,
+    /// Never skip: download everything again
+    #[serde(rename = "none")]
+    Never,
+}
+
+impl std::fmt::Display for SkipVerification {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Size => "size",
+            Self::QuickHash => "quick-hash",
+            Self::Never => "none",
+        })
+    }
+}
+
+/// What becomes of a file already in the download folder at another size
+/// than the one being downloaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExistingFiles {
+    /// Move it aside to `name.old` (then `name.old2` and so on) first
+    #[default]
+    Rename,
+    Overwrite,
+    /// Keep it and leave the file out of the download
+    Skip,
+}
+
+impl std::fmt::Display for ExistingFiles {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Rename => "rename",
+            Self::Overwrite => "overwrite",
+            Self::Skip => "skip",
+        })
+    }
+}
+
+/// How `--strict` marks the output folder of an NZB that failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StrictMark {
+    /// Write a `.dlnzb-strict-failed` file listing what went wrong
+    #[default]
+    Marker,
+    /// Rename the folder to `<name>.failed` (the NZB's own folder only)
+    Rename,
+}
+
+/// How completed files get into `post_processing.link_dir`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LinkMode {
+    /// A second name for the same data; copies across filesystems
+    #[default]
+    Hardlink,
+    /// A link pointing back into the download folder
+    Symlink,
+    Copy,
+    /// Leaves the download folder without them
+    Move,
+}
+
+/// What happens when a file is already where a link would go
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LinkCollision {
+    /// Keep what's there and leave the file out
+    #[default]
+    Skip,
+    Overwrite,
+    /// Add a number: `name (2).ext`
+    Rename,
+}
+
+/// How post-processing shares a disk with downloads still running
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IoPriority {
+    /// Both go ahead at once
+    #[default]
+    Parallel,
+    /// Downloads to the same disk pause during PAR2 and extraction
+    Exclusive,
+}
+
+impl std::fmt::Display for IoPriority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Parallel => "parallel",
+            Self::Exclusive => "exclusive",
+        })
+    }
+}
+
+/// Order in which an NZB's files are downloaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FileOrder {
+    /// Biggest files first, for the best throughput
+    #[default]
+    LargestFirst,
+    SmallestFirst,
+    /// As listed in the NZB
+    NzbOrder,
+    /// Files up to `metadata_size` (NFO, SFV, PAR2 index, images) and PAR2
+    /// index files in NZB order, then the rest largest first
+    MetadataFirst,
+}
+
+impl std::fmt::Display for FileOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::LargestFirst => "largest-first",
+            Self::SmallestFirst => "smallest-first",
+            Self::NzbOrder => "nzb-order",
+            Self::MetadataFirst => "metadata-first",
+        })
+    }
+}
+
+/// How download progress bars are laid out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProgressLayout {
+    /// One bar for the whole NZB
+    #[default]
+    Aggregate,
+    /// The aggregate bar plus one bar per file being downloaded
+    PerFile,
+}
+
+fn default_keep_files() -> usize {
+    5
+}
+
+fn default_progress_interval() -> u64 {
+    10
+}
+
+fn default_speed_window() -> u64 {
+    10
+}
+
+/// Webhook and desktop notifications when a run finishes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NotificationsConfig {
+    /// POST the JSON run summary to `webhook_url`
+    pub webhook: bool,
+    pub webhook_url: String,
+    /// Sent as `Authorization: Bearer <token>` when set
+    pub webhook_token: Secret,
+    /// Seconds before the webhook request is abandoned
+    pub webhook_timeout: u64,
+    pub webhook_events: Vec<NotifyEvent>,
+    /// Show a desktop notification (requires the `desktop-notifications` feature)
+    pub desktop: bool,
+    pub desktop_events: Vec<NotifyEvent>,
+}
+
+/// Run outcome a notification can be limited to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifyEvent {
+    Success,
+    /// Finished with failed segments or post-processing problems
+    Partial,
+    /// Stopped before finishing
+    Failure,
+}
+
+impl NotifyEvent {
+    pub const ALL: [Self; 3] = [Self::Success, Self::Partial, Self::Failure];
+
+    pub fn of(status: RunStatus) -> Self {
+        match status {
+            RunStatus::Success | RunStatus::Duplicate => Self::Success,
+            RunStatus::PostProcessingFailed | RunStatus::Incomplete => Self::Partial,
+            RunStatus::Par2Only
+            | RunStatus::Empty
+            | RunStatus::Aborted
+            | RunStatus::ConfigError
+            | RunStatus::Cancelled => Self::Failure,
+        }
+    }
+}
+
+/// An NZB indexer in `[[indexers]]`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IndexerConfig {
+    /// What `indexer:<name>:<id>` arguments call it
+    pub name: String,
+    /// NZB download URL with `{id}` and `{apikey}` placeholders, e.g.
+    /// `https://indexer.example/api?t=get&id={id}&apikey={apikey}`
+    pub url: String,
+    pub api_key: Secret,
+    /// User agent for this indexer; empty = `download.user_agent`
+    pub user_agent: String,
+    /// Extra request headers, e.g. `X-Api-Client = "dl-nzb"`
+    pub headers: BTreeMap<String, String>,
+}
+
+/// Prometheus metrics endpoint
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetricsConfig {
+    /// Address to serve `/metrics` on (e.g. `127.0.0.1:9090`); empty = off
+    pub listen: String,
+}
+
+/// HTTP API of `watch` (the `server` feature)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ApiConfig {
+    /// Address to serve the API on (e.g. `127.0.0.1:6789`); empty = off
+    pub listen: String,
+    /// Bearer token requests must carry; empty = none needed
+    pub token: Secret,
+    /// Allow `listen` to be an address other than loopback
+    pub allow_remote: bool,
+}
+
+/// Articles fetched by `test --speed`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpeedTestConfig {
+    pub group: String,
+    /// Message-IDs to download; empty = the newest posts in `group`
+    pub articles: Vec<String>,
+}
+
+fn default_auto_connections_margin() -> u32 {
+    5
+}
+
+fn default_auto_connections_window() -> u64 {
+    10
+}
+
+fn default_give_up_after_missing() -> usize {
+    200
 }
 
 /// Performance tuning parameters
@@ -120,22 +755,57 @@ pub struct TuningConfig {
     pub max_concurrent_connections: usize,
     /// File size threshold (bytes) above which to show progress during RAR extraction
     pub large_file_threshold: u64,
+    /// Percent more throughput it takes for `usenet.auto_connections` to keep adding connections
+    #[serde(default = "default_auto_connections_margin")]
+    pub auto_connections_margin: u32,
+    /// Seconds of throughput `usenet.auto_connections` measures each connection count over
+    #[serde(default = "default_auto_connections_window")]
+    pub auto_connections_window: u64,
+    /// Missing segments in a row after which a file's remaining segments
+    /// aren't asked for (0 = never give up)
+    #[serde(default = "default_give_up_after_missing")]
+    pub give_up_after_missing: usize,
 }
 
 // Default implementations
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            version: CONFIG_VERSION,
+            usenet: UsenetConfig::default(),
+            servers: Vec::new(),
+            indexers: Vec::new(),
+            download: DownloadConfig::default(),
+            memory: MemoryConfig::default(),
+            post_processing: PostProcessingConfig::default(),
+            logging: LoggingConfig::default(),
+            tuning: TuningConfig::default(),
+            notifications: NotificationsConfig::default(),
+            metrics: MetricsConfig::default(),
+            api: ApiConfig::default(),
+            speed_test: SpeedTestConfig::default(),
+        }
+    }
+}
+
 impl Default for UsenetConfig {
     fn default() -> Self {
         Self {
             server: String::new(),
             port: 563, // Default SSL port
             username: String::new(),
-            password: String::new(),
+            password: Secret::default(),
             ssl: true, // Default to SSL
             verify_ssl_certs: true,
             connections: 20,   // Conservative default (users can increase if needed)
             timeout: 30,       // Reduced from 45s
             retry_attempts: 2, // Faster failover
             retry_delay: 500,  // Quick retries
+            retention_days: 0, // Unlimited
+            password_cmd: None,
+            password_keyring: false,
+            auto_connections: false,
+            password_source: PasswordSource::Config,
         }
     }
 }
@@ -145,8 +815,32 @@ impl Default for DownloadConfig {
         Self {
             dir: PathBuf::from("downloads"),
             create_subfolders: true,
+            folder_template: default_folder_template(),
             user_agent: format!("dl-nzb/{}", env!("CARGO_PKG_VERSION")),
             force_redownload: false,
+            max_speed: 0,
+            speed_schedule: Vec::new(),
+            temp_dir: None,
+            temp_max_age_hours: default_temp_max_age_hours(),
+            keep_nzb: false,
+            follow_nested_nzbs: false,
+            fsync: FsyncPolicy::PerFile,
+            file_order: FileOrder::LargestFirst,
+            metadata_size: default_metadata_size(),
+            keep_partial: false,
+            skip_verification: SkipVerification::Size,
+            existing_files: ExistingFiles::Rename,
+            skip_unavailable_groups: false,
+            min_file_size: 0,
+            show_nfo: false,
+            dir_mode: None,
+            file_mode: None,
+            chown: None,
+            strict: false,
+            strict_mark: StrictMark::Marker,
+            max_retry_time: 0,
+            max_retry_bytes: 0,
+            stall_timeout: default_stall_timeout(),
         }
     }
 }
@@ -157,10 +851,22 @@ impl Default for MemoryConfig {
             max_segments_in_memory: 800, // Conservative: 800 concurrent segments (~20 per connection)
             io_buffer_size: 8 * 1024 * 1024, // 8MB buffer (reduced from 16MB)
             max_concurrent_files: 100,   // No longer throttles (downloader ignores this)
+            max_buffer_mb: 0,
         }
     }
 }
 
+impl MemoryConfig {
+    /// The memory budget for decoded segments, in bytes
+    pub fn buffer_bytes(&self) -> u64 {
+        let mb = match self.max_buffer_mb {
+            0 => self.max_segments_in_memory as u64,
+            mb => mb,
+        };
+        mb.saturating_mul(1024 * 1024)
+    }
+}
+
 impl Default for PostProcessingConfig {
     fn default() -> Self {
         Self {
@@ -168,7 +874,24 @@ impl Default for PostProcessingConfig {
             auto_extract_rar: true,
             delete_rar_after_extract: false,
             delete_par2_after_repair: false,
+            delete_par2_after_extract: None,
             deobfuscate_file_names: true,
+            refetch_damaged_segments: false,
+            verify_media: false,
+            extract_to_temp: true,
+            password_file: None,
+            script: None,
+            script_timeout: default_script_timeout(),
+            link_dir: None,
+            link_mode: LinkMode::Hardlink,
+            link_collision: LinkCollision::Skip,
+            io_priority: IoPriority::Parallel,
+            checksum_threads: default_checksum_threads(),
+            preserve_names: false,
+            cleanup_patterns: default_cleanup_patterns(),
+            create_par2_after_extract: false,
+            par2_redundancy: default_par2_redundancy(),
+            par2_block_size: 0,
         }
     }
 }
@@ -178,7 +901,13 @@ impl Default for LoggingConfig {
         Self {
             level: "info".to_string(),
             file: None,
-            format: "pretty".to_string(),
+            rotate_size_mb: 0,
+            keep_files: default_keep_files(),
+            format: LogFormat::Pretty,
+            progress_interval: default_progress_interval(),
+            progress: ProgressLayout::Aggregate,
+            speed_window: default_speed_window(),
+            protocol_trace: false,
         }
     }
 }
@@ -190,127 +919,707 @@ impl Default for TuningConfig {
             connection_wait_timeout: 300,           // 5 minutes max wait
             max_concurrent_connections: 10,         // Concurrent connection creation limit
             large_file_threshold: 10 * 1024 * 1024, // 10MB for progress monitoring
+            auto_connections_margin: default_auto_connections_margin(),
+            auto_connections_window: default_auto_connections_window(),
+            give_up_after_missing: default_give_up_after_missing(),
         }
     }
 }
 
-/// Load configuration from environment variables
-fn load_env_overrides(mut config: Config) -> Config {
-    // Override with DL_NZB_ prefixed environment variables
-    if let Ok(val) = env::var("DL_NZB_USENET_SERVER") {
-        config.usenet.server = val;
+impl Default for NotificationsConfig {
+    fn default() -> Self {
+        Self {
+            webhook: false,
+            webhook_url: String::new(),
+            webhook_token: Secret::default(),
+            webhook_timeout: 10,
+            webhook_events: NotifyEvent::ALL.to_vec(),
+            desktop: false,
+            desktop_events: NotifyEvent::ALL.to_vec(),
+        }
     }
-    if let Ok(val) = env::var("DL_NZB_USENET_PORT") {
-        if let Ok(port) = val.parse() {
-            config.usenet.port = port;
+}
+
+impl Default for SpeedTestConfig {
+    fn default() -> Self {
+        Self {
+            group: "alt.binaries.test".to_string(),
+            articles: Vec::new(),
         }
     }
-    if let Ok(val) = env::var("DL_NZB_USENET_USERNAME") {
-        config.usenet.username = val;
+}
+
+/// Current config file layout version
+///
+/// 1: no `version` key; `[usenet]` was the only server
+/// 2: `version` key; additional servers in `[[servers]]`
+/// 3: `temp_dir` moved from `[post_processing]` to `[download]`
+pub const CONFIG_VERSION: u32 = 3;
+
+/// Files without a `version` key predate versioning
+fn legacy_version() -> u32 {
+    1
+}
+
+/// Outcome of upgrading a parsed config file
+#[derive(Debug)]
+struct Migration {
+    /// Version the file was written with
+    from: u32,
+    /// Human-readable description of each structural change
+    changes: Vec<String>,
+}
+
+/// Upgrade an older config layout in place
+fn migrate(table: &mut toml::Table) -> Migration {
+    let from = table
+        .get("version")
+        .and_then(|v| v.as_integer())
+        .map_or(legacy_version(), |v| v as u32);
+    let mut changes = Vec::new();
+
+    if from < 2 {
+        migrate_v1_servers(table, &mut changes);
     }
-    if let Ok(val) = env::var("DL_NZB_USENET_PASSWORD") {
-        config.usenet.password = val;
+    if from < 3 {
+        migrate_v2_temp_dir(table, &mut changes);
     }
-    if let Ok(val) = env::var("DL_NZB_USENET_SSL") {
-        if let Ok(ssl) = val.parse() {
-            config.usenet.ssl = ssl;
-        }
+
+    if from < CONFIG_VERSION {
+        table.insert(
+            "version".to_string(),
+            toml::Value::Integer(CONFIG_VERSION as i64),
+        );
     }
-    if let Ok(val) = env::var("DL_NZB_USENET_CONNECTIONS") {
-        if let Ok(connections) = val.parse() {
-            config.usenet.connections = connections;
+    Migration { from, changes }
+}
+
+/// Version 1 had a single `[usenet]` server; map list-style layouts onto
+/// `[usenet]` plus `[[servers]]`
+fn migrate_v1_servers(table: &mut toml::Table, changes: &mut Vec<String>) {
+    // `[[usenet]]`: first entry is the primary, the rest become extra servers
+    if let Some(toml::Value::Array(list)) = table.get("usenet").cloned() {
+        let mut list = list.into_iter();
+        if let Some(primary) = list.next() {
+            table.insert("usenet".to_string(), primary);
+        } else {
+            table.remove("usenet");
         }
-    }
-    if let Ok(val) = env::var("DL_NZB_DOWNLOAD_DIR") {
-        config.download.dir = PathBuf::from(val);
+        let rest: Vec<toml::Value> = list.collect();
+        if !rest.is_empty() {
+            let servers = table
+                .entry("servers")
+                .or_insert_with(|| toml::Value::Array(Vec::new()));
+            if let Some(servers) = servers.as_array_mut() {
+                servers.splice(0..0, rest);
+            }
+        }
+        changes.push("[[usenet]] list split into [usenet] and [[servers]]".to_string());
     }
 
-    config
+    // `[[servers]]` without `[usenet]`: the first server is the primary
+    if !table.contains_key("usenet") {
+        if let Some(toml::Value::Array(servers)) = table.get_mut("servers") {
+            if !servers.is_empty() {
+                let primary = servers.remove(0);
+                if servers.is_empty() {
+                    table.remove("servers");
+                }
+                table.insert("usenet".to_string(), primary);
+                changes.push("first [[servers]] entry moved to [usenet]".to_string());
+            }
+        }
+    }
 }
 
-impl Config {
-    /// Get the standard config file path
-    pub fn config_path() -> Result<PathBuf> {
-        let config_dir = dirs::config_dir().ok_or_else(|| ConfigError::Invalid {
-            field: "config_dir".to_string(),
-            reason: "Could not determine config directory".to_string(),
-        })?;
-        Ok(config_dir.join("dl-nzb").join("config.toml"))
+/// Version 2 kept the extraction staging `temp_dir` under `[post_processing]`
+fn migrate_v2_temp_dir(table: &mut toml::Table, changes: &mut Vec<String>) {
+    let Some(temp_dir) = table
+        .get_mut("post_processing")
+        .and_then(|v| v.as_table_mut())
+        .and_then(|section| section.remove("temp_dir"))
+    else {
+        return;
+    };
+
+    let download = table.entry("download").or_insert_with(|| {
+        toml::Value::try_from(DownloadConfig::default())
+            .unwrap_or_else(|_| toml::Value::Table(toml::Table::new()))
+    });
+    if let Some(download) = download.as_table_mut() {
+        download.entry("temp_dir").or_insert(temp_dir);
     }
+    changes.push("post_processing.temp_dir moved to download.temp_dir".to_string());
+}
 
-    /// Load configuration from local or standard location
-    pub fn load() -> Result<Self> {
-        let local_config = PathBuf::from("dl-nzb.toml");
-        let standard_config = Self::config_path()?;
+/// Keys in a parsed config file that no setting reads, as dotted paths
+fn unknown_keys(table: &toml::Table) -> Vec<String> {
+    let section_keys = |name: &str| {
+        CONFIG_KEYS
+            .iter()
+            .find(|(s, _)| *s == name)
+            .map(|(_, k)| *k)
+    };
+    let check = |prefix: &str,
+                 values: &toml::Table,
+                 keys: &[&str],
+                 extra: &[&str],
+                 out: &mut Vec<String>| {
+        for key in values.keys() {
+            if !keys.contains(&key.as_str()) && !extra.contains(&key.as_str()) {
+                out.push(format!("{}.{}", prefix, key));
+            }
+        }
+    };
 
-        // Check for local config first (for development/testing)
-        let config_path = if local_config.exists() {
-            tracing::debug!("Loaded configuration from: {}", local_config.display());
-            local_config
-        } else {
-            // Create standard config file with defaults if it doesn't exist
-            if !standard_config.exists() {
-                tracing::debug!(
-                    "Config file not found, creating default at: {}",
-                    standard_config.display()
-                );
-
-                // Ensure directory exists
-                if let Some(parent) = standard_config.parent() {
-                    std::fs::create_dir_all(parent)?;
+    let mut unknown = Vec::new();
+    for (name, value) in table {
+        match (name.as_str(), value) {
+            ("version", _) => {}
+            ("servers", toml::Value::Array(servers)) => {
+                let keys = section_keys("usenet").unwrap_or_default();
+                for (i, server) in servers.iter().enumerate() {
+                    if let Some(server) = server.as_table() {
+                        check(&format!("servers[{}]", i), server, keys, &[], &mut unknown);
+                    }
                 }
+            }
+            ("indexers", toml::Value::Array(indexers)) => {
+                for (i, indexer) in indexers.iter().enumerate() {
+                    if let Some(indexer) = indexer.as_table() {
+                        let prefix = format!("indexers[{}]", i);
+                        check(&prefix, indexer, INDEXER_KEYS, &[], &mut unknown);
+                    }
+                }
+            }
+            (section, toml::Value::Table(values)) => match section_keys(section) {
+                Some(keys) => check(section, values, keys, &["speed_schedule"], &mut unknown),
+                None => unknown.push(section.to_string()),
+            },
+            (other, _) => unknown.push(other.to_string()),
+        }
+    }
+    unknown
+}
 
-                // Create default config file
-                Self::create_sample(&standard_config)?;
+/// Keys of an `[[indexers]]` entry, which has no environment overrides
+const INDEXER_KEYS: &[&str] = &["name", "url", "api_key", "user_agent", "headers"];
 
-                println!(
-                    "📝 Created default configuration at: {}",
-                    standard_config.display()
-                );
-                println!("⚙️  Please edit this file with your Usenet server credentials.");
-                println!();
-            }
-            tracing::debug!("Loaded configuration from: {}", standard_config.display());
-            standard_config
-        };
+/// Every `section.key` that can be set from the environment
+///
+/// Keep in sync with the config structs; `test_config_keys_cover_all_fields`
+/// fails when a field is missing here.
+pub const CONFIG_KEYS: &[(&str, &[&str])] = &[
+    (
+        "usenet",
+        &[
+            "server",
+            "port",
+            "username",
+            "password",
+            "ssl",
+            "verify_ssl_certs",
+            "connections",
+            "timeout",
+            "retry_attempts",
+            "retry_delay",
+            "retention_days",
+            "password_cmd",
+            "password_keyring",
+            "auto_connections",
+        ],
+    ),
+    (
+        "download",
+        &[
+            "dir",
+            "create_subfolders",
+            "folder_template",
+            "user_agent",
+            "force_redownload",
+            "max_speed",
+            "temp_dir",
+            "temp_max_age_hours",
+            "keep_nzb",
+            "follow_nested_nzbs",
+            "fsync",
+            "file_order",
+            "metadata_size",
+            "keep_partial",
+            "skip_verification",
+            "existing_files",
+            "skip_unavailable_groups",
+            "min_file_size",
+            "show_nfo",
+            "dir_mode",
+            "file_mode",
+            "chown",
+            "strict",
+            "strict_mark",
+            "max_retry_time",
+            "max_retry_bytes",
+            "stall_timeout",
+        ],
+    ),
+    (
+        "memory",
+        &[
+            "max_segments_in_memory",
+            "io_buffer_size",
+            "max_concurrent_files",
+            "max_buffer_mb",
+        ],
+    ),
+    (
+        "post_processing",
+        &[
+            "auto_par2_repair",
+            "auto_extract_rar",
+            "delete_rar_after_extract",
+            "delete_par2_after_repair",
+            "delete_par2_after_extract",
+            "deobfuscate_file_names",
+            "refetch_damaged_segments",
+            "verify_media",
+            "extract_to_temp",
+            "password_file",
+            "script",
+            "script_timeout",
+            "link_dir",
+            "link_mode",
+            "link_collision",
+            "io_priority",
+            "checksum_threads",
+            "preserve_names",
+            "cleanup_patterns",
+            "create_par2_after_extract",
+            "par2_redundancy",
+            "par2_block_size",
+        ],
+    ),
+    (
+        "logging",
+        &[
+            "level",
+            "file",
+            "rotate_size_mb",
+            "keep_files",
+            "format",
+            "progress_interval",
+            "progress",
+            "speed_window",
+            "protocol_trace",
+        ],
+    ),
+    (
+        "tuning",
+        &[
+            "pipeline_size",
+            "connection_wait_timeout",
+            "max_concurrent_connections",
+            "large_file_threshold",
+            "auto_connections_margin",
+            "auto_connections_window",
+            "give_up_after_missing",
+        ],
+    ),
+    (
+        "notifications",
+        &[
+            "webhook",
+            "webhook_url",
+            "webhook_token",
+            "webhook_timeout",
+            "webhook_events",
+            "desktop",
+            "desktop_events",
+        ],
+    ),
+    ("metrics", &["listen"]),
+    ("api", &["listen", "token", "allow_remote"]),
+    ("speed_test", &["group", "articles"]),
+];
 
-        // Load and parse TOML file
-        let content = std::fs::read_to_string(&config_path)?;
-        let mut config: Config = toml::from_str(&content)
-            .map_err(|e| ConfigError::ParseError(format!("Failed to parse config: {}", e)))?;
+const ENV_PREFIX: &str = "DL_NZB_";
 
-        // Apply environment variable overrides
-        config = load_env_overrides(config);
+/// Map an environment variable name to a `(section, key)` pair
+///
+/// Accepts `DL_NZB__SECTION__KEY` as well as the older `DL_NZB_SECTION_KEY`
+/// form, which is resolved against the known section names.
+fn env_key(name: &str) -> Option<(&'static str, &'static str)> {
+    let rest = name.strip_prefix(ENV_PREFIX)?.to_ascii_lowercase();
 
-        // Expand tilde in paths
-        config.download.dir = expand_tilde(&config.download.dir);
-        if let Some(log_file) = config.logging.file.as_ref() {
-            config.logging.file = Some(expand_tilde(log_file));
-        }
+    let (section, key) = match rest.strip_prefix('_') {
+        Some(explicit) => explicit.split_once("__")?,
+        None => CONFIG_KEYS.iter().find_map(|(section, _)| {
+            rest.strip_prefix(section)
+                .and_then(|r| r.strip_prefix('_'))
+                .map(|key| (*section, key))
+        })?,
+    };
 
-        config.validate()?;
-        Ok(config)
-    }
+    let (section, keys) = CONFIG_KEYS.iter().find(|(s, _)| *s == section)?;
+    let key = keys.iter().find(|k| **k == key)?;
+    Some((section, key))
+}
 
-    /// Create a sample configuration file
-    pub fn create_sample<P: AsRef<Path>>(path: P) -> Result<()> {
-        let sample = Self::default();
-        let content = toml::to_string_pretty(&sample)
-            .map_err(|e| ConfigError::ParseError(format!("Failed to serialize config: {}", e)))?;
+/// Resolve a dotted `section.key` name against [`CONFIG_KEYS`]
+fn resolve_key(key: &str) -> Result<(&'static str, &'static str)> {
+    let sections = || {
+        CONFIG_KEYS
+            .iter()
+            .map(|(s, _)| *s)
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let (section, field) = key.split_once('.').ok_or_else(|| ConfigError::Invalid {
+        field: key.to_string(),
+        reason: format!("Expected <section>.<key>; sections: {}", sections()),
+    })?;
 
-        // Add helpful comments
-        let commented_content = format!(
-            r#"# dl-nzb Configuration File
-#
-# This file configures the dl-nzb Usenet downloader.
-# All settings can be overridden via environment variables with the DL_NZB_ prefix.
-# For example: DL_NZB_USENET_SERVER=news.example.com
-#
-# REQUIRED: Set your Usenet server details below
+    let (section, keys) = CONFIG_KEYS
+        .iter()
+        .find(|(s, _)| *s == section)
+        .ok_or_else(|| ConfigError::Invalid {
+            field: key.to_string(),
+            reason: format!("Unknown section; valid sections: {}", sections()),
+        })?;
+    let field = keys
+        .iter()
+        .find(|k| **k == field)
+        .ok_or_else(|| ConfigError::Invalid {
+            field: key.to_string(),
+            reason: format!(
+                "Unknown key; valid keys for [{}]: {}",
+                section,
+                keys.join(", ")
+            ),
+        })?;
 
-{}
+    Ok((section, field))
+}
 
-# Configuration Guide:
+/// Parse a string value into the TOML type of the field it replaces
+fn parse_value(raw: &str, current: Option<&toml::Value>) -> Option<toml::Value> {
+    let parse_bool = |raw: &str| match raw.to_ascii_lowercase().as_str() {
+        "true" | "1" | "yes" | "on" => Some(true),
+        "false" | "0" | "no" | "off" => Some(false),
+        _ => None,
+    };
+
+    match current {
+        Some(toml::Value::Boolean(_)) => parse_bool(raw).map(toml::Value::Boolean),
+        Some(toml::Value::Integer(_)) => raw.parse().ok().map(toml::Value::Integer),
+        Some(toml::Value::Float(_)) => raw.parse().ok().map(toml::Value::Float),
+        // Lists are given comma-separated: `success,failure`
+        Some(toml::Value::Array(_)) => Some(toml::Value::Array(
+            raw.split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(|item| toml::Value::String(item.to_string()))
+                .collect(),
+        )),
+        Some(_) => Some(toml::Value::String(raw.to_string())),
+        // Unset optional field: infer the type from the value
+        None => Some(
+            parse_bool(raw)
+                .map(toml::Value::Boolean)
+                .or_else(|| raw.parse().ok().map(toml::Value::Integer))
+                .unwrap_or_else(|| toml::Value::String(raw.to_string())),
+        ),
+    }
+}
+
+/// Apply `DL_NZB_` environment variables on top of a loaded config
+fn load_env_overrides(config: Config) -> Result<Config> {
+    apply_env_overrides(config, env::vars())
+}
+
+fn apply_env_overrides(
+    config: Config,
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> Result<Config> {
+    let mut vars: Vec<(String, String)> = vars
+        .into_iter()
+        .filter(|(name, _)| name.starts_with(ENV_PREFIX))
+        .collect();
+    if vars.is_empty() {
+        return Ok(config);
+    }
+    // Explicit `DL_NZB__` names sort after the legacy ones and win on conflict
+    vars.sort();
+
+    let mut root = toml::Value::try_from(&config)
+        .map_err(|e| ConfigError::ParseError(format!("Failed to serialize config: {}", e)))?;
+
+    for (name, raw) in vars {
+        let Some((section, key)) = env_key(&name) else {
+            tracing::debug!("Ignoring unknown environment variable {}", name);
+            continue;
+        };
+
+        let Some(table) = root.get_mut(section).and_then(|t| t.as_table_mut()) else {
+            continue;
+        };
+        let value = parse_value(&raw, table.get(key)).ok_or_else(|| ConfigError::Invalid {
+            field: name.clone(),
+            reason: format!("Invalid value '{}' for {}.{}", raw, section, key),
+        })?;
+        table.insert(key.to_string(), value);
+    }
+
+    root.try_into()
+        .map_err(|e| ConfigError::ParseError(format!("Invalid environment override: {}", e)).into())
+}
+
+/// Dotted keys the environment overrides, sorted
+pub fn env_overrides() -> Vec<String> {
+    overridden_keys(env::vars())
+}
+
+fn overridden_keys(vars: impl IntoIterator<Item = (String, String)>) -> Vec<String> {
+    let mut keys: Vec<String> = vars
+        .into_iter()
+        .filter_map(|(name, _)| env_key(&name).map(|(section, key)| format!("{}.{}", section, key)))
+        .collect();
+    keys.sort();
+    keys.dedup();
+    keys
+}
+
+impl Config {
+    /// Get the standard config file path
+    pub fn config_path() -> Result<PathBuf> {
+        let config_dir = dirs::config_dir().ok_or_else(|| ConfigError::Invalid {
+            field: "config_dir".to_string(),
+            reason: "Could not determine config directory".to_string(),
+        })?;
+        Ok(config_dir.join("dl-nzb").join("config.toml"))
+    }
+
+    /// Load configuration from `./dl-nzb.toml` or the standard location
+    pub fn load() -> Result<Self> {
+        Self::load_source(&ConfigSource::locate(None))
+    }
+
+    /// Load configuration from an explicitly specified file
+    ///
+    /// Unlike the default location, a missing file is an error rather than
+    /// being created with defaults.
+    pub fn load_from(path: &Path) -> Result<Self> {
+        Self::load_source(&ConfigSource::Explicit(path.to_path_buf()))
+    }
+
+    /// Load and validate configuration from a resolved source
+    ///
+    /// Passwords from the keyring or `password_cmd` are resolved here, once.
+    pub fn load_source(source: &ConfigSource) -> Result<Self> {
+        let mut config = Self::read_source(source)?;
+        config.resolve_passwords()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Resolve keyring/command passwords for every server
+    pub fn resolve_passwords(&mut self) -> Result<()> {
+        for server in std::iter::once(&mut self.usenet).chain(&mut self.servers) {
+            server.resolve_password()?;
+        }
+        Ok(())
+    }
+
+    /// Load configuration from a resolved source without validating it
+    ///
+    /// Environment overrides are applied and paths expanded as for [`Config::load`].
+    pub fn read_source(source: &ConfigSource) -> Result<Self> {
+        let mut config = match source {
+            ConfigSource::Explicit(path) => {
+                if !path.exists() {
+                    return Err(ConfigError::NotFound(path.clone()).into());
+                }
+                Self::from_file(path)?
+            }
+            ConfigSource::Local(path) => Self::from_file(path)?,
+            ConfigSource::Standard(path) => {
+                // Create standard config file with defaults if it doesn't exist
+                if !path.exists() {
+                    tracing::debug!(
+                        "Config file not found, creating default at: {}",
+                        path.display()
+                    );
+
+                    // Ensure directory exists
+                    if let Some(parent) = path.parent() {
+                        std::fs::create_dir_all(parent)?;
+                    }
+
+                    // Create default config file
+                    Self::create_sample(path)?;
+                    tracing::info!("Created default configuration at {}", path.display());
+                }
+                Self::from_file(path)?
+            }
+            ConfigSource::EnvOnly => {
+                tracing::debug!("No configuration file location, using defaults");
+                Self::default()
+            }
+        };
+
+        // Apply environment variable overrides
+        config = load_env_overrides(config)?;
+
+        config.expand_paths();
+
+        Ok(config)
+    }
+
+    /// The `[logging]` settings of a source, read before logging is set up
+    ///
+    /// Nothing is created, printed or logged: a missing or invalid file gives
+    /// the defaults here and is reported when the configuration is loaded.
+    pub fn logging_settings(source: &ConfigSource) -> LoggingConfig {
+        let mut config = Self::default();
+        let file_table = source
+            .path()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .and_then(|content| toml::from_str::<toml::Table>(&content).ok())
+            .and_then(|mut table| table.remove("logging"))
+            .and_then(|logging| logging.try_into::<toml::Table>().ok());
+        if let Some(file_table) = file_table {
+            // Keys the file leaves out keep their defaults
+            let mut table = toml::Table::try_from(&config.logging).unwrap_or_default();
+            table.extend(file_table);
+            if let Ok(logging) = table.try_into() {
+                config.logging = logging;
+            }
+        }
+        let mut config = load_env_overrides(config).unwrap_or_default();
+        config.expand_paths();
+        config.logging
+    }
+
+    /// Expand `~` in all path settings
+    fn expand_paths(&mut self) {
+        self.download.dir = expand_tilde(&self.download.dir);
+        if let Some(log_file) = self.logging.file.as_ref() {
+            self.logging.file = Some(expand_tilde(log_file));
+        }
+        if let Some(temp_dir) = self.download.temp_dir.as_ref() {
+            self.download.temp_dir = Some(expand_tilde(temp_dir));
+        }
+        if let Some(password_file) = self.post_processing.password_file.as_ref() {
+            self.post_processing.password_file = Some(expand_tilde(password_file));
+        }
+        if let Some(script) = self.post_processing.script.as_ref() {
+            self.post_processing.script = Some(expand_tilde(script));
+        }
+        if let Some(link_dir) = self.post_processing.link_dir.as_ref() {
+            self.post_processing.link_dir = Some(expand_tilde(link_dir));
+        }
+    }
+
+    /// Parse a TOML configuration file exactly as written (no overrides or expansion)
+    ///
+    /// Older layouts are upgraded in memory and unknown keys are warned about.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        tracing::debug!("Loaded configuration from: {}", path.display());
+        let (config, migration) = Self::parse_and_migrate(path)?;
+
+        if !migration.changes.is_empty() {
+            tracing::info!(
+                "{} uses config version {}; upgraded in memory ({}). Run with --migrate-config to update the file",
+                path.display(),
+                migration.from,
+                migration.changes.join("; ")
+            );
+        } else if migration.from > CONFIG_VERSION {
+            tracing::warn!(
+                "{} was written by a newer dl-nzb (config version {})",
+                path.display(),
+                migration.from
+            );
+        }
+        Ok(config)
+    }
+
+    /// Rewrite an older config file in the current layout, keeping a `.bak` copy
+    ///
+    /// Returns the backup path, or `None` when the file was already current.
+    pub fn migrate_file(path: &Path) -> Result<Option<PathBuf>> {
+        let (config, migration) = Self::parse_and_migrate(path)?;
+        if migration.from >= CONFIG_VERSION {
+            return Ok(None);
+        }
+
+        let mut backup = path.as_os_str().to_owned();
+        backup.push(".bak");
+        let backup = PathBuf::from(backup);
+        std::fs::copy(path, &backup)?;
+        config.save(path)?;
+        Ok(Some(backup))
+    }
+
+    fn parse_and_migrate(path: &Path) -> Result<(Self, Migration)> {
+        let content = std::fs::read_to_string(path)?;
+        let parse_error = |e: &dyn std::fmt::Display| {
+            DlNzbError::from(ConfigError::ParseError(format!(
+                "Failed to parse {}: {}",
+                path.display(),
+                e
+            )))
+        };
+
+        let mut table: toml::Table = toml::from_str(&content).map_err(|e| parse_error(&e))?;
+        let migration = migrate(&mut table);
+
+        let unknown = unknown_keys(&table);
+        if !unknown.is_empty() {
+            tracing::warn!(
+                "Ignoring unknown keys in {}: {}",
+                path.display(),
+                unknown.join(", ")
+            );
+        }
+
+        let config = toml::Value::Table(table)
+            .try_into()
+            .map_err(|e| parse_error(&e))?;
+        Ok((config, migration))
+    }
+
+    /// Create a sample configuration file
+    pub fn create_sample<P: AsRef<Path>>(path: P) -> Result<()> {
+        Self::default().save(path)
+    }
+
+    /// Write this configuration to a file using the documented template
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        std::fs::write(path, self.render()?)?;
+        Ok(())
+    }
+
+    /// Render as TOML wrapped in the documented template comments
+    fn render(&self) -> Result<String> {
+        let content = toml::to_string_pretty(self)
+            .map_err(|e| ConfigError::ParseError(format!("Failed to serialize config: {}", e)))?;
+
+        // Add helpful comments
+        Ok(format!(
+            r#"# dl-nzb Configuration File
+#
+# This file configures the dl-nzb Usenet downloader.
+# All settings can be overridden via environment variables named
+# DL_NZB__<SECTION>__<KEY>, for example: DL_NZB__USENET__SERVER=news.example.com
+# (the older DL_NZB_USENET_SERVER form still works)
+#
+# REQUIRED: Set your Usenet server details below
+
+{}
+
+# Configuration Guide:
+#
+# version      - Layout version of this file; older files are upgraded on load
+#                (dl-nzb --migrate-config rewrites them, keeping a .bak copy)
 #
 # [usenet]
 # server       - Your Usenet provider's server address (REQUIRED)
@@ -320,47 +1629,303 @@ impl Config {
 # ssl          - Use encrypted SSL/TLS connection (recommended)
 # connections  - Number of connections (30-50 typical, check your provider's limit)
 # timeout      - Connection timeout in seconds
-# retry_attempts - Number of times to retry failed downloads
+# retry_attempts - Times a batch of segments is fetched again after a dropped
+#                  connection or timeout (missing articles are never retried)
+# retry_delay  - Milliseconds before the first retry; doubles with each one
+# retention_days - Days of articles the server keeps (0 = unlimited); older
+#                  posts skip this server
+# password_cmd   - Command whose output is the password, instead of `password`
+#                  (e.g. "pass show usenet")
+# password_keyring - true to read the password from the OS keyring entry
+#                  dl-nzb/<server>/<username> (requires the keyring feature)
+# auto_connections - true to start with fewer connections and add them only while
+#                  throughput keeps growing, up to `connections`; the count it
+#                  settles on is remembered for the next run
+#
+# [[servers]]
+# Additional servers with the same keys as [usenet], tried in order when the
+# primary server is out of retention or missing articles
+#
+# [[indexers]]
+# NZB indexers, for arguments like indexer:<name>:<id>
+# name       - Name used in those arguments
+# url        - NZB download URL; {id} and {apikey} are filled in,
+#              e.g. "https://indexer.example/api?t=get&id={id}&apikey={apikey}"
+# api_key    - Value for {apikey}
+# user_agent - User agent for this indexer (default: download.user_agent)
+# headers    - Extra request headers, e.g. { "X-Api-Client" = "dl-nzb" }
 #
 # [download]
 # dir               - Where to save downloads
 # create_subfolders - Create a subfolder for each NZB file
+# folder_template   - Name of that subfolder, with "/" for nested folders: {nzbname},
+#                     {title} (the name when the NZB has none), {category} (its level
+#                     is left out when there's none), {date} (YYYY-MM-DD) of the day
+#                     the download starts, {year} and {month} (MM) of the newest post
+# max_speed         - Download speed cap in bytes per second (0 = unlimited)
+# user_agent        - User agent sent when fetching NZBs from URLs and indexers
+# temp_dir          - Scratch folder for partial downloads and extraction staging,
+#                     one subfolder per NZB (default: work in the download folder)
+# temp_max_age_hours - Remove leftover temp subfolders older than this (0 = never)
+# keep_nzb          - Save NZBs fetched from a URL or stdin into their download folder
+# follow_nested_nzbs - Download NZBs found in a finished download too, each into a
+#                     folder inside it, two levels deep at most (otherwise they're
+#                     only listed)
+# fsync             - When finished files are synced to disk: "per-file" before each
+#                     counts as complete, "per-nzb" once the whole NZB is done,
+#                     or "never" (fastest, but a crash can lose finished files)
+# file_order        - Order files download in: "largest-first" (fastest), "smallest-first",
+#                     "nzb-order", or "metadata-first" (small files such as NFO, SFV
+#                     and the PAR2 index first, then the rest largest first)
+# metadata_size     - Largest file in bytes that "metadata-first" treats as metadata
+# keep_partial      - Keep files still missing segments after PAR2 instead of deleting
+#                     them; a .dlnzb-failed marker lists the missing message-ids and
+#                     the next run fetches just those
+# skip_verification - How a file already there at the expected size is checked before
+#                     it's skipped: "size" (the size alone), "quick-hash" (CRC32s of its
+#                     first and last MiB against those recorded in .dlnzb-hashes when
+#                     it downloaded; files without a record download again), or "none"
+#                     (download everything again)
+# existing_files    - What happens to a file already there at another size that no
+#                     earlier run kept incomplete: "rename" (move it aside to
+#                     <name>.old first), "overwrite", or "skip" (keep it and leave the
+#                     file out). Files a run kept incomplete (keep_partial, or a
+#                     shutdown) resume instead: only their missing segments download
+# skip_unavailable_groups - Skip files posted only to newsgroups no server carries
+#                     (checked with GROUP before downloading) instead of trying them;
+#                     either way they're reported
+# min_file_size     - Skip files the NZB declares smaller than this many bytes, such as
+#                     zero-byte placeholders and ad text files (0 = keep everything);
+#                     PAR2 files are always downloaded
+# show_nfo          - Print the release's NFO (decoded from CP437, cut to the terminal
+#                     width) after it downloads, when there is exactly one; not in
+#                     --quiet or --json output (same as --show-nfo)
+# dir_mode          - Octal mode for created folders, e.g. "0775" (Unix only; default: umask)
+# file_mode         - Octal mode for finished and extracted files, e.g. "0664" (Unix only)
+# chown             - "user:group", "user" or ":group" (names or ids) to own finished files
+#                     and folders; needs root or CAP_CHOWN, warns and carries on otherwise
+# strict            - Fail an NZB for any failed segment PAR2 didn't repair, archive not
+#                     extracted, failed media check or post-processing error
+# strict_mark       - How a strict failure marks the output folder: "marker" writes a
+#                     .dlnzb-strict-failed file listing why, "rename" renames it <name>.failed
+# max_retry_time    - Seconds an NZB may spend on batch retries, fill servers and
+#                     re-fetching damaged segments; once spent, what's still missing
+#                     is final (0 = unlimited)
+# max_retry_bytes   - Bytes an NZB may fetch on those (0 = unlimited)
+# stall_timeout     - Seconds a file may go without finishing a batch of articles while
+#                     the rest of the NZB gets on, once another file has finished; its
+#                     missing segments then fail and PAR2 makes up for them (0 = never)
+#
+# [[download.speed_schedule]]
+# days      - Days the window applies to, e.g. ["mon", "tue"] (empty = every day)
+# from, to  - Local time as "HH:MM"; "24:00" is midnight, to < from runs overnight
+# max_speed - Cap in bytes per second while the window is active; overlapping
+#             windows use the lowest cap, outside all windows max_speed applies
 #
 # [memory]
 # max_segments_in_memory - How many segments to buffer (affects memory usage)
 # io_buffer_size        - Buffer size in bytes (8MB recommended for performance)
 # max_concurrent_files  - How many files to download simultaneously
+# max_buffer_mb         - Hard cap on decoded segments held in memory, in MiB
+#                         (0 = one MiB per max_segments_in_memory)
 #
 # [post_processing]
 # auto_par2_repair        - Automatically verify/repair with PAR2 files
 # auto_extract_rar        - Automatically extract RAR archives
 # delete_rar_after_extract - Delete RAR files after successful extraction
 # delete_par2_after_repair - Delete PAR2 files after successful repair
+# delete_par2_after_extract - Delete PAR2/.rev files of extracted sets
+#                            (defaults to the value of delete_rar_after_extract)
 # deobfuscate_file_names  - Rename obfuscated files to meaningful names
+# refetch_damaged_segments - Re-download segments behind PAR2-damaged blocks
+#                            before falling back to a full repair
+# verify_media            - Check extracted MKV/MP4/AVI files for truncation
+# extract_to_temp         - Extract into a staging folder, move into place on success
+#                           (set to false to extract in place on tight disk budgets);
+#                           staging goes in download.temp_dir when set
+# password_file           - File with one archive password per line, tried after
+#                           the NZB's own password
+# script                  - Script to run after each download (see README for its environment)
+# script_timeout          - Seconds before the script is killed
+# link_dir                - Library folder the finished files (not archives or PAR2) of
+#                           each successful NZB go into, in the same subfolder
+# link_mode               - "hardlink" (copies when link_dir is on another filesystem),
+#                           "symlink", "copy" or "move"
+# link_collision          - When a file is already there: "skip", "overwrite" or
+#                           "rename" (adds " (2)" before the extension)
+# io_priority             - "parallel", or "exclusive" to pause downloads writing to the
+#                           same disk while PAR2 or extraction runs (for programs that
+#                           download and post-process at once; the binary takes NZBs
+#                           one at a time)
+# checksum_threads        - Files hashed at once when checking SFV files (verify
+#                           --checksums) and recording quick hashes
+# preserve_names          - Keep the names files were posted under: files PAR2
+#                           renames are moved back, deobfuscation is skipped and
+#                           the real names are listed in names.json
+# cleanup_patterns        - Files deleted from the download folder once post-processing
+#                           is done, as globs (or "re:" regexes) on the name, ignoring
+#                           case; the default catches common ad files ("*.url",
+#                           "*downloaded from*.txt", ...); [] keeps everything
+# create_par2_after_extract - Make a new PAR2 set for the finished files (archives
+#                           extracted, ads removed), as par2-create does; never when
+#                           PAR2, extraction or verify_media failed; needs a par2 binary
+# par2_redundancy         - Recovery data of created sets, in percent of the files' size
+# par2_block_size         - Block size of created sets in bytes, a multiple of 4
+#                           (0 = chosen by par2)
+#
+# [logging]
+# level             - "error", "warn", "info", "debug" or "trace"
+# file              - Write log lines to this file instead of the terminal (--log-file)
+# rotate_size_mb    - Rename the log file to <file>.1 once it reaches this many MiB and
+#                     start a new one (0 = never, the file grows without limit)
+# keep_files        - Rotated log files kept (<file>.1 is the newest)
+# format            - "pretty", "compact" or "json" (one object per line, with progress
+#                     logged as lines instead of bars), like --log-format
+# progress_interval - Seconds between plain-text progress lines, logged instead of
+#                     progress bars when stderr isn't a terminal (or --no-progress)
+# progress          - "aggregate" (one bar per NZB) or "per-file" (also a bar for each
+#                     file being downloaded, like --progress-per-file)
+# speed_window      - Seconds of history behind the displayed speed and ETA
+# protocol_trace    - Log each command sent and reply line received, per connection,
+#                     like -vvv (AUTHINFO PASS is masked; article bodies are only
+#                     counted)
+#
+# [notifications]
+# webhook         - POST the run summary (the --json document) to webhook_url
+# webhook_url     - http(s) URL to post to
+# webhook_token   - Optional bearer token for the Authorization header
+# webhook_timeout - Seconds before giving up on the webhook
+# webhook_events  - Outcomes to send: "success", "partial", "failure"
+# desktop         - Show a desktop notification (requires the
+#                   desktop-notifications feature)
+# desktop_events  - Outcomes to show, as for webhook_events
+#
+# [metrics]
+# listen - Address for a Prometheus /metrics endpoint, e.g. "127.0.0.1:9090"
+#          (requires the metrics feature; empty = off)
+#
+# [api]
+# listen       - Address for the JSON API of `watch`, e.g. "127.0.0.1:6789"
+#                (requires the server feature; empty = off)
+# token        - Bearer token every request must carry; empty = no auth
+# allow_remote - Allow listen to be an address other than loopback
+#
+# [speed_test]
+# group    - Newsgroup `test --speed` reads from
+# articles - Message-IDs to download (without <>); empty = the newest posts in group
 "#,
             content
-        );
+        ))
+    }
 
-        std::fs::write(path, commented_content)?;
+    /// Get a setting by its dotted key (e.g. `usenet.connections`)
+    ///
+    /// Returns `None` for optional settings that are unset.
+    pub fn get_key(&self, key: &str) -> Result<Option<String>> {
+        let (section, field) = resolve_key(key)?;
+        let root = toml::Value::try_from(self)
+            .map_err(|e| ConfigError::ParseError(format!("Failed to serialize config: {}", e)))?;
+
+        Ok(root
+            .get(section)
+            .and_then(|table| table.get(field))
+            .map(|value| match value {
+                toml::Value::String(s) => s.clone(),
+                other => other.to_string(),
+            }))
+    }
+
+    /// Set a setting by its dotted key, parsing the value as the field's type
+    pub fn set_key(&mut self, key: &str, value: &str) -> Result<()> {
+        let (section, field) = resolve_key(key)?;
+        let mut root = toml::Value::try_from(&*self)
+            .map_err(|e| ConfigError::ParseError(format!("Failed to serialize config: {}", e)))?;
+
+        let table = root
+            .get_mut(section)
+            .and_then(|t| t.as_table_mut())
+            .ok_or_else(|| ConfigError::ParseError(format!("Missing section [{}]", section)))?;
+        let parsed = parse_value(value, table.get(field)).ok_or_else(|| ConfigError::Invalid {
+            field: key.to_string(),
+            reason: format!("Invalid value '{}'", value),
+        })?;
+        table.insert(field.to_string(), parsed);
+
+        *self = root.try_into().map_err(|e| ConfigError::Invalid {
+            field: key.to_string(),
+            reason: e.to_string(),
+        })?;
         Ok(())
     }
 
+    /// A copy with every secret replaced by a mask, for display
+    pub fn redacted(&self) -> Self {
+        let mut config = self.clone();
+        for server in std::iter::once(&mut config.usenet).chain(&mut config.servers) {
+            server.password = server.password.masked();
+        }
+        config.notifications.webhook_token = config.notifications.webhook_token.masked();
+        config.api.token = config.api.token.masked();
+        for indexer in &mut config.indexers {
+            indexer.api_key = indexer.api_key.masked();
+        }
+        config
+    }
+
+    /// The primary server followed by any additional servers
+    pub fn all_servers(&self) -> impl Iterator<Item = &UsenetConfig> {
+        std::iter::once(&self.usenet).chain(&self.servers)
+    }
+
+    /// Indices into [`Config::all_servers`] whose retention covers a post of this age
+    pub fn servers_for_age(&self, age_days: f64) -> Vec<usize> {
+        self.all_servers()
+            .enumerate()
+            .filter(|(_, server)| server.retains(age_days))
+            .map(|(index, _)| index)
+            .collect()
+    }
+
     /// Validate configuration
     pub fn validate(&self) -> Result<()> {
         // Validate Usenet settings
-        if self.usenet.server.is_empty() {
-            return Err(ConfigError::NoServer.into());
+        for server in self.all_servers() {
+            if server.connections == 0 || server.connections > 100 {
+                return Err(ConfigError::InvalidConnections {
+                    count: server.connections,
+                }
+                .into());
+            }
         }
 
-        if self.usenet.username.is_empty() || self.usenet.password.is_empty() {
-            return Err(ConfigError::NoCredentials.into());
+        if let Some(server) = self.servers.iter().find(|s| s.server.is_empty()) {
+            return Err(ConfigError::Invalid {
+                field: "servers".to_string(),
+                reason: format!("Server entry on port {} has no address", server.port),
+            }
+            .into());
         }
 
-        if self.usenet.connections == 0 || self.usenet.connections > 100 {
-            return Err(ConfigError::InvalidConnections {
-                count: self.usenet.connections,
+        for (i, indexer) in self.indexers.iter().enumerate() {
+            let invalid = |reason: String| ConfigError::Invalid {
+                field: format!("indexers[{}]", i),
+                reason,
+            };
+            if indexer.name.is_empty() || indexer.name.contains(':') {
+                return Err(invalid("needs a name without ':'".to_string()).into());
+            }
+            if self.indexers[..i].iter().any(|o| o.name == indexer.name) {
+                return Err(invalid(format!("'{}' is listed twice", indexer.name)).into());
+            }
+            if !(indexer.url.starts_with("http://") || indexer.url.starts_with("https://"))
+                || !indexer.url.contains("{id}")
+            {
+                return Err(invalid(format!(
+                    "url '{}' must be an http(s) URL with an {{id}} placeholder",
+                    indexer.url
+                ))
+                .into());
             }
-            .into());
         }
 
         // Validate memory settings
@@ -389,6 +1954,99 @@ impl Config {
             .into());
         }
 
+        for window in &self.download.speed_schedule {
+            let invalid = |reason: String| ConfigError::Invalid {
+                field: "download.speed_schedule".to_string(),
+                reason,
+            };
+            for time in [&window.from, &window.to] {
+                if crate::download::parse_time_of_day(time).is_none() {
+                    return Err(invalid(format!("Invalid time '{}', expected HH:MM", time)).into());
+                }
+            }
+            if let Some(day) = window
+                .days
+                .iter()
+                .find(|d| d.parse::<chrono::Weekday>().is_err())
+            {
+                return Err(invalid(format!("Invalid day '{}'", day)).into());
+            }
+        }
+
+        crate::download::Permissions::from_config(&self.download)?;
+        crate::processing::compile_cleanup_patterns(&self.post_processing.cleanup_patterns)?;
+        if !(1..=100).contains(&self.post_processing.par2_redundancy) {
+            return Err(ConfigError::Invalid {
+                field: "post_processing.par2_redundancy".to_string(),
+                reason: "Must be between 1 and 100".to_string(),
+            }
+            .into());
+        }
+        if self.post_processing.par2_block_size % 4 != 0 {
+            return Err(ConfigError::Invalid {
+                field: "post_processing.par2_block_size".to_string(),
+                reason: "Must be a multiple of 4".to_string(),
+            }
+            .into());
+        }
+        crate::download::check_folder_template(&self.download.folder_template).map_err(
+            |reason| ConfigError::Invalid {
+                field: "download.folder_template".to_string(),
+                reason,
+            },
+        )?;
+
+        let webhook_url = &self.notifications.webhook_url;
+        if self.notifications.webhook
+            && !(webhook_url.starts_with("http://") || webhook_url.starts_with("https://"))
+        {
+            return Err(ConfigError::Invalid {
+                field: "notifications.webhook_url".to_string(),
+                reason: format!("'{}' is not an http(s) URL", webhook_url),
+            }
+            .into());
+        }
+
+        let listen = &self.metrics.listen;
+        if !listen.is_empty() && listen.parse::<std::net::SocketAddr>().is_err() {
+            return Err(ConfigError::Invalid {
+                field: "metrics.listen".to_string(),
+                reason: format!("'{}' is not an address like 127.0.0.1:9090", listen),
+            }
+            .into());
+        }
+
+        let listen = &self.api.listen;
+        if !listen.is_empty() {
+            let invalid = |reason: String| ConfigError::Invalid {
+                field: "api.listen".to_string(),
+                reason,
+            };
+            let addr = listen.parse::<std::net::SocketAddr>().map_err(|_| {
+                invalid(format!(
+                    "'{}' is not an address like 127.0.0.1:6789",
+                    listen
+                ))
+            })?;
+            if !addr.ip().is_loopback() && !self.api.allow_remote {
+                return Err(invalid(format!(
+                    "{} is reachable from other machines; set api.allow_remote = true to allow it",
+                    addr
+                ))
+                .into());
+            }
+        }
+
+        // Missing server details are checked last so `config set` can tell an
+        // incomplete setup apart from an invalid value
+        if self.usenet.server.is_empty() {
+            return Err(ConfigError::NoServer.into());
+        }
+
+        if self.usenet.username.is_empty() || self.usenet.password.is_empty() {
+            return Err(ConfigError::NoCredentials.into());
+        }
+
         Ok(())
     }
 
@@ -396,6 +2054,10 @@ impl Config {
     pub fn ensure_dirs(&self) -> Result<()> {
         std::fs::create_dir_all(&self.download.dir)?;
 
+        if let Some(temp_dir) = &self.download.temp_dir {
+            std::fs::create_dir_all(temp_dir)?;
+        }
+
         if let Some(log_file) = &self.logging.file {
             if let Some(parent) = log_file.parent() {
                 std::fs::create_dir_all(parent)?;
@@ -422,9 +2084,190 @@ impl Config {
         if let Some(dir) = overrides.download_dir {
             self.download.dir = dir;
         }
+        if let Some(dir) = overrides.temp_dir {
+            self.download.temp_dir = Some(dir);
+        }
         if let Some(level) = overrides.log_level {
             self.logging.level = level;
         }
+        if let Some(format) = overrides.log_format {
+            self.logging.format = format;
+        }
+        if let Some(file) = overrides.log_file {
+            self.logging.file = Some(file);
+        }
+        if overrides.progress_per_file {
+            self.logging.progress = ProgressLayout::PerFile;
+        }
+    }
+}
+
+/// Where the configuration is read from, in order of precedence
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigSource {
+    /// Given with `--config`; must exist
+    Explicit(PathBuf),
+    /// `dl-nzb.toml` in the working directory
+    Local(PathBuf),
+    /// The per-user config file, created with defaults if missing
+    Standard(PathBuf),
+    /// No config file location available; defaults plus environment overrides
+    EnvOnly,
+}
+
+impl ConfigSource {
+    /// Resolve the source for an optional `--config` path
+    pub fn locate(explicit: Option<&Path>) -> Self {
+        Self::choose(
+            explicit,
+            Path::new("dl-nzb.toml"),
+            Config::config_path().ok(),
+        )
+    }
+
+    fn choose(explicit: Option<&Path>, local: &Path, standard: Option<PathBuf>) -> Self {
+        if let Some(path) = explicit {
+            Self::Explicit(expand_tilde(path))
+        } else if local.exists() {
+            Self::Local(local.to_path_buf())
+        } else if let Some(path) = standard {
+            Self::Standard(path)
+        } else {
+            Self::EnvOnly
+        }
+    }
+
+    /// The file backing this source, if any
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Self::Explicit(path) | Self::Local(path) | Self::Standard(path) => Some(path),
+            Self::EnvOnly => None,
+        }
+    }
+}
+
+/// Sections a `<name>.nzb.toml` sidecar may override
+const SIDECAR_SECTIONS: &[&str] = &["download", "post_processing"];
+
+/// Per-NZB settings from a `<name>.nzb.toml` sidecar next to the NZB
+///
+/// The sidecar holds a partial config limited to the `download` and
+/// `post_processing` sections plus top-level `category` and `password`.
+#[derive(Debug, Clone)]
+pub struct NzbOverrides {
+    pub path: PathBuf,
+    /// Replaces the NZB's own category
+    pub category: Option<String>,
+    /// Archive password tried before the NZB's own passwords
+    pub password: Option<Secret>,
+    sections: toml::Table,
+}
+
+impl NzbOverrides {
+    /// `movie.nzb` -> `movie.nzb.toml`
+    pub fn sidecar_path(nzb_path: &Path) -> PathBuf {
+        let mut path = nzb_path.as_os_str().to_owned();
+        path.push(".toml");
+        PathBuf::from(path)
+    }
+
+    /// Load the sidecar for an NZB, if there is one
+    pub fn load(nzb_path: &Path) -> Result<Option<Self>> {
+        let path = Self::sidecar_path(nzb_path);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let content = std::fs::read_to_string(&path)?;
+        let mut sections: toml::Table = toml::from_str(&content).map_err(|e| {
+            ConfigError::ParseError(format!("Failed to parse {}: {}", path.display(), e))
+        })?;
+        let invalid = |reason: String| ConfigError::Invalid {
+            field: path.display().to_string(),
+            reason,
+        };
+
+        let mut take_string = |key: &str| match sections.remove(key) {
+            None => Ok(None),
+            Some(toml::Value::String(value)) => Ok(Some(value)),
+            Some(_) => Err(invalid(format!("'{}' must be a string", key))),
+        };
+        let category = take_string("category")?;
+        let password = take_string("password")?.map(Secret::from);
+
+        for (section, values) in &sections {
+            if !SIDECAR_SECTIONS.contains(&section.as_str()) {
+                return Err(invalid(format!(
+                    "'{}' can't be overridden per NZB (allowed: {}, category, password)",
+                    section,
+                    SIDECAR_SECTIONS.join(", ")
+                ))
+                .into());
+            }
+            let values = values
+                .as_table()
+                .ok_or_else(|| invalid(format!("'{}' must be a table", section)))?;
+            let (_, keys) = CONFIG_KEYS
+                .iter()
+                .find(|(s, _)| s == section)
+                .expect("sidecar sections are config sections");
+            if let Some(key) = values
+                .keys()
+                .find(|k| !keys.contains(&k.as_str()) && k.as_str() != "speed_schedule")
+            {
+                return Err(invalid(format!(
+                    "Unknown key {}.{}; valid keys: {}",
+                    section,
+                    key,
+                    keys.join(", ")
+                ))
+                .into());
+            }
+        }
+
+        let overrides = Self {
+            path,
+            category,
+            password,
+            sections,
+        };
+        // Surface type errors now rather than halfway through a run
+        overrides.apply(&Config::default())?;
+        Ok(Some(overrides))
+    }
+
+    /// Merge the sidecar over `config`, key by key
+    pub fn apply(&self, config: &Config) -> Result<Config> {
+        let mut root = toml::Value::try_from(config)
+            .map_err(|e| ConfigError::ParseError(format!("Failed to serialize config: {}", e)))?;
+
+        if let Some(root) = root.as_table_mut() {
+            for (section, values) in &self.sections {
+                let target = root
+                    .entry(section.clone())
+                    .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+                if let (Some(target), Some(values)) = (target.as_table_mut(), values.as_table()) {
+                    for (key, value) in values {
+                        target.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+
+        let mut merged: Config = root.try_into().map_err(|e| {
+            ConfigError::ParseError(format!("Invalid setting in {}: {}", self.path.display(), e))
+        })?;
+        crate::download::check_folder_template(&merged.download.folder_template).map_err(
+            |reason| {
+                ConfigError::ParseError(format!(
+                    "Invalid setting in {}: download.folder_template: {}",
+                    self.path.display(),
+                    reason
+                ))
+            },
+        )?;
+        merged.expand_paths();
+        Ok(merged)
     }
 }
 
@@ -436,7 +2279,219 @@ pub struct ConfigOverrides {
     pub connections: Option<u16>,
     pub ssl: Option<bool>,
     pub download_dir: Option<PathBuf>,
+    pub temp_dir: Option<PathBuf>,
     pub log_level: Option<String>,
+    pub log_format: Option<LogFormat>,
+    pub log_file: Option<PathBuf>,
+    pub progress_per_file: bool,
+}
+
+/// The settings a run works with once the config file, environment and
+/// command line are combined, with secrets masked
+///
+/// Download mode logs it, prints it with `-v` and puts it in the JSON
+/// summary; `config --check` shows the same snapshot.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EffectiveConfig {
+    /// The config file read, if there was one
+    pub config_file: Option<PathBuf>,
+    /// Dotted keys set by `DL_NZB_*` environment variables
+    pub env_overrides: Vec<String>,
+    /// Dotted keys set by command-line flags
+    pub cli_overrides: Vec<String>,
+    /// Servers in the order they're tried
+    pub servers: Vec<EffectiveServer>,
+    /// Segments requested per batch
+    pub pipeline_size: usize,
+    /// Speed cap in bytes per second (0 = unlimited)
+    pub max_speed: u64,
+    /// `download.speed_schedule` windows that may change the cap
+    pub speed_windows: usize,
+    /// Cap on decoded segments held in memory, in bytes
+    pub memory_budget: u64,
+    pub download_dir: PathBuf,
+    pub temp_dir: Option<PathBuf>,
+    pub post_processing: EffectivePostProcessing,
+    /// Optional subsystems that failed to start and what runs instead,
+    /// e.g. "log file: ...; logging to stderr"
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub degraded: Vec<String>,
+}
+
+/// One server as it will be used
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EffectiveServer {
+    pub server: String,
+    pub port: u16,
+    pub ssl: bool,
+    pub verify_ssl_certs: bool,
+    pub username: String,
+    /// Masked, or empty when unset
+    pub password: String,
+    pub password_source: PasswordSource,
+    /// Connections configured
+    pub connections: u16,
+    /// Connections the pool may open
+    pub connections_effective: usize,
+    /// Days of articles kept (0 = unlimited)
+    pub retention_days: u32,
+}
+
+/// Post-processing steps that will run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EffectivePostProcessing {
+    pub par2_repair: bool,
+    pub extract_rar: bool,
+    pub delete_rar_after_extract: bool,
+    pub delete_par2_after_repair: bool,
+    pub script: Option<PathBuf>,
+}
+
+impl EffectiveConfig {
+    /// Snapshot `config`, read from `source` with `cli_overrides` applied
+    pub fn new(config: &Config, source: &ConfigSource, cli_overrides: Vec<String>) -> Self {
+        let config = config.redacted();
+        Self {
+            config_file: source.path().filter(|p| p.exists()).map(Path::to_path_buf),
+            env_overrides: env_overrides(),
+            cli_overrides,
+            servers: config
+                .all_servers()
+                .map(|server| EffectiveServer {
+                    server: server.server.clone(),
+                    port: server.port,
+                    ssl: server.ssl,
+                    verify_ssl_certs: server.verify_ssl_certs,
+                    username: server.username.clone(),
+                    password: server.password.expose().to_string(),
+                    password_source: server.password_source,
+                    connections: server.connections,
+                    connections_effective: server.connections as usize,
+                    retention_days: server.retention_days,
+                })
+                .collect(),
+            pipeline_size: config.tuning.pipeline_size,
+            max_speed: config.download.max_speed,
+            speed_windows: config.download.speed_schedule.len(),
+            memory_budget: config.memory.buffer_bytes(),
+            download_dir: config.download.dir.clone(),
+            temp_dir: config.download.temp_dir.clone(),
+            post_processing: EffectivePostProcessing {
+                par2_repair: config.post_processing.auto_par2_repair,
+                extract_rar: config.post_processing.auto_extract_rar,
+                delete_rar_after_extract: config.post_processing.delete_rar_after_extract,
+                delete_par2_after_repair: config.post_processing.delete_par2_after_repair,
+                script: config.post_processing.script.clone(),
+            },
+            degraded: Vec::new(),
+        }
+    }
+
+    /// Note the subsystems running on a fallback
+    pub fn with_degraded(mut self, degraded: Vec<String>) -> Self {
+        self.degraded = degraded;
+        self
+    }
+
+    /// Take the connection limits of the pools actually built, one per
+    /// server in order, and the speed cap in force right now
+    pub fn with_runtime(mut self, pool_sizes: impl IntoIterator<Item = usize>, speed: u64) -> Self {
+        for (server, size) in self.servers.iter_mut().zip(pool_sizes) {
+            server.connections_effective = size;
+        }
+        self.max_speed = speed;
+        self
+    }
+}
+
+impl std::fmt::Display for EffectiveConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let on_off = |on: bool| if on { "on" } else { "off" };
+        let keys = |keys: &[String]| {
+            if keys.is_empty() {
+                "none".to_string()
+            } else {
+                keys.join(", ")
+            }
+        };
+
+        match &self.config_file {
+            Some(path) => writeln!(f, "  Config file:     {}", path.display())?,
+            None => writeln!(f, "  Config file:     none")?,
+        }
+        writeln!(f, "  Environment:     {}", keys(&self.env_overrides))?;
+        writeln!(f, "  Command line:    {}", keys(&self.cli_overrides))?;
+        for server in &self.servers {
+            let tls = match (server.ssl, server.verify_ssl_certs) {
+                (false, _) => "plain",
+                (true, true) => "SSL",
+                (true, false) => "SSL, unverified",
+            };
+            let password = if server.password.is_empty() {
+                "no password".to_string()
+            } else {
+                format!("password from {}", server.password_source)
+            };
+            writeln!(
+                f,
+                "  Server:          {}:{} ({}), user '{}', {}",
+                server.server, server.port, tls, server.username, password
+            )?;
+            write!(f, "    connections {}", server.connections)?;
+            if server.connections_effective != server.connections as usize {
+                write!(f, " (using {})", server.connections_effective)?;
+            }
+            if server.retention_days > 0 {
+                write!(f, ", retention {} days", server.retention_days)?;
+            }
+            writeln!(f)?;
+        }
+        writeln!(
+            f,
+            "  Pipeline:        {} segments per batch",
+            self.pipeline_size
+        )?;
+        let speed = match self.max_speed {
+            0 => "unlimited".to_string(),
+            limit => format!("{}/s", human_bytes::human_bytes(limit as f64)),
+        };
+        match self.speed_windows {
+            0 => writeln!(f, "  Speed limit:     {}", speed)?,
+            windows => writeln!(
+                f,
+                "  Speed limit:     {} ({} scheduled window{})",
+                speed,
+                windows,
+                if windows == 1 { "" } else { "s" }
+            )?,
+        }
+        writeln!(
+            f,
+            "  Memory budget:   {}",
+            human_bytes::human_bytes(self.memory_budget as f64)
+        )?;
+        writeln!(f, "  Download dir:    {}", self.download_dir.display())?;
+        if let Some(temp_dir) = &self.temp_dir {
+            writeln!(f, "  Temp dir:        {}", temp_dir.display())?;
+        }
+        let post = &self.post_processing;
+        write!(
+            f,
+            "  Post-processing: PAR2 repair {}, RAR extraction {}, delete RARs {}, delete PAR2 {}",
+            on_off(post.par2_repair),
+            on_off(post.extract_rar),
+            on_off(post.delete_rar_after_extract),
+            on_off(post.delete_par2_after_repair)
+        )?;
+        if let Some(script) = &post.script {
+            write!(f, ", script {}", script.display())?;
+        }
+        writeln!(f)?;
+        for note in &self.degraded {
+            writeln!(f, "  Degraded:        {}", note)?;
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -450,6 +2505,15 @@ mod tests {
         assert_eq!(config.memory.io_buffer_size, 8 * 1024 * 1024);
     }
 
+    #[test]
+    fn test_memory_buffer_bytes() {
+        let mut memory = MemoryConfig::default();
+        // Derived from the segment count until set
+        assert_eq!(memory.buffer_bytes(), 800 * 1024 * 1024);
+        memory.max_buffer_mb = 256;
+        assert_eq!(memory.buffer_bytes(), 256 * 1024 * 1024);
+    }
+
     #[test]
     fn test_config_validation() {
         let mut config = Config::default();
@@ -459,7 +2523,693 @@ mod tests {
         // Set required fields
         config.usenet.server = "news.example.org".to_string();
         config.usenet.username = "user".to_string();
-        config.usenet.password = "pass".to_string();
+        config.usenet.password = "pass".into();
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_config_source_precedence() {
+        let dir = tempfile::tempdir().unwrap();
+        let explicit = dir.path().join("work.toml");
+        let local = dir.path().join("dl-nzb.toml");
+        let standard = dir.path().join("xdg").join("config.toml");
+
+        // Without a local file the standard path wins, and with no location at all
+        // only defaults and the environment remain
+        assert_eq!(
+            ConfigSource::choose(None, &local, Some(standard.clone())),
+            ConfigSource::Standard(standard.clone())
+        );
+        assert_eq!(
+            ConfigSource::choose(None, &local, None),
+            ConfigSource::EnvOnly
+        );
+
+        std::fs::write(&local, "").unwrap();
+        assert_eq!(
+            ConfigSource::choose(None, &local, Some(standard.clone())),
+            ConfigSource::Local(local.clone())
+        );
+
+        // An explicit path wins even when it doesn't exist
+        assert_eq!(
+            ConfigSource::choose(Some(&explicit), &local, Some(standard)),
+            ConfigSource::Explicit(explicit)
+        );
+    }
+
+    #[test]
+    fn test_load_from_missing_file_is_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("missing.toml");
+
+        let err = Config::load_from(&path).unwrap_err();
+        assert!(matches!(
+            err,
+            DlNzbError::Config(ConfigError::NotFound(ref p)) if *p == path
+        ));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_load_from_explicit_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("work.toml");
+        std::fs::write(
+            &path,
+            r#"
+            [usenet]
+            server = "work.example.org"
+            username = "user"
+            password = "pass"
+            connections = 8
+            "#,
+        )
+        .unwrap();
+
+        let config = Config::load_from(&path).unwrap();
+        assert_eq!(config.usenet.server, "work.example.org");
+        assert_eq!(config.usenet.connections, 8);
+    }
+
+    #[test]
+    fn test_logging_settings_read_before_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dl-nzb.toml");
+        std::fs::write(
+            &path,
+            r#"
+            [usenet]
+            server = "news.example.com"
+
+            [logging]
+            format = "json"
+            file = "/var/log/dl-nzb.log"
+            "#,
+        )
+        .unwrap();
+
+        let logging = Config::logging_settings(&ConfigSource::Explicit(path.clone()));
+        assert_eq!(logging.format, LogFormat::Json);
+        assert_eq!(logging.file, Some(PathBuf::from("/var/log/dl-nzb.log")));
+
+        // A broken or missing file leaves the defaults, without creating one
+        std::fs::write(&path, "[logging]\nformat = \"xml\"\n").unwrap();
+        let logging = Config::logging_settings(&ConfigSource::Explicit(path));
+        assert_eq!(logging.format, LogFormat::Pretty);
+        let missing = dir.path().join("missing.toml");
+        Config::logging_settings(&ConfigSource::Explicit(missing.clone()));
+        assert!(!missing.exists());
+    }
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_env_overrides_every_section() {
+        let config = apply_env_overrides(
+            Config::default(),
+            vars(&[
+                ("DL_NZB__USENET__SERVER", "env.example.org"),
+                ("DL_NZB__USENET__PORT", "119"),
+                ("DL_NZB__USENET__SSL", "false"),
+                ("DL_NZB__DOWNLOAD__DIR", "/srv/downloads"),
+                ("DL_NZB__DOWNLOAD__CREATE_SUBFOLDERS", "0"),
+                ("DL_NZB__MEMORY__IO_BUFFER_SIZE", "65536"),
+                ("DL_NZB__POST_PROCESSING__AUTO_EXTRACT_RAR", "false"),
+                ("DL_NZB__POST_PROCESSING__DELETE_PAR2_AFTER_EXTRACT", "true"),
+                ("DL_NZB__POST_PROCESSING__SCRIPT_TIMEOUT", "60"),
+                ("DL_NZB__LOGGING__LEVEL", "debug"),
+                ("DL_NZB__LOGGING__FILE", "/var/log/dl-nzb.log"),
+                ("DL_NZB__TUNING__PIPELINE_SIZE", "25"),
+            ]),
+        )
+        .unwrap();
+
+        assert_eq!(config.usenet.server, "env.example.org");
+        assert_eq!(config.usenet.port, 119);
+        assert!(!config.usenet.ssl);
+        assert_eq!(config.download.dir, PathBuf::from("/srv/downloads"));
+        assert!(!config.download.create_subfolders);
+        assert_eq!(config.memory.io_buffer_size, 65536);
+        assert!(!config.post_processing.auto_extract_rar);
+        assert_eq!(config.post_processing.delete_par2_after_extract, Some(true));
+        assert_eq!(config.post_processing.script_timeout, 60);
+        assert_eq!(config.logging.level, "debug");
+        assert_eq!(
+            config.logging.file,
+            Some(PathBuf::from("/var/log/dl-nzb.log"))
+        );
+        assert_eq!(config.tuning.pipeline_size, 25);
+    }
+
+    #[test]
+    fn test_legacy_env_names() {
+        let config = apply_env_overrides(
+            Config::default(),
+            vars(&[
+                ("DL_NZB_USENET_SERVER", "legacy.example.org"),
+                ("DL_NZB_USENET_CONNECTIONS", "40"),
+                ("DL_NZB_POST_PROCESSING_AUTO_EXTRACT_RAR", "false"),
+                ("DL_NZB_MEMORY_MAX_CONCURRENT_FILES", "3"),
+                // The explicit form wins over the legacy one
+                ("DL_NZB_DOWNLOAD_DIR", "/legacy"),
+                ("DL_NZB__DOWNLOAD__DIR", "/explicit"),
+                ("DL_NZB_UNRELATED", "ignored"),
+            ]),
+        )
+        .unwrap();
+
+        assert_eq!(config.usenet.server, "legacy.example.org");
+        assert_eq!(config.usenet.connections, 40);
+        assert!(!config.post_processing.auto_extract_rar);
+        assert_eq!(config.memory.max_concurrent_files, 3);
+        assert_eq!(config.download.dir, PathBuf::from("/explicit"));
+    }
+
+    #[test]
+    fn test_env_override_invalid_value() {
+        let result = apply_env_overrides(
+            Config::default(),
+            vars(&[("DL_NZB__USENET__PORT", "not-a-port")]),
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_config_keys_cover_all_fields() {
+        let value = toml::Value::try_from(Config::default()).unwrap();
+        for (section, table) in value.as_table().unwrap() {
+            let Some(table) = table.as_table() else {
+                continue;
+            };
+            let (_, keys) = CONFIG_KEYS
+                .iter()
+                .find(|(s, _)| s == section)
+                .unwrap_or_else(|| panic!("section {} missing from CONFIG_KEYS", section));
+            for key in table.keys() {
+                assert!(keys.contains(&key.as_str()), "{}.{} missing", section, key);
+            }
+        }
+    }
+
+    #[test]
+    fn test_get_and_set_keys() {
+        let mut config = Config::default();
+
+        config.set_key("usenet.connections", "42").unwrap();
+        config
+            .set_key("post_processing.verify_media", "true")
+            .unwrap();
+        config.set_key("logging.file", "/tmp/dl-nzb.log").unwrap();
+        config.set_key("download.fsync", "per-nzb").unwrap();
+        config
+            .set_key("download.file_order", "metadata-first")
+            .unwrap();
+        config
+            .set_key("download.skip_verification", "quick-hash")
+            .unwrap();
+        config.set_key("download.existing_files", "skip").unwrap();
+        config
+            .set_key("post_processing.par2_redundancy", "25")
+            .unwrap();
+
+        assert_eq!(config.usenet.connections, 42);
+        assert_eq!(config.download.fsync, FsyncPolicy::PerNzb);
+        assert_eq!(config.download.file_order, FileOrder::MetadataFirst);
+        assert_eq!(
+            config.download.skip_verification,
+            SkipVerification::QuickHash
+        );
+        assert_eq!(config.download.existing_files, ExistingFiles::Skip);
+        assert_eq!(config.post_processing.par2_redundancy, 25);
+        assert!(config.set_key("download.fsync", "sometimes").is_err());
+        assert!(config.post_processing.verify_media);
+        assert_eq!(config.get_key("usenet.connections").unwrap().unwrap(), "42");
+        assert_eq!(
+            config.get_key("logging.file").unwrap().unwrap(),
+            "/tmp/dl-nzb.log"
+        );
+        assert_eq!(config.get_key("download.temp_dir").unwrap(), None);
+
+        // Type mismatches are rejected and leave the config untouched
+        assert!(config.set_key("usenet.connections", "many").is_err());
+        assert!(config.set_key("usenet.port", "70000").is_err());
+        assert_eq!(config.usenet.connections, 42);
+    }
+
+    #[test]
+    fn test_unknown_key_lists_valid_keys() {
+        let err = Config::default().get_key("usenet.hostname").unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("valid keys for [usenet]"), "{}", message);
+        assert!(message.contains("server"), "{}", message);
+
+        assert!(Config::default().get_key("nonsense").is_err());
+        assert!(Config::default().set_key("bogus.key", "1").is_err());
+    }
+
+    #[test]
+    fn test_notification_settings() {
+        let mut config = Config::default();
+        config
+            .set_key("notifications.webhook_events", "success, failure")
+            .unwrap();
+        assert_eq!(
+            config.notifications.webhook_events,
+            [NotifyEvent::Success, NotifyEvent::Failure]
+        );
+        assert!(config
+            .set_key("notifications.desktop_events", "done")
+            .is_err());
+
+        config
+            .set_key("notifications.webhook_token", "t0ken")
+            .unwrap();
+        assert_eq!(
+            config.redacted().notifications.webhook_token.expose(),
+            Secret::MASK
+        );
+
+        config.usenet.server = "news.example.com".to_string();
+        config.usenet.username = "user".to_string();
+        config.usenet.password = Secret::new("pass");
+        config.notifications.webhook = true;
+        config.notifications.webhook_url = "hooks.example.com".to_string();
+        assert!(config.validate().is_err());
+        config.notifications.webhook_url = "https://hooks.example.com/dl".to_string();
+        config.validate().unwrap();
+
+        assert_eq!(NotifyEvent::of(RunStatus::Incomplete), NotifyEvent::Partial);
+    }
+
+    #[test]
+    fn test_save_round_trips() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+
+        let mut config = Config::default();
+        config.set_key("usenet.server", "news.example.org").unwrap();
+        config.save(&path).unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with("# dl-nzb Configuration File"));
+        let reloaded: Config = toml::from_str(&content).unwrap();
+        assert_eq!(reloaded.usenet.server, "news.example.org");
+    }
+
+    #[test]
+    fn test_secrets_are_redacted() {
+        let mut config = Config::default();
+        config.usenet.password = "hunter2".into();
+        config.servers.push(UsenetConfig {
+            password: "backup-pass".into(),
+            ..UsenetConfig::default()
+        });
+
+        assert!(!format!("{:?}", config).contains("hunter2"));
+        assert_eq!(config.usenet.password.to_string(), Secret::MASK);
+
+        let shown = toml::to_string(&config.redacted()).unwrap();
+        assert!(!shown.contains("hunter2"));
+        assert!(!shown.contains("backup-pass"));
+        assert!(shown.contains(Secret::MASK));
+
+        // Saving still writes the real value
+        let saved = toml::to_string(&config).unwrap();
+        assert!(saved.contains("hunter2"));
+
+        // Unset passwords stay visibly empty
+        let empty = Config::default().redacted();
+        assert!(empty.usenet.password.is_empty());
+    }
+
+    #[test]
+    fn test_effective_config() {
+        let mut config = Config::default();
+        config.usenet.server = "news.example.org".to_string();
+        config.usenet.password = "hunter2".into();
+        config.usenet.connections = 30;
+        config.servers.push(UsenetConfig {
+            server: "backup.example.org".to_string(),
+            ..UsenetConfig::default()
+        });
+
+        let effective = EffectiveConfig::new(
+            &config,
+            &ConfigSource::EnvOnly,
+            vec!["usenet.connections".to_string()],
+        )
+        .with_runtime([20, 10], 5_000_000);
+        assert_eq!(effective.config_file, None);
+        assert_eq!(effective.servers.len(), 2);
+        assert_eq!(effective.servers[0].connections, 30);
+        assert_eq!(effective.servers[0].connections_effective, 20);
+        assert_eq!(effective.servers[0].password, Secret::MASK);
+        assert_eq!(effective.servers[1].password, "");
+        assert_eq!(effective.max_speed, 5_000_000);
+
+        let shown = effective.to_string();
+        let json = serde_json::to_string(&effective).unwrap();
+        for text in [&shown, &json] {
+            assert!(!text.contains("hunter2"));
+            assert!(text.contains("news.example.org"));
+        }
+        assert!(shown.contains("connections 30 (using 20)"));
+        assert!(shown.contains("Command line:    usenet.connections"));
+        assert!(!shown.contains("Degraded"));
+
+        let degraded = effective
+            .with_degraded(vec![
+                "history: no config directory; kept in memory".to_string()
+            ])
+            .to_string();
+        assert!(degraded.contains("Degraded:        history: no config directory"));
+    }
+
+    #[test]
+    fn test_env_overridden_keys() {
+        let keys = overridden_keys(vars(&[
+            ("DL_NZB__DOWNLOAD__DIR", "/explicit"),
+            ("DL_NZB_DOWNLOAD_DIR", "/legacy"),
+            ("DL_NZB_USENET_CONNECTIONS", "40"),
+            ("DL_NZB_UNRELATED", "ignored"),
+            ("HOME", "/root"),
+        ]));
+        assert_eq!(keys, ["download.dir", "usenet.connections"]);
+    }
+
+    #[test]
+    fn test_speed_schedule_validation() {
+        let mut config = Config::default();
+        config.usenet.server = "news.example.org".to_string();
+        config.usenet.username = "user".to_string();
+        config.usenet.password = "pass".into();
+        config.download.speed_schedule.push(SpeedWindow {
+            days: vec!["mon".to_string(), "Friday".to_string()],
+            from: "08:00".to_string(),
+            to: "24:00".to_string(),
+            max_speed: 5 * 1024 * 1024,
+        });
+        assert!(config.validate().is_ok());
+
+        config.download.speed_schedule[0].to = "25:00".to_string();
+        assert!(config.validate().is_err());
+
+        config.download.speed_schedule[0].to = "24:00".to_string();
+        config.download.speed_schedule[0]
+            .days
+            .push("someday".to_string());
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_indexers_from_toml() {
+        let mut config: Config = toml::from_str(
+            r#"
+[[indexers]]
+name = "geek"
+url = "https://geek.example/api?t=get&id={id}&apikey={apikey}"
+api_key = "k3y"
+headers = { "X-Api-Client" = "dl-nzb" }
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.indexers[0].name, "geek");
+        assert_eq!(config.indexers[0].headers["X-Api-Client"], "dl-nzb");
+        assert_eq!(config.redacted().indexers[0].api_key.expose(), Secret::MASK);
+
+        config.usenet.server = "news.example.org".to_string();
+        config.usenet.username = "user".to_string();
+        config.usenet.password = "pass".into();
+        assert!(config.validate().is_ok());
+        config.indexers.push(config.indexers[0].clone());
+        assert!(config.validate().is_err());
+        config.indexers.pop();
+        config.indexers[0].url = "https://geek.example/api?apikey={apikey}".to_string();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_api_listens_on_loopback_unless_allowed() {
+        let mut config = Config::default();
+        config.usenet.server = "news.example.org".to_string();
+        config.usenet.username = "user".to_string();
+        config.usenet.password = "pass".into();
+        config.api.listen = "127.0.0.1:6789".to_string();
+        assert!(config.validate().is_ok());
+
+        config.api.listen = "0.0.0.0:6789".to_string();
+        assert!(config.validate().is_err());
+        config.api.allow_remote = true;
+        assert!(config.validate().is_ok());
+
+        config.api.listen = "localhost".to_string();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_folder_template_validation() {
+        let mut config = Config::default();
+        config.usenet.server = "news.example.org".to_string();
+        config.usenet.username = "user".to_string();
+        config.usenet.password = "pass".into();
+        config.download.folder_template = "{category}/{year}/{title}".to_string();
         assert!(config.validate().is_ok());
+
+        config.download.folder_template = "{yyyy-mm}/{nzbname}".to_string();
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("download.folder_template"), "{}", err);
+        assert!(err.contains("{yyyy-mm}"), "{}", err);
+    }
+
+    #[test]
+    fn test_nzb_sidecar_overrides() {
+        let dir = tempfile::tempdir().unwrap();
+        let nzb = dir.path().join("movie.nzb");
+        assert!(NzbOverrides::load(&nzb).unwrap().is_none());
+
+        std::fs::write(
+            dir.path().join("movie.nzb.toml"),
+            r#"
+            category = "movies"
+            password = "secret"
+
+            [download]
+            dir = "/srv/movies"
+
+            [post_processing]
+            auto_extract_rar = false
+            "#,
+        )
+        .unwrap();
+
+        let overrides = NzbOverrides::load(&nzb).unwrap().unwrap();
+        assert_eq!(overrides.category.as_deref(), Some("movies"));
+        assert_eq!(overrides.password.as_ref().unwrap().expose(), "secret");
+
+        let mut base = Config::default();
+        base.usenet.server = "news.example.org".to_string();
+        base.post_processing.verify_media = true;
+
+        let merged = overrides.apply(&base).unwrap();
+        assert_eq!(merged.download.dir, PathBuf::from("/srv/movies"));
+        assert!(!merged.post_processing.auto_extract_rar);
+        // Untouched settings come from the effective config
+        assert!(merged.post_processing.verify_media);
+        assert_eq!(merged.usenet.server, "news.example.org");
+    }
+
+    #[test]
+    fn test_invalid_nzb_sidecars() {
+        let dir = tempfile::tempdir().unwrap();
+        let nzb = dir.path().join("movie.nzb");
+        let sidecar = dir.path().join("movie.nzb.toml");
+
+        for content in [
+            "[usenet]\nserver = \"other\"",
+            "[post_processing]\nauto_extract_rar = \"maybe\"",
+            "[download]\ndirectory = \"/tmp\"",
+            "category = 5",
+            "[download]\nfolder_template = \"{nope}/{title}\"",
+            "not toml at all [",
+        ] {
+            std::fs::write(&sidecar, content).unwrap();
+            let err = NzbOverrides::load(&nzb).unwrap_err();
+            assert!(
+                err.to_string().contains("movie.nzb.toml"),
+                "{}: {}",
+                content,
+                err
+            );
+        }
+    }
+
+    /// Config as written by 0.1.0: no version, no tuning section
+    const CONFIG_0_1: &str = r#"
+[usenet]
+server = "news.example.org"
+port = 563
+username = "user"
+password = "pass"
+ssl = true
+verify_ssl_certs = true
+connections = 40
+timeout = 45
+retry_attempts = 3
+retry_delay = 1000
+
+[download]
+dir = "~/Downloads/usenet"
+create_subfolders = true
+user_agent = "dl-nzb/0.1.0"
+
+[memory]
+max_segments_in_memory = 1000
+io_buffer_size = 16777216
+max_concurrent_files = 100
+
+[post_processing]
+auto_par2_repair = true
+auto_extract_rar = true
+delete_rar_after_extract = false
+delete_par2_after_repair = false
+deobfuscate_file_names = true
+
+[logging]
+level = "info"
+format = "pretty"
+"#;
+
+    /// Hand-written list layout with a typo'd key, version 1
+    const CONFIG_SERVER_LIST: &str = r#"
+[[servers]]
+server = "primary.example.org"
+username = "user"
+password = "pass"
+
+[[servers]]
+server = "backup.example.org"
+username = "user2"
+password = "pass2"
+retention_days = 3000
+
+[download]
+dir = "downloads"
+create_subfolders = false
+user_agent = "dl-nzb"
+max_sped = 100
+"#;
+
+    fn write_fixture(dir: &Path, content: &str) -> PathBuf {
+        let path = dir.join("config.toml");
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_legacy_0_1_config_loads() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_fixture(dir.path(), CONFIG_0_1);
+
+        let config = Config::from_file(&path).unwrap();
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert_eq!(config.usenet.server, "news.example.org");
+        assert_eq!(config.usenet.connections, 40);
+        assert_eq!(
+            config.tuning.pipeline_size,
+            TuningConfig::default().pipeline_size
+        );
+        assert!(config.servers.is_empty());
+    }
+
+    #[test]
+    fn test_server_list_config_migrates() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_fixture(dir.path(), CONFIG_SERVER_LIST);
+
+        let config = Config::from_file(&path).unwrap();
+        assert_eq!(config.usenet.server, "primary.example.org");
+        assert_eq!(config.servers.len(), 1);
+        assert_eq!(config.servers[0].server, "backup.example.org");
+        assert_eq!(config.servers[0].retention_days, 3000);
+
+        let mut table: toml::Table = toml::from_str(CONFIG_SERVER_LIST).unwrap();
+        let migration = migrate(&mut table);
+        assert_eq!(migration.from, 1);
+        assert_eq!(migration.changes.len(), 1);
+        assert_eq!(unknown_keys(&table), vec!["download.max_sped"]);
+    }
+
+    #[test]
+    fn test_v2_temp_dir_moves_to_download() {
+        // A version 2 file: staging dir under [post_processing]
+        let mut table = toml::Value::try_from(Config::default()).unwrap();
+        let root = table.as_table_mut().unwrap();
+        root.insert("version".into(), toml::Value::Integer(2));
+        root["post_processing"]
+            .as_table_mut()
+            .unwrap()
+            .insert("temp_dir".into(), "/scratch".into());
+        let mut table = root.clone();
+
+        migrate(&mut table);
+        assert!(unknown_keys(&table).is_empty());
+
+        let config: Config = toml::Value::Table(table).try_into().unwrap();
+        assert_eq!(config.download.temp_dir, Some(PathBuf::from("/scratch")));
+    }
+
+    #[test]
+    fn test_migrate_file_keeps_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_fixture(dir.path(), CONFIG_0_1);
+
+        let backup = Config::migrate_file(&path).unwrap().unwrap();
+        assert_eq!(std::fs::read_to_string(&backup).unwrap(), CONFIG_0_1);
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content.contains(&format!("version = {}", CONFIG_VERSION)));
+        assert_eq!(
+            Config::from_file(&path).unwrap().usenet.server,
+            "news.example.org"
+        );
+
+        // Already current: nothing to do
+        assert!(Config::migrate_file(&path).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_servers_for_age() {
+        let config: Config = toml::from_str(
+            r#"
+            [usenet]
+            server = "primary.example.org"
+            retention_days = 1000
+
+            [[servers]]
+            server = "backup.example.org"
+            retention_days = 4000
+
+            [[servers]]
+            server = "unlimited.example.org"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.servers[0].connections, 20);
+        assert_eq!(config.servers_for_age(10.0), vec![0, 1, 2]);
+        assert_eq!(config.servers_for_age(1500.0), vec![1, 2]);
+        assert_eq!(config.servers_for_age(5000.0), vec![2]);
+
+        let primary_only = Config {
+            servers: Vec::new(),
+            ..config
+        };
+        assert!(primary_only.servers_for_age(1500.0).is_empty());
     }
 }
@@ -0,0 +1,121 @@
+//! Alternative sources for the Usenet password
+//!
+//! Besides the plain `password` value, a server can take its password from a
+//! shell command (`password_cmd`, e.g. `pass show usenet`) or, with the
+//! `keyring` feature, from the OS keyring entry `dl-nzb/<server>/<username>`.
+//! Precedence is: command-line flag > keyring > command > config value.
+
+use serde::{Deserialize, Serialize};
+use std::process::Command;
+
+use crate::error::{ConfigError, DlNzbError};
+
+type Result<T> = std::result::Result<T, DlNzbError>;
+
+/// Where a server's password was taken from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PasswordSource {
+    /// `password` in the config file (or its environment override)
+    #[default]
+    Config,
+    /// Output of `password_cmd`
+    Command,
+    /// The OS keyring
+    Keyring,
+    /// The `--password` flag
+    Cli,
+}
+
+impl std::fmt::Display for PasswordSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Config => "config file",
+            Self::Command => "password_cmd",
+            Self::Keyring => "OS keyring",
+            Self::Cli => "command line",
+        })
+    }
+}
+
+fn failed(origin: &str, reason: impl Into<String>) -> DlNzbError {
+    ConfigError::PasswordSource {
+        origin: origin.to_string(),
+        reason: reason.into(),
+    }
+    .into()
+}
+
+/// Run `password_cmd` through the shell and use its first line of output
+pub fn password_from_command(command: &str) -> Result<String> {
+    let origin = format!("password_cmd `{}`", command);
+
+    #[cfg(windows)]
+    let output = Command::new("cmd").args(["/C", command]).output();
+    #[cfg(not(windows))]
+    let output = Command::new("sh").args(["-c", command]).output();
+
+    let output = output.map_err(|e| failed(&origin, e.to_string()))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr
+            .lines()
+            .next()
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("exited with {}", output.status));
+        return Err(failed(&origin, reason));
+    }
+
+    let stdout = String::from_utf8(output.stdout)
+        .map_err(|_| failed(&origin, "output is not valid UTF-8"))?;
+    match stdout.lines().next() {
+        Some(password) if !password.is_empty() => Ok(password.to_string()),
+        _ => Err(failed(&origin, "produced no output")),
+    }
+}
+
+/// Look up `dl-nzb/<server>/<username>` in the OS keyring
+#[cfg(feature = "keyring")]
+pub fn password_from_keyring(server: &str, username: &str) -> Result<String> {
+    let origin = format!("keyring entry dl-nzb/{}/{}", server, username);
+    keyring::Entry::new(&format!("dl-nzb/{}", server), username)
+        .and_then(|entry| entry.get_password())
+        .map_err(|e| failed(&origin, e.to_string()))
+}
+
+/// Look up `dl-nzb/<server>/<username>` in the OS keyring
+#[cfg(not(feature = "keyring"))]
+pub fn password_from_keyring(server: &str, username: &str) -> Result<String> {
+    Err(failed(
+        &format!("keyring entry dl-nzb/{}/{}", server, username),
+        "dl-nzb was built without the `keyring` feature",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_password_from_command() {
+        assert_eq!(
+            password_from_command("printf 'hunter2\\nignored\\n'").unwrap(),
+            "hunter2"
+        );
+
+        let err = password_from_command("echo nope >&2; exit 3").unwrap_err();
+        assert!(err.to_string().contains("nope"), "{}", err);
+
+        assert!(password_from_command("true").is_err());
+    }
+
+    #[cfg(not(feature = "keyring"))]
+    #[test]
+    fn test_keyring_without_feature_names_source() {
+        let err = password_from_keyring("news.example.org", "bob").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("keyring entry dl-nzb/news.example.org/bob"));
+    }
+}
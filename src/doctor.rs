@@ -0,0 +1,225 @@
+//! Setup diagnostics for `dl-nzb config --check`
+//!
+//! Runs every check independently so one problem doesn't hide the next, and
+//! reports pass/warn/fail per item. Nothing here prints; the caller renders
+//! the report.
+
+use human_bytes::human_bytes;
+use serde::Serialize;
+use std::path::Path;
+
+use crate::config::{Config, UsenetConfig};
+use crate::error::{DlNzbError, NntpError};
+use crate::nntp::{clock, AsyncNntpConnection};
+use crate::processing::par2_self_test;
+
+/// Below this much free space the download/temp directory check warns
+const LOW_SPACE_WARNING: u64 = 1024 * 1024 * 1024;
+
+/// Message-id no server should have, used to confirm "430 no such article" replies
+const MISSING_MESSAGE_ID: &str = "dl-nzb-config-check.0000000000@invalid";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+/// Outcome of a single diagnostic
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    /// Short label, e.g. "news.example.org: login"
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: impl Into<String>, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            status,
+            detail: detail.into(),
+        }
+    }
+
+    pub(crate) fn pass(name: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self::new(name, CheckStatus::Pass, detail)
+    }
+
+    pub(crate) fn warn(name: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self::new(name, CheckStatus::Warn, detail)
+    }
+
+    pub(crate) fn fail(name: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self::new(name, CheckStatus::Fail, detail)
+    }
+}
+
+/// Run all checks against a loaded configuration (or the error loading it)
+pub async fn diagnose(config: std::result::Result<Config, DlNzbError>) -> Vec<Check> {
+    let config = match config {
+        Ok(config) => config,
+        Err(e) => return vec![Check::fail("config", e.to_string())],
+    };
+
+    let mut checks = vec![match config.validate() {
+        Ok(()) => Check::pass("config", "loaded and valid"),
+        Err(e) => Check::fail("config", e.to_string()),
+    }];
+
+    for server in config.all_servers().filter(|s| !s.server.is_empty()) {
+        checks.extend(check_server(server).await);
+    }
+
+    checks.push(check_dir("download dir", &config.download.dir));
+    if let Some(temp_dir) = &config.download.temp_dir {
+        checks.push(check_dir("temp dir", temp_dir));
+    }
+
+    checks.push(match par2_self_test() {
+        Ok(backend) => Check::pass("par2", format!("{} PAR2 verified a test set", backend)),
+        Err(e) => Check::fail("par2", e.to_string()),
+    });
+
+    checks
+}
+
+/// DNS, connect, login, article lookup and clock checks for one server
+async fn check_server(server: &UsenetConfig) -> Vec<Check> {
+    let label = |what: &str| format!("{}: {}", server.server, what);
+    let mut checks = Vec::new();
+
+    match tokio::net::lookup_host((server.server.as_str(), server.port)).await {
+        Ok(mut addrs) => match addrs.next() {
+            Some(addr) => checks.push(Check::pass(label("dns"), addr.ip().to_string())),
+            None => {
+                checks.push(Check::fail(label("dns"), "no addresses"));
+                return checks;
+            }
+        },
+        Err(e) => {
+            checks.push(Check::fail(label("dns"), e.to_string()));
+            return checks;
+        }
+    }
+
+    // The usual ports are 563 for SSL and 119 for plain NNTP
+    let port_hint = match (server.ssl, server.port) {
+        (true, 119) => Some("port 119 is normally plain NNTP; SSL usually uses 563"),
+        (false, 563) => Some("port 563 is normally SSL; set ssl = true"),
+        _ => None,
+    };
+    if let Some(hint) = port_hint {
+        checks.push(Check::warn(label("port"), hint));
+    }
+
+    let mut conn = match AsyncNntpConnection::connect(server, None).await {
+        Ok(conn) => conn,
+        Err(DlNzbError::Nntp(NntpError::AuthFailed(reason))) => {
+            checks.push(Check::pass(label("connect"), connect_detail(server)));
+            checks.push(Check::fail(
+                label("login"),
+                format!("{} (password from {})", reason, server.password_source),
+            ));
+            return checks;
+        }
+        Err(e) => {
+            let detail = match port_hint {
+                Some(hint) => format!("{} ({})", e, hint),
+                None => e.to_string(),
+            };
+            checks.push(Check::fail(label("connect"), detail));
+            return checks;
+        }
+    };
+    checks.push(Check::pass(label("connect"), connect_detail(server)));
+    checks.push(Check::pass(
+        label("login"),
+        format!("password from {}", server.password_source),
+    ));
+
+    checks.push(match conn.stat(MISSING_MESSAGE_ID).await {
+        Ok(false) => Check::pass(label("article lookup"), "missing articles reported as 430"),
+        Ok(true) => Check::warn(
+            label("article lookup"),
+            "server claims to have a nonexistent article",
+        ),
+        Err(e) => Check::fail(label("article lookup"), e.to_string()),
+    });
+
+    checks.push(match conn.clock_skew().await {
+        Ok(skew) => match clock::skew_warning(&server.server, skew) {
+            Some(warning) => Check::warn(label("clock"), warning),
+            None => Check::pass(
+                label("clock"),
+                format!("within {}s of the server", skew.unsigned_abs()),
+            ),
+        },
+        Err(e) => Check::warn(label("clock"), format!("could not read server time: {}", e)),
+    });
+
+    let _ = conn.close().await;
+    checks
+}
+
+fn connect_detail(server: &UsenetConfig) -> String {
+    let transport = if server.ssl { "TLS" } else { "plain" };
+    format!("port {} ({})", server.port, transport)
+}
+
+/// Write access and free space for a directory dl-nzb writes to
+fn check_dir(name: &str, dir: &Path) -> Check {
+    let writable = std::fs::create_dir_all(dir).and_then(|()| {
+        let probe = dir.join(".dl-nzb-check");
+        std::fs::write(&probe, b"")?;
+        std::fs::remove_file(&probe)
+    });
+    if let Err(e) = writable {
+        return Check::fail(name, format!("{} is not writable: {}", dir.display(), e));
+    }
+
+    match fs2::available_space(dir) {
+        Ok(free) if free < LOW_SPACE_WARNING => Check::warn(
+            name,
+            format!("{}: only {} free", dir.display(), human_bytes(free as f64)),
+        ),
+        Ok(free) => Check::pass(
+            name,
+            format!("{} ({} free)", dir.display(), human_bytes(free as f64)),
+        ),
+        Err(e) => Check::warn(
+            name,
+            format!("{}: free space unknown: {}", dir.display(), e),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_unparseable_config_fails_alone() {
+        let err = crate::error::ConfigError::ParseError("bad".to_string()).into();
+        let checks = diagnose(Err(err)).await;
+        assert_eq!(checks.len(), 1);
+        assert_eq!(checks[0].status, CheckStatus::Fail);
+    }
+
+    #[test]
+    fn test_check_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let check = check_dir("download dir", &dir.path().join("new"));
+        assert_ne!(check.status, CheckStatus::Fail, "{}", check.detail);
+
+        let file = dir.path().join("file");
+        std::fs::write(&file, b"").unwrap();
+        assert_eq!(
+            check_dir("download dir", &file.join("sub")).status,
+            CheckStatus::Fail
+        );
+    }
+}
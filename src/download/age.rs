@@ -0,0 +1,74 @@
+//! How old a post is
+//!
+//! An NZB's `date` attributes are Unix timestamps set by the poster's
+//! software, which isn't always right: some write `0`, some a date years
+//! ahead. Those are treated as unknown rather than as a post from 1970 (past
+//! every server's retention) or one from the future. A date slightly ahead
+//! of the local clock is only skew, and counts as just posted.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SECONDS_PER_DAY: f64 = 86_400.0;
+
+/// How far ahead of the local clock a date may be and still count as skew
+const CLOCK_SKEW: u64 = 86_400;
+
+/// Age in days of a post dated `date` as of `now`, or `None` when the date
+/// can't be right
+pub fn post_age_days(date: u64, now: SystemTime) -> Option<f64> {
+    let now = now.duration_since(UNIX_EPOCH).ok()?.as_secs();
+    if date == 0 || date > now.saturating_add(CLOCK_SKEW) {
+        return None;
+    }
+    Some(now.saturating_sub(date) as f64 / SECONDS_PER_DAY)
+}
+
+/// An age in days the way people say it: "5 hours", "12 days", "2.3 years"
+pub fn format_age(days: f64) -> String {
+    if days >= 365.0 {
+        format!("{:.1} years", days / 365.25)
+    } else if days >= 1.0 {
+        let days = days.floor() as u64;
+        format!("{} day{}", days, if days == 1 { "" } else { "s" })
+    } else {
+        let hours = (days * 24.0).floor() as u64;
+        format!("{} hour{}", hours, if hours == 1 { "" } else { "s" })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn test_post_age_days() {
+        let now = at(1_700_000_000);
+        assert_eq!(post_age_days(1_700_000_000 - 86_400 * 3, now), Some(3.0));
+        // The epoch itself is a poster that left the date out
+        assert_eq!(post_age_days(0, now), None);
+        assert_eq!(
+            post_age_days(1, now),
+            Some((1_700_000_000 - 1) as f64 / 86_400.0)
+        );
+        // A clock a little behind the poster's, not a post from the future
+        assert_eq!(post_age_days(1_700_000_000 + 3_600, now), Some(0.0));
+        assert_eq!(post_age_days(1_700_000_000 + 86_400 * 30, now), None);
+        assert_eq!(post_age_days(u64::MAX, now), None);
+        // A local clock before 1970 can't date anything
+        assert_eq!(post_age_days(1, UNIX_EPOCH - Duration::from_secs(1)), None);
+    }
+
+    #[test]
+    fn test_format_age() {
+        assert_eq!(format_age(0.0), "0 hours");
+        assert_eq!(format_age(1.0 / 24.0), "1 hour");
+        assert_eq!(format_age(1.5), "1 day");
+        assert_eq!(format_age(364.9), "364 days");
+        assert_eq!(format_age(840.0), "2.3 years");
+    }
+}
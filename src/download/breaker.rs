@@ -0,0 +1,116 @@
+//! Giving up on a file whose articles are gone
+//!
+//! When a file's segments keep coming back `430`/`423` from every server, the
+//! rest of them almost certainly will too; asking for thousands more only
+//! costs time. A [`MissingBreaker`] watches each batch as it finishes and
+//! trips after `tuning.give_up_after_missing` missing segments in a row.
+//! Batches that haven't started by then count their segments as missing
+//! without a request. Segments lost to timeouts or dropped connections say
+//! nothing about whether the articles exist, so they neither count nor
+//! reset the run; any segment found resets it.
+
+use std::sync::Mutex;
+
+/// Trips after enough consecutive definitively missing segments
+#[derive(Debug)]
+pub struct MissingBreaker {
+    /// Missing segments in a row it takes to trip (0 = never)
+    threshold: usize,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Missing segments since the last one found
+    run: usize,
+    /// Segments the servers answered for, and how many of those were missing
+    answered: usize,
+    missing: usize,
+    tripped: bool,
+}
+
+impl MissingBreaker {
+    pub fn new(threshold: usize) -> Self {
+        Self {
+            threshold,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Whether the file has been given up on
+    pub fn is_tripped(&self) -> bool {
+        self.state.lock().unwrap().tripped
+    }
+
+    /// Record a finished batch: segments `found`, and segments every server
+    /// said it doesn't have
+    ///
+    /// Returns `(missing, answered)` when this batch tripped the breaker, for
+    /// the one line saying so.
+    pub fn record(&self, found: usize, missing: usize) -> Option<(usize, usize)> {
+        let mut state = self.state.lock().unwrap();
+        state.answered += found + missing;
+        state.missing += missing;
+        if found > 0 {
+            state.run = 0;
+        } else {
+            state.run += missing;
+        }
+        if state.tripped || self.threshold == 0 || state.run < self.threshold {
+            return None;
+        }
+        state.tripped = true;
+        Some((state.missing, state.answered))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trips_on_consecutive_missing() {
+        let breaker = MissingBreaker::new(200);
+        // Four full batches gone, the fourth tips it over
+        for _ in 0..3 {
+            assert_eq!(breaker.record(0, 50), None);
+        }
+        assert!(!breaker.is_tripped());
+        assert_eq!(breaker.record(0, 50), Some((200, 200)));
+        assert!(breaker.is_tripped());
+        // Reported once
+        assert_eq!(breaker.record(0, 50), None);
+        assert!(breaker.is_tripped());
+    }
+
+    #[test]
+    fn test_found_segment_resets_the_run() {
+        let breaker = MissingBreaker::new(100);
+        assert_eq!(breaker.record(0, 90), None);
+        // A batch with anything in it breaks the run
+        assert_eq!(breaker.record(1, 49), None);
+        assert_eq!(breaker.record(0, 50), None);
+        assert_eq!(breaker.record(0, 50), Some((239, 240)));
+    }
+
+    #[test]
+    fn test_transient_failures_do_not_count() {
+        let breaker = MissingBreaker::new(100);
+        assert_eq!(breaker.record(0, 60), None);
+        // A batch lost to timeouts: nothing found, nothing definitively missing
+        for _ in 0..10 {
+            assert_eq!(breaker.record(0, 0), None);
+        }
+        assert!(!breaker.is_tripped());
+        assert_eq!(breaker.record(0, 40), Some((100, 100)));
+    }
+
+    #[test]
+    fn test_zero_threshold_never_trips() {
+        let breaker = MissingBreaker::new(0);
+        for _ in 0..100 {
+            assert_eq!(breaker.record(0, 50), None);
+        }
+        assert!(!breaker.is_tripped());
+    }
+}
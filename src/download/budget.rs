@@ -0,0 +1,198 @@
+//! Bounding what an NZB spends on retries
+//!
+//! Batches fetched again after an error, batches resumed after the server
+//! closed on them, segments asked of fill servers and segments re-fetched for
+//! damaged PAR2 blocks all come on top of the download itself, and on a badly
+//! damaged post they can keep a run busy for hours. One [`RetryBudget`] per
+//! NZB is shared by all of them and caps the total at
+//! `download.max_retry_time` seconds and `download.max_retry_bytes`. Once
+//! either is spent none of them try again, and whatever is still missing
+//! counts as failed.
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::DownloadConfig;
+
+/// Where a [`RetryBudget`] takes the time from
+pub type BudgetClock = Arc<dyn Fn() -> Instant + Send + Sync>;
+
+/// Time and bytes left for one NZB's retries
+pub struct RetryBudget {
+    /// `None` for no limit
+    max_time: Option<Duration>,
+    max_bytes: Option<u64>,
+    clock: BudgetClock,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    time: Duration,
+    bytes: u64,
+    exhausted: bool,
+}
+
+impl RetryBudget {
+    pub fn new(max_time: Option<Duration>, max_bytes: Option<u64>) -> Self {
+        Self {
+            max_time,
+            max_bytes,
+            clock: Arc::new(Instant::now),
+            state: Mutex::default(),
+        }
+    }
+
+    /// `max_retry_time` and `max_retry_bytes`; 0 leaves either unlimited
+    pub fn from_config(config: &DownloadConfig) -> Self {
+        Self::new(
+            (config.max_retry_time > 0).then(|| Duration::from_secs(config.max_retry_time)),
+            (config.max_retry_bytes > 0).then_some(config.max_retry_bytes),
+        )
+    }
+
+    /// Never runs out
+    pub fn unlimited() -> Self {
+        Self::new(None, None)
+    }
+
+    /// Take the time from `clock`
+    pub fn with_clock(mut self, clock: impl Fn() -> Instant + Send + Sync + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Whether another retry may start
+    pub fn allows(&self) -> bool {
+        !self.is_exhausted()
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.state.lock().unwrap().exhausted
+    }
+
+    /// The time now, to pass to [`charge`](Self::charge) when the retry is done
+    pub fn start(&self) -> Instant {
+        (self.clock)()
+    }
+
+    /// Count a retry that began at `started` and moved `bytes` on the wire
+    ///
+    /// Returns `true` when this retry used up what was left.
+    pub fn charge(&self, started: Instant, bytes: u64) -> bool {
+        let elapsed = (self.clock)().saturating_duration_since(started);
+        let mut state = self.state.lock().unwrap();
+        state.time += elapsed;
+        state.bytes += bytes;
+        if state.exhausted
+            || !(self.max_time.is_some_and(|max| state.time >= max)
+                || self.max_bytes.is_some_and(|max| state.bytes >= max))
+        {
+            return false;
+        }
+        state.exhausted = true;
+        tracing::warn!(
+            "Retry budget exhausted after {:.0}s and {} bytes of retries; remaining failures are final",
+            state.time.as_secs_f64(),
+            state.bytes
+        );
+        true
+    }
+
+    /// Time and bytes retries have used so far
+    pub fn spent(&self) -> (Duration, u64) {
+        let state = self.state.lock().unwrap();
+        (state.time, state.bytes)
+    }
+}
+
+impl Default for RetryBudget {
+    fn default() -> Self {
+        Self::unlimited()
+    }
+}
+
+impl fmt::Debug for RetryBudget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RetryBudget")
+            .field("max_time", &self.max_time)
+            .field("max_bytes", &self.max_bytes)
+            .field("state", &self.state)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    /// A clock that only moves when told to, in whole seconds
+    fn mock_clock() -> (Arc<AtomicU64>, impl Fn() -> Instant + Send + Sync + 'static) {
+        let base = Instant::now();
+        let seconds = Arc::new(AtomicU64::new(0));
+        let now = Arc::clone(&seconds);
+        (seconds, move || {
+            base + Duration::from_secs(now.load(Ordering::Relaxed))
+        })
+    }
+
+    #[test]
+    fn test_time_runs_out() {
+        let (seconds, clock) = mock_clock();
+        let budget = RetryBudget::new(Some(Duration::from_secs(60)), None).with_clock(clock);
+
+        for _ in 0..2 {
+            let started = budget.start();
+            seconds.fetch_add(25, Ordering::Relaxed);
+            assert!(!budget.charge(started, 1_000_000));
+        }
+        assert!(budget.allows());
+
+        // Time between retries doesn't count
+        seconds.fetch_add(600, Ordering::Relaxed);
+        let started = budget.start();
+        seconds.fetch_add(10, Ordering::Relaxed);
+        assert!(budget.charge(started, 0));
+        assert!(budget.is_exhausted());
+        assert_eq!(budget.spent(), (Duration::from_secs(60), 2_000_000));
+
+        // Reported once
+        let started = budget.start();
+        seconds.fetch_add(10, Ordering::Relaxed);
+        assert!(!budget.charge(started, 0));
+        assert!(!budget.allows());
+    }
+
+    #[test]
+    fn test_bytes_run_out() {
+        let (seconds, clock) = mock_clock();
+        let budget = RetryBudget::new(None, Some(1_000)).with_clock(clock);
+        let started = budget.start();
+        seconds.fetch_add(3_600, Ordering::Relaxed);
+        assert!(!budget.charge(started, 999));
+        assert!(budget.charge(budget.start(), 1));
+        assert!(!budget.allows());
+    }
+
+    #[test]
+    fn test_unlimited_and_config() {
+        let (seconds, clock) = mock_clock();
+        let budget = RetryBudget::unlimited().with_clock(clock);
+        for _ in 0..100 {
+            let started = budget.start();
+            seconds.fetch_add(3_600, Ordering::Relaxed);
+            assert!(!budget.charge(started, u32::MAX as u64));
+        }
+        assert!(budget.allows());
+
+        let config = DownloadConfig {
+            max_retry_time: 0,
+            max_retry_bytes: 5,
+            ..DownloadConfig::default()
+        };
+        let budget = RetryBudget::from_config(&config);
+        assert_eq!((budget.max_time, budget.max_bytes), (None, Some(5)));
+    }
+}
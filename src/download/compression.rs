@@ -0,0 +1,171 @@
+//! Transparent decompression of `.nzb.gz` / `.nzb.bz2` input
+//!
+//! The format is detected from magic bytes rather than the extension, since
+//! indexers don't always name their downloads after the encoding. Output is
+//! capped at [`MAX_DECOMPRESSED_SIZE`] so a small, hostile file can't expand
+//! into memory without bound.
+
+use std::io::{BufRead, BufReader, Read};
+
+use crate::error::{DlNzbError, NzbError};
+
+type Result<T> = std::result::Result<T, DlNzbError>;
+
+/// Largest NZB accepted after decompression
+pub const MAX_DECOMPRESSED_SIZE: u64 = 256 * 1024 * 1024;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const BZIP2_MAGIC: &[u8] = b"BZh";
+
+/// Compression an NZB arrived in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Bzip2,
+}
+
+impl Compression {
+    pub fn detect(bytes: &[u8]) -> Self {
+        if bytes.starts_with(GZIP_MAGIC) {
+            Self::Gzip
+        } else if bytes.starts_with(BZIP2_MAGIC) {
+            Self::Bzip2
+        } else {
+            Self::None
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::None => "plain",
+            Self::Gzip => "gzip",
+            Self::Bzip2 => "bzip2",
+        }
+    }
+}
+
+/// Decode raw NZB bytes, decompressing gzip or bzip2 first
+pub fn decode(bytes: Vec<u8>) -> Result<String> {
+    let compression = Compression::detect(&bytes);
+    let bytes = match compression {
+        Compression::None => bytes,
+        Compression::Gzip => decompress(
+            compression,
+            flate2::read::MultiGzDecoder::new(&bytes[..]),
+            MAX_DECOMPRESSED_SIZE,
+        )?,
+        Compression::Bzip2 => decompress(
+            compression,
+            bzip2::read::MultiBzDecoder::new(&bytes[..]),
+            MAX_DECOMPRESSED_SIZE,
+        )?,
+    };
+    String::from_utf8(bytes).map_err(|_| {
+        NzbError::ParseError(format!(
+            "{} NZB is not valid UTF-8 text",
+            compression.name()
+        ))
+        .into()
+    })
+}
+
+/// Stream of decoded NZB bytes, decompressing gzip or bzip2 on the fly
+///
+/// Like [`decode`] it stops a byte past [`MAX_DECOMPRESSED_SIZE`], so an
+/// oversized NZB ends mid-document and fails to parse.
+pub fn reader<'a>(input: impl Read + 'a) -> Result<Box<dyn BufRead + 'a>> {
+    let mut input = BufReader::new(input);
+    let decoded: Box<dyn Read + 'a> = match Compression::detect(input.fill_buf()?) {
+        Compression::None => Box::new(input),
+        Compression::Gzip => Box::new(flate2::read::MultiGzDecoder::new(input)),
+        Compression::Bzip2 => Box::new(bzip2::read::MultiBzDecoder::new(input)),
+    };
+    Ok(Box::new(BufReader::new(
+        decoded.take(MAX_DECOMPRESSED_SIZE + 1),
+    )))
+}
+
+fn decompress(compression: Compression, reader: impl Read, limit: u64) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    reader
+        .take(limit + 1)
+        .read_to_end(&mut out)
+        .map_err(|e| NzbError::ParseError(format!("Corrupt {} data: {}", compression.name(), e)))?;
+    if out.len() as u64 > limit {
+        return Err(NzbError::ParseError(format!(
+            "{} NZB expands to more than {} bytes",
+            compression.name(),
+            limit
+        ))
+        .into());
+    }
+    Ok(out)
+}
+
+/// Strip a compression extension: `show.nzb.gz` -> `show.nzb`
+pub fn strip_extension(name: &str) -> &str {
+    [".gz", ".bz2"]
+        .iter()
+        .find_map(|ext| {
+            name.len()
+                .checked_sub(ext.len())
+                .filter(|&at| name.is_char_boundary(at) && name[at..].eq_ignore_ascii_case(ext))
+                .map(|at| &name[..at])
+        })
+        .unwrap_or(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const XML: &str =
+        r#"<?xml version="1.0"?><nzb xmlns="http://www.newzbin.com/DTD/2003/nzb"></nzb>"#;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_decode_by_magic_bytes() {
+        assert_eq!(decode(XML.as_bytes().to_vec()).unwrap(), XML);
+        assert_eq!(decode(gzip(XML.as_bytes())).unwrap(), XML);
+
+        let mut encoder = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+        encoder.write_all(XML.as_bytes()).unwrap();
+        let bz = encoder.finish().unwrap();
+        assert_eq!(Compression::detect(&bz), Compression::Bzip2);
+        assert_eq!(decode(bz).unwrap(), XML);
+
+        let mut streamed = String::new();
+        reader(&gzip(XML.as_bytes())[..])
+            .unwrap()
+            .read_to_string(&mut streamed)
+            .unwrap();
+        assert_eq!(streamed, XML);
+    }
+
+    #[test]
+    fn test_decompression_is_bounded() {
+        let bomb = gzip(&[b' '; 64 * 1024]);
+        assert!(bomb.len() < 1024);
+        let reader = flate2::read::MultiGzDecoder::new(&bomb[..]);
+        let err = decompress(Compression::Gzip, reader, 4096).unwrap_err();
+        assert!(err.to_string().contains("expands to more than"), "{}", err);
+
+        let mut truncated = gzip(XML.as_bytes());
+        truncated.truncate(truncated.len() / 2);
+        assert!(decode(truncated).is_err());
+    }
+
+    #[test]
+    fn test_strip_extension() {
+        assert_eq!(strip_extension("show.nzb.gz"), "show.nzb");
+        assert_eq!(strip_extension("show.nzb.BZ2"), "show.nzb");
+        assert_eq!(strip_extension("show.nzb"), "show.nzb");
+    }
+}
@@ -0,0 +1,339 @@
+//! How many connections downloads use at once
+//!
+//! Every batch of segments holds a permit from the [`ConnectionLimit`] while
+//! it's fetched, so the permits in use are the connections downloading right
+//! now. The limit is normally the primary server's `connections`. With
+//! `usenet.auto_connections` an [`AutoTune`] starts lower and adds
+//! connections for as long as each step raises throughput by
+//! `tuning.auto_connections_margin` percent over a
+//! `tuning.auto_connections_window`, then settles on the best count seen:
+//! past that point more connections only add handshakes, buffers and load on
+//! the server. A limit pinned at runtime (`POST /limits`) ends the tuning.
+
+use human_bytes::human_bytes;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// How often the tuner looks at whether every connection is busy
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Cap on concurrent connections, shared by all downloads
+#[derive(Clone)]
+pub struct ConnectionLimit {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    semaphore: Arc<Semaphore>,
+    max: usize,
+    limit: AtomicUsize,
+    /// Permits to retire as they come back, after the limit was lowered
+    /// below the number in use
+    excess: AtomicUsize,
+    active: AtomicUsize,
+    /// Limit auto-tuning settled on (0 = not yet, or not tuning)
+    settled: AtomicUsize,
+    /// Set at runtime, so auto-tuning leaves it alone
+    pinned: AtomicBool,
+}
+
+/// A connection's worth of the limit, returned when dropped
+pub struct ConnectionPermit {
+    permit: Option<OwnedSemaphorePermit>,
+    inner: Arc<Inner>,
+}
+
+impl ConnectionLimit {
+    /// Allow `limit` connections, and never more than `max`
+    pub fn new(limit: usize, max: usize) -> Self {
+        let max = max.max(1);
+        let limit = limit.clamp(1, max);
+        Self {
+            inner: Arc::new(Inner {
+                semaphore: Arc::new(Semaphore::new(limit)),
+                max,
+                limit: AtomicUsize::new(limit),
+                excess: AtomicUsize::new(0),
+                active: AtomicUsize::new(0),
+                settled: AtomicUsize::new(0),
+                pinned: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    /// Connections allowed at once right now
+    pub fn limit(&self) -> usize {
+        self.inner.limit.load(Ordering::Relaxed)
+    }
+
+    /// The most the limit can be raised to
+    pub fn max(&self) -> usize {
+        self.inner.max
+    }
+
+    /// Connections downloading right now
+    pub fn active(&self) -> usize {
+        self.inner.active.load(Ordering::Relaxed)
+    }
+
+    /// The limit auto-tuning settled on, once it has
+    pub fn settled(&self) -> Option<usize> {
+        match self.inner.settled.load(Ordering::Relaxed) {
+            0 => None,
+            limit => Some(limit),
+        }
+    }
+
+    /// Change the limit, held to 1..=max
+    ///
+    /// Lowering it takes effect as connections finish their current batch.
+    pub fn set_limit(&self, limit: usize) {
+        let inner = &self.inner;
+        let limit = limit.clamp(1, inner.max);
+        let previous = inner.limit.swap(limit, Ordering::Relaxed);
+        if limit > previous {
+            // Permits still due to be retired are simply kept instead
+            let mut added = limit - previous;
+            let kept = inner
+                .excess
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |excess| {
+                    Some(excess.saturating_sub(added))
+                })
+                .unwrap_or(0);
+            added -= kept.min(added);
+            inner.semaphore.add_permits(added);
+        } else if limit < previous {
+            let removed = previous - limit;
+            let idle = inner.semaphore.forget_permits(removed);
+            inner.excess.fetch_add(removed - idle, Ordering::Relaxed);
+        }
+        if previous != limit {
+            tracing::debug!("Connection limit changed: {} -> {}", previous, limit);
+        }
+    }
+
+    /// Set the limit at runtime, stopping auto-tuning from changing it
+    pub fn pin(&self, limit: usize) {
+        self.inner.pinned.store(true, Ordering::Relaxed);
+        self.set_limit(limit);
+    }
+
+    /// Whether the limit was set at runtime
+    pub fn is_pinned(&self) -> bool {
+        self.inner.pinned.load(Ordering::Relaxed)
+    }
+
+    /// Wait for a free connection slot
+    pub async fn acquire(&self) -> ConnectionPermit {
+        let permit = Arc::clone(&self.inner.semaphore)
+            .acquire_owned()
+            .await
+            .expect("the connection semaphore is never closed");
+        self.inner.active.fetch_add(1, Ordering::Relaxed);
+        ConnectionPermit {
+            permit: Some(permit),
+            inner: Arc::clone(&self.inner),
+        }
+    }
+
+    /// Tune the limit to throughput in a task that ends once it settles
+    ///
+    /// `transferred` reads the bytes received so far; `resized` is told of
+    /// every change, e.g. to close idle pooled connections.
+    pub fn auto_tune(
+        &self,
+        mut tune: AutoTune,
+        window: Duration,
+        transferred: impl Fn() -> u64 + Send + 'static,
+        resized: impl Fn(usize) + Send + 'static,
+    ) -> tokio::task::JoinHandle<()> {
+        let limit = self.clone();
+        limit.set_limit(tune.limit());
+        resized(tune.limit());
+        let samples_per_window = (window.as_secs_f64() / SAMPLE_INTERVAL.as_secs_f64())
+            .round()
+            .max(1.0) as usize;
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SAMPLE_INTERVAL);
+            let mut started = (tokio::time::Instant::now(), transferred());
+            let (mut samples, mut saturated) = (0, 0);
+            loop {
+                interval.tick().await;
+                if limit.is_pinned() {
+                    tracing::debug!("Connection tuning stopped: limit set at runtime");
+                    return;
+                }
+                samples += 1;
+                if limit.active() >= limit.limit() {
+                    saturated += 1;
+                }
+                if samples < samples_per_window {
+                    continue;
+                }
+
+                let now = (tokio::time::Instant::now(), transferred());
+                let seconds = now.0.duration_since(started.0).as_secs_f64();
+                let rate = now.1.saturating_sub(started.1) as f64 / seconds.max(f64::EPSILON);
+                // A window with connections to spare (between NZBs, at the
+                // tail of one) says nothing about what more of them would do
+                let busy = saturated * 2 >= samples;
+                started = now;
+                (samples, saturated) = (0, 0);
+                if !busy {
+                    continue;
+                }
+
+                let next = tune.observe(rate);
+                limit.set_limit(next);
+                resized(next);
+                if let Some(settled) = tune.settled() {
+                    limit.inner.settled.store(settled, Ordering::Relaxed);
+                    tracing::info!(
+                        "Connections settled at {} of {} ({}/s)",
+                        settled,
+                        limit.max(),
+                        human_bytes(tune.best_rate())
+                    );
+                    return;
+                }
+            }
+        })
+    }
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.inner.active.fetch_sub(1, Ordering::Relaxed);
+        let retire = self
+            .inner
+            .excess
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |excess| {
+                excess.checked_sub(1)
+            })
+            .is_ok();
+        if let Some(permit) = self.permit.take() {
+            if retire {
+                permit.forget();
+            }
+        }
+    }
+}
+
+/// Searches for the connection count where throughput stops growing
+///
+/// Each measurement that beats the best so far by the margin moves the
+/// count up a step; the first that doesn't settles on the best count.
+#[derive(Debug, Clone)]
+pub struct AutoTune {
+    max: usize,
+    /// Fraction a step has to improve throughput by
+    margin: f64,
+    limit: usize,
+    /// Best count so far and its throughput in bytes per second
+    best: Option<(usize, f64)>,
+    settled: bool,
+}
+
+impl AutoTune {
+    /// Tune between 1 and `max`, starting at `start` (e.g. where an earlier
+    /// run settled) or else a quarter of `max`
+    pub fn new(max: usize, start: Option<usize>, margin_percent: u32) -> Self {
+        let max = max.max(1);
+        let start = start.unwrap_or((max / 4).max(4));
+        Self {
+            max,
+            margin: margin_percent as f64 / 100.0,
+            limit: start.clamp(1, max),
+            best: None,
+            settled: false,
+        }
+    }
+
+    /// The count to use now
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// The count tuning settled on, once it has
+    pub fn settled(&self) -> Option<usize> {
+        self.settled.then_some(self.limit)
+    }
+
+    /// Best throughput seen, in bytes per second
+    pub fn best_rate(&self) -> f64 {
+        self.best.map_or(0.0, |(_, rate)| rate)
+    }
+
+    /// Record `rate` (bytes per second) at the current count and return the next count
+    pub fn observe(&mut self, rate: f64) -> usize {
+        if self.settled {
+            return self.limit;
+        }
+        match self.best {
+            Some((best, best_rate)) if rate <= best_rate * (1.0 + self.margin) => {
+                self.limit = best;
+                self.settled = true;
+            }
+            _ => {
+                self.best = Some((self.limit, rate));
+                if self.limit >= self.max {
+                    self.settled = true;
+                } else {
+                    self.limit = (self.limit + (self.limit / 4).max(2)).min(self.max);
+                }
+            }
+        }
+        self.limit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_auto_tune_settles_on_plateau() {
+        let mut tune = AutoTune::new(50, None, 5);
+        assert_eq!(tune.limit(), 12);
+        // Throughput grows with connections up to 18, then flattens
+        let rate = |connections: usize| connections.min(18) as f64 * 1_000_000.0;
+        while tune.settled().is_none() {
+            let limit = tune.limit();
+            tune.observe(rate(limit));
+        }
+        assert_eq!(tune.settled(), Some(18));
+        assert_eq!(tune.best_rate(), 18_000_000.0);
+        // Settled stays settled
+        assert_eq!(tune.observe(1.0), 18);
+    }
+
+    #[test]
+    fn test_auto_tune_stops_at_max() {
+        let mut tune = AutoTune::new(10, Some(8), 5);
+        assert_eq!(tune.observe(8.0), 10);
+        assert_eq!(tune.observe(10.0), 10);
+        assert_eq!(tune.settled(), Some(10));
+        // A remembered start is held to the range
+        assert_eq!(AutoTune::new(10, Some(40), 5).limit(), 10);
+    }
+
+    #[tokio::test]
+    async fn test_lowered_limit_retires_permits_in_use() {
+        let limit = ConnectionLimit::new(3, 5);
+        let held = [limit.acquire().await, limit.acquire().await];
+        assert_eq!(limit.active(), 2);
+
+        // One idle permit goes at once, one of those in use when it's returned
+        limit.set_limit(1);
+        drop(held);
+        assert_eq!(limit.active(), 0);
+        assert_eq!(limit.inner.semaphore.available_permits(), 1);
+
+        limit.set_limit(5);
+        assert_eq!(limit.inner.semaphore.available_permits(), 5);
+        assert_eq!(limit.limit(), 5);
+    }
+}
@@ -0,0 +1,138 @@
+//! Keeping download writes off a disk post-processing is busy with
+//!
+//! PAR2 verification and extraction read and write whole files; downloads
+//! writing to the same disk at the same time slow both down, badly so on
+//! spinning disks. With `post_processing.io_priority = "exclusive"`,
+//! post-processing holds the disk's [`DiskGate`] through its PAR2 and
+//! extraction phases, and file writers on that disk stop handing segments to
+//! their writer tasks until it lets go. Their channels fill and the batches
+//! behind them wait, so downloading pauses instead of buffering more.
+//!
+//! Disks are told apart by device id; off Unix everything counts as one disk.
+//! The binary processes NZBs one after another, so this only comes into play
+//! when a program runs a [`Downloader`](super::Downloader) alongside a
+//! [`PostProcessor`](crate::processing::PostProcessor).
+
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{OwnedRwLockWriteGuard, RwLock};
+
+/// Gates by device id, created as disks are first seen
+static GATES: Lazy<Mutex<HashMap<u64, Arc<DiskGate>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Whether downloads may write to one disk right now
+#[derive(Debug, Default)]
+pub struct DiskGate {
+    /// Writers share it; post-processing takes it whole
+    lock: Arc<RwLock<()>>,
+    /// File writers open on the disk
+    writers: AtomicUsize,
+}
+
+impl DiskGate {
+    /// The gate of the disk `path` is on (`path` must exist)
+    pub fn for_path(path: &Path) -> Arc<Self> {
+        Self::for_device(device_id(path))
+    }
+
+    pub(super) fn for_device(device: u64) -> Arc<Self> {
+        let mut gates = GATES.lock().unwrap();
+        Arc::clone(gates.entry(device).or_default())
+    }
+
+    /// Wait until post-processing isn't holding the disk
+    pub async fn wait_open(&self) {
+        drop(self.lock.read().await);
+    }
+
+    /// Count a file writer as open until the returned guard drops
+    pub fn register_writer(self: &Arc<Self>) -> WriterSlot {
+        self.writers.fetch_add(1, Ordering::Relaxed);
+        WriterSlot(Arc::clone(self))
+    }
+
+    /// Hold the disk until the returned [`DiskHold`] drops, waiting for
+    /// segments already being handed over
+    pub async fn hold(self: &Arc<Self>) -> DiskHold {
+        let guard = Arc::clone(&self.lock).write_owned().await;
+        DiskHold {
+            paused: self.writers.load(Ordering::Relaxed),
+            _guard: guard,
+        }
+    }
+}
+
+/// A file writer counted by its disk's gate
+#[derive(Debug)]
+pub struct WriterSlot(Arc<DiskGate>);
+
+impl WriterSlot {
+    pub fn gate(&self) -> &DiskGate {
+        &self.0
+    }
+}
+
+impl Drop for WriterSlot {
+    fn drop(&mut self) {
+        self.0.writers.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Post-processing's hold on a disk; downloads to it resume when dropped
+#[derive(Debug)]
+pub struct DiskHold {
+    paused: usize,
+    _guard: OwnedRwLockWriteGuard<()>,
+}
+
+impl DiskHold {
+    /// File writers that were open on the disk when it was taken
+    pub fn paused_writers(&self) -> usize {
+        self.paused
+    }
+}
+
+#[cfg(unix)]
+fn device_id(path: &Path) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    std::fs::metadata(path).map(|m| m.dev()).unwrap_or(0)
+}
+
+#[cfg(not(unix))]
+fn device_id(_path: &Path) -> u64 {
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_hold_pauses_writers_on_the_same_disk() {
+        // A device id no real disk has, so other tests can't interfere
+        let gate = DiskGate::for_device(u64::MAX);
+        let other = DiskGate::for_device(u64::MAX - 1);
+        assert!(Arc::ptr_eq(&gate, &DiskGate::for_device(u64::MAX)));
+
+        let slot = gate.register_writer();
+        let hold = gate.hold().await;
+        assert_eq!(hold.paused_writers(), 1);
+
+        // Writers on the held disk wait; another disk's don't
+        let waiting = tokio::time::timeout(Duration::from_millis(50), slot.gate().wait_open());
+        assert!(waiting.await.is_err());
+        other.wait_open().await;
+
+        drop(hold);
+        tokio::time::timeout(Duration::from_secs(1), slot.gate().wait_open())
+            .await
+            .unwrap();
+
+        drop(slot);
+        assert_eq!(gate.hold().await.paused_writers(), 0);
+    }
+}
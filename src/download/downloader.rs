@@ -1,74 +1,687 @@
 use bytes::Bytes;
 use futures::stream::{self, StreamExt};
-use indicatif::ProgressBar;
-use std::path::PathBuf;
-use std::time::{Duration, Instant};
-use tokio::fs::File;
-use tokio::io::{AsyncWriteExt, BufWriter};
-
-use super::nzb::{Nzb, NzbFile};
-use crate::config::Config;
-use crate::error::{DlNzbError, DownloadError};
-use crate::nntp::{NntpPool, NntpPoolBuilder, NntpPoolExt, SegmentRequest};
-use crate::progress;
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Range;
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+use super::age::format_age;
+use super::breaker::MissingBreaker;
+use super::budget::RetryBudget;
+use super::connections::{AutoTune, ConnectionLimit};
+use super::events::{DownloadEvent, Events};
+use super::groups;
+use super::limits::Limits;
+use super::memory::{MemoryBudget, Reservation};
+use super::nzb::{bare_message_id, sanitize_filename, Nzb, NzbFile, NzbSegment, Par2Kind};
+use super::partial::{self, PartialFiles};
+use super::permissions::Permissions;
+use super::sizes;
+use super::skip::{self, ExistingFile, SkipReason, SkipVerdict, SkippedFile};
+use super::speed::{self, SpeedTimeline};
+use super::stall::{StallWatch, STALL_CHECK_INTERVAL};
+use super::temp::{check_writable, move_file, sync_dir};
+use super::throttle::Throttle;
+use super::writer::{FileWriter, Placement};
+use crate::config::{
+    Config, DownloadConfig, ExistingFiles, FileOrder, FsyncPolicy, SkipVerification,
+};
+use crate::error::{panic_message, DlNzbError, DownloadError, NntpError, NzbError};
+use crate::metrics::{HistogramSnapshot, WaitHistogram};
+use crate::nntp::{
+    ConnectTimings, FetchedBatch, HandshakeStats, NntpPool, NntpPoolBuilder, NntpPoolExt,
+    PooledConnection, SegmentRequest,
+};
+use crate::patterns::par2 as par2_patterns;
+use crate::progress::{NoProgress, ProgressSink, SegmentsDone};
+use crate::report::Failure;
+use crate::retry::{retry_with_backoff, RetryPolicy};
+use crate::shutdown::Shutdown;
 
 type Result<T> = std::result::Result<T, DlNzbError>;
 
+/// Longest a single attempt to get a connection from the pool may take
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Fresh connections a batch gets after servers close connections under it
+/// (idle timeouts) before what's left counts as failed
+const CLOSED_RETRIES: usize = 3;
+
 /// Result of downloading a file
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct DownloadResult {
     pub filename: String,
     pub path: PathBuf,
     pub size: u64,
     pub segments_downloaded: usize,
     pub segments_failed: usize,
+    /// Failed segments that every server answered as not found; the rest
+    /// were lost to connection errors
+    pub segments_missing: usize,
+    /// Segments kept even though they failed their yEnc checksum
+    pub segments_corrupt: usize,
     pub download_time: Duration,
     pub average_speed: f64,              // MB/s
     pub failed_message_ids: Vec<String>, // Track failed segments for potential retry
+    /// Newsgroup the segments were fetched from
+    pub group: String,
+    /// Every newsgroup the file was posted to
+    pub groups: Vec<String>,
+    /// Poster from the NZB
+    pub poster: String,
+    /// Post date from the NZB, in seconds since the Unix epoch
+    pub posted: u64,
+    /// Where each segment landed in the output file (empty for skipped files)
+    pub segment_map: Vec<SegmentSpan>,
+    /// Decoded bytes each server provided, by server name
+    pub bytes_by_server: BTreeMap<String, u64>,
+    /// Encoded bytes read from servers for the file, including segments that
+    /// were fetched twice, failed to decode or broke off; `size` is what was
+    /// kept
+    pub transferred: u64,
+    /// How the file already there was checked, when it was skipped
+    pub skipped: Option<SkipVerification>,
+    /// What became of a file already there that wasn't skipped as complete
+    pub existing: Option<ExistingFile>,
+    /// Whether `filename` was given by a [`NameMap`](super::NameMap) rather
+    /// than taken from the subject
+    pub name_mapped: bool,
+}
+
+impl DownloadResult {
+    /// Why the file wasn't downloaded, when it was skipped
+    pub fn skip_reason(&self) -> Option<SkipReason> {
+        self.skipped?;
+        Some(match self.existing {
+            Some(ExistingFile::Kept) => SkipReason::KeptExisting,
+            _ => SkipReason::AlreadyComplete,
+        })
+    }
+}
+
+/// Files downloaded for one NZB, timed as a whole
+///
+/// Files download concurrently, so `download_time` is wall-clock time rather
+/// than the sum of the per-file times in `results`.
+#[derive(Debug, Default)]
+pub struct NzbDownload {
+    pub results: Vec<DownloadResult>,
+    /// Files that couldn't be downloaded at all
+    pub failures: Vec<Failure>,
+    pub download_time: Duration,
+    /// Aggregate throughput sampled while the files downloaded
+    pub speed: SpeedTimeline,
+    /// What's left for retries, to pass on to [`Downloader::refetch_ranges`]
+    pub retry_budget: Arc<RetryBudget>,
+    /// Files left out before downloading: duplicates, files with no segments
+    /// and, with `download.skip_unavailable_groups`, files in missing groups
+    pub skipped: Vec<SkippedFile>,
+    /// Newsgroups the files are posted to that no server carries
+    pub missing_groups: Vec<String>,
+}
+
+impl NzbDownload {
+    pub fn total_size(&self) -> u64 {
+        self.results.iter().map(|r| r.size).sum()
+    }
+
+    /// Encoded bytes read from servers across all files
+    pub fn total_transferred(&self) -> u64 {
+        self.results.iter().map(|r| r.transferred).sum()
+    }
+
+    /// Decoded bytes each server provided across all files
+    pub fn bytes_by_server(&self) -> BTreeMap<String, u64> {
+        let mut total = BTreeMap::new();
+        for result in &self.results {
+            for (server, bytes) in &result.bytes_by_server {
+                *total.entry(server.clone()).or_default() += bytes;
+            }
+        }
+        total
+    }
+
+    /// Average speed over the whole download in MiB/s
+    pub fn average_speed(&self) -> f64 {
+        let seconds = self.download_time.as_secs_f64();
+        if seconds > 0.0 {
+            (self.total_size() as f64 / 1024.0 / 1024.0) / seconds
+        } else {
+            0.0
+        }
+    }
+}
+
+/// An NZB download that stopped before it finished, with what it got done
+///
+/// `download` holds the files finished or partly written before `error`
+/// ended the NZB (empty when it stopped before any was fetched), and those
+/// left out before it started, so they can be cleaned up and reported.
+#[derive(Debug)]
+pub struct AbortedDownload {
+    pub error: DlNzbError,
+    pub download: NzbDownload,
+}
+
+impl AbortedDownload {
+    /// Stopped by `error` before anything was downloaded
+    fn new(error: impl Into<DlNzbError>) -> Self {
+        Self {
+            error: error.into(),
+            download: NzbDownload::default(),
+        }
+    }
+}
+
+impl std::fmt::Display for AbortedDownload {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.error)
+    }
+}
+
+impl std::error::Error for AbortedDownload {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl From<AbortedDownload> for DlNzbError {
+    fn from(aborted: AbortedDownload) -> Self {
+        aborted.error
+    }
+}
+
+/// Byte range of a segment within the assembled file
+#[derive(Debug, Clone)]
+pub struct SegmentSpan {
+    pub number: u32,
+    pub message_id: String,
+    pub offset: u64,
+    /// Decoded length; 0 for a missing final segment whose size is unknown
+    pub len: u64,
+}
+
+impl SegmentSpan {
+    fn overlaps(&self, range: &Range<u64>) -> bool {
+        let end = if self.len == 0 {
+            u64::MAX
+        } else {
+            self.offset + self.len
+        };
+        self.offset < range.end && range.start < end
+    }
+}
+
+/// Articles served by one server during this run
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ServerStats {
+    pub server: String,
+    /// Articles fetched successfully
+    pub articles: u64,
+    /// Decoded bytes fetched
+    pub bytes: u64,
+    /// Encoded bytes read from the server, whether kept or not
+    #[serde(default)]
+    pub transferred: u64,
+    /// Articles requested but not delivered (missing, or no connection)
+    pub missing: u64,
+    /// Connections the server closed on its own, e.g. on an idle timeout
+    #[serde(default)]
+    pub idle_closures: u64,
 }
 
-/// Result of downloading a single segment
-struct SegmentResult {
-    segment_number: u32,
+/// The first connection to the primary server, opened by [`Downloader::probe`]
+#[derive(Debug, Clone)]
+pub struct ServerProbe {
+    pub server: String,
+    /// What the server said on connecting, without the code
+    pub greeting: String,
+    /// How long connecting and logging in took, step by step
+    pub timings: ConnectTimings,
+    /// Seconds the local clock is ahead of the server's, when it answered
+    /// `DATE`; beyond [`MAX_CLOCK_SKEW`](crate::nntp::clock::MAX_CLOCK_SKEW)
+    /// the pool has warned about it
+    pub clock_skew: Option<i64>,
+}
+
+/// Live counters for one server, also read by the metrics endpoint
+#[derive(Debug, Clone, Default)]
+pub struct ServerMetrics {
+    pub server: String,
+    pub articles: u64,
+    pub bytes: u64,
+    /// Encoded bytes read, including articles thrown away
+    pub transferred: u64,
+    /// Articles the server didn't have
+    pub missing: u64,
+    /// Articles not requested because no connection could be had
+    pub no_connection: u64,
+    /// Articles lost to a connection failing mid-batch
+    pub errors: u64,
+    /// Connections the server closed on its own, with a `400` or by hanging
+    /// up; idle ones usually, so a count that keeps growing means the server
+    /// times out idle connections
+    pub idle_closures: u64,
+    pub connections_open: usize,
+    pub connections_in_use: usize,
+    /// Connections the pool may open
+    pub connections_max: usize,
+    /// Connections downloads may use at once (tuned with `usenet.auto_connections`)
+    pub connections_limit: usize,
+    /// Time spent waiting for a pooled connection
+    pub pool_wait: HistogramSnapshot,
+    /// How long opening connections took, step by step
+    pub handshakes: HandshakeStats,
+}
+
+#[derive(Default)]
+struct ServerCounters {
+    articles: AtomicU64,
+    bytes: AtomicU64,
+    transferred: AtomicU64,
+    missing: AtomicU64,
+    no_connection: AtomicU64,
+    errors: AtomicU64,
+    pool_wait: WaitHistogram,
+}
+
+/// Why a whole batch came back empty
+#[derive(Debug, Clone, Copy)]
+enum BatchFailure {
+    NoConnection,
+    Error,
+}
+
+/// A segment of a batch as servers take their turns: its data once one of
+/// them had it, and which one (by position in the file's route)
+struct BatchSegment {
+    number: u32,
     data: Option<Bytes>,
-    message_id: String, // Track for error reporting
+    server: Option<usize>,
+}
+
+/// A batch after every server has had its turn
+///
+/// Its segments have gone to the file's writer by then.
+struct BatchOutcome {
+    /// Segments fetched, and their decoded size
+    downloaded: usize,
+    size: u64,
+    /// Decoded bytes by position in the file's route
+    served: Vec<u64>,
+    /// Encoded bytes read from servers
+    transferred: u64,
+    /// Segments no server could provide
+    failed: Vec<u32>,
+    /// Segments still missing because the last server asked didn't have them
+    missing: usize,
+    /// Segments kept despite a yEnc checksum mismatch
+    corrupt: usize,
+    /// The file's decoded size, if a `=ybegin` line in the batch said
+    file_size: Option<u64>,
+}
+
+/// A configured server: its connection pool plus running statistics
+#[derive(Clone)]
+struct Server {
+    name: String,
+    pool: NntpPool,
+    retry: RetryPolicy,
+    counters: Arc<ServerCounters>,
+    /// Connections the pool was built with
+    connections: usize,
+    /// Shared by every batch on this server; only the primary server has one
+    limit: Option<ConnectionLimit>,
 }
 
+/// The current time, as the downloader sees it
+pub type Clock = Arc<dyn Fn() -> SystemTime + Send + Sync>;
+
 /// Optimized downloader using connection pooling and streaming
 pub struct Downloader {
-    pool: NntpPool,
+    /// One pool per configured server, in [`Config::all_servers`] order
+    servers: Vec<Server>,
+    /// Bandwidth limit shared by all connections
+    throttle: Throttle,
+    /// Cap on decoded segments held by all connections and writers
+    memory: MemoryBudget,
+    /// Re-evaluates `download.speed_schedule` while the downloader lives
+    schedule_task: Option<tokio::task::JoinHandle<()>>,
+    /// Tunes the primary server's connections under `usenet.auto_connections`
+    tune_task: Option<tokio::task::JoinHandle<()>>,
+    events: Events,
+    progress: Arc<dyn ProgressSink>,
+    /// Dates posts for retention routing
+    clock: Clock,
+    /// Stops new files and batches, and cuts short those running
+    shutdown: Shutdown,
+}
+
+/// Builder for a [`Downloader`] that uses parts supplied by the caller
+///
+/// Anything not supplied is made from the config, as [`Downloader::new`] does.
+pub struct DownloaderBuilder {
+    config: Config,
+    pool: Option<NntpPool>,
+    events: Events,
+    progress: Arc<dyn ProgressSink>,
+    clock: Clock,
+    tuned_start: Option<usize>,
+    shutdown: Shutdown,
+}
+
+impl DownloaderBuilder {
+    /// Use an existing pool for the primary server instead of building one,
+    /// e.g. to share it between downloaders or to point it at a test server
+    pub fn pool(mut self, pool: NntpPool) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    /// Report queue, file and byte progress on an event channel
+    pub fn events(mut self, events: Events) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Report progress to `sink` (silent by default)
+    pub fn progress(mut self, sink: Arc<dyn ProgressSink>) -> Self {
+        self.progress = sink;
+        self
+    }
+
+    /// Take the current time from `clock` when working out how old posts are
+    pub fn clock(mut self, clock: impl Fn() -> SystemTime + Send + Sync + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Start `usenet.auto_connections` tuning at `connections`, e.g. where an
+    /// earlier run settled, instead of a quarter of the maximum
+    pub fn tuned_start(mut self, connections: usize) -> Self {
+        self.tuned_start = Some(connections);
+        self
+    }
+
+    /// Stop downloading when `shutdown` trips (never, by default)
+    pub fn shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Create the downloader, building pools for the servers without one
+    pub async fn build(self) -> Result<Downloader> {
+        let mut pool = self.pool;
+        let mut servers = self
+            .config
+            .all_servers()
+            .map(|server| {
+                let pool = match pool.take() {
+                    Some(pool) => pool,
+                    None => NntpPoolBuilder::new(server.clone())
+                        .max_size(server.connections as usize)
+                        .build()?,
+                };
+                Ok(Server {
+                    name: server.server.clone(),
+                    connections: pool.status().max_size,
+                    pool,
+                    retry: RetryPolicy::from_config(server),
+                    counters: Arc::default(),
+                    limit: None,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let throttle = Throttle::unlimited();
+        let schedule_task = throttle.follow_schedule(&self.config.download);
+
+        let tune_task = match servers.first_mut() {
+            Some(primary) => {
+                let limit = ConnectionLimit::new(primary.connections, primary.connections);
+                primary.limit = Some(limit.clone());
+                self.config.usenet.auto_connections.then(|| {
+                    let tuning = &self.config.tuning;
+                    let tune = AutoTune::new(
+                        limit.max(),
+                        self.tuned_start,
+                        tuning.auto_connections_margin,
+                    );
+                    let (counters, pool) = (primary.counters.clone(), primary.pool.clone());
+                    limit.auto_tune(
+                        tune,
+                        Duration::from_secs(tuning.auto_connections_window.max(1)),
+                        move || counters.bytes.load(Ordering::Relaxed),
+                        // Idle connections past the limit are closed
+                        move |connections| pool.resize(connections),
+                    )
+                })
+            }
+            None => None,
+        };
+
+        Ok(Downloader {
+            servers,
+            throttle,
+            memory: MemoryBudget::new(self.config.memory.buffer_bytes()),
+            schedule_task,
+            tune_task,
+            events: self.events,
+            progress: self.progress,
+            clock: self.clock,
+            shutdown: self.shutdown,
+        })
+    }
+}
+
+impl Drop for Downloader {
+    fn drop(&mut self) {
+        for task in [&self.schedule_task, &self.tune_task].into_iter().flatten() {
+            task.abort();
+        }
+    }
 }
 
 impl Downloader {
-    /// Create a new downloader with connection pool
+    /// Create a new downloader with a connection pool per server
     pub async fn new(config: Config) -> Result<Self> {
-        let pool = NntpPoolBuilder::new(config.usenet.clone())
-            .max_size(config.usenet.connections as usize)
-            .build()?;
+        Self::builder(config).build().await
+    }
+
+    /// Start building a downloader, to supply its pool, progress sink or clock
+    pub fn builder(config: Config) -> DownloaderBuilder {
+        DownloaderBuilder {
+            config,
+            pool: None,
+            events: Events::default(),
+            progress: Arc::new(NoProgress),
+            clock: Arc::new(SystemTime::now),
+            tuned_start: None,
+            shutdown: Shutdown::new(),
+        }
+    }
 
-        Ok(Self { pool })
+    /// Report queue, file and byte progress on an event channel
+    pub fn with_events(mut self, events: Events) -> Self {
+        self.events = events;
+        self
     }
 
-    /// Download all files from an NZB, returns results and progress bar for reuse
+    /// Report progress to `sink` (silent by default)
+    pub fn with_progress(mut self, sink: Arc<dyn ProgressSink>) -> Self {
+        self.progress = sink;
+        self
+    }
+
+    /// Say goodbye (`QUIT`) on every idle connection and close the pools
+    ///
+    /// For shutting down: downloads started afterwards fail.
+    pub async fn close(&self) {
+        futures::future::join_all(self.servers.iter().map(|server| server.pool.close_all())).await;
+    }
+
+    /// The bandwidth limit shared by all connections
+    pub fn throttle(&self) -> &Throttle {
+        &self.throttle
+    }
+
+    /// Handle for changing the speed and connection limits while it runs
+    pub fn limits(&self) -> Limits {
+        let connections = self
+            .servers
+            .first()
+            .and_then(|server| Some((server.limit.clone()?, server.pool.clone())));
+        Limits::new(self.throttle.clone(), connections)
+    }
+
+    /// The memory budget shared by all connections
+    pub fn memory(&self) -> &MemoryBudget {
+        &self.memory
+    }
+
+    /// Connections the primary server's downloads use at once, and how many
+    /// are busy
+    pub fn connections(&self) -> Option<&ConnectionLimit> {
+        self.servers.first()?.limit.as_ref()
+    }
+
+    /// Per-server article counts since the downloader was created
+    pub fn server_stats(&self) -> Vec<ServerStats> {
+        self.stats().server_stats()
+    }
+
+    /// Handle on the live counters that outlives borrows of the downloader
+    pub fn stats(&self) -> StatsHandle {
+        StatsHandle {
+            servers: self.servers.clone(),
+        }
+    }
+
+    /// Connect to the primary server and log in, before anything downloads
+    ///
+    /// Pools connect lazily, so without this a refused login would only show
+    /// as every batch failing once downloads have started. The connection
+    /// goes back to the pool for the first download to use.
+    pub async fn probe(&self) -> Result<Option<ServerProbe>> {
+        let Some(server) = self.servers.first() else {
+            return Ok(None);
+        };
+        let conn = tokio::time::timeout(CONNECTION_TIMEOUT, server.pool.get_connection())
+            .await
+            .map_err(|_| NntpError::Timeout {
+                seconds: CONNECTION_TIMEOUT.as_secs(),
+            })??;
+        Ok(Some(ServerProbe {
+            server: server.name.clone(),
+            greeting: conn.greeting().to_string(),
+            timings: conn.timings(),
+            clock_skew: server.pool.handshakes().clock_skew,
+        }))
+    }
+
+    /// Download all files from an NZB
     pub async fn download_nzb(
         &self,
         nzb: &Nzb,
         config: Config,
-    ) -> Result<(Vec<DownloadResult>, ProgressBar)> {
-        config.ensure_dirs()?;
+    ) -> std::result::Result<NzbDownload, AbortedDownload> {
+        self.download_files(nzb.files(), config).await
+    }
+
+    /// Download a subset of an NZB's files (e.g. a `--select`ion)
+    ///
+    /// Totals and progress cover only these files. Their total size is
+    /// recorded as `size` on the caller's span (the `nzb` span of a run).
+    ///
+    /// An error that ends the whole NZB comes back with what was done
+    /// before it, see [`AbortedDownload`].
+    pub async fn download_files(
+        &self,
+        files: &[NzbFile],
+        config: Config,
+    ) -> std::result::Result<NzbDownload, AbortedDownload> {
+        if files.is_empty() {
+            return Err(AbortedDownload::new(NzbError::EmptyNzb(None)));
+        }
+        // An unwritable folder fails the NZB before any connection is used
+        check_writable(&config.download.dir).map_err(AbortedDownload::new)?;
+        if let Some(temp_dir) = &config.download.temp_dir {
+            check_writable(temp_dir).map_err(AbortedDownload::new)?;
+        }
+        config.ensure_dirs().map_err(AbortedDownload::new)?;
+        let permissions =
+            Permissions::from_config(&config.download).map_err(AbortedDownload::new)?;
+        permissions.apply_dir(&config.download.dir);
 
-        // Get all files to download (no separation between main and PAR2)
-        let all_files: Vec<&NzbFile> = nzb.files().iter().collect();
+        // No separation between main and PAR2 files; files with nothing to
+        // fetch, too small to keep, or with the same articles as one before
+        // them are left out
+        let (all_files, mut skipped) = skip::screen_files(
+            &files.iter().collect::<Vec<_>>(),
+            config.download.min_file_size,
+        );
+
+        // Newsgroups no server carries: a file also in a carried group is
+        // fetched through that one, the others are flagged before anything
+        // is fetched
+        let missing_groups = self.missing_groups(&all_files).await;
+        let rerouted: Vec<NzbFile> = all_files
+            .iter()
+            .filter_map(|file| groups::reroute(file, &missing_groups))
+            .collect();
+        let mut all_files: Vec<&NzbFile> = all_files
+            .into_iter()
+            .map(|file| {
+                rerouted
+                    .iter()
+                    .find(|r| r.index == file.index)
+                    .unwrap_or(file)
+            })
+            .collect();
+        let skip_missing = config.download.skip_unavailable_groups;
+        for line in groups::describe(&missing_groups, &all_files) {
+            tracing::warn!("{}", line);
+            self.progress.on_message(&format!(
+                "  \x1b[33m⚠ {}{}\x1b[0m",
+                line,
+                if skip_missing { ", skipped" } else { "" }
+            ));
+        }
+        if skip_missing && !missing_groups.is_empty() {
+            all_files.retain(|file| {
+                let unavailable = groups::only_in_missing(file, &missing_groups);
+                if unavailable {
+                    skipped.push(SkippedFile::of(file, SkipReason::GroupUnavailable));
+                }
+                !unavailable
+            });
+        }
 
         if all_files.is_empty() {
-            return Err(DownloadError::InsufficientSegments {
-                available: 0,
-                required: 1,
-            }
-            .into());
+            return Err(AbortedDownload {
+                error: DownloadError::InsufficientSegments {
+                    available: 0,
+                    required: 1,
+                }
+                .into(),
+                download: NzbDownload {
+                    skipped,
+                    missing_groups: missing_groups.into_iter().collect(),
+                    ..NzbDownload::default()
+                },
+            });
         }
 
-        // Create clean progress bar using centralized progress module
         let total_bytes: u64 = all_files
             .iter()
             .flat_map(|f| &f.segments.segment)
@@ -76,153 +689,567 @@ impl Downloader {
             .sum();
 
         let total_files = all_files.len();
-        let progress_bar =
-            progress::create_progress_bar(total_bytes, progress::ProgressStyle::Download);
-        progress_bar.set_message(format!("({}/{})", 0, total_files));
+        tracing::Span::current().record("size", total_bytes);
+        self.events.send(DownloadEvent::QueueStarted {
+            files: total_files,
+            total_bytes,
+        });
+        self.progress.on_start(total_files, total_bytes);
 
         // Download all files concurrently
-        let results = self
-            .download_files_concurrent_with_config(&all_files, progress_bar.clone(), config)
-            .await?;
-
-        // Finish the progress bar with clean formatting
-        let total_downloaded: u64 = results.iter().map(|r| r.size).sum();
-        let failed_files = results.iter().filter(|r| r.segments_failed > 0).count();
-
-        progress_bar.set_position(total_bytes);
+        let started = Instant::now();
+        let fsync = config.download.fsync;
+        let dir = config.download.dir.clone();
+        let record_hashes = (config.download.skip_verification == SkipVerification::QuickHash)
+            .then(|| {
+                let threads = config.post_processing.checksum_threads;
+                (config.download.dir.clone(), threads)
+            });
+        let retry_budget = Arc::new(RetryBudget::from_config(&config.download));
+        let ((results, failures), speed) = speed::sampled(
+            &self.stats(),
+            self.download_files_concurrent_with_config(
+                &all_files,
+                config,
+                (self.clock)(),
+                &retry_budget,
+            ),
+        )
+        .await;
+        if self.shutdown.is_triggered() {
+            // What was written is kept for the next run: on disk, with the
+            // finished files' sizes recorded and the rest listed to be
+            // fetched again rather than taken for finished
+            if fsync != FsyncPolicy::PerFile {
+                Self::sync_results(&results).await;
+            }
+            sizes::record(&dir, &results).await;
+            partial::clean_up(&dir, &results, true).await;
+            self.progress.on_abort(&results);
+            return Err(AbortedDownload {
+                error: DownloadError::Cancelled.into(),
+                download: NzbDownload {
+                    results,
+                    failures,
+                    download_time: started.elapsed(),
+                    speed,
+                    retry_budget,
+                    skipped,
+                    missing_groups: missing_groups.into_iter().collect(),
+                },
+            });
+        }
+        if !permissions.is_default() {
+            Self::apply_permissions(permissions, &results).await;
+        }
+        if fsync == FsyncPolicy::PerNzb {
+            Self::sync_results(&results).await;
+        }
+        sizes::record(&dir, &results).await;
+        if let Some((dir, threads)) = record_hashes {
+            skip::record(&dir, &results, threads).await;
+        }
+        let download_time = started.elapsed();
+        self.progress.on_finish(&results);
 
-        if failed_files == 0 {
-            progress_bar.finish_with_message(format!(
-                "({}/{})  ",
-                all_files.len(),
-                all_files.len()
-            ));
+        Ok(NzbDownload {
+            results,
+            failures,
+            download_time,
+            speed,
+            retry_budget,
+            skipped,
+            missing_groups: missing_groups.into_iter().collect(),
+        })
+    }
 
-            // Print download summary on new line with color
-            println!(
-                "  └─ \x1b[32m✓ Downloaded {}\x1b[0m",
-                human_bytes::human_bytes(total_downloaded as f64)
-            );
-        } else {
-            progress_bar.finish_with_message(format!(
-                "({}/{})  ",
-                all_files.len(),
-                all_files.len()
-            ));
+    /// Newsgroups of `files` that no server carries, by selecting each once
+    ///
+    /// A server that can't be reached, or answers anything but `211` or
+    /// `411`, leaves the groups it was asked about counted as carried, so
+    /// nothing is flagged on a guess.
+    async fn missing_groups(&self, files: &[&NzbFile]) -> BTreeSet<String> {
+        let mut unchecked = groups::groups_of(files);
+        for server in &self.servers {
+            if unchecked.is_empty() {
+                break;
+            }
+            let mut conn = match tokio::time::timeout(
+                CONNECTION_TIMEOUT,
+                server.pool.get_connection(),
+            )
+            .await
+            {
+                Ok(Ok(conn)) => conn,
+                Ok(Err(e)) => {
+                    tracing::debug!("Could not check newsgroups on {}: {}", server.name, e);
+                    return BTreeSet::new();
+                }
+                Err(_) => {
+                    tracing::debug!("Could not check newsgroups on {}: timed out", server.name);
+                    return BTreeSet::new();
+                }
+            };
+            let mut not_here = Vec::new();
+            for group in unchecked {
+                match conn.select_group(&group).await {
+                    Ok(_) => {}
+                    Err(DlNzbError::Nntp(NntpError::GroupNotFound { .. })) => {
+                        tracing::debug!("{} doesn't carry {}", server.name, group);
+                        not_here.push(group);
+                    }
+                    Err(e) => {
+                        tracing::debug!("Could not check {} on {}: {}", group, server.name, e);
+                        break;
+                    }
+                }
+            }
+            unchecked = not_here;
+        }
+        unchecked.into_iter().collect()
+    }
 
-            println!(
-                "  └─ \x1b[33m! Downloaded {} ({} file{} with errors)\x1b[0m",
-                human_bytes::human_bytes(total_downloaded as f64),
-                failed_files,
-                if failed_files == 1 { "" } else { "s" }
-            );
+    /// Give every downloaded file the configured mode and owner
+    async fn apply_permissions(permissions: Permissions, results: &[DownloadResult]) {
+        let paths: Vec<PathBuf> = results.iter().map(|r| r.path.clone()).collect();
+        let applied = tokio::task::spawn_blocking(move || {
+            for path in &paths {
+                permissions.apply_file(path);
+            }
+        })
+        .await;
+        if let Err(e) = applied {
+            tracing::warn!("Failed to set permissions on downloaded files: {}", e);
         }
+    }
 
-        Ok((results, progress_bar))
+    /// Sync every downloaded file and the directories holding them
+    ///
+    /// Failures are logged rather than failing the NZB, since the files
+    /// themselves are complete.
+    async fn sync_results(results: &[DownloadResult]) {
+        let paths: Vec<PathBuf> = results.iter().map(|r| r.path.clone()).collect();
+        let synced = tokio::task::spawn_blocking(move || {
+            let mut dirs: Vec<&Path> = Vec::new();
+            for path in &paths {
+                if let Err(e) = std::fs::File::open(path).and_then(|f| f.sync_all()) {
+                    tracing::warn!("Failed to sync {}: {}", path.display(), e);
+                }
+                if let Some(dir) = path.parent() {
+                    if !dirs.contains(&dir) {
+                        dirs.push(dir);
+                    }
+                }
+            }
+            for dir in dirs {
+                if let Err(e) = sync_dir(dir) {
+                    tracing::warn!("Failed to sync {}: {}", dir.display(), e);
+                }
+            }
+        })
+        .await;
+        if let Err(e) = synced {
+            tracing::warn!("Failed to sync downloaded files: {}", e);
+        }
     }
 
     /// Download multiple files concurrently with custom config
+    ///
+    /// Files that fail are returned as failures alongside the others' results.
     async fn download_files_concurrent_with_config(
         &self,
         files: &[&NzbFile],
-        progress_bar: ProgressBar,
         config: Config,
-    ) -> Result<Vec<DownloadResult>> {
-        let total_files = files.len();
-        let completed_count = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
-
-        // Wrap config in Arc to avoid cloning per-file (Config contains strings and paths)
-        let config = std::sync::Arc::new(config);
+        now: SystemTime,
+        budget: &RetryBudget,
+    ) -> (Vec<DownloadResult>, Vec<Failure>) {
+        let sorted_files = download_order(files.iter().copied(), &config.download);
+        let stall = &StallWatch::new(Duration::from_secs(config.download.stall_timeout));
 
-        // Sort files by size (largest first) to maximize initial throughput
-        let mut sorted_files: Vec<&NzbFile> = files.iter().copied().collect();
-        sorted_files.sort_by_key(|f| std::cmp::Reverse(f.segments.segment.len()));
-
-        let download_futures = sorted_files.iter().map(|file| {
-            let pool = self.pool.clone();
-            let config = config.clone(); // Now clones Arc, not Config
-            let file = (*file).clone();
-            let progress = progress_bar.clone();
-            let completed = completed_count.clone();
+        // The futures run on this task, so they borrow the files, config and
+        // servers instead of each taking a copy
+        let config = &config;
+        let download_futures = sorted_files.iter().map(|&file| {
+            let filename = Nzb::get_filename_from_subject(&file.subject)
+                .unwrap_or_else(|| file.subject.clone());
+            let span = tracing::info_span!(
+                "file",
+                name = %filename,
+                segments = file.segments.segment.len()
+            );
 
             async move {
-                let result =
-                    Self::download_file_with_pool(file, &config, pool, progress.clone()).await;
-
-                // Update file counter (only update every 5 files to reduce overhead)
-                let count = completed.fetch_add(1, std::sync::atomic::Ordering::Relaxed) + 1;
-                if count % 5 == 0 || count == total_files {
-                    progress.set_message(format!("({}/{})", count, total_files));
-                }
-
-                result
+                // A panic while fetching, decoding or writing the file fails
+                // the file alone. The futures share this task, so it's caught
+                // here rather than at a task boundary.
+                let download = Self::download_file_with_pool(
+                    file,
+                    config,
+                    now,
+                    &self.servers,
+                    &self.throttle,
+                    &self.memory,
+                    self.progress.as_ref(),
+                    &self.events,
+                    budget,
+                    &self.shutdown,
+                    stall,
+                );
+                let result = AssertUnwindSafe(download)
+                    .catch_unwind()
+                    .await
+                    .unwrap_or_else(|payload| {
+                        tracing::error!(
+                            "Download of {} panicked: {} (file {} of the NZB, {} segments, subject {:?})",
+                            filename,
+                            panic_message(payload.as_ref()),
+                            file.index,
+                            file.segments.segment.len(),
+                            file.subject
+                        );
+                        Err(DownloadError::panicked(&filename, payload.as_ref()).into())
+                    });
+                stall.finished(file.index);
+                let result = match result {
+                    Ok(result) => {
+                        self.progress.on_file_done(&result);
+                        Ok(Self::name_par2_by_contents(result, config).await)
+                    }
+                    Err(e) => {
+                        tracing::debug!("Download of {} failed: {}", filename, e);
+                        Err(e)
+                    }
+                };
+                (
+                    file.index,
+                    result.map_err(|e| Failure::from_error(Some(filename), &e)),
+                )
             }
+            .instrument(span)
         });
 
         // Process downloads with bounded concurrency to prevent pool exhaustion
         // Each file uses multiple connections for its batches, so limit concurrent files
         // to avoid total_batches = files × batches_per_file >> pool_size
         let max_concurrent_files = (config.usenet.connections as usize / 5).max(2);
-        let results: Vec<Result<DownloadResult>> = stream::iter(download_futures)
-            .buffer_unordered(max_concurrent_files)
-            .collect()
-            .await;
+        let mut results: Vec<(usize, std::result::Result<DownloadResult, Failure>)> =
+            stream::iter(download_futures)
+                .buffer_unordered(max_concurrent_files)
+                .collect()
+                .await;
+        // Files finish in whatever order; results are reported in the NZB's
+        results.sort_by_key(|(index, _)| *index);
 
-        // Collect successful results
         let mut successful_results = Vec::new();
-        for result in results {
+        let mut failures = Vec::new();
+        for (_, result) in results {
             match result {
                 Ok(download_result) => successful_results.push(download_result),
-                Err(e) => eprintln!("Download failed: {}", e),
+                Err(failure) => failures.push(failure),
             }
         }
 
-        Ok(successful_results)
+        (successful_results, failures)
     }
 
-    /// Download a single file using the connection pool
+    /// Pick the servers whose retention covers a file, in server order
+    ///
+    /// When the post is older than every configured retention all servers are
+    /// tried anyway, since retention figures are only approximate. A post
+    /// without a usable date is routed like a new one.
+    fn route_file(
+        file: &NzbFile,
+        filename: &str,
+        config: &Config,
+        now: SystemTime,
+        servers: &[Server],
+    ) -> Vec<Server> {
+        let age_days = file.age_days_at(now);
+        let mut route = config.servers_for_age(age_days.unwrap_or(0.0));
+        if route.is_empty() {
+            route = (0..servers.len()).collect();
+        }
+
+        tracing::debug!(
+            "{} ({} old) -> {}",
+            filename,
+            age_days.map_or_else(|| "unknown".to_string(), format_age),
+            route
+                .iter()
+                .map(|&i| servers[i].name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+
+        route.into_iter().map(|i| servers[i].clone()).collect()
+    }
+
+    /// Download a single file, falling back through the servers that can have it
     async fn download_file_with_pool(
-        file: NzbFile,
+        file: &NzbFile,
         config: &Config,
-        pool: NntpPool,
-        progress_bar: ProgressBar,
+        now: SystemTime,
+        servers: &[Server],
+        throttle: &Throttle,
+        memory: &MemoryBudget,
+        progress: &dyn ProgressSink,
+        events: &Events,
+        budget: &RetryBudget,
+        shutdown: &Shutdown,
+        stall: &StallWatch,
     ) -> Result<DownloadResult> {
-        let filename = Nzb::get_filename_from_subject(&file.subject)
-            .unwrap_or_else(|| format!("unknown_file_{}", file.date));
+        if shutdown.is_triggered() {
+            return Err(DownloadError::Cancelled.into());
+        }
+        // Numbers that can't order the segments give way to the order the NZB
+        // lists them in, and the writer places each part by its yEnc offset.
+        // Decoded parts are smaller than the articles the NZB declares, so no
+        // offset can be right past their total.
+        let expected_size: u64 = file.segments.segment.iter().map(|s| s.bytes).sum();
+        let (file, placement): (Cow<NzbFile>, _) = if file.numbering_usable() {
+            (Cow::Borrowed(file), Placement::InOrder)
+        } else {
+            let placement = Placement::ByOffset {
+                size_limit: expected_size,
+            };
+            (Cow::Owned(file.renumbered()), placement)
+        };
+        let file = file.as_ref();
+
+        // Servers that can still have this post, primary first
+        let servers = Self::route_file(file, &file.filename(), config, now, servers);
+        let connection_wait_timeout = config.tuning.connection_wait_timeout;
+
+        // A mapped name is used as given. Obfuscated subjects often carry no
+        // name; the first segment's yEnc header usually does. The index keeps
+        // the last resort unique.
+        let subject_name = || Nzb::get_filename_from_subject(&file.subject);
+        let filename = match file.name.clone().or_else(subject_name) {
+            Some(name) => name,
+            None => {
+                Self::name_from_first_segment(file, &servers, progress, connection_wait_timeout)
+                    .await
+                    .unwrap_or_else(|| format!("unknown_file_{}_{}", file.date, file.index))
+            }
+        };
 
         let output_path = config.download.dir.join(&filename);
 
-        // Check if file already exists with correct size (safe resume)
-        // Size check is sufficient - corruption will be caught by PAR2 verification
+        events.send(DownloadEvent::FileStarted {
+            filename: filename.clone(),
+            size: expected_size,
+            segments: file.segments.segment.len(),
+        });
+
+        // What's there already is looked at before anything is written. A
+        // file a previous run kept incomplete resumes; one at its expected
+        // size is checked as `download.skip_verification` says, against the
+        // decoded size recorded when it finished if there is one; one at
+        // another size is left to `download.existing_files`
+        let temp_path = config
+            .download
+            .temp_dir
+            .as_ref()
+            .map(|dir| dir.join(&filename));
+        let method = config.download.skip_verification;
+        let mut existing = None;
+        let mut resume_from: Option<(PathBuf, Vec<String>)> = None;
+        let mut skip_at: Option<u64> = None;
         if !config.download.force_redownload {
-            let expected_size: u64 = file.segments.segment.iter().map(|s| s.bytes).sum();
-            if let Ok(metadata) = tokio::fs::metadata(&output_path).await {
-                if metadata.len() == expected_size {
-                    // Log skip using progress bar for clean output
-                    if progress_bar.is_hidden() {
-                        eprintln!("  Skipping complete: {}", filename);
-                    } else {
-                        progress_bar.println(format!("  \x1b[90m↳ Skipping: {}\x1b[0m", filename));
+            let dir = &config.download.dir;
+            if let Some(missing) = partial::read_marker(dir).await.remove(&filename) {
+                let kept = temp_path.iter().chain([&output_path]).find(|p| p.is_file());
+                resume_from = kept.map(|path| (path.clone(), missing));
+            } else {
+                let expected = sizes::recorded_size(dir, &filename)
+                    .await
+                    .unwrap_or(expected_size);
+                match skip::check_existing(dir, &filename, expected, method).await {
+                    SkipVerdict::Absent => {}
+                    SkipVerdict::Rejected(reason) => {
+                        tracing::info!(
+                            "{}: already there, but {}; downloading again (skip_verification = {})",
+                            filename,
+                            reason,
+                            method
+                        );
+                    }
+                    SkipVerdict::Skip => {
+                        tracing::info!(
+                            "{}: already there, {} check passed; skipping",
+                            filename,
+                            method
+                        );
+                        progress.on_message(&format!("  \x1b[90m↳ Skipping: {}\x1b[0m", filename));
+                        skip_at = Some(expected);
+                    }
+                    SkipVerdict::OtherSize(size) => {
+                        let policy = config.download.existing_files;
+                        let decision = match policy {
+                            ExistingFiles::Skip => {
+                                skip_at = Some(size);
+                                existing = Some(ExistingFile::Kept);
+                                "kept it instead of downloading".to_string()
+                            }
+                            ExistingFiles::Overwrite => {
+                                existing = Some(ExistingFile::Overwritten);
+                                "downloading over it".to_string()
+                            }
+                            ExistingFiles::Rename => {
+                                let moved = skip::move_aside(&output_path).await?;
+                                let decision = format!("moved it aside to {}", moved.display());
+                                existing = Some(ExistingFile::Renamed(moved));
+                                decision
+                            }
+                        };
+                        tracing::info!(
+                            "{}: already there at {} bytes rather than {}; {} (existing_files = {})",
+                            filename,
+                            size,
+                            expected,
+                            decision,
+                            policy
+                        );
+                        progress.on_message(&format!(
+                            "  \x1b[90m↳ {}: already there at another size; {}\x1b[0m",
+                            filename, decision
+                        ));
                     }
-                    return Ok(DownloadResult {
-                        filename,
-                        path: output_path,
-                        size: expected_size,
-                        segments_downloaded: file.segments.segment.len(),
-                        segments_failed: 0,
-                        download_time: Duration::from_secs(0),
-                        average_speed: 0.0,
-                        failed_message_ids: Vec::new(),
-                    });
                 }
             }
         }
+        if let Some(size) = skip_at {
+            progress.on_segments(&SegmentsDone {
+                filename: &filename,
+                segments: file.segments.segment.len(),
+                declared: expected_size,
+                transferred: 0,
+            });
+            events.send(DownloadEvent::Progress {
+                bytes: expected_size,
+                transferred: 0,
+            });
+            events.send(DownloadEvent::FileCompleted {
+                filename: filename.clone(),
+                size,
+                segments_failed: 0,
+                skipped: true,
+            });
+            return Ok(DownloadResult {
+                filename,
+                path: output_path,
+                size,
+                segments_downloaded: file.segments.segment.len(),
+                segments_failed: 0,
+                segments_missing: 0,
+                segments_corrupt: 0,
+                download_time: Duration::from_secs(0),
+                average_speed: 0.0,
+                failed_message_ids: Vec::new(),
+                group: file.groups.group[0].name.clone(),
+                groups: file.groups.group.iter().map(|g| g.name.clone()).collect(),
+                poster: file.poster.clone(),
+                posted: file.date,
+                segment_map: Vec::new(),
+                bytes_by_server: BTreeMap::new(),
+                transferred: 0,
+                skipped: Some(method),
+                existing,
+                name_mapped: file.name.is_some(),
+            });
+        }
 
         let start_time = Instant::now();
+        progress.on_file_start(&filename, expected_size, file.segments.segment.len());
+        stall.start(file.index);
+
+        // A kept file resumes with the segments the marker lists; if none of
+        // them are the NZB's, it's downloaded afresh
+        let resume_from = resume_from.and_then(|(path, ids)| {
+            let ids: BTreeSet<String> = ids
+                .iter()
+                .map(|id| bare_message_id(id).to_string())
+                .collect();
+            (file.segments.segment.iter())
+                .any(|s| ids.contains(bare_message_id(&s.message_id)))
+                .then_some((path, ids))
+        });
+        let kept: Vec<&NzbSegment> = match &resume_from {
+            Some((_, ids)) => (file.segments.segment.iter())
+                .filter(|s| !ids.contains(bare_message_id(&s.message_id)))
+                .collect(),
+            None => Vec::new(),
+        };
+        if let Some((path, _)) = &resume_from {
+            let kept_bytes = kept.iter().map(|s| s.bytes).sum();
+            tracing::info!(
+                "{}: resuming {}; {} of {} segments already there",
+                filename,
+                path.display(),
+                kept.len(),
+                file.segments.segment.len()
+            );
+            progress.on_message(&format!(
+                "  \x1b[90m↳ Resuming: {} ({} segments left)\x1b[0m",
+                filename,
+                file.segments.segment.len() - kept.len()
+            ));
+            progress.on_segments(&SegmentsDone {
+                filename: &filename,
+                segments: kept.len(),
+                declared: kept_bytes,
+                transferred: 0,
+            });
+            events.send(DownloadEvent::Progress {
+                bytes: kept_bytes,
+                transferred: 0,
+            });
+            existing = Some(ExistingFile::Resumed);
+        }
+
+        // With a temp directory the file is assembled there and moved in
+        // when complete; a resumed file is written where it was kept
+        let work_path = match (&resume_from, &temp_path) {
+            (Some((path, _)), _) => path.clone(),
+            (None, Some(temp_path)) => {
+                if let Some(temp_dir) = &config.download.temp_dir {
+                    tokio::fs::create_dir_all(temp_dir).await?;
+                }
+                temp_path.clone()
+            }
+            (None, None) => output_path.clone(),
+        };
+
+        // Segments go to the file's writer task as batches finish; a gap the
+        // NZB leaves in the numbers still takes up its place in the file
+        let total_segments = file.segment_slots();
+        let mut segment_bytes = vec![0u64; total_segments];
+        for segment in &file.segments.segment {
+            if let Some(slot) = (segment.number as usize)
+                .checked_sub(1)
+                .and_then(|i| segment_bytes.get_mut(i))
+            {
+                *slot = segment.bytes;
+            }
+        }
 
-        // Create output file with async I/O
-        let output_file = File::create(&output_path).await?;
-        let mut writer = BufWriter::with_capacity(config.memory.io_buffer_size, output_file);
+        // Only now, with the decision made, is anything truncated; a resumed
+        // file's segments go where their yEnc offsets say, around what's kept
+        let (handle, writing) = if resume_from.is_some() {
+            let handle = OpenOptions::new().write(true).open(&work_path).await?;
+            let size_limit = expected_size;
+            (handle, Placement::ByOffset { size_limit })
+        } else {
+            (File::create(&work_path).await?, placement)
+        };
+        let writer = FileWriter::spawn(
+            handle.into_std().await,
+            segment_bytes.clone(),
+            config.memory.io_buffer_size,
+            config.memory.max_segments_in_memory,
+            config.download.fsync == FsyncPolicy::PerFile,
+            writing,
+        );
 
         // Prepare segment downloads using pipelining
         let group = &file.groups.group[0].name; // Use first group
@@ -232,6 +1259,11 @@ impl Downloader {
             .segments
             .segment
             .iter()
+            .filter(|segment| {
+                resume_from.as_ref().map_or(true, |(_, ids)| {
+                    ids.contains(bare_message_id(&segment.message_id))
+                })
+            })
             .map(|segment| SegmentRequest {
                 message_id: segment.message_id.clone(),
                 group: group.clone(),
@@ -244,171 +1276,352 @@ impl Downloader {
 
         // Split into batches for pipelining
         let num_connections = config.usenet.connections as usize;
-        let batches: Vec<Vec<SegmentRequest>> = segment_requests
-            .chunks(pipeline_size)
-            .map(|chunk| chunk.to_vec())
-            .collect();
+        let batches = segment_requests.chunks(pipeline_size);
 
         // Download batches in parallel using connection pool
-        let connection_wait_timeout = config.tuning.connection_wait_timeout;
-        let batch_futures = batches.into_iter().map(|batch| {
-            let pool = pool.clone();
-            let progress = progress_bar.clone();
-            let segment_bytes: Vec<u64> = file.segments.segment.iter().map(|s| s.bytes).collect();
+        // Shared by every batch instead of copied into each
+        let (servers, segment_bytes) = (&servers, &segment_bytes);
+        let declared = |batch: &[SegmentRequest]| -> u64 {
+            batch
+                .iter()
+                .filter_map(|req| (req.segment_number as usize).checked_sub(1))
+                .filter_map(|idx| segment_bytes.get(idx))
+                .sum()
+        };
+        // Once enough segments in a row are gone, the rest aren't asked for
+        let breaker = &MissingBreaker::new(config.tuning.give_up_after_missing);
+        // Nor once the file stalls, and the batches fetching are abandoned
+        let stalled = &CancellationToken::new();
+        // Memory is set aside one batch at a time in segment order, so the
+        // segments a writer holds back for a gap never starve the batch that
+        // fills it; a batch given up on needs none
+        let reserved = stream::iter(batches).then(|batch| async move {
+            let bytes = if breaker.is_tripped() || shutdown.is_triggered() || stalled.is_cancelled()
+            {
+                0
+            } else {
+                declared(batch)
+            };
+            (batch, memory.reserve(bytes).await)
+        });
+        let batch_futures = reserved.map(|(batch, reservation)| {
+            let filename = filename.as_str();
+            let writer = &writer;
+            let span = tracing::debug_span!(
+                "batch",
+                first = batch.first().map_or(0, |r| r.segment_number),
+                last = batch.last().map_or(0, |r| r.segment_number)
+            );
 
             async move {
-                // Get connection from pool with patient retry
-                // Keep trying until we get a connection - don't fail segments due to pool contention
-                let mut conn = None;
-                let mut attempt = 0u32;
-                let start = Instant::now();
-                let max_wait = Duration::from_secs(connection_wait_timeout);
-
-                while conn.is_none() && start.elapsed() < max_wait {
-                    if attempt > 0 {
-                        // Exponential backoff: 500ms, 1s, 2s, 4s, 8s (capped)
-                        let delay = Duration::from_millis(500) * (1 << attempt.min(4));
-                        tokio::time::sleep(delay).await;
-
-                        // Show feedback after several retries (every ~15s)
-                        if attempt % 5 == 0 && !progress.is_hidden() {
-                            progress.println(format!(
-                                "  \x1b[90m⏳ Waiting for connection... ({:.0}s)\x1b[0m",
-                                start.elapsed().as_secs_f64()
-                            ));
-                        }
+                let mut results: Vec<BatchSegment> = Vec::new();
+                let mut transferred = 0u64;
+                // Segments the last server to be asked didn't have
+                let mut not_found: Vec<u32> = Vec::new();
+                let mut corrupt = 0;
+                let mut offsets: Vec<(u32, u64)> = Vec::new();
+                let mut file_size = None;
+
+                // Past the breaker, the batch counts as missing without a request
+                let given_up = breaker.is_tripped();
+                if given_up {
+                    not_found = batch.iter().map(|req| req.segment_number).collect();
+                    results = batch
+                        .iter()
+                        .map(|req| BatchSegment {
+                            number: req.segment_number,
+                            data: None,
+                            server: None,
+                        })
+                        .collect();
+                }
+                // Shutting down or stalled, nothing more is asked for
+                let asked = if given_up || shutdown.is_triggered() || stalled.is_cancelled() {
+                    &servers[..0]
+                } else {
+                    &servers[..]
+                };
+
+                // Each server only gets the segments the previous ones couldn't
+                // provide; fill servers are only asked while the retry budget lasts
+                for (position, server) in asked.iter().enumerate() {
+                    if position > 0 && !budget.allows() {
+                        break;
+                    }
+                    let missing: Cow<[SegmentRequest]> = if results.is_empty() {
+                        Cow::Borrowed(batch)
+                    } else {
+                        batch
+                            .iter()
+                            .filter(|req| {
+                                !results.iter().any(|segment| {
+                                    segment.number == req.segment_number && segment.data.is_some()
+                                })
+                            })
+                            .cloned()
+                            .collect()
+                    };
+                    if missing.is_empty() {
+                        break;
                     }
 
-                    match tokio::time::timeout(Duration::from_secs(60), pool.get_connection()).await
-                    {
-                        Ok(Ok(c)) => {
-                            conn = Some(c);
+                    let fetch = Self::fetch_batch(
+                        server,
+                        &missing,
+                        throttle,
+                        &reservation,
+                        progress,
+                        connection_wait_timeout,
+                        budget,
+                        position > 0,
+                    );
+                    let fetch = async {
+                        tokio::select! {
+                            fetched = fetch => Some(fetched),
+                            _ = stalled.cancelled() => None,
                         }
-                        Ok(Err(_)) | Err(_) => {
-                            // Connection failed or timed out, will retry
-                            attempt += 1;
+                    };
+                    let Some(fetched) = shutdown.drain(fetch).await.flatten() else {
+                        tracing::debug!("{}: batch abandoned", filename);
+                        break;
+                    };
+                    transferred += fetched.wire_bytes;
+                    corrupt += fetched.corrupt.len();
+                    offsets.extend(fetched.offsets);
+                    file_size = file_size.or(fetched.file_size);
+                    not_found.retain(|num| !missing.iter().any(|r| r.segment_number == *num));
+                    not_found.extend(fetched.missing);
+                    for (number, data) in fetched.segments {
+                        let served = data.is_some().then_some(position);
+                        match results.iter_mut().find(|segment| segment.number == number) {
+                            Some(slot) if slot.data.is_none() => {
+                                slot.data = data;
+                                slot.server = served;
+                            }
+                            Some(_) => {}
+                            None => results.push(BatchSegment {
+                                number,
+                                data,
+                                server: served,
+                            }),
                         }
                     }
                 }
 
-                let mut conn = match conn {
-                    Some(c) => c,
-                    None => {
-                        // Only warn after exhausting retries
-                        if progress.is_hidden() {
-                            eprintln!(
-                                "  Warning: Could not get connection after {:?}",
-                                start.elapsed()
-                            );
-                        } else {
-                            progress.println(format!(
-                                "  \x1b[33m⚠ Connection unavailable, batch skipped\x1b[0m"
-                            ));
-                        }
-                        return batch.iter().map(|req| (req.segment_number, None)).collect();
+                // What the segments didn't take is free for other batches
+                drop(reservation);
+
+                // Segments a shutdown or stall left unfetched fail like any other
+                for req in batch {
+                    if !results.iter().any(|s| s.number == req.segment_number) {
+                        results.push(BatchSegment {
+                            number: req.segment_number,
+                            data: None,
+                            server: None,
+                        });
                     }
-                };
+                }
 
-                // Download pipelined batch
-                match conn.download_segments_pipelined(&batch).await {
-                    Ok(results) => {
-                        // Update progress for all segments
-                        for (seg_num, _) in &results {
-                            if let Some(idx) = (*seg_num as usize).checked_sub(1) {
-                                if idx < segment_bytes.len() {
-                                    progress.inc(segment_bytes[idx]);
-                                }
-                            }
-                        }
-                        results
+                // Account for all segments, fetched or not
+                let batch_bytes = declared(batch);
+                progress.on_bytes(transferred);
+                progress.on_segments(&SegmentsDone {
+                    filename,
+                    segments: batch.len(),
+                    declared: batch_bytes,
+                    transferred,
+                });
+                events.send(DownloadEvent::Progress {
+                    bytes: batch_bytes,
+                    transferred,
+                });
+                stall.progressed(file.index);
+
+                let mut outcome = BatchOutcome {
+                    downloaded: 0,
+                    size: 0,
+                    served: vec![0; servers.len()],
+                    transferred,
+                    failed: Vec::new(),
+                    missing: not_found.len(),
+                    corrupt,
+                    file_size,
+                };
+                if !given_up && !shutdown.is_triggered() && !stalled.is_cancelled() {
+                    let found = results.iter().filter(|s| s.data.is_some()).count();
+                    if let Some((missing, answered)) = breaker.record(found, not_found.len()) {
+                        tracing::warn!(
+                            "Gave up on {} after {}/{} segments missing; the rest count as missing without asking",
+                            filename,
+                            missing,
+                            answered
+                        );
                     }
-                    Err(_) => {
-                        // Failed - update progress anyway
-                        for req in &batch {
-                            if let Some(idx) = (req.segment_number as usize).checked_sub(1) {
-                                if idx < segment_bytes.len() {
-                                    progress.inc(segment_bytes[idx]);
-                                }
+                }
+                for BatchSegment {
+                    number,
+                    data,
+                    server,
+                } in results
+                {
+                    match &data {
+                        Some(data) => {
+                            outcome.downloaded += 1;
+                            outcome.size += data.len() as u64;
+                            if let Some(server) = server {
+                                outcome.served[server] += data.len() as u64;
                             }
                         }
-                        Vec::new()
+                        None => outcome.failed.push(number),
                     }
+                    let offset = offsets.iter().find(|(n, _)| *n == number).map(|&(_, o)| o);
+                    writer.send_at(number, data, offset).await;
                 }
+                outcome
             }
+            .instrument(span)
         });
 
         // Execute batches matching connection pool size exactly
         // This prevents timeout errors from queuing too many requests
-        let batch_results: Vec<Vec<(u32, Option<Bytes>)>> = stream::iter(batch_futures)
+        let batches = batch_futures
             .buffer_unordered(num_connections)
-            .collect()
-            .await;
-
-        // Flatten results into segment_results format
-        let segment_results: Vec<Result<SegmentResult>> = batch_results
-            .into_iter()
-            .flatten()
-            .map(|(segment_number, data)| {
-                let message_id = file
-                    .segments
-                    .segment
-                    .iter()
-                    .find(|s| s.number == segment_number)
-                    .map(|s| s.message_id.clone())
-                    .unwrap_or_default();
-
-                Ok(SegmentResult {
-                    segment_number,
-                    data,
-                    message_id,
-                })
-            })
-            .collect();
-
-        // Process results and write to file
-        // Pre-allocate Vec for segment data (faster than HashMap)
-        let total_segments = file.segments.segment.len();
-        let mut segment_data: Vec<Option<Bytes>> = vec![None; total_segments];
-        let mut segments_downloaded = 0;
-        let mut segments_failed = 0;
-        let mut actual_size = 0u64;
-        let mut failed_message_ids = Vec::new();
-
-        for result in segment_results {
-            match result {
-                Ok(segment_result) => {
-                    if let Some(data) = segment_result.data {
-                        segments_downloaded += 1;
-                        actual_size += data.len() as u64;
-                        // Segments are 1-indexed, Vec is 0-indexed
-                        let index = segment_result.segment_number.saturating_sub(1) as usize;
-                        if index < total_segments {
-                            segment_data[index] = Some(data);
-                        } else {
-                            tracing::debug!(
-                                "Invalid segment number: {} (expected 1-{})",
-                                segment_result.segment_number,
-                                total_segments
-                            );
-                        }
-                    } else {
-                        segments_failed += 1;
-                        failed_message_ids.push(segment_result.message_id);
+            .collect::<Vec<BatchOutcome>>();
+        tokio::pin!(batches);
+        let batch_results = loop {
+            tokio::select! {
+                results = &mut batches => break results,
+                _ = tokio::time::sleep(STALL_CHECK_INTERVAL), if !stalled.is_cancelled() => {
+                    if let Some(quiet) = stall.stalled(file.index) {
+                        tracing::warn!(
+                            "{} stalled, gave up after {}s without progress; its missing segments are left to PAR2",
+                            filename,
+                            quiet.as_secs()
+                        );
+                        progress.on_message(&format!(
+                            "  \x1b[33m⚠ {} stalled, gave up after {}s\x1b[0m",
+                            filename,
+                            quiet.as_secs()
+                        ));
+                        stalled.cancel();
                     }
                 }
-                Err(_) => segments_failed += 1,
+            }
+        };
+        let segments_missing = batch_results.iter().map(|b| b.missing).sum();
+        let segments_corrupt = batch_results.iter().map(|b| b.corrupt).sum();
+        let mut segments_downloaded: usize =
+            batch_results.iter().map(|b| b.downloaded).sum::<usize>() + kept.len();
+        let mut actual_size: u64 = batch_results.iter().map(|b| b.size).sum();
+        let transferred = batch_results.iter().map(|b| b.transferred).sum();
+        let mut bytes_by_server = BTreeMap::new();
+        for (position, server) in servers.iter().enumerate() {
+            let bytes: u64 = batch_results.iter().map(|b| b.served[position]).sum();
+            if bytes > 0 {
+                *bytes_by_server.entry(server.name.clone()).or_default() += bytes;
+            }
+        }
+
+        let mut message_ids: Vec<&str> = vec![""; total_segments];
+        for segment in &file.segments.segment {
+            if let Some(slot) = (segment.number as usize)
+                .checked_sub(1)
+                .and_then(|i| message_ids.get_mut(i))
+            {
+                *slot = &segment.message_id;
             }
         }
+        let mut failed_message_ids: Vec<String> = batch_results
+            .iter()
+            .flat_map(|b| &b.failed)
+            .map(|&num| {
+                (num as usize)
+                    .checked_sub(1)
+                    .and_then(|i| message_ids.get(i))
+                    .map(|id| id.to_string())
+                    .unwrap_or_default()
+            })
+            .collect();
 
-        // Write segments in order (Vec iteration is faster than HashMap lookups)
-        for data in segment_data.into_iter().flatten() {
-            writer.write_all(&data).await?;
+        // Flushed (and synced if configured) before the file counts as done
+        let written = writer.finish().await?;
+        for number in &written.oversized {
+            tracing::warn!(
+                "{}: segment {} is larger than the NZB declares",
+                filename,
+                number
+            );
+        }
+        if !written.rejected.is_empty() {
+            // Downloaded, but writing them would overwrite other parts; PAR2
+            // repairs the gap like any other missing segment
+            tracing::warn!(
+                "{}: left out {} segment(s) that would overlap their neighbours: {:?}",
+                filename,
+                written.rejected.len(),
+                written.rejected
+            );
+            segments_downloaded = segments_downloaded.saturating_sub(written.rejected.len());
+            failed_message_ids.extend(written.rejected.iter().map(|&number| {
+                message_ids
+                    .get(number as usize - 1)
+                    .map(|id| id.to_string())
+                    .unwrap_or_default()
+            }));
         }
+        let segments_failed = failed_message_ids.len();
+        if placement != Placement::InOrder {
+            tracing::info!(
+                "{}: segment numbers unusable; placed {} of {} segments by yEnc offset, the rest in NZB order",
+                filename,
+                written.placed_by_offset,
+                total_segments
+            );
+        }
+        Self::check_file_size(
+            &filename,
+            expected_size,
+            batch_results.iter().find_map(|b| b.file_size),
+            &written.spans,
+            segments_failed,
+        );
+        // A resumed file's size includes what was already there
+        actual_size += kept
+            .iter()
+            .filter_map(|s| written.spans.get((s.number as usize).checked_sub(1)?))
+            .map(|&(_, len)| len)
+            .sum::<u64>();
+        let segment_map = written
+            .spans
+            .into_iter()
+            .enumerate()
+            .map(|(index, (offset, len))| SegmentSpan {
+                number: index as u32 + 1,
+                message_id: message_ids[index].to_string(),
+                offset,
+                len,
+            })
+            .collect();
 
-        // Ensure all data is written
-        writer.flush().await?;
-        writer.shutdown().await?;
+        // A file a shutdown cut short stays in the temp directory
+        let output_path = if work_path == output_path {
+            output_path
+        } else if shutdown.is_triggered() && segments_failed > 0 {
+            work_path
+        } else {
+            move_file(&work_path, &output_path).await?;
+            output_path
+        };
+        if config.download.fsync == FsyncPolicy::PerFile {
+            if let Some(dir) = output_path.parent() {
+                let dir = dir.to_path_buf();
+                tokio::task::spawn_blocking(move || sync_dir(&dir))
+                    .await
+                    .map_err(std::io::Error::other)??;
+            }
+        }
 
+        // Includes the sync, so the reported speed is what reached the disk
         let download_time = start_time.elapsed();
         let average_speed = if download_time.as_secs() > 0 {
             (actual_size as f64 / 1024.0 / 1024.0) / download_time.as_secs_f64()
@@ -416,37 +1629,715 @@ impl Downloader {
             0.0
         };
 
+        events.send(DownloadEvent::FileCompleted {
+            filename: filename.clone(),
+            size: actual_size,
+            segments_failed,
+            skipped: false,
+        });
+
         Ok(DownloadResult {
             filename,
             path: output_path,
             size: actual_size,
             segments_downloaded,
             segments_failed,
+            segments_missing,
+            segments_corrupt,
             download_time,
             average_speed,
             failed_message_ids,
+            group: group.clone(),
+            groups: file.groups.group.iter().map(|g| g.name.clone()).collect(),
+            poster: file.poster.clone(),
+            posted: file.date,
+            segment_map,
+            bytes_by_server,
+            transferred,
+            skipped: None,
+            existing,
+            name_mapped: file.name.is_some(),
         })
     }
 
-    /// Clean up partial files after failed download
-    pub async fn cleanup_partial_files(results: &[DownloadResult]) -> Result<usize> {
-        let mut cleaned_count = 0;
+    /// Give a downloaded file a `.par2` name when it's a PAR2 file under
+    /// another one
+    ///
+    /// Subjects like `release [01/45] par2` don't give the name PAR2 needs
+    /// to find the file. Its first segment starts with the packet header, so
+    /// the file is looked at once it's down; files skipped, kept short by a
+    /// shutdown, named by a name map or kept under their posted names
+    /// (`post_processing.preserve_names`) are left as they are.
+    async fn name_par2_by_contents(result: DownloadResult, config: &Config) -> DownloadResult {
+        if result.skipped.is_some()
+            || result.name_mapped
+            || config.post_processing.preserve_names
+            || result.path.parent() != Some(config.download.dir.as_path())
+            || par2_patterns::is_par2_file(&result.path)
+        {
+            return result;
+        }
+        let path = result.path.clone();
+        let sniffed = tokio::task::spawn_blocking(move || par2_patterns::has_packet_magic(&path));
+        if !sniffed.await.unwrap_or(false) {
+            return result;
+        }
+        let renamed = format!("{}.par2", result.filename);
+        let renamed_path = result.path.with_file_name(&renamed);
+        if tokio::fs::try_exists(&renamed_path).await.unwrap_or(true) {
+            tracing::warn!(
+                "{} is a PAR2 file, but {} is already there; left under its name",
+                result.filename,
+                renamed
+            );
+            return result;
+        }
+        if let Err(e) = tokio::fs::rename(&result.path, &renamed_path).await {
+            tracing::warn!(
+                "{} is a PAR2 file, but it couldn't be renamed to {}: {}",
+                result.filename,
+                renamed,
+                e
+            );
+            return result;
+        }
+        tracing::info!("{} is a PAR2 file; saved as {}", result.filename, renamed);
+        DownloadResult {
+            filename: renamed,
+            path: renamed_path,
+            ..result
+        }
+    }
 
-        for result in results {
-            // Only clean up files with failed segments
-            if result.segments_failed > 0 && result.path.exists() {
-                match tokio::fs::remove_file(&result.path).await {
-                    Ok(_) => {
-                        tracing::debug!("Cleaned up partial file: {}", result.path.display());
-                        cleaned_count += 1;
+    /// Compare what a file decoded to with what it should be
+    ///
+    /// The file should end where the `=ybegin` header's size says, or
+    /// failing that where its segments put it; a whole file that doesn't is
+    /// worth a warning, as is an NZB whose `bytes` are far from either.
+    fn check_file_size(
+        filename: &str,
+        declared: u64,
+        header_size: Option<u64>,
+        spans: &[(u64, u64)],
+        segments_failed: usize,
+    ) {
+        let written = spans.iter().map(|&(offset, len)| offset + len).max();
+        let Some(written) = written else {
+            return;
+        };
+        let expected = header_size.unwrap_or(written);
+        if segments_failed == 0 && written != expected {
+            tracing::warn!(
+                "{}: decoded to {} bytes, but its yEnc header says {}",
+                filename,
+                written,
+                expected
+            );
+        }
+        if sizes::diverges(declared, expected) {
+            tracing::warn!(
+                "{}: the NZB declares {} bytes, the file decodes to {} (more than {}% apart)",
+                filename,
+                declared,
+                expected,
+                sizes::SIZE_TOLERANCE_PERCENT
+            );
+        }
+    }
+
+    /// File name from the `=ybegin` header of a file's first segment
+    ///
+    /// Servers are asked in order until one has the article. The segment is
+    /// fetched again with the rest; it's one article per nameless file.
+    async fn name_from_first_segment(
+        file: &NzbFile,
+        servers: &[Server],
+        progress: &dyn ProgressSink,
+        connection_wait_timeout: u64,
+    ) -> Option<String> {
+        let first = file.segments.segment.iter().min_by_key(|s| s.number)?;
+        let group = &file.groups.group.first()?.name;
+        for server in servers {
+            let Ok(mut conn) = Self::wait_for_connection(
+                &server.pool,
+                group,
+                progress,
+                connection_wait_timeout,
+                &server.counters.pool_wait,
+            )
+            .await
+            else {
+                continue;
+            };
+            match conn.yenc_name(&first.message_id, group).await {
+                Ok(name) => {
+                    let name = name.map(|name| sanitize_filename(&name));
+                    tracing::debug!(
+                        "No name in subject {:?}; yEnc header says {:?}",
+                        file.subject,
+                        name
+                    );
+                    return name.filter(|name| !name.is_empty());
+                }
+                Err(e) => tracing::debug!(
+                    "{} couldn't provide {} for its yEnc name: {}",
+                    server.name,
+                    first.message_id,
+                    e
+                ),
+            }
+        }
+        None
+    }
+
+    /// Fetch one pipelined batch from a server, updating its statistics
+    ///
+    /// Every requested segment appears in the result; segments that couldn't be
+    /// fetched (including when no connection was available) are `None`. A
+    /// `fill` server's fetch is charged to `budget` in full; otherwise only
+    /// retries are.
+    async fn fetch_batch(
+        server: &Server,
+        batch: &[SegmentRequest],
+        throttle: &Throttle,
+        memory: &Reservation,
+        progress: &dyn ProgressSink,
+        connection_wait_timeout: u64,
+        budget: &RetryBudget,
+        fill: bool,
+    ) -> FetchedBatch {
+        let counters = &server.counters;
+        let _permit = match &server.limit {
+            Some(limit) => Some(limit.acquire().await),
+            None => None,
+        };
+        let (results, failure) = Self::fetch_batch_from_pool(
+            &server.pool,
+            batch,
+            throttle,
+            memory,
+            progress,
+            &server.retry,
+            connection_wait_timeout,
+            &counters.pool_wait,
+            budget,
+            fill,
+        )
+        .instrument(tracing::debug_span!(
+            "server",
+            name = %server.name,
+            conn = tracing::field::Empty
+        ))
+        .await;
+
+        counters
+            .transferred
+            .fetch_add(results.wire_bytes, Ordering::Relaxed);
+        let failed = match failure {
+            Some(BatchFailure::NoConnection) => &counters.no_connection,
+            Some(BatchFailure::Error) => &counters.errors,
+            None => &counters.missing,
+        };
+        for (number, data) in &results.segments {
+            match data {
+                Some(data) => {
+                    counters.articles.fetch_add(1, Ordering::Relaxed);
+                    counters
+                        .bytes
+                        .fetch_add(data.len() as u64, Ordering::Relaxed);
+                }
+                None if results.interrupted.contains(number) => {
+                    counters.errors.fetch_add(1, Ordering::Relaxed);
+                }
+                None => {
+                    failed.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+        results
+    }
+
+    /// Segments from one batch, plus why the batch failed as a whole if it did
+    ///
+    /// A batch that breaks off with a retryable error (dropped connection,
+    /// timeout) is fetched again on a fresh connection, as `retry` and
+    /// `budget` allow.
+    async fn fetch_batch_from_pool(
+        pool: &NntpPool,
+        batch: &[SegmentRequest],
+        throttle: &Throttle,
+        memory: &Reservation,
+        progress: &dyn ProgressSink,
+        retry: &RetryPolicy,
+        connection_wait_timeout: u64,
+        pool_wait: &WaitHistogram,
+        budget: &RetryBudget,
+        fill: bool,
+    ) -> (FetchedBatch, Option<BatchFailure>) {
+        // Read by attempts that broke off; transferred all the same
+        let lost = AtomicU64::new(0);
+        let lost = &lost;
+        let fetched = retry_with_backoff(
+            retry,
+            |(failure, e): &(BatchFailure, DlNzbError)| {
+                matches!(failure, BatchFailure::Error) && e.is_retryable() && budget.allows()
+            },
+            |attempt| async move {
+                let started = budget.start();
+                let fetched = async {
+                    let mut conn = Self::wait_for_connection(
+                        pool,
+                        &batch[0].group,
+                        progress,
+                        connection_wait_timeout,
+                        pool_wait,
+                    )
+                    .await
+                    .map_err(|e| (BatchFailure::NoConnection, e))?;
+                    tracing::Span::current().record("conn", conn.id());
+                    let before = conn.bytes_read();
+                    let fetched = conn
+                        .download_segments_throttled(batch, throttle, memory)
+                        .await;
+                    if fetched.is_err() {
+                        lost.fetch_add(conn.bytes_read() - before, Ordering::Relaxed);
                     }
-                    Err(e) => {
-                        tracing::debug!("Failed to clean up {}: {}", result.path.display(), e);
+                    fetched.map_err(|e| (BatchFailure::Error, e))
+                }
+                .await;
+                if fill || attempt > 0 {
+                    let bytes = fetched.as_ref().map_or(0, |fetched| fetched.wire_bytes);
+                    budget.charge(started, bytes);
+                }
+                fetched
+            },
+        )
+        .await;
+
+        match fetched {
+            Ok(mut results) => {
+                // What a connection the server closed left unanswered is asked
+                // again on a fresh one, so an idle timeout fails nothing
+                for _ in 0..CLOSED_RETRIES {
+                    if results.interrupted.is_empty() || !budget.allows() {
+                        break;
+                    }
+                    let rest: Vec<SegmentRequest> = batch
+                        .iter()
+                        .filter(|req| results.interrupted.contains(&req.segment_number))
+                        .cloned()
+                        .collect();
+                    tracing::debug!(
+                        segments = rest.len(),
+                        "Connection closed by the server; resuming on a fresh one"
+                    );
+                    let started = budget.start();
+                    let again = match Self::wait_for_connection(
+                        pool,
+                        &batch[0].group,
+                        progress,
+                        connection_wait_timeout,
+                        pool_wait,
+                    )
+                    .await
+                    {
+                        Ok(mut conn) => {
+                            let before = conn.bytes_read();
+                            let again = conn
+                                .download_segments_throttled(&rest, throttle, memory)
+                                .await;
+                            if again.is_err() {
+                                lost.fetch_add(conn.bytes_read() - before, Ordering::Relaxed);
+                            }
+                            again
+                        }
+                        Err(e) => Err(e),
+                    };
+                    budget.charge(started, again.as_ref().map_or(0, |again| again.wire_bytes));
+                    match again {
+                        Ok(again) => results.resume(again),
+                        Err(e) => {
+                            tracing::debug!(error = %e, "Resuming the batch failed");
+                            break;
+                        }
                     }
                 }
+                results.wire_bytes += lost.load(Ordering::Relaxed);
+                (results, None)
+            }
+            Err((failure, e)) => {
+                tracing::debug!(error = %e, "Batch failed");
+                let segments = batch.iter().map(|req| (req.segment_number, None)).collect();
+                (
+                    FetchedBatch {
+                        segments,
+                        wire_bytes: lost.load(Ordering::Relaxed),
+                        ..FetchedBatch::default()
+                    },
+                    Some(failure),
+                )
             }
         }
+    }
+
+    /// Get a connection from the pool, waiting out pool contention for up to
+    /// `connection_wait_timeout` seconds rather than failing segments
+    ///
+    /// An idle connection that already has `group` selected is preferred, so
+    /// files from different groups don't keep connections switching.
+    async fn wait_for_connection(
+        pool: &NntpPool,
+        group: &str,
+        progress: &dyn ProgressSink,
+        connection_wait_timeout: u64,
+        pool_wait: &WaitHistogram,
+    ) -> Result<PooledConnection> {
+        let start = Instant::now();
+        let patience = RetryPolicy::new(0, Duration::from_secs(1))
+            .with_max_delay(Duration::from_secs(8))
+            .until(Duration::from_secs(connection_wait_timeout));
+
+        let conn = retry_with_backoff(&patience, DlNzbError::is_retryable, |attempt| async move {
+            // Show feedback after several retries (every ~15s)
+            if attempt > 0 && attempt % 5 == 0 {
+                progress.on_message(&format!(
+                    "  \x1b[90m⏳ Waiting for connection... ({:.0}s)\x1b[0m",
+                    start.elapsed().as_secs_f64()
+                ));
+            }
+            match tokio::time::timeout(CONNECTION_TIMEOUT, pool.get_connection_preferring(group))
+                .await
+            {
+                Ok(result) => result,
+                Err(_) => Err(NntpError::Timeout {
+                    seconds: CONNECTION_TIMEOUT.as_secs(),
+                }
+                .into()),
+            }
+        })
+        .await;
+
+        pool_wait.observe(start.elapsed());
+        if let Err(e) = &conn {
+            tracing::debug!(
+                "Could not get connection after {:?}: {}",
+                start.elapsed(),
+                e
+            );
+            progress.on_message("  \x1b[33m⚠ Connection unavailable, batch skipped\x1b[0m");
+        }
+        conn
+    }
+
+    /// Re-download the segments covering damaged byte ranges and rewrite them in place
+    ///
+    /// `damaged` pairs a file path with its damaged ranges (e.g. from PAR2 block
+    /// verification). Re-fetches are charged to `budget` (the download's
+    /// [`NzbDownload::retry_budget`]), and stop once it's spent. Returns the
+    /// number of segments rewritten.
+    pub async fn refetch_ranges(
+        &self,
+        results: &[DownloadResult],
+        damaged: &[(PathBuf, Vec<Range<u64>>)],
+        budget: &RetryBudget,
+    ) -> Result<usize> {
+        let mut refetched = 0;
+
+        for (path, ranges) in damaged {
+            if !budget.allows() {
+                tracing::debug!("Retry budget spent; not re-fetching the rest");
+                break;
+            }
+            let result = match results.iter().find(|r| &r.path == path) {
+                Some(result) if !result.segment_map.is_empty() => result,
+                _ => continue,
+            };
+
+            let spans: Vec<&SegmentSpan> = result
+                .segment_map
+                .iter()
+                .filter(|span| ranges.iter().any(|range| span.overlaps(range)))
+                .collect();
+            if spans.is_empty() {
+                continue;
+            }
+
+            let requests: Vec<SegmentRequest> = spans
+                .iter()
+                .map(|span| SegmentRequest {
+                    message_id: span.message_id.clone(),
+                    group: result.group.clone(),
+                    segment_number: span.number,
+                })
+                .collect();
+
+            let started = budget.start();
+            let mut conn = self.servers[0].pool.get_connection().await?;
+            let fetched = conn.download_segments_pipelined(&requests).await?;
+            budget.charge(started, fetched.wire_bytes);
+
+            let mut file = tokio::fs::OpenOptions::new().write(true).open(path).await?;
+            for (number, data) in fetched.segments {
+                let (span, data) = match (spans.iter().find(|s| s.number == number), data) {
+                    (Some(span), Some(data)) => (span, data),
+                    _ => continue,
+                };
+                // A different length means a different article; don't corrupt the file further
+                if span.len != 0 && data.len() as u64 != span.len {
+                    tracing::debug!(
+                        "Refetched segment {} of {} has unexpected length",
+                        number,
+                        result.filename
+                    );
+                    continue;
+                }
+                file.seek(std::io::SeekFrom::Start(span.offset)).await?;
+                file.write_all(&data).await?;
+                refetched += 1;
+            }
+            file.flush().await?;
+
+            tracing::debug!("Refetched {} segment(s) of {}", refetched, result.filename);
+        }
+
+        Ok(refetched)
+    }
+
+    /// Delete the files in `results` still missing segments, or with `keep`
+    /// leave them in place and list them in `dir`'s `.dlnzb-failed` marker
+    pub async fn cleanup_partial_files(
+        dir: &Path,
+        results: &[DownloadResult],
+        keep: bool,
+    ) -> PartialFiles {
+        partial::clean_up(dir, results, keep).await
+    }
+}
+
+/// The order `files` download in under `download.file_order`
+///
+/// Largest-first keeps every connection busy for longest; the other orders
+/// trade some of that for getting particular files sooner. Sorts are stable,
+/// so files of the same size keep their NZB order.
+pub fn download_order<'a>(
+    files: impl IntoIterator<Item = &'a NzbFile>,
+    download: &DownloadConfig,
+) -> Vec<&'a NzbFile> {
+    let size = |file: &NzbFile| -> u64 { file.segments.segment.iter().map(|s| s.bytes).sum() };
+    let mut files: Vec<&NzbFile> = files.into_iter().collect();
+    match download.file_order {
+        // Equal sizes keep to the NZB's order
+        FileOrder::LargestFirst => files.sort_by_key(|f| (std::cmp::Reverse(size(f)), f.index)),
+        FileOrder::SmallestFirst => files.sort_by_key(|f| (size(f), f.index)),
+        FileOrder::NzbOrder => files.sort_by_key(|f| f.index),
+        FileOrder::MetadataFirst => {
+            let (mut metadata, mut data): (Vec<_>, Vec<_>) = files.into_iter().partition(|f| {
+                size(f) <= download.metadata_size || f.par2_kind() == Some(Par2Kind::Index)
+            });
+            metadata.sort_by_key(|f| f.index);
+            data.sort_by_key(|f| (std::cmp::Reverse(size(f)), f.index));
+            metadata.extend(data);
+            files = metadata;
+        }
+    }
+    files
+}
+
+/// Live view of a [`Downloader`]'s per-server counters
+#[derive(Clone)]
+pub struct StatsHandle {
+    servers: Vec<Server>,
+}
+
+impl StatsHandle {
+    /// Article counts for the run summary
+    pub fn server_stats(&self) -> Vec<ServerStats> {
+        self.server_metrics()
+            .into_iter()
+            .map(|m| ServerStats {
+                server: m.server,
+                articles: m.articles,
+                bytes: m.bytes,
+                transferred: m.transferred,
+                missing: m.missing + m.no_connection + m.errors,
+                idle_closures: m.idle_closures,
+            })
+            .collect()
+    }
+
+    /// Everything the metrics endpoint reports per server
+    pub fn server_metrics(&self) -> Vec<ServerMetrics> {
+        self.servers
+            .iter()
+            .map(|server| {
+                let counters = &server.counters;
+                let status = server.pool.status();
+                ServerMetrics {
+                    server: server.name.clone(),
+                    articles: counters.articles.load(Ordering::Relaxed),
+                    bytes: counters.bytes.load(Ordering::Relaxed),
+                    transferred: counters.transferred.load(Ordering::Relaxed),
+                    missing: counters.missing.load(Ordering::Relaxed),
+                    no_connection: counters.no_connection.load(Ordering::Relaxed),
+                    errors: counters.errors.load(Ordering::Relaxed),
+                    idle_closures: server.pool.idle_closures(),
+                    connections_open: status.size,
+                    connections_in_use: status.size.saturating_sub(status.available),
+                    connections_max: status.max_size,
+                    connections_limit: server
+                        .limit
+                        .as_ref()
+                        .map_or(status.max_size, ConnectionLimit::limit),
+                    pool_wait: counters.pool_wait.snapshot(),
+                    handshakes: server.pool.handshakes(),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files() -> Vec<NzbFile> {
+        let file = |name: &str, bytes: &[u64]| {
+            let segments: String = bytes
+                .iter()
+                .enumerate()
+                .map(|(i, b)| {
+                    format!(
+                        r#"<segment bytes="{}" number="{}">{}.{}@x</segment>"#,
+                        b,
+                        i + 1,
+                        name,
+                        i
+                    )
+                })
+                .collect();
+            format!(
+                r#"<file poster="p" date="0" subject="&quot;{}&quot; yEnc (1/{})">
+                <groups><group>alt.binaries.test</group></groups>
+                <segments>{}</segments></file>"#,
+                name,
+                bytes.len(),
+                segments
+            )
+        };
+        let xml = [
+            file("show.part1.rar", &[700_000, 700_000, 700_000, 700_000]),
+            file("show.nfo", &[4_000]),
+            file("show.vol00+01.par2", &[700_000, 100_000]),
+            file("show.part2.rar", &[700_000, 700_000, 300_000]),
+            file("show.par2", &[40_000]),
+            file("show.sfv", &[200]),
+            file("show.sample.mkv", &[700_000; 30]),
+        ]
+        .concat();
+        format!("<nzb>{}</nzb>", xml)
+            .parse::<Nzb>()
+            .unwrap()
+            .files()
+            .clone()
+    }
+
+    fn order(file_order: FileOrder, metadata_size: u64) -> Vec<String> {
+        let files = files();
+        let download = DownloadConfig {
+            file_order,
+            metadata_size,
+            ..DownloadConfig::default()
+        };
+        download_order(&files, &download)
+            .iter()
+            .map(|f| f.filename())
+            .collect()
+    }
+
+    #[test]
+    fn test_download_order() {
+        assert_eq!(
+            order(FileOrder::LargestFirst, 0),
+            [
+                "show.sample.mkv",
+                "show.part1.rar",
+                "show.part2.rar",
+                "show.vol00+01.par2",
+                "show.par2",
+                "show.nfo",
+                "show.sfv",
+            ]
+        );
+        assert_eq!(
+            order(FileOrder::SmallestFirst, 0),
+            [
+                "show.sfv",
+                "show.nfo",
+                "show.par2",
+                "show.vol00+01.par2",
+                "show.part2.rar",
+                "show.part1.rar",
+                "show.sample.mkv",
+            ]
+        );
+        assert_eq!(
+            order(FileOrder::NzbOrder, 0),
+            [
+                "show.part1.rar",
+                "show.nfo",
+                "show.vol00+01.par2",
+                "show.part2.rar",
+                "show.par2",
+                "show.sfv",
+                "show.sample.mkv",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_metadata_first_order() {
+        // Small files in NZB order, then the rest largest first
+        assert_eq!(
+            order(FileOrder::MetadataFirst, 1_000_000),
+            [
+                "show.nfo",
+                "show.vol00+01.par2",
+                "show.par2",
+                "show.sfv",
+                "show.sample.mkv",
+                "show.part1.rar",
+                "show.part2.rar",
+            ]
+        );
+        // The PAR2 index comes early even when it is bigger than the threshold
+        assert_eq!(
+            order(FileOrder::MetadataFirst, 10_000),
+            [
+                "show.nfo",
+                "show.par2",
+                "show.sfv",
+                "show.sample.mkv",
+                "show.part1.rar",
+                "show.part2.rar",
+                "show.vol00+01.par2",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_segment_span_overlaps() {
+        let span = |offset, len| SegmentSpan {
+            number: 1,
+            message_id: String::new(),
+            offset,
+            len,
+        };
 
-        Ok(cleaned_count)
+        assert!(span(100, 100).overlaps(&(150..160)));
+        assert!(span(100, 100).overlaps(&(0..101)));
+        assert!(!span(100, 100).overlaps(&(200..300)));
+        assert!(!span(100, 100).overlaps(&(0..100)));
+        // Missing final segment of unknown size covers everything after its offset
+        assert!(span(100, 0).overlaps(&(5000..6000)));
     }
 }
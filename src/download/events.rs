@@ -0,0 +1,101 @@
+//! Progress events for frontends
+//!
+//! The downloader and post-processor report what they are doing on an
+//! optional channel, so a GUI or wrapper sees the same progress as the
+//! terminal bars without scraping them. A file's events are all sent from the
+//! task downloading it, so its `FileStarted` always arrives before its
+//! `FileCompleted`.
+
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+use crate::state::AppState;
+
+/// Something that happened during a run
+#[derive(Debug, Clone)]
+pub enum DownloadEvent {
+    /// Sent by the caller before handing an NZB to the downloader
+    NzbStarted {
+        nzb: PathBuf,
+    },
+    /// The downloader queued an NZB's files
+    QueueStarted {
+        files: usize,
+        total_bytes: u64,
+    },
+    FileStarted {
+        filename: String,
+        size: u64,
+        segments: usize,
+    },
+    /// Segment bytes handled since the last event (fetched or given up on)
+    Progress {
+        bytes: u64,
+        /// Encoded bytes actually read from the server for them
+        transferred: u64,
+    },
+    FileCompleted {
+        filename: String,
+        size: u64,
+        segments_failed: usize,
+        /// Already complete on disk, nothing downloaded
+        skipped: bool,
+    },
+    PostProcessing(PostProcessPhase),
+}
+
+/// Post-processing step that is about to run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PostProcessPhase {
+    Refetch,
+    Par2,
+    Extract,
+    Deobfuscate,
+    VerifyMedia,
+    CreatePar2,
+    Script,
+}
+
+/// Sending half of an event channel; a no-op when nobody listens
+///
+/// With [`with_state`](Self::with_state) every event also updates an
+/// [`AppState`] as it is sent, so readers of the state don't depend on
+/// anyone draining the channel.
+#[derive(Debug, Clone, Default)]
+pub struct Events {
+    tx: Option<mpsc::UnboundedSender<DownloadEvent>>,
+    state: Option<Arc<AppState>>,
+}
+
+impl Events {
+    /// New channel; the run's events arrive on the receiver in send order
+    pub fn channel() -> (Self, mpsc::UnboundedReceiver<DownloadEvent>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        (
+            Self {
+                tx: Some(tx),
+                state: None,
+            },
+            rx,
+        )
+    }
+
+    /// Keep `state` up to date with the events sent
+    pub fn with_state(mut self, state: Arc<AppState>) -> Self {
+        self.state = Some(state);
+        self
+    }
+
+    pub fn send(&self, event: DownloadEvent) {
+        if let Some(state) = &self.state {
+            state.record(&event);
+        }
+        if let Some(tx) = &self.tx {
+            // A receiver that went away just stops listening
+            let _ = tx.send(event);
+        }
+    }
+}
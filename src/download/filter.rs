@@ -0,0 +1,284 @@
+//! `--only` / `--exclude` / `--select` file selection
+//!
+//! Patterns are globs (`*.nfo`, `*.part01.rar`) matched against the whole
+//! file name, or regular expressions when prefixed with `re:`, which match
+//! anywhere in it. Files whose subject has no quoted file name are matched on
+//! the subject instead. `--select` picks files by their 1-based position in
+//! the NZB, as numbered by list mode.
+
+use regex::{Regex, RegexBuilder};
+use std::ops::RangeInclusive;
+
+use super::nzb::{Nzb, NzbFile};
+use crate::error::{ConfigError, DlNzbError};
+
+type Result<T> = std::result::Result<T, DlNzbError>;
+
+/// Prefix marking a pattern as a regular expression rather than a glob
+const REGEX_PREFIX: &str = "re:";
+
+/// Which files of an NZB to keep
+#[derive(Debug, Clone, Default)]
+pub struct FileFilter {
+    only: Vec<Regex>,
+    exclude: Vec<Regex>,
+    selection: Option<FileSelection>,
+}
+
+impl FileFilter {
+    /// Build a filter; `only` empty means every file not excluded
+    pub fn new(only: &[String], exclude: &[String], case_sensitive: bool) -> Result<Self> {
+        let compile = |flag: &str, patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|p| compile_pattern(p, case_sensitive).map_err(|reason| invalid(flag, reason)))
+                .collect::<Result<Vec<_>>>()
+        };
+        Ok(Self {
+            only: compile("--only", only)?,
+            exclude: compile("--exclude", exclude)?,
+            selection: None,
+        })
+    }
+
+    /// Also require files to be among these NZB positions
+    pub fn with_selection(mut self, selection: Option<FileSelection>) -> Self {
+        self.selection = selection;
+        self
+    }
+
+    /// Whether any pattern or selection was given
+    pub fn is_active(&self) -> bool {
+        !self.only.is_empty() || !self.exclude.is_empty() || self.selection.is_some()
+    }
+
+    /// Check the selection against the number of files in the NZB
+    pub fn validate_selection(&self, file_count: usize) -> Result<()> {
+        match &self.selection {
+            Some(selection) if selection.max() > file_count => Err(invalid(
+                "--select",
+                format!(
+                    "index {} is out of range; the NZB has {} file{}",
+                    selection.max(),
+                    file_count,
+                    if file_count == 1 { "" } else { "s" }
+                ),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn matches(&self, file: &NzbFile) -> bool {
+        if let Some(selection) = &self.selection {
+            if !selection.contains(file.index) {
+                return false;
+            }
+        }
+
+        let name =
+            Nzb::get_filename_from_subject(&file.subject).unwrap_or_else(|| file.subject.clone());
+        let included = self.only.is_empty() || self.only.iter().any(|re| re.is_match(&name));
+        included && !self.exclude.iter().any(|re| re.is_match(&name))
+    }
+}
+
+/// 1-based file positions such as `1,4-7`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileSelection(Vec<RangeInclusive<usize>>);
+
+impl FileSelection {
+    /// Parse a comma-separated list of indexes and `a-b` ranges
+    pub fn parse(spec: &str) -> Result<Self> {
+        let parse_index = |s: &str| match s.trim().parse::<usize>() {
+            Ok(0) => Err(invalid("--select", "indexes start at 1".to_string())),
+            Ok(n) => Ok(n),
+            Err(_) => Err(invalid("--select", format!("not a number: {:?}", s.trim()))),
+        };
+
+        let ranges = spec
+            .split(',')
+            .filter(|part| !part.trim().is_empty())
+            .map(|part| {
+                let (start, end) = match part.split_once('-') {
+                    Some((start, end)) => (parse_index(start)?, parse_index(end)?),
+                    None => {
+                        let index = parse_index(part)?;
+                        (index, index)
+                    }
+                };
+                if start > end {
+                    return Err(invalid(
+                        "--select",
+                        format!("range {} runs backwards", part.trim()),
+                    ));
+                }
+                Ok(start..=end)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if ranges.is_empty() {
+            return Err(invalid("--select", "no indexes given".to_string()));
+        }
+        Ok(Self(ranges))
+    }
+
+    /// Exactly these positions, e.g. as picked interactively
+    pub fn from_indexes(indexes: impl IntoIterator<Item = usize>) -> Self {
+        Self(indexes.into_iter().map(|index| index..=index).collect())
+    }
+
+    pub fn contains(&self, index: usize) -> bool {
+        self.0.iter().any(|range| range.contains(&index))
+    }
+
+    /// Highest selected index
+    pub fn max(&self) -> usize {
+        self.0.iter().map(|range| *range.end()).max().unwrap_or(0)
+    }
+}
+
+fn invalid(flag: &str, reason: String) -> DlNzbError {
+    ConfigError::Invalid {
+        field: flag.to_string(),
+        reason,
+    }
+    .into()
+}
+
+/// Compile a glob, or a regex after `re:`; the error names the pattern
+pub(crate) fn compile_pattern(
+    pattern: &str,
+    case_sensitive: bool,
+) -> std::result::Result<Regex, String> {
+    let source = match pattern.strip_prefix(REGEX_PREFIX) {
+        Some(regex) => regex.to_string(),
+        None => glob_to_regex(pattern),
+    };
+    RegexBuilder::new(&source)
+        .case_insensitive(!case_sensitive)
+        .build()
+        .map_err(|e| format!("{}: {}", pattern, e))
+}
+
+/// Translate a glob (`*`, `?`, `[...]`) into an anchored regex
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            '[' => {
+                regex.push('[');
+                if chars.peek() == Some(&'!') {
+                    chars.next();
+                    regex.push('^');
+                }
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    if c == '\\' {
+                        regex.push('\\');
+                    }
+                    regex.push(c);
+                }
+                regex.push(']');
+            }
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(subject: &str) -> NzbFile {
+        NzbFile {
+            index: 1,
+            poster: String::new(),
+            date: 0,
+            subject: subject.to_string(),
+            groups: super::super::nzb::NzbGroups { group: Vec::new() },
+            segments: super::super::nzb::NzbSegments {
+                segment: Vec::new(),
+            },
+            name: None,
+        }
+    }
+
+    fn patterns(p: &[&str]) -> Vec<String> {
+        p.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_glob_and_regex_patterns() {
+        let nfo = file(r#"[1/4] - "Show.S01E01.NFO" yEnc (1/1)"#);
+        let part1 = file(r#"[2/4] - "show.s01e01.part01.rar" yEnc (1/50)"#);
+        let vol = file(r#"[3/4] - "show.s01e01.vol07+08.par2" yEnc (1/9)"#);
+        let bare = file("no quoted name here");
+
+        let filter =
+            FileFilter::new(&patterns(&["*.nfo", "re:part0*1\\.rar$"]), &[], false).unwrap();
+        assert!(filter.matches(&nfo));
+        assert!(filter.matches(&part1));
+        assert!(!filter.matches(&vol));
+
+        let filter = FileFilter::new(&[], &patterns(&["*.vol??+??.par2"]), false).unwrap();
+        assert!(filter.matches(&part1));
+        assert!(!filter.matches(&vol));
+
+        // Case-sensitive globs stop matching the upper-case NFO
+        let filter = FileFilter::new(&patterns(&["*.nfo"]), &[], true).unwrap();
+        assert!(!filter.matches(&nfo));
+
+        // Subject fallback
+        let filter = FileFilter::new(&patterns(&["re:quoted"]), &[], false).unwrap();
+        assert!(filter.matches(&bare));
+    }
+
+    #[test]
+    fn test_selection_parse() {
+        let selection = FileSelection::parse("1, 4-7,9").unwrap();
+        assert!(selection.contains(1));
+        assert!(!selection.contains(2));
+        assert!(selection.contains(5));
+        assert!(selection.contains(9));
+        assert_eq!(selection.max(), 9);
+
+        for bad in ["", "0", "3-1", "a", "1-", "2,,x"] {
+            assert!(FileSelection::parse(bad).is_err(), "{:?}", bad);
+        }
+
+        let picked = FileSelection::from_indexes([2, 5]);
+        assert!(picked.contains(5) && !picked.contains(3));
+        assert_eq!(picked.max(), 5);
+    }
+
+    #[test]
+    fn test_selection_validated_against_file_count() {
+        let filter =
+            FileFilter::default().with_selection(Some(FileSelection::parse("2-4").unwrap()));
+        assert!(filter.validate_selection(4).is_ok());
+        let err = filter.validate_selection(3).unwrap_err();
+        assert!(
+            err.to_string().contains("index 4 is out of range"),
+            "{}",
+            err
+        );
+
+        let mut second = file(r#""b.rar""#);
+        second.index = 2;
+        assert!(filter.matches(&second));
+        assert!(!filter.matches(&file(r#""a.rar""#)));
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        let err = FileFilter::new(&patterns(&["re:("]), &[], false).unwrap_err();
+        assert!(err.to_string().contains("--only"), "{}", err);
+    }
+}
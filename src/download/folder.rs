@@ -0,0 +1,256 @@
+//! Output folder names from `download.folder_template`
+//!
+//! With `download.create_subfolders` on, each NZB downloads into a folder
+//! under `download.dir` named by the template, e.g. `{category}/{title}`.
+//! Every value filled in is sanitized like a poster-supplied filename, so it
+//! can't add folders of its own; the template's `/` are the only separators.
+//! A folder level left empty (no category, say) is dropped.
+//!
+//! `{year}` and `{month}` come from the newest post in the NZB, so archives
+//! sort by when things were posted (`{year}/{month}/{title}`); `{date}` is
+//! the day the download starts. Levels a run created but left empty are
+//! removed again by [`remove_empty_folders`].
+
+use chrono::{DateTime, NaiveDate};
+use std::path::{Path, PathBuf};
+
+use super::nzb::{sanitize_filename, Nzb};
+
+/// Placeholders a folder template may use
+pub const FOLDER_PLACEHOLDERS: &[&str] = &["title", "nzbname", "category", "date", "year", "month"];
+
+/// One folder per NZB, named after the NZB file
+pub const DEFAULT_FOLDER_TEMPLATE: &str = "{nzbname}";
+
+/// What a folder template's placeholders stand for
+#[derive(Debug, Clone)]
+pub struct FolderFields<'a> {
+    /// The NZB's name, without `.nzb`
+    pub nzbname: &'a str,
+    /// Title from the NZB metadata; `{title}` falls back to the name
+    pub title: Option<&'a str>,
+    /// Category from a sidecar or the NZB metadata
+    pub category: Option<&'a str>,
+    /// Day the download starts, for `{date}`
+    pub date: NaiveDate,
+    /// Day the newest file was posted, for `{year}` and `{month}`
+    pub posted: NaiveDate,
+}
+
+impl<'a> FolderFields<'a> {
+    /// Fields for `nzb` downloading today; a `category` given here (e.g. by
+    /// a sidecar) replaces the NZB's own
+    ///
+    /// An NZB without a usable post date counts as posted today.
+    pub fn new(nzbname: &'a str, nzb: &'a Nzb, category: Option<&'a str>) -> Self {
+        let date = chrono::Local::now().date_naive();
+        let posted = nzb
+            .newest_post_date()
+            .and_then(|date| DateTime::from_timestamp(i64::try_from(date).ok()?, 0))
+            .map(|time| time.with_timezone(&chrono::Local).date_naive())
+            .unwrap_or(date);
+        Self {
+            nzbname,
+            title: nzb.title(),
+            category: category.or(nzb.category()),
+            date,
+            posted,
+        }
+    }
+
+    /// Folder for these fields under `template`, relative to the download folder
+    ///
+    /// A template that comes out empty falls back to the NZB's name.
+    pub fn folder(&self, template: &str) -> PathBuf {
+        let folder: PathBuf = template
+            .split(['/', '\\'])
+            .map(|level| sanitize_filename(&self.fill(level)))
+            .filter(|level| !level.is_empty())
+            .collect();
+        if folder.as_os_str().is_empty() {
+            return PathBuf::from(sanitize_filename(self.nzbname));
+        }
+        folder
+    }
+
+    /// `level` with its placeholders filled in
+    fn fill(&self, level: &str) -> String {
+        let mut filled = String::new();
+        let mut rest = level;
+        while let Some((start, len)) = rest
+            .find('{')
+            .and_then(|start| Some((start, rest[start..].find('}')?)))
+        {
+            filled.push_str(&rest[..start]);
+            let value = match &rest[start + 1..start + len] {
+                "title" => self.title.unwrap_or(self.nzbname).to_string(),
+                "nzbname" => self.nzbname.to_string(),
+                "category" => self.category.unwrap_or_default().to_string(),
+                "date" => self.date.format("%Y-%m-%d").to_string(),
+                "year" => self.posted.format("%Y").to_string(),
+                "month" => self.posted.format("%m").to_string(),
+                // Rejected by check_folder_template before anything runs
+                _ => String::new(),
+            };
+            filled.push_str(&sanitize_filename(&value));
+            rest = &rest[start + len + 1..];
+        }
+        filled.push_str(rest);
+        filled
+    }
+}
+
+/// Remove `folder` and the folders between it and `base`, as long as
+/// they're empty
+///
+/// Undoes what creating a templated folder such as `2024/05/Title` made
+/// when a run left nothing in it. `base` itself stays, and so does every
+/// level with something in it.
+pub fn remove_empty_folders(base: &Path, folder: &Path) {
+    let base = std::fs::canonicalize(base).unwrap_or_else(|_| base.to_path_buf());
+    let mut dir = std::fs::canonicalize(folder).unwrap_or_else(|_| folder.to_path_buf());
+    let Ok(levels) = dir
+        .strip_prefix(&base)
+        .map(|rest| rest.components().count())
+    else {
+        return;
+    };
+    for _ in 0..levels {
+        if std::fs::remove_dir(&dir).is_err() {
+            break;
+        }
+        tracing::debug!("Removed empty folder {}", dir.display());
+        dir.pop();
+    }
+}
+
+/// Why `template` can't be used, if it can't: an unknown placeholder or an
+/// unclosed brace
+pub fn check_folder_template(template: &str) -> Result<(), String> {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(len) = rest[start..].find('}') else {
+            return Err(format!("'{}' has a '{{' without a '}}'", template));
+        };
+        let name = &rest[start + 1..start + len];
+        if !FOLDER_PLACEHOLDERS.contains(&name) {
+            return Err(format!(
+                "Unknown placeholder '{{{}}}' (known: {})",
+                name,
+                FOLDER_PLACEHOLDERS
+                    .iter()
+                    .map(|p| format!("{{{}}}", p))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+        rest = &rest[start + len + 1..];
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn fields<'a>(title: Option<&'a str>, category: Option<&'a str>) -> FolderFields<'a> {
+        FolderFields {
+            nzbname: "Some.Release.2024",
+            title,
+            category,
+            date: NaiveDate::from_ymd_opt(2026, 3, 9).unwrap(),
+            posted: NaiveDate::from_ymd_opt(2024, 5, 17).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_nested_templates() {
+        let full = fields(Some("Some Release"), Some("movies"));
+        assert_eq!(
+            full.folder(DEFAULT_FOLDER_TEMPLATE),
+            Path::new("Some.Release.2024")
+        );
+        assert_eq!(
+            full.folder("{category}/{title}"),
+            Path::new("movies/Some Release")
+        );
+        assert_eq!(
+            full.folder("{year}/{date} - {nzbname}"),
+            Path::new("2024/2026-03-09 - Some.Release.2024")
+        );
+        assert_eq!(
+            full.folder("{year}/{month}/{title}"),
+            Path::new("2024/05/Some Release")
+        );
+    }
+
+    #[test]
+    fn test_missing_metadata_falls_back() {
+        let bare = fields(None, None);
+        // No title means the name; no category drops its level
+        assert_eq!(
+            bare.folder("{category}/{title}"),
+            Path::new("Some.Release.2024")
+        );
+        assert_eq!(
+            bare.folder("{category}/{year}/{nzbname}"),
+            Path::new("2024/Some.Release.2024")
+        );
+        assert_eq!(bare.folder("{category}"), Path::new("Some.Release.2024"));
+    }
+
+    #[test]
+    fn test_values_cannot_add_levels() {
+        let sneaky = fields(Some("../../etc"), Some("a/b"));
+        assert_eq!(
+            sneaky.folder("{category}/{title}"),
+            Path::new("a_b/.._.._etc")
+        );
+        // Nor can the template climb out of the download folder
+        assert_eq!(sneaky.folder("/../{category}"), Path::new("a_b"));
+    }
+
+    #[test]
+    fn test_empty_template_levels_are_removed() {
+        let base = tempfile::tempdir().unwrap();
+        let folder = base
+            .path()
+            .join(fields(None, None).folder("{year}/{month}/{nzbname}"));
+        std::fs::create_dir_all(&folder).unwrap();
+        // A sibling from another run keeps the year
+        std::fs::create_dir_all(base.path().join("2024/04/Other")).unwrap();
+
+        remove_empty_folders(base.path(), &folder);
+        assert!(!base.path().join("2024/05").exists());
+        assert!(base.path().join("2024/04/Other").is_dir());
+
+        // A run that left a file behind keeps its folders
+        std::fs::create_dir_all(&folder).unwrap();
+        std::fs::write(folder.join("a.rar"), b"data").unwrap();
+        remove_empty_folders(base.path(), &folder);
+        assert!(folder.join("a.rar").is_file());
+
+        // Nothing at all left: everything up to the base goes, the base stays
+        std::fs::remove_dir_all(base.path().join("2024")).unwrap();
+        std::fs::create_dir_all(&folder).unwrap();
+        remove_empty_folders(base.path(), &folder);
+        assert!(!base.path().join("2024").exists());
+        assert!(base.path().is_dir());
+        remove_empty_folders(base.path(), base.path());
+        assert!(base.path().is_dir());
+    }
+
+    #[test]
+    fn test_check_folder_template() {
+        assert!(check_folder_template("{category}/{title}").is_ok());
+        assert!(check_folder_template("plain").is_ok());
+        let unknown = check_folder_template("{yyyy-mm}/{nzbname}").unwrap_err();
+        assert!(
+            unknown.starts_with("Unknown placeholder '{yyyy-mm}'"),
+            "{}",
+            unknown
+        );
+        assert!(check_folder_template("{title").is_err());
+    }
+}
@@ -0,0 +1,127 @@
+//! Newsgroups the servers don't carry
+//!
+//! Every article is fetched after a `GROUP` for the file's first newsgroup,
+//! so a group the server doesn't have (`411`) fails every segment of the
+//! file as missing, just as expired articles would. Before downloading, the
+//! downloader selects each newsgroup the files are posted to once, on the
+//! primary server and then on the others until one carries it. A file also
+//! posted to a carried group is fetched through that one ([`reroute`]); a
+//! file whose groups are all missing ([`only_in_missing`]) is flagged, and
+//! with `download.skip_unavailable_groups` skipped.
+
+use std::collections::BTreeSet;
+
+use super::NzbFile;
+
+/// The newsgroups `files` are posted to, each once, in order of first use
+pub(super) fn groups_of(files: &[&NzbFile]) -> Vec<String> {
+    let mut seen = BTreeSet::new();
+    files
+        .iter()
+        .flat_map(|file| &file.groups.group)
+        .filter(|group| seen.insert(group.name.as_str()))
+        .map(|group| group.name.clone())
+        .collect()
+}
+
+/// Whether every newsgroup `file` is posted to is in `missing`
+pub(super) fn only_in_missing(file: &NzbFile, missing: &BTreeSet<String>) -> bool {
+    !file.groups.group.is_empty()
+        && file
+            .groups
+            .group
+            .iter()
+            .all(|group| missing.contains(&group.name))
+}
+
+/// `file` without its missing newsgroups, when it's posted to a carried one
+/// too and a missing one would be used; `None` when nothing changes
+pub(super) fn reroute(file: &NzbFile, missing: &BTreeSet<String>) -> Option<NzbFile> {
+    let first = file.groups.group.first()?;
+    if !missing.contains(&first.name) || only_in_missing(file, missing) {
+        return None;
+    }
+    let mut rerouted = file.clone();
+    rerouted
+        .groups
+        .group
+        .retain(|group| !missing.contains(&group.name));
+    Some(rerouted)
+}
+
+/// One line per missing group: "group alt.binaries.obscure not on server: 3 files"
+pub(super) fn describe(missing: &BTreeSet<String>, files: &[&NzbFile]) -> Vec<String> {
+    missing
+        .iter()
+        .map(|name| {
+            let count = files
+                .iter()
+                .filter(|file| {
+                    only_in_missing(file, missing)
+                        && file.groups.group.iter().any(|group| &group.name == name)
+                })
+                .count();
+            format!(
+                "group {} not on server: {} file{}",
+                name,
+                count,
+                if count == 1 { "" } else { "s" }
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::download::Nzb;
+
+    fn nzb() -> Nzb {
+        let file = |name: &str, groups: &[&str]| {
+            let groups: String = groups
+                .iter()
+                .map(|g| format!("<group>{}</group>", g))
+                .collect();
+            format!(
+                r#"<file poster="p" date="0" subject="&quot;{}&quot; yEnc (1/1)">
+                <groups>{}</groups>
+                <segments><segment bytes="10" number="1">{}@x</segment></segments></file>"#,
+                name, groups, name
+            )
+        };
+        format!(
+            "<nzb>{}{}{}</nzb>",
+            file("a.rar", &["alt.binaries.obscure"]),
+            file("b.rar", &["alt.binaries.obscure", "alt.binaries.test"]),
+            file("c.rar", &["alt.binaries.test"]),
+        )
+        .parse()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_missing_groups() {
+        let nzb = nzb();
+        let files: Vec<&NzbFile> = nzb.files().iter().collect();
+        assert_eq!(
+            groups_of(&files),
+            ["alt.binaries.obscure", "alt.binaries.test"]
+        );
+
+        let missing = BTreeSet::from(["alt.binaries.obscure".to_string()]);
+        let flagged: Vec<bool> = files.iter().map(|f| only_in_missing(f, &missing)).collect();
+        assert_eq!(flagged, [true, false, false]);
+
+        // Only the file also in a carried group changes
+        assert!(reroute(files[0], &missing).is_none());
+        let rerouted = reroute(files[1], &missing).unwrap();
+        assert_eq!(rerouted.groups.group.len(), 1);
+        assert_eq!(rerouted.groups.group[0].name, "alt.binaries.test");
+        assert!(reroute(files[2], &missing).is_none());
+
+        assert_eq!(
+            describe(&missing, &files),
+            ["group alt.binaries.obscure not on server: 1 file"]
+        );
+    }
+}
@@ -0,0 +1,139 @@
+//! Changing the speed and connection limits while a run downloads
+//!
+//! [`Limits`] is the run's handle on its [`Throttle`] and the primary
+//! server's [`ConnectionLimit`]: SIGUSR1 toggles the speed cap off and on
+//! through it, `POST /limits` sets either, and `GET /status` and the progress
+//! bar show what it holds. Transfers in flight carry on: a new speed applies
+//! from their next segment, a new connection count as batches start and end.
+
+use human_bytes::human_bytes;
+use serde::Serialize;
+
+use super::connections::ConnectionLimit;
+use super::throttle::Throttle;
+use crate::nntp::NntpPool;
+
+/// The limits of a running downloader
+#[derive(Clone)]
+pub struct Limits {
+    throttle: Throttle,
+    /// The primary server's limit and pool, resized along with it
+    connections: Option<(ConnectionLimit, NntpPool)>,
+}
+
+/// Limits in force, as `GET /status` and `POST /limits` report them
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LimitsSnapshot {
+    /// Bytes per second (0 = unlimited)
+    pub max_speed: u64,
+    /// The limit from the config or schedule, which an override replaces
+    pub configured_speed: u64,
+    pub speed_overridden: bool,
+    pub connections: Option<usize>,
+    pub max_connections: Option<usize>,
+    pub connections_pinned: bool,
+}
+
+impl Limits {
+    pub fn new(throttle: Throttle, connections: Option<(ConnectionLimit, NntpPool)>) -> Self {
+        Self {
+            throttle,
+            connections,
+        }
+    }
+
+    pub fn throttle(&self) -> &Throttle {
+        &self.throttle
+    }
+
+    /// Override the speed limit (0 = unlimited), or go back to the
+    /// configured one with `None`
+    pub fn set_max_speed(&self, limit: Option<u64>) {
+        self.throttle.override_limit(limit);
+    }
+
+    /// Download with `connections` at once (held to the server's
+    /// `connections`), from the next batch on; `false` when there is no
+    /// limit to change
+    pub fn set_connections(&self, connections: usize) -> bool {
+        let Some((limit, pool)) = &self.connections else {
+            return false;
+        };
+        limit.pin(connections);
+        // Idle connections past the limit are closed, and a pool shrunk by
+        // auto-tuning grows back
+        pool.resize(limit.limit());
+        true
+    }
+
+    /// Switch between the configured speed cap and unlimited, for SIGUSR1
+    ///
+    /// Returns the note logged for it.
+    pub fn toggle_turbo(&self) -> String {
+        if self.throttle.is_overridden() {
+            return match self.throttle.toggle_unlimited() {
+                0 => "Turbo off: back to unlimited".to_string(),
+                limit => format!("Turbo off: back to {}/s", human_bytes(limit as f64)),
+            };
+        }
+        let configured = self.throttle.configured();
+        if configured == 0 {
+            return "Turbo: no speed limit is configured".to_string();
+        }
+        self.throttle.toggle_unlimited();
+        format!(
+            "Turbo on: unlimited (configured {}/s)",
+            human_bytes(configured as f64)
+        )
+    }
+
+    pub fn snapshot(&self) -> LimitsSnapshot {
+        let connections = self.connections.as_ref().map(|(limit, _)| limit);
+        LimitsSnapshot {
+            max_speed: self.throttle.limit(),
+            configured_speed: self.throttle.configured(),
+            speed_overridden: self.throttle.is_overridden(),
+            connections: connections.map(ConnectionLimit::limit),
+            max_connections: connections.map(ConnectionLimit::max),
+            connections_pinned: connections.is_some_and(ConnectionLimit::is_pinned),
+        }
+    }
+
+    /// Progress note for the limits in force, e.g. "limited to 5 MiB/s by
+    /// schedule · 8 connections"; connections show once set at runtime
+    pub fn status(&self) -> Option<String> {
+        let connections = self
+            .connections
+            .as_ref()
+            .filter(|(limit, _)| limit.is_pinned())
+            .map(|(limit, _)| format!("{} connections", limit.limit()));
+        match (self.throttle.status(), connections) {
+            (Some(speed), Some(connections)) => Some(format!("{} · {}", speed, connections)),
+            (speed, connections) => speed.or(connections),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_turbo_toggles_the_configured_cap() {
+        let limits = Limits::new(Throttle::new(0), None);
+        assert_eq!(limits.toggle_turbo(), "Turbo: no speed limit is configured");
+
+        limits.throttle().set_limit(5 << 20, false);
+        assert!(limits.toggle_turbo().starts_with("Turbo on"));
+        let snapshot = limits.snapshot();
+        assert_eq!(snapshot.max_speed, 0);
+        assert_eq!(snapshot.configured_speed, 5 << 20);
+        assert!(snapshot.speed_overridden);
+        assert_eq!(limits.status().as_deref(), Some("unlimited by override"));
+
+        assert_eq!(limits.toggle_turbo(), "Turbo off: back to 5 MiB/s");
+        assert_eq!(limits.snapshot().max_speed, 5 << 20);
+        assert!(!limits.set_connections(4));
+        assert_eq!(limits.snapshot().connections, None);
+    }
+}
@@ -0,0 +1,166 @@
+//! One dl-nzb at a time per folder
+//!
+//! Two instances writing the same output folder (or watching the same
+//! folder) corrupt each other's files, so each takes a [`DirLock`] on it
+//! first. On Unix the lock is an advisory `flock` on a [`LOCK_FILE`], which
+//! the kernel drops with the process, so a crashed holder never leaves it
+//! stuck. Elsewhere the lock file itself is the lock: it's created
+//! exclusively, holds the owner's PID, and is broken once that process is
+//! gone. Either way the file records the holder's PID for the error message.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Lock file kept in a locked folder for as long as it's held
+pub const LOCK_FILE: &str = ".dlnzb.lock";
+
+/// A held lock on a folder, released when dropped
+///
+/// Drop also runs while a panic unwinds; with `panic = "abort"` the process
+/// ends instead, which releases a `flock` and leaves a PID file stale.
+#[derive(Debug)]
+pub struct DirLock {
+    path: PathBuf,
+    _file: File,
+}
+
+impl DirLock {
+    /// Lock `dir`, or `None` if another process holds it
+    ///
+    /// The folder has to exist. Errors are those of the lock file itself,
+    /// e.g. a folder that can't be written to.
+    pub fn try_acquire(dir: &Path) -> std::io::Result<Option<Self>> {
+        let path = dir.join(LOCK_FILE);
+        let Some(mut file) = acquire(&path)? else {
+            return Ok(None);
+        };
+        file.set_len(0)?;
+        writeln!(file, "{}", std::process::id())?;
+        Ok(Some(Self { path, _file: file }))
+    }
+
+    /// PID of whoever holds or last held the lock on `dir`, if recorded
+    pub fn holder(dir: &Path) -> Option<u32> {
+        read_pid(&dir.join(LOCK_FILE))
+    }
+}
+
+impl Drop for DirLock {
+    /// The file goes while still locked, so a waiter that opened it
+    /// meanwhile notices and locks a fresh one instead
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                tracing::debug!("Failed to remove {}: {}", self.path.display(), e);
+            }
+        }
+    }
+}
+
+fn read_pid(path: &Path) -> Option<u32> {
+    std::fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+#[cfg(unix)]
+fn acquire(path: &Path) -> std::io::Result<Option<File>> {
+    use fs2::FileExt;
+    use std::os::unix::fs::MetadataExt;
+
+    loop {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        if let Err(e) = file.try_lock_exclusive() {
+            if e.raw_os_error() == fs2::lock_contended_error().raw_os_error() {
+                return Ok(None);
+            }
+            return Err(e);
+        }
+        // The previous holder may have removed the file between our open and
+        // lock, leaving us holding one nobody else can find
+        let ours = file.metadata()?;
+        match std::fs::metadata(path) {
+            Ok(now) if now.dev() == ours.dev() && now.ino() == ours.ino() => return Ok(Some(file)),
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn acquire(path: &Path) -> std::io::Result<Option<File>> {
+    loop {
+        match OpenOptions::new().write(true).create_new(true).open(path) {
+            Ok(file) => return Ok(Some(file)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(e),
+        }
+        // A file without a PID yet is still being written by its owner
+        match read_pid(path) {
+            Some(pid) if !process_alive(pid) => {
+                tracing::warn!(
+                    "Removing {} left behind by pid {}, which is no longer running",
+                    path.display(),
+                    pid
+                );
+                if let Err(e) = std::fs::remove_file(path) {
+                    if e.kind() != std::io::ErrorKind::NotFound {
+                        return Err(e);
+                    }
+                }
+            }
+            _ => return Ok(None),
+        }
+    }
+}
+
+#[cfg(windows)]
+fn process_alive(pid: u32) -> bool {
+    let filter = format!("PID eq {}", pid);
+    match std::process::Command::new("tasklist")
+        .args(["/FI", &filter, "/NH", "/FO", "CSV"])
+        .output()
+    {
+        Ok(out) => String::from_utf8_lossy(&out.stdout).contains(&format!("\"{}\"", pid)),
+        // Without a way to tell, the lock is left alone
+        Err(_) => true,
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn process_alive(_pid: u32) -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_second_lock_is_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock = DirLock::try_acquire(dir.path()).unwrap().unwrap();
+        assert!(DirLock::try_acquire(dir.path()).unwrap().is_none());
+        assert_eq!(DirLock::holder(dir.path()), Some(std::process::id()));
+
+        drop(lock);
+        assert!(!dir.path().join(LOCK_FILE).exists());
+        assert!(DirLock::try_acquire(dir.path()).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_stale_lock_is_broken() {
+        let dir = tempfile::tempdir().unwrap();
+        // Left by a process that crashed; no PID is ever this large
+        std::fs::write(dir.path().join(LOCK_FILE), format!("{}\n", u32::MAX)).unwrap();
+
+        let lock = DirLock::try_acquire(dir.path()).unwrap();
+        assert!(lock.is_some());
+        assert_eq!(DirLock::holder(dir.path()), Some(std::process::id()));
+    }
+}
@@ -0,0 +1,231 @@
+//! Global budget for decoded segment data
+//!
+//! Every connection decodes into memory that stays allocated until the file's
+//! writer has written it, so the budget is one weighted semaphore shared by
+//! the whole downloader, counted in KiB. A batch reserves what its segments
+//! are declared to take before it asks for them, and a file's batches reserve
+//! in segment order on a fair semaphore, so the segments a writer is holding
+//! back for an earlier gap never starve the batch that fills it. Each decoded
+//! segment then takes its share of the reservation and carries it inside its
+//! [`Bytes`], giving it back when the last copy is written or dropped.
+
+use bytes::Bytes;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Size of one permit
+const UNIT: u64 = 1024;
+
+/// Memory shared by every connection of a downloader
+#[derive(Clone)]
+pub struct MemoryBudget {
+    semaphore: Arc<Semaphore>,
+    /// Permits the budget was created with
+    capacity: u32,
+    usage: Arc<Usage>,
+}
+
+/// Bytes of segment data alive right now, and the most there have been
+#[derive(Default)]
+struct Usage {
+    in_use: AtomicU64,
+    peak: AtomicU64,
+}
+
+impl MemoryBudget {
+    /// A budget of `bytes` (at least one KiB)
+    pub fn new(bytes: u64) -> Self {
+        let capacity = units(bytes).clamp(1, u32::MAX as u64) as u32;
+        Self {
+            semaphore: Arc::new(Semaphore::new(capacity as usize)),
+            capacity,
+            usage: Arc::default(),
+        }
+    }
+
+    /// The cap in bytes
+    pub fn capacity(&self) -> u64 {
+        self.capacity as u64 * UNIT
+    }
+
+    /// Bytes of decoded segments that haven't been written or dropped yet
+    pub fn in_use(&self) -> u64 {
+        self.usage.in_use.load(Ordering::Relaxed)
+    }
+
+    /// The most [`in_use`](Self::in_use) has been
+    pub fn peak(&self) -> u64 {
+        self.usage.peak.load(Ordering::Relaxed)
+    }
+
+    /// Bytes reserved by batches, whether or not their data has arrived
+    pub fn reserved(&self) -> u64 {
+        (self.capacity as u64 - self.semaphore.available_permits() as u64) * UNIT
+    }
+
+    /// Wait until `bytes` are free and set them aside
+    ///
+    /// A request larger than the whole budget gets the whole budget, so it
+    /// runs alone instead of waiting forever.
+    pub async fn reserve(&self, bytes: u64) -> Reservation {
+        let wanted = units(bytes).min(self.capacity as u64) as u32;
+        let permit = if wanted == 0 {
+            None
+        } else {
+            self.semaphore.clone().acquire_many_owned(wanted).await.ok()
+        };
+        Reservation {
+            budget: self.clone(),
+            permit: Mutex::new(permit),
+        }
+    }
+}
+
+/// Memory set aside for one batch; what its segments don't take is given
+/// back when it's dropped
+pub struct Reservation {
+    budget: MemoryBudget,
+    permit: Mutex<Option<OwnedSemaphorePermit>>,
+}
+
+impl Reservation {
+    /// Decode a segment of at most `len` bytes into memory from this reservation
+    ///
+    /// The permits are taken before `decode` runs. A segment bigger than what
+    /// is left takes the rest from the budget if it is free right now; if not
+    /// it goes over the cap rather than wait on a batch that may be waiting
+    /// on it.
+    pub fn hold(&self, len: usize, decode: impl FnOnce() -> Vec<u8>) -> Bytes {
+        let permit = self.take(units(len as u64).min(u32::MAX as u64) as u32);
+        let data = decode();
+        let usage = self.budget.usage.clone();
+        let size = data.capacity() as u64;
+        let in_use = usage.in_use.fetch_add(size, Ordering::Relaxed) + size;
+        usage.peak.fetch_max(in_use, Ordering::Relaxed);
+        Bytes::from_owner(Held {
+            data,
+            _permit: permit,
+            usage,
+        })
+    }
+
+    fn take(&self, wanted: u32) -> Option<OwnedSemaphorePermit> {
+        if wanted == 0 {
+            return None;
+        }
+        let mut reserved = self.permit.lock().unwrap();
+        if let Some(permit) = reserved.as_mut().and_then(|p| p.split(wanted as usize)) {
+            return Some(permit);
+        }
+        let mut permit = reserved.take();
+        let have = permit.as_ref().map_or(0, |p| p.num_permits()) as u32;
+        match self
+            .budget
+            .semaphore
+            .clone()
+            .try_acquire_many_owned(wanted - have)
+        {
+            Ok(extra) => match &mut permit {
+                Some(permit) => permit.merge(extra),
+                None => permit = Some(extra),
+            },
+            Err(_) => tracing::debug!(
+                "Segment of {} KiB is over the memory budget ({} KiB reserved)",
+                wanted,
+                have
+            ),
+        }
+        permit
+    }
+}
+
+/// Decoded segment data and the budget it holds
+struct Held {
+    data: Vec<u8>,
+    _permit: Option<OwnedSemaphorePermit>,
+    usage: Arc<Usage>,
+}
+
+impl AsRef<[u8]> for Held {
+    fn as_ref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl Drop for Held {
+    fn drop(&mut self) {
+        self.usage
+            .in_use
+            .fetch_sub(self.data.capacity() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Permits covering `bytes`
+fn units(bytes: u64) -> u64 {
+    bytes.div_ceil(UNIT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(len: usize) -> impl FnOnce() -> Vec<u8> {
+        move || Vec::with_capacity(len)
+    }
+
+    #[tokio::test]
+    async fn test_segments_hold_the_budget_until_dropped() {
+        let budget = MemoryBudget::new(64 * 1024);
+        let reservation = budget.reserve(32 * 1024).await;
+        assert_eq!(budget.reserved(), 32 * 1024);
+
+        let first = reservation.hold(16 * 1024, segment(16 * 1024));
+        let second = reservation.hold(16 * 1024, segment(16 * 1024));
+        assert_eq!(budget.in_use(), 32 * 1024);
+
+        // The segments keep their share after the batch is done
+        drop(reservation);
+        assert_eq!(budget.reserved(), 32 * 1024);
+        let copy = first.clone();
+        drop(first);
+        assert_eq!(budget.in_use(), 32 * 1024);
+        drop(copy);
+        drop(second);
+        assert_eq!(budget.in_use(), 0);
+        assert_eq!(budget.reserved(), 0);
+        assert_eq!(budget.peak(), 32 * 1024);
+    }
+
+    #[tokio::test]
+    async fn test_reservations_wait_for_room() {
+        let budget = MemoryBudget::new(10 * 1024);
+        let first = budget.reserve(8 * 1024).await;
+
+        let waiting = tokio::spawn({
+            let budget = budget.clone();
+            async move { budget.reserve(4 * 1024).await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!waiting.is_finished());
+
+        drop(first);
+        waiting.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_oversized_requests_are_capped() {
+        let budget = MemoryBudget::new(4 * 1024);
+        // Bigger than the budget: takes all of it instead of never starting
+        let reservation = budget.reserve(1 << 30).await;
+        assert_eq!(budget.reserved(), 4 * 1024);
+
+        // A segment bigger than its reservation tops up from what is free
+        let small = budget.reserve(0).await;
+        drop(reservation);
+        let data = small.hold(2 * 1024, segment(2 * 1024));
+        assert_eq!(budget.reserved(), 2 * 1024);
+        drop(data);
+        assert_eq!(budget.reserved(), 0);
+    }
+}
@@ -3,8 +3,78 @@
 //! This module provides the core download functionality including NZB parsing,
 //! segment downloading, and file assembly.
 
+mod age;
+mod breaker;
+mod budget;
+mod compression;
+mod connections;
+mod disk_gate;
 mod downloader;
+mod events;
+mod filter;
+mod folder;
+mod groups;
+mod limits;
+mod lock;
+mod memory;
+mod name_map;
 mod nzb;
+mod nzb_stream;
+pub(crate) mod partial;
+mod permissions;
+mod self_test;
+pub(crate) mod sizes;
+mod skip;
+mod source;
+mod speed;
+mod stall;
+mod subject;
+mod temp;
+mod throttle;
+mod validate;
+mod writer;
 
-pub use downloader::{DownloadResult, Downloader};
-pub use nzb::Nzb;
+pub use age::{format_age, post_age_days};
+pub use breaker::MissingBreaker;
+pub use budget::{BudgetClock, RetryBudget};
+pub use compression::{Compression, MAX_DECOMPRESSED_SIZE};
+pub use connections::{AutoTune, ConnectionLimit, ConnectionPermit};
+pub use disk_gate::{DiskGate, DiskHold, WriterSlot};
+pub use downloader::{
+    download_order, AbortedDownload, Clock, DownloadResult, Downloader, DownloaderBuilder,
+    NzbDownload, SegmentSpan, ServerMetrics, ServerProbe, ServerStats, StatsHandle,
+};
+pub use events::{DownloadEvent, Events, PostProcessPhase};
+pub(crate) use filter::compile_pattern;
+pub use filter::{FileFilter, FileSelection};
+pub use folder::{
+    check_folder_template, remove_empty_folders, FolderFields, DEFAULT_FOLDER_TEMPLATE,
+    FOLDER_PLACEHOLDERS,
+};
+pub use limits::{Limits, LimitsSnapshot};
+pub use lock::{DirLock, LOCK_FILE};
+pub use memory::{MemoryBudget, Reservation};
+pub use name_map::NameMap;
+pub use nzb::{Nzb, NzbFile, Par2Coverage, Par2Kind, Par2Set, STREAMING_THRESHOLD};
+pub use partial::{PartialFiles, FAILED_MARKER};
+pub use permissions::Permissions;
+pub use self_test::self_test;
+pub use sizes::{SegmentSizes, SEGMENTS_FILE, SIZE_TOLERANCE_PERCENT};
+pub use skip::{
+    below_min_size, breakdown as skip_breakdown, check_existing, screen_files, ExistingFile,
+    QuickHash, SkipReason, SkipVerdict, SkippedFile, HASHES_FILE,
+};
+pub(crate) use source::serialize_nzb_path;
+pub use source::{
+    expand_inputs, is_nzb_name, move_nzb, move_processed, nested_nzbs, redact_filename_password,
+    redact_nzb_path, split_filename_password, LoadedNzb, NzbSource, FETCH_TIMEOUT, MAX_NZB_SIZE,
+    PROCESSED_DIR,
+};
+pub use speed::{SpeedSample, SpeedStats, SpeedTimeline};
+pub use stall::StallWatch;
+pub use temp::{
+    check_writable, cleanup_stale_temp_dirs, nzb_temp_dir, prepare_temp_dir, same_filesystem,
+    sync_dir,
+};
+pub use throttle::{active_limit, parse_time_of_day, Throttle};
+pub use validate::{FileReport, NzbReport};
@@ -0,0 +1,188 @@
+//! Output names given per file (`--name-map`)
+//!
+//! The map is a JSON object from a file's 1-based position in the NZB (as
+//! numbered by list mode) or its exact subject to the name to write it under:
+//!
+//! ```json
+//! { "1": "Show.S01E01.mkv", "a8f3e1b2c4d5 yEnc (1/40)": "Show.S01E01.nfo" }
+//! ```
+//!
+//! A mapped file skips subject parsing and deobfuscation, though its name is
+//! still made safe to write. Names are checked when the map is loaded (none
+//! empty, no two the same) and keys when it's applied to an NZB (each names
+//! one file, no file twice).
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use super::nzb::{sanitize_filename, NzbFile};
+use crate::error::{ConfigError, DlNzbError};
+
+type Result<T> = std::result::Result<T, DlNzbError>;
+
+/// Which file a key names
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Key {
+    Index(usize),
+    Subject(String),
+}
+
+/// Output names for files of an NZB, by position or subject
+#[derive(Debug, Clone, Default)]
+pub struct NameMap {
+    /// Key as written, what it names, and the name made safe
+    entries: Vec<(String, Key, String)>,
+}
+
+impl NameMap {
+    /// Read a map from a JSON file
+    pub fn load(path: &Path) -> Result<Self> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| invalid(format!("can't read {}: {}", path.display(), e)))?;
+        Self::parse(&json)
+    }
+
+    /// Parse a map from JSON, checking its names
+    pub fn parse(json: &str) -> Result<Self> {
+        let raw: BTreeMap<String, String> = serde_json::from_str(json)
+            .map_err(|e| invalid(format!("not a JSON object of names: {}", e)))?;
+        let mut entries = Vec::with_capacity(raw.len());
+        let mut taken: HashMap<String, String> = HashMap::new();
+        for (key, name) in raw {
+            let safe = sanitize_filename(&name);
+            if safe.is_empty() {
+                return Err(invalid(format!(
+                    "{:?} maps to an unusable name {:?}",
+                    key, name
+                )));
+            }
+            if let Some(other) = taken.insert(safe.clone(), key.clone()) {
+                return Err(invalid(format!(
+                    "{:?} and {:?} both map to {:?}",
+                    other, key, safe
+                )));
+            }
+            let target = match key.parse::<usize>() {
+                Ok(0) => return Err(invalid("file positions start at 1".to_string())),
+                Ok(index) => Key::Index(index),
+                Err(_) => Key::Subject(key.clone()),
+            };
+            entries.push((key, target, safe));
+        }
+        Ok(Self { entries })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The name for each file `index` the map names, among `files` (every
+    /// file of the NZB, filtered or not)
+    ///
+    /// Errors for a key that names no file or a file named twice, and for a
+    /// mapped name another file would be written under anyway.
+    pub(super) fn resolve(&self, files: &[&NzbFile]) -> Result<BTreeMap<usize, String>> {
+        let mut names = BTreeMap::new();
+        let mut keys: BTreeMap<usize, &str> = BTreeMap::new();
+        for (key, target, name) in &self.entries {
+            let file = files.iter().find(|file| match target {
+                Key::Index(index) => file.index == *index,
+                Key::Subject(subject) => &file.subject == subject,
+            });
+            let Some(file) = file else {
+                return Err(invalid(format!("{:?} matches no file in the NZB", key)));
+            };
+            if let Some(other) = keys.insert(file.index, key) {
+                return Err(invalid(format!(
+                    "{:?} and {:?} both name file {}",
+                    other, key, file.index
+                )));
+            }
+            names.insert(file.index, name.clone());
+        }
+        for file in files.iter().filter(|file| !names.contains_key(&file.index)) {
+            let derived = file.filename();
+            if let Some((index, _)) = names.iter().find(|(_, name)| **name == derived) {
+                return Err(invalid(format!(
+                    "file {} is mapped to {:?}, which file {} is already called",
+                    index, derived, file.index
+                )));
+            }
+        }
+        Ok(names)
+    }
+}
+
+fn invalid(reason: String) -> DlNzbError {
+    ConfigError::Invalid {
+        field: "--name-map".to_string(),
+        reason,
+    }
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::download::Nzb;
+
+    fn nzb() -> Nzb {
+        let file = |subject: &str, id: &str| {
+            format!(
+                r#"<file poster="p" date="0" subject="{}">
+                <groups><group>alt.binaries.test</group></groups>
+                <segments><segment bytes="10" number="1">{}@x</segment></segments></file>"#,
+                subject, id
+            )
+        };
+        format!(
+            "<nzb>{}{}{}</nzb>",
+            file("&quot;a8f3e1.rar&quot; yEnc (1/1)", "a"),
+            file("b7c2d9 yEnc (1/1)", "b"),
+            file("&quot;show.nfo&quot; yEnc (1/1)", "c"),
+        )
+        .parse()
+        .unwrap()
+    }
+
+    fn resolve(json: &str) -> Result<BTreeMap<usize, String>> {
+        let nzb = nzb();
+        let files: Vec<&NzbFile> = nzb.files().iter().collect();
+        NameMap::parse(json)?.resolve(&files)
+    }
+
+    #[test]
+    fn test_resolve_by_index_and_subject() {
+        // Files sort by subject: a8f3e1.rar, show.nfo, then b7c2d9
+        let names = resolve(r#"{"1": "Show.rar", "b7c2d9 yEnc (1/1)": "../Show.mkv"}"#).unwrap();
+        assert_eq!(
+            names,
+            BTreeMap::from([
+                (1, "Show.rar".to_string()),
+                // Still made safe to write
+                (3, ".._Show.mkv".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_bad_maps_are_refused() {
+        for json in [
+            "[]",
+            r#"{"1": ""}"#,
+            r#"{"0": "a.mkv"}"#,
+            r#"{"1": "a.mkv", "2": "a.mkv"}"#,
+        ] {
+            assert!(NameMap::parse(json).is_err(), "{}", json);
+        }
+        for json in [
+            r#"{"4": "a.mkv"}"#,
+            r#"{"no such subject": "a.mkv"}"#,
+            r#"{"1": "a.mkv", "\"a8f3e1.rar\" yEnc (1/1)": "b.mkv"}"#,
+            // File 2 is show.nfo already
+            r#"{"1": "show.nfo"}"#,
+        ] {
+            assert!(resolve(json).is_err(), "{}", json);
+        }
+    }
+}
@@ -1,12 +1,55 @@
+use md5::{Digest, Md5};
 pub use nzb_rs::Nzb as NzbRs;
+use once_cell::sync::Lazy;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+use std::io::Read;
 use std::path::Path;
 use std::str::FromStr;
+use std::time::SystemTime;
 
+use super::age::post_age_days;
+use super::compression;
+use super::downloader::DownloadResult;
+use super::filter::FileFilter;
+use super::name_map::NameMap;
+use super::nzb_stream::{self, ParsedNzb};
+use super::skip::{SkipReason, SkippedFile};
+use super::source::redact_nzb_path;
+use super::validate::part_count;
 use crate::error::{DlNzbError, NzbError};
+use crate::patterns::par2 as par2_patterns;
 
 type Result<T> = std::result::Result<T, DlNzbError>;
 
+/// Prologue of every NZB written out, as the 1.1 DTD has it
+const XML_PROLOGUE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE nzb PUBLIC "-//newzBin//DTD NZB 1.1//EN" "http://www.newzbin.com/DTD/nzb/nzb-1.1.dtd">
+<nzb xmlns="http://www.newzbin.com/DTD/2003/nzb">
+"#;
+
+/// `name.volAA+BB.par2`, or `name.volAA-BB.par2` as some posters write it
+static PAR2_VOLUME: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)^(.*?)\.vol(\d+)([+-])(\d+)\.par2$").expect("valid regex"));
+
+/// A bare `something.par2` word in a subject that has no quoted file name
+static PAR2_IN_SUBJECT: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)(?:^|\s)([^\s\[\]]+\.par2)(?:\s|$)").expect("valid regex"));
+
+/// A subject that ends in a bare `par2` word after the release name and an
+/// optional part counter: `release [01/45] par2`, `release - PAR2 yEnc (1/3)`.
+/// A name ending in `]` would be a counter or tag, not a release.
+static PAR2_WORD_IN_SUBJECT: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)^\s*(.*?[^\s\]])(?:\s*\[\d+/\d+\])?(?:\s+-)?\s+par2(?:\s+yEnc)?(?:\s*\(\d+/\d+\))?\s*$")
+        .expect("valid regex")
+});
+
+/// NZB files larger than this on disk are parsed as a stream rather than
+/// read into memory whole (compressed ones expand to several times this)
+pub const STREAMING_THRESHOLD: u64 = 16 * 1024 * 1024;
+
 // Re-export types for compatibility with existing code
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NzbSegment {
@@ -22,11 +65,236 @@ pub struct NzbGroup {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NzbFile {
+    /// 1-based position in the NZB document, kept when files are filtered
+    #[serde(default)]
+    pub index: usize,
     pub poster: String,
     pub date: u64,
     pub subject: String,
     pub groups: NzbGroups,
     pub segments: NzbSegments,
+    /// Name to write the file under instead of the one in the subject, from
+    /// a [`NameMap`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+impl NzbFile {
+    /// Age of the post in days, measured from its `date` attribute; `None`
+    /// when the poster left out the date or got it obviously wrong
+    pub fn age_days(&self) -> Option<f64> {
+        self.age_days_at(SystemTime::now())
+    }
+
+    /// Age of the post in days as of `now`
+    pub fn age_days_at(&self, now: SystemTime) -> Option<f64> {
+        post_age_days(self.date, now)
+    }
+
+    /// Whether the segment numbers can place the segments in the file: none
+    /// is 0 or listed twice, and none is past the subject's part count (or,
+    /// without one, the number of segments listed)
+    ///
+    /// Some indexers number every segment 0, or start again at 1 for each
+    /// part of a post.
+    pub fn numbering_usable(&self) -> bool {
+        let segments = &self.segments.segment;
+        let limit = part_count(&self.subject)
+            .unwrap_or(0)
+            .max(segments.len() as u32);
+        let mut seen = HashSet::with_capacity(segments.len());
+        segments
+            .iter()
+            .all(|s| (1..=limit).contains(&s.number) && seen.insert(s.number))
+    }
+
+    /// Segment numbers the file spans: the highest one, which is more than
+    /// the segments listed when the NZB leaves gaps
+    pub fn segment_slots(&self) -> usize {
+        self.segments
+            .segment
+            .iter()
+            .map(|s| s.number as usize)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// This file with its segments numbered 1 to N in the order the NZB
+    /// lists them, for when [`NzbFile::numbering_usable`] is false
+    pub fn renumbered(&self) -> NzbFile {
+        let mut file = self.clone();
+        for (i, segment) in file.segments.segment.iter_mut().enumerate() {
+            segment.number = i as u32 + 1;
+        }
+        file
+    }
+
+    /// Size of the file's articles as the NZB gives it (encoded, so a few
+    /// percent over the file's own)
+    pub fn size(&self) -> u64 {
+        self.segments.segment.iter().map(|s| s.bytes).sum()
+    }
+
+    /// Name of the file: the mapped [`name`](Self::name), else from the
+    /// subject (the subject itself when none is found)
+    pub fn filename(&self) -> String {
+        self.name.clone().unwrap_or_else(|| {
+            Nzb::get_filename_from_subject(&self.subject).unwrap_or_else(|| self.subject.clone())
+        })
+    }
+
+    /// Whether this is a PAR2 index or recovery volume, going by its name
+    pub fn par2_kind(&self) -> Option<Par2Kind> {
+        Par2Kind::of(&self.par2_name()?)
+    }
+
+    /// The file name to classify as PAR2 or not
+    ///
+    /// A [`name`](Self::name) is taken as given. When the subject's name
+    /// isn't a PAR2 one (or there is none), the subject is searched for a
+    /// `.par2` word, and failing that one ending in a bare `par2`
+    /// (`release [01/45] par2`) is taken for the set's index.
+    fn par2_name(&self) -> Option<String> {
+        if let Some(name) = &self.name {
+            return Some(name.clone());
+        }
+        let name = Nzb::get_filename_from_subject(&self.subject);
+        if name.as_deref().and_then(Par2Kind::of).is_some() {
+            return name;
+        }
+        PAR2_IN_SUBJECT
+            .captures(&self.subject)
+            .map(|caps| caps[1].to_string())
+            .or_else(|| {
+                PAR2_WORD_IN_SUBJECT
+                    .captures(&self.subject)
+                    .map(|caps| format!("{}.par2", caps[1].trim_end_matches(['-', ' '])))
+            })
+            .or(name)
+    }
+
+    /// Recovery blocks the file carries, if it's a PAR2 volume
+    pub fn par2_blocks(&self) -> Option<u32> {
+        match self.par2_kind()? {
+            Par2Kind::Volume { block_count, .. } => Some(block_count),
+            Par2Kind::Index => None,
+        }
+    }
+
+    /// Short tag for listings: `DATA`, `PAR2-IDX` or `PAR2 (+24 blocks)`
+    pub fn kind_label(&self) -> String {
+        match self.par2_kind() {
+            None => "DATA".to_string(),
+            Some(Par2Kind::Index) => "PAR2-IDX".to_string(),
+            Some(Par2Kind::Volume { block_count, .. }) => format!(
+                "PAR2 (+{} block{})",
+                block_count,
+                if block_count == 1 { "" } else { "s" }
+            ),
+        }
+    }
+}
+
+/// Role of a file in a PAR2 recovery set
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Par2Kind {
+    /// The index file (`name.par2`), which holds no recovery blocks
+    Index,
+    /// A recovery volume (`name.vol07+08.par2`)
+    Volume { first_block: u32, block_count: u32 },
+}
+
+impl Par2Kind {
+    /// Classify a file name; `None` when it isn't a PAR2 file
+    ///
+    /// `volAA+BB` names the first block and the count. The `volAA-BB` form
+    /// names the first block and the one after the last, so the count is
+    /// `BB - AA`; when that isn't positive `BB` is taken as the count.
+    pub fn of(filename: &str) -> Option<Self> {
+        let name = filename.trim();
+        if !par2_patterns::is_par2_file(Path::new(name)) {
+            return None;
+        }
+        let Some(caps) = PAR2_VOLUME.captures(name) else {
+            return Some(Self::Index);
+        };
+        // Too many digits to be a block number: some other `.vol` in the name
+        let (Ok(first_block), Ok(last)) = (caps[2].parse::<u32>(), caps[4].parse::<u32>()) else {
+            return Some(Self::Index);
+        };
+        let block_count = match &caps[3] {
+            "-" if last > first_block => last - first_block,
+            _ => last,
+        };
+        Some(Self::Volume {
+            first_block,
+            block_count,
+        })
+    }
+
+    /// Set name: the file name without `.par2` or `.volAA+BB.par2`
+    fn set_name(filename: &str) -> &str {
+        let name = filename.trim();
+        match PAR2_VOLUME.captures(name) {
+            Some(caps) => caps.get(1).map_or(name, |m| m.as_str()),
+            None => &name[..name.len() - ".par2".len()],
+        }
+    }
+}
+
+/// One PAR2 recovery set in an NZB: its index, volumes and the files it covers
+#[derive(Debug)]
+pub struct Par2Set<'a> {
+    /// File name of the set without `.par2` (or `.volAA+BB.par2`)
+    pub name: String,
+    pub index: Option<&'a NzbFile>,
+    /// Recovery volumes, in NZB order
+    pub volumes: Vec<&'a NzbFile>,
+    /// Files named after the set (`name.part01.rar`, `name.mkv`, ...)
+    pub data: Vec<&'a NzbFile>,
+}
+
+impl Par2Set<'_> {
+    /// Recovery blocks across all volumes
+    pub fn recovery_blocks(&self) -> u32 {
+        self.volumes.iter().filter_map(|f| f.par2_blocks()).sum()
+    }
+
+    /// Estimated block size of the set
+    ///
+    /// Only the index says what the block size is, so it's estimated from
+    /// the volumes: each holds its blocks and a little packet overhead, so
+    /// the smallest size per block is closest. `None` without volumes.
+    pub fn block_size(&self) -> Option<u64> {
+        self.volumes
+            .iter()
+            .filter_map(|f| Some(f.size() / f.par2_blocks().filter(|&b| b > 0)? as u64))
+            .filter(|&size| size > 0)
+            .min()
+    }
+
+    /// Estimated number of blocks the set's data files split into (see
+    /// [`block_size`](Self::block_size)); `None` without volumes or data
+    /// files to go by
+    pub fn data_blocks(&self) -> Option<u64> {
+        let block_size = self.block_size()?;
+        let blocks: u64 = self
+            .data
+            .iter()
+            .map(|f| f.size().div_ceil(block_size))
+            .sum();
+        (blocks > 0).then_some(blocks)
+    }
+}
+
+/// PAR2 recovery blocks set against the data blocks they protect
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Par2Coverage {
+    pub recovery_blocks: u64,
+    /// Estimated; see [`Par2Set::data_blocks`]
+    pub data_blocks: u64,
+    /// Recovery blocks per hundred data blocks
+    pub recovery_percent: f64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -44,23 +312,114 @@ pub struct NzbSegments {
 pub struct Nzb {
     // Cache converted files for performance
     files: Vec<NzbFile>,
+    title: Option<String>,
+    category: Option<String>,
+    passwords: Vec<String>,
+    content_hash: String,
+    /// Files [`apply_filter`](Self::apply_filter) left out
+    filtered: Vec<NzbFile>,
 }
 
 impl Nzb {
+    /// Read an NZB, decompressing `.nzb.gz` / `.nzb.bz2` transparently
+    ///
+    /// Files over [`STREAMING_THRESHOLD`] are streamed through
+    /// [`Nzb::from_reader`]; should that fail they are read whole after all.
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let content = std::fs::read_to_string(path)?;
+        let path = path.as_ref();
+        Self::read_file(path).map_err(|e| e.with_nzb_path(path))
+    }
+
+    fn read_file(path: &Path) -> Result<Self> {
+        if std::fs::metadata(path)?.len() > STREAMING_THRESHOLD {
+            match std::fs::File::open(path)
+                .map_err(DlNzbError::from)
+                .and_then(Self::from_reader)
+            {
+                Ok(nzb) => return Ok(nzb),
+                Err(e) => tracing::warn!(
+                    "Streaming parse of {} failed, reading it whole: {}",
+                    redact_nzb_path(path).display(),
+                    e
+                ),
+            }
+        }
+        let content = compression::decode(std::fs::read(path)?)?;
         content.parse()
     }
 
+    /// Parse an NZB as it is read, without holding the XML in memory
+    ///
+    /// Gzip and bzip2 input is decompressed on the fly.
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self> {
+        Ok(Self::from_parsed(nzb_stream::parse(compression::reader(
+            reader,
+        )?)?))
+    }
+
+    fn from_parsed(parsed: ParsedNzb) -> Self {
+        // Message-IDs identify the post regardless of formatting, compression,
+        // file order or the metadata an indexer adds
+        let mut ids: Vec<&str> = parsed
+            .files
+            .iter()
+            .flat_map(|file| &file.segments.segment)
+            .map(|segment| bare_message_id(&segment.message_id))
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        let mut hasher = Md5::new();
+        for id in ids {
+            hasher.update(id.as_bytes());
+            hasher.update(b"\n");
+        }
+
+        let nzb = Nzb {
+            content_hash: format!("{:x}", hasher.finalize()),
+            files: parsed.files,
+            title: parsed.title,
+            category: parsed.category,
+            passwords: parsed.passwords,
+            filtered: Vec::new(),
+        };
+        // Gaps are left to the caller, which knows whether they matter yet
+        for file in nzb.validate().problems() {
+            for warning in file.warnings() {
+                tracing::warn!("NZB file {}: {}", file.filename, warning);
+            }
+        }
+        nzb
+    }
+
     fn parse_content(content: &str) -> Result<Self> {
-        let inner = NzbRs::parse(content)
-            .map_err(|e| NzbError::ParseError(format!("Failed to parse NZB: {}", e)))?;
+        // nzb-rs sorts segments by number, which loses the order they were
+        // listed in; where the numbers can't be trusted that order is all
+        // there is to go on, so those NZBs are read by the streaming parser
+        // instead. A segment 0 is the usual sign, and may not survive nzb-rs.
+        if content.contains(r#"number="0""#) {
+            return Ok(Self::from_parsed(nzb_stream::parse(content.as_bytes())?));
+        }
+        let inner = match NzbRs::parse(content) {
+            Ok(inner) => inner,
+            // Told apart from a broken document by the streaming parser, as
+            // nzb-rs may refuse an NZB without files in its own words
+            Err(e) => {
+                return Err(match nzb_stream::parse(content.as_bytes()) {
+                    Err(empty @ DlNzbError::Nzb(NzbError::EmptyNzb(_))) => empty,
+                    _ => NzbError::ParseError(format!("Failed to parse NZB: {}", e)).into(),
+                })
+            }
+        };
+        if inner.files.is_empty() {
+            return Err(NzbError::EmptyNzb(None).into());
+        }
 
         // Convert nzb-rs structures to our compatible structures
         let files = inner
             .files
             .iter()
-            .map(|file| {
+            .enumerate()
+            .map(|(i, file)| {
                 let segments = file
                     .segments
                     .iter()
@@ -80,22 +439,336 @@ impl Nzb {
                     .collect();
 
                 NzbFile {
+                    index: i + 1,
                     poster: file.poster.clone(),
                     date: file.posted_at.timestamp() as u64,
                     subject: file.subject.clone(),
                     groups: NzbGroups { group: groups },
                     segments: NzbSegments { segment: segments },
+                    name: None,
                 }
             })
-            .collect();
+            .collect::<Vec<NzbFile>>();
+        if !files.iter().all(NzbFile::numbering_usable) {
+            return Ok(Self::from_parsed(nzb_stream::parse(content.as_bytes())?));
+        }
 
-        Ok(Nzb { files })
+        Ok(Self::from_parsed(ParsedNzb {
+            files,
+            title: inner.meta.title,
+            category: inner.meta.category,
+            passwords: inner.meta.passwords,
+        }))
+    }
+
+    /// Combine several NZBs into one, in the order given
+    ///
+    /// A file whose Message-IDs all match a file already taken is dropped,
+    /// so overlapping NZBs of the same post merge cleanly. The title and
+    /// category come from the first NZB that has one; passwords are pooled.
+    pub fn merge<I: IntoIterator<Item = Nzb>>(nzbs: I) -> Result<Self> {
+        let mut merged = ParsedNzb::default();
+        let mut seen = HashSet::new();
+        for nzb in nzbs {
+            merged.title = merged.title.or(nzb.title);
+            merged.category = merged.category.or(nzb.category);
+            for password in nzb.passwords {
+                if !merged.passwords.contains(&password) {
+                    merged.passwords.push(password);
+                }
+            }
+            for mut file in nzb.files {
+                let mut ids: Vec<String> = file
+                    .segments
+                    .segment
+                    .iter()
+                    .map(|segment| bare_message_id(&segment.message_id).to_string())
+                    .collect();
+                ids.sort_unstable();
+                if seen.insert(ids) {
+                    file.index = merged.files.len() + 1;
+                    merged.files.push(file);
+                }
+            }
+        }
+        if merged.files.is_empty() {
+            return Err(NzbError::EmptyNzb(None).into());
+        }
+        Ok(Self::from_parsed(merged))
+    }
+
+    /// The NZB as XML, with the files currently in it
+    ///
+    /// After a filter this is just the selection, so it can be saved and
+    /// handed to another client.
+    pub fn to_xml(&self) -> String {
+        let mut xml = String::from(XML_PROLOGUE);
+        let meta = [
+            ("title", self.title.as_deref()),
+            ("category", self.category.as_deref()),
+        ]
+        .into_iter()
+        .filter_map(|(kind, value)| Some((kind, value?)))
+        .chain(self.passwords.iter().map(|p| ("password", p.as_str())))
+        .collect::<Vec<_>>();
+        if !meta.is_empty() {
+            xml.push_str("  <head>\n");
+            for (kind, value) in meta {
+                let _ = writeln!(xml, r#"    <meta type="{}">{}</meta>"#, kind, escape(value));
+            }
+            xml.push_str("  </head>\n");
+        }
+        for file in &self.files {
+            let _ = writeln!(
+                xml,
+                r#"  <file poster="{}" date="{}" subject="{}">"#,
+                escape(&file.poster),
+                file.date,
+                escape(&file.subject)
+            );
+            xml.push_str("    <groups>\n");
+            for group in &file.groups.group {
+                let _ = writeln!(xml, "      <group>{}</group>", escape(&group.name));
+            }
+            xml.push_str("    </groups>\n    <segments>\n");
+            for segment in &file.segments.segment {
+                let _ = writeln!(
+                    xml,
+                    r#"      <segment bytes="{}" number="{}">{}</segment>"#,
+                    segment.bytes,
+                    segment.number,
+                    escape(bare_message_id(&segment.message_id))
+                );
+            }
+            xml.push_str("    </segments>\n  </file>\n");
+        }
+        xml.push_str("</nzb>\n");
+        xml
+    }
+
+    /// Write [`Nzb::to_xml`] to `path`
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        std::fs::write(path, self.to_xml())?;
+        Ok(())
     }
 
     pub fn files(&self) -> &Vec<NzbFile> {
         &self.files
     }
 
+    /// Drop the files the filter rejects; errors when none are left
+    ///
+    /// The dropped files stay listed in [`filtered_files`](Self::filtered_files).
+    pub fn apply_filter(&mut self, filter: &FileFilter) -> Result<()> {
+        if !filter.is_active() {
+            return Ok(());
+        }
+        filter.validate_selection(self.files.len())?;
+        let (kept, dropped) = std::mem::take(&mut self.files)
+            .into_iter()
+            .partition(|file| filter.matches(file));
+        self.files = kept;
+        self.filtered.extend(dropped);
+        if self.files.is_empty() {
+            return Err(NzbError::NoMatchingFiles.into());
+        }
+        Ok(())
+    }
+
+    /// Give the files `map` names the names it gives them
+    ///
+    /// Files a filter left out may be named too. Errors when a key names no
+    /// file, or a name clashes with another file's.
+    pub fn apply_name_map(&mut self, map: &NameMap) -> Result<()> {
+        if map.is_empty() {
+            return Ok(());
+        }
+        let all: Vec<&NzbFile> = self.files.iter().chain(&self.filtered).collect();
+        let names = map.resolve(&all)?;
+        for file in self.files.iter_mut().chain(&mut self.filtered) {
+            if let Some(name) = names.get(&file.index) {
+                file.name = Some(name.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// Name the files that downloaded as PAR2 other than their subject said
+    ///
+    /// A subject can hide a PAR2 file under a name without `.par2`; the
+    /// downloader finds it by its packet header (or the yEnc header's name)
+    /// and saves it under a `.par2` name, which the file takes here so
+    /// [`par2_sets`](Self::par2_sets) groups it with its set. Results are
+    /// matched to files by their articles.
+    pub fn adopt_par2_names(&mut self, results: &[DownloadResult]) {
+        for result in results {
+            if !par2_patterns::is_par2_file(&result.path) {
+                continue;
+            }
+            let Some(first) = result.segment_map.first() else {
+                continue;
+            };
+            let file = self.files.iter_mut().find(|file| {
+                (file.segments.segment.iter()).any(|segment| segment.message_id == first.message_id)
+            });
+            let kind = Par2Kind::of(&result.filename);
+            if let Some(file) = file.filter(|file| file.par2_kind() != kind) {
+                tracing::debug!(
+                    "{:?} downloaded as PAR2 file {}",
+                    file.subject,
+                    result.filename
+                );
+                file.name = Some(result.filename.clone());
+            }
+        }
+    }
+
+    /// Names given by [`apply_name_map`](Self::apply_name_map) to the files
+    /// being downloaded
+    pub fn mapped_names(&self) -> Vec<String> {
+        self.files
+            .iter()
+            .filter_map(|file| file.name.clone())
+            .collect()
+    }
+
+    /// How many PAR2 files the NZB holds, when it holds nothing else
+    ///
+    /// Files a filter left out count, so picking the PAR2 files of a
+    /// complete post isn't taken for one. Obfuscated names can't be told
+    /// apart and count as data.
+    pub fn par2_only(&self) -> Option<usize> {
+        let all = || self.files.iter().chain(&self.filtered);
+        all()
+            .all(|file| file.par2_kind().is_some())
+            .then(|| all().count())
+            .filter(|&count| count > 0)
+    }
+
+    /// Files a filter left out, as skipped files
+    pub fn filtered_files(&self) -> Vec<SkippedFile> {
+        self.filtered
+            .iter()
+            .map(|file| SkippedFile::of(file, SkipReason::Filtered))
+            .collect()
+    }
+
+    /// Title from the NZB `<head>` metadata, if present
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    /// Category from the NZB `<head>` metadata, if present
+    pub fn category(&self) -> Option<&str> {
+        self.category.as_deref()
+    }
+
+    /// Archive passwords from the NZB `<head>` metadata
+    pub fn passwords(&self) -> &[String] {
+        &self.passwords
+    }
+
+    /// Hash of every segment's Message-ID, taken before any file filter
+    ///
+    /// The IDs are sorted first, so the same post listed by two indexers
+    /// hashes the same whatever the file order, subjects or `<head>` metadata.
+    pub fn content_hash(&self) -> &str {
+        &self.content_hash
+    }
+
+    /// Recovery blocks against data blocks over every PAR2 set that has
+    /// both; data outside the sets isn't counted
+    pub fn par2_coverage(&self) -> Option<Par2Coverage> {
+        let (recovery_blocks, data_blocks) = self
+            .par2_sets()
+            .iter()
+            .filter_map(|set| Some((set.recovery_blocks() as u64, set.data_blocks()?)))
+            .fold((0, 0), |(recovery, data), (r, d)| (recovery + r, data + d));
+        (data_blocks > 0).then(|| Par2Coverage {
+            recovery_blocks,
+            data_blocks,
+            recovery_percent: recovery_blocks as f64 * 100.0 / data_blocks as f64,
+        })
+    }
+
+    /// The PAR2 sets in the NZB, in order of their first PAR2 file
+    ///
+    /// Data files join the set whose name they start with (the longest, when
+    /// several match); files outside every set are left out.
+    pub fn par2_sets(&self) -> Vec<Par2Set<'_>> {
+        let mut sets: Vec<Par2Set> = Vec::new();
+        let mut by_name: HashMap<String, usize> = HashMap::new();
+        let mut data = Vec::new();
+        for file in &self.files {
+            let Some(kind) = file.par2_kind() else {
+                data.push(file);
+                continue;
+            };
+            let filename = file.par2_name().unwrap_or_default();
+            let name = Par2Kind::set_name(&filename).to_string();
+            let position = *by_name.entry(name.to_lowercase()).or_insert_with(|| {
+                sets.push(Par2Set {
+                    name,
+                    index: None,
+                    volumes: Vec::new(),
+                    data: Vec::new(),
+                });
+                sets.len() - 1
+            });
+            match kind {
+                Par2Kind::Index if sets[position].index.is_none() => {
+                    sets[position].index = Some(file)
+                }
+                Par2Kind::Index => {}
+                Par2Kind::Volume { .. } => sets[position].volumes.push(file),
+            }
+        }
+
+        for file in data {
+            let filename = file.filename().to_lowercase();
+            let set = by_name
+                .iter()
+                .filter(|(name, _)| {
+                    filename == **name
+                        || filename
+                            .strip_prefix(name.as_str())
+                            .is_some_and(|rest| rest.starts_with('.'))
+                })
+                .max_by_key(|(name, _)| name.len())
+                .map(|(_, &position)| position);
+            if let Some(position) = set {
+                sets[position].data.push(file);
+            }
+        }
+        sets
+    }
+
+    /// Age in days of the oldest file in the NZB with a usable date
+    pub fn oldest_age_days(&self) -> Option<f64> {
+        self.files
+            .iter()
+            .filter_map(NzbFile::age_days)
+            .reduce(f64::max)
+    }
+
+    /// Posting time (Unix seconds) of the newest file with a usable date
+    pub fn newest_post_date(&self) -> Option<u64> {
+        let now = SystemTime::now();
+        self.files
+            .iter()
+            .map(|file| file.date)
+            .filter(|&date| post_age_days(date, now).is_some())
+            .max()
+    }
+
+    /// Age in days of the newest file in the NZB with a usable date
+    pub fn newest_age_days(&self) -> Option<f64> {
+        self.files
+            .iter()
+            .filter_map(NzbFile::age_days)
+            .reduce(f64::min)
+    }
+
     pub fn total_size(&self) -> u64 {
         self.files
             .iter()
@@ -111,13 +784,57 @@ impl Nzb {
             .sum()
     }
 
+    /// File name from a subject like `[1/9] - "filename.ext" yEnc (1/5202)`,
+    /// made safe to write (see [`subject::filename`](super::subject::filename))
     pub fn get_filename_from_subject(subject: &str) -> Option<String> {
-        // Extract filename from subject line like: [1/9] - "filename.ext" yEnc (1/5202)
-        // Handle both regular quotes and HTML entities (&quot;)
-        let re = regex::Regex::new(r#"(?:&quot;|")([^"]+)(?:&quot;|")"#).ok()?;
-        re.captures(subject)
-            .and_then(|caps| caps.get(1))
-            .map(|m| m.as_str().to_string())
+        super::subject::filename(subject)
+            .map(|name| sanitize_filename(&name))
+            .filter(|name| !name.is_empty())
+    }
+}
+
+/// A Message-ID without surrounding whitespace or angle brackets
+pub(super) fn bare_message_id(id: &str) -> &str {
+    id.trim().trim_start_matches('<').trim_end_matches('>')
+}
+
+/// Escape text for an XML attribute value or element body
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Make a poster-supplied filename safe to join onto the download directory
+///
+/// Path separators never survive, so a subject can't write outside the
+/// directory. On Windows the characters it refuses in filenames are replaced
+/// too, along with the trailing dots and spaces it silently drops.
+pub(super) fn sanitize_filename(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' => '_',
+            c if c.is_control() => '_',
+            #[cfg(windows)]
+            ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c => c,
+        })
+        .collect();
+    #[cfg(windows)]
+    let name = name.trim_end_matches(['.', ' ']);
+    match name.trim() {
+        "." | ".." => String::new(),
+        name => name.to_string(),
     }
 }
 
@@ -171,4 +888,586 @@ mod tests {
         println!("Meta title: {:?}", nzb_rs.meta.title);
         println!("Meta category: {:?}", nzb_rs.meta.category);
     }
+
+    #[test]
+    fn test_filename_from_subject() {
+        assert_eq!(
+            Nzb::get_filename_from_subject(r#"[1/9] - "show.part01.rar" yEnc (1/5202)"#),
+            Some("show.part01.rar".to_string())
+        );
+        assert_eq!(
+            Nzb::get_filename_from_subject("&quot;show.nfo&quot; yEnc (1/1)"),
+            Some("show.nfo".to_string())
+        );
+        assert_eq!(
+            Nzb::get_filename_from_subject(r#""../../etc/passwd" yEnc (1/1)"#),
+            Some(".._.._etc_passwd".to_string())
+        );
+        assert_eq!(
+            Nzb::get_filename_from_subject(r#""C:\Windows\evil.dll" yEnc (1/1)"#)
+                .map(|name| name.contains('\\')),
+            Some(false)
+        );
+        assert_eq!(Nzb::get_filename_from_subject(r#"".." yEnc (1/1)"#), None);
+        assert_eq!(Nzb::get_filename_from_subject("no quotes here"), None);
+    }
+
+    #[test]
+    fn test_from_file_gzipped() {
+        use std::io::Write;
+
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+        <nzb xmlns="http://www.newzbin.com/DTD/2003/nzb">
+            <head><meta type="title">Gzipped</meta></head>
+            <file poster="p@example.com" date="1234567890" subject="&quot;a.rar&quot; yEnc (1/1)">
+                <groups><group>alt.binaries.test</group></groups>
+                <segments><segment bytes="2048" number="1">a@example.com</segment></segments>
+            </file>
+        </nzb>"#;
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(xml.as_bytes()).unwrap();
+
+        // Named without a .gz extension: detection goes by magic bytes
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("show.nzb");
+        std::fs::write(&path, encoder.finish().unwrap()).unwrap();
+
+        let nzb = Nzb::from_file(&path).unwrap();
+        assert_eq!(nzb.title(), Some("Gzipped"));
+        assert_eq!(nzb.files().len(), 1);
+        assert_eq!(nzb.total_size(), 2048);
+
+        // Same post, same hash, whatever the encoding or whitespace
+        let plain: Nzb = xml.replace("\n", " ").parse().unwrap();
+        assert_eq!(plain.content_hash(), nzb.content_hash());
+        assert_eq!(nzb.content_hash().len(), 32);
+    }
+
+    #[test]
+    fn test_content_hash_ignores_order_and_metadata() {
+        let file = |name: &str, id: &str| {
+            format!(
+                r#"<file poster="p@example.com" date="1234567890" subject="&quot;{}&quot; yEnc (1/1)">
+                    <groups><group>alt.binaries.test</group></groups>
+                    <segments><segment bytes="2048" number="1">{}</segment></segments>
+                </file>"#,
+                name, id
+            )
+        };
+        let nzb = |head: &str, files: &[String]| -> Nzb {
+            format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+                <nzb xmlns="http://www.newzbin.com/DTD/2003/nzb">{}{}</nzb>"#,
+                head,
+                files.concat()
+            )
+            .parse()
+            .unwrap()
+        };
+
+        let first = nzb(
+            "",
+            &[
+                file("a.rar", "a@example.com"),
+                file("b.rar", "b@example.com"),
+            ],
+        );
+        // The same post from another indexer: reordered, renamed, with a title
+        let second = nzb(
+            r#"<head><meta type="title">Renamed</meta></head>"#,
+            &[
+                file("B.part2.rar", "b@example.com"),
+                file("A.part1.rar", "a@example.com"),
+            ],
+        );
+        assert_eq!(first.content_hash(), second.content_hash());
+
+        let other = nzb("", &[file("a.rar", "a@example.com")]);
+        assert_ne!(first.content_hash(), other.content_hash());
+    }
+
+    const ROUND_TRIP: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+        <nzb xmlns="http://www.newzbin.com/DTD/2003/nzb">
+            <head>
+                <meta type="title">Tom &amp; Jerry's "Best"</meta>
+                <meta type="category">TV &gt; HD</meta>
+                <meta type="password">p&lt;w&gt;d</meta>
+            </head>
+            <file poster="Tom &lt;tom@example.com&gt;" date="1234567890" subject="[1/2] - &quot;a &amp; b.rar&quot; yEnc (1/2)">
+                <groups><group>alt.binaries.test</group><group>alt.binaries.other</group></groups>
+                <segments>
+                    <segment bytes="2048" number="1">a1@example.com</segment>
+                    <segment bytes="1024" number="2">a2@example.com</segment>
+                </segments>
+            </file>
+            <file poster="p@example.com" date="1234567891" subject="[2/2] - &quot;it's.nfo&quot; yEnc (1/1)">
+                <groups><group>alt.binaries.test</group></groups>
+                <segments><segment bytes="512" number="1">b@example.com</segment></segments>
+            </file>
+        </nzb>"#;
+
+    /// Everything `to_xml` writes, in a comparable form
+    fn summary(nzb: &Nzb) -> Vec<String> {
+        let mut lines = vec![format!(
+            "{:?} {:?} {:?}",
+            nzb.title(),
+            nzb.category(),
+            nzb.passwords()
+        )];
+        for file in nzb.files() {
+            lines.push(format!(
+                "{} {} {} {:?}",
+                file.poster,
+                file.date,
+                file.subject,
+                file.groups
+                    .group
+                    .iter()
+                    .map(|g| &g.name)
+                    .collect::<Vec<_>>()
+            ));
+            for segment in &file.segments.segment {
+                lines.push(format!(
+                    "{} {} {}",
+                    segment.bytes, segment.number, segment.message_id
+                ));
+            }
+        }
+        lines
+    }
+
+    #[test]
+    fn test_to_xml_round_trips() {
+        let nzb: Nzb = ROUND_TRIP.parse().unwrap();
+        let xml = nzb.to_xml();
+        assert!(xml.contains("<!DOCTYPE nzb"));
+        assert!(xml.contains(r#"xmlns="http://www.newzbin.com/DTD/2003/nzb""#));
+
+        let reparsed: Nzb = xml.parse().unwrap();
+        assert_eq!(summary(&reparsed), summary(&nzb));
+        assert_eq!(reparsed.content_hash(), nzb.content_hash());
+        // The streaming parser reads it the same way
+        let streamed = Nzb::from_reader(xml.as_bytes()).unwrap();
+        assert_eq!(summary(&streamed), summary(&nzb));
+    }
+
+    #[test]
+    fn test_to_xml_writes_only_the_selection() {
+        let mut nzb: Nzb = ROUND_TRIP.parse().unwrap();
+        nzb.apply_filter(&FileFilter::new(&[], &["*.nfo".to_string()], false).unwrap())
+            .unwrap();
+        let filtered = nzb.filtered_files();
+        assert_eq!(filtered.len(), 1);
+        assert_eq!(filtered[0].reason, SkipReason::Filtered);
+        let saved: Nzb = nzb.to_xml().parse().unwrap();
+        assert_eq!(saved.files().len(), 1);
+        assert!(saved.filtered_files().is_empty());
+        assert_eq!(saved.total_segments(), 2);
+    }
+
+    #[test]
+    fn test_merge_drops_repeated_files() {
+        let first: Nzb = ROUND_TRIP.parse().unwrap();
+        let second: Nzb = r#"<?xml version="1.0" encoding="UTF-8"?>
+        <nzb xmlns="http://www.newzbin.com/DTD/2003/nzb">
+            <head><meta type="title">Other</meta><meta type="password">extra</meta></head>
+            <file poster="p@example.com" date="1234567891" subject="renamed.nfo">
+                <groups><group>alt.binaries.test</group></groups>
+                <segments><segment bytes="512" number="1">&lt;b@example.com&gt;</segment></segments>
+            </file>
+            <file poster="p@example.com" date="1234567892" subject="&quot;c.rar&quot; yEnc (1/1)">
+                <groups><group>alt.binaries.test</group></groups>
+                <segments><segment bytes="256" number="1">c@example.com</segment></segments>
+            </file>
+        </nzb>"#
+            .parse()
+            .unwrap();
+
+        let merged = Nzb::merge([first, second]).unwrap();
+        let indexes: Vec<usize> = merged.files().iter().map(|f| f.index).collect();
+        assert_eq!(indexes, [1, 2, 3]);
+        assert_eq!(merged.files()[2].subject, r#""c.rar" yEnc (1/1)"#);
+        assert_eq!(merged.title(), Some(r#"Tom & Jerry's "Best""#));
+        assert_eq!(merged.passwords(), ["p<w>d", "extra"]);
+
+        assert!(matches!(
+            Nzb::merge(Vec::new()),
+            Err(DlNzbError::Nzb(NzbError::EmptyNzb(None)))
+        ));
+    }
+
+    /// A one-segment file with this subject
+    fn file_with_subject(index: usize, subject: &str) -> NzbFile {
+        NzbFile {
+            index,
+            poster: "p@example.com".to_string(),
+            date: 1234567890,
+            subject: subject.to_string(),
+            groups: NzbGroups { group: Vec::new() },
+            segments: NzbSegments {
+                segment: vec![NzbSegment {
+                    bytes: 1024,
+                    number: 1,
+                    message_id: format!("{}@example.com", index),
+                }],
+            },
+            name: None,
+        }
+    }
+
+    #[test]
+    fn test_par2_kind_of_names() {
+        let volume = |first_block, block_count| {
+            Some(Par2Kind::Volume {
+                first_block,
+                block_count,
+            })
+        };
+        assert_eq!(Par2Kind::of("Show.S01E01.par2"), Some(Par2Kind::Index));
+        assert_eq!(Par2Kind::of("SHOW.PAR2"), Some(Par2Kind::Index));
+        assert_eq!(Par2Kind::of("Show.S01E01.vol00+01.par2"), volume(0, 1));
+        assert_eq!(
+            Par2Kind::of("Show.S01E01.vol127+128.PAR2"),
+            volume(127, 128)
+        );
+        assert_eq!(Par2Kind::of("show.VOL07+08.Par2"), volume(7, 8));
+        assert_eq!(Par2Kind::of("5f8e2c1a9b.vol0+1.par2"), volume(0, 1));
+        // The dash form gives the end of the range rather than the count
+        assert_eq!(Par2Kind::of("show.vol031-063.PAR2"), volume(31, 32));
+        assert_eq!(Par2Kind::of("show.vol01-02.par2"), volume(1, 1));
+        assert_eq!(Par2Kind::of("show.vol00-01.par2"), volume(0, 1));
+        assert_eq!(Par2Kind::of("show.vol05-03.par2"), volume(5, 3));
+        // `.vol` elsewhere in the name doesn't make a volume
+        assert_eq!(Par2Kind::of("Movie.Volume.1.par2"), Some(Par2Kind::Index));
+        assert_eq!(Par2Kind::of("Album.Vol.2.par2"), Some(Par2Kind::Index));
+        assert_eq!(
+            Par2Kind::of("show.vol99999999999+1.par2"),
+            Some(Par2Kind::Index)
+        );
+        assert_eq!(Par2Kind::of("show.part01.rar"), None);
+        assert_eq!(Par2Kind::of("show.par2.rar"), None);
+        assert_eq!(Par2Kind::of("show.vol00+01.par2.1"), None);
+        assert_eq!(Par2Kind::of("par2"), None);
+    }
+
+    #[test]
+    fn test_par2_kind_of_subjects() {
+        let kind = |subject: &str| file_with_subject(1, subject).par2_kind();
+        let blocks = |subject: &str| file_with_subject(1, subject).par2_blocks();
+
+        assert_eq!(
+            kind(r#"[01/15] - "Show.S01E01.1080p.WEB.par2" yEnc (1/1)"#),
+            Some(Par2Kind::Index)
+        );
+        assert_eq!(
+            blocks(r#"[PRiVATE]-[WtFnZb]-[Show.mkv]-[3/9] - "Show.vol03+04.PAR2" yEnc (1/2)"#),
+            Some(4)
+        );
+        assert_eq!(
+            blocks(r#"(????) [12/15] - "abc123.vol127+128.par2" yEnc (1/100)"#),
+            Some(128)
+        );
+        assert_eq!(
+            blocks("&quot;show.vol15+16.par2&quot; yEnc (1/3)"),
+            Some(16)
+        );
+        // No quoted name: the `.par2` word in the subject is used
+        assert_eq!(blocks("Show.S01E01.vol00+01.par2 (1/3)"), Some(1));
+        assert_eq!(
+            kind("[5/5] Show.S01E01.par2 yEnc (1/1)"),
+            Some(Par2Kind::Index)
+        );
+        assert_eq!(
+            kind(r#"[02/15] - "Show.S01E01.part01.rar" yEnc (1/200)"#),
+            None
+        );
+        assert_eq!(kind("Show.S01E01 par2 files (1/3)"), None);
+        // A bare `par2` word ending the subject stands for the set's index
+        for subject in [
+            "release [01/45] par2",
+            "Release.Name.2023.1080p [01/45] PAR2",
+            "release [01/45] - par2 yEnc (1/1)",
+            "release - [01/45] - PAR2 yEnc (1/3)",
+            "release par2 (1/1)",
+        ] {
+            assert_eq!(kind(subject), Some(Par2Kind::Index), "{}", subject);
+        }
+        for subject in [
+            "par2",
+            "[01/45] par2 yEnc (1/1)",
+            "release [02/45] rar",
+            "release.par2.notes [01/45] (1/1)",
+        ] {
+            assert_eq!(kind(subject), None, "{}", subject);
+        }
+        let sets = |subject: &str| {
+            Nzb::from_parsed(ParsedNzb {
+                files: vec![file_with_subject(1, subject)],
+                ..ParsedNzb::default()
+            })
+            .par2_sets()
+            .into_iter()
+            .map(|set| set.name)
+            .collect::<Vec<_>>()
+        };
+        assert_eq!(sets("Release.Name [01/45] par2"), ["Release.Name"]);
+        // The index carries no recovery blocks
+        assert_eq!(blocks(r#""Show.par2" yEnc (1/1)"#), None);
+    }
+
+    #[test]
+    fn test_kind_label() {
+        let label = |subject: &str| file_with_subject(1, subject).kind_label();
+        assert_eq!(label(r#""show.part01.rar" yEnc (1/9)"#), "DATA");
+        assert_eq!(label(r#""show.par2" yEnc (1/1)"#), "PAR2-IDX");
+        assert_eq!(
+            label(r#""show.vol00+01.par2" yEnc (1/1)"#),
+            "PAR2 (+1 block)"
+        );
+        assert_eq!(
+            label(r#""show.vol07+24.par2" yEnc (1/5)"#),
+            "PAR2 (+24 blocks)"
+        );
+    }
+
+    #[test]
+    fn test_empty_nzb_is_its_own_error() {
+        for xml in [
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<nzb xmlns="http://www.newzbin.com/DTD/2003/nzb"><head><meta type="title">x</meta></head></nzb>"#,
+            "<nzb></nzb>",
+        ] {
+            assert!(matches!(
+                xml.parse::<Nzb>(),
+                Err(DlNzbError::Nzb(NzbError::EmptyNzb(None)))
+            ));
+            assert!(matches!(
+                Nzb::from_reader(xml.as_bytes()),
+                Err(DlNzbError::Nzb(NzbError::EmptyNzb(None)))
+            ));
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("empty.nzb");
+        std::fs::write(&path, "<nzb></nzb>").unwrap();
+        match Nzb::from_file(&path) {
+            Err(DlNzbError::Nzb(NzbError::EmptyNzb(Some(named)))) => assert_eq!(named, path),
+            other => panic!("{:?}", other.map(|nzb| nzb.files().len())),
+        }
+    }
+
+    #[test]
+    fn test_par2_only() {
+        let nzb = |subjects: &[&str]| {
+            Nzb::from_parsed(ParsedNzb {
+                files: subjects
+                    .iter()
+                    .enumerate()
+                    .map(|(i, subject)| file_with_subject(i + 1, subject))
+                    .collect(),
+                ..ParsedNzb::default()
+            })
+        };
+        let recovery = [
+            r#""Show.par2" yEnc (1/1)"#,
+            r#""Show.vol00+01.par2" yEnc (1/2)"#,
+            r#""Show.vol01+02.PAR2" yEnc (1/3)"#,
+        ];
+        assert_eq!(nzb(&recovery).par2_only(), Some(3));
+
+        let mut complete = recovery.to_vec();
+        complete.push(r#""Show.mkv" yEnc (1/50)"#);
+        assert_eq!(nzb(&complete).par2_only(), None);
+        // Nor when only the PAR2 files were picked from it
+        let mut picked = nzb(&complete);
+        let filter = FileFilter::new(&["*.par2".to_string()], &[], false).unwrap();
+        picked.apply_filter(&filter).unwrap();
+        assert_eq!(picked.par2_only(), None);
+
+        // No telling what an obfuscated file is
+        assert_eq!(
+            nzb(&[recovery[0], "a8f3e1b2c4d5 yEnc (1/9)"]).par2_only(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_par2_sets_group_files() {
+        let subjects = [
+            r#"[1/9] - "Show.S01E01.par2" yEnc (1/1)"#,
+            r#"[2/9] - "Show.S01E01.part01.rar" yEnc (1/50)"#,
+            r#"[3/9] - "Show.S01E01.part02.rar" yEnc (1/50)"#,
+            r#"[4/9] - "Show.S01E01.vol00+01.PAR2" yEnc (1/2)"#,
+            r#"[5/9] - "show.s01e01.vol01+02.par2" yEnc (1/3)"#,
+            r#"[6/9] - "Show.S01E01.nfo" yEnc (1/1)"#,
+            // A second set whose name extends the first
+            r#"[7/9] - "Show.S01E01.Extras.mkv" yEnc (1/80)"#,
+            r#"[8/9] - "Show.S01E01.Extras.mkv.vol0+8.par2" yEnc (1/8)"#,
+            r#"[9/9] - "cover.jpg" yEnc (1/1)"#,
+        ];
+        let nzb = Nzb::from_parsed(ParsedNzb {
+            files: subjects
+                .iter()
+                .enumerate()
+                .map(|(i, subject)| file_with_subject(i + 1, subject))
+                .collect(),
+            ..ParsedNzb::default()
+        });
+        let indexes = |files: &[&NzbFile]| files.iter().map(|f| f.index).collect::<Vec<_>>();
+
+        let sets = nzb.par2_sets();
+        assert_eq!(sets.len(), 2);
+        assert_eq!(sets[0].name, "Show.S01E01");
+        assert_eq!(sets[0].index.map(|f| f.index), Some(1));
+        assert_eq!(indexes(&sets[0].volumes), [4, 5]);
+        assert_eq!(indexes(&sets[0].data), [2, 3, 6]);
+        assert_eq!(sets[0].recovery_blocks(), 3);
+
+        // A set posted without its index
+        assert_eq!(sets[1].name, "Show.S01E01.Extras.mkv");
+        assert!(sets[1].index.is_none());
+        assert_eq!(indexes(&sets[1].data), [7]);
+        assert_eq!(sets[1].recovery_blocks(), 8);
+
+        // Every file is 1 KiB: the volume of two blocks gives the first set
+        // 512-byte blocks, the second set's holds eight of 128 bytes
+        assert_eq!(sets[0].data_blocks(), Some(6));
+        assert_eq!(sets[1].data_blocks(), Some(8));
+        let coverage = nzb.par2_coverage().unwrap();
+        assert_eq!((coverage.recovery_blocks, coverage.data_blocks), (11, 14));
+        assert!((coverage.recovery_percent - 78.57).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_par2_found_by_contents_joins_its_set() {
+        let subjects = [
+            r#"[1/4] - "Show.par2" yEnc (1/1)"#,
+            r#"[2/4] - "Show.mkv" yEnc (1/1)"#,
+            // Nothing in the subject says it's a volume
+            "Show [3/4] yEnc (1/1)",
+            "a8f3e1b2c4d5 (1/1)",
+        ];
+        let mut nzb = Nzb::from_parsed(ParsedNzb {
+            files: subjects
+                .iter()
+                .enumerate()
+                .map(|(i, subject)| file_with_subject(i + 1, subject))
+                .collect(),
+            ..ParsedNzb::default()
+        });
+        assert!(nzb.par2_sets()[0].volumes.is_empty());
+
+        let result = |index: usize, name: &str| DownloadResult {
+            filename: name.to_string(),
+            path: Path::new("/dl").join(name),
+            size: 1024,
+            segments_downloaded: 1,
+            group: "alt.binaries.test".to_string(),
+            segment_map: vec![crate::download::SegmentSpan {
+                number: 1,
+                message_id: format!("{}@example.com", index),
+                offset: 0,
+                len: 1024,
+            }],
+            ..Default::default()
+        };
+        nzb.adopt_par2_names(&[
+            result(1, "Show.par2"),
+            result(2, "Show.mkv"),
+            result(3, "Show.vol00+01.par2"),
+            result(4, "a8f3e1b2c4d5"),
+        ]);
+
+        let sets = nzb.par2_sets();
+        assert_eq!(sets.len(), 1);
+        assert_eq!(sets[0].volumes.len(), 1);
+        assert_eq!(sets[0].volumes[0].index, 3);
+        assert_eq!(sets[0].recovery_blocks(), 1);
+        // Files whose subjects had them right keep their names
+        assert_eq!(nzb.files()[0].name, None);
+        assert_eq!(nzb.files()[3].name, None);
+    }
+
+    #[test]
+    fn test_post_ages_skip_bogus_dates() {
+        let dated = |index, date| NzbFile {
+            date,
+            ..file_with_subject(index, "a.rar")
+        };
+        let now = SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let nzb = Nzb::from_parsed(ParsedNzb {
+            files: vec![
+                dated(1, now - 86_400 * 10),
+                dated(2, 0),
+                dated(3, now - 86_400 * 2),
+                dated(4, now + 86_400 * 365 * 20),
+            ],
+            ..ParsedNzb::default()
+        });
+        assert_eq!(nzb.oldest_age_days().map(f64::round), Some(10.0));
+        assert_eq!(nzb.newest_age_days().map(f64::round), Some(2.0));
+        assert!(nzb.files()[1].age_days().is_none());
+
+        let undated = Nzb::from_parsed(ParsedNzb {
+            files: vec![dated(1, 0)],
+            ..ParsedNzb::default()
+        });
+        assert_eq!(undated.oldest_age_days(), None);
+    }
+
+    #[test]
+    fn test_unusable_numbers_keep_the_listed_order() {
+        let xml = |numbers: [u32; 3]| {
+            format!(
+                r#"<nzb xmlns="http://www.newzbin.com/DTD/2003/nzb">
+                <file poster="p@example.com" date="1234567890" subject="&quot;a.rar&quot; yEnc (1/3)">
+                    <groups><group>alt.binaries.test</group></groups>
+                    <segments>
+                        <segment bytes="700" number="{}">c@x</segment>
+                        <segment bytes="700" number="{}">a@x</segment>
+                        <segment bytes="300" number="{}">b@x</segment>
+                    </segments>
+                </file>
+            </nzb>"#,
+                numbers[0], numbers[1], numbers[2]
+            )
+        };
+        let ids = |nzb: &Nzb| -> Vec<String> {
+            nzb.files()[0]
+                .segments
+                .segment
+                .iter()
+                .map(|s| s.message_id.clone())
+                .collect()
+        };
+
+        // Usable numbers order the segments
+        let numbered: Nzb = xml([3, 1, 2]).parse().unwrap();
+        assert!(numbered.files()[0].numbering_usable());
+        assert_eq!(ids(&numbered), ["a@x", "b@x", "c@x"]);
+
+        // Otherwise the listed order is all there is, whichever parser reads it
+        for numbers in [[0, 0, 0], [1, 2, 1], [1, 2, 9]] {
+            let nzb: Nzb = xml(numbers).parse().unwrap();
+            let file = &nzb.files()[0];
+            assert!(!file.numbering_usable(), "{:?}", numbers);
+            assert_eq!(ids(&nzb), ["c@x", "a@x", "b@x"], "{:?}", numbers);
+            let renumbered = file.renumbered();
+            assert!(renumbered.numbering_usable());
+            assert_eq!(renumbered.segments.segment[0].message_id, "c@x");
+            assert_eq!(renumbered.segment_slots(), 3);
+        }
+    }
+
+    #[test]
+    fn test_gaps_keep_their_slots() {
+        // The subject promises 4 parts and the NZB only lists the third
+        let mut file = file_with_subject(1, r#""a.rar" yEnc (1/4)"#);
+        file.segments.segment[0].number = 3;
+        assert!(file.numbering_usable());
+        assert_eq!(file.segment_slots(), 3);
+    }
 }
@@ -0,0 +1,311 @@
+//! Streaming NZB parser for very large NZBs
+//!
+//! Builds the files from a stream of XML events, so the document itself is
+//! never held in memory. It follows nzb-rs, which parses everything else:
+//! elements are matched by local name whatever their namespace prefix,
+//! segments with unusable attributes are skipped, every file needs a group
+//! and a segment, and files end up sorted by subject with their groups and
+//! segments sorted. Unlike nzb-rs it leaves segments in the order they're
+//! listed when their numbers can't order them, as when every one is 0.
+
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::borrow::Cow;
+use std::io::BufRead;
+
+use super::nzb::{NzbFile, NzbGroup, NzbGroups, NzbSegment, NzbSegments};
+use crate::error::{DlNzbError, NzbError};
+
+type Result<T> = std::result::Result<T, DlNzbError>;
+
+/// What an NZB holds, before it becomes an [`super::Nzb`]
+#[derive(Debug, Default)]
+pub(super) struct ParsedNzb {
+    pub files: Vec<NzbFile>,
+    pub title: Option<String>,
+    pub category: Option<String>,
+    pub passwords: Vec<String>,
+}
+
+/// Element whose text is being collected
+enum Collecting {
+    Meta(Option<String>),
+    Group,
+    /// `bytes` and `number`, when both are usable
+    Segment(Option<(u64, u32)>),
+}
+
+pub(super) fn parse(input: impl BufRead) -> Result<ParsedNzb> {
+    let mut reader = Reader::from_reader(input);
+    let mut buf = Vec::new();
+    let mut nzb = ParsedNzb::default();
+    let mut file: Option<NzbFile> = None;
+    let mut collecting: Option<Collecting> = None;
+    let mut text = String::new();
+    // Elements open; a document cut short ends with some still open
+    let mut depth = 0usize;
+
+    loop {
+        let event = reader.read_event_into(&mut buf).map_err(|e| {
+            NzbError::ParseError(format!(
+                "Failed to parse NZB at byte {}: {}",
+                reader.buffer_position(),
+                e
+            ))
+        })?;
+        let empty = matches!(event, Event::Empty(_));
+        match event {
+            Event::Start(e) | Event::Empty(e) => {
+                if !empty {
+                    depth += 1;
+                }
+                match e.local_name().as_ref() {
+                    b"file" => file = Some(start_file(&e)?),
+                    b"meta" => {
+                        let kind = attribute(&e, b"type").map(|t| t.to_lowercase());
+                        collecting = Some(Collecting::Meta(kind));
+                    }
+                    b"group" if file.is_some() => collecting = Some(Collecting::Group),
+                    b"segment" if file.is_some() => {
+                        let bytes = attribute(&e, b"bytes").and_then(|b| b.parse().ok());
+                        let number = attribute(&e, b"number").and_then(|n| n.parse().ok());
+                        collecting = Some(Collecting::Segment(bytes.zip(number)));
+                    }
+                    _ => {}
+                }
+                text.clear();
+                if empty {
+                    end(
+                        e.local_name().as_ref(),
+                        &mut nzb,
+                        &mut file,
+                        &mut collecting,
+                        "",
+                    )?;
+                }
+            }
+            Event::Text(e) if collecting.is_some() => match e.unescape() {
+                Ok(unescaped) => text.push_str(&unescaped),
+                // Entities XML doesn't know (HTML's &nbsp; and the like) stay as written
+                Err(_) => text.push_str(&String::from_utf8_lossy(&e)),
+            },
+            Event::CData(e) if collecting.is_some() => {
+                text.push_str(&String::from_utf8_lossy(&e));
+            }
+            Event::End(e) => {
+                depth = depth.saturating_sub(1);
+                end(
+                    e.local_name().as_ref(),
+                    &mut nzb,
+                    &mut file,
+                    &mut collecting,
+                    &text,
+                )?;
+                text.clear();
+            }
+            Event::Eof if depth > 0 => {
+                return Err(NzbError::ParseError(
+                    "NZB ends before its last element is closed".to_string(),
+                )
+                .into());
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    if nzb.files.is_empty() {
+        return Err(NzbError::EmptyNzb(None).into());
+    }
+    nzb.files.sort_by(|a, b| a.subject.cmp(&b.subject));
+    for (i, file) in nzb.files.iter_mut().enumerate() {
+        file.index = i + 1;
+    }
+    Ok(nzb)
+}
+
+/// A `<file>` with its attributes and nothing in it yet
+fn start_file(e: &BytesStart) -> Result<NzbFile> {
+    let required = |name: &str| {
+        attribute(e, name.as_bytes()).ok_or_else(|| {
+            NzbError::ParseError(format!(
+                "Invalid or missing required attribute '{}' in a 'file' element",
+                name
+            ))
+        })
+    };
+    let date = required("date")?.trim().parse::<i64>().map_err(|_| {
+        NzbError::ParseError(
+            "Invalid or missing required attribute 'date' in a 'file' element".to_string(),
+        )
+    })?;
+    Ok(NzbFile {
+        index: 0,
+        poster: required("poster")?,
+        date: date as u64,
+        subject: required("subject")?,
+        groups: NzbGroups { group: Vec::new() },
+        segments: NzbSegments {
+            segment: Vec::new(),
+        },
+        name: None,
+    })
+}
+
+/// Close the element `name`, whose text was `text`
+fn end(
+    name: &[u8],
+    nzb: &mut ParsedNzb,
+    file: &mut Option<NzbFile>,
+    collecting: &mut Option<Collecting>,
+    text: &str,
+) -> Result<()> {
+    match (name, collecting.take()) {
+        (b"meta", Some(Collecting::Meta(kind))) if !text.is_empty() => match kind.as_deref() {
+            Some("title") => {
+                nzb.title.get_or_insert_with(|| text.to_string());
+            }
+            Some("category") => {
+                nzb.category.get_or_insert_with(|| text.to_string());
+            }
+            Some("password") if !nzb.passwords.iter().any(|p| p == text) => {
+                nzb.passwords.push(text.to_string());
+            }
+            _ => {}
+        },
+        (b"group", Some(Collecting::Group)) if !text.is_empty() => {
+            if let Some(file) = file {
+                file.groups.group.push(NzbGroup {
+                    name: text.to_string(),
+                });
+            }
+        }
+        (b"segment", Some(Collecting::Segment(Some((bytes, number))))) if !text.is_empty() => {
+            if let Some(file) = file {
+                file.segments.segment.push(NzbSegment {
+                    bytes,
+                    number,
+                    message_id: text.to_string(),
+                });
+            }
+        }
+        (b"file", _) => {
+            if let Some(mut file) = file.take() {
+                if file.groups.group.is_empty() {
+                    return Err(NzbError::ParseError(format!(
+                        "File '{}' lists no groups",
+                        file.subject
+                    ))
+                    .into());
+                }
+                if file.segments.segment.is_empty() {
+                    return Err(NzbError::ParseError(format!(
+                        "File '{}' lists no segments",
+                        file.subject
+                    ))
+                    .into());
+                }
+                file.groups.group.sort_by(|a, b| a.name.cmp(&b.name));
+                // Numbers that can't order the segments leave the listed order
+                if file.numbering_usable() {
+                    file.segments.segment.sort_by_key(|s| s.number);
+                }
+                nzb.files.push(file);
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Unescaped value of the attribute with local name `name`
+fn attribute(e: &BytesStart, name: &[u8]) -> Option<String> {
+    let attr = e
+        .attributes()
+        .flatten()
+        .find(|a| a.key.local_name().as_ref() == name)?;
+    let value = attr
+        .unescape_value()
+        .unwrap_or_else(|_| Cow::Owned(String::from_utf8_lossy(&attr.value).into_owned()));
+    Some(value.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_matches_nzb_rs_rules() {
+        let xml = r#"<?xml version="1.0" encoding="iso-8859-1" ?>
+        <!DOCTYPE nzb PUBLIC "-//newzBin//DTD NZB 1.1//EN" "http://www.newzbin.com/DTD/nzb/nzb-1.1.dtd">
+        <n:nzb xmlns:n="http://www.newzbin.com/DTD/2003/nzb">
+            <n:head>
+                <n:meta type="Title">Show &amp; Tell</n:meta>
+                <n:meta type="password">one</n:meta>
+                <n:meta type="password">one</n:meta>
+                <n:meta type="password"><![CDATA[two<>]]></n:meta>
+            </n:head>
+            <n:file poster="Joe &lt;joe@example.com&gt;" date="1071674882" subject="&quot;b.rar&quot; yEnc (1/2)">
+                <n:groups><n:group>alt.b</n:group><n:group>alt.a</n:group></n:groups>
+                <n:segments>
+                    <n:segment bytes="200" number="2">b2@x</n:segment>
+                    <n:segment bytes="oops" number="3">b3@x</n:segment>
+                    <n:segment bytes="100" number="1">b1&amp;@x</n:segment>
+                </n:segments>
+            </n:file>
+            <n:file poster="p" date="1071674882" subject="a &nbsp; quirk">
+                <n:groups><n:group>alt.a</n:group></n:groups>
+                <n:segments><n:segment bytes="5" number="1">a1@x</n:segment></n:segments>
+            </n:file>
+        </n:nzb>"#;
+        let nzb = parse(xml.as_bytes()).unwrap();
+
+        assert_eq!(nzb.title.as_deref(), Some("Show & Tell"));
+        assert_eq!(nzb.passwords, ["one", "two<>"]);
+        assert_eq!(nzb.files.len(), 2);
+
+        // Sorted by subject, unknown entities kept as written
+        let (b, a) = (&nzb.files[0], &nzb.files[1]);
+        assert_eq!((b.index, b.subject.as_str()), (1, "\"b.rar\" yEnc (1/2)"));
+        assert_eq!((a.index, a.subject.as_str()), (2, "a &nbsp; quirk"));
+        assert_eq!(b.poster, "Joe <joe@example.com>");
+        assert_eq!(b.date, 1071674882);
+        let groups: Vec<&str> = b.groups.group.iter().map(|g| g.name.as_str()).collect();
+        assert_eq!(groups, ["alt.a", "alt.b"]);
+        let segments: Vec<(u32, u64, &str)> = b
+            .segments
+            .segment
+            .iter()
+            .map(|s| (s.number, s.bytes, s.message_id.as_str()))
+            .collect();
+        assert_eq!(segments, [(1, 100, "b1&@x"), (2, 200, "b2@x")]);
+    }
+
+    #[test]
+    fn test_stream_rejects_incomplete_nzbs() {
+        let file = |attrs: &str, body: &str| format!("<nzb><file {}>{}</file></nzb>", attrs, body);
+        let groups = "<groups><group>alt.a</group></groups>";
+        let segments = r#"<segments><segment bytes="1" number="1">a@x</segment></segments>"#;
+        let attrs = r#"poster="p" date="1" subject="s""#;
+
+        for xml in [
+            "<nzb></nzb>".to_string(),
+            file(
+                r#"poster="p" subject="s""#,
+                &format!("{}{}", groups, segments),
+            ),
+            file(
+                r#"poster="p" date="soon" subject="s""#,
+                &format!("{}{}", groups, segments),
+            ),
+            file(attrs, segments),
+            file(attrs, groups),
+            file(attrs, &format!("{}{}", groups, segments)).replace("</nzb>", ""),
+            "<nzb><file></nzb>".to_string(),
+        ] {
+            assert!(parse(xml.as_bytes()).is_err(), "{}", xml);
+        }
+        assert!(parse(file(attrs, &format!("{}{}", groups, segments)).as_bytes()).is_ok());
+    }
+}
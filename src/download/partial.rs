@@ -0,0 +1,156 @@
+//! Files a download left incomplete
+//!
+//! A file still missing segments once PAR2 has had its go is deleted, or with
+//! `download.keep_partial` left in place and listed in a `.dlnzb-failed`
+//! marker in the download directory, mapping each file name to the
+//! message-ids it is missing. The next run resumes listed files, fetching
+//! just those segments, instead of skipping them as already there, and the
+//! marker goes away once nothing is incomplete.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use super::DownloadResult;
+
+/// Name of the marker listing kept incomplete files
+pub const FAILED_MARKER: &str = ".dlnzb-failed";
+
+/// What became of the files still missing segments
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PartialFiles {
+    /// Deleted incomplete files
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub removed: Vec<PathBuf>,
+    /// Incomplete files left in place and listed in the marker
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub kept: Vec<PathBuf>,
+}
+
+impl PartialFiles {
+    pub fn is_empty(&self) -> bool {
+        self.removed.is_empty() && self.kept.is_empty()
+    }
+}
+
+/// Delete or keep the files in `results` that are missing segments
+///
+/// `dir`'s marker is rewritten to list what is kept, and removed when
+/// nothing is. Failures are logged rather than returned: the download
+/// itself is already over.
+pub async fn clean_up(dir: &Path, results: &[DownloadResult], keep: bool) -> PartialFiles {
+    let mut partial = PartialFiles::default();
+    let mut marker = read_marker(dir).await;
+
+    for result in results {
+        marker.remove(&result.filename);
+        if result.segments_failed == 0 || !result.path.exists() {
+            continue;
+        }
+        if keep {
+            let mut missing = result.failed_message_ids.clone();
+            missing.sort();
+            marker.insert(result.filename.clone(), missing);
+            partial.kept.push(result.path.clone());
+            continue;
+        }
+        match tokio::fs::remove_file(&result.path).await {
+            Ok(()) => {
+                tracing::debug!("Removed incomplete file: {}", result.path.display());
+                partial.removed.push(result.path.clone());
+            }
+            Err(e) => tracing::warn!("Failed to remove {}: {}", result.path.display(), e),
+        }
+    }
+
+    let path = dir.join(FAILED_MARKER);
+    let written = if marker.is_empty() {
+        match tokio::fs::remove_file(&path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    } else {
+        let json = serde_json::to_vec_pretty(&marker).unwrap_or_default();
+        tokio::fs::write(&path, json).await
+    };
+    if let Err(e) = written {
+        tracing::warn!("Failed to update {}: {}", path.display(), e);
+    }
+
+    partial
+}
+
+/// Whether `dir`'s marker lists `filename` as incomplete
+pub async fn is_marked(dir: &Path, filename: &str) -> bool {
+    read_marker(dir).await.contains_key(filename)
+}
+
+/// Missing message-ids per kept file, from `dir`'s marker
+pub async fn read_marker(dir: &Path) -> BTreeMap<String, Vec<String>> {
+    match tokio::fs::read(dir.join(FAILED_MARKER)).await {
+        Ok(json) => serde_json::from_slice(&json).unwrap_or_else(|e| {
+            tracing::warn!("Ignoring unreadable {}: {}", FAILED_MARKER, e);
+            BTreeMap::new()
+        }),
+        Err(_) => BTreeMap::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(dir: &Path, name: &str, failed: &[&str]) -> DownloadResult {
+        let path = dir.join(name);
+        std::fs::write(&path, b"data").unwrap();
+        DownloadResult {
+            filename: name.to_string(),
+            path,
+            size: 4,
+            segments_downloaded: 1,
+            segments_failed: failed.len(),
+            segments_missing: failed.len(),
+            failed_message_ids: failed.iter().map(|id| id.to_string()).collect(),
+            group: "alt.binaries.test".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_marker_follows_kept_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let complete = result(dir.path(), "a.bin", &[]);
+        let broken = result(dir.path(), "b.bin", &["2@x", "1@x"]);
+
+        let results = [complete, broken];
+        let partial = clean_up(dir.path(), &results, true).await;
+        assert_eq!(partial.kept, [results[1].path.clone()]);
+        assert!(results[1].path.exists());
+        let marker = read_marker(dir.path()).await;
+        assert_eq!(marker.len(), 1);
+        assert_eq!(marker["b.bin"], ["1@x", "2@x"]);
+        assert!(is_marked(dir.path(), "b.bin").await);
+        assert!(!is_marked(dir.path(), "a.bin").await);
+
+        // Completed on a later run: off the list, and the marker goes
+        let fixed = result(dir.path(), "b.bin", &[]);
+        let partial = clean_up(dir.path(), &[fixed], true).await;
+        assert!(partial.is_empty());
+        assert!(!dir.path().join(FAILED_MARKER).exists());
+    }
+
+    #[tokio::test]
+    async fn test_incomplete_files_are_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let complete = result(dir.path(), "a.bin", &[]);
+        let broken = result(dir.path(), "b.bin", &["1@x"]);
+
+        let results = [complete, broken];
+        let partial = clean_up(dir.path(), &results, false).await;
+        assert_eq!(partial.removed, [results[1].path.clone()]);
+        assert!(partial.kept.is_empty());
+        assert!(results[0].path.exists());
+        assert!(!results[1].path.exists());
+        assert!(!dir.path().join(FAILED_MARKER).exists());
+    }
+}
@@ -0,0 +1,228 @@
+//! Modes and ownership of what a download creates
+//!
+//! Files and folders are created under the process umask, which under a
+//! service manager is often 077 and leaves them unreadable to anything else.
+//! `download.file_mode` and `download.dir_mode` set the mode outright once a
+//! file is finished or a folder is made, and `download.chown` hands them to
+//! another user and/or group. All three are Unix-only and ignored elsewhere;
+//! left unset, nothing is touched.
+
+use std::path::Path;
+#[cfg(unix)]
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::config::DownloadConfig;
+use crate::error::ConfigError;
+
+/// Set once a chown has failed, so a run without the privilege warns once
+#[cfg(unix)]
+static CHOWN_WARNED: AtomicBool = AtomicBool::new(false);
+
+/// Modes and owner to give created files and folders
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Permissions {
+    file_mode: Option<u32>,
+    dir_mode: Option<u32>,
+    /// User and group ids; `None` leaves that half as it is
+    owner: Option<(Option<u32>, Option<u32>)>,
+}
+
+impl Permissions {
+    /// Read the `download` settings, resolving user and group names
+    pub fn from_config(download: &DownloadConfig) -> Result<Self, ConfigError> {
+        if cfg!(not(unix)) {
+            return Ok(Self::default());
+        }
+        let mode = |field: &str, value: Option<&str>| {
+            value
+                .map(|v| {
+                    parse_mode(v).ok_or_else(|| ConfigError::Invalid {
+                        field: format!("download.{}", field),
+                        reason: format!("'{}' is not an octal mode like 0644", v),
+                    })
+                })
+                .transpose()
+        };
+        Ok(Self {
+            file_mode: mode("file_mode", download.file_mode.as_deref())?,
+            dir_mode: mode("dir_mode", download.dir_mode.as_deref())?,
+            owner: download
+                .chown
+                .as_deref()
+                .map(|spec| {
+                    parse_owner(spec).map_err(|reason| ConfigError::Invalid {
+                        field: "download.chown".to_string(),
+                        reason,
+                    })
+                })
+                .transpose()?,
+        })
+    }
+
+    /// Whether nothing is to be changed
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Give a finished file `file_mode` and the configured owner
+    pub fn apply_file(&self, path: &Path) {
+        self.apply(path, self.file_mode);
+    }
+
+    /// Give a folder `dir_mode` and the configured owner
+    pub fn apply_dir(&self, path: &Path) {
+        self.apply(path, self.dir_mode);
+    }
+
+    /// Apply to `root` and every folder and file under it
+    ///
+    /// Symlinks are left alone, so nothing outside `root` is changed.
+    pub fn apply_tree(&self, root: &Path) {
+        if self.is_default() {
+            return;
+        }
+        self.apply_dir(root);
+        let Ok(entries) = std::fs::read_dir(root) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            match entry.file_type() {
+                Ok(kind) if kind.is_dir() => self.apply_tree(&entry.path()),
+                Ok(kind) if kind.is_file() => self.apply_file(&entry.path()),
+                _ => {}
+            }
+        }
+    }
+
+    /// Owner first: a chown by anyone but root clears setuid and setgid bits
+    #[cfg(unix)]
+    fn apply(&self, path: &Path, mode: Option<u32>) {
+        use std::os::unix::fs::PermissionsExt;
+
+        if let Some((uid, gid)) = self.owner {
+            if let Err(e) = std::os::unix::fs::chown(path, uid, gid) {
+                if CHOWN_WARNED.swap(true, Ordering::Relaxed) {
+                    tracing::debug!("Failed to change owner of {}: {}", path.display(), e);
+                } else {
+                    tracing::warn!(
+                        "Failed to change owner of {}: {} (download.chown needs root or CAP_CHOWN)",
+                        path.display(),
+                        e
+                    );
+                }
+            }
+        }
+        if let Some(mode) = mode {
+            if let Err(e) = std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)) {
+                tracing::warn!("Failed to set mode {:o} on {}: {}", mode, path.display(), e);
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn apply(&self, _path: &Path, _mode: Option<u32>) {}
+}
+
+/// Parse an octal mode such as `0644`, `644` or `0o2775`
+fn parse_mode(value: &str) -> Option<u32> {
+    let digits = value.strip_prefix("0o").unwrap_or(value);
+    if digits.is_empty() {
+        return None;
+    }
+    u32::from_str_radix(digits, 8)
+        .ok()
+        .filter(|&mode| mode <= 0o7777)
+}
+
+/// Parse `user`, `user:group` or `:group`, by name or numeric id
+fn parse_owner(spec: &str) -> Result<(Option<u32>, Option<u32>), String> {
+    let (user, group) = spec.split_once(':').unwrap_or((spec, ""));
+    let uid = (!user.is_empty())
+        .then(|| lookup_id(user, "/etc/passwd").ok_or(format!("Unknown user '{}'", user)))
+        .transpose()?;
+    let gid = (!group.is_empty())
+        .then(|| lookup_id(group, "/etc/group").ok_or(format!("Unknown group '{}'", group)))
+        .transpose()?;
+    if uid.is_none() && gid.is_none() {
+        return Err(format!(
+            "'{}' is not user, user:group or :group",
+            spec.trim()
+        ));
+    }
+    Ok((uid, gid))
+}
+
+/// A numeric id, or the id `name` has in `database` (`/etc/passwd` or
+/// `/etc/group`, which both keep it in the third field)
+fn lookup_id(name: &str, database: &str) -> Option<u32> {
+    if let Ok(id) = name.parse() {
+        return Some(id);
+    }
+    std::fs::read_to_string(database)
+        .ok()?
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .find(|fields| fields.first() == Some(&name))
+        .and_then(|fields| fields.get(2)?.parse().ok())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    fn mode(path: &Path) -> u32 {
+        std::fs::metadata(path).unwrap().permissions().mode() & 0o7777
+    }
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!(parse_mode("0644"), Some(0o644));
+        assert_eq!(parse_mode("755"), Some(0o755));
+        assert_eq!(parse_mode("0o2775"), Some(0o2775));
+        assert_eq!(parse_mode("0888"), None);
+        assert_eq!(parse_mode("17777"), None);
+        assert_eq!(parse_mode(""), None);
+    }
+
+    #[test]
+    fn test_parse_owner() {
+        assert_eq!(parse_owner("1000:100"), Ok((Some(1000), Some(100))));
+        assert_eq!(parse_owner("1000"), Ok((Some(1000), None)));
+        assert_eq!(parse_owner(":100"), Ok((None, Some(100))));
+        assert_eq!(parse_owner("root:root"), Ok((Some(0), Some(0))));
+        assert!(parse_owner("no-such-user-here").is_err());
+        assert!(parse_owner(":").is_err());
+    }
+
+    #[test]
+    fn test_from_config_rejects_bad_values() {
+        let mut download = DownloadConfig::default();
+        assert!(Permissions::from_config(&download).unwrap().is_default());
+        download.file_mode = Some("rw-r--r--".to_string());
+        assert!(Permissions::from_config(&download).is_err());
+    }
+
+    #[test]
+    fn test_apply_tree() {
+        let dir = tempfile::tempdir().unwrap();
+        let sub = dir.path().join("Subs");
+        std::fs::create_dir(&sub).unwrap();
+        std::fs::write(sub.join("a.srt"), b"").unwrap();
+        std::fs::write(dir.path().join("a.mkv"), b"").unwrap();
+        let owner = std::fs::metadata(dir.path()).unwrap();
+
+        let permissions = Permissions {
+            file_mode: Some(0o640),
+            dir_mode: Some(0o2750),
+            // Handing things to their current owner works without privileges
+            owner: Some((Some(owner.uid()), Some(owner.gid()))),
+        };
+        permissions.apply_tree(dir.path());
+
+        assert_eq!(mode(dir.path()), 0o2750);
+        assert_eq!(mode(&sub), 0o2750);
+        assert_eq!(mode(&sub.join("a.srt")), 0o640);
+        assert_eq!(mode(&dir.path().join("a.mkv")), 0o640);
+    }
+}
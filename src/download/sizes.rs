@@ -0,0 +1,163 @@
+//! Decoded sizes of finished files
+//!
+//! The NZB's `bytes` count encoded articles, headers and all, so what a file
+//! decodes to is a few percent less than they add up to. Each file that
+//! downloads whole has its decoded segment sizes recorded in a
+//! `.dlnzb-segments` file next to it, and the next run compares a file
+//! already there against the size they add up to; a file without a record
+//! is held to the NZB's total as before.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use super::DownloadResult;
+
+/// Name of the file recording finished files' segment sizes
+pub const SEGMENTS_FILE: &str = ".dlnzb-segments";
+
+/// How far, in percent of the decoded size, the NZB's total may be off
+/// before it's worth a warning
+pub const SIZE_TOLERANCE_PERCENT: u64 = 5;
+
+/// Decoded sizes of a file's segments, in segment order
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SegmentSizes {
+    /// Where the file ends: the end of its last segment
+    pub size: u64,
+    pub segments: Vec<u64>,
+}
+
+impl SegmentSizes {
+    /// Sizes from where `result`'s segments landed
+    pub fn of(result: &DownloadResult) -> Self {
+        Self {
+            size: result
+                .segment_map
+                .iter()
+                .map(|span| span.offset + span.len)
+                .max()
+                .unwrap_or(0),
+            segments: result.segment_map.iter().map(|span| span.len).collect(),
+        }
+    }
+}
+
+/// Whether `declared` (the NZB's total) is further from `decoded` than
+/// [`SIZE_TOLERANCE_PERCENT`] allows
+pub fn diverges(declared: u64, decoded: u64) -> bool {
+    declared.abs_diff(decoded) * 100 > decoded * SIZE_TOLERANCE_PERCENT
+}
+
+/// The decoded size recorded for `filename` in `dir`, if any
+pub async fn recorded_size(dir: &Path, filename: &str) -> Option<u64> {
+    read_sizes(dir)
+        .await
+        .remove(filename)
+        .map(|sizes| sizes.size)
+}
+
+/// Every decoded size recorded in `dir`, by file name
+pub async fn recorded_sizes(dir: &Path) -> BTreeMap<String, u64> {
+    read_sizes(dir)
+        .await
+        .into_iter()
+        .map(|(name, sizes)| (name, sizes.size))
+        .collect()
+}
+
+/// Record the segment sizes of the files in `results` that downloaded whole
+///
+/// Files downloaded again but incomplete lose their record. Failures are
+/// logged; without a record a file is only held to the NZB's total.
+pub async fn record(dir: &Path, results: &[DownloadResult]) {
+    let mut sizes = read_sizes(dir).await;
+    let before = sizes.clone();
+    for result in results.iter().filter(|r| r.skipped.is_none()) {
+        if result.segments_failed > 0 || result.segment_map.is_empty() {
+            sizes.remove(&result.filename);
+        } else {
+            sizes.insert(result.filename.clone(), SegmentSizes::of(result));
+        }
+    }
+    if sizes == before {
+        return;
+    }
+
+    let path = dir.join(SEGMENTS_FILE);
+    let json = serde_json::to_vec(&sizes).unwrap_or_default();
+    if let Err(e) = tokio::fs::write(&path, json).await {
+        tracing::warn!("Failed to update {}: {}", path.display(), e);
+    }
+}
+
+/// Recorded sizes by file name, from `dir`'s [`SEGMENTS_FILE`]
+async fn read_sizes(dir: &Path) -> BTreeMap<String, SegmentSizes> {
+    match tokio::fs::read(dir.join(SEGMENTS_FILE)).await {
+        Ok(json) => serde_json::from_slice(&json).unwrap_or_else(|e| {
+            tracing::warn!("Ignoring unreadable {}: {}", SEGMENTS_FILE, e);
+            BTreeMap::new()
+        }),
+        Err(_) => BTreeMap::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::download::SegmentSpan;
+
+    fn result(name: &str, lens: &[u64], failed: usize) -> DownloadResult {
+        let mut offset = 0;
+        let segment_map = lens
+            .iter()
+            .enumerate()
+            .map(|(index, &len)| {
+                let span = SegmentSpan {
+                    number: index as u32 + 1,
+                    message_id: format!("{}@x", index),
+                    offset,
+                    len,
+                };
+                offset += len;
+                span
+            })
+            .collect();
+        DownloadResult {
+            filename: name.to_string(),
+            path: name.into(),
+            size: offset,
+            segments_downloaded: lens.len() - failed,
+            segments_failed: failed,
+            segments_missing: failed,
+            group: "alt.binaries.test".to_string(),
+            segment_map,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sizes_recorded_for_whole_files_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let results = [
+            result("a.bin", &[768_000, 768_000, 1000], 0),
+            result("b.bin", &[768_000, 768_000], 1),
+        ];
+        record(dir.path(), &results).await;
+        assert_eq!(recorded_size(dir.path(), "a.bin").await, Some(1_537_000));
+        assert_eq!(recorded_size(dir.path(), "b.bin").await, None);
+
+        // Downloaded again and left incomplete: the record goes
+        record(dir.path(), &[result("a.bin", &[768_000, 0], 1)]).await;
+        assert_eq!(recorded_size(dir.path(), "a.bin").await, None);
+    }
+
+    #[test]
+    fn test_diverges_beyond_tolerance() {
+        // yEnc and headers add a few percent
+        assert!(!diverges(1_030_000, 1_000_000));
+        assert!(diverges(1_100_000, 1_000_000));
+        assert!(diverges(900_000, 1_000_000));
+        assert!(!diverges(0, 0));
+    }
+}
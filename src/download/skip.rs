@@ -0,0 +1,443 @@
+//! Whether a file already in the download folder counts as downloaded
+//!
+//! A file there at its expected size is normally skipped: the decoded size
+//! recorded in `.dlnzb-segments` when it finished, or without a record the
+//! size the NZB adds up to. A run that left holes of zeros in a file of the
+//! right size gets past that.
+//! `download.skip_verification` says how much more is checked: `size`
+//! nothing more, `none` downloads everything again, and `quick-hash`
+//! compares the size and CRC32s of the first and last MiB with the ones
+//! recorded in a `.dlnzb-hashes` file when the file finished downloading
+//! (decoded files are smaller than the articles the NZB adds up, so the
+//! recorded size is the one that counts). A file with no record is
+//! downloaded again, so switching to `quick-hash` costs one more download
+//! of what's already there.
+//!
+//! A file there at another size is left to `download.existing_files`:
+//! moved aside to `name.old`, overwritten, or kept and left out. Nothing is
+//! truncated before that's decided. Files an earlier run kept incomplete
+//! and listed in `.dlnzb-failed` aren't held to any of this: they resume,
+//! fetching only the segments the marker lists.
+//!
+//! Files left out for other reasons — filtered out by `--only`/`--exclude`,
+//! listing the same articles as a file before them, listing no segments,
+//! declared smaller than `download.min_file_size`, or posted only to
+//! newsgroups the servers don't carry — are never fetched either;
+//! [`SkipReason`] says which, so summaries, JSON and history can tell them
+//! apart.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use super::nzb::bare_message_id;
+use super::{DownloadResult, NzbFile};
+use crate::checksum;
+use crate::config::SkipVerification;
+
+/// Name of the file recording finished files' quick hashes
+pub const HASHES_FILE: &str = ".dlnzb-hashes";
+
+/// Bytes hashed at each end of a file
+const SAMPLE_SIZE: u64 = 1024 * 1024;
+
+/// Size of a file plus CRC32s of its first and last [`SAMPLE_SIZE`] bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuickHash {
+    pub size: u64,
+    pub head: u32,
+    pub tail: u32,
+}
+
+impl QuickHash {
+    /// Hash the file at `path` (blocking)
+    pub fn of(path: &Path) -> std::io::Result<Self> {
+        let mut file = File::open(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            size,
+            head: checksum::crc32(&mut file, 0, SAMPLE_SIZE, &|_| {})?,
+            tail: checksum::crc32(
+                &mut file,
+                size.saturating_sub(SAMPLE_SIZE),
+                SAMPLE_SIZE,
+                &|_| {},
+            )?,
+        })
+    }
+}
+
+/// Why a file wasn't downloaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    /// Already in the folder and passed the `skip_verification` check
+    AlreadyComplete,
+    /// Left out by `--only`, `--exclude` or `--select`
+    Filtered,
+    /// Lists the same articles as a file before it
+    DuplicateFile,
+    /// Lists no segments to fetch
+    NoSegments,
+    /// Declared smaller than `download.min_file_size`
+    BelowMinSize,
+    /// Posted only to newsgroups no server carries (with
+    /// `download.skip_unavailable_groups`)
+    GroupUnavailable,
+    /// Already in the folder at another size, and kept with
+    /// `download.existing_files = "skip"`
+    KeptExisting,
+}
+
+impl SkipReason {
+    /// Short lowercase description, as in "2 already complete"
+    pub fn describe(self) -> &'static str {
+        match self {
+            Self::AlreadyComplete => "already complete",
+            Self::Filtered => "filtered",
+            Self::DuplicateFile => "duplicate",
+            Self::NoSegments => "no segments",
+            Self::BelowMinSize => "below minimum size",
+            Self::GroupUnavailable => "group not on server",
+            Self::KeptExisting => "kept existing file",
+        }
+    }
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.describe())
+    }
+}
+
+/// A file left out before downloading, and why
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SkippedFile {
+    pub filename: String,
+    /// Bytes the NZB declares for it
+    pub size: u64,
+    pub reason: SkipReason,
+}
+
+impl SkippedFile {
+    pub fn of(file: &NzbFile, reason: SkipReason) -> Self {
+        Self {
+            filename: file.filename(),
+            size: file.segments.segment.iter().map(|s| s.bytes).sum(),
+            reason,
+        }
+    }
+}
+
+/// Counts by reason, most common first: "2 already complete, 1 filtered"
+pub fn breakdown(reasons: impl IntoIterator<Item = SkipReason>) -> String {
+    let mut counts: BTreeMap<SkipReason, usize> = BTreeMap::new();
+    for reason in reasons {
+        *counts.entry(reason).or_default() += 1;
+    }
+    let mut counts: Vec<_> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1));
+    counts
+        .iter()
+        .map(|(reason, count)| format!("{} {}", count, reason))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Whether `download.min_file_size` leaves `file` out
+///
+/// PAR2 files never are: repair needs the small index file as much as the
+/// volumes.
+pub fn below_min_size(file: &NzbFile, min_size: u64) -> bool {
+    file.size() < min_size && file.par2_kind().is_none()
+}
+
+/// Split `files` into those worth fetching and those that can't be or
+/// needn't be: files listing no segments, files declared smaller than
+/// `min_size` bytes, and files listing exactly the articles of a file
+/// before them
+pub fn screen_files<'a>(
+    files: &[&'a NzbFile],
+    min_size: u64,
+) -> (Vec<&'a NzbFile>, Vec<SkippedFile>) {
+    let mut seen: BTreeSet<Vec<&str>> = BTreeSet::new();
+    let mut kept = Vec::with_capacity(files.len());
+    let mut skipped = Vec::new();
+    for &file in files {
+        if file.segments.segment.is_empty() {
+            skipped.push(SkippedFile::of(file, SkipReason::NoSegments));
+            continue;
+        }
+        if below_min_size(file, min_size) {
+            skipped.push(SkippedFile::of(file, SkipReason::BelowMinSize));
+            continue;
+        }
+        let mut ids: Vec<&str> = file
+            .segments
+            .segment
+            .iter()
+            .map(|s| bare_message_id(&s.message_id))
+            .collect();
+        ids.sort_unstable();
+        if seen.insert(ids) {
+            kept.push(file);
+        } else {
+            tracing::info!(
+                "{}: same articles as an earlier file; skipping",
+                file.filename()
+            );
+            skipped.push(SkippedFile::of(file, SkipReason::DuplicateFile));
+        }
+    }
+    (kept, skipped)
+}
+
+/// What the check made of a file already in the folder
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipVerdict {
+    /// Not there
+    Absent,
+    /// There at this size rather than the expected one, with nothing
+    /// recorded about it
+    OtherSize(u64),
+    /// Complete as far as the check goes
+    Skip,
+    /// At the expected size, but turned down for the reason given
+    Rejected(&'static str),
+}
+
+/// Check `filename` in `dir` against `expected_size` the way `method` says
+pub async fn check_existing(
+    dir: &Path,
+    filename: &str,
+    expected_size: u64,
+    method: SkipVerification,
+) -> SkipVerdict {
+    let path = dir.join(filename);
+    let Ok(metadata) = tokio::fs::metadata(&path).await else {
+        return SkipVerdict::Absent;
+    };
+    let at_size = metadata.len() == expected_size;
+    match method {
+        SkipVerification::Size if at_size => SkipVerdict::Skip,
+        SkipVerification::Never if at_size => SkipVerdict::Rejected("skipping is off"),
+        SkipVerification::QuickHash => match read_hashes(dir).await.remove(filename) {
+            Some(recorded) if recorded.size == metadata.len() => {
+                match tokio::task::spawn_blocking(move || QuickHash::of(&path)).await {
+                    Ok(Ok(hash)) if hash == recorded => SkipVerdict::Skip,
+                    Ok(Ok(_)) => SkipVerdict::Rejected("quick hash differs"),
+                    _ => SkipVerdict::Rejected("unreadable"),
+                }
+            }
+            Some(_) => SkipVerdict::Rejected("size differs from the one recorded"),
+            None if at_size => SkipVerdict::Rejected("no quick hash recorded"),
+            None => SkipVerdict::OtherSize(metadata.len()),
+        },
+        _ => SkipVerdict::OtherSize(metadata.len()),
+    }
+}
+
+/// What became of a file already in the folder that wasn't skipped as
+/// complete
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExistingFile {
+    /// Kept incomplete by an earlier run; only its missing segments were
+    /// fetched
+    Resumed,
+    /// At another size, and moved aside to this path
+    Renamed(PathBuf),
+    /// At another size, and overwritten
+    Overwritten,
+    /// At another size, and kept instead of downloaded
+    Kept,
+}
+
+/// Move the file at `path` aside to the first free `name.old`, `name.old2`
+/// and so on; returns where it went
+pub async fn move_aside(path: &Path) -> std::io::Result<PathBuf> {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut n = 1;
+    loop {
+        let suffix = if n == 1 { String::new() } else { n.to_string() };
+        let target = path.with_file_name(format!("{}.old{}", name, suffix));
+        if tokio::fs::symlink_metadata(&target).await.is_err() {
+            tokio::fs::rename(path, &target).await?;
+            return Ok(target);
+        }
+        n += 1;
+    }
+}
+
+/// Record the quick hashes of the files in `results` that downloaded whole
+///
+/// Files downloaded again but incomplete lose their record. The files are
+/// hashed on up to `threads` threads. Failures are logged; the files are
+/// there either way, and a missing record only means another download.
+pub async fn record(dir: &Path, results: &[DownloadResult], threads: usize) {
+    let mut hashes = read_hashes(dir).await;
+    let mut complete: Vec<(String, PathBuf)> = Vec::new();
+    for result in results.iter().filter(|r| r.skipped.is_none()) {
+        hashes.remove(&result.filename);
+        if result.segments_failed == 0 && result.segments_corrupt == 0 {
+            complete.push((result.filename.clone(), result.path.clone()));
+        }
+    }
+    let hashed = tokio::task::spawn_blocking(move || {
+        let hashed = checksum::in_parallel(&complete, threads, |(_, path)| QuickHash::of(path));
+        complete.into_iter().zip(hashed).collect::<Vec<_>>()
+    })
+    .await
+    .unwrap_or_default();
+    for ((filename, path), hash) in hashed {
+        match hash {
+            Ok(hash) => {
+                hashes.insert(filename, hash);
+            }
+            Err(e) => tracing::warn!("Failed to hash {}: {}", path.display(), e),
+        }
+    }
+
+    let path = dir.join(HASHES_FILE);
+    let json = serde_json::to_vec_pretty(&hashes).unwrap_or_default();
+    if let Err(e) = tokio::fs::write(&path, json).await {
+        tracing::warn!("Failed to update {}: {}", path.display(), e);
+    }
+}
+
+/// Recorded hashes by file name, from `dir`'s [`HASHES_FILE`]
+async fn read_hashes(dir: &Path) -> BTreeMap<String, QuickHash> {
+    match tokio::fs::read(dir.join(HASHES_FILE)).await {
+        Ok(json) => serde_json::from_slice(&json).unwrap_or_else(|e| {
+            tracing::warn!("Ignoring unreadable {}: {}", HASHES_FILE, e);
+            BTreeMap::new()
+        }),
+        Err(_) => BTreeMap::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_quick_hash_catches_zeroed_head() {
+        let dir = tempfile::tempdir().unwrap();
+        let data: Vec<u8> = (0..3 * SAMPLE_SIZE).map(|i| (i % 251) as u8).collect();
+        let path = dir.path().join("file.bin");
+        std::fs::write(&path, &data).unwrap();
+        let hashes = BTreeMap::from([("file.bin".to_string(), QuickHash::of(&path).unwrap())]);
+        std::fs::write(
+            dir.path().join(HASHES_FILE),
+            serde_json::to_vec(&hashes).unwrap(),
+        )
+        .unwrap();
+        let size = data.len() as u64;
+        let check = |method| check_existing(dir.path(), "file.bin", size, method);
+
+        assert_eq!(check(SkipVerification::QuickHash).await, SkipVerdict::Skip);
+        assert_eq!(
+            check_existing(dir.path(), "file.bin", size + 1, SkipVerification::Size).await,
+            SkipVerdict::Absent
+        );
+
+        // Same size, a hole of zeros at the start
+        let mut holed = data.clone();
+        holed[..4096].fill(0);
+        std::fs::write(&path, &holed).unwrap();
+        assert_eq!(check(SkipVerification::Size).await, SkipVerdict::Skip);
+        assert_eq!(
+            check(SkipVerification::QuickHash).await,
+            SkipVerdict::Rejected("quick hash differs")
+        );
+        assert!(matches!(
+            check(SkipVerification::Never).await,
+            SkipVerdict::Rejected(_)
+        ));
+        assert_eq!(
+            check_existing(dir.path(), "other.bin", 0, SkipVerification::QuickHash).await,
+            SkipVerdict::Absent
+        );
+    }
+
+    #[test]
+    fn test_quick_hash_of_small_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("small.nfo");
+        std::fs::write(&path, b"tiny").unwrap();
+        let hash = QuickHash::of(&path).unwrap();
+        assert_eq!(hash.size, 4);
+        // Both ends are the whole file
+        assert_eq!(hash.head, hash.tail);
+    }
+
+    #[test]
+    fn test_screen_files_leaves_out_duplicates_and_empty_files() {
+        let nzb: crate::download::Nzb = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <nzb xmlns="http://www.newzbin.com/DTD/2003/nzb">
+                <file poster="p" date="1" subject="&quot;a.rar&quot; yEnc (1/2)">
+                    <groups><group>alt.binaries.test</group></groups>
+                    <segments>
+                        <segment bytes="100" number="1">a1@x</segment>
+                        <segment bytes="100" number="2">a2@x</segment>
+                    </segments>
+                </file>
+                <file poster="p" date="1" subject="&quot;a.copy.rar&quot; yEnc (1/2)">
+                    <groups><group>alt.binaries.test</group></groups>
+                    <segments>
+                        <segment bytes="100" number="1">&lt;a2@x&gt;</segment>
+                        <segment bytes="100" number="2">a1@x</segment>
+                    </segments>
+                </file>
+                <file poster="p" date="1" subject="&quot;b.nfo&quot; yEnc (1/1)">
+                    <groups><group>alt.binaries.test</group></groups>
+                    <segments><segment bytes="10" number="1">b@x</segment></segments>
+                </file>
+            </nzb>"#
+            .parse()
+            .unwrap();
+        let mut empty = nzb.files()[2].clone();
+        empty.segments.segment.clear();
+        let files: Vec<&NzbFile> = nzb.files().iter().chain([&empty]).collect();
+
+        let (kept, skipped) = screen_files(&files, 0);
+        let kept: Vec<String> = kept.iter().map(|f| f.filename()).collect();
+        assert_eq!(kept, ["a.rar", "b.nfo"]);
+        let reasons: Vec<_> = skipped
+            .iter()
+            .map(|s| (s.filename.as_str(), s.reason))
+            .collect();
+        assert_eq!(
+            reasons,
+            [
+                ("a.copy.rar", SkipReason::DuplicateFile),
+                ("b.nfo", SkipReason::NoSegments)
+            ]
+        );
+        assert_eq!(skipped[0].size, 200);
+
+        // The 10-byte NFO is under a minimum size; PAR2 files never are
+        let (kept, skipped) = screen_files(&files, 50);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(skipped[1].reason, SkipReason::BelowMinSize);
+        let mut index = nzb.files()[2].clone();
+        index.subject = "\"a.par2\" yEnc (1/1)".to_string();
+        assert!(!below_min_size(&index, 50));
+    }
+
+    #[test]
+    fn test_breakdown_counts_by_reason() {
+        let reasons = [
+            SkipReason::Filtered,
+            SkipReason::AlreadyComplete,
+            SkipReason::AlreadyComplete,
+        ];
+        assert_eq!(breakdown(reasons), "2 already complete, 1 filtered");
+        assert_eq!(breakdown([]), "");
+    }
+}
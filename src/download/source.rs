@@ -0,0 +1,978 @@
+//! Where an NZB comes from: a file, stdin (`-`), an `http(s)://` URL or an
+//! indexer (`indexer:<name>:<id>`)
+//!
+//! URLs are fetched with redirects followed, bounded by [`FETCH_TIMEOUT`] and
+//! [`MAX_NZB_SIZE`], and named after the server's `Content-Disposition`
+//! filename when it sends one. Indexer arguments are turned into a URL by
+//! their `[[indexers]]` entry, with its API key, user agent and headers, and
+//! are shown without the key in errors. A `429` is waited out as `Retry-After`
+//! says, up to [`MAX_RETRY_AFTER`]. Input from any source may be gzip- or
+//! bzip2-compressed. Directory and glob arguments are expanded into the NZB
+//! files they contain by [`expand_inputs`].
+//!
+//! An NZB named like `Release.Name{{p4ssw0rd}}.nzb` carries its archive
+//! password: [`split_filename_password`] takes it off the folder name and
+//! [`LoadedNzb::password`] keeps it, out of logs and summaries.
+
+use std::borrow::Cow;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::compression;
+use super::nzb::Nzb;
+use crate::config::{Config, IndexerConfig, Secret};
+use crate::error::{ConfigError, DlNzbError, NzbError};
+
+type Result<T> = std::result::Result<T, DlNzbError>;
+
+/// Give up on an NZB URL that hasn't finished downloading after this long
+pub const FETCH_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Largest NZB accepted from a URL or stdin
+pub const MAX_NZB_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Times a rate-limited request is tried again
+const RATE_LIMIT_RETRIES: u32 = 3;
+
+/// Longest `Retry-After` waited out; a longer one fails the NZB straight away
+pub const MAX_RETRY_AFTER: Duration = Duration::from_secs(300);
+
+/// Subfolder `--move-processed` moves finished NZBs into
+pub const PROCESSED_DIR: &str = ".done";
+
+/// An NZB argument as given on the command line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NzbSource {
+    File(PathBuf),
+    Stdin,
+    Url(String),
+    /// `indexer:<name>:<id>`, fetched through the `[[indexers]]` entry `name`
+    Indexer {
+        name: String,
+        id: String,
+    },
+}
+
+impl NzbSource {
+    pub fn from_arg(arg: &Path) -> Self {
+        match arg.to_str() {
+            Some("-") => Self::Stdin,
+            Some(s) if s.starts_with("http://") || s.starts_with("https://") => {
+                Self::Url(s.to_string())
+            }
+            Some(s) if s.starts_with("indexer:") => {
+                let rest = &s["indexer:".len()..];
+                let (name, id) = rest.split_once(':').unwrap_or((rest, ""));
+                Self::Indexer {
+                    name: name.to_string(),
+                    id: id.to_string(),
+                }
+            }
+            _ => Self::File(arg.to_path_buf()),
+        }
+    }
+
+    /// Whether the NZB only exists in memory once loaded
+    pub fn is_remote(&self) -> bool {
+        !matches!(self, Self::File(_))
+    }
+}
+
+/// A parsed NZB plus what's needed to name and keep it
+#[derive(Debug)]
+pub struct LoadedNzb {
+    pub nzb: Nzb,
+    /// Folder name for the download (file stem, served filename, or title)
+    pub name: String,
+    /// Raw XML for NZBs that didn't come from a file, for `download.keep_nzb`
+    pub xml: Option<String>,
+    /// Archive password from a `{{password}}` at the end of the file name
+    pub password: Option<Secret>,
+}
+
+impl LoadedNzb {
+    /// Load from `source`, fetching URLs and indexer NZBs as `config` says
+    pub async fn load(source: &NzbSource, config: &Config) -> Result<Self> {
+        match source {
+            NzbSource::File(path) => {
+                let (name, password) = split_stem(file_stem(path));
+                Ok(Self {
+                    nzb: Nzb::from_file(path)?,
+                    name: name.unwrap_or_else(|| "download".to_string()),
+                    xml: None,
+                    password,
+                })
+            }
+            NzbSource::Stdin => {
+                let bytes = tokio::task::spawn_blocking(read_stdin)
+                    .await
+                    .map_err(|e| std::io::Error::other(e.to_string()))??;
+                Self::from_xml(compression::decode(bytes)?, None)
+            }
+            NzbSource::Url(url) => {
+                let request = FetchRequest {
+                    url: url.clone(),
+                    label: url.clone(),
+                    user_agent: &config.download.user_agent,
+                    headers: Vec::new(),
+                };
+                let (bytes, filename) = fetch(&request).await?;
+                let (name, password) = split_stem(
+                    filename
+                        .as_deref()
+                        .or_else(|| url_filename(url))
+                        .and_then(|f| file_stem(Path::new(f))),
+                );
+                Ok(Self {
+                    password,
+                    ..Self::from_xml(compression::decode(bytes)?, name)?
+                })
+            }
+            NzbSource::Indexer { name, id } => {
+                let invalid = |reason: String| ConfigError::Invalid {
+                    field: "FILE".to_string(),
+                    reason,
+                };
+                if id.is_empty() {
+                    let reason = format!("indexer:{} needs an NZB id: indexer:<name>:<id>", name);
+                    return Err(invalid(reason).into());
+                }
+                let indexer = config
+                    .indexers
+                    .iter()
+                    .find(|indexer| indexer.name == *name)
+                    .ok_or_else(|| invalid(format!("no [[indexers]] entry named '{}'", name)))?;
+                let request = FetchRequest::indexer(indexer, id, &config.download.user_agent);
+                let (bytes, filename) = fetch(&request).await?;
+                // Indexer URLs are API calls whose path names nothing; without
+                // a served filename the NZB's title names the folder
+                let (name, password) = split_stem(filename.and_then(|f| file_stem(Path::new(&f))));
+                Ok(Self {
+                    password,
+                    ..Self::from_xml(compression::decode(bytes)?, name)?
+                })
+            }
+        }
+    }
+
+    /// NZB from XML held in memory (stdin, a URL, or the download history)
+    pub fn from_xml(xml: String, name: Option<String>) -> Result<Self> {
+        let nzb: Nzb = xml.parse()?;
+        let name = name
+            .or_else(|| nzb.title().map(sanitize))
+            .unwrap_or_else(|| "download".to_string());
+        Ok(Self {
+            nzb,
+            name,
+            xml: Some(xml),
+            password: None,
+        })
+    }
+}
+
+impl Nzb {
+    /// Load an NZB from a path, an `http(s)://` URL, `indexer:<name>:<id>`,
+    /// or `-` for stdin
+    pub async fn fetch(path_or_url: impl AsRef<Path>, config: &Config) -> Result<Self> {
+        let source = NzbSource::from_arg(path_or_url.as_ref());
+        Ok(LoadedNzb::load(&source, config).await?.nzb)
+    }
+}
+
+/// Replace directory and glob arguments with the NZB files they match
+///
+/// Directories contribute their `.nzb`, `.nzb.gz` and `.nzb.bz2` files sorted
+/// by name (walking subdirectories when `recursive`, except [`PROCESSED_DIR`]);
+/// glob patterns are expanded in the same order. Either one matching nothing
+/// is an error. Other arguments pass through untouched.
+pub fn expand_inputs(args: &[PathBuf], recursive: bool) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for arg in args {
+        if NzbSource::from_arg(arg).is_remote() || arg.is_file() {
+            files.push(arg.clone());
+            continue;
+        }
+
+        let before = files.len();
+        if arg.is_dir() {
+            collect_dir(arg, recursive, &mut files)?;
+        } else if let Some(pattern) = arg.to_str().filter(|s| is_glob(s)) {
+            let paths = glob::glob(pattern).map_err(|e| ConfigError::Invalid {
+                field: "FILE".to_string(),
+                reason: format!("{}: {}", pattern, e),
+            })?;
+            files.extend(paths.filter_map(|p| p.ok()).filter(|p| p.is_file()));
+        } else {
+            // Let loading report the missing file
+            files.push(arg.clone());
+            continue;
+        }
+
+        if files.len() == before {
+            return Err(NzbError::NoInputMatches(arg.display().to_string()).into());
+        }
+    }
+    Ok(files)
+}
+
+fn collect_dir(dir: &Path, recursive: bool, files: &mut Vec<PathBuf>) -> Result<()> {
+    let mut entries: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .collect();
+    entries.sort();
+
+    for path in entries {
+        if path.is_dir() {
+            if recursive && path.file_name().is_some_and(|n| n != PROCESSED_DIR) {
+                collect_dir(&path, recursive, files)?;
+            }
+        } else if is_nzb_name(&path) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// NZB files that turned up in a download folder, sorted by path
+///
+/// Subdirectories are walked, except [`PROCESSED_DIR`]; paths in `inputs`
+/// (the NZB the folder was downloaded from, or a copy kept with it) are left
+/// out. An unreadable folder has none.
+pub fn nested_nzbs(dir: &Path, inputs: &[PathBuf]) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    if let Err(e) = collect_dir(dir, true, &mut files) {
+        tracing::debug!("Failed to look for NZBs in {}: {}", dir.display(), e);
+    }
+    let inputs: Vec<PathBuf> = inputs
+        .iter()
+        .map(|p| std::fs::canonicalize(p).unwrap_or_else(|_| p.clone()))
+        .collect();
+    files.retain(|file| {
+        let file = std::fs::canonicalize(file).unwrap_or_else(|_| file.clone());
+        !inputs.contains(&file)
+    });
+    files
+}
+
+/// Whether the file name ends in `.nzb`, `.nzb.gz` or `.nzb.bz2`
+pub fn is_nzb_name(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+        return false;
+    };
+    compression::strip_extension(name)
+        .to_ascii_lowercase()
+        .ends_with(".nzb")
+}
+
+fn is_glob(arg: &str) -> bool {
+    arg.contains(['*', '?', '['])
+}
+
+/// Move a finished NZB (and its `.toml` sidecar) into [`PROCESSED_DIR`]
+pub fn move_processed(nzb_path: &Path) -> std::io::Result<PathBuf> {
+    let parent = nzb_path.parent().unwrap_or(Path::new("."));
+    move_nzb(nzb_path, &parent.join(PROCESSED_DIR))
+}
+
+/// Move an NZB (and its `.toml` sidecar) into `dir`, creating it if needed
+pub fn move_nzb(nzb_path: &Path, dir: &Path) -> std::io::Result<PathBuf> {
+    std::fs::create_dir_all(dir)?;
+
+    let file_name = nzb_path.file_name().unwrap_or_default();
+    let target = dir.join(file_name);
+    std::fs::rename(nzb_path, &target)?;
+
+    let sidecar = crate::config::NzbOverrides::sidecar_path(nzb_path);
+    if sidecar.exists() {
+        std::fs::rename(&sidecar, crate::config::NzbOverrides::sidecar_path(&target))?;
+    }
+    Ok(target)
+}
+
+fn read_stdin() -> std::io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    std::io::stdin()
+        .take(MAX_NZB_SIZE + 1)
+        .read_to_end(&mut bytes)?;
+    if bytes.len() as u64 > MAX_NZB_SIZE {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("NZB on stdin is larger than {} bytes", MAX_NZB_SIZE),
+        ));
+    }
+    Ok(bytes)
+}
+
+/// One NZB to download over HTTP
+struct FetchRequest<'a> {
+    url: String,
+    /// What errors call it: the URL, or `indexer:<name>:<id>` so the API key
+    /// stays out of logs
+    label: String,
+    user_agent: &'a str,
+    headers: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> FetchRequest<'a> {
+    /// NZB `id` of `indexer`; `user_agent` unless the indexer has its own
+    fn indexer(indexer: &'a IndexerConfig, id: &str, user_agent: &'a str) -> Self {
+        Self {
+            url: indexer
+                .url
+                .replace("{id}", &percent_encode(id))
+                .replace("{apikey}", &percent_encode(indexer.api_key.expose())),
+            label: format!("indexer:{}:{}", indexer.name, id),
+            user_agent: if indexer.user_agent.is_empty() {
+                user_agent
+            } else {
+                &indexer.user_agent
+            },
+            headers: indexer
+                .headers
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str()))
+                .collect(),
+        }
+    }
+}
+
+/// Download an NZB, returning its body and any `Content-Disposition` filename
+async fn fetch(request: &FetchRequest<'_>) -> Result<(Vec<u8>, Option<String>)> {
+    let url = &request.label;
+    let error = |reason: String| NzbError::Fetch {
+        url: url.clone(),
+        reason,
+    };
+
+    let client = reqwest::Client::builder()
+        .user_agent(request.user_agent)
+        .timeout(FETCH_TIMEOUT)
+        .build()
+        .map_err(|e| error(e.to_string()))?;
+    let mut retries = 0;
+    let mut response = loop {
+        let mut builder = client.get(&request.url);
+        for (name, value) in &request.headers {
+            builder = builder.header(*name, *value);
+        }
+        // Without the URL, which may carry the API key
+        let response = builder
+            .send()
+            .await
+            .map_err(|e| error(e.without_url().to_string()))?;
+
+        let status = response.status();
+        match status.as_u16() {
+            401 | 403 => {
+                return Err(NzbError::FetchDenied {
+                    url: url.clone(),
+                    status: status.as_u16(),
+                }
+                .into())
+            }
+            404 => return Err(NzbError::FetchNotFound { url: url.clone() }.into()),
+            429 => {
+                let retry_after = response
+                    .headers()
+                    .get(reqwest::header::RETRY_AFTER)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| parse_retry_after(value, chrono::Utc::now()));
+                match retry_after.filter(|wait| *wait <= MAX_RETRY_AFTER) {
+                    Some(wait) if retries < RATE_LIMIT_RETRIES => {
+                        retries += 1;
+                        tracing::warn!(
+                            "{} is rate limited; trying again in {}s",
+                            url,
+                            wait.as_secs()
+                        );
+                        tokio::time::sleep(wait).await;
+                    }
+                    _ => {
+                        return Err(NzbError::RateLimited {
+                            url: url.clone(),
+                            retry_after,
+                        }
+                        .into())
+                    }
+                }
+            }
+            _ if !status.is_success() => {
+                return Err(error(format!("HTTP {}", status)).into());
+            }
+            _ => break response,
+        }
+    };
+
+    if response
+        .content_length()
+        .is_some_and(|len| len > MAX_NZB_SIZE)
+    {
+        return Err(error(format!("larger than {} bytes", MAX_NZB_SIZE)).into());
+    }
+
+    let filename = response
+        .headers()
+        .get(reqwest::header::CONTENT_DISPOSITION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(content_disposition_filename);
+
+    let mut body = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| error(e.without_url().to_string()))?
+    {
+        body.extend_from_slice(&chunk);
+        if body.len() as u64 > MAX_NZB_SIZE {
+            return Err(error(format!("larger than {} bytes", MAX_NZB_SIZE)).into());
+        }
+    }
+
+    Ok((body, filename))
+}
+
+/// Filename from a `Content-Disposition` header (`filename*=` or `filename=`)
+fn content_disposition_filename(header: &str) -> Option<String> {
+    let params: Vec<(&str, &str)> = header
+        .split(';')
+        .filter_map(|part| part.split_once('='))
+        .map(|(key, value)| (key.trim(), value.trim()))
+        .collect();
+
+    let encoded = params
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case("filename*"))
+        .and_then(|(_, value)| value.split_once("''"))
+        .map(|(_, name)| percent_decode(name));
+    let plain = || {
+        params
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case("filename"))
+            .map(|(_, value)| value.trim_matches('"').to_string())
+    };
+
+    encoded
+        .or_else(plain)
+        .map(|name| sanitize(&name))
+        .filter(|name| !name.is_empty())
+}
+
+/// `Retry-After` as a wait: delay seconds, or an HTTP date relative to `now`
+fn parse_retry_after(value: &str, now: chrono::DateTime<chrono::Utc>) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (at.with_timezone(&chrono::Utc) - now)
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+/// Percent-encode everything but unreserved URL characters
+fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Last path segment of a URL, ignoring the query string
+fn url_filename(url: &str) -> Option<&str> {
+    let path = url.split(['?', '#']).next()?;
+    let (_, after_scheme) = path.split_once("://")?;
+    let (_, path) = after_scheme.split_once('/')?;
+    path.rsplit('/').next().filter(|name| !name.is_empty())
+}
+
+/// Folder name for an NZB file: `show.nzb` and `show.nzb.gz` both give `show`
+fn file_stem(path: &Path) -> Option<String> {
+    let name = path.file_name()?.to_str()?;
+    Path::new(compression::strip_extension(name))
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .map(str::to_string)
+        .filter(|s| !s.is_empty())
+}
+
+/// Split `Release.Name{{p4ssw0rd}}` into the name and the archive password
+///
+/// As SABnzbd reads it, the password runs from the first `{{` to the `}}`
+/// ending the name, so braces in between belong to it. Empty braces are
+/// stripped but give no password.
+pub fn split_filename_password(stem: &str) -> (&str, Option<&str>) {
+    let Some(start) = stem.strip_suffix("}}").and_then(|inner| inner.find("{{")) else {
+        return (stem, None);
+    };
+    let password = &stem[start + 2..stem.len() - 2];
+    (
+        stem[..start].trim_end(),
+        Some(password).filter(|p| !p.is_empty()),
+    )
+}
+
+/// An NZB file name with the password of its `{{password}}` masked, for
+/// logs and summaries
+pub fn redact_filename_password(filename: &str) -> Cow<'_, str> {
+    let Some(start) = filename.find("{{") else {
+        return Cow::Borrowed(filename);
+    };
+    match filename.rfind("}}") {
+        Some(end) if end > start + 2 => Cow::Owned(format!(
+            "{}{{{{{}}}}}{}",
+            &filename[..start],
+            Secret::MASK,
+            &filename[end + 2..]
+        )),
+        _ => Cow::Borrowed(filename),
+    }
+}
+
+/// An NZB path or URL with the password of a `{{password}}` file name
+/// masked, for anything shown or kept: messages, logs, the JSON summary and
+/// the history
+pub fn redact_nzb_path(path: &Path) -> Cow<'_, Path> {
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return Cow::Borrowed(path);
+    };
+    match redact_filename_password(name) {
+        Cow::Borrowed(_) => Cow::Borrowed(path),
+        Cow::Owned(name) => Cow::Owned(path.with_file_name(name)),
+    }
+}
+
+/// Serialize an NZB path through [`redact_nzb_path`]
+pub(crate) fn serialize_nzb_path<S: serde::Serializer>(
+    path: &Path,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serde::Serialize::serialize(redact_nzb_path(path).as_ref(), serializer)
+}
+
+/// Folder name and filename password from an NZB's file stem
+fn split_stem(stem: Option<String>) -> (Option<String>, Option<Secret>) {
+    let Some(stem) = stem else {
+        return (None, None);
+    };
+    let (name, password) = split_filename_password(&stem);
+    (
+        Some(name.to_string()).filter(|name| !name.is_empty()),
+        password.map(Secret::from),
+    )
+}
+
+/// Make a server- or NZB-supplied name safe to use as a folder name
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>()
+        .trim_matches(|c: char| c == '.' || c.is_whitespace())
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source_from_arg() {
+        assert_eq!(NzbSource::from_arg(Path::new("-")), NzbSource::Stdin);
+        assert_eq!(
+            NzbSource::from_arg(Path::new("https://indexer.example/getnzb/abc")),
+            NzbSource::Url("https://indexer.example/getnzb/abc".to_string())
+        );
+        assert!(!NzbSource::from_arg(Path::new("show.nzb")).is_remote());
+        assert_eq!(
+            NzbSource::from_arg(Path::new("indexer:geek:a1b2:c3")),
+            NzbSource::Indexer {
+                name: "geek".to_string(),
+                id: "a1b2:c3".to_string()
+            }
+        );
+        assert!(NzbSource::from_arg(Path::new("indexer:geek")).is_remote());
+    }
+
+    #[test]
+    fn test_indexer_request() {
+        let indexer = IndexerConfig {
+            name: "geek".to_string(),
+            url: "https://geek.example/api?t=get&id={id}&apikey={apikey}".to_string(),
+            api_key: "k3y/+".into(),
+            headers: [("X-Api-Client".to_string(), "dl-nzb".to_string())].into(),
+            ..Default::default()
+        };
+        let request = FetchRequest::indexer(&indexer, "a b", "dl-nzb/1.0");
+        assert_eq!(
+            request.url,
+            "https://geek.example/api?t=get&id=a%20b&apikey=k3y%2F%2B"
+        );
+        assert_eq!(request.label, "indexer:geek:a b");
+        assert_eq!(request.user_agent, "dl-nzb/1.0");
+        assert_eq!(request.headers, [("X-Api-Client", "dl-nzb")]);
+
+        let own_agent = IndexerConfig {
+            user_agent: "Mozilla/5.0".to_string(),
+            ..indexer
+        };
+        let request = FetchRequest::indexer(&own_agent, "1", "dl-nzb/1.0");
+        assert_eq!(request.user_agent, "Mozilla/5.0");
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        let now = chrono::DateTime::parse_from_rfc3339("2015-10-21T07:28:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        assert_eq!(
+            parse_retry_after(" 120 ", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:29:30 GMT", now),
+            Some(Duration::from_secs(90))
+        );
+        // A date already past: no wait
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+    /// Answer one request per response, in order, on a local port; returns
+    /// the base URL and the requests as received
+    async fn serve_responses(
+        responses: Vec<String>,
+    ) -> (String, tokio::task::JoinHandle<Vec<String>>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let task = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for response in responses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0; 4096];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let n = stream.read(&mut buffer).await.unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buffer[..n]);
+                }
+                requests.push(String::from_utf8_lossy(&request).to_lowercase());
+                stream.write_all(response.as_bytes()).await.unwrap();
+                stream.shutdown().await.unwrap();
+            }
+            requests
+        });
+        (url, task)
+    }
+
+    fn config_with_indexer(url: &str) -> Config {
+        let mut config = Config::default();
+        config.download.user_agent = "dl-nzb-test".to_string();
+        config.indexers.push(IndexerConfig {
+            name: "geek".to_string(),
+            url: format!("{}/api?id={{id}}&apikey={{apikey}}", url),
+            api_key: "s3cret".into(),
+            headers: [("X-Api-Client".to_string(), "dl-nzb".to_string())].into(),
+            ..Default::default()
+        });
+        config
+    }
+
+    #[tokio::test]
+    async fn test_indexer_errors_by_status() {
+        let (url, server) = serve_responses(
+            [
+                "401 Unauthorized\r\n",
+                "404 Not Found\r\n",
+                "429 Too Many Requests\r\nRetry-After: 3600\r\n",
+            ]
+            .map(|head| {
+                format!(
+                    "HTTP/1.1 {}Content-Length: 0\r\nConnection: close\r\n\r\n",
+                    head
+                )
+            })
+            .to_vec(),
+        )
+        .await;
+        let config = config_with_indexer(&url);
+        let source = NzbSource::from_arg(Path::new("indexer:geek:42"));
+        let load = || LoadedNzb::load(&source, &config);
+
+        let denied = load().await.unwrap_err();
+        assert!(
+            matches!(
+                denied,
+                DlNzbError::Nzb(NzbError::FetchDenied { status: 401, .. })
+            ),
+            "{}",
+            denied
+        );
+        // The key never shows up in the message
+        assert!(!denied.to_string().contains("s3cret"), "{}", denied);
+        assert!(denied.to_string().contains("indexer:geek:42"), "{}", denied);
+
+        let missing = load().await.unwrap_err();
+        assert!(
+            matches!(missing, DlNzbError::Nzb(NzbError::FetchNotFound { .. })),
+            "{}",
+            missing
+        );
+
+        // An hour is longer than is waited out
+        let limited = load().await.unwrap_err();
+        assert!(
+            matches!(
+                limited,
+                DlNzbError::Nzb(NzbError::RateLimited {
+                    retry_after: Some(wait),
+                    ..
+                }) if wait == Duration::from_secs(3600)
+            ),
+            "{}",
+            limited
+        );
+
+        let requests = server.await.unwrap();
+        assert!(requests[0].starts_with("get /api?id=42&apikey=s3cret "));
+        assert!(requests[0].contains("user-agent: dl-nzb-test"));
+        assert!(requests[0].contains("x-api-client: dl-nzb"));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_is_waited_out() {
+        let nzb = r#"<?xml version="1.0"?>
+<nzb xmlns="http://www.newzbin.com/DTD/2003/nzb">
+<file poster="p" date="1" subject="&quot;a.bin&quot; yEnc (1/1)">
+<groups><group>alt.binaries.test</group></groups>
+<segments><segment bytes="10" number="1">a@b</segment></segments>
+</file>
+</nzb>"#;
+        let ok = format!(
+            "HTTP/1.1 200 OK\r\nContent-Disposition: attachment; filename=\"Show.nzb\"\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            nzb.len(),
+            nzb
+        );
+        let (url, server) = serve_responses(vec![
+            "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 1\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+            ok,
+        ])
+        .await;
+        let config = config_with_indexer(&url);
+
+        let started = std::time::Instant::now();
+        let loaded = LoadedNzb::load(&NzbSource::from_arg(Path::new("indexer:geek:7")), &config)
+            .await
+            .unwrap();
+        assert!(started.elapsed() >= Duration::from_secs(1));
+        assert_eq!(loaded.name, "Show");
+        assert_eq!(server.await.unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_content_disposition_filename() {
+        assert_eq!(
+            content_disposition_filename(r#"attachment; filename="Show.S01E01.nzb""#).as_deref(),
+            Some("Show.S01E01.nzb")
+        );
+        assert_eq!(
+            content_disposition_filename(
+                "attachment; filename=\"fallback.nzb\"; filename*=UTF-8''Caf%C3%A9.nzb"
+            )
+            .as_deref(),
+            Some("Café.nzb")
+        );
+        assert_eq!(
+            content_disposition_filename(r#"attachment; filename="../../etc/passwd""#).as_deref(),
+            Some("_.._etc_passwd")
+        );
+        assert_eq!(content_disposition_filename("inline"), None);
+    }
+
+    #[test]
+    fn test_url_filename() {
+        assert_eq!(
+            url_filename("https://x.example/nzb/Show.nzb?apikey=1"),
+            Some("Show.nzb")
+        );
+        assert_eq!(url_filename("https://x.example/"), None);
+        assert_eq!(url_filename("https://x.example"), None);
+    }
+
+    #[test]
+    fn test_expand_inputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        std::fs::create_dir_all(root.join(PROCESSED_DIR)).unwrap();
+        for name in [
+            "b.nzb",
+            "a.NZB.gz",
+            "notes.txt",
+            "sub/c.nzb",
+            ".done/old.nzb",
+        ] {
+            std::fs::write(root.join(name), "").unwrap();
+        }
+
+        let flat = expand_inputs(&[root.to_path_buf()], false).unwrap();
+        assert_eq!(flat, vec![root.join("a.NZB.gz"), root.join("b.nzb")]);
+
+        let walked = expand_inputs(&[root.to_path_buf()], true).unwrap();
+        assert_eq!(walked.len(), 3);
+        assert_eq!(walked[2], root.join("sub").join("c.nzb"));
+
+        let pattern = root.join("*.nzb");
+        assert_eq!(
+            expand_inputs(&[pattern], false).unwrap(),
+            vec![root.join("b.nzb")]
+        );
+
+        let err = expand_inputs(&[root.join("*.missing")], false).unwrap_err();
+        assert!(err.to_string().contains("*.missing"), "{}", err);
+
+        // Missing plain paths are left for loading to report
+        let missing = vec![root.join("gone.nzb")];
+        assert_eq!(expand_inputs(&missing, false).unwrap(), missing);
+    }
+
+    #[test]
+    fn test_nested_nzbs_leave_out_inputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("extras")).unwrap();
+        std::fs::create_dir_all(root.join(PROCESSED_DIR)).unwrap();
+        for name in [
+            "Show.S01.nzb",
+            "Show.S01.E01.mkv",
+            "extras/Show.S01.Extras.nzb.gz",
+            ".done/seen.nzb",
+        ] {
+            std::fs::write(root.join(name), "").unwrap();
+        }
+
+        let found = nested_nzbs(root, &[root.join("Show.S01.nzb")]);
+        assert_eq!(found, vec![root.join("extras/Show.S01.Extras.nzb.gz")]);
+        assert!(nested_nzbs(&root.join("missing"), &[]).is_empty());
+    }
+
+    #[test]
+    fn test_move_processed() {
+        let dir = tempfile::tempdir().unwrap();
+        let nzb = dir.path().join("show.nzb");
+        std::fs::write(&nzb, "").unwrap();
+        std::fs::write(dir.path().join("show.nzb.toml"), "").unwrap();
+
+        let moved = move_processed(&nzb).unwrap();
+        assert_eq!(moved, dir.path().join(PROCESSED_DIR).join("show.nzb"));
+        assert!(moved.exists() && !nzb.exists());
+        assert!(dir
+            .path()
+            .join(PROCESSED_DIR)
+            .join("show.nzb.toml")
+            .exists());
+    }
+
+    #[test]
+    fn test_filename_password() {
+        assert_eq!(
+            split_filename_password("Release.Name{{p4ssw0rd}}"),
+            ("Release.Name", Some("p4ssw0rd"))
+        );
+        // Nested braces belong to the password
+        assert_eq!(
+            split_filename_password("Release.Name {{pa{{ss}}w0rd}}"),
+            ("Release.Name", Some("pa{{ss}}w0rd"))
+        );
+        assert_eq!(
+            split_filename_password("Release.Name{{}}"),
+            ("Release.Name", None)
+        );
+        for plain in [
+            "Release.Name",
+            "Release{{Name",
+            "Release}}Name",
+            "{{pw}}.Name",
+            "}}",
+        ] {
+            assert_eq!(split_filename_password(plain), (plain, None));
+        }
+
+        assert_eq!(
+            split_stem(file_stem(Path::new("/nzbs/Show.S01{{s3cret}}.nzb.gz"))),
+            (Some("Show.S01".to_string()), Some(Secret::new("s3cret")))
+        );
+        assert_eq!(split_stem(file_stem(Path::new("{{s3cret}}.nzb"))).0, None);
+
+        assert_eq!(
+            redact_filename_password("Show.S01{{s3cret}}.nzb"),
+            "Show.S01{{********}}.nzb"
+        );
+        assert_eq!(redact_filename_password("Show{{}}.nzb"), "Show{{}}.nzb");
+        assert_eq!(redact_filename_password("Show.S01.nzb"), "Show.S01.nzb");
+        assert_eq!(
+            redact_nzb_path(Path::new("/nzbs/Show{{s3cret}}.nzb")),
+            Path::new("/nzbs/Show{{********}}.nzb")
+        );
+        assert!(matches!(
+            redact_nzb_path(Path::new("/nzbs/Show.nzb")),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn test_file_stem_strips_compression() {
+        assert_eq!(
+            file_stem(Path::new("/nzbs/Show.S01.nzb")).as_deref(),
+            Some("Show.S01")
+        );
+        assert_eq!(
+            file_stem(Path::new("Show.S01.nzb.gz")).as_deref(),
+            Some("Show.S01")
+        );
+        assert_eq!(
+            file_stem(Path::new("Show.S01.nzb.bz2")).as_deref(),
+            Some("Show.S01")
+        );
+    }
+}
@@ -0,0 +1,276 @@
+//! Download speed over time
+//!
+//! While an NZB downloads, aggregate throughput is sampled once a second from
+//! the per-server byte counters (the ones behind the metrics speed gauge).
+//! A [`SpeedTimeline`] holds at most [`MAX_SAMPLES`] points: when it fills up,
+//! neighbouring points are merged and each point covers twice the time, so a
+//! day-long download keeps the whole curve in bounded memory.
+
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::io::Write;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use super::downloader::StatsHandle;
+
+/// Time between samples
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Points kept per NZB before they are merged in pairs
+pub const MAX_SAMPLES: usize = 600;
+
+/// Throughput over one stretch of the download
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SpeedSample {
+    /// Start of the stretch, in seconds since the Unix epoch
+    pub timestamp: u64,
+    pub bytes_per_sec: u64,
+    /// Connections busy fetching articles, across all servers
+    pub connections: usize,
+}
+
+/// Summary figures of a timeline, in bytes per second
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SpeedStats {
+    pub min: u64,
+    pub avg: u64,
+    pub max: u64,
+    pub p95: u64,
+}
+
+/// Speed samples of one download, downsampled to at most [`MAX_SAMPLES`]
+#[derive(Debug, Clone)]
+pub struct SpeedTimeline {
+    samples: Vec<SpeedSample>,
+    /// One-second samples merged into each stored point
+    step: u32,
+    /// Samples waiting to make up the next point, with how many there are
+    pending: Option<(SpeedSample, u32)>,
+}
+
+impl Default for SpeedTimeline {
+    fn default() -> Self {
+        Self {
+            samples: Vec::new(),
+            step: 1,
+            pending: None,
+        }
+    }
+}
+
+impl SpeedTimeline {
+    /// Header line for [`write_csv`](Self::write_csv) output
+    pub const CSV_HEADER: &'static str = "nzb,timestamp,bytes_per_sec,connections";
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add one [`SAMPLE_INTERVAL`] sample
+    pub fn record(&mut self, sample: SpeedSample) {
+        let (sum, count) = match self.pending.take() {
+            Some((sum, count)) => (
+                SpeedSample {
+                    timestamp: sum.timestamp,
+                    bytes_per_sec: sum.bytes_per_sec + sample.bytes_per_sec,
+                    connections: sum.connections + sample.connections,
+                },
+                count + 1,
+            ),
+            None => (sample, 1),
+        };
+        if count < self.step {
+            self.pending = Some((sum, count));
+            return;
+        }
+        self.samples.push(average(sum, count));
+        if self.samples.len() >= MAX_SAMPLES {
+            self.samples = self
+                .samples
+                .chunks(2)
+                .map(|pair| {
+                    let sum = pair.iter().skip(1).fold(pair[0], |sum, s| SpeedSample {
+                        timestamp: sum.timestamp,
+                        bytes_per_sec: sum.bytes_per_sec + s.bytes_per_sec,
+                        connections: sum.connections + s.connections,
+                    });
+                    average(sum, pair.len() as u32)
+                })
+                .collect();
+            self.step *= 2;
+        }
+    }
+
+    /// The stored points, oldest first (a partly filled last point is left out)
+    pub fn samples(&self) -> &[SpeedSample] {
+        &self.samples
+    }
+
+    /// Seconds each point covers
+    pub fn step(&self) -> Duration {
+        SAMPLE_INTERVAL * self.step
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Min, mean, max and 95th percentile over the stored points
+    pub fn stats(&self) -> Option<SpeedStats> {
+        let mut speeds: Vec<u64> = self.samples.iter().map(|s| s.bytes_per_sec).collect();
+        if speeds.is_empty() {
+            return None;
+        }
+        speeds.sort_unstable();
+        let p95 = (speeds.len() * 95).div_ceil(100).max(1) - 1;
+        Some(SpeedStats {
+            min: speeds[0],
+            avg: speeds.iter().sum::<u64>() / speeds.len() as u64,
+            max: speeds[speeds.len() - 1],
+            p95: speeds[p95],
+        })
+    }
+
+    /// Write the points as CSV rows, each prefixed by `name` (no header)
+    pub fn write_csv(&self, out: &mut impl Write, name: &str) -> std::io::Result<()> {
+        let name = if name.contains([',', '"', '\n']) {
+            format!("\"{}\"", name.replace('"', "\"\""))
+        } else {
+            name.to_string()
+        };
+        for sample in &self.samples {
+            writeln!(
+                out,
+                "{},{},{},{}",
+                name, sample.timestamp, sample.bytes_per_sec, sample.connections
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn average(sum: SpeedSample, count: u32) -> SpeedSample {
+    SpeedSample {
+        timestamp: sum.timestamp,
+        bytes_per_sec: sum.bytes_per_sec / count as u64,
+        connections: (sum.connections as f64 / count as f64).round() as usize,
+    }
+}
+
+/// Run `work`, sampling the speed of `stats`' servers every second meanwhile
+pub(super) async fn sampled<T>(
+    stats: &StatsHandle,
+    work: impl Future<Output = T>,
+) -> (T, SpeedTimeline) {
+    let mut timeline = SpeedTimeline::new();
+    let mut tick = tokio::time::interval(SAMPLE_INTERVAL);
+    tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    // The first tick is immediate
+    tick.tick().await;
+    let mut last = (Instant::now(), total_bytes(stats));
+    tokio::pin!(work);
+    loop {
+        tokio::select! {
+            output = &mut work => return (output, timeline),
+            _ = tick.tick() => {
+                let metrics = stats.server_metrics();
+                let bytes: u64 = metrics.iter().map(|m| m.bytes).sum();
+                let elapsed = last.0.elapsed().as_secs_f64();
+                if elapsed > 0.0 {
+                    timeline.record(SpeedSample {
+                        timestamp: SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .map_or(0, |d| d.as_secs()),
+                        bytes_per_sec: (bytes.saturating_sub(last.1) as f64 / elapsed) as u64,
+                        connections: metrics.iter().map(|m| m.connections_in_use).sum(),
+                    });
+                }
+                last = (Instant::now(), bytes);
+            }
+        }
+    }
+}
+
+fn total_bytes(stats: &StatsHandle) -> u64 {
+    stats.server_metrics().iter().map(|m| m.bytes).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(timestamp: u64, bytes_per_sec: u64) -> SpeedSample {
+        SpeedSample {
+            timestamp,
+            bytes_per_sec,
+            connections: 4,
+        }
+    }
+
+    #[test]
+    fn test_timeline_stays_bounded() {
+        let mut timeline = SpeedTimeline::new();
+        // A day of one-second samples
+        for second in 0..86_400 {
+            timeline.record(sample(second, 1000 + second % 10));
+            assert!(timeline.samples().len() < MAX_SAMPLES);
+        }
+        assert!(timeline.samples().len() >= MAX_SAMPLES / 2);
+        assert_eq!(timeline.step(), Duration::from_secs(256));
+        // Points stay in order and each starts where its stretch starts
+        let samples = timeline.samples();
+        assert_eq!(samples[0].timestamp, 0);
+        assert_eq!(samples[1].timestamp, 256);
+        assert_eq!(samples[0].connections, 4);
+    }
+
+    #[test]
+    fn test_merging_averages_pairs() {
+        let mut timeline = SpeedTimeline::new();
+        for second in 0..MAX_SAMPLES as u64 {
+            timeline.record(sample(second, if second % 2 == 0 { 100 } else { 300 }));
+        }
+        assert_eq!(timeline.samples().len(), MAX_SAMPLES / 2);
+        assert!(timeline.samples().iter().all(|s| s.bytes_per_sec == 200));
+
+        // The next point waits for two samples
+        timeline.record(sample(600, 500));
+        assert_eq!(timeline.samples().len(), MAX_SAMPLES / 2);
+        timeline.record(sample(601, 700));
+        assert_eq!(timeline.samples().last(), Some(&sample(600, 600)));
+    }
+
+    #[test]
+    fn test_stats() {
+        assert_eq!(SpeedTimeline::new().stats(), None);
+
+        let mut timeline = SpeedTimeline::new();
+        for speed in 1..=100 {
+            timeline.record(sample(speed, speed * 10));
+        }
+        assert_eq!(
+            timeline.stats(),
+            Some(SpeedStats {
+                min: 10,
+                avg: 505,
+                max: 1000,
+                p95: 950,
+            })
+        );
+    }
+
+    #[test]
+    fn test_write_csv() {
+        let mut timeline = SpeedTimeline::new();
+        timeline.record(sample(1_700_000_000, 5_000_000));
+        timeline.record(sample(1_700_000_001, 4_000_000));
+
+        let mut out = Vec::new();
+        timeline.write_csv(&mut out, "Show, Season 1").unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\"Show, Season 1\",1700000000,5000000,4\n\
+             \"Show, Season 1\",1700000001,4000000,4\n"
+        );
+    }
+}
@@ -0,0 +1,134 @@
+//! Giving up on a file that stopped making progress
+//!
+//! Now and then one file's articles sit on a backend so slow that its
+//! batches never finish while the rest of the NZB does, and the run hangs
+//! just short of the end. A [`StallWatch`] shared by the files of a download
+//! notes when each last finished a batch. Once another file has finished, a
+//! file that hasn't for `download.stall_timeout` is stalled: its batches in
+//! flight are abandoned and the rest count as failed, for PAR2 to make up.
+//! When every other file still downloading has gone as quiet, the whole run
+//! is slow rather than one file stuck, and nothing is given up on.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How often a downloading file checks whether it has stalled
+pub const STALL_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When each file of a download last got anywhere
+#[derive(Debug)]
+pub struct StallWatch {
+    /// Time without progress it takes to stall (zero = never)
+    timeout: Duration,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Files downloading, by NZB index, and when each last finished a batch
+    active: HashMap<usize, Instant>,
+    /// Files done with, whether downloaded, skipped or failed
+    finished: usize,
+}
+
+impl StallWatch {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            state: Mutex::default(),
+        }
+    }
+
+    /// File `index` starts fetching
+    pub fn start(&self, index: usize) {
+        self.progressed(index);
+    }
+
+    /// File `index` finished a batch
+    pub fn progressed(&self, index: usize) {
+        self.state
+            .lock()
+            .unwrap()
+            .active
+            .insert(index, Instant::now());
+    }
+
+    /// File `index` is done with, however it went
+    pub fn finished(&self, index: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.active.remove(&index);
+        state.finished += 1;
+    }
+
+    /// How long file `index` has gone without progress, when that makes it
+    /// stalled
+    pub fn stalled(&self, index: usize) -> Option<Duration> {
+        self.stalled_at(index, Instant::now())
+    }
+
+    fn stalled_at(&self, index: usize, now: Instant) -> Option<Duration> {
+        if self.timeout.is_zero() {
+            return None;
+        }
+        let state = self.state.lock().unwrap();
+        let quiet = now.saturating_duration_since(*state.active.get(&index)?);
+        if quiet < self.timeout || state.finished == 0 {
+            return None;
+        }
+        let mut others = (state.active.iter())
+            .filter(|&(&other, _)| other != index)
+            .map(|(_, &last)| now.saturating_duration_since(last))
+            .peekable();
+        let whole_run_quiet = others.peek().is_some() && others.all(|q| q >= self.timeout);
+        (!whole_run_quiet).then_some(quiet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(300);
+
+    #[test]
+    fn test_stalls_once_another_file_finished() {
+        let watch = StallWatch::new(TIMEOUT);
+        watch.start(0);
+        watch.start(1);
+        let later = Instant::now() + TIMEOUT;
+        // Nothing has finished, so there's nothing to compare with
+        assert_eq!(watch.stalled_at(0, later), None);
+
+        watch.finished(1);
+        assert!(watch.stalled_at(0, later).is_some_and(|q| q >= TIMEOUT));
+        assert_eq!(watch.stalled_at(0, later - Duration::from_secs(1)), None);
+    }
+
+    #[test]
+    fn test_whole_run_slowness_is_no_stall() {
+        let watch = StallWatch::new(TIMEOUT);
+        for index in 0..3 {
+            watch.start(index);
+        }
+        watch.finished(2);
+        let later = Instant::now() + TIMEOUT;
+        // Both files still downloading went quiet together
+        assert_eq!(watch.stalled_at(0, later), None);
+
+        // One of them gets on while the other doesn't
+        std::thread::sleep(Duration::from_millis(10));
+        watch.progressed(1);
+        let later = later + Duration::from_millis(5);
+        assert!(watch.stalled_at(0, later).is_some());
+        assert_eq!(watch.stalled_at(1, later), None);
+    }
+
+    #[test]
+    fn test_zero_timeout_never_stalls() {
+        let watch = StallWatch::new(Duration::ZERO);
+        watch.start(0);
+        watch.finished(1);
+        assert_eq!(watch.stalled_at(0, Instant::now() + TIMEOUT), None);
+    }
+}
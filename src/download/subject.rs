@@ -0,0 +1,267 @@
+//! File names from NZB subject lines
+//!
+//! Posters mostly quote the name, as in `[1/9] - "show.part01.rar" yEnc
+//! (1/5202)`, but not always cleanly: quotes doubled inside the name, the
+//! `yEnc` part counter inside the quotes, `&quot;` left encoded, bracketed
+//! tags with quotes of their own. [`filename`] takes the last quoted span
+//! before `yEnc`, trims what leaked into it, and falls back to the longest
+//! word with a file extension when nothing is quoted. What it returns still
+//! goes through [`sanitize_filename`](super::nzb::sanitize_filename).
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::ops::Range;
+
+/// Part counters and sizes trailing a name: ` yEnc (1/123)`, ` [01/50]`, ` 1.4 GB`
+static TRAILING_ANNOTATION: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"(?i)(?:\s+(?:yEnc\b.*|\(\d+/\d+\)|\[\d+/\d+\]|[(\[]?\d+(?:[.,]\d+)?\s*[kmgt]i?b[)\]]?))+$",
+    )
+    .expect("valid regex")
+});
+
+/// The file name a subject gives, before sanitizing
+pub(super) fn filename(subject: &str) -> Option<String> {
+    let subject = decode_entities(subject);
+    let spans = quoted_spans(&subject);
+    if spans.is_empty() {
+        return longest_name_token(&subject);
+    }
+
+    // The name is quoted before the yEnc marker; quotes after it are comments
+    let lower = subject.to_ascii_lowercase();
+    let marker = lower
+        .match_indices("yenc")
+        .map(|(i, _)| i)
+        .find(|&i| !spans.iter().any(|(range, _)| range.contains(&i)));
+    let before: Vec<String> = spans
+        .into_iter()
+        .filter(|(range, _)| marker.map_or(true, |marker| range.end <= marker))
+        .map(|(_, text)| strip_annotations(&text))
+        .filter(|text| !text.is_empty())
+        .collect();
+    before
+        .iter()
+        .rev()
+        .find(|text| has_extension(text))
+        .or(before.last())
+        .cloned()
+}
+
+/// Decode the entities subjects carry when an NZB was escaped twice
+fn decode_entities(subject: &str) -> String {
+    if !subject.contains('&') {
+        return subject.to_string();
+    }
+    subject
+        .replace("&quot;", "\"")
+        .replace("&#34;", "\"")
+        .replace("&#x22;", "\"")
+        .replace("&apos;", "'")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+/// Every `"..."` span with where it sits in `subject`
+///
+/// A doubled quote inside a span stands for one quote; a span left open
+/// runs to the end of the subject.
+fn quoted_spans(subject: &str) -> Vec<(Range<usize>, String)> {
+    let mut spans = Vec::new();
+    let mut chars = subject.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        if c != '"' {
+            continue;
+        }
+        let mut text = String::new();
+        let mut end = subject.len();
+        while let Some((i, c)) = chars.next() {
+            if c != '"' {
+                text.push(c);
+                continue;
+            }
+            if !text.is_empty() && chars.peek().is_some_and(|&(_, next)| next == '"') {
+                chars.next();
+                text.push('"');
+                continue;
+            }
+            end = i + 1;
+            break;
+        }
+        spans.push((start..end, text));
+    }
+    spans
+}
+
+/// `text` without surrounding space or the counters and sizes after the name
+fn strip_annotations(text: &str) -> String {
+    TRAILING_ANNOTATION
+        .replace(text.trim(), "")
+        .trim()
+        .to_string()
+}
+
+/// Whether `name` ends in something that looks like a file extension:
+/// `.mkv`, `.r01`, `.7z`, or three digits after another extension (`.7z.001`)
+fn has_extension(name: &str) -> bool {
+    let Some((stem, ext)) = name.rsplit_once('.') else {
+        return false;
+    };
+    if stem.is_empty() || !(2..=5).contains(&ext.len()) {
+        return false;
+    }
+    if !ext.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return false;
+    }
+    ext.chars().any(|c| c.is_ascii_alphabetic()) || (ext.len() == 3 && stem.contains('.'))
+}
+
+/// The longest word of an unquoted subject that looks like a file name
+///
+/// Newsgroup names, addresses and `#channel@network` tags look like names
+/// too and are passed over.
+fn longest_name_token(subject: &str) -> Option<String> {
+    subject
+        .split(|c: char| c.is_whitespace() || "[](){}<>|,".contains(c))
+        .map(|token| token.trim_matches(|c: char| "-:;'*".contains(c)))
+        .filter(|token| {
+            let lower = token.to_ascii_lowercase();
+            !token.starts_with('#')
+                && !token.contains('@')
+                && !token.contains("://")
+                && !lower.starts_with("alt.binaries.")
+                && !lower.starts_with("a.b.")
+                && !lower.starts_with("www.")
+        })
+        .filter(|token| has_extension(token))
+        .fold(None, |longest: Option<&str>, token| match longest {
+            Some(longest) if longest.len() >= token.len() => Some(longest),
+            _ => Some(token),
+        })
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Subjects as posted (names changed), and the name each should give
+    const CORPUS: &[(&str, Option<&str>)] = &[
+        (
+            r#"[1/9] - "show.part01.rar" yEnc (1/5202)"#,
+            Some("show.part01.rar"),
+        ),
+        ("&quot;show.nfo&quot; yEnc (1/1)", Some("show.nfo")),
+        ("&#34;encoded.mkv&#34; yEnc (1/2)", Some("encoded.mkv")),
+        (
+            r#""Tom &amp; Jerry.avi" yEnc (1/3)"#,
+            Some("Tom & Jerry.avi"),
+        ),
+        (
+            r#""weird ""quoted"" name.mkv" yEnc (1/12)"#,
+            Some(r#"weird "quoted" name.mkv"#),
+        ),
+        (r#""Show.S01E01.mkv yEnc (1/123)""#, Some("Show.S01E01.mkv")),
+        (r#""file.nfo (1/1)""#, Some("file.nfo")),
+        (r#""file.mkv 1.43 GB" yEnc (1/3)"#, Some("file.mkv")),
+        (r#""unterminated.mkv yEnc (1/2)"#, Some("unterminated.mkv")),
+        (
+            r#"[PRiVATE]-["Some Group" presents]-[1/3] - "movie.2019.1080p.mkv" yEnc (1/800)"#,
+            Some("movie.2019.1080p.mkv"),
+        ),
+        (r#""My Files" - "document.pdf" yEnc (1/1)"#, Some("document.pdf")),
+        (r#""document.pdf" - "My Files" yEnc (1/1)"#, Some("document.pdf")),
+        (
+            r#""name.mkv" yEnc (1/2) "trailing comment""#,
+            Some("name.mkv"),
+        ),
+        (r#""My Files" yEnc (1/1)"#, Some("My Files")),
+        (r#"Re: "file.par2" yEnc (1/1)"#, Some("file.par2")),
+        (
+            r#""file.vol00+01.par2" yEnc (1/2) 52428"#,
+            Some("file.vol00+01.par2"),
+        ),
+        (
+            r#"(01/15) "Album - 01 - Track.flac" - 25.3 MB - yEnc (1/35)"#,
+            Some("Album - 01 - Track.flac"),
+        ),
+        (
+            r#""track 01.mp3" yEnc (1/2) 4.5 MB"#,
+            Some("track 01.mp3"),
+        ),
+        (r#""archive.7z.001" yEnc (1/100)"#, Some("archive.7z.001")),
+        (r#""movie.mkv" [01/50] yEnc (1/100)"#, Some("movie.mkv")),
+        (
+            r#""Show Name - S02E03 - Title.mkv" yEnc (001/999)"#,
+            Some("Show Name - S02E03 - Title.mkv"),
+        ),
+        (r#"[3/7] - "show.r01" yEnc (1/134)"#, Some("show.r01")),
+        (
+            r#"poster@example.com "file.rar" yEnc (1/5)"#,
+            Some("file.rar"),
+        ),
+        (
+            r#""Über Film (2020).mkv" yEnc (1/9)"#,
+            Some("Über Film (2020).mkv"),
+        ),
+        (
+            r#""file name with 'apostrophe'.mkv" yEnc (1/2)"#,
+            Some("file name with 'apostrophe'.mkv"),
+        ),
+        (
+            r#""Release.2021.Part 2 of 3.mkv" yEnc (1/9)"#,
+            Some("Release.2021.Part 2 of 3.mkv"),
+        ),
+        (
+            r#""ABC.DEF.part001.rar"yEnc(1/55)"#,
+            Some("ABC.DEF.part001.rar"),
+        ),
+        (r#""../../etc/passwd" yEnc (1/1)"#, Some("../../etc/passwd")),
+        (r#""" yEnc (1/1)"#, None),
+        // Nothing quoted: the longest word with an extension
+        (
+            "Some.Show.S01E01.720p.mkv (1/50)",
+            Some("Some.Show.S01E01.720p.mkv"),
+        ),
+        (
+            "[12345]-[FULL]-[#a.b.teevee]-[ Show.S01E02.1080p.WEB.H264-GRP ]-[03/45] - Show.S01E02.1080p.WEB.H264-GRP.part02.rar yEnc (1/100)",
+            Some("Show.S01E02.1080p.WEB.H264-GRP.part02.rar"),
+        ),
+        ("archive.7z.002 yEnc (5/100)", Some("archive.7z.002")),
+        (
+            "[#a.b.mom@EFNet]-[FULL]-[ sample.avi ]-[1/1] yEnc (1/3)",
+            Some("sample.avi"),
+        ),
+        (
+            "post from alt.binaries.test: clip.mp4 yEnc (1/3)",
+            Some("clip.mp4"),
+        ),
+        ("Release.Name.2023 [01/50] - yEnc (1/10)", None),
+        ("a8f3e1b2c4d5e6f7 yEnc (1/40)", None),
+        ("no quotes here", None),
+    ];
+
+    #[test]
+    fn test_subject_corpus() {
+        for (subject, expected) in CORPUS {
+            assert_eq!(
+                filename(subject).as_deref(),
+                *expected,
+                "subject: {}",
+                subject
+            );
+        }
+    }
+
+    #[test]
+    fn test_has_extension() {
+        for name in ["a.mkv", "a.r01", "a.7z", "a.7z.001", "a.flac"] {
+            assert!(has_extension(name), "{}", name);
+        }
+        for name in [".mkv", "a.2023", "a.001", "a.x264-GRP", "a.teevee", "a"] {
+            assert!(!has_extension(name), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! Scratch space for in-progress work (`download.temp_dir`)
+//!
+//! Each NZB works in its own `dl-nzb-<name>` subdirectory: files are
+//! downloaded there and moved into the download directory once complete, and
+//! RAR extraction is staged there. Subdirectories are removed when their NZB
+//! finishes; ones left behind by crashed or interrupted runs are swept at
+//! startup once they are older than `download.temp_max_age_hours`.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::error::{ConfigError, DlNzbError, DownloadError};
+
+type Result<T> = std::result::Result<T, DlNzbError>;
+
+/// Prefix marking per-NZB subdirectories as ours, so cleanup never touches
+/// anything else living in a shared temp directory
+const NZB_DIR_PREFIX: &str = "dl-nzb-";
+
+/// Create the temp directory and make sure files can be written to it
+///
+/// Warns when it is on a different filesystem than `download_dir`, since
+/// finished files then have to be copied instead of renamed.
+pub fn prepare_temp_dir(temp_dir: &Path, download_dir: &Path) -> Result<()> {
+    let invalid = |reason: String| ConfigError::InvalidPath {
+        path: temp_dir.to_path_buf(),
+        reason,
+    };
+
+    std::fs::create_dir_all(temp_dir).map_err(|e| invalid(e.to_string()))?;
+    let probe = temp_dir.join(format!(".{}write-test", NZB_DIR_PREFIX));
+    std::fs::write(&probe, b"")
+        .map_err(|e| invalid(format!("temp directory is not writable ({})", e)))?;
+    let _ = std::fs::remove_file(&probe);
+
+    if !same_filesystem(temp_dir, download_dir) {
+        tracing::warn!(
+            "Temp directory {} is on a different filesystem than {}; finished files will be copied, not moved",
+            temp_dir.display(),
+            download_dir.display()
+        );
+    }
+    Ok(())
+}
+
+/// Create `dir` and make sure a file can be written in it
+///
+/// Run before an NZB starts, so a folder that is read-only, out of quota or
+/// not ours fails it up front instead of file by file once downloading.
+pub fn check_writable(dir: &Path) -> Result<()> {
+    std::fs::create_dir_all(dir).map_err(|e| DownloadError::write(dir, e))?;
+    let probe = dir.join(format!(
+        ".{}write-test-{}",
+        NZB_DIR_PREFIX,
+        std::process::id()
+    ));
+    std::fs::write(&probe, b"").map_err(|e| DownloadError::write(dir, e))?;
+    let _ = std::fs::remove_file(&probe);
+    Ok(())
+}
+
+/// Working directory for one NZB inside the temp directory
+pub fn nzb_temp_dir(temp_dir: &Path, nzb_name: &str) -> PathBuf {
+    let name: String = nzb_name
+        .chars()
+        .map(|c| if std::path::is_separator(c) { '_' } else { c })
+        .collect();
+    temp_dir.join(format!("{}{}", NZB_DIR_PREFIX, name))
+}
+
+/// Remove per-NZB temp directories last modified more than `max_age` ago
+///
+/// Returns how many were removed; failures are logged and skipped.
+pub fn cleanup_stale_temp_dirs(temp_dir: &Path, max_age: Duration) -> usize {
+    cleanup_stale_temp_dirs_at(temp_dir, max_age, SystemTime::now())
+}
+
+fn cleanup_stale_temp_dirs_at(temp_dir: &Path, max_age: Duration, now: SystemTime) -> usize {
+    let Ok(entries) = std::fs::read_dir(temp_dir) else {
+        return 0;
+    };
+
+    let mut removed = 0;
+    for entry in entries.flatten() {
+        let is_ours = entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with(NZB_DIR_PREFIX)
+            && entry.file_type().is_ok_and(|t| t.is_dir());
+        if !is_ours {
+            continue;
+        }
+
+        let stale = entry
+            .metadata()
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|modified| now.duration_since(modified).ok())
+            .is_some_and(|age| age > max_age);
+        if !stale {
+            continue;
+        }
+
+        match std::fs::remove_dir_all(entry.path()) {
+            Ok(()) => {
+                tracing::info!("Removed stale temp directory {}", entry.path().display());
+                removed += 1;
+            }
+            Err(e) => tracing::warn!("Failed to remove {}: {}", entry.path().display(), e),
+        }
+    }
+    removed
+}
+
+/// Move a finished file out of the temp directory
+///
+/// Renames where possible and falls back to copy + remove across filesystems.
+pub async fn move_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    if tokio::fs::rename(from, to).await.is_ok() {
+        return Ok(());
+    }
+    tokio::fs::copy(from, to).await?;
+    tokio::fs::remove_file(from).await
+}
+
+/// Sync a directory so entries created or renamed in it survive a crash
+///
+/// On Unix a file's directory entry is synced separately from its data;
+/// elsewhere this does nothing.
+#[cfg(unix)]
+pub fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    std::fs::File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+pub fn sync_dir(_dir: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Check whether two existing paths live on the same filesystem
+#[cfg(unix)]
+pub fn same_filesystem(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (std::fs::metadata(a), std::fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev(),
+        _ => true,
+    }
+}
+
+#[cfg(not(unix))]
+pub fn same_filesystem(a: &Path, b: &Path) -> bool {
+    a.components().next() == b.components().next()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cleanup_only_removes_old_nzb_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        let ours = nzb_temp_dir(dir.path(), "Some.Release");
+        std::fs::create_dir_all(ours.join("Some.Release.rar.extracting")).unwrap();
+        std::fs::create_dir(dir.path().join("unrelated")).unwrap();
+        std::fs::write(dir.path().join("dl-nzb-file"), b"").unwrap();
+
+        // Fresh directories survive
+        let max_age = Duration::from_secs(3600);
+        assert_eq!(cleanup_stale_temp_dirs(dir.path(), max_age), 0);
+        assert!(ours.exists());
+
+        let later = SystemTime::now() + Duration::from_secs(2 * 3600);
+        assert_eq!(cleanup_stale_temp_dirs_at(dir.path(), max_age, later), 1);
+        assert!(!ours.exists());
+        assert!(dir.path().join("unrelated").exists());
+        assert!(dir.path().join("dl-nzb-file").exists());
+    }
+
+    #[test]
+    fn test_prepare_rejects_unwritable_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("not-a-dir");
+        std::fs::write(&file, b"").unwrap();
+
+        assert!(prepare_temp_dir(&dir.path().join("scratch"), dir.path()).is_ok());
+        let err = prepare_temp_dir(&file.join("scratch"), dir.path()).unwrap_err();
+        assert!(err.to_string().contains("not-a-dir"), "{}", err);
+    }
+
+    #[test]
+    fn test_check_writable() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("new").join("folder");
+        check_writable(&target).unwrap();
+        // The probe is gone again
+        assert_eq!(std::fs::read_dir(&target).unwrap().count(), 0);
+
+        let file = dir.path().join("not-a-dir");
+        std::fs::write(&file, b"").unwrap();
+        let err = check_writable(&file.join("sub")).unwrap_err();
+        assert!(matches!(
+            err,
+            DlNzbError::Download(DownloadError::WriteError { .. })
+        ));
+    }
+
+    #[test]
+    fn test_nzb_temp_dir_stays_inside() {
+        let dir = nzb_temp_dir(Path::new("/tmp/work"), "a/../b");
+        assert_eq!(dir.parent(), Some(Path::new("/tmp/work")));
+    }
+}
@@ -0,0 +1,377 @@
+//! Global download speed limiting
+//!
+//! All connections share one token bucket. The limit can change at any time
+//! (e.g. from `download.speed_schedule`); transfers already waiting keep their
+//! computed delay and pick up the new rate on their next segment.
+//!
+//! A limit set at runtime (SIGUSR1, `POST /limits`) overrides the configured
+//! one until it is cleared; the schedule keeps tracking what the configured
+//! limit would be meanwhile, so clearing the override lands on the right one.
+
+use chrono::{Datelike, Local, Timelike, Weekday};
+use human_bytes::human_bytes;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::{DownloadConfig, SpeedWindow};
+
+/// How often the speed schedule is re-evaluated
+const SCHEDULE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Shared bandwidth limiter (bytes per second, 0 = unlimited)
+#[derive(Clone)]
+pub struct Throttle {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    limit: AtomicU64,
+    scheduled: AtomicBool,
+    /// Limit from `download.max_speed` or the schedule, in force unless overridden
+    configured: AtomicU64,
+    overridden: AtomicBool,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    available: f64,
+    last: Instant,
+}
+
+impl Throttle {
+    pub fn new(limit: u64) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                limit: AtomicU64::new(limit),
+                scheduled: AtomicBool::new(false),
+                configured: AtomicU64::new(limit),
+                overridden: AtomicBool::new(false),
+                bucket: Mutex::new(Bucket {
+                    available: limit as f64,
+                    last: Instant::now(),
+                }),
+            }),
+        }
+    }
+
+    /// An unlimited throttle
+    pub fn unlimited() -> Self {
+        Self::new(0)
+    }
+
+    /// Current limit in bytes per second (0 = unlimited)
+    pub fn limit(&self) -> u64 {
+        self.inner.limit.load(Ordering::Relaxed)
+    }
+
+    /// Change the configured limit; `scheduled` marks it as coming from the
+    /// speed schedule
+    ///
+    /// While a runtime override is in force, it only takes effect once the
+    /// override is cleared.
+    pub fn set_limit(&self, limit: u64, scheduled: bool) {
+        self.inner.configured.store(limit, Ordering::Relaxed);
+        self.inner.scheduled.store(scheduled, Ordering::Relaxed);
+        if !self.is_overridden() {
+            self.apply(limit);
+        }
+    }
+
+    /// Override the configured limit at runtime (0 = unlimited), or go back
+    /// to it with `None`
+    pub fn override_limit(&self, limit: Option<u64>) {
+        self.inner
+            .overridden
+            .store(limit.is_some(), Ordering::Relaxed);
+        self.apply(limit.unwrap_or_else(|| self.configured()));
+    }
+
+    /// Switch between the configured limit and unlimited, returning the
+    /// limit now in force
+    ///
+    /// Any other override is cleared first, so the configured limit comes
+    /// back. Does nothing when the configured limit is already unlimited.
+    pub fn toggle_unlimited(&self) -> u64 {
+        if self.is_overridden() {
+            self.override_limit(None);
+        } else if self.configured() > 0 {
+            self.override_limit(Some(0));
+        }
+        self.limit()
+    }
+
+    /// Limit from the config or schedule, whether or not it is overridden
+    pub fn configured(&self) -> u64 {
+        self.inner.configured.load(Ordering::Relaxed)
+    }
+
+    /// Whether a limit set at runtime is in force
+    pub fn is_overridden(&self) -> bool {
+        self.inner.overridden.load(Ordering::Relaxed)
+    }
+
+    fn apply(&self, limit: u64) {
+        let previous = self.inner.limit.swap(limit, Ordering::Relaxed);
+        if previous != limit {
+            tracing::debug!("Speed limit changed: {} -> {} B/s", previous, limit);
+        }
+    }
+
+    /// Progress note describing an active limit, e.g. "limited to 5 MiB/s by
+    /// schedule", or "unlimited by override" while one lifts the configured
+    /// limit
+    pub fn status(&self) -> Option<String> {
+        let limit = self.limit();
+        let by = if self.is_overridden() {
+            " by override"
+        } else if self.inner.scheduled.load(Ordering::Relaxed) {
+            " by schedule"
+        } else {
+            ""
+        };
+        match limit {
+            0 if self.is_overridden() && self.configured() > 0 => Some(format!("unlimited{}", by)),
+            0 => None,
+            _ => Some(format!("limited to {}/s{}", human_bytes(limit as f64), by)),
+        }
+    }
+
+    /// Account for `bytes` received, sleeping as long as needed to stay under the limit
+    pub async fn consume(&self, bytes: u64) {
+        let limit = self.limit();
+        if limit == 0 {
+            return;
+        }
+
+        let wait = {
+            let mut bucket = match self.inner.bucket.lock() {
+                Ok(bucket) => bucket,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            let now = Instant::now();
+            let refill = now.duration_since(bucket.last).as_secs_f64() * limit as f64;
+            // At most one second of burst
+            bucket.available = (bucket.available + refill).min(limit as f64);
+            bucket.last = now;
+            bucket.available -= bytes as f64;
+
+            if bucket.available < 0.0 {
+                Duration::from_secs_f64(-bucket.available / limit as f64)
+            } else {
+                Duration::ZERO
+            }
+        };
+
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Apply `download.max_speed` / `download.speed_schedule` now and keep it up to date
+    ///
+    /// Returns the background task re-evaluating the schedule every minute, if any.
+    pub fn follow_schedule(&self, config: &DownloadConfig) -> Option<tokio::task::JoinHandle<()>> {
+        let (limit, scheduled) = current_limit(config);
+        self.set_limit(limit, scheduled);
+
+        if config.speed_schedule.is_empty() {
+            return None;
+        }
+
+        let throttle = self.clone();
+        let config = config.clone();
+        Some(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SCHEDULE_INTERVAL);
+            loop {
+                interval.tick().await;
+                let (limit, scheduled) = current_limit(&config);
+                throttle.set_limit(limit, scheduled);
+            }
+        }))
+    }
+}
+
+/// The limit in force right now, and whether it comes from the schedule
+fn current_limit(config: &DownloadConfig) -> (u64, bool) {
+    let now = Local::now();
+    active_limit(
+        &config.speed_schedule,
+        config.max_speed,
+        now.weekday(),
+        now.hour() * 60 + now.minute(),
+    )
+}
+
+/// Lowest cap among the windows active at `minute` past midnight on `day`
+///
+/// Falls back to `static_limit` when no window is active.
+pub fn active_limit(
+    schedule: &[SpeedWindow],
+    static_limit: u64,
+    day: Weekday,
+    minute: u32,
+) -> (u64, bool) {
+    let caps = schedule
+        .iter()
+        .filter(|window| window.is_active(day, minute))
+        .map(|window| window.max_speed);
+
+    let mut active = false;
+    let mut lowest: Option<u64> = None;
+    for cap in caps {
+        active = true;
+        if cap > 0 {
+            lowest = Some(lowest.map_or(cap, |l| l.min(cap)));
+        }
+    }
+
+    match (active, lowest) {
+        (true, Some(cap)) => (cap, true),
+        // Only uncapped windows are active: full speed
+        (true, None) => (0, true),
+        (false, _) => (static_limit, false),
+    }
+}
+
+/// Parse `HH:MM` into minutes past midnight (`24:00` is end of day)
+pub fn parse_time_of_day(value: &str) -> Option<u32> {
+    let (hours, minutes) = value.trim().split_once(':')?;
+    let hours: u32 = hours.parse().ok()?;
+    let minutes: u32 = minutes.parse().ok()?;
+    match (hours, minutes) {
+        (24, 0) => Some(24 * 60),
+        (0..=23, 0..=59) => Some(hours * 60 + minutes),
+        _ => None,
+    }
+}
+
+impl SpeedWindow {
+    /// Whether the window covers `minute` past midnight on `day`
+    ///
+    /// Windows with `to` before `from` run past midnight into the next day.
+    pub fn is_active(&self, day: Weekday, minute: u32) -> bool {
+        let (Some(from), Some(to)) = (parse_time_of_day(&self.from), parse_time_of_day(&self.to))
+        else {
+            return false;
+        };
+        let on = |day: Weekday| {
+            self.days.is_empty()
+                || self
+                    .days
+                    .iter()
+                    .any(|d| d.parse::<Weekday>().is_ok_and(|d| d == day))
+        };
+
+        if from < to {
+            on(day) && minute >= from && minute < to
+        } else {
+            (on(day) && minute >= from) || (on(day.pred()) && minute < to)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(days: &[&str], from: &str, to: &str, max_speed: u64) -> SpeedWindow {
+        SpeedWindow {
+            days: days.iter().map(|d| d.to_string()).collect(),
+            from: from.to_string(),
+            to: to.to_string(),
+            max_speed,
+        }
+    }
+
+    #[test]
+    fn test_parse_time_of_day() {
+        assert_eq!(parse_time_of_day("08:00"), Some(480));
+        assert_eq!(parse_time_of_day("23:59"), Some(1439));
+        assert_eq!(parse_time_of_day("24:00"), Some(1440));
+        assert_eq!(parse_time_of_day("24:30"), None);
+        assert_eq!(parse_time_of_day("8am"), None);
+    }
+
+    #[test]
+    fn test_daytime_cap_and_static_fallback() {
+        let schedule = vec![window(&[], "08:00", "24:00", 5_000_000)];
+
+        assert_eq!(
+            active_limit(&schedule, 0, Weekday::Mon, 9 * 60),
+            (5_000_000, true)
+        );
+        assert_eq!(active_limit(&schedule, 0, Weekday::Mon, 3 * 60), (0, false));
+        assert_eq!(
+            active_limit(&[], 1_000, Weekday::Mon, 9 * 60),
+            (1_000, false)
+        );
+    }
+
+    #[test]
+    fn test_overlapping_windows_take_lowest_cap() {
+        let schedule = vec![
+            window(&[], "08:00", "24:00", 5_000_000),
+            window(&["sat", "sun"], "10:00", "12:00", 1_000_000),
+            window(&[], "10:00", "11:00", 0),
+        ];
+
+        assert_eq!(
+            active_limit(&schedule, 0, Weekday::Sat, 10 * 60 + 30),
+            (1_000_000, true)
+        );
+        assert_eq!(
+            active_limit(&schedule, 0, Weekday::Mon, 10 * 60 + 30),
+            (5_000_000, true)
+        );
+    }
+
+    #[test]
+    fn test_window_past_midnight() {
+        let night = window(&["fri"], "22:00", "06:00", 1);
+
+        assert!(night.is_active(Weekday::Fri, 23 * 60));
+        assert!(night.is_active(Weekday::Sat, 5 * 60));
+        assert!(!night.is_active(Weekday::Sat, 23 * 60));
+        assert!(!night.is_active(Weekday::Fri, 5 * 60));
+    }
+
+    #[tokio::test]
+    async fn test_consume_waits_when_over_limit() {
+        let throttle = Throttle::new(100_000);
+        let start = Instant::now();
+        throttle.consume(100_000).await; // Initial burst allowance
+        throttle.consume(20_000).await;
+        assert!(start.elapsed() >= Duration::from_millis(150));
+
+        throttle.set_limit(0, false);
+        let start = Instant::now();
+        throttle.consume(u64::MAX / 2).await;
+        assert!(start.elapsed() < Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_override_outlasts_the_schedule() {
+        let throttle = Throttle::new(0);
+        throttle.set_limit(5_000_000, true);
+        assert_eq!(throttle.toggle_unlimited(), 0);
+        assert_eq!(throttle.status().as_deref(), Some("unlimited by override"));
+
+        // The schedule moves on underneath, and is back once toggled again
+        throttle.set_limit(1_000_000, true);
+        assert_eq!(throttle.limit(), 0);
+        assert_eq!(throttle.toggle_unlimited(), 1_000_000);
+        assert!(!throttle.is_overridden());
+
+        throttle.override_limit(Some(2_000_000));
+        assert_eq!(throttle.limit(), 2_000_000);
+        assert!(throttle.status().unwrap().ends_with("by override"));
+        assert_eq!(throttle.toggle_unlimited(), 1_000_000);
+
+        // Nothing to lift without a configured limit
+        throttle.set_limit(0, false);
+        assert_eq!(throttle.toggle_unlimited(), 0);
+        assert!(!throttle.is_overridden());
+        assert_eq!(throttle.status(), None);
+    }
+}
@@ -0,0 +1,477 @@
+//! Checks on the segment lists of a parsed NZB
+//!
+//! Some indexers emit NZBs that can never download whole: segment numbers
+//! missing or listed twice, a segment 0, the same article listed twice, or
+//! declared sizes that make no sense. [`Nzb::validate`] finds these before
+//! any bandwidth is spent on them.
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Serialize;
+use std::collections::HashMap;
+
+use super::nzb::{Nzb, NzbFile};
+
+/// The `(1/120)` part count at the end of a yEnc subject
+static PART_COUNT: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\(\d+/(\d+)\)[^(]*$").expect("valid regex"));
+
+/// A segment declaring this many times the file's median size is suspect
+const OVERSIZED_FACTOR: u64 = 4;
+
+/// Segment numbers shown in a problem line before the rest are counted
+const MAX_SHOWN: usize = 8;
+
+/// What [`Nzb::validate`] found, one report per file in NZB order
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct NzbReport {
+    pub files: Vec<FileReport>,
+}
+
+impl NzbReport {
+    /// Check each of `files`; Message-IDs are compared across all of them
+    pub fn of(files: &[NzbFile]) -> Self {
+        let mut seen_ids = HashMap::new();
+        Self {
+            files: files
+                .iter()
+                .map(|file| FileReport::of(file, &mut seen_ids))
+                .collect(),
+        }
+    }
+
+    /// Whether every file's segment list is sound
+    pub fn is_clean(&self) -> bool {
+        self.files.iter().all(FileReport::is_clean)
+    }
+
+    /// Files with at least one problem
+    pub fn problems(&self) -> impl Iterator<Item = &FileReport> {
+        self.files.iter().filter(|file| !file.is_clean())
+    }
+
+    /// One line per file the NZB itself leaves incomplete
+    pub fn gap_warnings(&self) -> Vec<String> {
+        self.files
+            .iter()
+            .filter(|file| !file.missing.is_empty())
+            .map(|file| {
+                format!(
+                    "file {} is missing {} of {} segments in the NZB itself",
+                    file.filename,
+                    file.missing.len(),
+                    file.expected
+                )
+            })
+            .collect()
+    }
+}
+
+/// Segment-list problems of one file
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct FileReport {
+    /// 1-based position in the NZB, as accepted by `--select`
+    pub index: usize,
+    pub filename: String,
+    /// Segments the subject's `(1/N)` promises, else the highest number listed
+    pub expected: u32,
+    /// Distinct segment numbers from 1 to `expected` that are listed
+    pub present: u32,
+    /// Numbers from 1 to `expected` that aren't listed
+    pub missing: Vec<u32>,
+    /// Numbers listed more than once
+    pub duplicates: Vec<u32>,
+    /// Numbers that can't belong to the file: 0, or past `expected`
+    pub out_of_range: Vec<u32>,
+    /// Message-IDs listed again, in this file or an earlier one
+    pub duplicate_ids: Vec<String>,
+    /// Segments declaring no bytes at all
+    pub zero_bytes: Vec<u32>,
+    /// Segments declaring several times the file's median segment size
+    pub oversized: Vec<u32>,
+    /// Whether the numbers are too broken to order the segments by (see
+    /// [`NzbFile::numbering_usable`]); they're then placed by their yEnc
+    /// part offsets, or else in the order the NZB lists them
+    pub unusable_numbering: bool,
+}
+
+impl FileReport {
+    pub fn is_clean(&self) -> bool {
+        self.missing.is_empty() && self.warnings().is_empty()
+    }
+
+    /// Every problem, gaps first, one line each
+    pub fn describe(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        if !self.missing.is_empty() {
+            lines.push(format!(
+                "missing {} of {} segments: {}",
+                self.missing.len(),
+                self.expected,
+                ranges(&self.missing)
+            ));
+        }
+        lines.extend(self.warnings());
+        lines
+    }
+
+    /// The problems other than gaps, which callers show on their own
+    pub fn warnings(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        if self.unusable_numbering {
+            lines.push(
+                "segment numbers can't order the file; placed by yEnc offsets, else NZB order"
+                    .to_string(),
+            );
+        }
+        if !self.duplicates.is_empty() {
+            lines.push(format!(
+                "segments listed more than once: {}",
+                ranges(&self.duplicates)
+            ));
+        }
+        if !self.out_of_range.is_empty() {
+            lines.push(format!(
+                "segment numbers outside 1-{}: {}",
+                self.expected,
+                ranges(&self.out_of_range)
+            ));
+        }
+        if !self.duplicate_ids.is_empty() {
+            let shown: Vec<&str> = self
+                .duplicate_ids
+                .iter()
+                .take(MAX_SHOWN)
+                .map(String::as_str)
+                .collect();
+            lines.push(format!(
+                "{} Message-ID{} listed again: {}{}",
+                self.duplicate_ids.len(),
+                if self.duplicate_ids.len() == 1 {
+                    ""
+                } else {
+                    "s"
+                },
+                shown.join(", "),
+                if self.duplicate_ids.len() > MAX_SHOWN {
+                    ", …"
+                } else {
+                    ""
+                }
+            ));
+        }
+        if !self.zero_bytes.is_empty() {
+            lines.push(format!(
+                "segments declaring 0 bytes: {}",
+                ranges(&self.zero_bytes)
+            ));
+        }
+        if !self.oversized.is_empty() {
+            lines.push(format!(
+                "segments declaring over {}x the usual size: {}",
+                OVERSIZED_FACTOR,
+                ranges(&self.oversized)
+            ));
+        }
+        lines
+    }
+
+    fn of(file: &NzbFile, seen_ids: &mut HashMap<String, usize>) -> Self {
+        let segments = &file.segments.segment;
+        let highest = segments.iter().map(|s| s.number).max().unwrap_or(0);
+        let expected = part_count(&file.subject).unwrap_or(highest);
+
+        let mut counts: HashMap<u32, usize> = HashMap::new();
+        for segment in segments {
+            *counts.entry(segment.number).or_default() += 1;
+        }
+        let mut duplicates: Vec<u32> = counts
+            .iter()
+            .filter(|&(_, &count)| count > 1)
+            .map(|(&number, _)| number)
+            .collect();
+        duplicates.sort_unstable();
+        let mut out_of_range: Vec<u32> = counts
+            .keys()
+            .copied()
+            .filter(|&number| number == 0 || number > expected)
+            .collect();
+        out_of_range.sort_unstable();
+        // Gaps mean nothing when the numbers don't
+        let unusable_numbering = !file.numbering_usable();
+        let missing: Vec<u32> = if unusable_numbering {
+            Vec::new()
+        } else {
+            (1..=expected)
+                .filter(|number| !counts.contains_key(number))
+                .collect()
+        };
+
+        let mut duplicate_ids = Vec::new();
+        for segment in segments {
+            let id = segment.message_id.trim_matches(|c| c == '<' || c == '>');
+            let seen = seen_ids.entry(id.to_string()).or_default();
+            *seen += 1;
+            if *seen == 2 {
+                duplicate_ids.push(id.to_string());
+            }
+        }
+
+        // A median, so the short last segment doesn't skew what's usual
+        let mut sizes: Vec<u64> = segments.iter().map(|s| s.bytes).collect();
+        sizes.sort_unstable();
+        let median = sizes.get(sizes.len() / 2).copied().unwrap_or(0);
+        let mut zero_bytes: Vec<u32> = segments
+            .iter()
+            .filter(|s| s.bytes == 0)
+            .map(|s| s.number)
+            .collect();
+        zero_bytes.sort_unstable();
+        let mut oversized: Vec<u32> = segments
+            .iter()
+            .filter(|s| median > 0 && s.bytes > median * OVERSIZED_FACTOR)
+            .map(|s| s.number)
+            .collect();
+        oversized.sort_unstable();
+
+        Self {
+            index: file.index,
+            filename: Nzb::get_filename_from_subject(&file.subject)
+                .unwrap_or_else(|| file.subject.clone()),
+            expected,
+            present: expected - missing.len() as u32,
+            missing,
+            duplicates,
+            out_of_range,
+            duplicate_ids,
+            zero_bytes,
+            oversized,
+            unusable_numbering,
+        }
+    }
+}
+
+impl Nzb {
+    /// Check every file's segment list
+    ///
+    /// A file whose subject ends in `(1/N)` should list segments 1 to N once
+    /// each; without one the highest number listed stands in for N.
+    pub fn validate(&self) -> NzbReport {
+        NzbReport::of(self.files())
+    }
+}
+
+/// Part count from a subject like `"a.rar" yEnc (1/120)`
+pub(super) fn part_count(subject: &str) -> Option<u32> {
+    PART_COUNT
+        .captures(subject)
+        .and_then(|caps| caps[1].parse().ok())
+        .filter(|&count| count > 0)
+}
+
+/// Sorted numbers as `1-3, 7, 9-12`, cut short after [`MAX_SHOWN`] runs
+fn ranges(numbers: &[u32]) -> String {
+    let mut runs: Vec<(u32, u32)> = Vec::new();
+    for &number in numbers {
+        match runs.last_mut() {
+            Some((_, end)) if number == *end + 1 => *end = number,
+            _ => runs.push((number, number)),
+        }
+    }
+    let mut parts: Vec<String> = runs
+        .iter()
+        .take(MAX_SHOWN)
+        .map(|&(start, end)| {
+            if start == end {
+                start.to_string()
+            } else {
+                format!("{}-{}", start, end)
+            }
+        })
+        .collect();
+    if runs.len() > MAX_SHOWN {
+        parts.push("…".to_string());
+    }
+    parts.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::download::nzb::{NzbGroups, NzbSegment, NzbSegments};
+
+    /// Files as `(subject, segments)`, segments as `(number, bytes, id)`
+    fn validate(files: &[(&str, &[(u32, u64, &str)])]) -> NzbReport {
+        let files: Vec<NzbFile> = files
+            .iter()
+            .enumerate()
+            .map(|(i, (subject, segments))| NzbFile {
+                index: i + 1,
+                poster: "p@example.com".to_string(),
+                date: 1_234_567_890,
+                subject: subject.to_string(),
+                groups: NzbGroups { group: Vec::new() },
+                segments: NzbSegments {
+                    segment: segments
+                        .iter()
+                        .map(|&(number, bytes, id)| NzbSegment {
+                            bytes,
+                            number,
+                            message_id: id.to_string(),
+                        })
+                        .collect(),
+                },
+                name: None,
+            })
+            .collect();
+        NzbReport::of(&files)
+    }
+
+    #[test]
+    fn test_clean_nzb() {
+        let report = validate(&[(
+            "&quot;a.rar&quot; yEnc (1/3)",
+            &[(1, 700, "a1@x"), (2, 700, "a2@x"), (3, 300, "a3@x")],
+        )]);
+        assert!(report.is_clean());
+        assert_eq!(report.files[0].expected, 3);
+        assert_eq!(report.files[0].present, 3);
+        assert!(report.gap_warnings().is_empty());
+    }
+
+    #[test]
+    fn test_gaps_against_the_subject() {
+        // Segments 3 and 5 never made it into the NZB
+        let report = validate(&[(
+            "[1/1] - &quot;a.rar&quot; yEnc (1/5)",
+            &[(1, 700, "a1@x"), (2, 700, "a2@x"), (4, 700, "a4@x")],
+        )]);
+        let file = &report.files[0];
+        assert_eq!(file.filename, "a.rar");
+        assert_eq!(file.missing, [3, 5]);
+        assert_eq!(file.present, 3);
+        assert_eq!(
+            report.gap_warnings(),
+            ["file a.rar is missing 2 of 5 segments in the NZB itself"]
+        );
+        assert_eq!(file.describe()[0], "missing 2 of 5 segments: 3, 5");
+        // Gaps aren't repeated among the other warnings
+        assert!(file.warnings().is_empty());
+    }
+
+    #[test]
+    fn test_gaps_without_a_part_count() {
+        let report = validate(&[(
+            "a.rar",
+            &[(1, 700, "a1@x"), (2, 700, "a2@x"), (6, 700, "a6@x")],
+        )]);
+        assert_eq!(report.files[0].expected, 6);
+        assert_eq!(report.files[0].missing, [3, 4, 5]);
+        assert_eq!(
+            report.files[0].describe()[0],
+            "missing 3 of 6 segments: 3-5"
+        );
+    }
+
+    #[test]
+    fn test_segment_zero_and_numbers_past_the_end() {
+        let report = validate(&[(
+            "&quot;a.rar&quot; yEnc (1/2)",
+            &[
+                (0, 700, "a0@x"),
+                (1, 700, "a1@x"),
+                (2, 700, "a2@x"),
+                (3, 700, "a3@x"),
+            ],
+        )]);
+        let file = &report.files[0];
+        assert_eq!(file.out_of_range, [0, 3]);
+        assert!(file.missing.is_empty());
+        assert!(!file.is_clean());
+        assert!(file.unusable_numbering);
+        assert_eq!(file.warnings()[0], "segment numbers outside 1-2: 0, 3");
+    }
+
+    #[test]
+    fn test_unusable_numbering() {
+        let report = validate(&[
+            // Every segment numbered 0
+            (
+                "&quot;a.rar&quot; yEnc (1/3)",
+                &[(0, 700, "a1@x"), (0, 700, "a2@x"), (0, 300, "a3@x")],
+            ),
+            // Numbers starting over for each part of the post
+            (
+                "&quot;b.rar&quot; yEnc (1/4)",
+                &[
+                    (1, 700, "b1@x"),
+                    (2, 700, "b2@x"),
+                    (1, 700, "b3@x"),
+                    (2, 300, "b4@x"),
+                ],
+            ),
+            // A gap the subject accounts for is only a gap
+            (
+                "&quot;c.rar&quot; yEnc (1/3)",
+                &[(1, 700, "c1@x"), (3, 300, "c3@x")],
+            ),
+        ]);
+        assert!(report.files[0].unusable_numbering);
+        assert!(report.files[1].unusable_numbering);
+        // Numbers that mean nothing leave no gaps to report
+        assert!(report.files[1].missing.is_empty());
+        assert!(report.files[1]
+            .warnings()
+            .iter()
+            .any(|line| line.starts_with("segment numbers can't order the file")));
+        assert!(!report.files[2].unusable_numbering);
+        assert_eq!(report.files[2].missing, [2]);
+    }
+
+    #[test]
+    fn test_duplicate_numbers_and_message_ids() {
+        let report = validate(&[
+            (
+                "&quot;a.rar&quot; yEnc (1/2)",
+                &[(1, 700, "a1@x"), (1, 700, "a1-again@x"), (2, 700, "a2@x")],
+            ),
+            // The second file reuses an article of the first
+            (
+                "&quot;b.rar&quot; yEnc (1/2)",
+                &[(1, 700, "b1@x"), (2, 700, "<a2@x>")],
+            ),
+        ]);
+        assert_eq!(report.files[0].duplicates, [1]);
+        assert!(report.files[0].duplicate_ids.is_empty());
+        assert_eq!(report.files[1].duplicate_ids, ["a2@x"]);
+        assert!(report.files[1].duplicates.is_empty());
+        assert_eq!(report.problems().count(), 2);
+    }
+
+    #[test]
+    fn test_suspicious_sizes() {
+        let report = validate(&[(
+            "&quot;a.rar&quot; yEnc (1/5)",
+            &[
+                (1, 700_000, "a1@x"),
+                (2, 0, "a2@x"),
+                (3, 700_000, "a3@x"),
+                (4, 90_000_000, "a4@x"),
+                (5, 200_000, "a5@x"),
+            ],
+        )]);
+        let file = &report.files[0];
+        assert_eq!(file.zero_bytes, [2]);
+        assert_eq!(file.oversized, [4]);
+        assert_eq!(file.warnings().len(), 2);
+    }
+
+    #[test]
+    fn test_ranges() {
+        assert_eq!(ranges(&[1, 2, 3, 7, 9, 10]), "1-3, 7, 9-10");
+        assert_eq!(ranges(&[]), "");
+        let scattered: Vec<u32> = (0..20).map(|n| n * 2).collect();
+        assert!(ranges(&scattered).ends_with(", …"));
+    }
+}
@@ -0,0 +1,722 @@
+//! Per-file writer task
+//!
+//! Each downloading file gets a blocking task that owns its file handle.
+//! Batches hand it their segments over a bounded channel as they finish, in
+//! whatever order that is; the writer holds them until the segments before
+//! them are written, then writes every run of consecutive segments with
+//! vectored writes of up to `io_buffer_size` bytes. A full channel makes the
+//! batches wait, which caps the decoded segments that haven't been handed
+//! over yet.
+//!
+//! yEnc parts are a fixed size except the last, so a segment that couldn't
+//! be fetched is zero-filled with that size to keep later segments at their
+//! real offsets.
+//!
+//! A file whose segment numbers can't be trusted is written
+//! [`Placement::ByOffset`] instead: each part goes where its `=ypart begin=`
+//! says, whatever number it was fetched under, and what never came is left
+//! as a hole that reads as zeros.
+//!
+//! A file resumed from an earlier run is opened as it is and written
+//! [`Placement::ByOffset`] too, with only its missing segments sent: what
+//! was there stays, and the file never gets shorter. Spans of the segments
+//! that weren't sent are where full parts would be, cut off at the end of
+//! the file.
+//!
+//! A decoded part can't be larger than the article the NZB declares for it,
+//! so one that is (re-posts have been seen doing it) would run into the part
+//! after it. Such a part doesn't count towards the size of a full part, and
+//! a file written in order switches to yEnc offsets when one arrives. A part
+//! that would land on data already written by another part — an oversized
+//! one with no usable offset, or one placed by its number where another part
+//! already is — isn't written at all: it's reported in
+//! [`WrittenFile::rejected`] and left for PAR2 like a missing segment.
+//!
+//! Segments aren't handed over while post-processing holds the file's disk
+//! (see [`DiskGate`]).
+
+use bytes::{Buf, Bytes};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, IoSlice, Seek, SeekFrom, Write};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use super::disk_gate::{DiskGate, WriterSlot};
+
+/// A segment for the writer: its 1-based number, its data unless it
+/// couldn't be fetched, and where its yEnc header says it starts
+type Segment = (u32, Option<Bytes>, Option<u64>);
+
+/// How segments find their place in the file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Placement {
+    /// One after another in segment order
+    InOrder,
+    /// At their yEnc part offsets, ignored past `size_limit`; a part without
+    /// one goes where its number puts it
+    ByOffset { size_limit: u64 },
+}
+
+/// Where each segment of a written file ended up
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(super) struct WrittenFile {
+    /// Offset and length of every segment, in segment order; zero-filled
+    /// segments count too
+    pub spans: Vec<(u64, u64)>,
+    /// Segments placed by their yEnc offset rather than their number
+    pub placed_by_offset: usize,
+    /// Segments that decoded larger than the NZB declares, by number
+    pub oversized: Vec<u32>,
+    /// Segments left unwritten because they'd overlap another, by number
+    pub rejected: Vec<u32>,
+}
+
+/// Handle to a file's writer task
+pub(super) struct FileWriter {
+    tx: mpsc::Sender<Segment>,
+    task: JoinHandle<io::Result<WrittenFile>>,
+    /// The gate of the disk the file is on
+    disk: Option<WriterSlot>,
+}
+
+impl FileWriter {
+    /// Start writing `file`, whose segments the NZB declares at `declared`
+    /// bytes each (0 where it doesn't say)
+    ///
+    /// `queue` is how many segments may wait in the channel; with `fsync`
+    /// the data is synced to disk before [`FileWriter::finish`] returns.
+    pub fn spawn(
+        file: File,
+        declared: Vec<u64>,
+        buffer_size: usize,
+        queue: usize,
+        fsync: bool,
+        placement: Placement,
+    ) -> Self {
+        let disk = device_gate(&file).map(|gate| gate.register_writer());
+        let (tx, mut rx) = mpsc::channel::<Segment>(queue.max(1));
+        let task = tokio::task::spawn_blocking(move || {
+            let mut assembler = Assembler::new(file, declared, buffer_size, placement);
+            while let Some((number, data, offset)) = rx.blocking_recv() {
+                assembler.add(number, data, offset)?;
+            }
+            assembler.finish(fsync)
+        });
+        Self { tx, task, disk }
+    }
+
+    /// Queue a segment, waiting while the channel is full
+    ///
+    /// A writer that failed takes no more; [`FileWriter::finish`] reports why.
+    pub async fn send(&self, number: u32, data: Option<Bytes>) {
+        self.send_at(number, data, None).await;
+    }
+
+    /// Queue a segment along with its yEnc part offset, if it has one
+    pub async fn send_at(&self, number: u32, data: Option<Bytes>, offset: Option<u64>) {
+        if let Some(disk) = &self.disk {
+            disk.gate().wait_open().await;
+        }
+        let _ = self.tx.send((number, data, offset)).await;
+    }
+
+    /// Write out everything queued, zero-filling segments that never came
+    pub async fn finish(self) -> io::Result<WrittenFile> {
+        drop(self.tx);
+        self.task.await.map_err(io::Error::other)?
+    }
+}
+
+/// The gate of the disk `file` is on, if its metadata can be read
+#[cfg(unix)]
+fn device_gate(file: &File) -> Option<std::sync::Arc<DiskGate>> {
+    use std::os::unix::fs::MetadataExt;
+    file.metadata()
+        .ok()
+        .map(|metadata| DiskGate::for_device(metadata.dev()))
+}
+
+#[cfg(not(unix))]
+fn device_gate(_file: &File) -> Option<std::sync::Arc<DiskGate>> {
+    Some(DiskGate::for_device(0))
+}
+
+/// Puts segments in order and writes them
+struct Assembler {
+    file: File,
+    segments: usize,
+    /// Article size the NZB declares for each segment; 0 when unknown
+    declared: Vec<u64>,
+    buffer_size: usize,
+    /// 0-based index of the next segment to write
+    next: usize,
+    /// Segments ahead of `next`, with their yEnc offsets
+    waiting: BTreeMap<usize, (Option<Bytes>, Option<u64>)>,
+    /// Size of a full part, from the largest segment yet that isn't the last
+    /// or oversized
+    part_size: u64,
+    offset: u64,
+    spans: Vec<(u64, u64)>,
+    /// Consecutive data not written yet
+    pending: Vec<Bytes>,
+    pending_len: usize,
+    zeros: Bytes,
+    placement: Placement,
+    /// With [`Placement::ByOffset`], where each segment went
+    placed: Vec<Option<(u64, u64)>>,
+    placed_by_offset: usize,
+    /// Segments placed by their number, which a part placed by its yEnc
+    /// offset overrules
+    by_number: Vec<usize>,
+    /// Segments written in order as zeros
+    zero_filled: Vec<usize>,
+    oversized: Vec<u32>,
+    rejected: Vec<u32>,
+    /// Length of the file before anything was written; 0 unless resumed
+    existing: u64,
+}
+
+impl Assembler {
+    fn new(file: File, declared: Vec<u64>, buffer_size: usize, placement: Placement) -> Self {
+        let segments = declared.len();
+        let existing = file.metadata().map_or(0, |metadata| metadata.len());
+        let placed = match placement {
+            Placement::InOrder => Vec::new(),
+            Placement::ByOffset { .. } => vec![None; segments],
+        };
+        Self {
+            file,
+            segments,
+            declared,
+            buffer_size: buffer_size.max(1),
+            next: 0,
+            waiting: BTreeMap::new(),
+            part_size: 0,
+            offset: 0,
+            spans: Vec::with_capacity(segments),
+            pending: Vec::new(),
+            pending_len: 0,
+            zeros: Bytes::new(),
+            placement,
+            placed,
+            placed_by_offset: 0,
+            by_number: Vec::new(),
+            zero_filled: Vec::new(),
+            oversized: Vec::new(),
+            rejected: Vec::new(),
+            existing,
+        }
+    }
+
+    /// Whether `len` decoded bytes are more than segment `index`'s article holds
+    fn is_oversized(&self, index: usize, len: u64) -> bool {
+        self.declared
+            .get(index)
+            .is_some_and(|&declared| declared > 0 && len > declared)
+    }
+
+    fn add(&mut self, number: u32, data: Option<Bytes>, offset: Option<u64>) -> io::Result<()> {
+        let index = number.saturating_sub(1) as usize;
+        if index >= self.segments || index < self.next {
+            tracing::debug!(
+                "Invalid segment number: {} (expected {}-{})",
+                number,
+                self.next + 1,
+                self.segments
+            );
+            return Ok(());
+        }
+        let mut data = data;
+        if let Some(len) = data.as_ref().map(|data| data.len() as u64) {
+            if self.is_oversized(index, len) {
+                self.oversized.push(number);
+                // In order it would push everything after it out of place
+                if self.placement == Placement::InOrder {
+                    let size_limit = self.declared.iter().sum();
+                    if offset.is_some_and(|offset| offset.saturating_add(len) <= size_limit) {
+                        self.switch_to_offsets(size_limit)?;
+                    } else {
+                        self.rejected.push(number);
+                        data = None;
+                    }
+                }
+            } else if index + 1 < self.segments {
+                self.part_size = self.part_size.max(len);
+            }
+        }
+        if let Placement::ByOffset { size_limit } = self.placement {
+            return self.place(index, data, offset, size_limit);
+        }
+        self.waiting.insert(index, (data, offset));
+        self.write_ready(false)
+    }
+
+    /// Place the rest of an in-order file by yEnc offset; what's written
+    /// stays where it is, as if placed by number
+    fn switch_to_offsets(&mut self, size_limit: u64) -> io::Result<()> {
+        self.flush_pending()?;
+        self.placement = Placement::ByOffset { size_limit };
+        self.placed = vec![None; self.segments];
+        for (index, &span) in self.spans.iter().enumerate() {
+            if !self.zero_filled.contains(&index) {
+                self.placed[index] = Some(span);
+                self.by_number.push(index);
+            }
+        }
+        for (index, (data, offset)) in std::mem::take(&mut self.waiting) {
+            self.place(index, data, offset, size_limit)?;
+        }
+        Ok(())
+    }
+
+    /// Whether `start..end` runs into data another segment than `index`
+    /// wrote, counting those placed by number only if `by_number`
+    fn overlaps(&self, index: usize, start: u64, end: u64, by_number: bool) -> bool {
+        self.placed.iter().enumerate().any(|(other, span)| {
+            other != index
+                && (by_number || !self.by_number.contains(&other))
+                && span.is_some_and(|(offset, len)| start < offset + len && offset < end)
+        })
+    }
+
+    /// Write a segment where its yEnc offset says, or else where its number does
+    fn place(
+        &mut self,
+        index: usize,
+        data: Option<Bytes>,
+        offset: Option<u64>,
+        size_limit: u64,
+    ) -> io::Result<()> {
+        // What never came stays a hole
+        let Some(data) = data else {
+            return Ok(());
+        };
+        let len = data.len() as u64;
+        let oversized = self.is_oversized(index, len);
+        let by_offset = offset.filter(|&offset| offset.saturating_add(len) <= size_limit);
+        let offset = by_offset.unwrap_or(index as u64 * self.part_size.max(len));
+
+        // A part placed by number mustn't land on another part, nor an
+        // oversized one on a part at its own yEnc offset; an oversized part
+        // without an offset has no place at all
+        let conflict = match (by_offset, oversized) {
+            (None, true) => true,
+            (None, false) => self.overlaps(index, offset, offset + len, true),
+            (Some(_), true) => self.overlaps(index, offset, offset + len, false),
+            (Some(_), false) => false,
+        };
+        if conflict {
+            self.rejected.push(index as u32 + 1);
+            return Ok(());
+        }
+
+        // A part at its yEnc offset overrules those put there by number
+        if by_offset.is_some() {
+            self.placed_by_offset += 1;
+            let end = offset + len;
+            let placed = &mut self.placed;
+            let rejected = &mut self.rejected;
+            self.by_number.retain(|&other| match placed[other] {
+                Some((start, other_len)) if start < end && offset < start + other_len => {
+                    placed[other] = None;
+                    rejected.push(other as u32 + 1);
+                    false
+                }
+                _ => true,
+            });
+        } else {
+            self.by_number.push(index);
+        }
+
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(&data)?;
+        self.placed[index] = Some((offset, len));
+        self.offset = self.offset.max(offset + len);
+        Ok(())
+    }
+
+    /// Write the run of segments from `next` on that can be placed; at the
+    /// end every remaining segment can be
+    fn write_ready(&mut self, end: bool) -> io::Result<()> {
+        while self.next < self.segments {
+            let data = match self.waiting.first_key_value() {
+                Some((&index, _)) if index == self.next => {
+                    self.waiting.pop_first().and_then(|(_, (data, _))| data)
+                }
+                _ if end => None,
+                _ => break,
+            };
+            let is_last = self.next + 1 == self.segments;
+            let data = match data {
+                Some(data) => data,
+                None if is_last => Bytes::new(),
+                // The fill size isn't known until a full part has arrived
+                None if self.part_size == 0 && !end => {
+                    self.waiting.insert(self.next, (None, None));
+                    break;
+                }
+                None => {
+                    self.zero_filled.push(self.next);
+                    self.zero_fill()
+                }
+            };
+            let len = data.len() as u64;
+            self.spans.push((self.offset, len));
+            self.offset += len;
+            self.next += 1;
+            if !data.is_empty() {
+                self.pending_len += data.len();
+                self.pending.push(data);
+            }
+            if self.pending_len >= self.buffer_size {
+                self.flush_pending()?;
+            }
+        }
+        self.flush_pending()
+    }
+
+    /// A part's worth of zeros
+    fn zero_fill(&mut self) -> Bytes {
+        if self.zeros.len() as u64 != self.part_size {
+            self.zeros = Bytes::from(vec![0u8; self.part_size as usize]);
+        }
+        self.zeros.clone()
+    }
+
+    fn flush_pending(&mut self) -> io::Result<()> {
+        let mut bufs = std::mem::take(&mut self.pending);
+        self.pending_len = 0;
+        let mut first = 0;
+        while first < bufs.len() {
+            let slices: Vec<IoSlice> = bufs[first..].iter().map(|b| IoSlice::new(b)).collect();
+            let mut written = match self.file.write_vectored(&slices) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            while written > 0 {
+                let buf = &mut bufs[first];
+                let take = written.min(buf.len());
+                buf.advance(take);
+                written -= take;
+                if buf.is_empty() {
+                    first += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Size of a full part; in a resumed file where no full part was
+    /// fetched, worked out from where a part after the first went
+    fn resumed_part_size(&self) -> u64 {
+        if self.part_size > 0 || self.existing == 0 {
+            return self.part_size;
+        }
+        (self.placed.iter().enumerate().skip(1))
+            .find_map(|(index, span)| span.map(|(offset, _)| offset / index as u64))
+            .unwrap_or(0)
+    }
+
+    fn finish(mut self, fsync: bool) -> io::Result<WrittenFile> {
+        match self.placement {
+            Placement::InOrder => self.write_ready(true)?,
+            Placement::ByOffset { .. } => {
+                // Holes read as zeros once the file reaches past them; a
+                // missing segment's span is where a full part would be
+                let end = self.offset.max(self.existing);
+                self.file.set_len(end)?;
+                let part_size = self.resumed_part_size();
+                let resumed = self.existing > 0;
+                self.spans = (self.placed.iter().enumerate())
+                    .map(|(index, span)| {
+                        span.unwrap_or_else(|| {
+                            let offset = index as u64 * part_size;
+                            if resumed {
+                                (offset.min(end), part_size.min(end.saturating_sub(offset)))
+                            } else {
+                                (offset, part_size)
+                            }
+                        })
+                    })
+                    .collect();
+            }
+        }
+        self.file.flush()?;
+        if fsync {
+            self.file.sync_all()?;
+        }
+        self.rejected.sort_unstable();
+        Ok(WrittenFile {
+            spans: self.spans,
+            placed_by_offset: self.placed_by_offset,
+            oversized: self.oversized,
+            rejected: self.rejected,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_out_of_order_segments_land_in_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.bin");
+        let writer = FileWriter::spawn(
+            File::create(&path).unwrap(),
+            vec![0; 5],
+            4,
+            2,
+            true,
+            Placement::InOrder,
+        );
+
+        // Segment 2 is missing and arrives before any full part does
+        writer.send(2, None).await;
+        writer.send(5, Some(Bytes::from_static(b"e"))).await;
+        writer.send(3, Some(Bytes::from_static(b"ccc"))).await;
+        writer.send(1, Some(Bytes::from_static(b"aaa"))).await;
+        writer.send(9, Some(Bytes::from_static(b"zzz"))).await;
+        // Segment 4 never comes
+        let written = writer.finish().await.unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"aaa\0\0\0ccc\0\0\0e");
+        assert_eq!(written.spans, [(0, 3), (3, 3), (6, 3), (9, 3), (12, 1)]);
+    }
+
+    #[test]
+    fn test_runs_are_written_in_buffer_sized_batches() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("b.bin");
+        let mut assembler = Assembler::new(
+            File::create(&path).unwrap(),
+            vec![0; 4],
+            8,
+            Placement::InOrder,
+        );
+
+        assembler
+            .add(2, Some(Bytes::from_static(b"2222")), None)
+            .unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 0);
+        assembler
+            .add(1, Some(Bytes::from_static(b"1111")), None)
+            .unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"11112222");
+        assembler
+            .add(4, Some(Bytes::from_static(b"44")), None)
+            .unwrap();
+        assembler
+            .add(3, Some(Bytes::from_static(b"3333")), None)
+            .unwrap();
+        let written = assembler.finish(false).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"1111222233334444"[..14]);
+        assert_eq!(written.spans.last(), Some(&(12, 2)));
+    }
+
+    #[tokio::test]
+    async fn test_parts_placed_by_offset() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("c.bin");
+        let writer = FileWriter::spawn(
+            File::create(&path).unwrap(),
+            vec![0; 4],
+            4,
+            2,
+            false,
+            Placement::ByOffset { size_limit: 100 },
+        );
+
+        // Listed in the wrong order; the yEnc offsets know better
+        writer
+            .send_at(1, Some(Bytes::from_static(b"ccc")), Some(6))
+            .await;
+        writer
+            .send_at(2, Some(Bytes::from_static(b"aaa")), Some(0))
+            .await;
+        writer
+            .send_at(3, Some(Bytes::from_static(b"d")), Some(9))
+            .await;
+        // The part at 3 never comes, so it reads as zeros
+        writer.send_at(4, None, None).await;
+        let written = writer.finish().await.unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"aaa\0\0\0cccd");
+        assert_eq!(written.spans, [(6, 3), (0, 3), (9, 1), (9, 3)]);
+        assert_eq!(written.placed_by_offset, 3);
+    }
+
+    #[tokio::test]
+    async fn test_resumed_file_keeps_what_was_there() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("r.bin");
+        let resume = |kept: &[u8]| {
+            std::fs::write(&path, kept).unwrap();
+            let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+            FileWriter::spawn(
+                file,
+                vec![0; 4],
+                4,
+                2,
+                false,
+                Placement::ByOffset { size_limit: 100 },
+            )
+        };
+
+        // Segments 2 and 4 missing; the last was left out in order
+        let writer = resume(b"aaa\0\0\0ccc");
+        writer
+            .send_at(2, Some(Bytes::from_static(b"bbb")), Some(3))
+            .await;
+        writer
+            .send_at(4, Some(Bytes::from_static(b"d")), Some(9))
+            .await;
+        let written = writer.finish().await.unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"aaabbbcccd");
+        assert_eq!(written.spans, [(0, 3), (3, 3), (6, 3), (9, 1)]);
+
+        // Only the last one missing: the part size comes from its offset
+        let writer = resume(b"aaabbbccc");
+        writer
+            .send_at(4, Some(Bytes::from_static(b"d")), Some(9))
+            .await;
+        let written = writer.finish().await.unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"aaabbbcccd");
+        assert_eq!(written.spans, [(0, 3), (3, 3), (6, 3), (9, 1)]);
+    }
+
+    #[test]
+    fn test_implausible_offsets_fall_back_to_numbers() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("d.bin");
+        let placement = Placement::ByOffset { size_limit: 8 };
+        let mut assembler = Assembler::new(File::create(&path).unwrap(), vec![0; 2], 8, placement);
+
+        assembler
+            .add(1, Some(Bytes::from_static(b"1111")), Some(u64::MAX - 1))
+            .unwrap();
+        assembler
+            .add(2, Some(Bytes::from_static(b"22")), None)
+            .unwrap();
+        let written = assembler.finish(false).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"111122");
+        assert_eq!(written.placed_by_offset, 0);
+    }
+
+    /// Three parts of `aaaa` `bbbbbb` `cc`, the middle one bigger than the
+    /// 5 bytes its article is declared at
+    const DECLARED: [u64; 3] = [5, 5, 3];
+
+    #[tokio::test]
+    async fn test_oversized_part_switches_to_offsets() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("e.bin");
+        let writer = FileWriter::spawn(
+            File::create(&path).unwrap(),
+            DECLARED.to_vec(),
+            64,
+            4,
+            false,
+            Placement::InOrder,
+        );
+        writer
+            .send_at(1, Some(Bytes::from_static(b"aaaa")), Some(0))
+            .await;
+        writer
+            .send_at(3, Some(Bytes::from_static(b"cc")), Some(10))
+            .await;
+        writer
+            .send_at(2, Some(Bytes::from_static(b"bbbbbb")), Some(4))
+            .await;
+        let written = writer.finish().await.unwrap();
+
+        // Written whole, nothing overwritten
+        assert_eq!(std::fs::read(&path).unwrap(), b"aaaabbbbbbcc");
+        assert_eq!(written.oversized, [2]);
+        assert!(written.rejected.is_empty());
+        assert_eq!(written.spans, [(0, 4), (4, 6), (10, 2)]);
+    }
+
+    #[test]
+    fn test_oversized_part_without_offset_is_left_for_par2() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("f.bin");
+        let mut assembler = Assembler::new(
+            File::create(&path).unwrap(),
+            DECLARED.to_vec(),
+            64,
+            Placement::InOrder,
+        );
+        assembler
+            .add(1, Some(Bytes::from_static(b"aaaa")), None)
+            .unwrap();
+        assembler
+            .add(2, Some(Bytes::from_static(b"bbbbbb")), None)
+            .unwrap();
+        assembler
+            .add(3, Some(Bytes::from_static(b"cc")), None)
+            .unwrap();
+        let written = assembler.finish(false).unwrap();
+
+        // A hole where it was, and the last part still where it belongs
+        assert_eq!(std::fs::read(&path).unwrap(), b"aaaa\0\0\0\0cc");
+        assert_eq!(written.rejected, [2]);
+    }
+
+    #[test]
+    fn test_parts_never_overlap_by_offset() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("g.bin");
+        let placement = Placement::ByOffset { size_limit: 13 };
+        let mut assembler = Assembler::new(
+            File::create(&path).unwrap(),
+            DECLARED.to_vec(),
+            64,
+            placement,
+        );
+        assembler
+            .add(1, Some(Bytes::from_static(b"aaaa")), Some(0))
+            .unwrap();
+        // Oversized with no offset: nowhere it can safely go
+        assembler
+            .add(2, Some(Bytes::from_static(b"bbbbbb")), None)
+            .unwrap();
+        assembler
+            .add(3, Some(Bytes::from_static(b"ccc")), None)
+            .unwrap();
+        let written = assembler.finish(false).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"aaaa\0\0\0\0ccc");
+        assert_eq!(written.rejected, [2]);
+
+        // Placed by number, a part would land on one at its yEnc offset
+        let path = dir.path().join("h.bin");
+        let placement = Placement::ByOffset { size_limit: 100 };
+        let mut assembler = Assembler::new(File::create(&path).unwrap(), vec![0; 3], 64, placement);
+        assembler
+            .add(1, Some(Bytes::from_static(b"aaaa")), Some(6))
+            .unwrap();
+        assembler
+            .add(2, Some(Bytes::from_static(b"bbbb")), None)
+            .unwrap();
+        assert_eq!(assembler.rejected, [2]);
+
+        // and gives way to one that arrives later
+        let path = dir.path().join("i.bin");
+        let placement = Placement::ByOffset { size_limit: 13 };
+        let mut assembler = Assembler::new(File::create(&path).unwrap(), vec![5, 5], 64, placement);
+        assembler
+            .add(2, Some(Bytes::from_static(b"bbbb")), None)
+            .unwrap();
+        assembler
+            .add(1, Some(Bytes::from_static(b"aaaaaa")), Some(2))
+            .unwrap();
+        let written = assembler.finish(false).unwrap();
+        assert_eq!(written.oversized, [1]);
+        assert_eq!(written.rejected, [2]);
+        assert_eq!(&std::fs::read(&path).unwrap()[2..8], b"aaaaaa");
+    }
+}
@@ -3,9 +3,13 @@
 //! This module provides structured error handling with proper error chains
 //! and context preservation.
 
-use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 
+use crate::download::redact_nzb_path;
+
 /// Top-level error type for the dl-nzb application
 #[derive(Error, Debug)]
 pub enum DlNzbError {
@@ -32,6 +36,215 @@ pub enum DlNzbError {
 
     #[error("JSON error: {0}")]
     SerdeJson(#[from] serde_json::Error),
+
+    #[error("History error: {0}")]
+    History(#[from] HistoryError),
+}
+
+impl DlNzbError {
+    /// How far this error got a run; the single source of process exit codes
+    pub fn run_status(&self) -> RunStatus {
+        match self {
+            Self::Config(_) | Self::Nntp(NntpError::AuthFailed(_) | NntpError::AuthRequired) => {
+                RunStatus::ConfigError
+            }
+            Self::PostProcessing(_) => RunStatus::PostProcessingFailed,
+            Self::Nzb(NzbError::EmptyNzb(_)) => RunStatus::Empty,
+            Self::Nzb(NzbError::Par2Only { .. }) => RunStatus::Par2Only,
+            Self::Download(DownloadError::Cancelled) => RunStatus::Cancelled,
+            Self::Nzb(_)
+            | Self::Nntp(_)
+            | Self::Download(_)
+            | Self::Io(_)
+            | Self::NativeTls(_)
+            | Self::SerdeJson(_)
+            | Self::History(_) => RunStatus::Aborted,
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        self.run_status().exit_code()
+    }
+
+    /// Name `path` in an [`NzbError::EmptyNzb`] that doesn't say which NZB
+    pub fn with_nzb_path(self, path: &Path) -> Self {
+        match self {
+            Self::Nzb(NzbError::EmptyNzb(None)) => {
+                NzbError::EmptyNzb(Some(path.to_path_buf())).into()
+            }
+            other => other,
+        }
+    }
+
+    /// Whether the same work may succeed if tried again after a pause
+    ///
+    /// Server and network problems (refused or dropped connections, timeouts,
+    /// an exhausted pool, a server closing down, NNTP 503 replies) are retryable. Everything the
+    /// server or user has decided for good is not: rejected credentials,
+    /// missing articles or groups, bad configuration, unreadable NZBs, local
+    /// I/O and post-processing failures.
+    pub fn is_retryable(&self) -> bool {
+        use std::io::ErrorKind;
+        match self {
+            Self::Nntp(e) => e.is_retryable(),
+            Self::Download(e) => e.is_retryable(),
+            Self::Io(e) => matches!(
+                e.kind(),
+                ErrorKind::ConnectionRefused
+                    | ErrorKind::ConnectionReset
+                    | ErrorKind::ConnectionAborted
+                    | ErrorKind::NotConnected
+                    | ErrorKind::TimedOut
+                    | ErrorKind::BrokenPipe
+                    | ErrorKind::UnexpectedEof
+                    | ErrorKind::Interrupted
+            ),
+            Self::Nzb(_)
+            | Self::Config(_)
+            | Self::PostProcessing(_)
+            | Self::NativeTls(_)
+            | Self::SerdeJson(_)
+            | Self::History(_) => false,
+        }
+    }
+
+    /// Kind of failure, for grouping in the end-of-run report
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            Self::Nzb(_) => ErrorClass::Nzb,
+            Self::Config(_) => ErrorClass::Config,
+            Self::PostProcessing(_) => ErrorClass::PostProcessing,
+            Self::Nntp(e) => e.class(),
+            Self::Download(DownloadError::WriteError { .. }) => ErrorClass::Disk,
+            Self::Download(DownloadError::PoolExhausted) => ErrorClass::Connection,
+            Self::Download(
+                DownloadError::SegmentFailed { .. } | DownloadError::InsufficientSegments { .. },
+            ) => ErrorClass::MissingArticles,
+            Self::Download(
+                DownloadError::FileFailed { .. }
+                | DownloadError::Cancelled
+                | DownloadError::Locked { .. },
+            ) => ErrorClass::Other,
+            Self::NativeTls(_) => ErrorClass::Connection,
+            Self::Io(_) if self.is_retryable() => ErrorClass::Connection,
+            Self::Io(_) => ErrorClass::Disk,
+            Self::SerdeJson(_) | Self::History(_) => ErrorClass::Other,
+        }
+    }
+}
+
+/// What kind of failure an error is, for the end-of-run report
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorClass {
+    /// Articles no server has
+    MissingArticles,
+    /// Articles that arrived damaged
+    Corrupt,
+    /// Connections that failed, dropped or timed out
+    Connection,
+    /// Credentials rejected or access refused
+    Auth,
+    /// Other errors reported by the server
+    Server,
+    /// Unreadable NZB
+    Nzb,
+    Config,
+    /// Local files and directories
+    Disk,
+    /// Repair, extraction, media checks or the script
+    PostProcessing,
+    Other,
+}
+
+impl ErrorClass {
+    /// Short lowercase description for messages
+    pub fn describe(self) -> &'static str {
+        match self {
+            Self::MissingArticles => "missing articles",
+            Self::Corrupt => "corrupt articles",
+            Self::Connection => "connection",
+            Self::Auth => "authentication",
+            Self::Server => "server",
+            Self::Nzb => "nzb",
+            Self::Config => "configuration",
+            Self::Disk => "disk",
+            Self::PostProcessing => "post-processing",
+            Self::Other => "other",
+        }
+    }
+}
+
+/// Outcome of an NZB or a whole run, from best to worst
+///
+/// A run reports the worst outcome of its NZBs; see [`RunStatus::exit_code`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    /// Everything downloaded and post-processed
+    Success,
+    /// Skipped: the same post was already downloaded (see `--force`)
+    Duplicate,
+    /// Download complete, but repair, extraction, media checks or the script failed
+    PostProcessingFailed,
+    /// Finished with failed segments or files
+    Incomplete,
+    /// Not downloaded: the NZB holds only PAR2 files (see `--allow-par2-only`)
+    Par2Only,
+    /// Not downloaded: the NZB lists no files
+    Empty,
+    /// Stopped before finishing (server down, removed post, unreadable NZB)
+    Aborted,
+    /// Bad configuration or rejected credentials
+    ConfigError,
+    /// Stopped by SIGTERM or Ctrl-C; left queued to finish later
+    Cancelled,
+}
+
+impl RunStatus {
+    /// Every status that isn't a success, best to worst
+    pub const FAILURES: [Self; 7] = [
+        Self::PostProcessingFailed,
+        Self::Incomplete,
+        Self::Par2Only,
+        Self::Empty,
+        Self::Aborted,
+        Self::ConfigError,
+        Self::Cancelled,
+    ];
+
+    /// Process exit code (documented in `--help`)
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Self::Success | Self::Duplicate => 0,
+            Self::Incomplete => 1,
+            Self::Par2Only | Self::Empty | Self::Aborted => 2,
+            Self::ConfigError => 3,
+            Self::PostProcessingFailed => 4,
+            // 128 + SIGINT, as shells report an interrupted command
+            Self::Cancelled => 130,
+        }
+    }
+
+    /// Whether the NZB's files are all there: downloaded now or before
+    pub fn succeeded(self) -> bool {
+        matches!(self, Self::Success | Self::Duplicate)
+    }
+
+    /// Short lowercase description for messages
+    pub fn describe(self) -> &'static str {
+        match self {
+            Self::Success => "succeeded",
+            Self::Duplicate => "already downloaded",
+            Self::PostProcessingFailed => "post-processing failed",
+            Self::Incomplete => "incomplete",
+            Self::Par2Only => "only PAR2 files",
+            Self::Empty => "empty NZB",
+            Self::Aborted => "aborted",
+            Self::ConfigError => "configuration error",
+            Self::Cancelled => "cancelled",
+        }
+    }
 }
 
 /// NZB parsing and validation errors
@@ -46,8 +259,36 @@ pub enum NzbError {
     #[error("NZB file not found: {0}")]
     NotFound(PathBuf),
 
-    #[error("No files found in NZB")]
-    EmptyNzb,
+    /// An NZB without a single file, naming it when known
+    #[error("{} lists no files", nzb_label(.0))]
+    EmptyNzb(Option<PathBuf>),
+
+    /// Nothing but PAR2 volumes, usually the wrong half of a split post
+    #[error("NZB holds only PAR2 recovery files ({files}), with nothing to repair; use --allow-par2-only to download them anyway")]
+    Par2Only { files: usize },
+
+    #[error("No files in the NZB match --only/--exclude/--select")]
+    NoMatchingFiles,
+
+    #[error("No NZB files match {0}")]
+    NoInputMatches(String),
+
+    #[error("Failed to fetch NZB from {url}: {reason}")]
+    Fetch { url: String, reason: String },
+
+    /// HTTP 401 or 403, usually a wrong or expired API key
+    #[error("Failed to fetch NZB from {url}: access denied (HTTP {status}); check the API key")]
+    FetchDenied { url: String, status: u16 },
+
+    #[error("Failed to fetch NZB from {url}: no such NZB (HTTP 404)")]
+    FetchNotFound { url: String },
+
+    /// HTTP 429 that couldn't be waited out
+    #[error("Failed to fetch NZB from {url}: rate limited (HTTP 429){}", retry_hint(.retry_after))]
+    RateLimited {
+        url: String,
+        retry_after: Option<std::time::Duration>,
+    },
 
     #[error("Missing required field: {0}")]
     MissingField(String),
@@ -56,6 +297,21 @@ pub enum NzbError {
     InvalidSegment(String),
 }
 
+/// `NZB <path>`, or `The NZB` when the path isn't known
+fn nzb_label(path: &Option<PathBuf>) -> String {
+    match path {
+        Some(path) => format!("NZB {}", redact_nzb_path(path).display()),
+        None => "The NZB".to_string(),
+    }
+}
+
+/// `; try again in 600s` for a rate limit that says when it ends
+fn retry_hint(retry_after: &Option<std::time::Duration>) -> String {
+    retry_after
+        .map(|wait| format!("; try again in {}s", wait.as_secs()))
+        .unwrap_or_default()
+}
+
 /// NNTP protocol and connection errors
 #[derive(Error, Debug)]
 pub enum NntpError {
@@ -75,6 +331,15 @@ pub enum NntpError {
     #[error("Authentication failed: {0}")]
     AuthFailed(String),
 
+    #[error("Server requires authentication")]
+    AuthRequired,
+
+    #[error("Server is closing the connection: {message}")]
+    ServerClosing { message: String },
+
+    #[error("Access denied (connection limit or permissions): {message}")]
+    AccessDenied { message: String },
+
     #[error("Protocol error: {0}")]
     ProtocolError(String),
 
@@ -94,6 +359,46 @@ pub enum NntpError {
     UnhealthyConnection,
 }
 
+impl NntpError {
+    /// Whether a new connection or a later attempt may get a different answer
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::ConnectionFailed { .. }
+            | Self::Timeout { .. }
+            | Self::UnhealthyConnection
+            | Self::ServerClosing { .. } => true,
+            // 503: service temporarily unavailable or program fault
+            Self::ServerError { code, .. } => *code == 503,
+            Self::TlsError(_)
+            | Self::AuthFailed(_)
+            | Self::AuthRequired
+            | Self::AccessDenied { .. }
+            | Self::ProtocolError(_)
+            | Self::ArticleNotFound { .. }
+            | Self::GroupNotFound { .. }
+            | Self::YencDecode(_) => false,
+        }
+    }
+
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            Self::ConnectionFailed { .. }
+            | Self::Timeout { .. }
+            | Self::TlsError(_)
+            | Self::UnhealthyConnection
+            | Self::ServerClosing { .. } => ErrorClass::Connection,
+            Self::AuthFailed(_) | Self::AuthRequired | Self::AccessDenied { .. } => {
+                ErrorClass::Auth
+            }
+            Self::ArticleNotFound { .. } | Self::GroupNotFound { .. } => {
+                ErrorClass::MissingArticles
+            }
+            Self::YencDecode(_) => ErrorClass::Corrupt,
+            Self::ProtocolError(_) | Self::ServerError { .. } => ErrorClass::Server,
+        }
+    }
+}
+
 /// Configuration validation errors
 #[derive(Error, Debug)]
 pub enum ConfigError {
@@ -112,6 +417,9 @@ pub enum ConfigError {
     #[error("Credentials not configured")]
     NoCredentials,
 
+    #[error("Failed to read password from {origin}: {reason}")]
+    PasswordSource { origin: String, reason: String },
+
     #[error("Invalid connection count: {count} (must be 1-100)")]
     InvalidConnections { count: u16 },
 
@@ -144,19 +452,117 @@ pub enum DownloadError {
     #[error("Download cancelled")]
     Cancelled,
 
-    #[error("Write error for {path}: {source}")]
+    #[error("Cannot write to {path}: {reason}")]
     WriteError {
         path: PathBuf,
+        /// What the filesystem said, in words ([`DownloadError::write`])
+        reason: String,
         source: std::io::Error,
     },
+
+    #[error(
+        "Another dl-nzb instance{} is {} {}",
+        .pid.map(|pid| format!(" (pid {})", pid)).unwrap_or_default(),
+        .activity,
+        .dir.display()
+    )]
+    Locked {
+        dir: PathBuf,
+        pid: Option<u32>,
+        /// What the holder is doing with `dir`, e.g. "downloading into"
+        activity: &'static str,
+    },
+}
+
+/// `EROFS`, `EDQUOT` and `ENOSPC`, which `std::io::ErrorKind` only names on
+/// newer toolchains
+#[cfg(target_os = "linux")]
+const WRITE_ERRNOS: [i32; 3] = [30, 122, 28];
+#[cfg(all(unix, not(target_os = "linux")))]
+const WRITE_ERRNOS: [i32; 3] = [30, 69, 28];
+/// `ERROR_WRITE_PROTECT`, `ERROR_DISK_QUOTA_EXCEEDED` and `ERROR_DISK_FULL`
+#[cfg(windows)]
+const WRITE_ERRNOS: [i32; 3] = [19, 1295, 112];
+
+impl DownloadError {
+    /// Writing under `path` failed, with the usual causes spelled out
+    pub fn write(path: &Path, source: std::io::Error) -> Self {
+        let [read_only, quota, full] = WRITE_ERRNOS;
+        let reason = match (source.kind(), source.raw_os_error()) {
+            (std::io::ErrorKind::PermissionDenied, _) => "permission denied".to_string(),
+            (_, Some(code)) if code == read_only => "the filesystem is read-only".to_string(),
+            (_, Some(code)) if code == quota => "the disk quota is exceeded".to_string(),
+            (_, Some(code)) if code == full => "the disk is full".to_string(),
+            _ => source.to_string(),
+        };
+        Self::WriteError {
+            path: path.to_path_buf(),
+            reason,
+            source,
+        }
+    }
+
+    /// A file whose download panicked, failed with what the panic said
+    pub fn panicked(filename: &str, payload: &(dyn Any + Send)) -> Self {
+        Self::FileFailed {
+            filename: filename.to_string(),
+            reason: format!("panicked: {}", panic_message(payload)),
+        }
+    }
+
+    /// Only running out of connections clears up by itself; the other
+    /// variants already report the outcome of any retries
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::PoolExhausted => true,
+            Self::SegmentFailed { .. }
+            | Self::FileFailed { .. }
+            | Self::InsufficientSegments { .. }
+            | Self::Cancelled
+            | Self::WriteError { .. }
+            | Self::Locked { .. } => false,
+        }
+    }
+}
+
+/// What a caught panic said: its message when it was raised with one
+pub fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("no message")
+}
+
+/// Download history errors
+#[derive(Error, Debug)]
+pub enum HistoryError {
+    #[error("No history entry #{0}")]
+    NotFound(u64),
+
+    #[error("Could not determine the config directory for the history and queue files")]
+    NoLocation,
+
+    #[error("No failed downloads in history")]
+    NoFailed,
+
+    #[error("NZB for history entry #{id} no longer exists: {path}")]
+    NzbMissing { id: u64, path: PathBuf },
+
+    #[error("#{id} already completed into {dir}; use --force to download it again")]
+    AlreadyComplete { id: u64, dir: PathBuf },
 }
 
 /// Post-processing errors (PAR2, RAR extraction)
 #[derive(Error, Debug)]
 pub enum PostProcessingError {
+    #[cfg(feature = "builtin-par2")]
     #[error("PAR2 error: {0}")]
     Par2(#[from] par2_rs::Par2Error),
 
+    #[error("PAR2 failed: {reason}")]
+    Par2Failed { reason: String },
+
     #[error("RAR extraction failed for {archive}: {reason}")]
     RarFailed { archive: PathBuf, reason: String },
 
@@ -169,6 +575,19 @@ pub enum PostProcessingError {
     #[error("Extraction tool not found: {tool}")]
     ToolNotFound { tool: String },
 
+    #[error("Insufficient disk space to extract {archive}: {required} bytes needed, {available} available")]
+    InsufficientDiskSpace {
+        archive: PathBuf,
+        required: u64,
+        available: u64,
+    },
+
+    #[error("Password required for {archive}")]
+    PasswordRequired { archive: PathBuf },
+
+    #[error("Post-processing script {script} failed: {reason}")]
+    ScriptFailed { script: PathBuf, reason: String },
+
     #[error("Failed to rename file from {from} to {to}: {source}")]
     FileRenameError {
         from: PathBuf,
@@ -217,16 +636,207 @@ where
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_write_error_names_the_cause() {
+        let dir = Path::new("/srv/media");
+        let denied = DownloadError::write(dir, std::io::ErrorKind::PermissionDenied.into());
+        assert_eq!(
+            denied.to_string(),
+            "Cannot write to /srv/media: permission denied"
+        );
+        let causes = WRITE_ERRNOS.map(|code| {
+            DownloadError::write(dir, std::io::Error::from_raw_os_error(code)).to_string()
+        });
+        assert_eq!(
+            causes,
+            [
+                "Cannot write to /srv/media: the filesystem is read-only",
+                "Cannot write to /srv/media: the disk quota is exceeded",
+                "Cannot write to /srv/media: the disk is full",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_panicked_file_keeps_the_message() {
+        let caught = std::panic::catch_unwind(|| panic!("index {} out of range", 7)).unwrap_err();
+        assert_eq!(
+            DownloadError::panicked("show.mkv", caught.as_ref()).to_string(),
+            "Failed to download file show.mkv: panicked: index 7 out of range"
+        );
+        let caught = std::panic::catch_unwind(|| std::panic::panic_any(42)).unwrap_err();
+        assert_eq!(panic_message(caught.as_ref()), "no message");
+    }
+
     #[test]
     fn test_error_display() {
         let err = NzbError::NotFound(PathBuf::from("/test/file.nzb"));
         assert_eq!(err.to_string(), "NZB file not found: /test/file.nzb");
+
+        let locked = DownloadError::Locked {
+            dir: PathBuf::from("/srv/media/Some.Release"),
+            pid: Some(1234),
+            activity: "downloading into",
+        };
+        assert_eq!(
+            locked.to_string(),
+            "Another dl-nzb instance (pid 1234) is downloading into /srv/media/Some.Release"
+        );
+    }
+
+    #[test]
+    fn test_exit_codes() {
+        let code = |e: DlNzbError| e.exit_code();
+        assert_eq!(code(ConfigError::NoServer.into()), 3);
+        assert_eq!(code(NntpError::AuthFailed("481".to_string()).into()), 3);
+        assert_eq!(code(NntpError::Timeout { seconds: 30 }.into()), 2);
+        assert_eq!(code(DownloadError::PoolExhausted.into()), 2);
+        assert_eq!(code(NzbError::EmptyNzb(None).into()), 2);
+        assert_eq!(code(PostProcessingError::NoRarArchives.into()), 4);
+    }
+
+    #[test]
+    fn test_run_status_severity() {
+        let worst = [
+            RunStatus::Success,
+            RunStatus::PostProcessingFailed,
+            RunStatus::Incomplete,
+        ]
+        .into_iter()
+        .max();
+        assert_eq!(worst, Some(RunStatus::Incomplete));
+        assert!(RunStatus::ConfigError > RunStatus::Aborted);
+        assert!(RunStatus::Cancelled > RunStatus::ConfigError);
+        let cancelled: DlNzbError = DownloadError::Cancelled.into();
+        assert_eq!(cancelled.exit_code(), 130);
+        assert_eq!(RunStatus::Success.exit_code(), 0);
+    }
+
+    #[test]
+    fn test_empty_and_par2_only_statuses() {
+        let empty: DlNzbError = NzbError::EmptyNzb(None).into();
+        assert_eq!(empty.run_status(), RunStatus::Empty);
+        let named = empty.with_nzb_path(Path::new("/nzbs/show.nzb"));
+        assert_eq!(
+            named.to_string(),
+            "NZB error: NZB /nzbs/show.nzb lists no files"
+        );
+        // Only an unnamed one is named
+        assert_eq!(
+            named.with_nzb_path(Path::new("other.nzb")).to_string(),
+            "NZB error: NZB /nzbs/show.nzb lists no files"
+        );
+
+        let par2: DlNzbError = NzbError::Par2Only { files: 3 }.into();
+        assert_eq!(par2.run_status(), RunStatus::Par2Only);
+        assert_eq!(par2.exit_code(), 2);
+        assert_eq!(
+            serde_json::to_string(&RunStatus::Par2Only).unwrap(),
+            r#""par2_only""#
+        );
     }
 
     #[test]
     fn test_error_conversion() {
-        let nzb_err = NzbError::EmptyNzb;
+        let nzb_err = NzbError::EmptyNzb(None);
         let dl_err: DlNzbError = nzb_err.into();
         assert!(matches!(dl_err, DlNzbError::Nzb(_)));
     }
+
+    #[test]
+    fn test_retryable_classification() {
+        let io = |kind| DlNzbError::from(std::io::Error::from(kind));
+        let nntp = |e: NntpError| DlNzbError::from(e);
+        let server = |code| NntpError::ServerError {
+            code,
+            message: String::new(),
+        };
+
+        // is_retryable matches without wildcards, so new variants must be added here too
+        let table: Vec<(DlNzbError, bool)> = vec![
+            (
+                nntp(NntpError::ConnectionFailed {
+                    server: "news".into(),
+                    port: 563,
+                    source: std::io::ErrorKind::ConnectionRefused.into(),
+                }),
+                true,
+            ),
+            (nntp(NntpError::Timeout { seconds: 30 }), true),
+            (nntp(NntpError::UnhealthyConnection), true),
+            (
+                nntp(NntpError::ServerClosing {
+                    message: "bye".into(),
+                }),
+                true,
+            ),
+            (nntp(server(503)), true),
+            (nntp(server(500)), false),
+            (nntp(NntpError::AuthRequired), false),
+            (
+                nntp(NntpError::AccessDenied {
+                    message: "too many connections".into(),
+                }),
+                false,
+            ),
+            (nntp(NntpError::TlsError("bad cert".into())), false),
+            (nntp(NntpError::AuthFailed("481".into())), false),
+            (nntp(NntpError::ProtocolError("?".into())), false),
+            (
+                nntp(NntpError::ArticleNotFound {
+                    message_id: "a@b".into(),
+                }),
+                false,
+            ),
+            (
+                nntp(NntpError::GroupNotFound {
+                    group: "alt.test".into(),
+                }),
+                false,
+            ),
+            (nntp(NntpError::YencDecode("crc".into())), false),
+            (DownloadError::PoolExhausted.into(), true),
+            (DownloadError::Cancelled.into(), false),
+            (
+                DownloadError::SegmentFailed {
+                    number: 1,
+                    total: 2,
+                    reason: "430".into(),
+                }
+                .into(),
+                false,
+            ),
+            (
+                DownloadError::FileFailed {
+                    filename: "a".into(),
+                    reason: "x".into(),
+                }
+                .into(),
+                false,
+            ),
+            (
+                DownloadError::InsufficientSegments {
+                    available: 0,
+                    required: 1,
+                }
+                .into(),
+                false,
+            ),
+            (
+                DownloadError::write(Path::new("a"), std::io::ErrorKind::Other.into()).into(),
+                false,
+            ),
+            (io(std::io::ErrorKind::ConnectionReset), true),
+            (io(std::io::ErrorKind::TimedOut), true),
+            (io(std::io::ErrorKind::PermissionDenied), false),
+            (io(std::io::ErrorKind::NotFound), false),
+            (NzbError::EmptyNzb(None).into(), false),
+            (ConfigError::NoServer.into(), false),
+            (PostProcessingError::NoRarArchives.into(), false),
+            (HistoryError::NoFailed.into(), false),
+        ];
+        for (error, retryable) in table {
+            assert_eq!(error.is_retryable(), retryable, "{}", error);
+        }
+    }
 }
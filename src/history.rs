@@ -0,0 +1,662 @@
+//! Download history
+//!
+//! Every NZB that reaches the download stage leaves one JSON line in
+//! `history.jsonl` next to the config file. Writers take an exclusive lock on
+//! a sibling `.lock` file, so parallel `dl-nzb` runs can append safely; a
+//! line that fails to parse (e.g. cut short by a crash) is skipped with a
+//! warning rather than hiding the rest of the history. When the file can't
+//! be used, a run keeps its history in memory instead. NZBs kept for
+//! `retry` are written to `history/<id>.nzb` beside the file rather than
+//! inline, so the history stays small.
+
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::completion::CompletionStats;
+use crate::download::{redact_nzb_path, NzbSource, SpeedStats};
+use crate::error::{DlNzbError, HistoryError, RunStatus};
+use crate::json_output::{DownloadFileResult, DownloadSummary};
+use crate::phases::PhaseRecord;
+use crate::report::FailureCause;
+
+type Result<T> = std::result::Result<T, DlNzbError>;
+
+/// One finished NZB
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// Assigned when the entry is appended
+    pub id: u64,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// NZB the download came from: a file path, URL or `-`
+    pub nzb: PathBuf,
+    /// RFC 3339 time the NZB finished
+    pub completed_at: String,
+    pub status: RunStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_dir: Option<PathBuf>,
+    pub total_size: u64,
+    pub download_time_seconds: f64,
+    pub average_speed_mbps: f64,
+    /// Decoded bytes each server provided
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub bytes_by_server: BTreeMap<String, u64>,
+    /// Encoded bytes read from servers, whether kept or not (0 in entries
+    /// written before it was recorded)
+    #[serde(default)]
+    pub transferred: u64,
+    pub failed_segments: usize,
+    /// [`Nzb::content_hash`](crate::Nzb::content_hash) of the whole NZB; empty
+    /// for one that listed no files
+    pub content_hash: String,
+    /// Likely reason articles were missing or damaged (see [`FailureCause`])
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure_cause: Option<FailureCause>,
+    /// Speed figures from the samples taken while downloading
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speed: Option<SpeedStats>,
+    #[serde(default)]
+    pub files: Vec<DownloadFileResult>,
+    /// NZB XML for downloads that didn't come from a file (URL or stdin),
+    /// until [`History::append`] moves it to `nzb_file`; entries written
+    /// before that kept it inline
+    #[serde(default, skip_serializing)]
+    pub xml: Option<String>,
+    /// Where the NZB XML was kept for `retry`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nzb_file: Option<PathBuf>,
+    /// Connection count `usenet.auto_connections` had settled on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connections: Option<usize>,
+    /// Entry of the download the NZB was found in, for a nested NZB
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<u64>,
+    /// Download and post-processing steps, with their times and outcomes
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub phases: Vec<PhaseRecord>,
+    /// Segments fetched per newsgroup and per poster
+    #[serde(default, skip_serializing_if = "CompletionStats::is_empty")]
+    pub completion: CompletionStats,
+}
+
+impl HistoryEntry {
+    /// Entry for a finished NZB; `nzb` is where it was loaded from
+    ///
+    /// A `{{password}}` file name is kept masked, so the NZB itself is kept
+    /// for `retry` instead.
+    pub fn new(summary: &DownloadSummary, nzb: &Path, name: &str, loaded: &crate::Nzb) -> Self {
+        let entry = Self::unloaded(summary, nzb, name);
+        Self {
+            title: loaded.title().map(str::to_string),
+            content_hash: loaded.content_hash().to_string(),
+            xml: (entry.nzb != nzb).then(|| loaded.to_xml()),
+            ..entry
+        }
+    }
+
+    /// Entry for an NZB that was read but had nothing to download, so there
+    /// is no title or content hash to record
+    pub fn unloaded(summary: &DownloadSummary, nzb: &Path, name: &str) -> Self {
+        Self {
+            id: 0,
+            name: name.to_string(),
+            title: None,
+            nzb: redact_nzb_path(nzb).into_owned(),
+            completed_at: chrono::Local::now().to_rfc3339(),
+            status: summary.status,
+            error: summary.error.clone(),
+            output_dir: summary.output_dir.clone(),
+            total_size: summary.total_size,
+            download_time_seconds: summary.download_time_seconds,
+            average_speed_mbps: summary.average_speed_mbps,
+            bytes_by_server: summary.bytes_by_server.clone(),
+            transferred: summary.transferred,
+            failed_segments: summary.files.iter().map(|f| f.segments_failed).sum(),
+            content_hash: String::new(),
+            failure_cause: summary.failure_cause,
+            speed: summary.speed,
+            files: summary.files.clone(),
+            xml: None,
+            nzb_file: None,
+            connections: None,
+            parent: None,
+            phases: summary.phases.clone(),
+            completion: summary.completion.clone(),
+        }
+    }
+
+    /// Keep the NZB XML so `retry` can reload an NZB that has no file
+    pub fn with_xml(mut self, xml: Option<String>) -> Self {
+        if xml.is_some() {
+            self.xml = xml;
+        }
+        self
+    }
+
+    /// Record the connection count auto-tuning settled on, if it has
+    pub fn with_connections(mut self, connections: Option<usize>) -> Self {
+        self.connections = connections;
+        self
+    }
+
+    /// Record the download a nested NZB was found in
+    pub fn with_parent(mut self, parent: Option<u64>) -> Self {
+        self.parent = parent;
+        self
+    }
+
+    /// The NZB XML kept for `retry`, if any
+    pub fn stored_xml(&self) -> Option<String> {
+        if let Some(xml) = &self.xml {
+            return Some(xml.clone());
+        }
+        let path = self.nzb_file.as_ref()?;
+        match std::fs::read_to_string(path) {
+            Ok(xml) => Some(xml),
+            Err(e) => {
+                tracing::warn!("Failed to read {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Whether the NZB can be loaded again: stored XML, a URL, or an existing file
+    pub fn nzb_available(&self) -> bool {
+        if self.xml.is_some() || self.nzb_file.as_ref().is_some_and(|path| path.is_file()) {
+            return true;
+        }
+        match NzbSource::from_arg(&self.nzb) {
+            NzbSource::File(path) => path.exists(),
+            NzbSource::Url(_) | NzbSource::Indexer { .. } => true,
+            NzbSource::Stdin => false,
+        }
+    }
+
+    /// Whether the output folder still exists with something in it
+    pub fn has_files(&self) -> bool {
+        self.output_dir
+            .as_ref()
+            .and_then(|dir| dir.read_dir().ok())
+            .is_some_and(|mut entries| entries.next().is_some())
+    }
+
+    /// Completion time as local `YYYY-MM-DD HH:MM`
+    pub fn date(&self) -> String {
+        chrono::DateTime::parse_from_rfc3339(&self.completed_at)
+            .map(|time| {
+                time.with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d %H:%M")
+                    .to_string()
+            })
+            .unwrap_or_else(|_| self.completed_at.clone())
+    }
+}
+
+/// The history file
+#[derive(Debug, Clone)]
+pub struct History {
+    path: PathBuf,
+    /// Entries of a history that has no file ([`History::in_memory`])
+    memory: Option<Arc<Mutex<Vec<HistoryEntry>>>>,
+}
+
+impl History {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            memory: None,
+        }
+    }
+
+    /// A history that lasts as long as this process, for a run whose file
+    /// can't be used; duplicates are still caught within the run
+    pub fn in_memory() -> Self {
+        Self {
+            path: PathBuf::new(),
+            memory: Some(Arc::default()),
+        }
+    }
+
+    /// Whether this is an [`in_memory`](Self::in_memory) history
+    pub fn is_in_memory(&self) -> bool {
+        self.memory.is_some()
+    }
+
+    /// Make sure entries can be written, creating the folder if needed
+    pub fn check_writable(&self) -> Result<()> {
+        self.lock(true).map(drop)
+    }
+
+    /// `history.jsonl` in the standard config directory
+    pub fn open_default() -> Result<Self> {
+        let dir = dirs::config_dir().ok_or(HistoryError::NoLocation)?;
+        Ok(Self::new(dir.join("dl-nzb").join("history.jsonl")))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append an entry under the next free id and return that id
+    pub fn append(&self, mut entry: HistoryEntry) -> Result<u64> {
+        if let Some(memory) = &self.memory {
+            let mut entries = memory.lock().unwrap_or_else(|e| e.into_inner());
+            let id = entries.iter().map(|e| e.id).max().unwrap_or(0) + 1;
+            entry.id = id;
+            entries.push(entry);
+            return Ok(id);
+        }
+        let _lock = self.lock(true)?;
+        entry.id = self.read()?.iter().map(|e| e.id).max().unwrap_or(0) + 1;
+        self.store_xml(&mut entry);
+
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(line.as_bytes())?;
+        Ok(entry.id)
+    }
+
+    /// All entries, oldest first
+    pub fn entries(&self) -> Result<Vec<HistoryEntry>> {
+        let _lock = self.lock(false)?;
+        self.read()
+    }
+
+    /// Most recent entry that didn't succeed
+    pub fn last_failed(&self) -> Result<HistoryEntry> {
+        self.entries()?
+            .into_iter()
+            .rev()
+            .find(|e| !e.status.succeeded())
+            .ok_or_else(|| HistoryError::NoFailed.into())
+    }
+
+    /// Most recent entry for the same post, by [`HistoryEntry::content_hash`]
+    pub fn latest_for(&self, content_hash: &str) -> Result<Option<HistoryEntry>> {
+        Ok(self
+            .entries()?
+            .into_iter()
+            .rev()
+            .find(|e| e.content_hash == content_hash))
+    }
+
+    /// Connection count auto-tuning last settled on, to start the next run near it
+    pub fn tuned_connections(&self) -> Result<Option<usize>> {
+        Ok(self
+            .entries()?
+            .into_iter()
+            .rev()
+            .find_map(|e| e.connections))
+    }
+
+    pub fn get(&self, id: u64) -> Result<HistoryEntry> {
+        self.entries()?
+            .into_iter()
+            .find(|e| e.id == id)
+            .ok_or_else(|| HistoryError::NotFound(id).into())
+    }
+
+    /// Delete one entry
+    pub fn remove(&self, id: u64) -> Result<()> {
+        let _lock = self.lock(true)?;
+        let (removed, kept): (Vec<_>, Vec<_>) = self.read()?.into_iter().partition(|e| e.id == id);
+        if removed.is_empty() {
+            return Err(HistoryError::NotFound(id).into());
+        }
+        self.rewrite(kept)?;
+        Self::remove_nzb_files(&removed);
+        Ok(())
+    }
+
+    /// Delete every entry; returns how many there were
+    pub fn clear(&self) -> Result<usize> {
+        let _lock = self.lock(true)?;
+        let entries = self.read()?;
+        self.rewrite(Vec::new())?;
+        Self::remove_nzb_files(&entries);
+        Ok(entries.len())
+    }
+
+    /// Folder the NZBs kept for `retry` go in: `history/` beside the file
+    fn nzb_dir(&self) -> PathBuf {
+        self.path.with_file_name("history")
+    }
+
+    /// Move an entry's XML out to `history/<id>.nzb`
+    ///
+    /// An in-memory history keeps it inline. Failing to write it only costs
+    /// `retry` the NZB, so it's logged rather than losing the entry.
+    fn store_xml(&self, entry: &mut HistoryEntry) {
+        if self.memory.is_some() {
+            return;
+        }
+        let Some(xml) = entry.xml.take() else {
+            return;
+        };
+        let dir = self.nzb_dir();
+        let path = dir.join(format!("{}.nzb", entry.id));
+        match std::fs::create_dir_all(&dir).and_then(|()| std::fs::write(&path, xml)) {
+            Ok(()) => entry.nzb_file = Some(path),
+            Err(e) => tracing::warn!("Failed to save {}: {}", path.display(), e),
+        }
+    }
+
+    /// Delete the NZBs kept for removed entries
+    fn remove_nzb_files(entries: &[HistoryEntry]) {
+        for path in entries.iter().filter_map(|e| e.nzb_file.as_ref()) {
+            if let Err(e) = std::fs::remove_file(path) {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    tracing::warn!("Failed to remove {}: {}", path.display(), e);
+                }
+            }
+        }
+    }
+
+    /// Lock file held for the duration of a read (shared) or write
+    /// (exclusive); an in-memory history has none
+    fn lock(&self, exclusive: bool) -> Result<Option<File>> {
+        if self.memory.is_some() {
+            return Ok(None);
+        }
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut lock_path = self.path.clone().into_os_string();
+        lock_path.push(".lock");
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(lock_path)?;
+        if exclusive {
+            FileExt::lock_exclusive(&file)?;
+        } else {
+            FileExt::lock_shared(&file)?;
+        }
+        Ok(Some(file))
+    }
+
+    fn read(&self) -> Result<Vec<HistoryEntry>> {
+        if let Some(memory) = &self.memory {
+            return Ok(memory.lock().unwrap_or_else(|e| e.into_inner()).clone());
+        }
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut entries = Vec::new();
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(&line) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => tracing::warn!(
+                    "Skipping unreadable line {} of {}: {}",
+                    number + 1,
+                    self.path.display(),
+                    e
+                ),
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Replace the file's contents atomically, moving XML older entries kept
+    /// inline out to its own file on the way
+    fn rewrite(&self, mut entries: Vec<HistoryEntry>) -> Result<()> {
+        if let Some(memory) = &self.memory {
+            *memory.lock().unwrap_or_else(|e| e.into_inner()) = entries;
+            return Ok(());
+        }
+        let dir = self.path.parent().unwrap_or(Path::new("."));
+        let mut temp = tempfile::NamedTempFile::new_in(dir)?;
+        for entry in &mut entries {
+            self.store_xml(entry);
+            writeln!(temp, "{}", serde_json::to_string(entry)?)?;
+        }
+        temp.persist(&self.path).map_err(|e| e.error)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str) -> HistoryEntry {
+        HistoryEntry {
+            id: 0,
+            name: name.to_string(),
+            title: None,
+            nzb: PathBuf::from(format!("/nzbs/{}.nzb", name)),
+            completed_at: "2026-01-02T03:04:05+00:00".to_string(),
+            status: RunStatus::Success,
+            error: None,
+            output_dir: None,
+            total_size: 1024,
+            download_time_seconds: 2.0,
+            average_speed_mbps: 0.5,
+            bytes_by_server: Default::default(),
+            transferred: 0,
+            failed_segments: 0,
+            content_hash: "0".repeat(32),
+            failure_cause: None,
+            speed: None,
+            files: Vec::new(),
+            xml: None,
+            nzb_file: None,
+            connections: None,
+            parent: None,
+            phases: Vec::new(),
+            completion: CompletionStats::default(),
+        }
+    }
+
+    #[test]
+    fn test_append_assigns_increasing_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = History::new(dir.path().join("sub").join("history.jsonl"));
+        assert!(history.entries().unwrap().is_empty());
+
+        assert_eq!(history.append(entry("a")).unwrap(), 1);
+        assert_eq!(history.append(entry("b")).unwrap(), 2);
+        history.remove(1).unwrap();
+        // Ids are never reused while later entries exist
+        assert_eq!(history.append(entry("c")).unwrap(), 3);
+
+        let names: Vec<String> = history
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|e| e.name)
+            .collect();
+        assert_eq!(names, ["b", "c"]);
+        assert_eq!(history.get(3).unwrap().name, "c");
+        assert!(matches!(
+            history.get(1),
+            Err(DlNzbError::History(HistoryError::NotFound(1)))
+        ));
+        assert!(history.remove(1).is_err());
+
+        assert_eq!(history.clear().unwrap(), 2);
+        assert!(history.entries().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_last_failed_and_availability() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = History::new(dir.path().join("history.jsonl"));
+        assert!(matches!(
+            history.last_failed(),
+            Err(DlNzbError::History(HistoryError::NoFailed))
+        ));
+
+        let failed = HistoryEntry {
+            status: RunStatus::Incomplete,
+            failure_cause: Some(FailureCause::LikelyRemoved),
+            ..entry("partial")
+        };
+        history.append(failed.clone()).unwrap();
+        history.append(entry("done")).unwrap();
+        let last_failed = history.last_failed().unwrap();
+        assert_eq!(last_failed.name, "partial");
+        assert_eq!(last_failed.failure_cause, Some(FailureCause::LikelyRemoved));
+
+        // Both share a hash; the later attempt is the one that counts
+        let zeros = "0".repeat(32);
+        assert_eq!(history.latest_for(&zeros).unwrap().unwrap().name, "done");
+        assert!(history.latest_for(&"1".repeat(32)).unwrap().is_none());
+
+        // A missing file can't be reloaded, stored XML or a URL can
+        assert!(!failed.nzb_available());
+        assert!(failed
+            .clone()
+            .with_xml(Some("<nzb/>".into()))
+            .nzb_available());
+        let url = HistoryEntry {
+            nzb: PathBuf::from("https://indexer.example/getnzb/1"),
+            ..failed
+        };
+        assert!(url.nzb_available());
+    }
+
+    #[test]
+    fn test_xml_is_kept_beside_the_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = History::new(dir.path().join("history.jsonl"));
+        let id = history
+            .append(entry("url").with_xml(Some("<nzb/>".into())))
+            .unwrap();
+
+        let raw = std::fs::read_to_string(history.path()).unwrap();
+        assert!(!raw.contains("<nzb/>"));
+        let stored = history.get(id).unwrap();
+        let path = dir.path().join("history").join(format!("{}.nzb", id));
+        assert_eq!(stored.nzb_file.as_deref(), Some(path.as_path()));
+        assert_eq!(stored.stored_xml().as_deref(), Some("<nzb/>"));
+        assert!(stored.nzb_available());
+
+        // Entries from before kept it inline; a rewrite moves it out
+        let mut old = serde_json::to_value(entry("old")).unwrap();
+        old["id"] = 5.into();
+        old["xml"] = "<nzb>old</nzb>".into();
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(history.path())
+            .unwrap();
+        writeln!(file, "{}", old).unwrap();
+        assert_eq!(
+            history.get(5).unwrap().stored_xml().as_deref(),
+            Some("<nzb>old</nzb>")
+        );
+        history.remove(id).unwrap();
+        assert!(!path.exists());
+        let moved = history.get(5).unwrap();
+        assert!(moved.xml.is_none());
+        assert_eq!(moved.stored_xml().as_deref(), Some("<nzb>old</nzb>"));
+    }
+
+    #[test]
+    fn test_filename_password_is_not_kept() {
+        let nzb: crate::Nzb = r#"<?xml version="1.0" encoding="UTF-8"?>
+        <nzb xmlns="http://www.newzbin.com/DTD/2003/nzb">
+            <file poster="p@example.com" date="1234567890" subject="&quot;a.rar&quot; yEnc (1/1)">
+                <groups><group>alt.binaries.test</group></groups>
+                <segments><segment bytes="256" number="1">a@example.com</segment></segments>
+            </file>
+        </nzb>"#
+            .parse()
+            .unwrap();
+        let error = crate::error::DownloadError::Cancelled.into();
+        let path = Path::new("/nzbs/Show{{s3cret}}.nzb");
+        let summary = DownloadSummary::failed(path, None, &error);
+
+        let entry = HistoryEntry::new(&summary, path, "Show", &nzb).with_xml(None);
+        assert_eq!(entry.nzb, Path::new("/nzbs/Show{{********}}.nzb"));
+        assert!(!serde_json::to_string(&entry).unwrap().contains("s3cret"));
+        // The masked path leads nowhere, so the NZB is kept for `retry`
+        assert!(entry.nzb_available());
+
+        let plain = HistoryEntry::new(&summary, Path::new("/nzbs/Show.nzb"), "Show", &nzb);
+        assert!(plain.xml.is_none());
+    }
+
+    #[test]
+    fn test_unreadable_lines_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = History::new(dir.path().join("history.jsonl"));
+        history.append(entry("a")).unwrap();
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(history.path())
+            .unwrap();
+        file.write_all(b"{\"id\": 2, \"na").unwrap();
+
+        let entries = history.entries().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].name, "a");
+    }
+
+    #[test]
+    fn test_in_memory_history() {
+        let history = History::in_memory();
+        assert!(history.is_in_memory());
+        history.check_writable().unwrap();
+        assert_eq!(history.append(entry("a")).unwrap(), 1);
+        // Clones share the entries, as the run's copies do
+        assert_eq!(history.clone().append(entry("b")).unwrap(), 2);
+        assert_eq!(
+            history.latest_for(&"0".repeat(32)).unwrap().unwrap().name,
+            "b"
+        );
+        history.remove(1).unwrap();
+        assert_eq!(history.entries().unwrap().len(), 1);
+        assert_eq!(history.clear().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_tuned_connections_come_from_latest_entry_with_one() {
+        let history = History::in_memory();
+        assert_eq!(history.tuned_connections().unwrap(), None);
+        history
+            .append(entry("a").with_connections(Some(24)))
+            .unwrap();
+        history
+            .append(entry("b").with_connections(Some(18)))
+            .unwrap();
+        // An NZB too short for tuning to settle doesn't reset it
+        history.append(entry("c")).unwrap();
+        assert_eq!(history.tuned_connections().unwrap(), Some(18));
+    }
+
+    #[test]
+    fn test_concurrent_appends() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.jsonl");
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                let history = History::new(&path);
+                std::thread::spawn(move || history.append(entry(&i.to_string())).unwrap())
+            })
+            .collect();
+        let mut ids: Vec<u64> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+        ids.sort_unstable();
+        assert_eq!(ids, (1..=8).collect::<Vec<_>>());
+        assert_eq!(History::new(&path).entries().unwrap().len(), 8);
+    }
+}
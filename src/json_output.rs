@@ -1,53 +1,918 @@
+//! Documents printed with `--json`
+//!
+//! Output has to diff cleanly between runs of the same input, so every
+//! collection serialized here has a fixed order: `Vec`s in NZB order (or
+//! sorted by an explicit key where there isn't one) and `BTreeMap`s keyed by
+//! name, never a `HashMap` or `HashSet`. `tests/list_output.rs` holds
+//! snapshots of the list document.
+
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::completion::CompletionStats;
+use crate::config::{EffectiveConfig, SkipVerification};
+use crate::download::{
+    screen_files, serialize_nzb_path, DownloadEvent, DownloadResult, ExistingFile, Nzb,
+    NzbDownload, NzbFile, Par2Coverage, Par2Kind, PartialFiles, PostProcessPhase, ServerStats,
+    SkipReason, SkippedFile, SpeedSample, SpeedStats, SpeedTimeline,
+};
+use crate::error::DlNzbError;
+pub use crate::error::RunStatus;
+use crate::history::HistoryEntry;
+use crate::phases::PhaseRecord;
+use crate::plan::{Availability, FileSample};
+pub use crate::processing::PostProcessResult as PostProcessingResult;
+use crate::processing::ScriptOutcome;
+use crate::progress::{self, RateWindow};
+use crate::report::{Failure, FailureCause};
+use crate::speedtest::SpeedReport;
+use crate::strict::{self, Violation};
+
+/// Version of the list-mode document; bumped on incompatible changes
+pub const LIST_SCHEMA_VERSION: u32 = 1;
 
 /// JSON output for list mode
 #[derive(Debug, Serialize, Deserialize)]
 pub struct NzbInfo {
+    /// Schema version ([`LIST_SCHEMA_VERSION`])
+    pub version: u32,
     pub file: PathBuf,
+    pub meta: NzbMeta,
     pub total_files: usize,
     pub total_size: u64,
     pub total_segments: usize,
+    /// Age in days of the oldest post with a usable date
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub age_days: Option<f64>,
+    /// Age in days of the newest post with a usable date
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub newest_age_days: Option<f64>,
+    /// Whether the oldest post is beyond every configured server's retention
+    #[serde(default)]
+    pub exceeds_retention: bool,
+    /// PAR2 recovery blocks against the data blocks they protect
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub par2_coverage: Option<Par2Coverage>,
+    /// Percent of sampled segments the servers have, with `--check-availability`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completeness: Option<f64>,
+    /// `<name>.nzb.toml` sidecar with per-NZB settings, if present
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sidecar: Option<PathBuf>,
     pub files: Vec<FileInfo>,
+    /// Files a download would leave out: filtered, duplicates, no segments,
+    /// below `download.min_file_size`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skipped: Vec<SkippedFile>,
+    /// File indexes in download order, with `--show-order`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub download_order: Vec<usize>,
+}
+
+impl NzbInfo {
+    /// Describe a parsed NZB, with files under `min_file_size` among the
+    /// skipped; retention and sidecar details are left unset
+    pub fn from_nzb(path: &Path, nzb: &Nzb, min_file_size: u64) -> Self {
+        let (_, screened) = screen_files(&nzb.files().iter().collect::<Vec<_>>(), min_file_size);
+        Self {
+            version: LIST_SCHEMA_VERSION,
+            file: path.to_path_buf(),
+            meta: NzbMeta {
+                title: nzb.title().map(str::to_string),
+                category: nzb.category().map(str::to_string),
+                has_password: !nzb.passwords().is_empty(),
+            },
+            total_files: nzb.files().len(),
+            total_size: nzb.total_size(),
+            total_segments: nzb.total_segments(),
+            age_days: nzb.oldest_age_days(),
+            newest_age_days: nzb.newest_age_days(),
+            exceeds_retention: false,
+            par2_coverage: nzb.par2_coverage(),
+            completeness: None,
+            sidecar: None,
+            files: nzb.files().iter().map(FileInfo::from_nzb_file).collect(),
+            skipped: nzb.filtered_files().into_iter().chain(screened).collect(),
+            download_order: Vec::new(),
+        }
+    }
+
+    /// Fill in completeness estimates from a sample of the NZB's segments
+    pub fn set_availability(&mut self, availability: &Availability) {
+        self.completeness = availability.completeness();
+        for file in &mut self.files {
+            file.completeness = availability
+                .files
+                .get(&file.index)
+                .and_then(FileSample::completeness);
+        }
+    }
+}
+
+/// `<head>` metadata of an NZB (passwords are never included)
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NzbMeta {
+    pub title: Option<String>,
+    pub category: Option<String>,
+    pub has_password: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FileInfo {
+    /// 1-based position in the NZB, as accepted by `--select`
+    pub index: usize,
+    pub subject: String,
+    /// Name derived from the subject (the subject itself when none is found)
     pub filename: String,
     pub size: u64,
     pub segments: usize,
+    #[serde(default)]
+    pub poster: String,
+    /// The NZB's `date` attribute as given, in seconds since the Unix epoch
+    #[serde(default)]
+    pub date: u64,
+    /// Age of the post in days; absent when `date` is missing or bogus
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub age_days: Option<f64>,
     pub is_par2: bool,
+    pub par2: Par2Role,
+    /// Recovery blocks in a PAR2 volume, from its `volAA+BB` name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub par2_blocks: Option<u32>,
+    pub groups: Vec<String>,
+    /// Percent of the file's sampled segments the servers have, with
+    /// `--check-availability`; absent when the sample didn't reach the file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completeness: Option<f64>,
+}
+
+impl FileInfo {
+    fn from_nzb_file(file: &NzbFile) -> Self {
+        let par2 = Par2Role::from(file.par2_kind());
+
+        Self {
+            index: file.index,
+            subject: file.subject.clone(),
+            size: file.segments.segment.iter().map(|s| s.bytes).sum(),
+            segments: file.segments.segment.len(),
+            poster: file.poster.clone(),
+            date: file.date,
+            age_days: file.age_days(),
+            is_par2: par2 != Par2Role::None,
+            par2,
+            par2_blocks: file.par2_blocks(),
+            groups: file.groups.group.iter().map(|g| g.name.clone()).collect(),
+            completeness: None,
+            filename: file.filename(),
+        }
+    }
 }
 
-/// JSON output for download results
+/// Role of a file in a PAR2 recovery set
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Par2Role {
+    /// Not a PAR2 file
+    None,
+    /// The index file (`name.par2`)
+    Index,
+    /// A recovery volume (`name.vol00+01.par2`)
+    Volume,
+}
+
+impl From<Option<Par2Kind>> for Par2Role {
+    fn from(kind: Option<Par2Kind>) -> Self {
+        match kind {
+            None => Self::None,
+            Some(Par2Kind::Index) => Self::Index,
+            Some(Par2Kind::Volume { .. }) => Self::Volume,
+        }
+    }
+}
+
+/// Version of the download run document; bumped on incompatible changes
+pub const RUN_SCHEMA_VERSION: u32 = 2;
+
+impl RunStatus {
+    /// Status of a finished download given its files and post-processing
+    ///
+    /// Missing data outranks post-processing trouble, since the latter
+    /// usually follows from the former.
+    pub fn of_download(
+        results: &[DownloadResult],
+        expected_files: usize,
+        post_processing: &PostProcessingResult,
+        script: Option<&ScriptOutcome>,
+    ) -> Self {
+        if results.is_empty() && expected_files > 0 {
+            return Self::Aborted;
+        }
+
+        let incomplete =
+            results.len() < expected_files || results.iter().any(|r| r.segments_failed > 0);
+        let post_processing_failed = !post_processing.extract_failed.is_empty()
+            || post_processing.par2_error.is_some()
+            || !post_processing.password_required.is_empty()
+            || !post_processing.space_shortfalls.is_empty()
+            || post_processing.failed_media().next().is_some()
+            || post_processing.par2_create_error.is_some()
+            || script.is_some_and(|s| !s.success());
+        if incomplete {
+            Self::Incomplete
+        } else if post_processing_failed {
+            Self::PostProcessingFailed
+        } else {
+            Self::Success
+        }
+    }
+}
+
+/// JSON document for a download run (printed once, at the end)
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RunSummary {
+    /// Schema version ([`RUN_SCHEMA_VERSION`])
+    pub version: u32,
+    /// Worst status of any NZB
+    pub status: RunStatus,
+    pub exit_code: i32,
+    /// Why the run didn't fully succeed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub total_size: u64,
+    /// Encoded bytes read from servers across all NZBs
+    #[serde(default)]
+    pub transferred: u64,
+    pub download_time_seconds: f64,
+    pub average_speed_mbps: f64,
+    pub nzbs: Vec<DownloadSummary>,
+    /// Articles served per server
+    pub servers: Vec<ServerStats>,
+    /// Segments fetched per newsgroup and per poster, across all NZBs
+    #[serde(default, skip_serializing_if = "CompletionStats::is_empty")]
+    pub completion: CompletionStats,
+    /// The settings the run used, secrets masked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub settings: Option<EffectiveConfig>,
+}
+
+impl RunSummary {
+    pub fn new(nzbs: Vec<DownloadSummary>, servers: Vec<ServerStats>, elapsed: Duration) -> Self {
+        let status = nzbs
+            .iter()
+            .map(|n| n.status)
+            .max()
+            .unwrap_or(RunStatus::Aborted);
+        let reason = if nzbs.is_empty() {
+            Some("no NZB files were processed".to_string())
+        } else {
+            let unsuccessful: Vec<String> = RunStatus::FAILURES
+                .iter()
+                .rev()
+                .map(|&status| (status, nzbs.iter().filter(|n| n.status == status).count()))
+                .filter(|&(_, count)| count > 0)
+                .map(|(status, count)| format!("{} {}", count, status.describe()))
+                .collect();
+            let failed = nzbs.iter().filter(|n| !n.status.succeeded()).count();
+            (failed > 0).then(|| {
+                format!(
+                    "{} of {} NZBs: {}",
+                    failed,
+                    nzbs.len(),
+                    unsuccessful.join(", ")
+                )
+            })
+        };
+        let total_size = nzbs.iter().map(|n| n.total_size).sum();
+        let transferred = nzbs.iter().map(|n| n.transferred).sum();
+        let mut completion = CompletionStats::default();
+        for nzb in &nzbs {
+            completion.merge(&nzb.completion);
+        }
+
+        Self {
+            version: RUN_SCHEMA_VERSION,
+            status,
+            exit_code: status.exit_code(),
+            reason,
+            total_size,
+            transferred,
+            download_time_seconds: elapsed.as_secs_f64(),
+            average_speed_mbps: mbps(total_size, elapsed),
+            nzbs,
+            servers,
+            completion,
+            settings: None,
+        }
+    }
+
+    /// Count the NZBs a shutdown kept from starting, which stay queued, and
+    /// call the run cancelled if there were any
+    pub fn with_not_started(mut self, left: usize) -> Self {
+        if left > 0 {
+            self.status = self.status.max(RunStatus::Cancelled);
+            self.exit_code = self.status.exit_code();
+            let note = format!(
+                "{} NZB{} not started",
+                left,
+                if left == 1 { "" } else { "s" }
+            );
+            self.reason = Some(match self.reason {
+                Some(reason) => format!("{}; {}", reason, note),
+                None => note,
+            });
+        }
+        self
+    }
+
+    /// Record the settings the run used
+    pub fn with_settings(mut self, settings: &EffectiveConfig) -> Self {
+        self.settings = Some(settings.clone());
+        self
+    }
+}
+
+/// Average speed in MiB/s (0 for runs shorter than a second)
+pub fn mbps(bytes: u64, elapsed: Duration) -> f64 {
+    if elapsed.as_secs() > 0 {
+        (bytes as f64 / 1024.0 / 1024.0) / elapsed.as_secs_f64()
+    } else {
+        0.0
+    }
+}
+
+/// JSON output for one NZB of a download run
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DownloadSummary {
+    /// The NZB, with any `{{password}}` in its file name masked when written
+    #[serde(serialize_with = "serialize_nzb_path")]
     pub nzb: PathBuf,
-    pub output_dir: PathBuf,
+    pub status: RunStatus,
+    /// Error that stopped this NZB, for `failed` entries
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_dir: Option<PathBuf>,
     pub success: bool,
     pub total_size: u64,
+    /// Encoded bytes read from servers, including articles fetched twice,
+    /// damaged or cut short; compare with `total_size` for the overhead
+    #[serde(default)]
+    pub transferred: u64,
     pub download_time_seconds: f64,
     pub average_speed_mbps: f64,
+    /// Decoded bytes each server provided, for block accounts billed per byte
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub bytes_by_server: BTreeMap<String, u64>,
     pub files: Vec<DownloadFileResult>,
     pub post_processing: PostProcessingResult,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub script: Option<ScriptOutcome>,
+    /// Everything that went wrong, for the end-of-run report
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failures: Vec<Failure>,
+    /// Whether PAR2 repaired what failed to download
+    #[serde(default)]
+    pub salvaged: bool,
+    /// Likely reason articles were missing or damaged
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure_cause: Option<FailureCause>,
+    /// Min, mean, max and 95th percentile download speed in bytes per second
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speed: Option<SpeedStats>,
+    /// Download speed over time, at most a few hundred points
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub speed_timeline: Vec<SpeedSample>,
+    /// History entry that already downloaded this post, for `duplicate` entries
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duplicate_of: Option<u64>,
+    /// Files still missing segments after PAR2, and whether they were
+    /// deleted or kept
+    #[serde(default, skip_serializing_if = "PartialFiles::is_empty")]
+    pub partial_files: PartialFiles,
+    /// What failed the NZB under `--strict`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub strict_violations: Vec<Violation>,
+    /// Where finished files were linked or copied (`post_processing.link_dir`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub linked_files: Vec<PathBuf>,
+    /// `download.max_retry_time` or `max_retry_bytes` ran out, so retries
+    /// stopped and the remaining failures are final
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub retry_budget_exhausted: bool,
+    /// Newsgroups the files are posted to that no server carries
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub missing_groups: Vec<String>,
+    /// The release's NFO, when the output folder holds exactly one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nfo: Option<PathBuf>,
+    /// Download and post-processing steps that ran, in order, with their
+    /// times and outcomes
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub phases: Vec<PhaseRecord>,
+    /// Segments fetched per newsgroup and per poster
+    #[serde(default, skip_serializing_if = "CompletionStats::is_empty")]
+    pub completion: CompletionStats,
+    /// The NZB's file name carried an archive password (`{{password}}`);
+    /// the password itself is never written
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub filename_password: bool,
+}
+
+impl DownloadSummary {
+    /// Summary of a finished download
+    pub fn new(
+        nzb: &Path,
+        output_dir: &Path,
+        status: RunStatus,
+        results: &[DownloadResult],
+        download_time: Duration,
+        post_processing: PostProcessingResult,
+        script: Option<ScriptOutcome>,
+    ) -> Self {
+        let total_size = results.iter().map(|r| r.size).sum();
+        let failures = Failure::of_download(results, &post_processing, script.as_ref());
+        let damaged = results
+            .iter()
+            .any(|r| r.segments_failed > 0 || r.segments_corrupt > 0);
+        let mut bytes_by_server = BTreeMap::new();
+        for (server, bytes) in results.iter().flat_map(|r| &r.bytes_by_server) {
+            *bytes_by_server.entry(server.clone()).or_default() += bytes;
+        }
+        Self {
+            nzb: nzb.to_path_buf(),
+            status,
+            error: None,
+            output_dir: Some(output_dir.to_path_buf()),
+            success: status.succeeded(),
+            total_size,
+            transferred: results.iter().map(|r| r.transferred).sum(),
+            download_time_seconds: download_time.as_secs_f64(),
+            average_speed_mbps: mbps(total_size, download_time),
+            bytes_by_server,
+            files: results.iter().map(DownloadFileResult::of).collect(),
+            salvaged: damaged && post_processing.par2_repaired,
+            failure_cause: FailureCause::classify(results),
+            post_processing,
+            script,
+            failures,
+            speed: None,
+            speed_timeline: Vec::new(),
+            duplicate_of: None,
+            partial_files: PartialFiles::default(),
+            strict_violations: Vec::new(),
+            linked_files: Vec::new(),
+            retry_budget_exhausted: false,
+            missing_groups: Vec::new(),
+            nfo: None,
+            phases: Vec::new(),
+            completion: CompletionStats::of_download(results),
+            filename_password: false,
+        }
+    }
+
+    /// Add the speed samples taken while the files downloaded
+    pub fn with_speed(mut self, timeline: &SpeedTimeline) -> Self {
+        self.speed = timeline.stats();
+        self.speed_timeline = timeline.samples().to_vec();
+        self
+    }
+
+    /// Record what was done with the files left incomplete
+    pub fn with_partial_files(mut self, partial_files: PartialFiles) -> Self {
+        self.partial_files = partial_files;
+        self
+    }
+
+    /// Record where finished files were put in the library
+    pub fn with_linked_files(mut self, linked_files: Vec<PathBuf>) -> Self {
+        self.linked_files = linked_files;
+        self
+    }
+
+    /// List the files left out before downloading alongside the rest
+    pub fn with_skipped(mut self, skipped: &[SkippedFile]) -> Self {
+        let output_dir = self.output_dir.clone().unwrap_or_default();
+        self.files.extend(
+            skipped
+                .iter()
+                .map(|file| DownloadFileResult::not_downloaded(&output_dir, file)),
+        );
+        self
+    }
+
+    /// Files skipped for each reason, already complete ones included
+    pub fn skip_reasons(&self) -> impl Iterator<Item = SkipReason> + '_ {
+        self.files.iter().filter_map(|file| file.skip_reason)
+    }
+
+    pub fn with_retry_budget_exhausted(mut self, exhausted: bool) -> Self {
+        self.retry_budget_exhausted = exhausted;
+        self
+    }
+
+    /// Record the newsgroups no server carried
+    pub fn with_missing_groups(mut self, groups: Vec<String>) -> Self {
+        self.missing_groups = groups;
+        self
+    }
+
+    /// Record whether the NZB's file name carried an archive password
+    pub fn with_filename_password(mut self, present: bool) -> Self {
+        self.filename_password = present;
+        self
+    }
+
+    /// Record the NFO found in the output folder
+    pub fn with_nfo(mut self, nfo: Option<PathBuf>) -> Self {
+        self.nfo = nfo;
+        self
+    }
+
+    /// Add the phases the NZB went through
+    pub fn with_phases(mut self, phases: Vec<PhaseRecord>) -> Self {
+        self.phases = phases;
+        self
+    }
+
+    /// Fail the NZB for `violations` found under `--strict`
+    ///
+    /// The reasons become its `error`, so history records why.
+    pub fn with_violations(mut self, violations: Vec<Violation>) -> Self {
+        if !violations.is_empty() {
+            self.status = self.status.max(strict::status(&violations));
+            self.success = self.status.succeeded();
+            let reasons: Vec<String> = violations.iter().map(Violation::describe).collect();
+            self.error = Some(format!("strict: {}", reasons.join("; ")));
+        }
+        self.strict_violations = violations;
+        self
+    }
+
+    /// Add failures found outside the finished files, e.g. files that
+    /// couldn't be downloaded at all
+    pub fn with_failures(mut self, failures: impl IntoIterator<Item = Failure>) -> Self {
+        self.failures.extend(failures);
+        self
+    }
+
+    /// Summary of an NZB that could not be loaded or downloaded
+    pub fn failed(nzb: &Path, output_dir: Option<&Path>, error: &DlNzbError) -> Self {
+        Self {
+            nzb: nzb.to_path_buf(),
+            status: error.run_status(),
+            error: Some(error.to_string()),
+            output_dir: output_dir.map(Path::to_path_buf),
+            success: false,
+            total_size: 0,
+            transferred: 0,
+            download_time_seconds: 0.0,
+            average_speed_mbps: 0.0,
+            bytes_by_server: BTreeMap::new(),
+            files: Vec::new(),
+            post_processing: PostProcessingResult::default(),
+            script: None,
+            failures: vec![Failure::from_error(None, error)],
+            salvaged: false,
+            failure_cause: None,
+            speed: None,
+            speed_timeline: Vec::new(),
+            duplicate_of: None,
+            partial_files: PartialFiles::default(),
+            strict_violations: Vec::new(),
+            linked_files: Vec::new(),
+            retry_budget_exhausted: false,
+            missing_groups: Vec::new(),
+            nfo: None,
+            phases: Vec::new(),
+            completion: CompletionStats::default(),
+            filename_password: false,
+        }
+    }
+
+    /// Add what a download that stopped early got done: the files it
+    /// finished or partly wrote, and those left out before it started
+    ///
+    /// The status and error stay those of the failure.
+    pub fn with_partial_download(mut self, download: &NzbDownload) -> Self {
+        let results = &download.results;
+        self.total_size = download.total_size();
+        self.transferred = download.total_transferred();
+        self.download_time_seconds = download.download_time.as_secs_f64();
+        self.average_speed_mbps = mbps(self.total_size, download.download_time);
+        self.bytes_by_server = download.bytes_by_server();
+        self.files = results.iter().map(DownloadFileResult::of).collect();
+        self.failure_cause = FailureCause::classify(results);
+        self.completion = CompletionStats::of_download(results);
+        self.failures
+            .extend(Failure::of_download(results, &self.post_processing, None));
+        self.failures.extend(download.failures.iter().cloned());
+        self.retry_budget_exhausted = download.retry_budget.is_exhausted();
+        self.missing_groups = download.missing_groups.clone();
+        self.with_speed(&download.speed)
+            .with_skipped(&download.skipped)
+    }
+
+    /// Summary of an NZB skipped because `previous` already downloaded it
+    pub fn duplicate(nzb: &Path, previous: &HistoryEntry) -> Self {
+        Self {
+            nzb: nzb.to_path_buf(),
+            status: RunStatus::Duplicate,
+            error: None,
+            output_dir: previous.output_dir.clone(),
+            success: true,
+            total_size: previous.total_size,
+            transferred: 0,
+            download_time_seconds: 0.0,
+            average_speed_mbps: 0.0,
+            bytes_by_server: BTreeMap::new(),
+            files: previous.files.clone(),
+            post_processing: PostProcessingResult::default(),
+            script: None,
+            failures: Vec::new(),
+            salvaged: false,
+            failure_cause: None,
+            speed: None,
+            speed_timeline: Vec::new(),
+            duplicate_of: Some(previous.id),
+            partial_files: PartialFiles::default(),
+            strict_violations: Vec::new(),
+            linked_files: Vec::new(),
+            retry_budget_exhausted: false,
+            missing_groups: Vec::new(),
+            nfo: None,
+            phases: Vec::new(),
+            completion: CompletionStats::default(),
+            filename_password: false,
+        }
+    }
+}
+
+/// Version of `--json-progress` event lines; bumped on incompatible changes
+pub const EVENT_SCHEMA_VERSION: u32 = 1;
+
+/// One `--json-progress` line
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EventLine {
+    /// Schema version ([`EVENT_SCHEMA_VERSION`])
+    pub version: u32,
+    /// Seconds since the run started
+    pub elapsed_seconds: f64,
+    #[serde(flatten)]
+    pub event: ProgressEvent,
 }
 
+/// `--json-progress` event, tagged by its `event` field
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProgressEvent {
+    /// An NZB's files were queued
+    QueueStart {
+        nzb: Option<PathBuf>,
+        files: usize,
+        total_bytes: u64,
+    },
+    FileStart {
+        filename: String,
+        size: u64,
+        segments: usize,
+    },
+    /// Aggregate progress of the current NZB, sent every progress interval
+    Progress(ProgressSnapshot),
+    FileComplete {
+        filename: String,
+        size: u64,
+        segments_failed: usize,
+        skipped: bool,
+    },
+    PostProcess {
+        phase: PostProcessPhase,
+    },
+    /// Always the last line
+    Summary {
+        summary: RunSummary,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProgressSnapshot {
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    /// MiB/s transferred over the last `logging.speed_window` seconds
+    pub speed_mbps: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub eta_seconds: Option<f64>,
+    pub files_done: usize,
+    pub files_total: usize,
+}
+
+/// Turns [`DownloadEvent`]s into `--json-progress` events
+///
+/// Byte progress is only accumulated; [`ProgressTracker::snapshot`] reports
+/// it at whatever interval the caller chooses.
+pub struct ProgressTracker {
+    run_start: Instant,
+    nzb: Option<PathBuf>,
+    bytes_done: u64,
+    bytes_total: u64,
+    /// Bytes read from the server, which the speed is computed from
+    transferred: u64,
+    rate: RateWindow,
+    files_done: usize,
+    files_total: usize,
+    changed: bool,
+}
+
+impl Default for ProgressTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProgressTracker {
+    pub fn new() -> Self {
+        Self {
+            run_start: Instant::now(),
+            nzb: None,
+            bytes_done: 0,
+            bytes_total: 0,
+            transferred: 0,
+            rate: RateWindow::new(progress::speed_window()),
+            files_done: 0,
+            files_total: 0,
+            changed: false,
+        }
+    }
+
+    /// Event to emit right away for a download event, if any
+    pub fn record(&mut self, event: DownloadEvent) -> Option<ProgressEvent> {
+        match event {
+            DownloadEvent::NzbStarted { nzb } => {
+                self.nzb = Some(nzb);
+                None
+            }
+            DownloadEvent::QueueStarted { files, total_bytes } => {
+                self.bytes_done = 0;
+                self.bytes_total = total_bytes;
+                self.transferred = 0;
+                self.rate = RateWindow::new(progress::speed_window());
+                self.rate.record(0, Instant::now());
+                self.files_done = 0;
+                self.files_total = files;
+                self.changed = false;
+                Some(ProgressEvent::QueueStart {
+                    nzb: self.nzb.clone(),
+                    files,
+                    total_bytes,
+                })
+            }
+            DownloadEvent::FileStarted {
+                filename,
+                size,
+                segments,
+            } => Some(ProgressEvent::FileStart {
+                filename,
+                size,
+                segments,
+            }),
+            DownloadEvent::Progress { bytes, transferred } => {
+                self.bytes_done += bytes;
+                self.transferred += transferred;
+                self.rate.record(self.transferred, Instant::now());
+                self.changed = true;
+                None
+            }
+            DownloadEvent::FileCompleted {
+                filename,
+                size,
+                segments_failed,
+                skipped,
+            } => {
+                self.files_done += 1;
+                self.changed = true;
+                Some(ProgressEvent::FileComplete {
+                    filename,
+                    size,
+                    segments_failed,
+                    skipped,
+                })
+            }
+            DownloadEvent::PostProcessing(phase) => Some(ProgressEvent::PostProcess { phase }),
+        }
+    }
+
+    /// Aggregate progress, if anything changed since the last snapshot
+    pub fn snapshot(&mut self) -> Option<ProgressEvent> {
+        if !std::mem::take(&mut self.changed) {
+            return None;
+        }
+
+        let now = Instant::now();
+        let bytes_per_sec = self.rate.rate(now);
+        let remaining = self.bytes_total.saturating_sub(self.bytes_done);
+        let eta_seconds = self.rate.eta(remaining, now).map(|eta| eta.as_secs_f64());
+
+        Some(ProgressEvent::Progress(ProgressSnapshot {
+            bytes_done: self.bytes_done,
+            bytes_total: self.bytes_total,
+            speed_mbps: bytes_per_sec / 1024.0 / 1024.0,
+            eta_seconds,
+            files_done: self.files_done,
+            files_total: self.files_total,
+        }))
+    }
+
+    /// Wrap an event with the schema version and run time
+    pub fn line(&self, event: ProgressEvent) -> EventLine {
+        EventLine {
+            version: EVENT_SCHEMA_VERSION,
+            elapsed_seconds: self.run_start.elapsed().as_secs_f64(),
+            event,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DownloadFileResult {
     pub filename: String,
     pub path: PathBuf,
     pub size: u64,
     pub segments_downloaded: usize,
     pub segments_failed: usize,
+    /// Failed segments no server had
+    #[serde(default)]
+    pub segments_missing: usize,
+    /// Segments that failed their yEnc checksum
+    #[serde(default)]
+    pub segments_corrupt: usize,
     pub success: bool,
+    /// This file's own time; files download concurrently, so these overlap
+    #[serde(default)]
+    pub download_time_seconds: f64,
+    #[serde(default)]
+    pub average_speed_mbps: f64,
+    /// Decoded bytes each server provided
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub bytes_by_server: BTreeMap<String, u64>,
+    /// How the file already there was checked, when it was skipped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skipped: Option<SkipVerification>,
+    /// Why the file wasn't downloaded, when it wasn't
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skip_reason: Option<SkipReason>,
+    /// What became of a file already there at another size, or kept
+    /// incomplete by an earlier run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub existing: Option<ExistingFile>,
+    /// The name came from `--name-map`, not the subject
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub name_mapped: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct PostProcessingResult {
-    pub par2_verified: bool,
-    pub par2_repaired: bool,
-    pub rar_extracted: bool,
-    pub files_renamed: usize,
+impl DownloadFileResult {
+    /// Entry for a file that was downloaded, or skipped as already there
+    pub fn of(r: &DownloadResult) -> Self {
+        Self {
+            filename: r.filename.clone(),
+            path: r.path.clone(),
+            size: r.size,
+            segments_downloaded: r.segments_downloaded,
+            segments_failed: r.segments_failed,
+            segments_missing: r.segments_missing,
+            segments_corrupt: r.segments_corrupt,
+            success: r.segments_failed == 0,
+            download_time_seconds: r.download_time.as_secs_f64(),
+            average_speed_mbps: r.average_speed,
+            bytes_by_server: r.bytes_by_server.clone(),
+            skipped: r.skipped,
+            skip_reason: r.skip_reason(),
+            existing: r.existing.clone(),
+            name_mapped: r.name_mapped,
+        }
+    }
+
+    /// Entry for a file left out before downloading; `size` is what the NZB
+    /// declares for it
+    pub fn not_downloaded(output_dir: &Path, file: &SkippedFile) -> Self {
+        Self {
+            filename: file.filename.clone(),
+            path: output_dir.join(&file.filename),
+            size: file.size,
+            segments_downloaded: 0,
+            segments_failed: 0,
+            segments_missing: 0,
+            segments_corrupt: 0,
+            success: true,
+            download_time_seconds: 0.0,
+            average_speed_mbps: 0.0,
+            bytes_by_server: BTreeMap::new(),
+            skipped: None,
+            skip_reason: Some(file.reason),
+            existing: None,
+            name_mapped: false,
+        }
+    }
 }
 
 /// JSON output for test command
@@ -56,10 +921,19 @@ pub struct TestResult {
     pub server: String,
     pub port: u16,
     pub ssl: bool,
+    /// Where the password came from (config file, password_cmd, OS keyring, command line)
+    pub password_source: String,
     pub connected: bool,
     pub authenticated: bool,
     pub healthy: bool,
+    /// Seconds the local clock is ahead of the server's (behind when
+    /// negative), when the server answers `DATE`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clock_skew: Option<i64>,
     pub error: Option<String>,
+    /// `test --speed` results
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speed: Option<SpeedReport>,
 }
 
 /// JSON output for config command
@@ -76,6 +950,9 @@ pub struct ConfigInfo {
 pub struct ErrorOutput {
     pub error: String,
     pub details: Option<String>,
+    /// Input file the error belongs to, when it affects only that file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>,
 }
 
 impl ErrorOutput {
@@ -83,6 +960,204 @@ impl ErrorOutput {
         Self {
             error: e.to_string(),
             details: e.source().map(|s| s.to_string()),
+            file: None,
+        }
+    }
+
+    /// Attribute the error to one input file
+    pub fn for_file(mut self, file: &Path) -> Self {
+        self.file = Some(file.to_path_buf());
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(status: RunStatus) -> DownloadSummary {
+        let error = crate::error::NzbError::EmptyNzb(None).into();
+        let mut summary = DownloadSummary::failed(Path::new("a.nzb"), None, &error);
+        summary.status = status;
+        summary
+    }
+
+    #[test]
+    fn test_filename_password_is_not_written() {
+        let error = crate::error::NzbError::EmptyNzb(None).into();
+        let named = DownloadSummary::failed(Path::new("/nzbs/Show{{s3cret}}.nzb"), None, &error)
+            .with_filename_password(true);
+        let json = serde_json::to_value(&named).unwrap();
+        assert_eq!(json["nzb"], "/nzbs/Show{{********}}.nzb");
+        assert_eq!(json["filename_password"], true);
+        assert!(!json.to_string().contains("s3cret"));
+
+        let json = serde_json::to_value(summary(RunStatus::Success)).unwrap();
+        assert_eq!(json["nzb"], "a.nzb");
+        assert!(json.get("filename_password").is_none());
+    }
+
+    #[test]
+    fn test_failed_summary_keeps_partial_download() {
+        let result = DownloadResult {
+            filename: "a.bin".to_string(),
+            path: PathBuf::from("/out/a.bin"),
+            size: 400,
+            segments_downloaded: 4,
+            segments_failed: 2,
+            download_time: Duration::from_secs(1),
+            failed_message_ids: vec!["5@test".to_string(), "6@test".to_string()],
+            group: "alt.test".to_string(),
+            groups: vec!["alt.test".to_string()],
+            transferred: 420,
+            ..Default::default()
+        };
+        let download = NzbDownload {
+            results: vec![result],
+            download_time: Duration::from_secs(2),
+            skipped: vec![SkippedFile {
+                filename: "b.bin".to_string(),
+                size: 100,
+                reason: SkipReason::GroupUnavailable,
+            }],
+            ..NzbDownload::default()
+        };
+        let error = crate::error::DownloadError::Cancelled.into();
+        let summary = DownloadSummary::failed(Path::new("a.nzb"), Some(Path::new("/out")), &error)
+            .with_partial_download(&download);
+
+        assert_eq!(summary.status, RunStatus::Cancelled);
+        assert!(!summary.success);
+        assert_eq!(summary.error, Some(error.to_string()));
+        assert_eq!((summary.total_size, summary.transferred), (400, 420));
+        let files: Vec<_> = summary.files.iter().map(|f| f.filename.as_str()).collect();
+        assert_eq!(files, ["a.bin", "b.bin"]);
+        assert!(!summary.files[0].success);
+        // The error that stopped it comes first, then what the files show
+        assert_eq!(summary.failures.len(), 2);
+        assert_eq!(summary.failures[0].message, error.to_string());
+        assert_eq!(summary.failures[1].file.as_deref(), Some("a.bin"));
+    }
+
+    #[test]
+    fn test_failed_extraction_fails_post_processing() {
+        let post_processing = PostProcessingResult {
+            extract_failed: vec![PathBuf::from("a.rar")],
+            ..Default::default()
+        };
+        assert_eq!(
+            RunStatus::of_download(&[], 0, &post_processing, None),
+            RunStatus::PostProcessingFailed
+        );
+    }
+
+    #[test]
+    fn test_failed_par2_repair_fails_post_processing() {
+        let post_processing = PostProcessingResult {
+            par2_error: Some("not enough recovery blocks".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            RunStatus::of_download(&[], 0, &post_processing, None),
+            RunStatus::PostProcessingFailed
+        );
+        assert_eq!(
+            RunStatus::of_download(&[], 0, &PostProcessingResult::default(), None),
+            RunStatus::Success
+        );
+    }
+
+    #[test]
+    fn test_run_status_is_worst_nzb() {
+        let run = RunSummary::new(
+            vec![
+                summary(RunStatus::Success),
+                summary(RunStatus::PostProcessingFailed),
+                summary(RunStatus::Incomplete),
+            ],
+            Vec::new(),
+            Duration::from_secs(1),
+        );
+        assert_eq!(run.status, RunStatus::Incomplete);
+        assert_eq!(run.exit_code, 1);
+        assert_eq!(
+            run.reason.as_deref(),
+            Some("2 of 3 NZBs: 1 incomplete, 1 post-processing failed")
+        );
+
+        // Skipping an NZB that was already downloaded isn't a failure
+        let run = RunSummary::new(
+            vec![summary(RunStatus::Success), summary(RunStatus::Duplicate)],
+            Vec::new(),
+            Duration::ZERO,
+        );
+        assert_eq!(run.exit_code, 0);
+        assert!(run.reason.is_none());
+        assert_eq!(
+            serde_json::to_value(run.status).unwrap(),
+            serde_json::json!("duplicate")
+        );
+    }
+
+    #[test]
+    fn test_progress_tracker() {
+        let mut tracker = ProgressTracker::new();
+        assert!(tracker
+            .record(DownloadEvent::NzbStarted {
+                nzb: PathBuf::from("a.nzb"),
+            })
+            .is_none());
+        let queued = tracker.record(DownloadEvent::QueueStarted {
+            files: 2,
+            total_bytes: 300,
+        });
+        assert!(matches!(
+            queued,
+            Some(ProgressEvent::QueueStart {
+                nzb: Some(_),
+                files: 2,
+                ..
+            })
+        ));
+        assert!(tracker.snapshot().is_none());
+
+        assert!(tracker
+            .record(DownloadEvent::Progress {
+                bytes: 100,
+                transferred: 102,
+            })
+            .is_none());
+        tracker.record(DownloadEvent::FileCompleted {
+            filename: "a.rar".to_string(),
+            size: 100,
+            segments_failed: 0,
+            skipped: false,
+        });
+        match tracker.snapshot() {
+            Some(ProgressEvent::Progress(snapshot)) => {
+                assert_eq!(snapshot.bytes_done, 100);
+                assert_eq!(snapshot.bytes_total, 300);
+                assert_eq!((snapshot.files_done, snapshot.files_total), (1, 2));
+            }
+            other => panic!("expected progress, got {:?}", other),
         }
+        // Nothing new since the last snapshot
+        assert!(tracker.snapshot().is_none());
+
+        let line = tracker.line(ProgressEvent::PostProcess {
+            phase: PostProcessPhase::Par2,
+        });
+        let json = serde_json::to_value(&line).unwrap();
+        assert_eq!(json["version"], EVENT_SCHEMA_VERSION);
+        assert_eq!(json["event"], "post_process");
+        assert_eq!(json["phase"], "par2");
+    }
+
+    #[test]
+    fn test_par2_role() {
+        let role = |name: &str| Par2Role::from(Par2Kind::of(name));
+        assert_eq!(role("Movie.par2"), Par2Role::Index);
+        assert_eq!(role("Movie.vol03+04.PAR2"), Par2Role::Volume);
+        assert_eq!(role("Movie.part01.rar"), Par2Role::None);
     }
 }
@@ -11,8 +11,48 @@
 //! - PAR2 verification and repair
 //! - RAR extraction
 //!
+//! The library itself prints nothing: downloads and post-processing report
+//! through a [`progress::ProgressSink`], which is silent unless one is given.
+//!
+//! # Cargo features
+//!
+//! | Feature                 | Default | Enables                                                        |
+//! |-------------------------|---------|----------------------------------------------------------------|
+//! | `cli`                   | yes     | The `dl-nzb` binary, `cli`, `listing`, `picker`, terminal bars |
+//! | `builtin-par2`          | yes     | In-process PAR2 verify/repair, else a `par2` binary on PATH    |
+//! | `keyring`               | no      | Reading the Usenet password from the OS keyring                |
+//! | `desktop-notifications` | no      | Desktop notifications when a run finishes                      |
+//! | `metrics`               | no      | The Prometheus endpoint in [`metrics`]                         |
+//! | `server`                | no      | The JSON API of `watch` in [`api`]                             |
+//! | `vendored-openssl`      | no      | Building OpenSSL from source, for cross-compiling              |
+//!
+//! [`capabilities()`] tells at runtime which of these a build has, along with
+//! its version and JSON schema versions.
+//!
+//! `cli` brings in clap, indicatif, inquire and tracing-subscriber; library
+//! users who don't need the binary can turn it off:
+//!
+//! ```toml
+//! dl-nzb = { version = "0.2", default-features = false }
+//! ```
+//!
 //! # Example
 //!
+//! [`download()`] takes an NZB from a path or URL to finished files, with the
+//! settings from the usual config file:
+//!
+//! ```no_run
+//! #[tokio::main]
+//! async fn main() -> dl_nzb::Result<()> {
+//!     let outcome = dl_nzb::download("some.release.nzb", Default::default()).await?;
+//!     println!("Downloaded to {}", outcome.output_dir.display());
+//!     Ok(())
+//! }
+//! ```
+//!
+//! For more control (a shared pool, several NZBs, your own progress sink),
+//! build a [`Downloader`] and [`PostProcessor`] yourself:
+//!
 //! ```no_run
 //! use dl_nzb::{config::Config, nntp::NntpPoolBuilder};
 //!
@@ -24,14 +64,77 @@
 //!     Ok(())
 //! }
 //! ```
+//!
+//! Articles can also be fetched directly, without an NZB, for example to
+//! check that a post is still complete or to grab just the first RAR volume's
+//! headers. Each result carries its own error, so a missing article can be
+//! told apart from a corrupt one:
+//!
+//! ```no_run
+//! use dl_nzb::error::{DlNzbError, NntpError};
+//! use dl_nzb::nntp::{NntpPoolBuilder, NntpPoolExt, SegmentRequest};
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     let config = dl_nzb::Config::load()?;
+//!     let pool = NntpPoolBuilder::new(config.usenet.clone())
+//!         .max_size(1)
+//!         .build()?;
+//!
+//!     let request = SegmentRequest {
+//!         message_id: "part1of42.abc123@example.com".to_string(),
+//!         group: "alt.binaries.test".to_string(),
+//!         segment_number: 1,
+//!     };
+//!     for (request, result) in pool.download_segments(&[request]).await {
+//!         match result {
+//!             Ok(data) => println!("{}: {} bytes", request.message_id, data.len()),
+//!             Err(DlNzbError::Nntp(NntpError::ArticleNotFound { .. })) => {
+//!                 println!("{}: missing", request.message_id)
+//!             }
+//!             Err(e) => println!("{}: {}", request.message_id, e),
+//!         }
+//!     }
+//!     Ok(())
+//! }
+//! ```
 
 // Core modules
+pub mod api;
+pub mod capabilities;
+pub mod checksum;
+#[cfg(feature = "cli")]
 pub mod cli;
+pub mod completion;
 pub mod config;
+pub mod credentials;
+pub mod doctor;
 pub mod error;
+pub mod history;
 pub mod json_output;
+#[cfg(feature = "cli")]
+pub mod listing;
+pub mod log_file;
+pub mod metrics;
+pub mod nfo;
+pub mod notify;
+pub mod oneshot;
 pub mod patterns;
+pub mod phases;
+#[cfg(feature = "cli")]
+pub mod picker;
+pub mod plan;
+pub mod preview;
 pub mod progress;
+pub mod queue;
+pub mod report;
+pub mod retry;
+pub mod shutdown;
+pub mod speedtest;
+pub mod state;
+pub mod strict;
+pub mod verify;
+pub mod watch;
 
 // Feature modules organized by functionality
 pub mod download;
@@ -39,10 +142,12 @@ pub mod nntp;
 pub mod processing;
 
 // Re-export commonly used types
+pub use capabilities::{capabilities, Capabilities};
 pub use config::Config;
 pub use download::{DownloadResult, Downloader, Nzb};
 pub use error::{DlNzbError, Result};
 pub use nntp::{NntpPool, NntpPoolBuilder, NntpPoolExt};
+pub use oneshot::{download, DownloadOptions, NzbOutcome};
 pub use processing::PostProcessor;
 
 // Re-export serde_json for binary
@@ -0,0 +1,281 @@
+//! The file table `-l -v` prints
+//!
+//! One row per file with its kind, size, segment count, post age, poster,
+//! newsgroups, after `--check-availability` how much of it the sample found
+//! on the servers, and whether `download.min_file_size` would skip it. The table is fitted to the terminal: long names are
+//! shortened first, and when even that leaves too little room the groups and
+//! then the poster columns are dropped. `--json -l` always carries all of it.
+
+use human_bytes::human_bytes;
+use std::collections::BTreeMap;
+use std::io::IsTerminal;
+use std::time::SystemTime;
+
+use crate::download::{below_min_size, format_age, NzbFile};
+use crate::plan::FileSample;
+use crate::progress::fit_name;
+
+/// Longest the poster and groups columns get
+const POSTER_WIDTH: usize = 24;
+const GROUPS_WIDTH: usize = 28;
+
+/// Names aren't squeezed narrower than this to make room for other columns
+const MIN_NAME_WIDTH: usize = 24;
+
+/// Space between columns
+const GAP: &str = "  ";
+
+/// Columns the table may use: the terminal's width, else `$COLUMNS`, else
+/// no limit (output piped to a file keeps every column and whole names)
+pub fn terminal_width() -> usize {
+    if std::io::stdout().is_terminal() {
+        if let Some(width) = indicatif::ProgressDrawTarget::stdout().width() {
+            return width as usize;
+        }
+    }
+    std::env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .unwrap_or(usize::MAX)
+}
+
+/// The table's lines, header first, for `files` as of `now`
+///
+/// `samples` holds the availability sample by file index; without one the
+/// completeness column is left out. The skip column only shows when a file
+/// is under `min_size` bytes.
+pub fn file_table(
+    files: &[&NzbFile],
+    samples: &BTreeMap<usize, FileSample>,
+    min_size: u64,
+    width: usize,
+    now: SystemTime,
+) -> Vec<String> {
+    let mut columns = vec![
+        Column::right("#", files, |f| f.index.to_string()),
+        Column::left("Kind", files, |f| f.kind_label()),
+        Column::right("Size", files, |f| {
+            human_bytes(f.segments.segment.iter().map(|s| s.bytes).sum::<u64>() as f64)
+        }),
+        Column::right("Segs", files, |f| f.segments.segment.len().to_string()),
+        Column::right("Age", files, |f| {
+            f.age_days_at(now)
+                .map(format_age)
+                .unwrap_or_else(|| "-".to_string())
+        }),
+    ];
+    if !samples.is_empty() {
+        columns.push(Column::right("Avail", files, |f| {
+            samples
+                .get(&f.index)
+                .and_then(FileSample::completeness)
+                .map(|percent| format!("{:.0}%", percent))
+                .unwrap_or_else(|| "-".to_string())
+        }));
+    }
+    if files.iter().any(|f| below_min_size(f, min_size)) {
+        columns.push(Column::left("Skip", files, |f| {
+            if below_min_size(f, min_size) {
+                "too small".to_string()
+            } else {
+                String::new()
+            }
+        }));
+    }
+
+    // Dropped last to first while the name would get too little room
+    let mut optional = vec![
+        Column::left("Poster", files, |f| f.poster.clone()).capped(POSTER_WIDTH),
+        Column::left("Groups", files, |f| {
+            f.groups
+                .group
+                .iter()
+                .map(|g| short_group(&g.name))
+                .collect::<Vec<_>>()
+                .join(",")
+        })
+        .capped(GROUPS_WIDTH),
+    ];
+    let used = |columns: &[Column]| columns.iter().map(|c| c.width + GAP.len()).sum::<usize>();
+    while !optional.is_empty()
+        && width.saturating_sub(used(&columns) + used(&optional)) < MIN_NAME_WIDTH
+    {
+        optional.pop();
+    }
+    columns.extend(optional);
+    let name_width = width.saturating_sub(used(&columns)).max(MIN_NAME_WIDTH);
+
+    let line = |cells: Vec<String>, name: &str| {
+        let mut line = String::new();
+        for (column, cell) in columns.iter().zip(cells) {
+            line.push_str(&column.pad(&cell));
+            line.push_str(GAP);
+        }
+        if name.chars().count() > name_width {
+            line.push_str(&fit_name(name, name_width));
+        } else {
+            line.push_str(name);
+        }
+        line
+    };
+    let mut lines = vec![line(
+        columns.iter().map(|c| c.header.to_string()).collect(),
+        "Name",
+    )];
+    for (row, file) in files.iter().enumerate() {
+        let cells = columns.iter().map(|c| c.cells[row].clone()).collect();
+        lines.push(line(cells, &file.filename()));
+    }
+    lines
+}
+
+/// One column of the table, its cells already formatted
+struct Column {
+    header: &'static str,
+    cells: Vec<String>,
+    width: usize,
+    right: bool,
+}
+
+impl Column {
+    fn left(header: &'static str, files: &[&NzbFile], cell: impl Fn(&NzbFile) -> String) -> Self {
+        let cells: Vec<String> = files.iter().map(|f| cell(f)).collect();
+        let width = cells
+            .iter()
+            .map(|c| c.chars().count())
+            .chain([header.len()])
+            .max()
+            .unwrap_or(0);
+        Self {
+            header,
+            cells,
+            width,
+            right: false,
+        }
+    }
+
+    fn right(header: &'static str, files: &[&NzbFile], cell: impl Fn(&NzbFile) -> String) -> Self {
+        Self {
+            right: true,
+            ..Self::left(header, files, cell)
+        }
+    }
+
+    /// Cut cells longer than `max` short with `…`
+    fn capped(mut self, max: usize) -> Self {
+        for cell in &mut self.cells {
+            if cell.chars().count() > max {
+                *cell = cell.chars().take(max - 1).chain(['…']).collect();
+            }
+        }
+        self.width = self.width.min(max);
+        self
+    }
+
+    fn pad(&self, cell: &str) -> String {
+        if self.right {
+            format!("{:>width$}", cell, width = self.width)
+        } else {
+            format!("{:<width$}", cell, width = self.width)
+        }
+    }
+}
+
+/// `alt.binaries.` written `a.b.`, as Usenet clients abbreviate it
+fn short_group(name: &str) -> String {
+    match name.strip_prefix("alt.binaries.") {
+        Some(rest) => format!("a.b.{}", rest),
+        None => name.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::download::Nzb;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn nzb() -> Nzb {
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<nzb xmlns="http://www.newzbin.com/DTD/2003/nzb">
+  <file poster="Somebody With A Long Name &lt;poster@example.com&gt;" date="1700000000" subject="&quot;Some.Release.2024.1080p.WEB.part01.rar&quot; yEnc (1/2)">
+    <groups><group>alt.binaries.teevee</group><group>alt.binaries.multimedia</group></groups>
+    <segments>
+      <segment bytes="700000" number="1">a@test</segment>
+      <segment bytes="300000" number="2">b@test</segment>
+    </segments>
+  </file>
+  <file poster="p@test" date="4102444800" subject="&quot;Some.Release.2024.vol00+01.par2&quot; yEnc (1/1)">
+    <groups><group>alt.binaries.teevee</group></groups>
+    <segments><segment bytes="1000" number="1">c@test</segment></segments>
+  </file>
+</nzb>"#
+            .parse()
+            .unwrap()
+    }
+
+    fn now() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_700_000_000 + 86_400 * 3)
+    }
+
+    #[test]
+    fn test_wide_table_has_every_column() {
+        let nzb = nzb();
+        let files: Vec<&NzbFile> = nzb.files().iter().collect();
+        let samples = BTreeMap::from([(
+            1,
+            FileSample {
+                sampled: 4,
+                missing: 1,
+            },
+        )]);
+        let lines = file_table(&files, &samples, 0, usize::MAX, now());
+
+        assert_eq!(lines.len(), 3);
+        for header in ["Kind", "Segs", "Age", "Avail", "Poster", "Groups", "Name"] {
+            assert!(lines[0].contains(header), "{}", lines[0]);
+        }
+        assert!(lines[1].contains("3 days"), "{}", lines[1]);
+        assert!(lines[1].contains("75%"), "{}", lines[1]);
+        assert!(
+            lines[1].contains("a.b.teevee,a.b.multimedia"),
+            "{}",
+            lines[1]
+        );
+        assert!(lines[1].ends_with("Some.Release.2024.1080p.WEB.part01.rar"));
+        // No usable date, and not reached by the sample
+        assert!(lines[2].contains(" - "), "{}", lines[2]);
+        // Columns line up
+        let name_at = lines[0].find("Name").unwrap();
+        assert_eq!(lines[1].find("Some.Release").unwrap(), name_at);
+        assert!(!lines[0].contains("Skip"), "{}", lines[0]);
+    }
+
+    #[test]
+    fn test_files_under_the_minimum_size_are_marked() {
+        let nzb = nzb();
+        let files: Vec<&NzbFile> = nzb.files().iter().collect();
+        let lines = file_table(&files, &BTreeMap::new(), 2_000_000, usize::MAX, now());
+        assert!(lines[0].contains("Skip"), "{}", lines[0]);
+        assert!(lines[1].contains("too small"), "{}", lines[1]);
+        // PAR2 files are kept whatever their size
+        assert!(!lines[2].contains("too small"), "{}", lines[2]);
+    }
+
+    #[test]
+    fn test_narrow_table_drops_columns_then_shortens_names() {
+        let nzb = nzb();
+        let files: Vec<&NzbFile> = nzb.files().iter().collect();
+        let lines = file_table(&files, &BTreeMap::new(), 0, 100, now());
+        assert!(lines[0].contains("Poster"), "{}", lines[0]);
+        assert!(!lines[0].contains("Groups"), "{}", lines[0]);
+        assert!(!lines[0].contains("Avail"));
+        assert!(lines.iter().all(|line| line.chars().count() <= 100));
+
+        let lines = file_table(&files, &BTreeMap::new(), 0, 72, now());
+        assert!(!lines[0].contains("Poster"), "{}", lines[0]);
+        assert!(lines.iter().all(|line| line.chars().count() <= 72));
+        // The extension survives shortening
+        assert!(lines[1].ends_with(".part01.rar"), "{}", lines[1]);
+    }
+}
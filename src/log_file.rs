@@ -0,0 +1,134 @@
+//! Size-rotated log file for `--log-file` / `logging.file`
+//!
+//! Once the file would grow past its limit it is renamed to `<file>.1`, older
+//! copies shift up to `<file>.2` … `<file>.N`, the oldest is deleted, and a
+//! fresh file is started. Tracing hands over each event as one write from its
+//! non-blocking worker, so rotation never splits a line.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Append-only log file that rotates by size
+#[derive(Debug)]
+pub struct RotatingFile {
+    path: PathBuf,
+    /// Rotate before a write would take the file past this; 0 never rotates
+    max_bytes: u64,
+    /// Rotated copies kept next to the live file
+    keep: usize,
+    file: File,
+    len: u64,
+}
+
+impl RotatingFile {
+    /// Open `path` for appending; `max_bytes` 0 disables rotation
+    pub fn open(path: impl Into<PathBuf>, max_bytes: u64, keep: usize) -> io::Result<Self> {
+        let path = path.into();
+        let file = Self::append(&path)?;
+        let len = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_bytes,
+            keep,
+            file,
+            len,
+        })
+    }
+
+    fn append(path: &Path) -> io::Result<File> {
+        OpenOptions::new().create(true).append(true).open(path)
+    }
+
+    /// `<file>.n`
+    pub fn rotated_path(&self, n: usize) -> PathBuf {
+        let mut name = self.path.as_os_str().to_os_string();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.keep == 0 {
+            // Nothing kept: start the file over
+            self.file = File::create(&self.path)?;
+        } else {
+            match std::fs::remove_file(self.rotated_path(self.keep)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+            for n in (1..self.keep).rev() {
+                let from = self.rotated_path(n);
+                if from.exists() {
+                    std::fs::rename(&from, self.rotated_path(n + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, self.rotated_path(1))?;
+            self.file = Self::append(&self.path)?;
+        }
+        self.len = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // A line longer than the limit still goes into a file of its own
+        if self.max_bytes > 0 && self.len > 0 && self.len + buf.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.len += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotates_past_threshold_and_keeps_n_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dl-nzb.log");
+        let mut log = RotatingFile::open(&path, 100, 2).unwrap();
+        for i in 0..10 {
+            // 40-byte lines, one write each like tracing's: two fit in each file
+            let line = format!("line {:02} {}\n", i, "x".repeat(31));
+            log.write_all(line.as_bytes()).unwrap();
+        }
+        log.flush().unwrap();
+
+        let read = |p: PathBuf| std::fs::read_to_string(p).unwrap();
+        let live = read(path.clone());
+        assert!(live.starts_with("line 08 ") && live.contains("line 09 "));
+        assert!(read(log.rotated_path(1)).starts_with("line 06 "));
+        assert!(read(log.rotated_path(2)).starts_with("line 04 "));
+        assert!(!log.rotated_path(3).exists());
+        for n in 1..=2 {
+            assert!(std::fs::metadata(log.rotated_path(n)).unwrap().len() <= 100);
+        }
+
+        // Reopening picks up the current size instead of starting from zero
+        let mut log = RotatingFile::open(&path, 100, 2).unwrap();
+        let line = format!("line 10 {}\n", "x".repeat(31));
+        log.write_all(line.as_bytes()).unwrap();
+        assert!(read(log.rotated_path(1)).starts_with("line 08 "));
+    }
+
+    #[test]
+    fn test_no_limit_never_rotates() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dl-nzb.log");
+        let mut log = RotatingFile::open(&path, 0, 3).unwrap();
+        for _ in 0..100 {
+            log.write_all(&[b'x'; 100]).unwrap();
+        }
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 10_000);
+        assert!(!log.rotated_path(1).exists());
+    }
+}
@@ -1,23 +1,83 @@
 use human_bytes::human_bytes;
 use std::error::Error;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tracing::Instrument;
+use tracing_appender::non_blocking::{NonBlockingBuilder, WorkerGuard};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::prelude::*;
 use tracing_subscriber::EnvFilter;
 
 use dl_nzb::{
-    cli::{Cli, Commands},
-    config::Config,
-    download::{Downloader, Nzb},
-    error::{ConfigError, DlNzbError},
+    api::{ApiServer, ApiState},
+    cli::{Cli, Commands, ConfigAction},
+    completion::CompletionStats,
+    config::{
+        Config, ConfigSource, EffectiveConfig, LinkMode, LogFormat, NzbOverrides, SkipVerification,
+        UsenetConfig,
+    },
+    credentials::PasswordSource,
+    doctor::{self, CheckStatus},
+    download::{
+        self, below_min_size, cleanup_stale_temp_dirs, download_order, expand_inputs, format_age,
+        move_nzb, nested_nzbs, nzb_temp_dir, prepare_temp_dir, redact_filename_password,
+        redact_nzb_path, remove_empty_folders, screen_files, skip_breakdown, AbortedDownload,
+        ConnectionLimit, DirLock, DownloadEvent, Downloader, Events, FileFilter, FileSelection,
+        FolderFields, Limits, LoadedNzb, NameMap, Nzb, NzbDownload, NzbSource, PartialFiles,
+        Permissions, PostProcessPhase, SkipReason, SkippedFile, SpeedTimeline, FAILED_MARKER,
+        PROCESSED_DIR,
+    },
+    error::{ConfigError, DlNzbError, DownloadError, ErrorClass, HistoryError, NzbError},
+    history::{History, HistoryEntry},
     json_output::{
-        DownloadFileResult, DownloadSummary, ErrorOutput, FileInfo, NzbInfo, PostProcessingResult,
-        TestResult,
+        DownloadSummary, ErrorOutput, NzbInfo, PostProcessingResult, ProgressEvent,
+        ProgressTracker, RunStatus, RunSummary, TestResult,
     },
-    nntp::AsyncNntpConnection,
-    processing::PostProcessor,
+    listing,
+    log_file::RotatingFile,
+    metrics::MetricsServer,
+    nfo,
+    nntp::{clock, AsyncNntpConnection, PROTOCOL_TARGET},
+    notify::{self, Notifier},
+    phases::{self, Phase, PhaseRecorder, PhaseStatus},
+    picker,
+    plan::{self, Plan},
+    preview::{self, Preview},
+    processing::{
+        create_par2, link_files, par2_shortfalls, run_script, Manifest, Par2CreateOptions,
+        Par2Index, PostProcessor, ScriptContext, ScriptOutcome,
+    },
+    progress::{self, style::Stream, OutputMode, ProgressSink, TerminalProgress},
+    queue::{Queue, QueueEntry, QueueStatus},
+    report::{self, Failure, FailureCause},
+    retry::Backoff,
     serde_json,
+    shutdown::Shutdown,
+    speedtest::SpeedTest,
+    state::{AppState, FinishedNzb},
+    strict::{self, Violation},
+    verify::{ChecksumSource, FileStatus, Verification},
+    watch::{self, WatchFolder},
 };
 
 type Result<T> = std::result::Result<T, DlNzbError>;
 
+/// `println!` with colors and glyphs cut down to what stdout can show
+macro_rules! outln {
+    ($($arg:tt)*) => {
+        progress::style::print(&format!($($arg)*))
+    };
+}
+
+/// `eprintln!` with colors and glyphs cut down to what stderr can show
+macro_rules! errln {
+    ($($arg:tt)*) => {
+        progress::style::eprint(&format!($($arg)*))
+    };
+}
+
 #[tokio::main]
 async fn main() {
     let cli = Cli::parse_and_validate();
@@ -32,7 +92,7 @@ async fn main() {
             eprintln!(
                 "{}",
                 serde_json::to_string_pretty(&error_output)
-                    .unwrap_or_else(|_| { format!(r#"{{"error": "Failed to serialize error"}}"#) })
+                    .unwrap_or_else(|_| r#"{"error": "Failed to serialize error"}"#.to_string())
             );
         } else {
             eprintln!("Error: {}", e);
@@ -42,88 +102,447 @@ async fn main() {
                 source = err.source();
             }
         }
-        std::process::exit(1);
+        exit(e.exit_code());
     }
+    flush_logs();
 }
 
-async fn run(cli: Cli) -> Result<()> {
+async fn run(mut cli: Cli) -> Result<()> {
+    // Colors and glyphs only where the terminal shows them
+    progress::style::detect(cli.no_color);
+
     // Initialize logging
-    init_logging(&cli)?;
+    let log_format = init_logging(&cli)?;
+
+    // One policy decides which bars, spinners and status lines appear
+    progress::set_mode(cli.output_mode());
+
+    if cli.migrate_config {
+        migrate_config_file(&cli)?;
+        if cli.command.is_none() && cli.files.is_empty() {
+            return Ok(());
+        }
+    }
+
+    // `retry` re-runs a history entry through the normal download path
+    let retry = match &cli.command {
+        Some(Commands::Retry { id, force, .. }) => Some(plan_retry(*id, *force)?),
+        _ => None,
+    };
+    if let Some(entry) = &retry {
+        cli.command = None;
+        cli.files = vec![entry.nzb.clone()];
+        cli.list = false;
+        cli.move_processed = false;
+    }
+
+    // `watch` also downloads, so it shares the setup below
+    let watch = match cli.command.take() {
+        Some(Commands::Watch { dir, poll_interval }) => Some((dir, poll_interval)),
+        command => {
+            cli.command = command;
+            None
+        }
+    };
 
     // Handle special commands first
     if let Some(command) = &cli.command {
         return handle_command(command, &cli).await;
     }
 
-    // Load configuration (auto-creates if it doesn't exist)
-    let mut config = Config::load()?;
-
-    // Apply CLI overrides
-    config.apply_overrides(cli.get_config_overrides());
+    // Load configuration (auto-creates the default file if it doesn't exist)
+    let mut config = load_config(&cli)?;
 
     // Handle deprecated flags for backwards compatibility
     if cli.has_deprecated_flags() {
         eprintln!("Note: Some flags used are deprecated. See --help for current usage.");
     }
 
-    // Handle username/password from CLI
-    if let Some(username) = &cli.username {
-        config.usenet.username = username.clone();
+    // Validate configuration
+    config.validate()?;
+
+    // A retry lands in the original folder so complete files are kept
+    if let Some(dir) = retry.as_ref().and_then(|entry| entry.output_dir.clone()) {
+        config.download.dir = dir;
+        config.download.create_subfolders = false;
     }
-    if let Some(password) = &cli.password {
-        config.usenet.password = password.clone();
+
+    progress::set_speed_window(std::time::Duration::from_secs(
+        config.logging.speed_window.max(1),
+    ));
+
+    // Off a terminal or with JSON logs, progress bars become periodic log lines
+    if cli.plain_progress() || log_format == LogFormat::Json {
+        let interval = config.logging.progress_interval.max(1);
+        progress::set_plain(Some(std::time::Duration::from_secs(interval)));
     }
 
-    // Validate configuration
-    config.validate()?;
+    // `--process-dir` works on a folder, with no NZB involved
+    if let Some(dir) = &cli.process_dir {
+        return handle_process_dir(&cli, &config, dir).await;
+    }
+
+    // NZBs a run that died left unfinished go first, if the user wants them
+    // A dry run or preview leaves the queue alone
+    let queue = if cli.dry_run || cli.preview.is_some() {
+        None
+    } else {
+        Queue::open_default()
+            .map_err(|e| tracing::warn!("Download queue disabled: {}", e))
+            .ok()
+    };
+    let resumed = match &queue {
+        Some(queue) if !cli.list && cli.save_nzb.is_none() => resume_queue(&cli, queue),
+        _ => Vec::new(),
+    };
+
+    if let Some((dir, poll_interval)) = watch {
+        let interval = std::time::Duration::from_secs(poll_interval.max(1));
+        return handle_watch(&cli, config, &dir, interval, queue, resumed).await;
+    }
+
+    // Directories and quoted glob patterns stand for the NZBs they contain
+    cli.files = expand_inputs(&cli.files, cli.recursive)?;
+
+    // `-l --interactive` turns the listing into a picker feeding `--select`
+    if cli.list && cli.interactive {
+        if picker::available() && !cli.json && !cli.quiet {
+            match pick_files(&cli, &config).await? {
+                Some(selection) => {
+                    cli.select = Some(selection);
+                    cli.list = false;
+                }
+                None => {
+                    println!("No files selected");
+                    return Ok(());
+                }
+            }
+        } else if !cli.json && !cli.quiet {
+            eprintln!("Note: --interactive needs a terminal; listing instead");
+        }
+    }
+
+    // `--save-nzb` writes out the selection rather than downloading it
+    if let Some(path) = &cli.save_nzb {
+        return save_nzb(&cli, &config, path).await;
+    }
 
     // Handle list mode
     if cli.list {
-        return handle_list_mode(&cli).await;
+        return handle_list_mode(&cli, &config).await;
+    }
+
+    if cli.dry_run {
+        return handle_dry_run(&cli, &config).await;
+    }
+
+    if let Some(budget) = cli.preview {
+        return handle_preview(&cli, &config, budget).await;
     }
 
     // Check if we have files to download
-    if cli.files.is_empty() {
+    if cli.files.is_empty() && resumed.is_empty() {
         eprintln!("No NZB files specified. Use 'dl-nzb --help' for usage information.");
         return Ok(());
     }
 
     // Download mode
-    handle_download_mode(&cli, config).await
+    handle_download_mode(&cli, config, retry.as_ref(), queue, resumed).await
+}
+
+/// Unfinished NZBs of runs that died, taken over for this run if the user
+/// agrees (or passed `--resume-queue`)
+fn resume_queue(cli: &Cli, queue: &Queue) -> Vec<QueueEntry> {
+    if let Err(e) = queue.prune() {
+        tracing::warn!("Failed to prune the download queue: {}", e);
+    }
+    let abandoned = match queue.abandoned() {
+        Ok(abandoned) => abandoned,
+        Err(e) => {
+            tracing::warn!("Failed to read the download queue: {}", e);
+            return Vec::new();
+        }
+    };
+    if abandoned.is_empty() {
+        return abandoned;
+    }
+
+    let count = format!(
+        "{} NZB{}",
+        abandoned.len(),
+        if abandoned.len() == 1 { "" } else { "s" }
+    );
+    let interactive = !cli.quiet && !cli.json && std::io::stdin().is_terminal();
+    let resume = cli.resume_queue
+        || (interactive && {
+            eprintln!("An earlier run didn't finish {}:", count);
+            for entry in &abandoned {
+                eprintln!("  {}", entry.display_name());
+            }
+            inquire::Confirm::new("Resume them?")
+                .with_default(true)
+                .prompt()
+                .unwrap_or(false)
+        });
+
+    if resume {
+        return queue.adopt().unwrap_or_else(|e| {
+            tracing::warn!("Failed to take over the download queue: {}", e);
+            Vec::new()
+        });
+    }
+    if interactive {
+        // Declined: forget them rather than ask again next time
+        if let Err(e) = queue.discard_abandoned() {
+            tracing::warn!("Failed to clear the download queue: {}", e);
+        }
+    } else if !cli.json && !cli.quiet {
+        eprintln!(
+            "Note: an earlier run didn't finish {}; use --resume-queue to resume them",
+            count
+        );
+    }
+    Vec::new()
+}
+
+/// Pick the history entry for `retry` and check it can run again
+fn plan_retry(id: Option<u64>, force: bool) -> Result<HistoryEntry> {
+    let history = History::open_default()?;
+    // Without an id clap guarantees `--last-failed`
+    let entry = match id {
+        Some(id) => history.get(id)?,
+        None => history.last_failed()?,
+    };
+    if !entry.nzb_available() {
+        return Err(HistoryError::NzbMissing {
+            id: entry.id,
+            path: entry.nzb.clone(),
+        }
+        .into());
+    }
+
+    if let Some(dir) = &entry.output_dir {
+        let has_files = entry.has_files();
+        if entry.status == RunStatus::Success && has_files && !force {
+            return Err(HistoryError::AlreadyComplete {
+                id: entry.id,
+                dir: dir.clone(),
+            }
+            .into());
+        }
+        if !has_files {
+            let warning = format!(
+                "files from #{} are gone from {}; downloading everything again",
+                entry.id,
+                dir.display()
+            );
+            if progress::is_hidden() {
+                tracing::warn!("{}", warning);
+            } else {
+                errln!("\x1b[1;33m⚠ {}\x1b[0m", warning);
+            }
+        }
+    }
+    Ok(entry)
+}
+
+/// Handle `--migrate-config`: rewrite an older config file in place
+fn migrate_config_file(cli: &Cli) -> Result<()> {
+    let source = ConfigSource::locate(cli.config.as_deref());
+    let Some(path) = source.path().filter(|p| p.exists()) else {
+        return Ok(());
+    };
+
+    match Config::migrate_file(path)? {
+        Some(backup) => outln!(
+            "✓ Upgraded {} (previous version saved as {})",
+            path.display(),
+            backup.display()
+        ),
+        None => outln!("✓ {} is already up to date", path.display()),
+    }
+    Ok(())
+}
+
+/// [`Config::read_source`], telling the user when it created the per-user
+/// config file
+fn read_config(source: &ConfigSource) -> Result<Config> {
+    let created = match source {
+        ConfigSource::Standard(path) if !path.exists() => Some(path),
+        _ => None,
+    };
+    let config = Config::read_source(source)?;
+    if let Some(path) = created {
+        errln!("📝 Created default configuration at: {}", path.display());
+        errln!("⚙️  Please edit this file with your Usenet server credentials.");
+        eprintln!();
+    }
+    Ok(config)
+}
+
+/// Load configuration with CLI overrides applied before passwords are resolved
+///
+/// This way `--password` wins over the keyring and `password_cmd`, which are
+/// then not consulted at all.
+fn load_config(cli: &Cli) -> Result<Config> {
+    let mut config = read_config(&ConfigSource::locate(cli.config.as_deref()))?;
+
+    // Apply CLI overrides
+    config.apply_overrides(cli.get_config_overrides());
+
+    // Handle username/password from CLI
+    if let Some(username) = &cli.username {
+        config.usenet.username = username.clone();
+    }
+    if let Some(password) = &cli.password {
+        config.usenet.password = password.clone().into();
+        config.usenet.password_source = PasswordSource::Cli;
+    }
+
+    config.resolve_passwords()?;
+    Ok(config)
+}
+
+/// Keeps the log file's writer thread running until [`flush_logs`]
+static LOG_GUARD: Mutex<Option<WorkerGuard>> = Mutex::new(None);
+
+/// Write out log lines still queued for the log file
+fn flush_logs() {
+    drop(LOG_GUARD.lock().ok().and_then(|mut guard| guard.take()));
+}
+
+/// Optional subsystems that failed to start, and what runs instead
+static DEGRADED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Carry on without an optional subsystem: one warning on stderr, and a note
+/// for the `-v` settings snapshot and the JSON summary
+fn degrade(subsystem: &str, reason: impl std::fmt::Display, fallback: &str) {
+    let note = format!("{}: {}; {}", subsystem, reason, fallback);
+    eprintln!("Warning: {}", note);
+    DEGRADED
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(note);
+}
+
+/// What [`degrade`] has noted so far
+fn degraded() -> Vec<String> {
+    DEGRADED.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// `std::process::exit` without losing the last log lines
+fn exit(code: i32) -> ! {
+    flush_logs();
+    std::process::exit(code)
 }
 
-/// Initialize logging based on CLI arguments
-fn init_logging(cli: &Cli) -> Result<()> {
-    // Base filter from CLI, but suppress par2-rs logs (they break progress bars)
+/// Set up the tracing subscriber; returns the log format in effect
+fn init_logging(cli: &Cli) -> Result<LogFormat> {
+    // Logging starts before the config is loaded, so only its [logging]
+    // table is read here; the CLI flags win over it
+    let mut settings = Config::logging_settings(&ConfigSource::locate(cli.config.as_deref()));
+
+    // Base filter from CLI, but suppress par2-rs logs (they break progress
+    // bars), and the protocol log unless asked for
+    let protocol = if cli.verbose >= 3 || settings.protocol_trace {
+        "trace"
+    } else {
+        "off"
+    };
     let filter = EnvFilter::try_new(cli.get_log_level())
         .unwrap_or_else(|_| EnvFilter::new("info"))
-        .add_directive("par2_rs=off".parse().unwrap());
-
-    let subscriber = tracing_subscriber::fmt()
-        .with_env_filter(filter)
-        .with_target(false);
-
-    if cli.quiet {
-        subscriber.without_time().init();
-    } else if let Some(log_file) = &cli.log_file {
-        let file = std::fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(log_file)?;
-        subscriber.with_writer(file).init();
-    } else {
-        subscriber.init();
+        .add_directive("par2_rs=off".parse().unwrap())
+        .add_directive(format!("{}={}", PROTOCOL_TARGET, protocol).parse().unwrap());
+    if let Some(format) = cli.log_format {
+        settings.format = format;
+    }
+    if let Some(log_file) = &cli.log_file {
+        settings.file = Some(log_file.clone());
     }
+    let json = settings.format == LogFormat::Json;
+
+    let (writer, ansi, timestamps) = if cli.json && settings.file.is_none() {
+        // stdout carries the JSON document
+        (BoxMakeWriter::new(std::io::stderr), false, true)
+    } else if cli.quiet && settings.file.is_none() {
+        // Errors only, and never mixed into stdout's per-NZB lines
+        (
+            BoxMakeWriter::new(std::io::stderr),
+            progress::style::color(Stream::Stderr),
+            false,
+        )
+    } else if let Some(log_file) = &settings.file {
+        match RotatingFile::open(
+            log_file,
+            settings.rotate_size_mb.saturating_mul(1024 * 1024),
+            settings.keep_files,
+        ) {
+            Ok(file) => {
+                // Written from a background thread; lines wait rather than being dropped
+                let (writer, guard) = NonBlockingBuilder::default().lossy(false).finish(file);
+                *LOG_GUARD.lock().unwrap_or_else(|e| e.into_inner()) = Some(guard);
+                (BoxMakeWriter::new(writer), false, true)
+            }
+            // A log file is no reason not to download
+            Err(e) => {
+                degrade(
+                    "log file",
+                    format_args!("can't write {} ({})", log_file.display(), e),
+                    "logging to stderr",
+                );
+                (
+                    BoxMakeWriter::new(std::io::stderr),
+                    progress::style::color(Stream::Stderr),
+                    true,
+                )
+            }
+        }
+    } else {
+        // Colors only where they show; plain progress lines may be going to a file
+        (
+            BoxMakeWriter::new(std::io::stdout),
+            progress::style::color(Stream::Stdout),
+            true,
+        )
+    };
 
-    Ok(())
+    let layer = tracing_subscriber::fmt::layer()
+        .with_target(false)
+        .with_writer(writer)
+        .with_ansi(ansi && !json);
+    let layer = match (settings.format, timestamps) {
+        // Machine-read lines always carry their time
+        (LogFormat::Json, _) => layer.json().flatten_event(true).boxed(),
+        (LogFormat::Compact, true) => layer.compact().boxed(),
+        (LogFormat::Compact, false) => layer.compact().without_time().boxed(),
+        (LogFormat::Pretty, true) => layer.boxed(),
+        (LogFormat::Pretty, false) => layer.without_time().boxed(),
+    };
+    tracing_subscriber::registry()
+        .with(layer)
+        .with(filter)
+        .init();
+
+    Ok(settings.format)
 }
 
 /// Handle subcommands
 async fn handle_command(command: &Commands, cli: &Cli) -> Result<()> {
     match command {
-        Commands::Test => {
-            let config = Config::load()?;
-            let test_config = config.usenet.clone();
+        Commands::Test { server, speed } => {
+            let config = load_config(cli)?;
+            config.validate()?;
+            let mut test_config = config.usenet.clone();
+            if let Some(address) = server {
+                address.apply(&mut test_config);
+            }
+            let speed_test = speed.map(|seconds| {
+                SpeedTest::new(
+                    test_config.clone(),
+                    config.speed_test.clone(),
+                    std::time::Duration::from_secs(seconds.max(1)),
+                )
+            });
 
             if cli.json {
                 // JSON output mode
@@ -131,10 +550,13 @@ async fn handle_command(command: &Commands, cli: &Cli) -> Result<()> {
                     server: test_config.server.clone(),
                     port: test_config.port,
                     ssl: test_config.ssl,
+                    password_source: test_config.password_source.to_string(),
                     connected: false,
                     authenticated: false,
                     healthy: false,
+                    clock_skew: None,
                     error: None,
+                    speed: None,
                 };
 
                 match AsyncNntpConnection::connect(&test_config, None).await {
@@ -142,7 +564,14 @@ async fn handle_command(command: &Commands, cli: &Cli) -> Result<()> {
                         result.connected = true;
                         result.authenticated = true;
                         result.healthy = conn.is_healthy().await;
+                        result.clock_skew = conn.clock_skew().await.ok();
                         let _ = conn.close().await;
+                        if let Some(test) = &speed_test {
+                            match test.run().await {
+                                Ok(report) => result.speed = Some(report),
+                                Err(e) => result.error = Some(e.to_string()),
+                            }
+                        }
                     }
                     Err(e) => {
                         result.error = Some(e.to_string());
@@ -156,386 +585,3483 @@ async fn handle_command(command: &Commands, cli: &Cli) -> Result<()> {
 
                 match AsyncNntpConnection::connect(&test_config, None).await {
                     Ok(mut conn) => {
-                        println!("✓ Successfully connected to {}", test_config.server);
-                        println!("   Authentication: OK");
+                        outln!(
+                            "✓ Successfully connected to {}:{}{}",
+                            test_config.server,
+                            test_config.port,
+                            if test_config.ssl { " (SSL)" } else { "" }
+                        );
+                        println!(
+                            "   Authentication: OK (password from {})",
+                            test_config.password_source
+                        );
+
+                        if cli.verbose > 0 {
+                            let t = conn.timings();
+                            println!(
+                                "   Connect: {} ms TCP, {} ms TLS, {} ms greeting, {} ms AUTHINFO",
+                                t.tcp.as_millis(),
+                                t.tls.as_millis(),
+                                t.greeting.as_millis(),
+                                t.auth.as_millis()
+                            );
+                        }
 
                         if conn.is_healthy().await {
                             println!("   Server status: Healthy");
                         }
 
+                        match conn.clock_skew().await {
+                            Ok(skew) => match clock::skew_warning(&test_config.server, skew) {
+                                Some(warning) => outln!("   \x1b[33m⚠ Clock: {}\x1b[0m", warning),
+                                None => println!(
+                                    "   Clock: within {}s of the server",
+                                    skew.unsigned_abs()
+                                ),
+                            },
+                            Err(e) => tracing::debug!("No server time: {}", e),
+                        }
+
                         let _ = conn.close().await;
                     }
                     Err(e) => {
-                        eprintln!("❌ Connection failed: {}", e);
+                        errln!("❌ Connection failed: {}", e);
                         return Err(e);
                     }
                 }
+
+                if let Some(test) = &speed_test {
+                    run_speed_test(test, &test_config, cli.verbose > 0).await?;
+                }
             }
 
             Ok(())
         }
 
-        Commands::Config => {
-            let config_path = Config::config_path()?;
+        Commands::Config { check: true, .. } => handle_config_check(cli).await,
+
+        Commands::Config {
+            action: Some(ConfigAction::Get { key }),
+            show_secrets,
+            ..
+        } => {
+            let source = ConfigSource::locate(cli.config.as_deref());
+            let mut config = read_config(&source)?;
+            if !show_secrets {
+                config = config.redacted();
+            }
+            if let Some(value) = config.get_key(key)? {
+                println!("{}", value);
+            }
+            Ok(())
+        }
+
+        Commands::Config {
+            action: Some(ConfigAction::Set { key, value }),
+            ..
+        } => handle_config_set(&ConfigSource::locate(cli.config.as_deref()), key, value),
+
+        Commands::Config {
+            action: None,
+            show_secrets,
+            ..
+        } => {
+            let source = ConfigSource::locate(cli.config.as_deref());
 
             println!("Configuration file location:");
-            println!("  {}", config_path.display());
+            match source.path() {
+                Some(path) => println!("  {}", path.display()),
+                None => println!("  (none, using defaults and environment variables)"),
+            }
             println!();
 
-            if config_path.exists() {
+            if source.path().is_some_and(|p| p.exists()) {
                 println!("Current configuration:");
-                println!("{}", "─".repeat(60));
-                let config = Config::load()?;
+                outln!("{}", "─".repeat(60));
+                // Shown as configured, without running password_cmd or the keyring
+                let mut config = read_config(&source)?;
+                if !show_secrets {
+                    config = config.redacted();
+                }
                 let toml = toml::to_string_pretty(&config).map_err(|e| {
                     ConfigError::ParseError(format!("Failed to serialize config: {}", e))
                 })?;
                 println!("{}", toml);
-                println!("{}", "─".repeat(60));
+                outln!("{}", "─".repeat(60));
             } else {
                 println!("Configuration file does not exist yet.");
-                println!("Run any command to auto-create it with default values.");
+                if matches!(source, ConfigSource::Standard(_)) {
+                    println!("Run any command to auto-create it with default values.");
+                }
             }
 
             Ok(())
         }
 
-        Commands::Version => {
+        Commands::History {
+            show,
+            remove,
+            clear,
+            limit,
+        } => handle_history(cli, *show, *remove, *clear, *limit),
+
+        Commands::Retry { .. } | Commands::Watch { .. } => {
+            unreachable!("retry and watch run as downloads from run()")
+        }
+
+        Commands::Verify {
+            nzb,
+            dir,
+            checksums,
+            check_server,
+        } => handle_verify(cli, nzb, dir.as_deref(), *checksums, *check_server).await,
+
+        Commands::Extract {
+            dir,
+            password,
+            delete_archives,
+        } => handle_extract(cli, dir, password, *delete_archives).await,
+
+        Commands::Par2Create {
+            dir,
+            redundancy,
+            block_size,
+        } => handle_par2_create(cli, dir, *redundancy, *block_size).await,
+
+        Commands::Merge { files, output } => handle_merge(cli, files, output).await,
+
+        Commands::Completions { shell } => {
+            use clap::CommandFactory;
+            clap_complete::generate(
+                *shell,
+                &mut Cli::command(),
+                "dl-nzb",
+                &mut std::io::stdout(),
+            );
+            Ok(())
+        }
+
+        Commands::Version {
+            self_test: true, ..
+        } => handle_self_test(cli).await,
+        Commands::Version { detailed: true, .. } if cli.json => {
+            println!("{}", serde_json::to_string_pretty(&dl_nzb::capabilities())?);
+            Ok(())
+        }
+        Commands::Version { detailed, .. } => {
             println!("dl-nzb {}", env!("CARGO_PKG_VERSION"));
+            if *detailed {
+                let caps = dl_nzb::capabilities();
+                let features = caps.enabled();
+                let source = ConfigSource::locate(cli.config.as_deref());
+
+                println!("Platform: {} {}", caps.os, caps.arch);
+                println!(
+                    "Features: {}",
+                    if features.is_empty() {
+                        "none".to_string()
+                    } else {
+                        features.join(", ")
+                    }
+                );
+                println!("TLS:      {}", caps.tls_backend);
+                println!("PAR2:     {}", caps.par2_backend);
+                println!(
+                    "Schemas:  run {}, list {}, events {}",
+                    caps.schemas.run, caps.schemas.list, caps.schemas.events
+                );
+                match source.path() {
+                    Some(path) if path.exists() => println!("Config: {}", path.display()),
+                    Some(path) => println!("Config: {} (not created yet)", path.display()),
+                    None => println!("Config: none (defaults and environment variables)"),
+                }
+                return Ok(());
+            }
             println!("A fast, lightweight NZB downloader");
             println!();
             println!("Features:");
-            println!("  • Parallel segment downloads");
-            println!("  • Built-in PAR2 repair");
-            println!("  • Automatic RAR extraction");
-            println!("  • Resume support");
-            println!("  • JSON output for scripting");
+            outln!("  • Parallel segment downloads");
+            outln!("  • Built-in PAR2 repair");
+            outln!("  • Automatic RAR extraction");
+            outln!("  • Resume support");
+            outln!("  • JSON output for scripting");
             Ok(())
         }
     }
 }
 
-/// Handle list mode
-async fn handle_list_mode(cli: &Cli) -> Result<()> {
-    if cli.json {
-        // JSON output mode
-        let mut results = Vec::new();
-
-        for nzb_path in &cli.files {
-            let nzb = Nzb::from_file(nzb_path)?;
-
-            let files: Vec<FileInfo> = nzb
-                .files()
-                .iter()
-                .map(|file| {
-                    let filename = Nzb::get_filename_from_subject(&file.subject)
-                        .unwrap_or_else(|| file.subject.clone());
-                    let size: u64 = file.segments.segment.iter().map(|s| s.bytes).sum();
-                    let is_par2 = filename.to_lowercase().ends_with(".par2");
-
-                    FileInfo {
-                        filename,
-                        size,
-                        segments: file.segments.segment.len(),
-                        is_par2,
-                    }
-                })
-                .collect();
-
-            results.push(NzbInfo {
-                file: nzb_path.clone(),
-                total_files: nzb.files().len(),
-                total_size: nzb.total_size(),
-                total_segments: nzb.total_segments(),
-                files,
-            });
-        }
+/// Handle `version --self-test`: run the pipeline on generated data and
+/// fail if any stage did
+async fn handle_self_test(cli: &Cli) -> Result<()> {
+    let checks = download::self_test().await;
+    let failed = checks
+        .iter()
+        .filter(|c| c.status == CheckStatus::Fail)
+        .count();
 
-        println!("{}", serde_json::to_string_pretty(&results)?);
+    if cli.json {
+        let report = serde_json::json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "checks": checks,
+        });
+        println!("{}", serde_json::to_string_pretty(&report)?);
     } else {
-        // Human-readable output
-        for nzb_path in &cli.files {
-            println!("\n📄 {}", nzb_path.display());
-            println!("{}", "─".repeat(50));
-
-            let nzb = Nzb::from_file(nzb_path)?;
-
-            // Display NZB info
-            println!("Total files: {}", nzb.files().len());
-            println!("Total size: {}", human_bytes(nzb.total_size() as f64));
-            println!("Total segments: {}", nzb.total_segments());
-
-            println!("\nFiles:");
-            for file in nzb.files() {
-                let filename = Nzb::get_filename_from_subject(&file.subject)
-                    .unwrap_or_else(|| file.subject.clone());
-                let size: u64 = file.segments.segment.iter().map(|s| s.bytes).sum();
-                let file_type = if filename.to_lowercase().ends_with(".par2") {
-                    "PAR2"
-                } else {
-                    "DATA"
-                };
-                println!(
-                    "  [{:4}] {} ({})",
-                    file_type,
-                    filename,
-                    human_bytes(size as f64)
-                );
-            }
+        println!(
+            "dl-nzb {} self-test ({} {})",
+            env!("CARGO_PKG_VERSION"),
+            std::env::consts::OS,
+            std::env::consts::ARCH
+        );
+        for check in &checks {
+            let mark = match check.status {
+                CheckStatus::Pass => "\x1b[32m✓\x1b[0m",
+                CheckStatus::Warn => "\x1b[33m⚠\x1b[0m",
+                CheckStatus::Fail => "\x1b[31m✗\x1b[0m",
+            };
+            outln!("{} {}: \x1b[90m{}\x1b[0m", mark, check.name, check.detail);
+        }
+        if failed == 0 {
+            println!("All stages passed");
+        } else {
+            println!("{} of {} stages failed", failed, checks.len());
         }
     }
 
+    if failed > 0 {
+        exit(1);
+    }
     Ok(())
 }
 
-/// Handle download mode
-async fn handle_download_mode(cli: &Cli, mut config: Config) -> Result<()> {
-    // Apply CLI settings to config
-    if cli.no_directories {
-        config.download.create_subfolders = false;
+/// Handle `history`: list entries, or show, remove or clear them
+fn handle_history(
+    cli: &Cli,
+    show: Option<u64>,
+    remove: Option<u64>,
+    clear: bool,
+    limit: usize,
+) -> Result<()> {
+    let history = History::open_default()?;
+
+    if clear {
+        let count = history.clear()?;
+        if cli.json {
+            println!("{}", serde_json::json!({ "removed": count }));
+        } else {
+            println!(
+                "Removed {} history entr{}",
+                count,
+                if count == 1 { "y" } else { "ies" }
+            );
+        }
+        return Ok(());
     }
 
-    if cli.no_par2 {
-        config.post_processing.auto_par2_repair = false;
+    if let Some(id) = remove {
+        history.remove(id)?;
+        if cli.json {
+            println!("{}", serde_json::json!({ "removed": 1 }));
+        } else {
+            println!("Removed history entry #{}", id);
+        }
+        return Ok(());
     }
 
-    if cli.no_extract_rar {
-        config.post_processing.auto_extract_rar = false;
+    if let Some(id) = show {
+        let entry = history.get(id)?;
+        if cli.json {
+            println!("{}", serde_json::to_string_pretty(&entry)?);
+        } else {
+            print_history_entry(&entry);
+        }
+        return Ok(());
     }
 
-    if cli.delete_rar_after_extract {
-        config.post_processing.delete_rar_after_extract = true;
+    let entries = history.entries()?;
+    let recent =
+        &entries[entries
+            .len()
+            .saturating_sub(if limit == 0 { entries.len() } else { limit })..];
+    if cli.json {
+        println!("{}", serde_json::to_string_pretty(recent)?);
+        return Ok(());
+    }
+    if recent.is_empty() {
+        println!("No downloads recorded yet");
+        return Ok(());
     }
 
-    if cli.delete_par2 {
-        config.post_processing.delete_par2_after_repair = true;
+    println!(
+        "{:>5}  {:16}  {:22}  {:>10}  {:>11}  NAME",
+        "ID", "DATE", "STATUS", "SIZE", "SPEED"
+    );
+    for entry in recent.iter().rev() {
+        println!(
+            "{:>5}  {:16}  {:22}  {:>10}  {:>11}  {}",
+            entry.id,
+            entry.date(),
+            entry.status.describe(),
+            human_bytes(entry.total_size as f64),
+            format!("{:.2} MB/s", entry.average_speed_mbps),
+            entry.name
+        );
     }
+    if entries.len() > recent.len() {
+        outln!(
+            "\x1b[90m({} older entries; --limit 0 shows all)\x1b[0m",
+            entries.len() - recent.len()
+        );
+    }
+    Ok(())
+}
 
-    // Update memory settings (from deprecated flags if present)
-    if let Some(memory_mb) = cli.memory_limit {
-        config.memory.max_segments_in_memory = (memory_mb * 1024 * 1024) / 100_000;
-        // Rough estimate
+/// Print one history entry in full for `history --show`
+fn print_history_entry(entry: &HistoryEntry) {
+    println!("#{}  {}", entry.id, entry.name);
+    if let Some(title) = &entry.title {
+        println!("  Title:       {}", title);
     }
-    if let Some(buffer_kb) = cli.buffer_size {
-        config.memory.io_buffer_size = buffer_kb * 1024;
+    println!("  NZB:         {}", redact_nzb_path(&entry.nzb).display());
+    if let Some(parent) = entry.parent {
+        println!("  Found in:    #{}", parent);
     }
-    if let Some(concurrent) = cli.max_concurrent_files {
-        config.memory.max_concurrent_files = concurrent;
+    println!("  Finished:    {}", entry.date());
+    println!("  Status:      {}", entry.status.describe());
+    if let Some(error) = &entry.error {
+        println!("  Error:       {}", error);
     }
-
-    // Create downloader with spinner (unless JSON output)
-    let downloader = if cli.json {
-        Downloader::new(config.clone()).await?
-    } else {
-        use indicatif::{ProgressBar, ProgressStyle};
-        let spinner = ProgressBar::new_spinner();
-        spinner.set_style(
-            ProgressStyle::with_template("{spinner:.cyan} {msg}")
-                .unwrap()
-                .tick_strings(&["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"]),
+    if let Some(dir) = &entry.output_dir {
+        println!("  Output:      {}", dir.display());
+    }
+    println!(
+        "  Size:        {} in {} ({:.2} MB/s)",
+        human_bytes(entry.total_size as f64),
+        progress::format_duration(std::time::Duration::from_secs_f64(
+            entry.download_time_seconds
+        )),
+        entry.average_speed_mbps
+    );
+    if entry.transferred > 0 {
+        println!(
+            "  Transfer:    {}",
+            progress::format_transfer(entry.transferred, entry.total_size)
         );
-        spinner.enable_steady_tick(std::time::Duration::from_millis(80));
-        spinner.set_message("Connecting to server...");
-
-        let downloader = Downloader::new(config.clone()).await?;
-
-        spinner.finish_and_clear();
-        downloader
-    };
+    }
+    if !entry.bytes_by_server.is_empty() {
+        println!(
+            "  Servers:     {}",
+            progress::format_server_bytes(&entry.bytes_by_server)
+        );
+    }
+    println!("  Failed segs: {}", entry.failed_segments);
+    if let Some(cause) = entry.failure_cause {
+        println!("  Cause:       {}", cause.describe());
+    }
+    if let Some(speed) = entry.speed {
+        println!(
+            "  Speed:       {}/s min, {}/s avg, {}/s p95, {}/s max",
+            human_bytes(speed.min as f64),
+            human_bytes(speed.avg as f64),
+            human_bytes(speed.p95 as f64),
+            human_bytes(speed.max as f64)
+        );
+    }
+    if !entry.phases.is_empty() {
+        println!("  Phases:      {}", phases::describe(&entry.phases));
+    }
+    for line in entry.completion.lines() {
+        println!("  Completion:  {}", line);
+    }
+    println!("  Hash:        {}", entry.content_hash);
+    if !entry.files.is_empty() {
+        println!("  Files:");
+        for file in &entry.files {
+            if let Some(reason) = file.skip_reason {
+                outln!(
+                    "    \x1b[90m↷ {} (skipped: {})\x1b[0m",
+                    file.filename,
+                    reason
+                );
+            } else if file.segments_failed == 0 {
+                outln!(
+                    "    \x1b[32m✓\x1b[0m {} ({})",
+                    file.filename,
+                    human_bytes(file.size as f64)
+                );
+            } else {
+                outln!(
+                    "    \x1b[31m✗\x1b[0m {} ({}, {} of {} segments failed)",
+                    file.filename,
+                    human_bytes(file.size as f64),
+                    file.segments_failed,
+                    file.segments_downloaded + file.segments_failed
+                );
+            }
+        }
+    }
+}
 
-    // Process each NZB file
-    let mut all_results = Vec::new();
+/// Append a finished NZB to the history; failures only warn
+fn record_history(history: Option<&History>, entry: HistoryEntry) -> Option<u64> {
+    match history?.append(entry) {
+        Ok(id) => Some(id),
+        Err(e) => {
+            tracing::warn!("Failed to record download history: {}", e);
+            None
+        }
+    }
+}
 
-    for nzb_path in &cli.files {
-        let nzb = match Nzb::from_file(nzb_path) {
-            Ok(nzb) => nzb,
-            Err(e) => {
-                eprintln!("Failed to load {}: {}", nzb_path.display(), e);
-                continue;
-            }
-        };
+/// Handle `config --check`: run the diagnostics and fail if any check failed
+async fn handle_config_check(cli: &Cli) -> Result<()> {
+    let config = load_config(cli);
+    // The same snapshot a download run logs and reports
+    let settings = config.as_ref().ok().map(|config| {
+        EffectiveConfig::new(
+            config,
+            &ConfigSource::locate(cli.config.as_deref()),
+            cli.overridden_keys(),
+        )
+    });
+    let checks = doctor::diagnose(config).await;
+    let failed = checks
+        .iter()
+        .filter(|c| c.status == CheckStatus::Fail)
+        .count();
 
-        // Create output directory based on NZB filename
-        let output_dir = if config.download.create_subfolders {
-            // Use NZB filename (without extension) as folder name
-            let folder_name = nzb_path
-                .file_stem()
-                .and_then(|s| s.to_str())
-                .unwrap_or("download")
-                .to_string();
-            config.download.dir.join(folder_name)
+    if cli.json {
+        let report = serde_json::json!({ "settings": settings, "checks": checks });
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        if let Some(settings) = &settings {
+            println!("Effective settings:");
+            print!("{}", settings);
+            println!();
+        }
+        for check in &checks {
+            let mark = match check.status {
+                CheckStatus::Pass => "\x1b[32m✓\x1b[0m",
+                CheckStatus::Warn => "\x1b[33m⚠\x1b[0m",
+                CheckStatus::Fail => "\x1b[31m✗\x1b[0m",
+            };
+            outln!("{} {}: \x1b[90m{}\x1b[0m", mark, check.name, check.detail);
+        }
+        println!();
+        if failed == 0 {
+            println!("All checks passed");
+        } else {
+            println!("{} of {} checks failed", failed, checks.len());
+        }
+    }
+
+    if failed > 0 {
+        exit(RunStatus::ConfigError.exit_code());
+    }
+    Ok(())
+}
+
+/// Handle `config set`, writing the change back to the config file
+fn handle_config_set(source: &ConfigSource, key: &str, value: &str) -> Result<()> {
+    let path = source.path().ok_or_else(|| ConfigError::Invalid {
+        field: "config".to_string(),
+        reason: "No configuration file location; pass --config <FILE>".to_string(),
+    })?;
+
+    if !path.exists() {
+        if !matches!(source, ConfigSource::Standard(_)) {
+            return Err(ConfigError::NotFound(path.to_path_buf()).into());
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        Config::create_sample(path)?;
+    }
+
+    // Read from stdin so secrets stay out of shell history
+    let value = if value == "-" {
+        if std::io::stdin().is_terminal() {
+            rpassword::prompt_password(format!("{}: ", key))?
+        } else {
+            let mut line = String::new();
+            std::io::stdin().read_line(&mut line)?;
+            line.trim_end_matches(['\r', '\n']).to_string()
+        }
+    } else {
+        value.to_string()
+    };
+
+    // Edit the file as written so environment overrides aren't persisted
+    let mut config = Config::from_file(path)?;
+    config.set_key(key, &value)?;
+
+    // An incomplete first-run setup is fine; anything else is rejected
+    match config.validate() {
+        Ok(()) | Err(DlNzbError::Config(ConfigError::NoServer | ConfigError::NoCredentials)) => {}
+        Err(e) => return Err(e),
+    }
+
+    config.save(path)?;
+    outln!("✓ Set {} in {}", key, path.display());
+    Ok(())
+}
+
+/// Write `--json-progress` lines until every event sender is gone
+///
+/// Aggregate progress goes out at most once per `interval`, plus once at the
+/// end so the final byte count is always reported.
+async fn stream_progress(
+    mut rx: tokio::sync::mpsc::UnboundedReceiver<DownloadEvent>,
+    interval: std::time::Duration,
+) -> ProgressTracker {
+    let mut tracker = ProgressTracker::new();
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    let emit = |tracker: &ProgressTracker, event: ProgressEvent| {
+        if let Ok(json) = serde_json::to_string(&tracker.line(event)) {
+            println!("{}", json);
+        }
+    };
+
+    loop {
+        tokio::select! {
+            event = rx.recv() => match event {
+                Some(event) => {
+                    if let Some(event) = tracker.record(event) {
+                        emit(&tracker, event);
+                    }
+                }
+                None => break,
+            },
+            _ = ticker.tick() => {
+                if let Some(event) = tracker.snapshot() {
+                    emit(&tracker, event);
+                }
+            }
+        }
+    }
+
+    if let Some(event) = tracker.snapshot() {
+        emit(&tracker, event);
+    }
+    tracker
+}
+
+/// Load an NZB and keep only the files `--only`/`--exclude`/`--select` pick
+///
+/// `path` may also be `-` (stdin), an `http(s)://` URL or `indexer:<name>:<id>`.
+async fn load_nzb(
+    path: &std::path::Path,
+    filter: &FileFilter,
+    config: &Config,
+) -> Result<LoadedNzb> {
+    let source = NzbSource::from_arg(path);
+    let mut loaded = LoadedNzb::load(&source, config)
+        .await
+        .map_err(|e| e.with_nzb_path(path))?;
+    loaded.nzb.apply_filter(filter)?;
+    Ok(loaded)
+}
+
+/// Show the picker for the single NZB argument
+async fn pick_files(cli: &Cli, config: &Config) -> Result<Option<FileSelection>> {
+    let invalid = |reason: &str| ConfigError::Invalid {
+        field: "--interactive".to_string(),
+        reason: reason.to_string(),
+    };
+    let [path] = cli.files.as_slice() else {
+        return Err(invalid("pick files from one NZB at a time").into());
+    };
+    // The download re-reads the NZB, which stdin can't provide twice
+    if NzbSource::from_arg(path) == NzbSource::Stdin {
+        return Err(invalid("can't pick from an NZB read from stdin").into());
+    }
+
+    let filter = FileFilter::new(&cli.only, &cli.exclude, cli.case_sensitive)?
+        .with_selection(cli.select.clone());
+    let nzb = load_nzb(path, &filter, config).await?.nzb;
+    picker::pick_files(&nzb)
+}
+
+/// Write the single NZB argument, filtered, to `path`
+async fn save_nzb(cli: &Cli, config: &Config, path: &Path) -> Result<()> {
+    let [nzb_path] = cli.files.as_slice() else {
+        return Err(ConfigError::Invalid {
+            field: "--save-nzb".to_string(),
+            reason: "save one NZB at a time; use `dl-nzb merge` to combine several".to_string(),
+        }
+        .into());
+    };
+    let filter = FileFilter::new(&cli.only, &cli.exclude, cli.case_sensitive)?
+        .with_selection(cli.select.clone());
+    let nzb = load_nzb(nzb_path, &filter, config).await?.nzb;
+    nzb.save(path)?;
+    if !cli.json && !cli.quiet {
+        println!(
+            "Saved {} file{} ({}) to {}",
+            nzb.files().len(),
+            if nzb.files().len() == 1 { "" } else { "s" },
+            human_bytes(nzb.total_size() as f64),
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Combine NZBs into `output`, dropping files already taken from an earlier one
+async fn handle_merge(cli: &Cli, files: &[PathBuf], output: &Path) -> Result<()> {
+    let config = load_config(cli)?;
+    let filter = FileFilter::new(&[], &[], false)?;
+    let mut nzbs = Vec::new();
+    for path in expand_inputs(files, false)? {
+        nzbs.push(load_nzb(&path, &filter, &config).await?.nzb);
+    }
+    let count = nzbs.len();
+    let listed: usize = nzbs.iter().map(|nzb| nzb.files().len()).sum();
+    let merged = Nzb::merge(nzbs)?;
+    merged.save(output)?;
+
+    if !cli.json && !cli.quiet {
+        let dropped = listed - merged.files().len();
+        println!(
+            "Merged {} NZB{} into {} ({} files, {}){}",
+            count,
+            if count == 1 { "" } else { "s" },
+            output.display(),
+            merged.files().len(),
+            human_bytes(merged.total_size() as f64),
+            match dropped {
+                0 => String::new(),
+                1 => ", 1 duplicate file dropped".to_string(),
+                n => format!(", {} duplicate files dropped", n),
+            }
+        );
+    }
+    Ok(())
+}
+
+/// Handle `verify`: check a folder against an NZB and print what's wrong
+async fn handle_verify(
+    cli: &Cli,
+    nzb_path: &Path,
+    dir: Option<&Path>,
+    checksums: bool,
+    check_server: bool,
+) -> Result<()> {
+    let config = load_config(cli)?;
+    let filter = FileFilter::new(&[], &[], false)?;
+    let loaded = load_nzb(nzb_path, &filter, &config).await?;
+
+    // Without --dir, the folder a run would use, found as --dry-run finds it
+    let dir = match dir {
+        Some(dir) => dir.to_path_buf(),
+        None => {
+            let sidecar = match loaded.xml {
+                Some(_) => None,
+                None => NzbOverrides::load(nzb_path)?,
+            };
+            let nzb_config = match &sidecar {
+                Some(overrides) => overrides.apply(&config)?,
+                None => config.clone(),
+            };
+            let previous = History::open_default()
+                .and_then(|history| history.latest_for(loaded.nzb.content_hash()))
+                .map_err(|e| tracing::warn!("Download history unavailable: {}", e))
+                .ok()
+                .flatten();
+            let fields = FolderFields::new(
+                &loaded.name,
+                &loaded.nzb,
+                sidecar.as_ref().and_then(|o| o.category.as_deref()),
+            );
+            plan::output_dir(&nzb_config, &fields, previous.as_ref()).0
+        }
+    };
+    if !dir.is_dir() {
+        return Err(ConfigError::InvalidPath {
+            path: dir,
+            reason: "not a directory".to_string(),
+        }
+        .into());
+    }
+
+    // Hashing shows its own bar
+    let spinner = (!cli.json && !cli.quiet && !checksums)
+        .then(|| progress::create_spinner(format!("Verifying {}...", dir.display())));
+    let hashing: Arc<dyn ProgressSink> = Arc::new(TerminalProgress::new(config.logging.progress));
+    let verification = Verification::new(
+        nzb_path,
+        &loaded.nzb,
+        &dir,
+        &config,
+        checksums,
+        check_server,
+        &hashing,
+    )
+    .await;
+    if let Some(spinner) = spinner {
+        spinner.finish_and_clear();
+    }
+
+    if cli.json {
+        println!("{}", serde_json::to_string_pretty(&verification)?);
+    } else if !cli.quiet {
+        print_verification(&verification, cli.verbose > 0);
+    }
+    if !verification.complete {
+        exit(RunStatus::Incomplete.exit_code());
+    }
+    Ok(())
+}
+
+/// Print a per-file table (only the failures without -v) and the verdict
+fn print_verification(verification: &Verification, verbose: bool) {
+    outln!("\n📄 {}", redact_nzb_path(&verification.nzb).display());
+    outln!("{}", "─".repeat(50));
+    println!("Folder: {}", verification.dir.display());
+    println!();
+    for file in &verification.files {
+        if !verbose && file.status == FileStatus::Ok {
+            continue;
+        }
+        let mark = match file.status {
+            FileStatus::Ok => "\x1b[32m✓\x1b[0m",
+            _ => "\x1b[31m✗\x1b[0m",
+        };
+        let size = match (file.size, file.expected_size) {
+            (Some(size), Some(expected)) if size != expected => format!(
+                "{} of {}",
+                human_bytes(size as f64),
+                human_bytes(expected as f64)
+            ),
+            (Some(size), _) => human_bytes(size as f64),
+            (None, _) => format!("~{}", human_bytes(file.declared_size as f64)),
+        };
+        let mut detail = file.status.describe().to_string();
+        if let Some(found_as) = &file.found_as {
+            detail.push_str(&format!(", as {}", found_as));
+        }
+        match file.checksum {
+            Some(ChecksumSource::Par2) => detail.push_str(", PAR2 checked"),
+            Some(ChecksumSource::Sfv) => detail.push_str(", SFV checked"),
+            None => {}
+        }
+        if let Some(available) = file.available {
+            detail.push_str(&format!(", ~{:.0}% on the servers", available));
+        }
+        outln!(
+            "{} {:>4}  {} ({}) \x1b[90m{}\x1b[0m",
+            mark,
+            file.index,
+            file.filename,
+            size,
+            detail
+        );
+    }
+
+    let failed = verification.failed().count();
+    if verification.complete {
+        outln!(
+            "\x1b[32m✓ All {} files complete\x1b[0m",
+            verification.files.len()
+        );
+    } else {
+        outln!(
+            "\n\x1b[1;31m✗ {} of {} files missing or damaged\x1b[0m",
+            failed,
+            verification.files.len()
+        );
+    }
+}
+
+/// Handle list mode
+async fn handle_list_mode(cli: &Cli, config: &Config) -> Result<()> {
+    let filter = FileFilter::new(&cli.only, &cli.exclude, cli.case_sensitive)?
+        .with_selection(cli.select.clone());
+
+    if cli.json {
+        // JSON output mode: an array of NZB documents on stdout, one error
+        // object per unreadable NZB on stderr
+        let mut results = Vec::new();
+        let mut failed = 0;
+
+        for nzb_path in &cli.files {
+            let nzb = match load_nzb(nzb_path, &filter, config).await {
+                Ok(loaded) => loaded.nzb,
+                Err(e) => {
+                    failed += 1;
+                    let error = ErrorOutput::from_error(&e).for_file(nzb_path);
+                    eprintln!("{}", serde_json::to_string(&error)?);
+                    continue;
+                }
+            };
+
+            let mut info = NzbInfo::from_nzb(nzb_path, &nzb, config.download.min_file_size);
+            info.exceeds_retention = plan::retention_warning(config, &nzb).is_some();
+            if cli.check_availability {
+                let availability =
+                    plan::sample_availability(config, nzb.files(), plan::SAMPLE_SIZE).await;
+                info.set_availability(&availability);
+            }
+            info.sidecar = Some(NzbOverrides::sidecar_path(nzb_path)).filter(|p| p.exists());
+            if cli.show_order {
+                info.download_order = download_order(nzb.files(), &config.download)
+                    .iter()
+                    .map(|f| f.index)
+                    .collect();
+            }
+            results.push(info);
+        }
+
+        println!("{}", serde_json::to_string_pretty(&results)?);
+        if failed > 0 {
+            exit(1);
+        }
+    } else {
+        // Human-readable output
+        for nzb_path in &cli.files {
+            outln!("\n📄 {}", redact_nzb_path(nzb_path).display());
+            outln!("{}", "─".repeat(50));
+
+            let nzb = load_nzb(nzb_path, &filter, config).await?.nzb;
+            let availability = if cli.check_availability {
+                let spinner = progress::create_spinner("Sampling segment availability...");
+                let availability =
+                    plan::sample_availability(config, nzb.files(), plan::SAMPLE_SIZE).await;
+                spinner.finish_and_clear();
+                Some(availability)
+            } else {
+                None
+            };
+
+            // Display NZB info
+            println!("Total files: {}", nzb.files().len());
+            println!("Total size: {}", human_bytes(nzb.total_size() as f64));
+            println!("Total segments: {}", nzb.total_segments());
+            // What a download would leave out, classified as it would be
+            let (_, screened) = screen_files(
+                &nzb.files().iter().collect::<Vec<_>>(),
+                config.download.min_file_size,
+            );
+            let skipped: Vec<SkippedFile> =
+                nzb.filtered_files().into_iter().chain(screened).collect();
+            if !skipped.is_empty() {
+                println!(
+                    "Skipped: {} ({})",
+                    skipped.len(),
+                    skip_breakdown(skipped.iter().map(|file| file.reason))
+                );
+            }
+            if let (Some(oldest), Some(newest)) = (nzb.oldest_age_days(), nzb.newest_age_days()) {
+                let (oldest, newest) = (format_age(oldest), format_age(newest));
+                if oldest == newest {
+                    println!("Post age: {}", oldest);
+                } else {
+                    println!("Post age: {} (oldest) to {} (newest)", oldest, newest);
+                }
+            }
+            if let Some(availability) = &availability {
+                match availability.completeness() {
+                    Some(percent) => println!(
+                        "Availability: {:.1}% of {} sampled segments",
+                        percent, availability.sampled
+                    ),
+                    None => println!("Availability: no segments sampled"),
+                }
+                for server in &availability.unreachable {
+                    outln!("\x1b[33m⚠ Not reachable: {}\x1b[0m", server);
+                }
+            }
+            if cli.verbose > 0 {
+                match nzb.par2_coverage() {
+                    Some(coverage) => println!(
+                        "PAR2 recovery: {} blocks for about {} data blocks ({:.1}%)",
+                        coverage.recovery_blocks, coverage.data_blocks, coverage.recovery_percent
+                    ),
+                    None => println!("PAR2 recovery: none"),
+                }
+            }
+            if let Some(warning) = plan::retention_warning(config, &nzb) {
+                outln!("\x1b[1;31m⚠ Warning: {}\x1b[0m", warning);
+            }
+            let report = nzb.validate();
+            for warning in report.gap_warnings() {
+                outln!("\x1b[1;31m⚠ Warning: {}\x1b[0m", warning);
+            }
+            let sidecar = NzbOverrides::sidecar_path(nzb_path);
+            if sidecar.exists() {
+                println!("Per-NZB settings: {}", redact_nzb_path(&sidecar).display());
+            }
+
+            // With --show-order, in the order the files would download
+            let files = if cli.show_order {
+                println!(
+                    "\nFiles in download order ({}):",
+                    config.download.file_order
+                );
+                download_order(nzb.files(), &config.download)
+            } else {
+                println!("\nFiles:");
+                nzb.files().iter().collect()
+            };
+            let samples = availability.map(|a| a.files).unwrap_or_default();
+            // -v lays the files out as a table with more about each
+            if cli.verbose > 0 {
+                let table = listing::file_table(
+                    &files,
+                    &samples,
+                    config.download.min_file_size,
+                    listing::terminal_width().saturating_sub(2),
+                    SystemTime::now(),
+                );
+                for line in table {
+                    println!("  {}", line);
+                }
+            } else {
+                for file in &files {
+                    let size: u64 = file.segments.segment.iter().map(|s| s.bytes).sum();
+                    println!(
+                        "  {:>4}  [{}] {} ({}, {} segment{}{}{})",
+                        file.index,
+                        file.kind_label(),
+                        file.filename(),
+                        human_bytes(size as f64),
+                        file.segments.segment.len(),
+                        if file.segments.segment.len() == 1 {
+                            ""
+                        } else {
+                            "s"
+                        },
+                        file.age_days()
+                            .map(|age| format!(", {} old", format_age(age)))
+                            .unwrap_or_default(),
+                        samples
+                            .get(&file.index)
+                            .and_then(|sample| sample.completeness())
+                            .map(|percent| format!(", {:.0}% available", percent))
+                            .unwrap_or_default()
+                    );
+                    if below_min_size(file, config.download.min_file_size) {
+                        println!("        skipped: below download.min_file_size");
+                    }
+                }
+            }
+
+            // -v spells out every problem the NZB itself has
+            if cli.verbose > 0 {
+                if report.is_clean() {
+                    println!("\nNZB check: no missing, duplicate or odd segments");
+                } else {
+                    println!("\nNZB check:");
+                    for file in report.problems() {
+                        println!("  {:>4}  {}", file.index, file.filename);
+                        for problem in file.describe() {
+                            println!("        {}", problem);
+                        }
+                    }
+                }
+            }
+        }
+        println!("\nDownload a subset with --select, e.g. --select 1,3-5");
+    }
+
+    Ok(())
+}
+
+/// `--process-dir`: post-process everything in `dir` as it is
+async fn handle_process_dir(cli: &Cli, config: &Config, dir: &Path) -> Result<()> {
+    let outcome = process_standalone(cli, config, dir, Vec::new()).await?;
+    let status = RunStatus::of_download(&[], 0, &outcome, None);
+    if status != RunStatus::Success {
+        exit(status.exit_code());
+    }
+    Ok(())
+}
+
+/// `extract`: extract the archives already in `dir`
+async fn handle_extract(
+    cli: &Cli,
+    dir: &Path,
+    passwords: &[String],
+    delete_archives: bool,
+) -> Result<()> {
+    let mut config = load_config(cli)?;
+    let post = &mut config.post_processing;
+    post.auto_par2_repair = false;
+    post.auto_extract_rar = true;
+    post.deobfuscate_file_names = false;
+    post.verify_media = false;
+    post.delete_rar_after_extract |= delete_archives;
+
+    let outcome = process_standalone(cli, &config, dir, passwords.to_vec()).await?;
+    let failed = !outcome.extract_failed.is_empty()
+        || !outcome.password_required.is_empty()
+        || !outcome.space_shortfalls.is_empty();
+    if failed {
+        exit(RunStatus::PostProcessingFailed.exit_code());
+    }
+    if !outcome.rar_extracted {
+        if !cli.json && !cli.quiet {
+            println!("Nothing to extract in {}", dir.display());
+        }
+        // As documented in `extract --help`
+        exit(1);
+    }
+    Ok(())
+}
+
+/// `par2-create`: make a recovery set for the files in `dir`
+async fn handle_par2_create(
+    cli: &Cli,
+    dir: &Path,
+    redundancy: Option<u32>,
+    block_size: Option<u64>,
+) -> Result<()> {
+    let config = load_config(cli)?;
+    if !dir.is_dir() {
+        return Err(ConfigError::InvalidPath {
+            path: dir.to_path_buf(),
+            reason: "not a directory".to_string(),
+        }
+        .into());
+    }
+    let mut options = Par2CreateOptions::from_config(&config.post_processing);
+    options.redundancy = redundancy.unwrap_or(options.redundancy);
+    options.block_size = block_size.unwrap_or(options.block_size);
+    if options.block_size % 4 != 0 {
+        return Err(ConfigError::Invalid {
+            field: "--block-size".to_string(),
+            reason: "Must be a multiple of 4".to_string(),
+        }
+        .into());
+    }
+
+    let progress: Arc<dyn ProgressSink> = Arc::new(TerminalProgress::new(config.logging.progress));
+    progress.on_phase(PostProcessPhase::CreatePar2);
+    let created = create_par2(dir, options, &progress).await?;
+
+    if cli.json {
+        println!("{}", serde_json::to_string_pretty(&created)?);
+    } else if !cli.quiet {
+        println!(
+            "Created {} for {} file{} ({}% recovery data, {} volume{})",
+            created.index.display(),
+            created.files,
+            if created.files == 1 { "" } else { "s" },
+            created.redundancy,
+            created.volumes.len(),
+            if created.volumes.len() == 1 { "" } else { "s" }
+        );
+        for volume in &created.volumes {
+            println!("  {}", volume.display());
+        }
+    }
+    Ok(())
+}
+
+/// Post-process every file in `dir` as the download path would, printing
+/// the result with --json
+async fn process_standalone(
+    cli: &Cli,
+    config: &Config,
+    dir: &Path,
+    passwords: Vec<String>,
+) -> Result<PostProcessingResult> {
+    if !dir.is_dir() {
+        return Err(ConfigError::InvalidPath {
+            path: dir.to_path_buf(),
+            reason: "not a directory".to_string(),
+        }
+        .into());
+    }
+    let manifest = Manifest::scan(dir)?;
+    let processor = PostProcessor::new(
+        config.post_processing.clone(),
+        config.tuning.large_file_threshold,
+    )
+    .with_passwords(passwords)
+    .interactive(!cli.quiet && !cli.json && std::io::stdin().is_terminal())
+    .with_temp_dir(config.download.temp_dir.clone())
+    .with_fsync(config.download.fsync)
+    .with_permissions(Permissions::from_config(&config.download)?)
+    .with_progress(Arc::new(TerminalProgress::new(config.logging.progress)));
+    let outcome = processor.process_downloads(&manifest).await?;
+
+    if cli.json {
+        println!("{}", serde_json::to_string_pretty(&outcome)?);
+    }
+    Ok(outcome)
+}
+
+/// Handle `--dry-run`: plan each NZB's download and report it
+async fn handle_dry_run(cli: &Cli, config: &Config) -> Result<()> {
+    let filter = FileFilter::new(&cli.only, &cli.exclude, cli.case_sensitive)?
+        .with_selection(cli.select.clone());
+    let history = History::open_default()
+        .map_err(|e| tracing::warn!("Download history unavailable: {}", e))
+        .ok();
+    let speed = history
+        .as_ref()
+        .and_then(|history| {
+            history
+                .entries()
+                .map_err(|e| tracing::warn!("Failed to read download history: {}", e))
+                .ok()
+        })
+        .and_then(|entries| plan::recent_speed(&entries));
+
+    let mut plans = Vec::new();
+    let mut status = RunStatus::Success;
+    for nzb_path in &cli.files {
+        let spinner = (!cli.json && !cli.quiet).then(|| {
+            progress::create_spinner(format!(
+                "Planning {}...",
+                redact_nzb_path(nzb_path).display()
+            ))
+        });
+        let planned = plan_nzb(cli, config, &filter, nzb_path, history.as_ref(), speed).await;
+        if let Some(spinner) = spinner {
+            spinner.finish_and_clear();
+        }
+        match planned {
+            Ok(plan) => {
+                if !plan.viable {
+                    status = status.max(RunStatus::Aborted);
+                }
+                if !cli.json {
+                    print_plan(&plan, cli.verbose > 0);
+                }
+                plans.push(plan);
+            }
+            Err(e) => {
+                status = status.max(e.run_status());
+                if cli.json {
+                    let error = ErrorOutput::from_error(&e).for_file(nzb_path);
+                    eprintln!("{}", serde_json::to_string(&error)?);
+                } else {
+                    eprintln!(
+                        "Failed to load {}: {}",
+                        redact_nzb_path(nzb_path).display(),
+                        e
+                    );
+                }
+            }
+        }
+    }
+
+    if cli.json {
+        println!("{}", serde_json::to_string_pretty(&plans)?);
+    } else if !cli.quiet && plans.len() > 1 {
+        let viable = plans.iter().filter(|plan| plan.viable).count();
+        println!(
+            "
+{} of {} NZBs look ready to download",
+            viable,
+            plans.len()
+        );
+    }
+    if status != RunStatus::Success {
+        exit(status.exit_code());
+    }
+    Ok(())
+}
+
+/// Plan one NZB with its sidecar settings, as a run would load it
+async fn plan_nzb(
+    cli: &Cli,
+    config: &Config,
+    filter: &FileFilter,
+    nzb_path: &Path,
+    history: Option<&History>,
+    speed: Option<u64>,
+) -> Result<Plan> {
+    let loaded = load_nzb(nzb_path, filter, config).await?;
+    let sidecar = match loaded.xml {
+        Some(_) => None,
+        None => NzbOverrides::load(nzb_path)?,
+    };
+    let mut nzb_config = match &sidecar {
+        Some(overrides) => overrides.apply(config)?,
+        None => config.clone(),
+    };
+    nzb_config.download.force_redownload = cli.force;
+
+    let previous = match history.filter(|_| !cli.force) {
+        Some(history) => history
+            .latest_for(loaded.nzb.content_hash())
+            .map_err(|e| tracing::warn!("Failed to read download history: {}", e))
+            .ok()
+            .flatten(),
+        None => None,
+    };
+    Ok(Plan::new(
+        nzb_path,
+        &loaded,
+        &nzb_config,
+        sidecar.as_ref(),
+        previous.as_ref(),
+        speed,
+    )
+    .await)
+}
+
+/// Handle `--preview`: fetch the start of each NZB's telling files into a
+/// scratch folder, show what they hold and stop
+async fn handle_preview(cli: &Cli, config: &Config, budget: u64) -> Result<()> {
+    let filter = FileFilter::new(&cli.only, &cli.exclude, cli.case_sensitive)?
+        .with_selection(cli.select.clone());
+    let spinner = progress::create_spinner("Connecting to server...");
+    let downloader = Downloader::builder(config.clone()).build().await;
+    spinner.finish_and_clear();
+    let downloader = downloader?;
+    let scratch = config
+        .download
+        .temp_dir
+        .clone()
+        .unwrap_or_else(std::env::temp_dir);
+
+    let mut previews = Vec::new();
+    let mut status = RunStatus::Success;
+    for nzb_path in &cli.files {
+        let spinner = progress::create_spinner(format!(
+            "Previewing {}...",
+            redact_nzb_path(nzb_path).display()
+        ));
+        let previewed = preview_nzb(
+            cli,
+            config,
+            &downloader,
+            &filter,
+            nzb_path,
+            budget,
+            &scratch,
+        )
+        .await;
+        spinner.finish_and_clear();
+        match previewed {
+            Ok(preview) => {
+                if !cli.json {
+                    print_preview(&preview);
+                }
+                previews.push(preview);
+            }
+            Err(e) => {
+                status = status.max(e.run_status());
+                if cli.json {
+                    let error = ErrorOutput::from_error(&e).for_file(nzb_path);
+                    eprintln!("{}", serde_json::to_string(&error)?);
+                } else {
+                    eprintln!(
+                        "Failed to preview {}: {}",
+                        redact_nzb_path(nzb_path).display(),
+                        e
+                    );
+                }
+            }
+        }
+    }
+
+    if cli.json {
+        println!("{}", serde_json::to_string_pretty(&previews)?);
+    }
+    if status != RunStatus::Success {
+        exit(status.exit_code());
+    }
+    Ok(())
+}
+
+/// Download the preview of one NZB into a folder under `scratch`, which is
+/// removed again unless `--keep-partial`
+async fn preview_nzb(
+    cli: &Cli,
+    config: &Config,
+    downloader: &Downloader,
+    filter: &FileFilter,
+    nzb_path: &Path,
+    budget: u64,
+    scratch: &Path,
+) -> Result<Preview> {
+    let loaded = load_nzb(nzb_path, filter, config).await?;
+    let files = preview::preview_files(loaded.nzb.files(), budget);
+    if files.is_empty() {
+        // Nothing but PAR2 files is all that leaves nothing to look at
+        let files = loaded.nzb.files().len();
+        return Err(NzbError::Par2Only { files }.into());
+    }
+
+    let dir = nzb_temp_dir(scratch, &format!("{}.preview", loaded.name));
+    let mut preview_config = config.clone();
+    preview_config.download.dir = dir.clone();
+    preview_config.download.temp_dir = None;
+    preview_config.download.create_subfolders = false;
+    let download = downloader
+        .download_files(&files, preview_config)
+        .await
+        .map_err(DlNzbError::from);
+    let preview =
+        download.map(|download| Preview::gather(nzb_path, loaded.nzb.files(), &download.results));
+
+    if cli.keep_partial {
+        return preview.map(|preview| Preview {
+            kept_in: Some(dir),
+            ..preview
+        });
+    }
+    if let Err(e) = std::fs::remove_dir_all(&dir) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            tracing::warn!("Failed to remove {}: {}", dir.display(), e);
+        }
+    }
+    preview
+}
+
+fn print_preview(preview: &Preview) {
+    outln!("\n📄 {}", redact_nzb_path(&preview.nzb).display());
+    outln!("{}", "─".repeat(50));
+
+    for file in &preview.files {
+        let extent = if file.complete() {
+            "whole".to_string()
+        } else {
+            format!("{} of {} segments", file.segments, file.total_segments)
+        };
+        println!(
+            "Fetched {} ({}, {})",
+            file.filename,
+            human_bytes(file.fetched as f64),
+            extent
+        );
+    }
+
+    if let Some(nfo) = &preview.nfo {
+        println!("\nNFO:");
+        for line in nfo.lines() {
+            println!("  {}", line.trim_end());
+        }
+    }
+
+    for archive in &preview.archives {
+        let listing = &archive.listing;
+        println!(
+            "\n{}: {} file{}",
+            archive.volume,
+            listing.entries.len(),
+            if listing.entries.len() == 1 { "" } else { "s" }
+        );
+        for entry in &listing.entries {
+            println!("  {:>10}  {}", human_bytes(entry.size as f64), entry.name);
+        }
+        if let Some(reason) = &listing.stopped {
+            outln!(
+                "  \x1b[90m(listing stopped, as expected past the fetched part: {})\x1b[0m",
+                reason
+            );
+        }
+    }
+
+    if let Some(dir) = &preview.kept_in {
+        println!("\nKept in {}", dir.display());
+    }
+}
+
+/// Print a plan the way `-l` prints an NZB
+fn print_plan(plan: &Plan, verbose: bool) {
+    outln!("\n📄 {}", redact_nzb_path(&plan.nzb).display());
+    outln!("{}", "─".repeat(50));
+
+    println!("Output folder: {}", plan.output_dir.display());
+    if let Some(category) = &plan.category {
+        println!("Category: {}", category);
+    }
+    if let Some(sidecar) = &plan.sidecar {
+        println!("Per-NZB settings: {}", sidecar.display());
+    }
+    if let Some(id) = plan.duplicate_of {
+        println!("Already downloaded (#{}); nothing would be fetched", id);
+    } else {
+        if let Some(id) = plan.resumes {
+            println!("Resumes the unfinished download #{}", id);
+        }
+        let skipped: Vec<_> = plan.files.iter().filter(|f| !f.action.fetches()).collect();
+        println!(
+            "Would download: {} of {} files, {} of {}",
+            plan.files.len() - skipped.len(),
+            plan.files.len(),
+            human_bytes(plan.download_size as f64),
+            human_bytes(plan.total_size as f64)
+        );
+        if !skipped.is_empty() {
+            println!("Already there: {} files", skipped.len());
+        }
+        if let Some(seconds) = plan.estimated_seconds {
+            println!(
+                "Estimated time: {}",
+                progress::format_duration(std::time::Duration::from_secs(seconds))
+            );
+        }
+    }
+
+    // -v lists what would happen to every file
+    if verbose {
+        println!("\nFiles:");
+        for file in &plan.files {
+            let action = match file.action {
+                plan::FileAction::Download => "download",
+                plan::FileAction::Skip => "skip",
+                plan::FileAction::Resume => "resume",
+                plan::FileAction::Rename => "rename",
+                plan::FileAction::Overwrite => "overwrite",
+                plan::FileAction::Keep => "keep",
+            };
+            println!(
+                "  {:>4}  {:<8} {} ({}){}",
+                file.index,
+                action,
+                file.filename,
+                human_bytes(file.size as f64),
+                if file.name_known {
+                    ""
+                } else {
+                    " [name comes from the first segment]"
+                }
+            );
+        }
+    }
+
+    println!();
+    for check in &plan.checks {
+        let mark = match check.status {
+            CheckStatus::Pass => "\x1b[32m✓\x1b[0m",
+            CheckStatus::Warn => "\x1b[33m⚠\x1b[0m",
+            CheckStatus::Fail => "\x1b[31m✗\x1b[0m",
+        };
+        outln!("{} {}: \x1b[90m{}\x1b[0m", mark, check.name, check.detail);
+    }
+    if plan.viable {
+        println!("\nReady to download");
+    } else {
+        outln!("\n\x1b[1;31mNot ready to download\x1b[0m");
+    }
+}
+
+/// Run `test --speed` and print the report
+async fn run_speed_test(test: &SpeedTest, server: &UsenetConfig, verbose: bool) -> Result<()> {
+    let spinner = progress::create_spinner(format!(
+        "Measuring download speed over {} connections...",
+        server.connections
+    ));
+    let report = test.run().await;
+    spinner.finish_and_clear();
+    let report = match report {
+        Ok(report) => report,
+        Err(e) => {
+            errln!("❌ Speed test failed: {}", e);
+            return Err(e);
+        }
+    };
+
+    println!();
+    println!(
+        "Speed test: {} of {} connections for {:.1}s",
+        report.connections, report.connections_requested, report.seconds
+    );
+    println!(
+        "   Total: {:.1} MiB/s ({} in {} articles)",
+        report.mbps,
+        human_bytes(report.bytes as f64),
+        report.articles
+    );
+    let mut speeds: Vec<f64> = report.per_connection.iter().map(|c| c.mbps).collect();
+    speeds.sort_by(f64::total_cmp);
+    if let (Some(min), Some(max)) = (speeds.first(), speeds.last()) {
+        println!(
+            "   Per connection: {:.2} min, {:.2} median, {:.2} max MiB/s",
+            min,
+            speeds[speeds.len() / 2],
+            max
+        );
+    }
+    if verbose {
+        for (i, connection) in report.per_connection.iter().enumerate() {
+            println!(
+                "     #{:<3} {:>7.2} MiB/s  {} articles{}",
+                i + 1,
+                connection.mbps,
+                connection.articles,
+                connection
+                    .error
+                    .as_deref()
+                    .map(|e| format!("  ({})", e))
+                    .unwrap_or_default()
+            );
+        }
+    }
+    let latency = &report.connect_ms;
+    println!(
+        "   Connect + login: {:.0} ms p50, {:.0} ms p90, {:.0} ms p99, {:.0} ms max",
+        latency.p50, latency.p90, latency.p99, latency.max
+    );
+    if verbose {
+        let steps = &report.connect_steps_ms;
+        for (step, latency) in [
+            ("TCP connect", &steps.tcp),
+            ("TLS handshake", &steps.tls),
+            ("Greeting", &steps.greeting),
+            ("AUTHINFO", &steps.auth),
+        ] {
+            println!(
+                "     {:<14} {:.0} ms p50, {:.0} ms p90, {:.0} ms max",
+                step, latency.p50, latency.p90, latency.max
+            );
+        }
+        if let (Some(first), Some(later)) = (report.handshakes.first, report.handshakes.later) {
+            if server.ssl {
+                // Resumed sessions skip most of the handshake
+                println!(
+                    "     TLS handshake: {} ms for the first connection, {} ms average after",
+                    first.tls.as_millis(),
+                    later.tls.as_millis()
+                );
+            }
+        }
+    }
+    if report.missing > 0 {
+        outln!(
+            "   ⚠ {} test article{} missing on the server",
+            report.missing,
+            if report.missing == 1 {
+                " was"
+            } else {
+                "s were"
+            }
+        );
+    }
+    let stopped = report
+        .per_connection
+        .iter()
+        .filter(|c| c.error.is_some())
+        .count();
+    if stopped > 0 {
+        outln!(
+            "   ⚠ {} connection(s) stopped early (-v for details)",
+            stopped
+        );
+    }
+    for error in &report.connect_errors {
+        outln!("   ⚠ Connection failed to open: {}", error);
+    }
+    Ok(())
+}
+
+/// Handle download mode
+async fn handle_download_mode(
+    cli: &Cli,
+    config: Config,
+    retry: Option<&HistoryEntry>,
+    queue: Option<Queue>,
+    resumed: Vec<QueueEntry>,
+) -> Result<()> {
+    // With --json-progress a background task turns download events into NDJSON lines
+    let (events, event_task) = if cli.json_progress {
+        let (events, rx) = Events::channel();
+        let interval = std::time::Duration::from_millis(cli.progress_interval.max(1));
+        (events, Some(tokio::spawn(stream_progress(rx, interval))))
+    } else {
+        (Events::default(), None)
+    };
+
+    let shutdown = shutdown_on_signal();
+    let mut run = DownloadRun::start(cli, config, events, shutdown.clone()).await?;
+    run.retry = retry;
+    run.set_queue(queue);
+
+    // The whole queue is on disk before the first NZB starts
+    let mut jobs: Vec<(PathBuf, QueueEntry)> = resumed
+        .into_iter()
+        .map(|entry| (entry.nzb.clone(), entry))
+        .collect();
+    jobs.extend(cli.files.iter().cloned().zip(run.enqueue(&cli.files)));
+
+    // Process each NZB file
+    let run_start = std::time::Instant::now();
+    let mut summaries = Vec::new();
+    // NZBs found inside downloads join the end of the list
+    let mut next = 0;
+    while let Some((nzb_path, entry)) = jobs.get(next).cloned() {
+        if shutdown.is_triggered() {
+            break;
+        }
+        let outcome = run.download(&nzb_path, &entry).await?;
+        jobs.extend(outcome.nested);
+        summaries.push(outcome.summary);
+        next += 1;
+    }
+    if shutdown.is_triggered() {
+        run.downloader.close().await;
+    }
+
+    // NZBs a shutdown kept from starting are still queued for the next run
+    let summary = RunSummary::new(
+        summaries,
+        run.downloader.server_stats(),
+        run_start.elapsed(),
+    )
+    .with_not_started(jobs.len() - next)
+    .with_settings(&run.settings);
+    let (status, exit_code) = (summary.status, summary.exit_code);
+    let notifier = Notifier::new(&run.config.notifications, &run.config.download.user_agent);
+    if event_task.is_none() && !progress::is_hidden() {
+        print_report(&summary.nzbs);
+        if progress::mode() == OutputMode::Verbose {
+            print_completion(&summary.completion);
+        }
+    }
+
+    // Closing every sender ends the event stream, so the summary is the last line
+    drop(run);
+    if let Some(notifier) = &notifier {
+        notifier.notify(&summary).await;
+    }
+    if let Some(task) = event_task {
+        let tracker = task.await.unwrap_or_default();
+        let line = tracker.line(ProgressEvent::Summary { summary });
+        println!("{}", serde_json::to_string(&line)?);
+    } else if cli.json {
+        println!("{}", serde_json::to_string_pretty(&summary)?);
+    } else if !progress::is_hidden() && progress::plain_interval().is_none() {
+        // Terminal bell to notify completion
+        print!("\x07");
+    }
+
+    // The worst NZB decides the exit code (see `--help`)
+    if !status.succeeded() {
+        std::io::stdout().flush()?;
+        exit(exit_code);
+    }
+
+    Ok(())
+}
+
+/// Handle `watch`: download NZBs dropped into `dir` until stopped
+async fn handle_watch(
+    cli: &Cli,
+    config: Config,
+    dir: &Path,
+    interval: std::time::Duration,
+    queue: Option<Queue>,
+    resumed: Vec<QueueEntry>,
+) -> Result<()> {
+    use std::time::Duration;
+
+    let mut folder = WatchFolder::new(dir)?;
+    let _lock = lock_dir(folder.dir(), "watching", cli.wait_lock).await?;
+    let shutdown = shutdown_on_signal();
+    let mut backoff = Backoff::new(Duration::from_secs(5), Duration::from_secs(300));
+
+    // Keep trying to connect while the server is down
+    let mut run = loop {
+        match DownloadRun::start(cli, config.clone(), Events::default(), shutdown.clone()).await {
+            Ok(run) => break run,
+            Err(e) if e.is_retryable() => {
+                let delay = backoff.next_delay();
+                eprintln!(
+                    "Server unavailable ({}); retrying in {}",
+                    e,
+                    progress::format_duration(delay)
+                );
+                if sleep_or_stop(&shutdown, delay).await {
+                    return Ok(());
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    };
+    backoff.reset();
+    run.set_queue(queue);
+    let notifier = Notifier::new(&config.notifications, &config.download.user_agent);
+    run.after = AfterDownload::Sort {
+        completed: folder.destination(true),
+        failed: folder.destination(false),
+    };
+
+    // Dropping it when watch stops closes the port
+    let _api = match &run.queue {
+        Some(_) => {
+            let state = ApiState::new(
+                run.state.clone(),
+                run.history.clone().unwrap_or_else(History::in_memory),
+                config.api.token.clone(),
+            );
+            ApiServer::start(&config.api, state)
+                .await
+                .unwrap_or_else(|e| {
+                    degrade("api", e, "no HTTP API");
+                    None
+                })
+        }
+        None if !config.api.listen.is_empty() => {
+            degrade("api", "the download queue is unavailable", "no HTTP API");
+            None
+        }
+        None => None,
+    };
+
+    if !progress::is_hidden() {
+        progress::print_status(&format!(
+            "Watching {} for NZBs (Ctrl-C or SIGTERM to stop)",
+            folder.dir().display()
+        ));
+    }
+
+    // Resumed NZBs go first; any still waiting after an outage stay ahead
+    // of the folder, which may hold some of them too
+    let mut waiting: Vec<(PathBuf, QueueEntry)> = resumed
+        .into_iter()
+        .map(|entry| (entry.nzb.clone(), entry))
+        .collect();
+    // Whether a shutdown cut a download short
+    let mut cancelled = false;
+    loop {
+        let found = folder.poll()?;
+        let mut jobs = std::mem::take(&mut waiting);
+        // NZBs found inside this round's downloads, for the next round
+        let mut nested = Vec::new();
+        for (nzb_path, entry) in found.iter().cloned().zip(run.enqueue(&found)) {
+            if entry.id == 0 || !jobs.iter().any(|(_, job)| job.id == entry.id) {
+                jobs.push((nzb_path, entry));
+            }
+        }
+        // NZBs queued through the API
+        for entry in run.pending() {
+            if !jobs.iter().any(|(_, job)| job.id == entry.id) {
+                jobs.push((entry.nzb.clone(), entry));
+            }
+        }
+
+        for (index, (nzb_path, entry)) in jobs.iter().enumerate() {
+            if shutdown.is_triggered() {
+                break;
+            }
+            // Taken off the queue through the API while waiting
+            if !run.is_queued(entry) {
+                continue;
+            }
+            let started = std::time::Instant::now();
+            let mut outcome = run.download(nzb_path, entry).await?;
+            let name = nzb_path.file_name().unwrap_or_default().to_string_lossy();
+            nested.append(&mut outcome.nested);
+            if outcome.summary.status == RunStatus::Cancelled {
+                cancelled = true;
+                break;
+            }
+
+            // The NZB stays queued and is tried again after the wait
+            if outcome.transient {
+                waiting = jobs[index..].to_vec();
+                let delay = backoff.next_delay();
+                eprintln!(
+                    "{}: server unavailable; retrying in {}",
+                    name,
+                    progress::format_duration(delay)
+                );
+                sleep_or_stop(&shutdown, delay).await;
+                break;
+            }
+            backoff.reset();
+
+            let summary = &outcome.summary;
+            match progress::mode() {
+                OutputMode::Json => println!("{}", serde_json::to_string(summary)?),
+                // The download already printed its one line
+                OutputMode::Quiet => {}
+                OutputMode::Normal | OutputMode::Verbose => println!(
+                    "{}: {} ({} in {}), moved to {}/",
+                    name,
+                    summary.status.describe(),
+                    human_bytes(summary.total_size as f64),
+                    progress::format_duration(started.elapsed()),
+                    if summary.status.succeeded() {
+                        watch::COMPLETED_DIR
+                    } else {
+                        watch::FAILED_DIR
+                    }
+                ),
+            }
+            if !progress::is_hidden() {
+                print_report(std::slice::from_ref(summary));
+            }
+
+            // Sent in the background so a slow webhook doesn't hold up the queue
+            if let Some(notifier) = notifier.clone() {
+                let summary = RunSummary::new(
+                    vec![outcome.summary],
+                    run.state.server_stats(),
+                    started.elapsed(),
+                );
+                tokio::spawn(async move { notifier.notify(&summary).await });
+            }
+        }
+
+        // Nested NZBs start right away, after any left waiting
+        let follow_now = !nested.is_empty() && !shutdown.is_triggered();
+        waiting.append(&mut nested);
+        if !follow_now && sleep_or_stop(&shutdown, interval).await {
+            break;
+        }
+    }
+
+    run.downloader.close().await;
+    if cancelled {
+        std::io::stdout().flush()?;
+        exit(RunStatus::Cancelled.exit_code());
+    }
+    Ok(())
+}
+
+/// Ctrl-C or SIGTERM stops the run: nothing new starts and downloads in
+/// progress wind down (see [`Shutdown`]); a second one exits straight away
+fn shutdown_on_signal() -> Shutdown {
+    let shutdown = Shutdown::new();
+    let trigger = shutdown.clone();
+    tokio::spawn(async move {
+        wait_for_signal().await;
+        eprintln!("Stopping: finishing the articles in flight (Ctrl-C again to quit now)");
+        trigger.trigger();
+        wait_for_signal().await;
+        exit(RunStatus::Cancelled.exit_code());
+    });
+    shutdown
+}
+
+/// Toggles the speed cap off and back on at every SIGUSR1, until dropped
+struct TurboSignal(Option<tokio::task::JoinHandle<()>>);
+
+impl TurboSignal {
+    #[cfg_attr(not(unix), allow(unused_variables))]
+    fn start(limits: Limits, progress: Arc<TerminalProgress>) -> Self {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+            match signal(SignalKind::user_defined1()) {
+                Ok(mut toggles) => {
+                    return Self(Some(tokio::spawn(async move {
+                        while toggles.recv().await.is_some() {
+                            let note = limits.toggle_turbo();
+                            tracing::info!("{}", note);
+                            progress.on_message(&note);
+                        }
+                    })))
+                }
+                Err(e) => tracing::warn!("Not toggling the speed limit on SIGUSR1: {}", e),
+            }
+        }
+        Self(None)
+    }
+}
+
+impl Drop for TurboSignal {
+    fn drop(&mut self) {
+        if let Some(task) = &self.0 {
+            task.abort();
+        }
+    }
+}
+
+async fn wait_for_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        if let Ok(mut terminate) = signal(SignalKind::terminate()) {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = terminate.recv() => {}
+            }
+            return;
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// How many downloads deep `download.follow_nested_nzbs` follows NZBs found
+/// inside downloads
+const MAX_NESTED_DEPTH: u8 = 2;
+
+/// How often `--wait-lock` checks whether a folder is free
+const LOCK_POLL: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Lock `dir` against other instances, waiting for it under `--wait-lock`
+///
+/// A lock file that can't be created only warns: an unwritable folder is
+/// reported by whatever goes on to use it.
+async fn lock_dir(dir: &Path, activity: &'static str, wait: bool) -> Result<Option<DirLock>> {
+    let mut waiting = false;
+    loop {
+        match DirLock::try_acquire(dir) {
+            Ok(Some(lock)) => return Ok(Some(lock)),
+            Ok(None) => {}
+            Err(e) => {
+                tracing::warn!("Failed to lock {}: {}", dir.display(), e);
+                return Ok(None);
+            }
+        }
+        let locked = DownloadError::Locked {
+            dir: dir.to_path_buf(),
+            pid: DirLock::holder(dir),
+            activity,
+        };
+        if !wait {
+            return Err(locked.into());
+        }
+        if !waiting {
+            waiting = true;
+            if progress::is_hidden() {
+                tracing::info!("{}; waiting for it to finish", locked);
+            } else {
+                eprintln!("{}; waiting for it to finish", locked);
+            }
+        }
+        tokio::time::sleep(LOCK_POLL).await;
+    }
+}
+
+/// Sleep for `delay` unless a shutdown starts first; true when stopping
+async fn sleep_or_stop(shutdown: &Shutdown, delay: std::time::Duration) -> bool {
+    tokio::select! {
+        _ = tokio::time::sleep(delay) => shutdown.is_triggered(),
+        _ = shutdown.triggered() => true,
+    }
+}
+
+/// What happens to a local NZB file once it has been handled
+enum AfterDownload {
+    /// Leave it where it is
+    Keep,
+    /// `--move-processed`: into `.done/` once it finished downloading
+    MoveProcessed,
+    /// `watch`: into `completed/` on success, `failed/` otherwise
+    Sort { completed: PathBuf, failed: PathBuf },
+}
+
+/// How one NZB of a run ended
+struct NzbOutcome {
+    summary: DownloadSummary,
+    /// Stopped by a server or network problem that may clear up
+    transient: bool,
+    /// NZBs found in the download and queued after it
+    nested: Vec<(PathBuf, QueueEntry)>,
+}
+
+/// Everything the NZBs of one download run share
+struct DownloadRun<'a> {
+    cli: &'a Cli,
+    config: Config,
+    filter: FileFilter,
+    /// Output names from `--name-map`
+    name_map: Option<NameMap>,
+    downloader: Downloader,
+    events: Events,
+    /// What the API, metrics and notifications read, kept current by `events`
+    state: Arc<AppState>,
+    /// Progress bars shared by the downloader and post-processing
+    progress: Arc<TerminalProgress>,
+    history: Option<History>,
+    /// Where the run's NZBs are tracked until they finish
+    queue: Option<Arc<Queue>>,
+    /// Password prompts need a terminal and must never block quiet/JSON runs
+    interactive: bool,
+    /// History entry being retried
+    retry: Option<&'a HistoryEntry>,
+    after: AfterDownload,
+    /// `/metrics` listener, when `[metrics] listen` is set; open until the
+    /// run is dropped
+    _metrics: Option<MetricsServer>,
+    /// SIGUSR1 handler, until the run is dropped
+    _turbo: TurboSignal,
+    /// What the run is actually working with, for `-v` and the JSON summary
+    settings: EffectiveConfig,
+}
+
+impl<'a> DownloadRun<'a> {
+    /// Apply the CLI's download flags, prepare the temp directory and connect
+    async fn start(
+        cli: &'a Cli,
+        mut config: Config,
+        events: Events,
+        shutdown: Shutdown,
+    ) -> Result<Self> {
+        // Apply CLI settings to config
+        if cli.no_directories {
+            config.download.create_subfolders = false;
+        }
+
+        if cli.no_par2 {
+            config.post_processing.auto_par2_repair = false;
+        }
+
+        if cli.no_extract_rar {
+            config.post_processing.auto_extract_rar = false;
+        }
+
+        if cli.delete_rar_after_extract {
+            config.post_processing.delete_rar_after_extract = true;
+        }
+
+        if cli.delete_par2 {
+            config.post_processing.delete_par2_after_repair = true;
+        }
+
+        if let Some(script) = &cli.script {
+            config.post_processing.script = Some(script.clone());
+        }
+
+        if cli.keep_partial {
+            config.download.keep_partial = true;
+        }
+
+        if cli.show_nfo {
+            config.download.show_nfo = true;
+        }
+
+        if cli.strict {
+            config.download.strict = true;
+        }
+
+        // Update memory settings (from deprecated flags if present)
+        if let Some(memory_mb) = cli.memory_limit {
+            config.memory.max_buffer_mb = memory_mb as u64;
+        }
+        if let Some(buffer_kb) = cli.buffer_size {
+            config.memory.io_buffer_size = buffer_kb * 1024;
+        }
+        if let Some(concurrent) = cli.max_concurrent_files {
+            config.memory.max_concurrent_files = concurrent;
+        }
+
+        let filter = FileFilter::new(&cli.only, &cli.exclude, cli.case_sensitive)?
+            .with_selection(cli.select.clone());
+        let name_map = cli.name_map.as_deref().map(NameMap::load).transpose()?;
+
+        if let Some(temp_dir) = config.download.temp_dir.clone() {
+            match prepare_temp_dir(&temp_dir, &config.download.dir) {
+                Ok(()) => {
+                    let max_age = config.download.temp_max_age_hours;
+                    if !cli.keep_temp && max_age > 0 {
+                        cleanup_stale_temp_dirs(
+                            &temp_dir,
+                            std::time::Duration::from_secs(max_age * 3600),
+                        );
+                    }
+                }
+                // Files can be assembled in the download folder instead
+                Err(e) => {
+                    degrade("temp dir", e, "working in the download folder");
+                    config.download.temp_dir = None;
+                }
+            }
+        }
+
+        if let Some(reason) = notify::unavailable(&config.notifications) {
+            degrade("notifications", reason, "desktop notifications off");
+        }
+
+        let history = match History::open_default().and_then(|history| {
+            history.check_writable()?;
+            Ok(history)
+        }) {
+            Ok(history) => history,
+            Err(e) => {
+                degrade("history", e, "kept in memory for this run");
+                History::in_memory()
+            }
+        };
+
+        // Create downloader with spinner (hidden in quiet and JSON modes)
+        let mut builder = Downloader::builder(config.clone()).shutdown(shutdown.clone());
+        if config.usenet.auto_connections {
+            match history.tuned_connections() {
+                Ok(Some(connections)) => builder = builder.tuned_start(connections),
+                Ok(None) => {}
+                Err(e) => tracing::warn!("Failed to read download history: {}", e),
+            }
+        }
+        // The spinner lasts until the server has taken a login, so a
+        // refused one stops the run before any progress bar is drawn
+        let spinner = progress::create_spinner("Connecting to server...");
+        let downloader = builder.build().await;
+        let probe = match &downloader {
+            Ok(downloader) => Some(downloader.probe().await),
+            Err(_) => None,
+        };
+        spinner.finish_and_clear();
+        let downloader = downloader?;
+        let state = Arc::new(
+            AppState::new()
+                .with_stats(downloader.stats())
+                .with_limits(downloader.limits())
+                .with_shutdown(shutdown),
+        );
+        let events = events.with_state(state.clone());
+        let downloader = downloader.with_events(events.clone());
+        match probe {
+            Some(Ok(Some(probe))) => {
+                let t = probe.timings;
+                tracing::debug!(server = %probe.server, greeting = %probe.greeting, "Logged in");
+                if progress::mode() == OutputMode::Verbose {
+                    println!("Connected to {}: {}", probe.server, probe.greeting);
+                    println!(
+                        "   Connect: {} ms TCP, {} ms TLS, {} ms greeting, {} ms AUTHINFO",
+                        t.tcp.as_millis(),
+                        t.tls.as_millis(),
+                        t.greeting.as_millis(),
+                        t.auth.as_millis()
+                    );
+                    if let Some(skew) = probe.clock_skew {
+                        println!("   Clock: {} the server", clock::describe_skew(skew));
+                    }
+                }
+            }
+            Some(Err(e)) if e.class() == ErrorClass::Auth => return Err(e),
+            // The server may be back by the time the downloads ask for it
+            Some(Err(e)) => tracing::warn!("Could not connect to the server yet: {}", e),
+            _ => {}
+        }
+        let progress = Arc::new(
+            TerminalProgress::new(config.logging.progress).with_limits(downloader.limits()),
+        );
+        let downloader = downloader.with_progress(progress.clone());
+        let turbo = TurboSignal::start(downloader.limits(), progress.clone());
+
+        if let Some(path) = &cli.speed_log {
+            std::fs::write(path, format!("{}\n", SpeedTimeline::CSV_HEADER))?;
+        }
+
+        let metrics = match MetricsServer::start(&config.metrics, state.clone()).await {
+            Ok(metrics) => metrics,
+            Err(e) => {
+                degrade("metrics", e, "no /metrics endpoint");
+                None
+            }
+        };
+
+        let settings = EffectiveConfig::new(
+            &config,
+            &ConfigSource::locate(cli.config.as_deref()),
+            cli.overridden_keys(),
+        )
+        .with_runtime(
+            downloader
+                .stats()
+                .server_metrics()
+                .iter()
+                .map(|m| m.connections_max),
+            downloader.throttle().limit(),
+        )
+        .with_degraded(degraded());
+        tracing::debug!(
+            "Effective settings: {}",
+            serde_json::to_string(&settings).unwrap_or_default()
+        );
+        if progress::mode() == OutputMode::Verbose {
+            println!("Effective settings:");
+            print!("{}", settings);
+            println!();
+        }
+
+        Ok(Self {
+            cli,
+            config,
+            filter,
+            name_map,
+            downloader,
+            events,
+            state,
+            progress,
+            history: Some(history),
+            queue: None,
+            interactive: !cli.quiet && !cli.json && std::io::stdin().is_terminal(),
+            retry: None,
+            after: if cli.move_processed {
+                AfterDownload::MoveProcessed
+            } else {
+                AfterDownload::Keep
+            },
+            _metrics: metrics,
+            _turbo: turbo,
+            settings,
+        })
+    }
+
+    /// Download and post-process one NZB
+    ///
+    /// Problems with this NZB end up in the returned summary; only errors
+    /// that would stop every NZB (e.g. an unwritable download folder) are
+    /// returned as `Err`.
+    async fn download(&self, nzb_path: &Path, queued: &QueueEntry) -> Result<NzbOutcome> {
+        // Every log line of this NZB carries its name; the size is filled in
+        // once the files are known
+        let span = tracing::info_span!(
+            "nzb",
+            name = %redact_filename_password(&nzb_path.file_name().unwrap_or_default().to_string_lossy()),
+            size = tracing::field::Empty
+        );
+        let outcome = self.process(nzb_path, queued).instrument(span).await?;
+        // An NZB left queued by an outage is counted once it really finishes
+        if outcome.transient {
+            self.state.pause_nzb();
+        } else {
+            self.state.finish_nzb(FinishedNzb {
+                nzb: outcome.summary.nzb.clone(),
+                status: outcome.summary.status,
+                total_size: outcome.summary.total_size,
+            });
+        }
+
+        // A finished NZB is in the history by now and leaves the queue
+        let status = if outcome.transient {
+            QueueStatus::Pending
+        } else if outcome.summary.status.succeeded() {
+            QueueStatus::Done
         } else {
-            config.download.dir.clone()
+            QueueStatus::Failed
+        };
+        self.update_queue(queued, |entry| entry.status = status);
+        if let Some(queue) = &self.queue {
+            if let Err(e) = queue.prune() {
+                tracing::warn!("Failed to prune the download queue: {}", e);
+            }
+        }
+        Ok(outcome)
+    }
+
+    /// Track the run's NZBs in `queue`, which the API reads too
+    fn set_queue(&mut self, queue: Option<Queue>) {
+        self.queue = queue.map(Arc::new);
+        if let Some(queue) = &self.queue {
+            self.state.set_queue(queue.clone());
+        }
+    }
+
+    /// Queue NZBs for this run; without a queue file the entries aren't stored
+    fn enqueue(&self, nzbs: &[PathBuf]) -> Vec<QueueEntry> {
+        if let Some(queue) = &self.queue {
+            match queue.push(nzbs) {
+                Ok(entries) => return entries,
+                Err(e) => tracing::warn!("Failed to queue NZBs: {}", e),
+            }
+        }
+        nzbs.iter().map(|nzb| QueueEntry::new(nzb)).collect()
+    }
+
+    /// This run's entries waiting in the queue file, including any queued
+    /// through the API
+    fn pending(&self) -> Vec<QueueEntry> {
+        let Some(queue) = &self.queue else {
+            return Vec::new();
+        };
+        match queue.entries() {
+            Ok(entries) => entries
+                .into_iter()
+                .filter(|e| e.owner == std::process::id() && e.status == QueueStatus::Pending)
+                .collect(),
+            Err(e) => {
+                tracing::warn!("Failed to read the download queue: {}", e);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Whether a queued NZB is still in the queue file; entries that aren't
+    /// stored, and a queue that can't be read, count as queued
+    fn is_queued(&self, queued: &QueueEntry) -> bool {
+        let Some(queue) = self.queue.as_ref().filter(|_| queued.id > 0) else {
+            return true;
+        };
+        queue
+            .entries()
+            .map(|entries| entries.iter().any(|e| e.id == queued.id))
+            .unwrap_or(true)
+    }
+
+    /// Record progress on a queued NZB; failures only warn
+    fn update_queue(&self, queued: &QueueEntry, change: impl FnOnce(&mut QueueEntry)) {
+        let Some(queue) = self.queue.as_ref().filter(|_| queued.id > 0) else {
+            return;
+        };
+        if let Err(e) = queue.update(queued.id, change) {
+            tracing::warn!("Failed to update the download queue: {}", e);
+        }
+    }
+
+    async fn process(&self, nzb_path: &Path, queued: &QueueEntry) -> Result<NzbOutcome> {
+        self.events.send(DownloadEvent::NzbStarted {
+            nzb: nzb_path.to_path_buf(),
+        });
+        // A resumed or retried URL or stdin NZB comes back from the XML kept
+        // in the queue or history
+        let stored = match (&queued.xml, self.retry) {
+            (Some(xml), _) => Some((xml.clone(), queued.name.clone())),
+            (None, Some(entry)) => entry
+                .stored_xml()
+                .map(|xml| (xml, Some(entry.name.clone()))),
+            (None, None) => None,
+        };
+        let loaded = match stored {
+            Some((xml, name)) => LoadedNzb::from_xml(xml, name).and_then(|mut loaded| {
+                loaded.nzb.apply_filter(&self.filter)?;
+                Ok(loaded)
+            }),
+            None => load_nzb(nzb_path, &self.filter, &self.config).await,
+        };
+        let loaded = loaded.and_then(|mut loaded| {
+            if let Some(map) = &self.name_map {
+                loaded.nzb.apply_name_map(map)?;
+            }
+            Ok(loaded)
+        });
+        let LoadedNzb {
+            mut nzb,
+            name: nzb_name,
+            xml: fetched_xml,
+            password: filename_password,
+        } = match loaded {
+            Ok(loaded) => loaded,
+            Err(e) => return Ok(self.load_failed(nzb_path, &e.with_nzb_path(nzb_path))),
+        };
+        if filename_password.is_some() {
+            tracing::info!("The NZB's file name carries an archive password");
+        }
+
+        // Recovery volumes alone are usually the wrong half of a split post
+        if let Some(files) = nzb.par2_only() {
+            if !self.allow_par2_only(&nzb_name, files) {
+                return Ok(self.skip_par2_only(nzb_path, &nzb_name, &nzb, files, fetched_xml));
+            }
+        }
+
+        // A post downloaded before is skipped; an unfinished attempt carries on
+        // in its folder, where complete files are kept
+        let resume_dir = match self.previous_download(&nzb) {
+            Some(entry) if entry.status.succeeded() && entry.has_files() => {
+                return Ok(self.skip_duplicate(nzb_path, &nzb_name, &entry));
+            }
+            // A run that died part way left its files where the queue says
+            _ if queued.output_dir.as_deref().is_some_and(Path::is_dir) => {
+                queued.output_dir.clone()
+            }
+            Some(entry) if !entry.status.succeeded() => {
+                let dir = entry.output_dir.clone().filter(|dir| dir.is_dir());
+                if let Some(dir) = &dir {
+                    let note = format!(
+                        "{} was {} on {} (#{}); resuming in {}",
+                        nzb_name,
+                        entry.status.describe(),
+                        entry.date(),
+                        entry.id,
+                        dir.display()
+                    );
+                    if progress::is_hidden() {
+                        tracing::info!("{}", note);
+                    } else {
+                        eprintln!("{}", note);
+                    }
+                }
+                dir
+            }
+            _ => None,
+        };
+
+        // Per-NZB overrides from a `<name>.nzb.toml` sidecar; a bad one only fails this NZB
+        let sidecar = match fetched_xml {
+            Some(_) => Ok(None),
+            None => NzbOverrides::load(nzb_path),
+        };
+        let sidecar = match sidecar {
+            Ok(sidecar) => sidecar,
+            Err(e) => return Ok(self.load_failed(nzb_path, &e)),
+        };
+        let mut nzb_config = match &sidecar {
+            Some(overrides) => match overrides.apply(&self.config) {
+                Ok(merged) => merged,
+                Err(e) => return Ok(self.load_failed(nzb_path, &e)),
+            },
+            None => self.config.clone(),
         };
+        // A nested NZB's folder goes inside the download it was found in
+        if let Some(dir) = &queued.parent_dir {
+            nzb_config.download.dir = dir.clone();
+        }
+        if let Some(overrides) = &sidecar {
+            tracing::info!(
+                "Using per-NZB settings from {}",
+                redact_nzb_path(&overrides.path).display()
+            );
+        }
+
+        // Create the output directory `download.folder_template` names
+        let output_dir = match resume_dir {
+            Some(dir) => dir,
+            None if nzb_config.download.create_subfolders => {
+                let fields = FolderFields::new(
+                    &nzb_name,
+                    &nzb,
+                    sidecar.as_ref().and_then(|o| o.category.as_deref()),
+                );
+                nzb_config
+                    .download
+                    .dir
+                    .join(fields.folder(&nzb_config.download.folder_template))
+            }
+            None => nzb_config.download.dir.clone(),
+        };
+
+        if let Some(age) = nzb.oldest_age_days() {
+            if progress::is_hidden() {
+                tracing::info!(
+                    "{}: post age: {}",
+                    redact_nzb_path(nzb_path).display(),
+                    format_age(age)
+                );
+            } else {
+                eprintln!("Post age: {}", format_age(age));
+            }
+        }
+
+        // Segments the NZB never listed mean failure before a byte is fetched
+        let warnings = plan::retention_warning(&nzb_config, &nzb)
+            .into_iter()
+            .chain(nzb.validate().gap_warnings());
+        for warning in warnings {
+            if progress::is_hidden() {
+                tracing::warn!("{}: {}", redact_nzb_path(nzb_path).display(), warning);
+            } else {
+                errln!(
+                    "\x1b[1;31m⚠ {}: {}\x1b[0m",
+                    redact_nzb_path(nzb_path).display(),
+                    warning
+                );
+            }
+        }
 
-        std::fs::create_dir_all(&output_dir)?;
+        // A folder that can't be created or written to is reported by the
+        // download's own check below, which fails just this NZB
+        let _ = std::fs::create_dir_all(&output_dir);
+        // Summary and history record the folder as resolved
+        let output_dir = if output_dir.is_relative() {
+            std::env::current_dir()
+                .map(|cwd| cwd.join(&output_dir))
+                .unwrap_or(output_dir)
+        } else {
+            output_dir
+        };
+        // Held until this NZB is done with, post-processing included
+        let lock = match lock_dir(&output_dir, "downloading into", self.cli.wait_lock).await {
+            Ok(lock) => lock,
+            Err(e) => {
+                if !self.cli.json {
+                    eprintln!("Skipping {}: {}", redact_nzb_path(nzb_path).display(), e);
+                }
+                return Ok(NzbOutcome {
+                    summary: DownloadSummary::failed(nzb_path, Some(&output_dir), &e),
+                    transient: false,
+                    nested: Vec::new(),
+                });
+            }
+        };
+        self.update_queue(queued, |entry| {
+            entry.status = QueueStatus::Active;
+            entry.name = Some(nzb_name.clone());
+            entry.output_dir = Some(output_dir.clone());
+            entry.xml = fetched_xml.clone();
+        });
+
+        if let Some(xml) = fetched_xml
+            .as_deref()
+            .filter(|_| nzb_config.download.keep_nzb)
+        {
+            let saved = output_dir.join(format!("{}.nzb", nzb_name));
+            if let Err(e) = std::fs::write(&saved, xml) {
+                tracing::warn!("Failed to save {}: {}", saved.display(), e);
+            }
+        }
 
         // Update config for this download
-        let mut download_config = config.clone();
+        let mut download_config = nzb_config.clone();
         download_config.download.dir = output_dir.clone();
-        download_config.download.force_redownload = cli.force;
-
-        // Track timing for JSON output
-        let download_start = std::time::Instant::now();
+        download_config.download.force_redownload = self.cli.force;
+        download_config.download.temp_dir = nzb_config
+            .download
+            .temp_dir
+            .as_deref()
+            .map(|temp_dir| nzb_temp_dir(temp_dir, &nzb_name));
+
+        // Local NZBs are recorded by absolute path so `history` stays useful
+        // elsewhere; a retry already has one
+        let mut history_source = match NzbSource::from_arg(nzb_path) {
+            NzbSource::File(path) if self.retry.is_none() => {
+                std::fs::canonicalize(&path).unwrap_or(path)
+            }
+            _ => nzb_path.to_path_buf(),
+        };
 
         // Download the NZB with updated config
-        match downloader.download_nzb(&nzb, download_config.clone()).await {
-            Ok((results, _progress_bar)) => {
-                let download_time = download_start.elapsed();
-
-                if cli.print_names {
-                    for result in &results {
+        let phases = PhaseRecorder::new();
+        let mut download_phase = phases.start(Phase::Download);
+        match self
+            .downloader
+            .download_files(nzb.files(), download_config.clone())
+            .await
+        {
+            Ok(download) => {
+                let results = &download.results;
+                // PAR2 files found by their contents join their sets
+                nzb.adopt_par2_names(results);
+                let segments_failed: usize = results.iter().map(|r| r.segments_failed).sum();
+                download_phase
+                    .count("files", results.len())
+                    .count("bytes", results.iter().map(|r| r.size).sum::<u64>())
+                    .count("segments_failed", segments_failed);
+                download_phase.finish(if segments_failed > 0 {
+                    PhaseStatus::Incomplete
+                } else {
+                    PhaseStatus::Success
+                });
+                let post_start = std::time::Instant::now();
+                // Files never fetched: filtered out, duplicates, no segments
+                let skipped: Vec<SkippedFile> = nzb
+                    .filtered_files()
+                    .into_iter()
+                    .chain(download.skipped.iter().cloned())
+                    .collect();
+                let expected_files = nzb.files().len() - download.skipped.len();
+
+                if self.cli.print_names && !self.cli.json {
+                    for result in results {
                         println!("{}", result.path.display());
                     }
                 }
 
                 // Post-processing
-                let mut post_result = PostProcessingResult {
-                    par2_verified: false,
-                    par2_repaired: false,
-                    rar_extracted: false,
-                    files_renamed: 0,
+                let mut post_result = PostProcessingResult::default();
+                let mut post_failed = false;
+                let mut failures = download.failures.clone();
+
+                // Read once for the re-fetch, the shortfall check and post-processing
+                let par2_index = Par2Index::read(&output_dir);
+
+                // Re-download segments behind damaged PAR2 blocks before repairing;
+                // block-hashing is only worth it when segments were lost or broken
+                let mut segments_refetched = 0;
+                if download_config.post_processing.refetch_damaged_segments
+                    && download_config.post_processing.auto_par2_repair
+                    && results
+                        .iter()
+                        .any(|r| r.segments_failed > 0 || r.segments_corrupt > 0)
+                {
+                    let damaged = par2_index.damaged_files(&output_dir);
+                    if !damaged.is_empty() {
+                        self.events
+                            .send(DownloadEvent::PostProcessing(PostProcessPhase::Refetch));
+                        let mut timer = phases.start(Phase::Refetch);
+                        match self
+                            .downloader
+                            .refetch_ranges(results, &damaged, &download.retry_budget)
+                            .await
+                        {
+                            Ok(count) => {
+                                timer.count("segments", count);
+                                timer.finish(PhaseStatus::Success);
+                                segments_refetched = count;
+                                if count > 0 && !progress::is_hidden() {
+                                    progress::print_status(&format!(
+                                        "  └─ \x1b[32m✓ Re-fetched {} damaged segment{}\x1b[0m",
+                                        count,
+                                        if count == 1 { "" } else { "s" }
+                                    ));
+                                }
+                            }
+                            Err(e) => tracing::warn!("Failed to re-fetch damaged segments: {}", e),
+                        }
+                    }
+                }
+
+                // Sets that lost more blocks than they can repair; after a
+                // re-fetch the failed segments no longer tell
+                let shortfalls = if download_config.post_processing.auto_par2_repair
+                    && segments_refetched == 0
+                {
+                    par2_shortfalls(&nzb, results, &par2_index)
+                } else {
+                    Vec::new()
                 };
+                for shortfall in &shortfalls {
+                    tracing::warn!("Not enough PAR2 recovery: {}", shortfall.describe());
+                    if !progress::is_hidden() {
+                        progress::print_status(&format!(
+                            "  \x1b[33m⚠ {} — repair will fail\x1b[0m",
+                            shortfall.describe()
+                        ));
+                    }
+                }
 
-                if config.post_processing.auto_par2_repair
-                    || config.post_processing.auto_extract_rar
+                if download_config.post_processing.auto_par2_repair
+                    || download_config.post_processing.auto_extract_rar
+                    || download_config.post_processing.verify_media
                 {
+                    // The NZB file name's password is tried first, then a
+                    // sidecar's, then the NZB's own
+                    let passwords = filename_password
+                        .iter()
+                        .chain(sidecar.iter().filter_map(|o| o.password.as_ref()))
+                        .map(|p| p.expose().to_string())
+                        .chain(nzb.passwords().iter().cloned())
+                        .collect();
                     let processor = PostProcessor::new(
                         download_config.post_processing.clone(),
                         download_config.tuning.large_file_threshold,
-                    );
-                    if let Err(e) = processor.process_downloads(&results).await {
-                        if !cli.json {
-                            eprintln!("Post-processing error: {}", e);
+                    )
+                    .with_passwords(passwords)
+                    .interactive(self.interactive)
+                    .with_temp_dir(download_config.download.temp_dir.clone())
+                    .with_fsync(download_config.download.fsync)
+                    // Already checked when the download started
+                    .with_permissions(
+                        Permissions::from_config(&download_config.download).unwrap_or_default(),
+                    )
+                    .with_events(self.events.clone())
+                    .with_progress(self.progress.clone())
+                    .with_par2_shortfalls(shortfalls)
+                    .with_par2_index(par2_index)
+                    .with_mapped_names(nzb.mapped_names())
+                    .with_phases(phases.clone());
+                    match processor
+                        .process_downloads(&Manifest::from_results(results))
+                        .await
+                    {
+                        Ok(outcome) => post_result = outcome,
+                        Err(e) => {
+                            post_failed = true;
+                            failures.push(Failure::from_error(None, &e));
                         }
-                    } else {
-                        post_result.par2_verified = config.post_processing.auto_par2_repair;
-                        post_result.rar_extracted = config.post_processing.auto_extract_rar;
                     }
                 }
 
-                // Output results
-                if cli.json {
-                    let total_size: u64 = results.iter().map(|r| r.size).sum();
-                    let summary = DownloadSummary {
-                        nzb: nzb_path.clone(),
+                post_result.segments_refetched = segments_refetched;
+
+                // What PAR2 couldn't complete is deleted unless asked to keep it
+                let repaired = download_config.post_processing.auto_par2_repair
+                    && (post_result.par2_repaired || post_result.par2_verified);
+                let mut cleanup_phase = phases.start(Phase::Cleanup);
+                let partial_files = Downloader::cleanup_partial_files(
+                    &output_dir,
+                    if repaired { &[] } else { results },
+                    download_config.download.keep_partial,
+                )
+                .await;
+                cleanup_phase
+                    .count("junk_removed", post_result.junk_removed)
+                    .count("partial_removed", partial_files.removed.len())
+                    .count("partial_kept", partial_files.kept.len());
+                cleanup_phase.finish(PhaseStatus::Success);
+
+                let total_size: u64 = results.iter().map(|r| r.size).sum();
+
+                // External post-processing script
+                let script_outcome = if let Some(script) = &download_config.post_processing.script {
+                    self.events
+                        .send(DownloadEvent::PostProcessing(PostProcessPhase::Script));
+                    let context = ScriptContext {
+                        nzb_name: nzb_name.clone(),
                         output_dir: output_dir.clone(),
                         success: results.iter().all(|r| r.segments_failed == 0),
-                        total_size,
-                        download_time_seconds: download_time.as_secs_f64(),
-                        average_speed_mbps: if download_time.as_secs() > 0 {
-                            (total_size as f64 / 1024.0 / 1024.0) / download_time.as_secs_f64()
-                        } else {
-                            0.0
-                        },
-                        files: results
-                            .iter()
-                            .map(|r| DownloadFileResult {
-                                filename: r.filename.clone(),
-                                path: r.path.clone(),
-                                size: r.size,
-                                segments_downloaded: r.segments_downloaded,
-                                segments_failed: r.segments_failed,
-                                success: r.segments_failed == 0,
-                            })
-                            .collect(),
-                        post_processing: post_result,
+                        bytes: total_size,
+                        category: sidecar
+                            .as_ref()
+                            .and_then(|o| o.category.clone())
+                            .or_else(|| nzb.category().map(str::to_string)),
+                        failed_segments: results.iter().map(|r| r.segments_failed).sum(),
                     };
-                    println!("{}", serde_json::to_string_pretty(&summary)?);
+                    let timeout = std::time::Duration::from_secs(
+                        download_config.post_processing.script_timeout,
+                    );
+                    let script_phase = phases.start(Phase::Script);
+                    match run_script(script, timeout, &context, &post_result).await {
+                        Ok(outcome) => {
+                            script_phase.finish(if outcome.success() {
+                                PhaseStatus::Success
+                            } else {
+                                PhaseStatus::Failed
+                            });
+                            Some(outcome)
+                        }
+                        Err(e) => {
+                            post_failed = true;
+                            failures.push(Failure::from_error(None, &e));
+                            None
+                        }
+                    }
                 } else {
-                    print_final_summary(&nzb, &results, &output_dir);
+                    None
+                };
+
+                let mut status = RunStatus::of_download(
+                    results,
+                    expected_files,
+                    &post_result,
+                    script_outcome.as_ref(),
+                );
+                if post_failed {
+                    status = status.max(RunStatus::PostProcessingFailed);
                 }
 
-                all_results.extend(results);
-            }
-            Err(e) => {
-                if cli.json {
-                    let error_output = ErrorOutput::from_error(&e);
-                    println!("{}", serde_json::to_string_pretty(&error_output)?);
+                // `--strict`: anything short of perfect fails the NZB and marks its folder
+                let violations = if download_config.download.strict {
+                    strict::violations(&strict::Outcome {
+                        results,
+                        expected_files,
+                        post_processing: &post_result,
+                        script: script_outcome.as_ref(),
+                        post_failed,
+                    })
+                } else {
+                    Vec::new()
+                };
+                status = status.max(strict::status(&violations));
+                let output_dir = if violations.is_empty() {
+                    output_dir
+                } else {
+                    let reasons: Vec<String> = violations.iter().map(Violation::describe).collect();
+                    self.mark_failed(&output_dir, &nzb_config, &reasons)
+                };
+
+                // Finished files into the library, in the same subfolder
+                let linked_files = match &download_config.post_processing.link_dir {
+                    Some(link_dir) if status == RunStatus::Success => {
+                        self.link_into(link_dir, &output_dir, &nzb_config).await
+                    }
+                    _ => Vec::new(),
+                };
+
+                let post_time = post_start.elapsed();
+                let nfo_file = nfo::find(&output_dir);
+                // Why articles went missing, when it left the NZB short
+                let cause = FailureCause::classify(results).filter(|_| !status.succeeded());
+
+                // Output results: the full block, or one line per NZB with --quiet
+                if progress::mode() == OutputMode::Quiet {
+                    println!(
+                        "{}: {}{} ({} in {})",
+                        nzb_name,
+                        status.describe(),
+                        cause
+                            .map(|cause| format!(", {}", cause.describe()))
+                            .unwrap_or_default(),
+                        human_bytes(total_size as f64),
+                        output_dir.display()
+                    );
+                } else if !progress::is_hidden() {
+                    print_final_summary(
+                        &download,
+                        &skipped,
+                        &output_dir,
+                        download_config.download.min_file_size,
+                        post_time,
+                    );
+                    if progress::mode() == OutputMode::Verbose {
+                        progress::print_status(&format!(
+                            "  \x1b[90m└─ Phases: {}\x1b[0m",
+                            phases::describe(&phases.records())
+                        ));
+                    }
+                    if let Some(cause) = cause {
+                        progress::print_status(&format!(
+                            "  \x1b[90m└─\x1b[0m \x1b[33m⚠ Failure cause: {} ({})\x1b[0m",
+                            cause.describe(),
+                            cause.advice()
+                        ));
+                    }
+                    for archive in &post_result.password_required {
+                        progress::print_status(&format!(
+                            "  \x1b[90m└─\x1b[0m \x1b[31m✗ Not extracted: {} (password required)\x1b[0m",
+                            archive
+                                .file_name()
+                                .map(|n| n.to_string_lossy().to_string())
+                                .unwrap_or_default()
+                        ));
+                    }
+                    for shortfall in &post_result.space_shortfalls {
+                        progress::print_status(&format!(
+                            "  \x1b[90m└─\x1b[0m \x1b[31m✗ Not extracted: {} ({} more free space needed)\x1b[0m",
+                            shortfall
+                                .archive
+                                .file_name()
+                                .map(|n| n.to_string_lossy().to_string())
+                                .unwrap_or_default(),
+                            human_bytes(shortfall.missing() as f64)
+                        ));
+                    }
+                    if let Some(outcome) = script_outcome.as_ref().filter(|o| !o.success()) {
+                        print_script_warning(outcome);
+                    }
+                    print_partial_files(&partial_files);
+                    if download.retry_budget.is_exhausted() {
+                        progress::print_status(
+                            "  \x1b[90m└─\x1b[0m \x1b[33m⚠ Retry budget exhausted; remaining failures are final\x1b[0m",
+                        );
+                    }
+                    if let Some(link_dir) = download_config
+                        .post_processing
+                        .link_dir
+                        .as_ref()
+                        .filter(|_| !linked_files.is_empty())
+                    {
+                        progress::print_status(&format!(
+                            "  \x1b[90m└─\x1b[0m \x1b[32m✓ {} {} file{} into {}\x1b[0m",
+                            match download_config.post_processing.link_mode {
+                                LinkMode::Hardlink | LinkMode::Symlink => "Linked",
+                                LinkMode::Copy => "Copied",
+                                LinkMode::Move => "Moved",
+                            },
+                            linked_files.len(),
+                            if linked_files.len() == 1 { "" } else { "s" },
+                            link_dir.display()
+                        ));
+                    }
+                    for violation in &violations {
+                        progress::print_status(&format!(
+                            "  \x1b[90m└─\x1b[0m \x1b[31m✗ Strict: {}\x1b[0m",
+                            violation.describe()
+                        ));
+                    }
+                    if let Some(nfo_file) = nfo_file
+                        .as_ref()
+                        .filter(|_| download_config.download.show_nfo)
+                    {
+                        print_nfo(nfo_file);
+                    }
+                }
+
+                // Under `watch`, segments lost to a server outage leave the NZB queued
+                let transient = matches!(self.after, AfterDownload::Sort { .. })
+                    && status >= RunStatus::Incomplete
+                    && !self.server_reachable().await;
+                if !transient {
+                    if let Some(moved) = self.set_aside(nzb_path, status) {
+                        history_source = std::fs::canonicalize(&moved).unwrap_or(moved);
+                    }
+                }
+
+                if let Some(work_dir) = &download_config.download.temp_dir {
+                    if !self.cli.keep_temp {
+                        if let Err(e) = std::fs::remove_dir_all(work_dir) {
+                            tracing::debug!("Failed to remove {}: {}", work_dir.display(), e);
+                        }
+                    }
+                }
+
+                let summary = DownloadSummary::new(
+                    nzb_path,
+                    &output_dir,
+                    status,
+                    results,
+                    download.download_time,
+                    post_result,
+                    script_outcome,
+                )
+                .with_failures(failures)
+                .with_speed(&download.speed)
+                .with_partial_files(partial_files)
+                .with_violations(violations)
+                .with_linked_files(linked_files)
+                .with_retry_budget_exhausted(download.retry_budget.is_exhausted())
+                .with_missing_groups(download.missing_groups.clone())
+                .with_skipped(&skipped)
+                .with_nfo(nfo_file)
+                .with_phases(phases.records())
+                .with_filename_password(filename_password.is_some());
+                if let Some(path) = &self.cli.speed_log {
+                    log_speed(path, &nzb_name, &download.speed);
+                }
+                let history_id = record_history(
+                    self.history.as_ref(),
+                    HistoryEntry::new(&summary, &history_source, &nzb_name, &nzb)
+                        .with_xml(fetched_xml.clone())
+                        .with_connections(
+                            self.downloader
+                                .connections()
+                                .and_then(ConnectionLimit::settled),
+                        )
+                        .with_parent(queued.parent),
+                );
+                let nested = if transient {
+                    Vec::new()
                 } else {
-                    eprintln!("Download failed for {}: {}", nzb_path.display(), e);
-                    if !cli.keep_partial {
-                        eprintln!("Note: Partial files may remain. Use --keep-partial to explicitly keep them.");
+                    let kept = output_dir.join(format!("{}.nzb", nzb_name));
+                    let found = nested_nzbs(&output_dir, &[nzb_path.to_path_buf(), kept]);
+                    self.follow_nested(queued, history_id, &output_dir, &found, &nzb_config)
+                };
+                // Folders the template created for a run that produced nothing
+                drop(lock);
+                remove_empty_folders(&nzb_config.download.dir, &output_dir);
+                Ok(NzbOutcome {
+                    summary,
+                    transient,
+                    nested,
+                })
+            }
+            Err(AbortedDownload { error: e, download }) => {
+                // A server outage or a shutdown leaves the NZB where it is to
+                // be tried again
+                let cancelled = e.run_status() == RunStatus::Cancelled;
+                let transient = e.is_retryable() || cancelled;
+                if !transient {
+                    if let Some(moved) = self.set_aside(nzb_path, RunStatus::Aborted) {
+                        history_source = std::fs::canonicalize(&moved).unwrap_or(moved);
                     }
                 }
+                // What it got to goes as a finished download's would, except
+                // that a shutdown keeps it for the next run
+                let partial_files = Downloader::cleanup_partial_files(
+                    &output_dir,
+                    &download.results,
+                    cancelled || download_config.download.keep_partial,
+                )
+                .await;
+                // Aborted before a file was written: no empty `2024/05/...` left behind
+                drop(lock);
+                remove_empty_folders(&nzb_config.download.dir, &output_dir);
+                let output_dir = if nzb_config.download.strict && !cancelled && output_dir.exists()
+                {
+                    self.mark_failed(&output_dir, &nzb_config, &[e.to_string()])
+                } else {
+                    output_dir
+                };
+                download_phase
+                    .count("files", download.results.len())
+                    .count("bytes", download.total_size());
+                drop(download_phase);
+                let summary = DownloadSummary::failed(nzb_path, Some(&output_dir), &e)
+                    .with_partial_download(&download)
+                    .with_partial_files(partial_files)
+                    .with_phases(phases.records())
+                    .with_filename_password(filename_password.is_some());
+                record_history(
+                    self.history.as_ref(),
+                    HistoryEntry::new(&summary, &history_source, &nzb_name, &nzb)
+                        .with_xml(fetched_xml.clone())
+                        .with_parent(queued.parent),
+                );
+                if cancelled && !self.cli.json {
+                    eprintln!(
+                        "Stopped {}; run again or use --resume-queue to finish it",
+                        redact_nzb_path(nzb_path).display()
+                    );
+                } else if !self.cli.json {
+                    eprintln!(
+                        "Download failed for {}: {}",
+                        redact_nzb_path(nzb_path).display(),
+                        e
+                    );
+                }
+                Ok(NzbOutcome {
+                    summary,
+                    transient,
+                    nested: Vec::new(),
+                })
             }
         }
     }
 
-    // Terminal bell to notify completion (skip in quiet/json mode)
-    if !cli.quiet && !cli.json {
-        print!("\x07");
+    /// Queue the NZBs `found` in a finished download with
+    /// `download.follow_nested_nzbs`, or just list them
+    ///
+    /// Queued ones download into folders inside `output_dir`, up to
+    /// [`MAX_NESTED_DEPTH`] downloads deep, and are recorded in the history
+    /// under `parent`.
+    fn follow_nested(
+        &self,
+        queued: &QueueEntry,
+        parent: Option<u64>,
+        output_dir: &Path,
+        found: &[PathBuf],
+        nzb_config: &Config,
+    ) -> Vec<(PathBuf, QueueEntry)> {
+        if found.is_empty() {
+            return Vec::new();
+        }
+        let names = found
+            .iter()
+            .map(|path| {
+                path.strip_prefix(output_dir)
+                    .unwrap_or(path)
+                    .display()
+                    .to_string()
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let plural = if found.len() == 1 { "" } else { "s" };
+
+        if !nzb_config.download.follow_nested_nzbs {
+            tracing::info!(
+                "Found {} NZB{} in {}: {}",
+                found.len(),
+                plural,
+                output_dir.display(),
+                names
+            );
+            if self.interactive {
+                progress::print_status(&format!(
+                    "  \x1b[90m└─\x1b[0m \x1b[33m⚠ Found NZB{} inside: {} (set download.follow_nested_nzbs to download them)\x1b[0m",
+                    plural, names
+                ));
+            }
+            return Vec::new();
+        }
+        if queued.depth >= MAX_NESTED_DEPTH {
+            tracing::warn!(
+                "Not following NZB{} in {}: already {} downloads deep ({})",
+                plural,
+                output_dir.display(),
+                queued.depth,
+                names
+            );
+            if !progress::is_hidden() {
+                progress::print_status(&format!(
+                    "  \x1b[90m└─\x1b[0m \x1b[33m⚠ Not following NZB{} nested {} deep: {}\x1b[0m",
+                    plural,
+                    MAX_NESTED_DEPTH + 1,
+                    names
+                ));
+            }
+            return Vec::new();
+        }
+        let note = format!("Queued nested NZB{}: {}", plural, names);
+        tracing::info!("{}", note);
+        if !progress::is_hidden() {
+            progress::print_status(&format!("  \x1b[90m└─\x1b[0m \x1b[32m✓ {}\x1b[0m", note));
+        }
+
+        let depth = queued.depth + 1;
+        found
+            .iter()
+            .cloned()
+            .zip(self.enqueue(found))
+            .map(|(path, mut entry)| {
+                entry.parent = parent;
+                entry.depth = depth;
+                entry.parent_dir = Some(output_dir.to_path_buf());
+                self.update_queue(&entry, |stored| {
+                    stored.parent = parent;
+                    stored.depth = depth;
+                    stored.parent_dir = Some(output_dir.to_path_buf());
+                });
+                (path, entry)
+            })
+            .collect()
     }
 
-    Ok(())
+    /// Report an NZB that failed to load
+    fn load_failed(&self, nzb_path: &Path, error: &DlNzbError) -> NzbOutcome {
+        if !self.cli.json {
+            eprintln!(
+                "Failed to load {}: {}",
+                redact_nzb_path(nzb_path).display(),
+                error
+            );
+        }
+        let summary = DownloadSummary::failed(nzb_path, None, error);
+        // An NZB that lists nothing was read fine, so it's kept on record
+        if error.run_status() == RunStatus::Empty {
+            let name = nzb_path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "download".to_string());
+            let source = std::fs::canonicalize(nzb_path).unwrap_or_else(|_| nzb_path.to_path_buf());
+            record_history(
+                self.history.as_ref(),
+                HistoryEntry::unloaded(&summary, &source, &name),
+            );
+        }
+        self.set_aside(nzb_path, error.run_status());
+        NzbOutcome {
+            summary,
+            transient: error.is_retryable(),
+            nested: Vec::new(),
+        }
+    }
+
+    /// Warn about an NZB of PAR2 files alone, and whether to download it
+    /// anyway: with `--allow-par2-only`, or if the user says so
+    fn allow_par2_only(&self, nzb_name: &str, files: usize) -> bool {
+        let warning = format!(
+            "{}: only PAR2 recovery files ({}), nothing for them to repair",
+            nzb_name, files
+        );
+        if progress::is_hidden() {
+            tracing::warn!("{}", warning);
+        } else {
+            errln!("\x1b[1;33m⚠ {}\x1b[0m", warning);
+        }
+        if self.cli.allow_par2_only || !self.interactive {
+            return self.cli.allow_par2_only;
+        }
+        let mut allowed = false;
+        self.progress.suspend(&mut || {
+            allowed = inquire::Confirm::new("Download them anyway?")
+                .with_default(false)
+                .prompt()
+                .unwrap_or(false);
+        });
+        allowed
+    }
+
+    /// Report an NZB of PAR2 files alone that wasn't downloaded
+    fn skip_par2_only(
+        &self,
+        nzb_path: &Path,
+        nzb_name: &str,
+        nzb: &Nzb,
+        files: usize,
+        xml: Option<String>,
+    ) -> NzbOutcome {
+        let error: DlNzbError = NzbError::Par2Only { files }.into();
+        if !self.cli.json {
+            eprintln!(
+                "Skipping {}: {}",
+                redact_nzb_path(nzb_path).display(),
+                error
+            );
+        }
+        let summary = DownloadSummary::failed(nzb_path, None, &error);
+        let source = std::fs::canonicalize(nzb_path).unwrap_or_else(|_| nzb_path.to_path_buf());
+        record_history(
+            self.history.as_ref(),
+            HistoryEntry::new(&summary, &source, nzb_name, nzb).with_xml(xml),
+        );
+        self.set_aside(nzb_path, error.run_status());
+        NzbOutcome {
+            summary,
+            transient: false,
+            nested: Vec::new(),
+        }
+    }
+
+    /// Latest history entry for the same post; none with `--force` or under `retry`
+    fn previous_download(&self, nzb: &Nzb) -> Option<HistoryEntry> {
+        if self.cli.force || self.retry.is_some() {
+            return None;
+        }
+        match self.history.as_ref()?.latest_for(nzb.content_hash()) {
+            Ok(entry) => entry,
+            Err(e) => {
+                tracing::warn!("Failed to read download history: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Report an NZB skipped because `previous` already downloaded it
+    fn skip_duplicate(
+        &self,
+        nzb_path: &Path,
+        nzb_name: &str,
+        previous: &HistoryEntry,
+    ) -> NzbOutcome {
+        let dir = previous
+            .output_dir
+            .as_deref()
+            .map(|dir| dir.display().to_string())
+            .unwrap_or_default();
+        match progress::mode() {
+            OutputMode::Json => tracing::info!("{}: already downloaded (#{})", nzb_name, previous.id),
+            OutputMode::Quiet => println!(
+                "{}: {} ({} in {})",
+                nzb_name,
+                RunStatus::Duplicate.describe(),
+                human_bytes(previous.total_size as f64),
+                dir
+            ),
+            OutputMode::Normal | OutputMode::Verbose => errln!(
+                "\x1b[1;33m⚠ {}: already downloaded on {} to {} (#{}); use --force to download it again\x1b[0m",
+                nzb_name,
+                previous.date(),
+                dir,
+                previous.id
+            ),
+        }
+        self.set_aside(nzb_path, RunStatus::Duplicate);
+        NzbOutcome {
+            summary: DownloadSummary::duplicate(nzb_path, previous),
+            transient: false,
+            nested: Vec::new(),
+        }
+    }
+
+    /// Whether the main server accepts a connection right now
+    async fn server_reachable(&self) -> bool {
+        match AsyncNntpConnection::connect(&self.config.usenet, None).await {
+            Ok(mut conn) => {
+                let _ = conn.close().await;
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Link, copy or move the finished files in `output_dir` into `link_dir`,
+    /// under the same subfolder of it as `output_dir` is of the download folder
+    async fn link_into(
+        &self,
+        link_dir: &Path,
+        output_dir: &Path,
+        nzb_config: &Config,
+    ) -> Vec<PathBuf> {
+        let target = match output_dir.strip_prefix(&nzb_config.download.dir) {
+            Ok(subfolder) => link_dir.join(subfolder),
+            Err(_) => link_dir.join(output_dir.file_name().unwrap_or_default()),
+        };
+        let output_dir = output_dir.to_path_buf();
+        let post = &nzb_config.post_processing;
+        let (mode, collision) = (post.link_mode, post.link_collision);
+        let permissions = Permissions::from_config(&nzb_config.download).unwrap_or_default();
+        tokio::task::spawn_blocking(move || {
+            link_files(&output_dir, &target, mode, collision, permissions)
+        })
+        .await
+        .unwrap_or_default()
+    }
+
+    /// Mark an NZB's output folder as failed under `--strict`, returning
+    /// where it now is
+    fn mark_failed(&self, output_dir: &Path, nzb_config: &Config, reasons: &[String]) -> PathBuf {
+        let own_folder = output_dir != nzb_config.download.dir;
+        match strict::mark_failed(
+            output_dir,
+            nzb_config.download.strict_mark,
+            own_folder,
+            reasons,
+        ) {
+            Ok(dir) => dir,
+            Err(e) => {
+                tracing::warn!("Failed to mark {} as failed: {}", output_dir.display(), e);
+                output_dir.to_path_buf()
+            }
+        }
+    }
+
+    /// Move a local NZB out of the way as `after` says; returns its new path
+    fn set_aside(&self, nzb_path: &Path, status: RunStatus) -> Option<PathBuf> {
+        if NzbSource::from_arg(nzb_path).is_remote() {
+            return None;
+        }
+        // NZBs found inside a download stay with it
+        if std::fs::canonicalize(&self.config.download.dir)
+            .is_ok_and(|dir| nzb_path.starts_with(dir))
+        {
+            return None;
+        }
+        let dir = match &self.after {
+            AfterDownload::Keep => return None,
+            // Only NZBs that finished downloading are set aside
+            AfterDownload::MoveProcessed if status <= RunStatus::PostProcessingFailed => nzb_path
+                .parent()
+                .unwrap_or(Path::new("."))
+                .join(PROCESSED_DIR),
+            AfterDownload::MoveProcessed => return None,
+            AfterDownload::Sort { completed, .. } if status.succeeded() => completed.clone(),
+            AfterDownload::Sort { failed, .. } => failed.clone(),
+        };
+        match move_nzb(nzb_path, &dir) {
+            Ok(moved) => {
+                tracing::info!("Moved NZB to {}", redact_nzb_path(&moved).display());
+                Some(moved)
+            }
+            Err(e) => {
+                tracing::warn!(
+                    "Failed to move {}: {}",
+                    redact_nzb_path(nzb_path).display(),
+                    e
+                );
+                None
+            }
+        }
+    }
+}
+
+/// Append an NZB's speed samples to the `--speed-log` CSV; failures only warn
+fn log_speed(path: &Path, name: &str, timeline: &SpeedTimeline) {
+    let written = std::fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)
+        .and_then(|mut file| timeline.write_csv(&mut file, name));
+    if let Err(e) = written {
+        tracing::warn!("Failed to write speed log {}: {}", path.display(), e);
+    }
+}
+
+/// Print an NFO below the summary, cut to the terminal's width
+fn print_nfo(path: &Path) {
+    let text = match nfo::read(path) {
+        Ok(text) => text,
+        Err(e) => {
+            tracing::warn!("Failed to read {}: {}", path.display(), e);
+            return;
+        }
+    };
+    progress::print_status("");
+    progress::print_status(&format!(
+        "\x1b[1m{}\x1b[0m",
+        path.file_name().unwrap_or_default().to_string_lossy()
+    ));
+    for line in nfo::clamp_lines(&text, listing::terminal_width()) {
+        progress::print_status(&line);
+    }
 }
 
 /// Print a final summary after all processing is complete
 fn print_final_summary(
-    _nzb: &Nzb,
-    results: &[dl_nzb::download::DownloadResult],
-    output_dir: &std::path::Path,
+    download: &NzbDownload,
+    skipped: &[SkippedFile],
+    output_dir: &Path,
+    min_file_size: u64,
+    post_time: std::time::Duration,
 ) {
-    use std::time::Duration;
-
-    // Calculate total stats
-    let total_size: u64 = results.iter().map(|r| r.size).sum();
-    let total_time: Duration = results.iter().map(|r| r.download_time).sum();
+    let results = &download.results;
     let failed_count = results.iter().filter(|r| r.segments_failed > 0).count();
 
-    // Find the main video/media file (largest non-PAR2, non-RAR file)
-    let main_file = std::fs::read_dir(output_dir).ok().and_then(|entries| {
-        entries
-            .filter_map(|e| e.ok())
-            .filter(|e| e.path().is_file())
-            .filter(|e| {
-                let name = e.file_name().to_string_lossy().to_lowercase();
-                !name.ends_with(".par2")
-                    && !name.ends_with(".rar")
-                    && !name.ends_with(".nfo")
-                    && !name.ends_with(".sfv")
-            })
-            .max_by_key(|e| e.metadata().ok().map(|m| m.len()).unwrap_or(0))
-    });
+    // Find the main video/media file (largest non-PAR2, non-RAR file, and
+    // not one too small to have been downloaded)
+    let main_file = progress::main_file(output_dir, min_file_size);
 
-    println!();
+    progress::print_status("");
 
     if failed_count == 0 {
-        if let Some(file) = main_file {
-            let filename = file.file_name().to_string_lossy().to_string();
-            let file_size = file.metadata().ok().map(|m| m.len()).unwrap_or(0);
-
-            println!("\x1b[1;32m✓ Complete:\x1b[0m \x1b[37m{}\x1b[0m", filename);
-            println!(
-                "  \x1b[90m└─\x1b[0m \x1b[34m{}\x1b[0m",
-                output_dir.display()
-            );
-            println!(
-                "  \x1b[90m└─\x1b[0m \x1b[36m{}\x1b[0m in \x1b[35m{:.0}s\x1b[0m",
-                human_bytes(file_size as f64),
-                total_time.as_secs_f64()
-            );
-        } else {
-            // No main file found, just show stats
-            println!("\x1b[1;32m✓ Complete\x1b[0m");
-            println!(
-                "  \x1b[90m└─\x1b[0m \x1b[34m{}\x1b[0m",
-                output_dir.display()
-            );
-            println!(
-                "  \x1b[90m└─\x1b[0m \x1b[36m{}\x1b[0m in \x1b[35m{:.0}s\x1b[0m",
-                human_bytes(total_size as f64),
-                total_time.as_secs_f64()
-            );
+        match main_file {
+            Some(file) => progress::print_status(&format!(
+                "\x1b[1;32m✓ Complete:\x1b[0m \x1b[37m{}\x1b[0m",
+                file.file_name().unwrap_or_default().to_string_lossy()
+            )),
+            None => progress::print_status("\x1b[1;32m✓ Complete\x1b[0m"),
         }
     } else {
-        println!(
+        progress::print_status(&format!(
             "\x1b[1;33m! Completed with {} file{} having errors\x1b[0m",
             failed_count,
             if failed_count == 1 { "" } else { "s" }
-        );
-        println!(
-            "  \x1b[90m└─\x1b[0m \x1b[34m{}\x1b[0m",
-            output_dir.display()
-        );
+        ));
+    }
+    progress::print_status(&format!(
+        "  \x1b[90m└─\x1b[0m \x1b[34m{}\x1b[0m",
+        output_dir.display()
+    ));
+
+    // Wall-clock figures: files download concurrently, so per-file times don't add up
+    progress::print_status(&format!(
+        "  \x1b[90m└─\x1b[0m \x1b[36m{}\x1b[0m in \x1b[35m{}\x1b[0m ({:.1} MiB/s)",
+        human_bytes(download.total_size() as f64),
+        progress::format_duration(download.download_time),
+        download.average_speed()
+    ));
+    let transferred = download.total_transferred();
+    if transferred > 0 {
+        let written: u64 = results
+            .iter()
+            .filter(|r| r.skipped.is_none())
+            .map(|r| r.size)
+            .sum();
+        progress::print_status(&format!(
+            "  \x1b[90m└─\x1b[0m {}",
+            progress::format_transfer(transferred, written)
+        ));
+    }
+    // "12 downloaded, 3 skipped: 2 already complete, 1 filtered"
+    let passed: Vec<SkipReason> = results.iter().filter_map(|r| r.skip_reason()).collect();
+    if !passed.is_empty() || !skipped.is_empty() {
+        let complete: Vec<SkipVerification> = results
+            .iter()
+            .filter(|r| r.skip_reason() == Some(SkipReason::AlreadyComplete))
+            .filter_map(|r| r.skipped)
+            .collect();
+        let reasons = (passed.iter().copied()).chain(skipped.iter().map(|file| file.reason));
+        let how = if complete.is_empty() {
+            ""
+        } else if complete.iter().all(|&m| m == SkipVerification::QuickHash) {
+            "; existing files verified"
+        } else {
+            "; existing files checked by size only"
+        };
+        progress::print_status(&format!(
+            "  \x1b[90m└─\x1b[0m {} downloaded, {} skipped: {}{}",
+            results.len() - passed.len(),
+            passed.len() + skipped.len(),
+            skip_breakdown(reasons),
+            how
+        ));
     }
+    // Only worth a line when a fill server took some of it
+    let by_server = download.bytes_by_server();
+    if by_server.len() > 1 {
+        progress::print_status(&format!(
+            "  \x1b[90m└─\x1b[0m Servers: {}",
+            progress::format_server_bytes(&by_server)
+        ));
+    }
+    if post_time >= std::time::Duration::from_secs(1) {
+        progress::print_status(&format!(
+            "  \x1b[90m└─\x1b[0m Post-processing in \x1b[35m{}\x1b[0m",
+            progress::format_duration(post_time)
+        ));
+    }
+}
+
+/// Say what became of the files PAR2 couldn't complete
+fn print_partial_files(partial: &PartialFiles) {
+    if !partial.removed.is_empty() {
+        progress::print_status(&format!(
+            "  \x1b[90m└─\x1b[0m \x1b[33mDeleted {} incomplete file{} (use --keep-partial to keep them)\x1b[0m",
+            partial.removed.len(),
+            if partial.removed.len() == 1 { "" } else { "s" }
+        ));
+    }
+    if !partial.kept.is_empty() {
+        progress::print_status(&format!(
+            "  \x1b[90m└─\x1b[0m \x1b[33mKept {} incomplete file{} (missing segments listed in {})\x1b[0m",
+            partial.kept.len(),
+            if partial.kept.len() == 1 { "" } else { "s" },
+            FAILED_MARKER
+        ));
+    }
+}
+
+/// The end-of-run error report, if anything failed
+fn print_report(nzbs: &[DownloadSummary]) {
+    let lines = report::render(nzbs);
+    if let Some((heading, rest)) = lines.split_first() {
+        progress::print_status("");
+        progress::print_status(&format!("\x1b[1;31m{}\x1b[0m", heading));
+        for line in rest {
+            progress::print_status(line);
+        }
+    }
+}
+
+/// How complete the run's busiest groups and posters were, in verbose mode
+fn print_completion(completion: &CompletionStats) {
+    let lines = completion.lines();
+    if !lines.is_empty() {
+        progress::print_status("");
+        progress::print_status("\x1b[1mCompletion\x1b[0m");
+        for line in lines {
+            progress::print_status(&format!("  \x1b[90m└─\x1b[0m {}", line));
+        }
+    }
+}
+
+/// Warn about a post-processing script that failed or timed out
+fn print_script_warning(outcome: &ScriptOutcome) {
+    progress::print_status(&format!(
+        "  \x1b[90m└─\x1b[0m \x1b[33m⚠ Script {} {}\x1b[0m",
+        outcome.script.display(),
+        outcome.status()
+    ));
 }
@@ -0,0 +1,467 @@
+//! Prometheus metrics for long-running downloads
+//!
+//! Everything comes from the run's [`AppState`]: the per-server counters are
+//! the downloader's own (the ones behind the `servers` list of the run
+//! summary), and the NZB totals and speed are what the state has counted.
+//! This module renders them in the Prometheus text format. The HTTP listener needs the `metrics` feature and
+//! only starts when `[metrics] listen` is set.
+
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::MetricsConfig;
+use crate::download::{ServerMetrics, StatsHandle};
+use crate::error::DlNzbError;
+use crate::state::AppState;
+
+type Result<T> = std::result::Result<T, DlNzbError>;
+
+/// Upper bounds, in seconds, of the pool wait histogram buckets
+pub const WAIT_BUCKETS: [f64; 8] = [0.001, 0.01, 0.1, 0.5, 1.0, 5.0, 15.0, 60.0];
+
+/// Histogram of how long downloads waited for a pooled connection
+#[derive(Debug, Default)]
+pub struct WaitHistogram {
+    /// Observations per bucket of [`WAIT_BUCKETS`] (not cumulative)
+    buckets: [AtomicU64; WAIT_BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl WaitHistogram {
+    pub fn observe(&self, wait: Duration) {
+        let seconds = wait.as_secs_f64();
+        if let Some(i) = WAIT_BUCKETS.iter().position(|&bound| seconds <= bound) {
+            self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(wait.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        let mut total = 0;
+        let buckets = WAIT_BUCKETS
+            .iter()
+            .zip(&self.buckets)
+            .map(|(&bound, bucket)| {
+                total += bucket.load(Ordering::Relaxed);
+                (bound, total)
+            })
+            .collect();
+        HistogramSnapshot {
+            buckets,
+            count: self.count.load(Ordering::Relaxed),
+            sum: self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0,
+        }
+    }
+}
+
+/// Point-in-time copy of a [`WaitHistogram`]
+#[derive(Debug, Clone, Default)]
+pub struct HistogramSnapshot {
+    /// `(upper bound, cumulative count)` per bucket
+    pub buckets: Vec<(f64, u64)>,
+    pub count: u64,
+    /// Total seconds waited
+    pub sum: f64,
+}
+
+/// Everything `/metrics` reports
+pub struct Metrics {
+    state: Arc<AppState>,
+}
+
+impl Metrics {
+    pub fn new(state: Arc<AppState>) -> Self {
+        Self { state }
+    }
+
+    /// The Prometheus text exposition of every metric
+    pub fn render(&self) -> String {
+        let servers = self
+            .state
+            .stats()
+            .map(StatsHandle::server_metrics)
+            .unwrap_or_default();
+        let (completed, failed) = self.state.nzbs();
+        let labels: Vec<String> = servers
+            .iter()
+            .map(|s| format!("server=\"{}\"", escape_label(&s.server)))
+            .collect();
+        let mut out = String::new();
+
+        // One family: HELP and TYPE, then `(name suffix and labels, value)` samples
+        let mut family = |name: &str, kind: &str, help: &str, samples: Vec<(String, String)>| {
+            let _ = writeln!(out, "# HELP dl_nzb_{} {}", name, help);
+            let _ = writeln!(out, "# TYPE dl_nzb_{} {}", name, kind);
+            for (series, value) in samples {
+                let _ = writeln!(out, "dl_nzb_{}{} {}", name, series, value);
+            }
+        };
+        let per_server = |value: &dyn Fn(&ServerMetrics) -> String| -> Vec<(String, String)> {
+            servers
+                .iter()
+                .zip(&labels)
+                .map(|(s, label)| (format!("{{{}}}", label), value(s)))
+                .collect()
+        };
+
+        family(
+            "downloaded_bytes_total",
+            "counter",
+            "Decoded bytes downloaded",
+            per_server(&|s| s.bytes.to_string()),
+        );
+        family(
+            "transferred_bytes_total",
+            "counter",
+            "Encoded bytes read, including articles thrown away",
+            per_server(&|s| s.transferred.to_string()),
+        );
+        family(
+            "segments_total",
+            "counter",
+            "Segments fetched successfully",
+            per_server(&|s| s.articles.to_string()),
+        );
+        family(
+            "segments_failed_total",
+            "counter",
+            "Segments not fetched, by reason",
+            servers
+                .iter()
+                .zip(&labels)
+                .flat_map(|(s, label)| {
+                    [
+                        ("missing", s.missing),
+                        ("no_connection", s.no_connection),
+                        ("error", s.errors),
+                    ]
+                    .map(|(reason, count)| {
+                        (
+                            format!("{{{},reason=\"{}\"}}", label, reason),
+                            count.to_string(),
+                        )
+                    })
+                })
+                .collect(),
+        );
+        family(
+            "idle_closures_total",
+            "counter",
+            "Connections the server closed on its own (idle timeouts)",
+            per_server(&|s| s.idle_closures.to_string()),
+        );
+        family(
+            "connections_open",
+            "gauge",
+            "Open connections in the pool",
+            per_server(&|s| s.connections_open.to_string()),
+        );
+        family(
+            "connections_active",
+            "gauge",
+            "Connections currently downloading",
+            per_server(&|s| s.connections_in_use.to_string()),
+        );
+        family(
+            "connections_limit",
+            "gauge",
+            "Connections downloads may use at once",
+            per_server(&|s| s.connections_limit.to_string()),
+        );
+        family(
+            "pool_wait_seconds",
+            "histogram",
+            "Time spent waiting for a pooled connection",
+            servers
+                .iter()
+                .zip(&labels)
+                .flat_map(|(s, label)| {
+                    let wait = &s.pool_wait;
+                    wait.buckets
+                        .iter()
+                        .map(|(bound, count)| (bound.to_string(), *count))
+                        .chain([("+Inf".to_string(), wait.count)])
+                        .map(|(bound, count)| {
+                            (
+                                format!("_bucket{{{},le=\"{}\"}}", label, bound),
+                                count.to_string(),
+                            )
+                        })
+                        .chain([
+                            (format!("_sum{{{}}}", label), wait.sum.to_string()),
+                            (format!("_count{{{}}}", label), wait.count.to_string()),
+                        ])
+                        .collect::<Vec<_>>()
+                })
+                .collect(),
+        );
+        family(
+            "connect_seconds",
+            "gauge",
+            "Time to open a connection by step, for the first connection and the mean of the rest",
+            servers
+                .iter()
+                .zip(&labels)
+                .flat_map(|(s, label)| {
+                    [("first", s.handshakes.first), ("later", s.handshakes.later)]
+                        .into_iter()
+                        .filter_map(|(which, timings)| Some((which, timings?)))
+                        .flat_map(move |(which, t)| {
+                            [
+                                ("tcp", t.tcp),
+                                ("tls", t.tls),
+                                ("greeting", t.greeting),
+                                ("auth", t.auth),
+                            ]
+                            .map(|(step, time)| {
+                                (
+                                    format!(
+                                        "{{{},connection=\"{}\",step=\"{}\"}}",
+                                        label, which, step
+                                    ),
+                                    time.as_secs_f64().to_string(),
+                                )
+                            })
+                        })
+                })
+                .collect(),
+        );
+        family(
+            "clock_skew_seconds",
+            "gauge",
+            "Seconds the local clock is ahead of the server's, from DATE on the first connection",
+            servers
+                .iter()
+                .zip(&labels)
+                .filter_map(|(s, label)| {
+                    let skew = s.handshakes.clock_skew?;
+                    Some((format!("{{{}}}", label), skew.to_string()))
+                })
+                .collect(),
+        );
+        family(
+            "nzbs_total",
+            "counter",
+            "NZBs finished, by result",
+            vec![
+                ("{result=\"completed\"}".to_string(), completed.to_string()),
+                ("{result=\"failed\"}".to_string(), failed.to_string()),
+            ],
+        );
+        family(
+            "download_speed_bytes",
+            "gauge",
+            "Download speed in bytes per second",
+            vec![(String::new(), self.state.speed().to_string())],
+        );
+
+        out
+    }
+}
+
+/// Escape a label value for the text format
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// A running `/metrics` listener; stops when dropped
+pub struct MetricsServer {
+    addr: SocketAddr,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl MetricsServer {
+    /// Start the endpoint when `[metrics] listen` is set
+    ///
+    /// A build without the `metrics` feature only warns that it is unavailable.
+    pub async fn start(config: &MetricsConfig, state: Arc<AppState>) -> Result<Option<Self>> {
+        if config.listen.is_empty() {
+            return Ok(None);
+        }
+        if !cfg!(feature = "metrics") {
+            tracing::warn!(
+                "metrics.listen is set, but dl-nzb was built without the metrics feature"
+            );
+            return Ok(None);
+        }
+
+        let invalid = |reason: String| crate::error::ConfigError::Invalid {
+            field: "metrics.listen".to_string(),
+            reason,
+        };
+        let addr: SocketAddr = config
+            .listen
+            .parse()
+            .map_err(|e| invalid(format!("{}: {}", config.listen, e)))?;
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .map_err(|e| invalid(format!("cannot listen on {}: {}", addr, e)))?;
+        let addr = listener.local_addr()?;
+
+        let task = tokio::spawn(serve(listener, Arc::new(Metrics::new(state))));
+        tracing::info!("Serving metrics on http://{}/metrics", addr);
+        Ok(Some(Self { addr, task }))
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for MetricsServer {
+    fn drop(&mut self) {
+        // Closes the listener and every open scrape connection
+        self.task.abort();
+    }
+}
+
+/// Answer scrapes until aborted
+#[cfg(feature = "metrics")]
+async fn serve(listener: tokio::net::TcpListener, metrics: Arc<Metrics>) {
+    use bytes::Bytes;
+    use http_body_util::Full;
+    use hyper::{Method, Request, Response, StatusCode};
+
+    let mut connections = tokio::task::JoinSet::new();
+
+    loop {
+        tokio::select! {
+            Some(_) = connections.join_next(), if !connections.is_empty() => {}
+            accepted = listener.accept() => {
+                let stream = match accepted {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        tracing::debug!("Metrics accept failed: {}", e);
+                        continue;
+                    }
+                };
+                let metrics = metrics.clone();
+                let service = hyper::service::service_fn(move |request: Request<_>| {
+                    let response = if request.method() != Method::GET {
+                        Response::builder()
+                            .status(StatusCode::METHOD_NOT_ALLOWED)
+                            .body(Full::new(Bytes::from("method not allowed\n")))
+                    } else if request.uri().path() == "/metrics" {
+                        Response::builder()
+                            .header("Content-Type", "text/plain; version=0.0.4")
+                            .body(Full::new(Bytes::from(metrics.render())))
+                    } else {
+                        Response::builder()
+                            .status(StatusCode::NOT_FOUND)
+                            .body(Full::new(Bytes::from("not found\n")))
+                    };
+                    std::future::ready(response)
+                });
+                connections.spawn(async move {
+                    let io = hyper_util::rt::TokioIo::new(stream);
+                    if let Err(e) = hyper::server::conn::http1::Builder::new()
+                        .serve_connection(io, service)
+                        .await
+                    {
+                        tracing::debug!("Metrics connection error: {}", e);
+                    }
+                });
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+async fn serve(_listener: tokio::net::TcpListener, _metrics: Arc<Metrics>) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wait_histogram_is_cumulative() {
+        let histogram = WaitHistogram::default();
+        histogram.observe(Duration::from_micros(500));
+        histogram.observe(Duration::from_millis(300));
+        histogram.observe(Duration::from_secs(600));
+
+        let snapshot = histogram.snapshot();
+        assert_eq!(snapshot.count, 3);
+        assert_eq!(snapshot.buckets[0], (0.001, 1));
+        assert_eq!(snapshot.buckets[3], (0.5, 2));
+        // Longer than the last bound: only in +Inf
+        assert_eq!(snapshot.buckets.last().unwrap().1, 2);
+        assert!((snapshot.sum - 600.3005).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_escape_label() {
+        assert_eq!(escape_label(r#"a"b\c"#), r#"a\"b\\c"#);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn test_metrics_endpoint() {
+        use crate::error::RunStatus;
+        use crate::state::FinishedNzb;
+        use std::io::{Read, Write};
+
+        let config = crate::config::Config {
+            usenet: crate::config::UsenetConfig {
+                server: "news.example.com".to_string(),
+                ..Default::default()
+            },
+            metrics: MetricsConfig {
+                listen: "127.0.0.1:0".to_string(),
+            },
+            ..Default::default()
+        };
+        let downloader = crate::download::Downloader::new(config.clone())
+            .await
+            .unwrap();
+        let state = Arc::new(AppState::new().with_stats(downloader.stats()));
+        let server = MetricsServer::start(&config.metrics, state.clone())
+            .await
+            .unwrap()
+            .unwrap();
+        for status in [RunStatus::Success, RunStatus::Incomplete] {
+            state.finish_nzb(FinishedNzb {
+                nzb: "show.nzb".into(),
+                status,
+                total_size: 0,
+            });
+        }
+
+        let addr = server.addr();
+        let body = tokio::task::spawn_blocking(move || {
+            let mut stream = std::net::TcpStream::connect(addr).unwrap();
+            stream
+                .write_all(b"GET /metrics HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n")
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        })
+        .await
+        .unwrap();
+
+        assert!(body.starts_with("HTTP/1.1 200"), "{}", body);
+        assert!(body.contains("# TYPE dl_nzb_pool_wait_seconds histogram"));
+        assert!(body.contains("dl_nzb_downloaded_bytes_total{server=\"news.example.com\"} 0"));
+        assert!(body.contains("dl_nzb_transferred_bytes_total{server=\"news.example.com\"} 0"));
+        assert!(body.contains("dl_nzb_connections_limit{server=\"news.example.com\"} 20"));
+        assert!(body.contains("dl_nzb_idle_closures_total{server=\"news.example.com\"} 0"));
+        assert!(body.contains("dl_nzb_nzbs_total{result=\"completed\"} 1"));
+        assert!(body.contains("dl_nzb_nzbs_total{result=\"failed\"} 1"));
+
+        // Dropping the server closes the port
+        drop(server);
+        tokio::task::yield_now().await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(std::net::TcpStream::connect(addr).is_err());
+    }
+}
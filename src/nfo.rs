@@ -0,0 +1,169 @@
+//! NFO files: finding the one a release came with and reading its text
+//!
+//! NFOs are written in code page 437, the DOS character set their ASCII art
+//! is drawn in, so [`decode`] maps each byte through the CP437 table rather
+//! than reading them as UTF-8 (a handful of newer NFOs that are UTF-8 are
+//! taken as such). Text stops at the DOS end-of-file byte, which SAUCE
+//! metadata follows.
+
+use std::path::{Path, PathBuf};
+
+use crate::patterns::ext;
+
+/// NFO text read at most, for display and `--preview`
+pub const MAX_BYTES: usize = 64 * 1024;
+
+/// Characters of bytes 0x80 to 0xFF in code page 437
+const CP437_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', //
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', //
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', //
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐', //
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧', //
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀', //
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', //
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/// DOS end-of-file, after which SAUCE records and padding follow
+const DOS_EOF: u8 = 0x1a;
+
+/// Text of an NFO
+///
+/// Bytes that are neither printable nor a tab or line break come out as
+/// U+FFFD, so nothing read can drive the terminal.
+pub fn decode(bytes: &[u8]) -> String {
+    let bytes = bytes.split(|&b| b == DOS_EOF).next().unwrap_or_default();
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        if !text.is_ascii() {
+            return text
+                .trim_start_matches('\u{feff}')
+                .chars()
+                .map(|c| match c {
+                    '\t' | '\n' | '\r' => c,
+                    c if c.is_control() => char::REPLACEMENT_CHARACTER,
+                    c => c,
+                })
+                .collect();
+        }
+    }
+    bytes
+        .iter()
+        .map(|&b| match b {
+            b'\t' | b'\n' | b'\r' => b as char,
+            0x7f => '⌂',
+            0x20..=0x7e => b as char,
+            0x80..=0xff => CP437_HIGH[(b - 0x80) as usize],
+            _ => char::REPLACEMENT_CHARACTER,
+        })
+        .collect()
+}
+
+/// Read and decode an NFO, up to [`MAX_BYTES`] of it
+pub fn read(path: &Path) -> std::io::Result<String> {
+    let mut bytes = std::fs::read(path)?;
+    bytes.truncate(MAX_BYTES);
+    Ok(decode(&bytes))
+}
+
+/// The NFO under `dir`, when there is exactly one
+///
+/// Folders extracted into `dir` are searched too; hidden files are not.
+pub fn find(dir: &Path) -> Option<PathBuf> {
+    let mut found = Vec::new();
+    collect(dir, &mut found);
+    match <[PathBuf; 1]>::try_from(found) {
+        Ok([nfo]) => Some(nfo),
+        Err(_) => None,
+    }
+}
+
+fn collect(dir: &Path, found: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.filter_map(|e| e.ok()) {
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let path = entry.path();
+        match entry.file_type() {
+            Ok(kind) if kind.is_dir() => collect(&path, found),
+            Ok(kind) if kind.is_file() && ext::has_extension(&path, "nfo") => found.push(path),
+            _ => {}
+        }
+    }
+}
+
+/// Lines of `text` cut to `width` characters, tabs expanded and trailing
+/// blanks dropped
+pub fn clamp_lines(text: &str, width: usize) -> Vec<String> {
+    let mut lines: Vec<String> = text
+        .lines()
+        .map(|line| {
+            let mut out = String::new();
+            let mut column = 0;
+            for c in line.chars() {
+                if c == '\r' {
+                    continue;
+                }
+                let spaces = if c == '\t' { 8 - column % 8 } else { 1 };
+                if column + spaces > width {
+                    break;
+                }
+                if c == '\t' {
+                    out.push_str(&" ".repeat(spaces));
+                } else {
+                    out.push(c);
+                }
+                column += spaces;
+            }
+            out.trim_end().to_string()
+        })
+        .collect();
+    while lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_cp437_art() {
+        // ╔══╗ over ║▓▓║, then SAUCE after the end-of-file byte
+        let bytes = b"\xc9\xcd\xcd\xbb\r\n\xba\xb2\xb2\xba\r\n\x1aSAUCE00";
+        assert_eq!(decode(bytes), "╔══╗\r\n║▓▓║\r\n");
+        assert_eq!(decode(b"caf\x82 \x1b[2J\x7f"), "café \u{fffd}[2J⌂");
+        // UTF-8 NFOs stay UTF-8
+        assert_eq!(
+            decode("\u{feff}Groupe: Équipe".as_bytes()),
+            "Groupe: Équipe"
+        );
+    }
+
+    #[test]
+    fn test_clamp_lines() {
+        let lines = clamp_lines("ab\tc\r\n0123456789  \n\n\n", 9);
+        assert_eq!(lines, ["ab      c", "012345678"]);
+        assert_eq!(clamp_lines("╔══╗", 2), ["╔═"]);
+    }
+
+    #[test]
+    fn test_find_only_a_single_nfo() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("show.mkv"), b"x").unwrap();
+        assert_eq!(find(dir.path()), None);
+
+        let extracted = dir.path().join("Show");
+        std::fs::create_dir(&extracted).unwrap();
+        std::fs::write(extracted.join("show.NFO"), b"x").unwrap();
+        std::fs::write(dir.path().join(".dlnzb-show.nfo"), b"x").unwrap();
+        assert_eq!(find(dir.path()), Some(extracted.join("show.NFO")));
+
+        std::fs::write(dir.path().join("other.nfo"), b"x").unwrap();
+        assert_eq!(find(dir.path()), None);
+    }
+}
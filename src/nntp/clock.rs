@@ -0,0 +1,74 @@
+//! How far the local clock is from a server's
+//!
+//! A clock that is days out breaks things far from the clock: TLS
+//! certificates look expired or not yet valid, and post ages come out wrong,
+//! so downloads are skipped as past retention or kept as just posted.
+//! `DATE` (RFC 3977 section 7.1) gives the server's time to the second,
+//! which is plenty to tell.
+
+use chrono::NaiveDateTime;
+use std::time::Duration;
+
+use crate::progress::format_duration;
+
+/// Clock differences beyond this are reported
+pub const MAX_CLOCK_SKEW: Duration = Duration::from_secs(5 * 60);
+
+/// Seconds the local clock at `local` is ahead of a server that said
+/// `server_time` (negative when it's behind); both in UTC
+pub fn skew_seconds(server_time: NaiveDateTime, local: NaiveDateTime) -> i64 {
+    (local - server_time).num_seconds()
+}
+
+/// Whether a skew of `seconds` is past [`MAX_CLOCK_SKEW`]
+pub fn is_skewed(seconds: i64) -> bool {
+    seconds.unsigned_abs() > MAX_CLOCK_SKEW.as_secs()
+}
+
+/// `3h 12m ahead of` or `45s behind`
+pub fn describe_skew(seconds: i64) -> String {
+    let amount = format_duration(Duration::from_secs(seconds.unsigned_abs()));
+    if seconds >= 0 {
+        format!("{} ahead of", amount)
+    } else {
+        format!("{} behind", amount)
+    }
+}
+
+/// What to tell the user about a skew of `seconds` against `server`, when
+/// it's past [`MAX_CLOCK_SKEW`]
+pub fn skew_warning(server: &str, seconds: i64) -> Option<String> {
+    is_skewed(seconds).then(|| {
+        format!(
+            "local clock is {} {}; TLS certificates may be rejected as expired or not yet valid and post ages will be off (check NTP)",
+            describe_skew(seconds),
+            server
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y%m%d%H%M%S").unwrap()
+    }
+
+    #[test]
+    fn test_skew_direction_and_threshold() {
+        let server = time("20240101120000");
+        let ahead = skew_seconds(server, time("20240104120000"));
+        assert_eq!(ahead, 3 * 86_400);
+        assert_eq!(describe_skew(ahead), "72h 0m ahead of");
+        assert_eq!(skew_seconds(server, time("20240101115915")), -45);
+        assert_eq!(describe_skew(-45), "45s behind");
+
+        assert!(!is_skewed(300));
+        assert!(is_skewed(-301));
+        assert_eq!(skew_warning("news.example.org", 120), None);
+        let warning = skew_warning("news.example.org", ahead).unwrap();
+        assert!(warning.starts_with("local clock is 72h 0m ahead of news.example.org;"));
+        assert!(warning.contains("TLS certificates"));
+    }
+}
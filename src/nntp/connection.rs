@@ -1,40 +1,169 @@
 use bytes::Bytes;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
 use tokio::net::TcpStream;
 use tokio::time::{timeout, Duration};
 use tokio_native_tls::TlsConnector;
 
-use crate::config::UsenetConfig;
+use crate::config::{Secret, UsenetConfig};
+use crate::download::{Reservation, Throttle};
 use crate::error::{DlNzbError, NntpError};
 
+use super::response::NntpResponse;
+use super::yenc::{decode_yenc, yenc_checksum_matches, yenc_file_size, yenc_name, yenc_part_begin};
+use super::PROTOCOL_TARGET;
+
 type Result<T> = std::result::Result<T, DlNzbError>;
 
+/// Source of connection ids, which tag every log line a connection writes
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Write one line of the protocol log for connection `$conn`
+///
+/// Checked against the filter first, so with the protocol log off nothing
+/// is formatted on the download path.
+macro_rules! protocol {
+    ($conn:expr, $($arg:tt)+) => {
+        if tracing::enabled!(target: PROTOCOL_TARGET, tracing::Level::TRACE) {
+            tracing::trace!(
+                target: PROTOCOL_TARGET,
+                conn = $conn.id,
+                ms = $conn.opened.elapsed().as_millis() as u64,
+                $($arg)+
+            );
+        }
+    };
+}
+
 /// Async NNTP connection that can be pooled
 pub struct AsyncNntpConnection {
+    /// Unique within the process, for correlating log lines
+    id: u64,
+    /// When connecting started; protocol log lines are timed from it
+    opened: std::time::Instant,
     writer: Box<dyn AsyncWrite + Unpin + Send>,
     reader: BufReader<Box<dyn AsyncRead + Unpin + Send>>,
     current_group: Option<String>,
+    /// Time taken to connect and log in
+    connect_time: Duration,
+    /// The steps of `connect_time`
+    timings: ConnectTimings,
+    /// The server's greeting, without its code
+    greeting: String,
+    /// The server is closing the connection, or replies may be out of step
+    /// with commands; the pool drops it instead of reusing it
+    broken: bool,
+    /// The server closed the connection: a `400` (idle timeout and the like)
+    /// or a hang-up. Nothing was wrong with what was asked on it.
+    closed_by_server: bool,
+    /// Article body bytes read so far, whole bodies or not
+    bytes_read: u64,
+    /// Kept to log in again when the server asks mid-session (`480`)
+    username: String,
+    password: Secret,
+}
+
+/// How long each step of opening a connection took
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectTimings {
+    /// TCP connect
+    pub tcp: Duration,
+    /// TLS handshake; zero without SSL
+    pub tls: Duration,
+    /// Wait for the server's greeting
+    pub greeting: Duration,
+    /// `AUTHINFO USER` and `PASS`
+    pub auth: Duration,
+}
+
+impl ConnectTimings {
+    pub fn total(&self) -> Duration {
+        self.tcp + self.tls + self.greeting + self.auth
+    }
 }
 
-/// Request for pipelined downloading
-#[derive(Clone)]
+/// An article to fetch, for pipelined downloading or
+/// [`NntpPoolExt::download_segments`](super::NntpPoolExt::download_segments)
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SegmentRequest {
+    /// Message-ID without the angle brackets
     pub message_id: String,
+    /// Newsgroup to select before asking for the article
     pub group: String,
+    /// The caller's number for the article, such as its part of the file
     pub segment_number: u32,
 }
 
+/// Result of a pipelined batch
+#[derive(Debug, Default)]
+pub struct FetchedBatch {
+    /// Every requested segment in request order; `None` when it couldn't be fetched
+    pub segments: Vec<(u32, Option<Bytes>)>,
+    /// Encoded article bytes read from the socket, for progress, speed and
+    /// the bytes-transferred total; bodies cut short count too
+    pub wire_bytes: u64,
+    /// Segments the server said it doesn't have (430/423)
+    pub missing: Vec<u32>,
+    /// Segments whose data failed its yEnc checksum; the data is kept for PAR2
+    pub corrupt: Vec<u32>,
+    /// Segments left unanswered because the server closed the connection
+    /// first (a `400` or a hang-up, as on an idle timeout); they're `None` in
+    /// `segments` but worth asking for again on a fresh connection
+    pub interrupted: Vec<u32>,
+    /// Where each fetched part starts in its file, for parts whose
+    /// `=ypart` line says
+    pub offsets: Vec<(u32, u64)>,
+    /// The file's decoded size, from the first `=ybegin size=` received
+    pub file_size: Option<u64>,
+}
+
+impl FetchedBatch {
+    /// Take in `rest`, which asked again for some of this batch's segments
+    /// (those interrupted, say)
+    pub fn resume(&mut self, rest: FetchedBatch) {
+        self.interrupted
+            .retain(|number| !rest.segments.iter().any(|(n, _)| n == number));
+        for (number, data) in rest.segments {
+            if let Some(slot) = self.segments.iter_mut().find(|(n, _)| *n == number) {
+                slot.1 = data;
+            }
+        }
+        self.wire_bytes += rest.wire_bytes;
+        self.missing.extend(rest.missing);
+        self.corrupt.extend(rest.corrupt);
+        self.offsets.extend(rest.offsets);
+        self.file_size = self.file_size.or(rest.file_size);
+        self.interrupted.extend(rest.interrupted);
+    }
+}
+
+/// Mask the argument of `AUTHINFO PASS` for protocol logging
+fn redact_command(command: &str) -> std::borrow::Cow<'_, str> {
+    const PASS: &str = "AUTHINFO PASS";
+    match command.get(..PASS.len()) {
+        Some(prefix) if prefix.eq_ignore_ascii_case(PASS) => {
+            format!("{} {}", PASS, crate::config::Secret::MASK).into()
+        }
+        _ => command.into(),
+    }
+}
+
 impl AsyncNntpConnection {
     /// Create a new NNTP connection with optional shared TLS connector
     ///
-    /// Using a shared TLS connector enables session reuse across connections to the same server,
-    /// which significantly reduces TLS handshake overhead (can save ~35% CPU on SSL operations)
+    /// Using a shared TLS connector lets TLS backends that cache sessions per
+    /// connector resume them on later connections to the same server, which
+    /// saves a round trip and the key exchange. [`timings`](Self::timings)
+    /// shows whether it does.
     pub async fn connect(
         config: &UsenetConfig,
         tls_connector: Option<Arc<TlsConnector>>,
     ) -> Result<Self> {
+        let started = std::time::Instant::now();
+        let id = NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed);
         let addr = format!("{}:{}", config.server, config.port);
+        tracing::debug!(conn = id, server = %addr, "Connecting");
 
         // Connect with timeout
         let tcp_stream = timeout(Duration::from_secs(30), TcpStream::connect(&addr))
@@ -48,6 +177,10 @@ impl AsyncNntpConnection {
 
         // Set socket options for better performance
         tcp_stream.set_nodelay(true)?;
+        let mut timings = ConnectTimings {
+            tcp: started.elapsed(),
+            ..ConnectTimings::default()
+        };
 
         // Wrap in TLS if needed
         let (reader, writer): (
@@ -69,6 +202,7 @@ impl AsyncNntpConnection {
             };
 
             // Perform TLS handshake
+            let handshake = std::time::Instant::now();
             let tls_stream = timeout(
                 Duration::from_secs(30),
                 connector.connect(&config.server, tcp_stream),
@@ -76,6 +210,7 @@ impl AsyncNntpConnection {
             .await
             .map_err(|_| NntpError::Timeout { seconds: 30 })?
             .map_err(|e| NntpError::TlsError(e.to_string()))?;
+            timings.tls = handshake.elapsed();
 
             // Split TLS stream
             let (read_half, write_half) = tokio::io::split(tls_stream);
@@ -89,77 +224,192 @@ impl AsyncNntpConnection {
         let reader = BufReader::with_capacity(256 * 1024, reader); // 256KB read buffer for pipelining
 
         let mut conn = Self {
+            id,
+            opened: started,
             writer,
             reader,
             current_group: None,
+            connect_time: Duration::ZERO,
+            timings,
+            greeting: String::new(),
+            broken: false,
+            closed_by_server: false,
+            bytes_read: 0,
+            username: config.username.clone(),
+            password: config.password.clone(),
         };
 
         // Initialize connection
         conn.initialize(config).await?;
+        conn.connect_time = started.elapsed();
+        tracing::debug!(
+            conn = id,
+            ms = conn.connect_time.as_millis() as u64,
+            tcp_ms = conn.timings.tcp.as_millis() as u64,
+            tls_ms = conn.timings.tls.as_millis() as u64,
+            auth_ms = conn.timings.auth.as_millis() as u64,
+            "Connected"
+        );
 
         Ok(conn)
     }
 
     async fn initialize(&mut self, config: &UsenetConfig) -> Result<()> {
         // Read server greeting
+        let started = std::time::Instant::now();
         let response = self.read_response().await?;
-        if !response.starts_with("200") && !response.starts_with("201") {
-            return Err(
-                NntpError::ProtocolError(format!("Server greeting failed: {}", response)).into(),
-            );
+        if !matches!(response.code, 200 | 201) {
+            return Err(response.into_error(&config.server).into());
         }
+        self.timings.greeting = started.elapsed();
+        self.greeting = response.message;
 
         // Authenticate
-        self.authenticate(config).await
+        let started = std::time::Instant::now();
+        self.authenticate().await?;
+        self.timings.auth = started.elapsed();
+        Ok(())
     }
 
-    async fn authenticate(&mut self, config: &UsenetConfig) -> Result<()> {
+    async fn authenticate(&mut self) -> Result<()> {
         // Send username
-        self.send_command(&format!("AUTHINFO USER {}", config.username))
-            .await?;
-        let response = self.read_response().await?;
+        let command = format!("AUTHINFO USER {}", self.username);
+        self.send_command(&command).await?;
+        let mut response = self.read_response().await?;
 
-        if response.starts_with("381") {
+        if response.code == 381 {
             // Server wants password
-            self.send_command(&format!("AUTHINFO PASS {}", config.password))
-                .await?;
-            let response = self.read_response().await?;
-
-            if !response.starts_with("281") {
-                // Sanitize response to avoid leaking sensitive info
-                let sanitized = response.split_whitespace().next().unwrap_or("Unknown");
-                return Err(NntpError::AuthFailed(format!(
-                    "Authentication failed ({})",
-                    sanitized
-                ))
-                .into());
-            }
-        } else if !response.starts_with("281") {
-            // Sanitize response to avoid leaking sensitive info
-            let sanitized = response.split_whitespace().next().unwrap_or("Unknown");
-            return Err(
-                NntpError::AuthFailed(format!("Authentication failed ({})", sanitized)).into(),
-            );
+            let command = format!("AUTHINFO PASS {}", self.password.expose());
+            self.send_command(&command).await?;
+            response = self.read_response().await?;
         }
 
-        Ok(())
+        match response.code {
+            281 => Ok(()),
+            400 => Err(response.into_error("AUTHINFO").into()),
+            // Only the code: the rest of the line may echo credentials
+            code => Err(NntpError::AuthFailed(format!("Authentication failed ({})", code)).into()),
+        }
+    }
+
+    /// Log in again after the server answered `480` to a command
+    ///
+    /// Load-balanced server farms sometimes hand a connection to a backend
+    /// that doesn't know it logged in. That backend may not know the selected
+    /// group either, so it's selected again with the next command.
+    async fn reauthenticate(&mut self) -> Result<()> {
+        tracing::debug!(conn = self.id, "Server asked to log in again");
+        self.current_group = None;
+        let result = timeout(Duration::from_secs(10), self.authenticate())
+            .await
+            .unwrap_or_else(|_| Err(NntpError::Timeout { seconds: 10 }.into()));
+        if result.is_err() {
+            self.broken = true;
+        }
+        result
+    }
+
+    /// A `480` that logging in again didn't cure: the login is refused after
+    /// all, and the connection is no use
+    fn still_unauthenticated(&mut self) -> DlNzbError {
+        self.broken = true;
+        NntpError::AuthFailed("Authentication required again after logging in".to_string()).into()
+    }
+
+    /// What the server said on connecting, e.g. `news.example.com NNRP Service Ready`
+    pub fn greeting(&self) -> &str {
+        &self.greeting
+    }
+
+    /// Id that tags this connection's log lines
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Article body bytes read from the socket since connecting, including
+    /// bodies cut short and bodies thrown away
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// Time the connection took to establish, including TLS and login
+    pub fn connect_time(&self) -> Duration {
+        self.connect_time
+    }
+
+    /// Time spent on each step of connecting
+    pub fn timings(&self) -> ConnectTimings {
+        self.timings
+    }
+
+    /// Whether the server closed the connection (see [`FetchedBatch::interrupted`])
+    pub fn closed_by_server(&self) -> bool {
+        self.closed_by_server
+    }
+
+    /// Newsgroup last selected, which articles in it are fetched without
+    /// another `GROUP`
+    pub fn current_group(&self) -> Option<&str> {
+        self.current_group.as_deref()
+    }
+
+    /// Select a newsgroup and return the range of article numbers it holds
+    pub async fn select_group(&mut self, group: &str) -> Result<std::ops::RangeInclusive<u64>> {
+        self.send_command(&format!("GROUP {}", group)).await?;
+        let response = timeout(Duration::from_secs(10), self.read_response())
+            .await
+            .map_err(|_| NntpError::Timeout { seconds: 10 })??;
+        if response.code != 211 {
+            return Err(response.into_error(group).into());
+        }
+        self.current_group = Some(group.to_string());
+
+        // 211 <count> <first> <last> <group>
+        let mut numbers = response
+            .message
+            .split_whitespace()
+            .skip(1)
+            .map(|n| n.parse::<u64>().unwrap_or(0));
+        let first = numbers.next().unwrap_or(0);
+        let last = numbers.next().unwrap_or(0);
+        Ok(first..=last)
     }
 
     /// Download a segment and return the decoded data
     pub async fn download_segment(&mut self, message_id: &str, group: &str) -> Result<Bytes> {
-        // Select group if different from current
-        if self.current_group.as_deref() != Some(group) {
-            self.send_command(&format!("GROUP {}", group)).await?;
-            let response = timeout(Duration::from_secs(10), self.read_response())
-                .await
-                .map_err(|_| NntpError::Timeout { seconds: 10 })??;
-            if !response.starts_with("211") {
-                return Err(NntpError::GroupNotFound {
-                    group: group.to_string(),
+        let encoded_data = self.fetch_body(message_id, group).await?;
+        Ok(Bytes::from(decode_yenc(&encoded_data)))
+    }
+
+    /// Download a segment and return the file name on its `=ybegin` line
+    ///
+    /// `None` when the article isn't yEnc or its header has no name.
+    pub async fn yenc_name(&mut self, message_id: &str, group: &str) -> Result<Option<String>> {
+        let encoded_data = self.fetch_body(message_id, group).await?;
+        Ok(yenc_name(&encoded_data))
+    }
+
+    /// Fetch an article body, still yEnc-encoded, logging in again once if
+    /// the server asks
+    async fn fetch_body(&mut self, message_id: &str, group: &str) -> Result<Vec<u8>> {
+        match self.try_fetch_body(message_id, group).await {
+            Err(DlNzbError::Nntp(NntpError::AuthRequired)) => {
+                self.reauthenticate().await?;
+                match self.try_fetch_body(message_id, group).await {
+                    Err(DlNzbError::Nntp(NntpError::AuthRequired)) => {
+                        Err(self.still_unauthenticated())
+                    }
+                    result => result,
                 }
-                .into());
             }
-            self.current_group = Some(group.to_string());
+            result => result,
+        }
+    }
+
+    async fn try_fetch_body(&mut self, message_id: &str, group: &str) -> Result<Vec<u8>> {
+        // Select group if different from current
+        if self.current_group.as_deref() != Some(group) {
+            self.select_group(group).await?;
         }
 
         // Request article body
@@ -167,30 +417,69 @@ impl AsyncNntpConnection {
         let response = timeout(Duration::from_secs(10), self.read_response())
             .await
             .map_err(|_| NntpError::Timeout { seconds: 10 })??;
-        if !response.starts_with("222") {
-            return Err(NntpError::ArticleNotFound {
-                message_id: message_id.to_string(),
-            }
-            .into());
+        if response.code != 222 {
+            return Err(response.into_error(message_id).into());
         }
 
-        // Read and decode the body
         let encoded_data = timeout(Duration::from_secs(30), self.read_article_body())
             .await
             .map_err(|_| NntpError::Timeout { seconds: 30 })??;
+        Ok(encoded_data)
+    }
 
-        // Simple yEnc decoding
-        let decoded = self.decode_yenc_simple(&encoded_data)?;
+    /// Fetch an article body and throw it away, returning its size on the wire
+    ///
+    /// `article` is a Message-ID (without angle brackets) or an article
+    /// number in `group`. `None` when the server doesn't have it.
+    pub async fn discard_body(&mut self, article: &str, group: &str) -> Result<Option<u64>> {
+        if self.current_group.as_deref() != Some(group) {
+            self.select_group(group).await?;
+        }
 
-        Ok(Bytes::from(decoded))
+        let command = if article.bytes().all(|b| b.is_ascii_digit()) {
+            format!("BODY {}", article)
+        } else {
+            format!("BODY <{}>", article)
+        };
+        self.send_command(&command).await?;
+        let response = timeout(Duration::from_secs(10), self.read_response())
+            .await
+            .map_err(|_| NntpError::Timeout { seconds: 10 })??;
+        match response.code {
+            222 => {}
+            // No such article, or no article with that number
+            430 | 423 | 420 => return Ok(None),
+            _ => return Err(response.into_error(article).into()),
+        }
+
+        let mut size = 0u64;
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            let read = timeout(
+                Duration::from_secs(30),
+                self.reader.read_until(b'\n', &mut line),
+            )
+            .await
+            .map_err(|_| NntpError::Timeout { seconds: 30 })??;
+            if read == 0 || line == b".\r\n" || line == b".\n" {
+                break;
+            }
+            size += read as u64;
+            self.bytes_read += read as u64;
+        }
+        protocol!(self, "< read {} bytes body", size);
+        Ok(Some(size))
     }
 
-    /// Read article body until termination
+    /// Read article body until termination, adding what it took on the wire
+    /// to [`bytes_read`](Self::bytes_read)
     async fn read_article_body(&mut self) -> Result<Vec<u8>> {
         use tokio::io::AsyncBufReadExt;
 
         let mut body = Vec::with_capacity(1024 * 1024); // Pre-allocate 1MB for larger segments
         let mut line = Vec::new();
+        let mut wire_bytes = 0u64;
 
         loop {
             line.clear();
@@ -198,13 +487,18 @@ impl AsyncNntpConnection {
             // Read line efficiently using BufRead
             let bytes_read = self.reader.read_until(b'\n', &mut line).await?;
             if bytes_read == 0 {
-                break; // EOF
+                // Closed before the terminating dot: the body is cut short
+                self.broken = true;
+                self.closed_by_server = true;
+                return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
             }
 
             // Check for termination (single dot followed by newline)
             if line == b".\r\n" || line == b".\n" {
                 break;
             }
+            wire_bytes += bytes_read as u64;
+            self.bytes_read += bytes_read as u64;
 
             // Handle dot-stuffing (lines starting with .. become .)
             if line.len() >= 2 && line[0] == b'.' && line[1] == b'.' {
@@ -223,83 +517,94 @@ impl AsyncNntpConnection {
             body.push(b'\n'); // Add newline back for yenc decoder
         }
 
+        protocol!(self, "< read {} bytes body", wire_bytes);
         Ok(body)
     }
 
-    /// Optimized yEnc decoder with pre-allocation and efficient iteration
-    fn decode_yenc_simple(&self, data: &[u8]) -> Result<Vec<u8>> {
-        // Pre-allocate based on expected output size (roughly same as input)
-        let mut decoded = Vec::with_capacity(data.len());
-        let mut in_data = false;
-
-        // Use split for efficient line iteration
-        for line in data.split(|&b| b == b'\n') {
-            // Check for yEnc markers
-            if line.starts_with(b"=ybegin") {
-                in_data = true;
-                continue;
-            }
-            if line.starts_with(b"=yend") {
-                break;
-            }
-            if line.starts_with(b"=ypart") {
-                continue;
-            }
-
-            if in_data && !line.is_empty() {
-                // Decode the line using iterator for better performance
-                let mut iter = line.iter().copied();
-                while let Some(byte) = iter.next() {
-                    if byte == b'=' {
-                        // Escaped character
-                        if let Some(next_byte) = iter.next() {
-                            decoded.push(next_byte.wrapping_sub(64).wrapping_sub(42));
-                        }
-                    } else if byte != b'\r' {
-                        // Normal character (skip carriage returns)
-                        decoded.push(byte.wrapping_sub(42));
-                    }
-                }
-            }
-        }
-
-        // Shrink to actual size if we over-allocated
-        decoded.shrink_to_fit();
-        Ok(decoded)
-    }
-
     async fn send_command(&mut self, command: &str) -> Result<()> {
+        protocol!(self, "> {}", redact_command(command));
         self.writer.write_all(command.as_bytes()).await?;
         self.writer.write_all(b"\r\n").await?;
         self.writer.flush().await?;
         Ok(())
     }
 
-    async fn read_response(&mut self) -> Result<String> {
-        let mut response = String::new();
-        self.reader.read_line(&mut response).await?;
+    async fn read_response(&mut self) -> Result<NntpResponse> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line).await? == 0 {
+            self.broken = true;
+            self.closed_by_server = true;
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
 
         // Remove CRLF
-        if response.ends_with("\r\n") {
-            response.truncate(response.len() - 2);
-        } else if response.ends_with('\n') {
-            response.truncate(response.len() - 1);
+        let line = line.trim_end_matches(['\r', '\n']);
+        protocol!(self, "< {}", line);
+        let response = NntpResponse::parse(line);
+        match &response {
+            Ok(NntpResponse { code: 400, .. }) => {
+                self.broken = true;
+                self.closed_by_server = true;
+            }
+            Err(_) => self.broken = true,
+            Ok(_) => {}
         }
-
-        Ok(response)
+        Ok(response?)
     }
 
     /// Check if connection is healthy by sending a NOOP
     pub async fn is_healthy(&mut self) -> bool {
+        if self.broken {
+            return false;
+        }
         match self.send_command("NOOP").await {
             Ok(_) => match timeout(Duration::from_secs(5), self.read_response()).await {
-                Ok(Ok(response)) => response.starts_with("200"),
+                Ok(Ok(response)) => response.code == 200,
                 _ => false,
             },
             Err(_) => false,
         }
     }
 
+    /// Check whether an article exists without fetching it (`STAT`)
+    pub async fn stat(&mut self, message_id: &str) -> Result<bool> {
+        self.send_command(&format!("STAT <{}>", message_id)).await?;
+        let response = timeout(Duration::from_secs(10), self.read_response())
+            .await
+            .map_err(|_| NntpError::Timeout { seconds: 10 })??;
+
+        match response.code {
+            223 => Ok(true),
+            430 | 423 => Ok(false),
+            _ => Err(response.into_error(message_id).into()),
+        }
+    }
+
+    /// The server's clock in UTC (`DATE`, RFC 3977 section 7.1)
+    pub async fn server_time(&mut self) -> Result<chrono::NaiveDateTime> {
+        self.send_command("DATE").await?;
+        let response = timeout(Duration::from_secs(10), self.read_response())
+            .await
+            .map_err(|_| NntpError::Timeout { seconds: 10 })??;
+
+        if response.code != 111 {
+            return Err(response.into_error("DATE").into());
+        }
+        chrono::NaiveDateTime::parse_from_str(&response.message, "%Y%m%d%H%M%S").map_err(|_| {
+            NntpError::ProtocolError(format!("Unexpected DATE reply: {}", response)).into()
+        })
+    }
+
+    /// Seconds the local clock is ahead of the server's, behind when
+    /// negative (see [`clock`](super::clock))
+    pub async fn clock_skew(&mut self) -> Result<i64> {
+        let server_time = self.server_time().await?;
+        Ok(super::clock::skew_seconds(
+            server_time,
+            chrono::Utc::now().naive_utc(),
+        ))
+    }
+
     /// Download multiple segments using pipelining for maximum throughput
     ///
     /// This sends multiple BODY commands before waiting for responses,
@@ -307,29 +612,85 @@ impl AsyncNntpConnection {
     pub async fn download_segments_pipelined(
         &mut self,
         requests: &[SegmentRequest],
-    ) -> Result<Vec<(u32, Option<Bytes>)>> {
+    ) -> Result<FetchedBatch> {
+        self.download_segments_throttled(requests, None, None).await
+    }
+
+    /// Pipelined download that waits on a shared speed limit after each article
+    ///
+    /// Pausing between articles stops reading from the socket, so the server is
+    /// slowed down by TCP backpressure rather than data being buffered locally.
+    /// With `memory`, each decoded article holds its share of that reservation
+    /// until the data is dropped.
+    pub async fn download_segments_throttled(
+        &mut self,
+        requests: &[SegmentRequest],
+        throttle: Option<&Throttle>,
+        memory: Option<&Reservation>,
+    ) -> Result<FetchedBatch> {
+        let (mut batch, refused) = self.fetch_pipelined(requests, throttle, memory).await?;
+        if refused.is_empty() {
+            return Ok(batch);
+        }
+        if self.closed_by_server {
+            // A fresh connection logs in anyway
+            batch.interrupted.extend(refused);
+            return Ok(batch);
+        }
+
+        self.reauthenticate().await?;
+        let rest: Vec<SegmentRequest> = requests
+            .iter()
+            .filter(|req| refused.contains(&req.segment_number))
+            .cloned()
+            .collect();
+        let (again, refused) = self.fetch_pipelined(&rest, throttle, memory).await?;
+        if !refused.is_empty() {
+            return Err(self.still_unauthenticated());
+        }
+        batch.resume(again);
+        Ok(batch)
+    }
+
+    /// One pipelined pass over `requests`, with the segments refused by a
+    /// `480` (log in again) alongside the batch
+    async fn fetch_pipelined(
+        &mut self,
+        requests: &[SegmentRequest],
+        throttle: Option<&Throttle>,
+        memory: Option<&Reservation>,
+    ) -> Result<(FetchedBatch, Vec<u32>)> {
         if requests.is_empty() {
-            return Ok(Vec::new());
+            return Ok((FetchedBatch::default(), Vec::new()));
         }
 
         // Switch to the group if needed (all requests should be from same group)
         let group = &requests[0].group;
         if self.current_group.as_deref() != Some(group) {
-            self.send_command(&format!("GROUP {}", group)).await?;
-            let response = timeout(Duration::from_secs(10), self.read_response())
-                .await
-                .map_err(|_| NntpError::Timeout { seconds: 10 })??;
-            if !response.starts_with("211") {
-                return Err(NntpError::GroupNotFound {
-                    group: group.to_string(),
+            match self.select_group(group).await {
+                Err(DlNzbError::Nntp(NntpError::AuthRequired)) => {
+                    let batch = FetchedBatch {
+                        segments: requests.iter().map(|r| (r.segment_number, None)).collect(),
+                        ..FetchedBatch::default()
+                    };
+                    let refused = requests.iter().map(|r| r.segment_number).collect();
+                    return Ok((batch, refused));
+                }
+                result => {
+                    result?;
                 }
-                .into());
             }
-            self.current_group = Some(group.to_string());
         }
 
         // Pipeline all BODY requests - send them all without waiting
         for req in requests {
+            tracing::trace!(
+                conn = self.id,
+                message_id = %req.message_id,
+                segment = req.segment_number,
+                "Requesting article"
+            );
+            protocol!(self, "> BODY <{}>", req.message_id);
             self.writer
                 .write_all(format!("BODY <{}>\r\n", req.message_id).as_bytes())
                 .await?;
@@ -338,28 +699,89 @@ impl AsyncNntpConnection {
 
         // Now read all responses in order
         let mut results = Vec::with_capacity(requests.len());
-
-        for req in requests {
+        let read_before = self.bytes_read;
+        let mut missing = Vec::new();
+        let mut corrupt = Vec::new();
+        let mut offsets = Vec::new();
+        let mut file_size = None;
+        // Where the server closed the connection, leaving the rest unanswered
+        let mut closed_at = None;
+        let mut refused = Vec::new();
+
+        for (index, req) in requests.iter().enumerate() {
             // Read response code
-            let response = match timeout(Duration::from_secs(10), self.read_response()).await {
-                Ok(Ok(r)) => r,
-                _ => {
+            let response = timeout(Duration::from_secs(10), self.read_response())
+                .await
+                .unwrap_or_else(|_| Err(NntpError::Timeout { seconds: 10 }.into()));
+            let response = match response {
+                Ok(r) if r.code == 400 => {
+                    tracing::debug!(conn = self.id, reply = %r, "Server closed the connection");
+                    closed_at = Some(index);
+                    break;
+                }
+                Ok(r) => r,
+                Err(e) if self.closed_by_server => {
+                    tracing::debug!(conn = self.id, error = %e, "Server closed the connection");
+                    closed_at = Some(index);
+                    break;
+                }
+                Err(e) => {
+                    tracing::debug!(
+                        conn = self.id,
+                        message_id = %req.message_id,
+                        error = %e,
+                        "No reply for article"
+                    );
+                    // A late reply would be taken for the next article's
+                    self.broken = true;
                     results.push((req.segment_number, None));
                     continue;
                 }
             };
 
-            if !response.starts_with("222") {
-                // Article not found or error - we still need to read the body if server sent one
-                // to keep the connection in sync for remaining pipelined responses
-                if response.starts_with("430") || response.starts_with("423") {
-                    // 430 = no such article, 423 = no such article number
-                    // These don't send a body, safe to skip
+            match response.code {
+                222 => {}
+                // A multi-line reply we didn't ask for; read past it to stay in step
+                220 | 221 => {
+                    let _ = timeout(Duration::from_secs(30), self.read_article_body()).await;
                     results.push((req.segment_number, None));
                     continue;
-                } else {
-                    // Unknown response, try to read body anyway to avoid desync
-                    let _ = timeout(Duration::from_secs(30), self.read_article_body()).await;
+                }
+                // Lost our login; the rest of the replies are still read
+                // so the connection stays in step, and the caller logs in
+                // again
+                480 => {
+                    tracing::debug!(
+                        conn = self.id,
+                        message_id = %req.message_id,
+                        "Authentication required"
+                    );
+                    refused.push(req.segment_number);
+                    results.push((req.segment_number, None));
+                    continue;
+                }
+                // Every remaining article would be refused as well
+                481 | 502 => {
+                    tracing::debug!(
+                        conn = self.id,
+                        message_id = %req.message_id,
+                        code = response.code,
+                        "Batch refused"
+                    );
+                    self.broken = true;
+                    return Err(response.into_error(&req.message_id).into());
+                }
+                // No such article, or another error; neither sends a body
+                code => {
+                    tracing::debug!(
+                        conn = self.id,
+                        message_id = %req.message_id,
+                        code,
+                        "Article unavailable"
+                    );
+                    if matches!(code, 430 | 423) {
+                        missing.push(req.segment_number);
+                    }
                     results.push((req.segment_number, None));
                     continue;
                 }
@@ -369,24 +791,75 @@ impl AsyncNntpConnection {
             let encoded_data =
                 match timeout(Duration::from_secs(30), self.read_article_body()).await {
                     Ok(Ok(data)) => data,
+                    _ if self.closed_by_server => {
+                        tracing::debug!(
+                            conn = self.id,
+                            message_id = %req.message_id,
+                            "Server closed the connection mid-article"
+                        );
+                        closed_at = Some(index);
+                        break;
+                    }
                     _ => {
+                        tracing::debug!(
+                            conn = self.id,
+                            message_id = %req.message_id,
+                            "Article body not received"
+                        );
+                        // The rest of the body may still arrive as later replies
+                        self.broken = true;
                         results.push((req.segment_number, None));
                         continue;
                     }
                 };
 
-            // Decode yEnc
-            match self.decode_yenc_simple(&encoded_data) {
-                Ok(decoded) => {
-                    results.push((req.segment_number, Some(Bytes::from(decoded))));
-                }
-                Err(_) => {
-                    results.push((req.segment_number, None));
-                }
+            if let Some(throttle) = throttle {
+                throttle.consume(encoded_data.len() as u64).await;
+            }
+
+            let decoded = match memory {
+                Some(memory) => memory.hold(encoded_data.len(), || decode_yenc(&encoded_data)),
+                None => Bytes::from(decode_yenc(&encoded_data)),
+            };
+            if !yenc_checksum_matches(&encoded_data, &decoded) {
+                tracing::debug!(
+                    conn = self.id,
+                    message_id = %req.message_id,
+                    "Article failed its yEnc checksum"
+                );
+                corrupt.push(req.segment_number);
+            }
+            if let Some(begin) = yenc_part_begin(&encoded_data) {
+                offsets.push((req.segment_number, begin));
             }
+            if file_size.is_none() {
+                file_size = yenc_file_size(&encoded_data);
+            }
+            tracing::trace!(
+                conn = self.id,
+                message_id = %req.message_id,
+                bytes = decoded.len(),
+                "Article received"
+            );
+            results.push((req.segment_number, Some(decoded)));
+        }
+
+        let mut interrupted = Vec::new();
+        for req in closed_at.map_or(&[][..], |index| &requests[index..]) {
+            results.push((req.segment_number, None));
+            interrupted.push(req.segment_number);
         }
 
-        Ok(results)
+        let batch = FetchedBatch {
+            segments: results,
+            wire_bytes: self.bytes_read - read_before,
+            missing,
+            corrupt,
+            offsets,
+            interrupted,
+            file_size,
+        };
+        Ok((batch, refused))
     }
 
     /// Close the connection gracefully
@@ -397,3 +870,90 @@ impl AsyncNntpConnection {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact_command_hides_password() {
+        assert_eq!(
+            redact_command("AUTHINFO PASS hunter2"),
+            "AUTHINFO PASS ********"
+        );
+        assert_eq!(
+            redact_command("authinfo pass hunter2"),
+            "AUTHINFO PASS ********"
+        );
+        assert_eq!(redact_command("AUTHINFO USER bob"), "AUTHINFO USER bob");
+        assert_eq!(redact_command("BODY <id@example>"), "BODY <id@example>");
+    }
+
+    #[tokio::test]
+    async fn test_pipelined_batch_counts_wire_bytes() {
+        use std::io::{BufRead, BufReader, Write};
+
+        // 2 lines of 128 encoded bytes plus yEnc header and trailer lines
+        let body = format!(
+            "=ybegin line=128 size=256 name=a.bin\r\n{0}\r\n{0}\r\n=yend size=256\r\n",
+            "k".repeat(128)
+        );
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let served = body.clone();
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            writer.write_all(b"200 ready\r\n").unwrap();
+            for line in BufReader::new(stream).lines() {
+                let Ok(line) = line else { break };
+                let reply = if line.starts_with("AUTHINFO USER") {
+                    "381 more\r\n".to_string()
+                } else if line.starts_with("AUTHINFO PASS") {
+                    "281 ok\r\n".to_string()
+                } else if line.starts_with("GROUP") {
+                    "211 2 1 2 alt.test\r\n".to_string()
+                } else if line == "BODY <missing@x>" {
+                    "430 no such article\r\n".to_string()
+                } else if line.starts_with("BODY") {
+                    format!("222 0 body\r\n{}.\r\n", served)
+                } else {
+                    "500 what\r\n".to_string()
+                };
+                if writer.write_all(reply.as_bytes()).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let config = UsenetConfig {
+            server: "127.0.0.1".to_string(),
+            port,
+            ssl: false,
+            username: "u".to_string(),
+            password: "p".into(),
+            ..UsenetConfig::default()
+        };
+        let mut conn = AsyncNntpConnection::connect(&config, None).await.unwrap();
+        let request = |id: &str, number| SegmentRequest {
+            message_id: id.to_string(),
+            group: "alt.test".to_string(),
+            segment_number: number,
+        };
+        let batch = conn
+            .download_segments_pipelined(&[
+                request("a@x", 1),
+                request("missing@x", 2),
+                request("b@x", 3),
+            ])
+            .await
+            .unwrap();
+
+        assert_eq!(batch.wire_bytes, 2 * body.len() as u64);
+        assert_eq!(conn.bytes_read(), batch.wire_bytes);
+        assert_eq!(batch.segments[0].1.as_ref().map(|d| d.len()), Some(256));
+        assert!(batch.segments[1].1.is_none());
+        assert_eq!(batch.missing, [2]);
+        assert!(batch.corrupt.is_empty());
+    }
+}
@@ -3,8 +3,24 @@
 //! This module provides async NNTP connection handling with connection pooling,
 //! health checks, and optimized yEnc decoding.
 
+/// Tracing target of the protocol log: each command sent and reply line
+/// received, per connection
+///
+/// Off unless asked for with `-vvv` or `logging.protocol_trace`, even at
+/// trace level. `AUTHINFO PASS` arguments are masked, and article bodies
+/// only show up as their size.
+pub const PROTOCOL_TARGET: &str = "dl_nzb::protocol";
+
+pub mod clock;
 mod connection;
 mod pool;
+mod response;
+mod yenc;
 
-pub use connection::{AsyncNntpConnection, SegmentRequest};
-pub use pool::{NntpPool, NntpPoolBuilder, NntpPoolExt, PooledConnection};
+pub use connection::{AsyncNntpConnection, ConnectTimings, FetchedBatch, SegmentRequest};
+pub use pool::{HandshakeStats, NntpPool, NntpPoolBuilder, NntpPoolExt, PooledConnection};
+pub use response::NntpResponse;
+pub use yenc::{
+    decode_yenc, encode_yenc_part, yenc_checksum_matches, yenc_file_size, yenc_name,
+    yenc_part_begin,
+};
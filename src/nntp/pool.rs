@@ -3,23 +3,73 @@
 //! This module provides a robust connection pool that handles connection lifecycle,
 //! health checks, and automatic reconnection.
 
-use super::connection::AsyncNntpConnection;
+use super::clock;
+use super::connection::{AsyncNntpConnection, ConnectTimings, FetchedBatch, SegmentRequest};
 use crate::config::UsenetConfig;
-use crate::error::{DlNzbError, NntpError};
+use crate::error::{DlNzbError, ErrorClass, NntpError};
 use async_trait::async_trait;
 use bytes::Bytes;
 use deadpool::managed::{Manager, Pool, RecycleResult};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use tokio::time::Duration;
 
 /// Maximum concurrent connection creation attempts to avoid overwhelming the server
 const MAX_CONCURRENT_CONNECTION_CREATION: usize = 10;
 
+/// Articles pipelined on one connection by [`NntpPoolExt::download_segments`]
+const SEGMENT_BATCH_SIZE: usize = 50;
+
 /// Connection manager for deadpool with rate-limited creation
 pub struct NntpConnectionManager {
     config: Arc<UsenetConfig>,
     tls_connector: Option<Arc<tokio_native_tls::TlsConnector>>,
     creation_semaphore: Arc<tokio::sync::Semaphore>,
+    handshakes: Mutex<HandshakeStats>,
+    /// Connections found closed by the server (see [`NntpPoolExt::idle_closures`])
+    idle_closures: AtomicU64,
+    /// Whether the first connection has asked for the server's time yet
+    clock_checked: AtomicBool,
+}
+
+/// How long the pool's connections took to open
+///
+/// The first connection is kept apart from the average of the rest: when the
+/// TLS backend resumes sessions through the shared connector, later
+/// handshakes are markedly shorter than the first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HandshakeStats {
+    /// Connections opened so far
+    pub connections: u32,
+    pub first: Option<ConnectTimings>,
+    /// Mean of every connection after the first
+    pub later: Option<ConnectTimings>,
+    /// Seconds the local clock is ahead of the server's (behind when
+    /// negative), asked with `DATE` on the first connection; `None` when
+    /// the server doesn't answer it
+    pub clock_skew: Option<i64>,
+}
+
+impl HandshakeStats {
+    fn record(&mut self, timings: ConnectTimings) {
+        self.connections += 1;
+        if self.first.is_none() {
+            self.first = Some(timings);
+            return;
+        }
+        // Running mean over the connections after the first
+        let n = self.connections - 1;
+        let mean = |old: Duration, new: Duration| (old * (n - 1) + new) / n;
+        self.later = Some(match self.later {
+            None => timings,
+            Some(later) => ConnectTimings {
+                tcp: mean(later.tcp, timings.tcp),
+                tls: mean(later.tls, timings.tls),
+                greeting: mean(later.greeting, timings.greeting),
+                auth: mean(later.auth, timings.auth),
+            },
+        });
+    }
 }
 
 impl NntpConnectionManager {
@@ -50,8 +100,35 @@ impl NntpConnectionManager {
             config: Arc::new(config),
             tls_connector,
             creation_semaphore,
+            handshakes: Mutex::default(),
+            idle_closures: AtomicU64::new(0),
+            clock_checked: AtomicBool::new(false),
         })
     }
+
+    /// Compare the local clock with the server's on `conn`, warning when
+    /// they're far apart
+    ///
+    /// A server that doesn't take `DATE` is no reason to give up the
+    /// connection; one that stops answering is.
+    async fn check_clock(&self, conn: &mut AsyncNntpConnection) -> Result<(), DlNzbError> {
+        match conn.clock_skew().await {
+            Ok(skew) => {
+                if let Some(warning) = clock::skew_warning(&self.config.server, skew) {
+                    tracing::warn!("{}", warning);
+                }
+                if let Ok(mut handshakes) = self.handshakes.lock() {
+                    handshakes.clock_skew = Some(skew);
+                }
+                Ok(())
+            }
+            Err(e) if e.class() == ErrorClass::Connection => Err(e),
+            Err(e) => {
+                tracing::debug!(server = %self.config.server, error = %e, "No server time");
+                Ok(())
+            }
+        }
+    }
 }
 
 impl Manager for NntpConnectionManager {
@@ -68,12 +145,25 @@ impl Manager for NntpConnectionManager {
             })
         })?;
 
-        AsyncNntpConnection::connect(&self.config, self.tls_connector.clone())
+        let mut conn = AsyncNntpConnection::connect(&self.config, self.tls_connector.clone())
             .await
             .map_err(|e| {
-                tracing::debug!("Failed to create NNTP connection: {}", e);
+                tracing::debug!(
+                    server = %self.config.server,
+                    error = %e,
+                    "Failed to create NNTP connection"
+                );
                 e
-            })
+            })?;
+        if let Ok(mut handshakes) = self.handshakes.lock() {
+            handshakes.record(conn.timings());
+        }
+        // Once per pool: one DATE is cheap, and a clock days out is the
+        // hidden cause of baffling TLS and retention errors
+        if !self.clock_checked.swap(true, Ordering::Relaxed) {
+            self.check_clock(&mut conn).await?;
+        }
+        Ok(conn)
     }
 
     async fn recycle(
@@ -85,6 +175,9 @@ impl Manager for NntpConnectionManager {
         if conn.is_healthy().await {
             Ok(())
         } else {
+            if conn.closed_by_server() {
+                self.idle_closures.fetch_add(1, Ordering::Relaxed);
+            }
             Err(deadpool::managed::RecycleError::Backend(
                 NntpError::UnhealthyConnection.into(),
             ))
@@ -110,13 +203,82 @@ impl PooledConnection {
         self.conn.download_segment(message_id, group).await
     }
 
+    /// File name from a segment's yEnc header (see [`AsyncNntpConnection::yenc_name`])
+    pub async fn yenc_name(
+        &mut self,
+        message_id: &str,
+        group: &str,
+    ) -> Result<Option<String>, DlNzbError> {
+        self.conn.yenc_name(message_id, group).await
+    }
+
+    /// Id of the underlying connection, as it appears in log lines
+    pub fn id(&self) -> u64 {
+        self.conn.id()
+    }
+
+    /// Article body bytes the underlying connection has read
+    pub fn bytes_read(&self) -> u64 {
+        self.conn.bytes_read()
+    }
+
+    /// Time the underlying connection took to connect and log in
+    pub fn connect_time(&self) -> Duration {
+        self.conn.connect_time()
+    }
+
+    /// The steps of [`connect_time`](Self::connect_time)
+    pub fn timings(&self) -> ConnectTimings {
+        self.conn.timings()
+    }
+
+    /// What the server said on connecting
+    pub fn greeting(&self) -> &str {
+        self.conn.greeting()
+    }
+
+    /// Newsgroup the underlying connection has selected
+    pub fn current_group(&self) -> Option<&str> {
+        self.conn.current_group()
+    }
+
+    /// Fetch an article body without keeping it (see [`AsyncNntpConnection::discard_body`])
+    pub async fn discard_body(
+        &mut self,
+        article: &str,
+        group: &str,
+    ) -> Result<Option<u64>, DlNzbError> {
+        self.conn.discard_body(article, group).await
+    }
+
+    /// Select a newsgroup, returning its article number range
+    pub async fn select_group(
+        &mut self,
+        group: &str,
+    ) -> Result<std::ops::RangeInclusive<u64>, DlNzbError> {
+        self.conn.select_group(group).await
+    }
+
     /// Download multiple segments using pipelining
     pub async fn download_segments_pipelined(
         &mut self,
         requests: &[crate::nntp::SegmentRequest],
-    ) -> Result<Vec<(u32, Option<Bytes>)>, DlNzbError> {
+    ) -> Result<FetchedBatch, DlNzbError> {
         self.conn.download_segments_pipelined(requests).await
     }
+
+    /// Download multiple segments using pipelining under a shared speed limit,
+    /// decoding into memory reserved for them
+    pub async fn download_segments_throttled(
+        &mut self,
+        requests: &[crate::nntp::SegmentRequest],
+        throttle: &crate::download::Throttle,
+        memory: &crate::download::Reservation,
+    ) -> Result<FetchedBatch, DlNzbError> {
+        self.conn
+            .download_segments_throttled(requests, Some(throttle), Some(memory))
+            .await
+    }
 }
 
 /// Builder for creating connection pools with configuration
@@ -172,21 +334,218 @@ impl NntpPoolBuilder {
 pub trait NntpPoolExt {
     /// Get a connection from the pool
     async fn get_connection(&self) -> Result<PooledConnection, DlNzbError>;
+
+    /// Get a connection from the pool, preferring an idle one that already
+    /// has `group` selected
+    ///
+    /// `GROUP` is slow on some servers for the big binary groups, and
+    /// batches from files in different groups would otherwise have
+    /// connections switching back and forth. When no idle connection has
+    /// the group this is [`get_connection`](Self::get_connection).
+    async fn get_connection_preferring(&self, group: &str) -> Result<PooledConnection, DlNzbError>;
+
+    /// Say goodbye on every idle connection and close the pool
+    async fn close_all(&self);
+
+    /// Connect, TLS and login times of the connections opened so far
+    fn handshakes(&self) -> HandshakeStats;
+
+    /// Connections the server closed on its own, with a `400` (an idle
+    /// timeout, usually) or by hanging up
+    ///
+    /// Counted as the pool comes to reuse them and finds them closed; a
+    /// batch they cut short is fetched again on a fresh connection.
+    fn idle_closures(&self) -> u64;
+
+    /// Fetch and decode specific articles, without an NZB
+    ///
+    /// Requests are pipelined in batches per group, one batch per pooled
+    /// connection, and the results come back in request order. Each carries
+    /// its own error: [`NntpError::ArticleNotFound`] when the server doesn't
+    /// have the article, [`NntpError::YencDecode`] when the data fails its yEnc
+    /// checksum, and otherwise whatever fetching the article on its own
+    /// reported. `segment_number` is passed through untouched.
+    async fn download_segments(
+        &self,
+        requests: &[SegmentRequest],
+    ) -> Vec<(SegmentRequest, Result<Bytes, DlNzbError>)>;
 }
 
 #[async_trait]
 impl NntpPoolExt for NntpPool {
     async fn get_connection(&self) -> Result<PooledConnection, DlNzbError> {
         let conn = self.get().await.map_err(|e| {
-            tracing::debug!("Failed to get connection from pool: {}", e);
-            NntpError::ConnectionFailed {
-                server: "pool".to_string(),
-                port: 0,
-                source: std::io::Error::other(e),
+            tracing::debug!(
+                server = %self.manager().config.server,
+                error = %e,
+                "Failed to get connection from pool"
+            );
+            match e {
+                // A refused login is the same on every connection, and worth
+                // telling apart from a network problem
+                deadpool::managed::PoolError::Backend(e) if e.class() == ErrorClass::Auth => e,
+                e => NntpError::ConnectionFailed {
+                    server: "pool".to_string(),
+                    port: 0,
+                    source: std::io::Error::other(e),
+                }
+                .into(),
             }
         })?;
         Ok(PooledConnection { conn })
     }
+
+    async fn get_connection_preferring(&self, group: &str) -> Result<PooledConnection, DlNzbError> {
+        // Look at the idle connections without taking any out
+        let idle_in_group = AtomicUsize::new(0);
+        self.retain(|conn, _| {
+            if conn.current_group() == Some(group) {
+                idle_in_group.fetch_add(1, Ordering::Relaxed);
+            }
+            true
+        });
+
+        let mut conn = self.get_connection().await?;
+        if idle_in_group.into_inner() == 0 || conn.current_group() == Some(group) {
+            return Ok(conn);
+        }
+        // Go through the other idle ones until one has it; those passed
+        // over go back to the pool when this returns. Only while some are
+        // idle, so no connection is opened for this
+        let mut passed_over = Vec::new();
+        for _ in 0..self.status().available {
+            if self.status().available == 0 {
+                break;
+            }
+            let Ok(next) = self.get_connection().await else {
+                break;
+            };
+            let found = next.current_group() == Some(group);
+            passed_over.push(std::mem::replace(&mut conn, next));
+            if found {
+                break;
+            }
+        }
+        Ok(conn)
+    }
+
+    async fn close_all(&self) {
+        let idle = self.retain(|_, _| false).removed;
+        futures::future::join_all(idle.into_iter().map(|mut conn| async move {
+            let _ = conn.close().await;
+        }))
+        .await;
+        self.close();
+    }
+
+    fn handshakes(&self) -> HandshakeStats {
+        self.manager()
+            .handshakes
+            .lock()
+            .map(|stats| *stats)
+            .unwrap_or_default()
+    }
+
+    fn idle_closures(&self) -> u64 {
+        self.manager().idle_closures.load(Ordering::Relaxed)
+    }
+
+    async fn download_segments(
+        &self,
+        requests: &[SegmentRequest],
+    ) -> Vec<(SegmentRequest, Result<Bytes, DlNzbError>)> {
+        // Positions in `requests`, grouped so each batch needs a single GROUP
+        let mut groups: Vec<(&str, Vec<usize>)> = Vec::new();
+        for (index, request) in requests.iter().enumerate() {
+            match groups.iter_mut().find(|(group, _)| *group == request.group) {
+                Some((_, indices)) => indices.push(index),
+                None => groups.push((request.group.as_str(), vec![index])),
+            }
+        }
+        for (_, indices) in &mut groups {
+            indices.sort_by_key(|&index| requests[index].segment_number);
+        }
+
+        let batches = groups
+            .iter()
+            .flat_map(|(_, indices)| indices.chunks(SEGMENT_BATCH_SIZE))
+            .map(|indices| fetch_batch(self, requests, indices));
+        let mut results: Vec<_> = futures::future::join_all(batches)
+            .await
+            .into_iter()
+            .flatten()
+            .collect();
+        results.sort_by_key(|(index, _)| *index);
+
+        requests
+            .iter()
+            .cloned()
+            .zip(results.into_iter().map(|(_, result)| result))
+            .collect()
+    }
+}
+
+/// Fetch `requests[i]` for each of `indices` as one pipelined batch
+async fn fetch_batch(
+    pool: &NntpPool,
+    requests: &[SegmentRequest],
+    indices: &[usize],
+) -> Vec<(usize, Result<Bytes, DlNzbError>)> {
+    // Numbered by position, so the batch's missing and corrupt lists can be
+    // matched up whatever numbers the caller used
+    let batch: Vec<SegmentRequest> = indices
+        .iter()
+        .enumerate()
+        .map(|(position, &index)| SegmentRequest {
+            segment_number: position as u32,
+            ..requests[index].clone()
+        })
+        .collect();
+
+    let fetched = match pool.get_connection_preferring(&batch[0].group).await {
+        Ok(mut conn) => conn.download_segments_pipelined(&batch).await,
+        Err(e) => Err(e),
+    };
+    let fetched = match fetched {
+        Ok(fetched) => fetched,
+        Err(e) => {
+            tracing::debug!(error = %e, "Segment batch failed, fetching one at a time");
+            FetchedBatch {
+                segments: batch.iter().map(|r| (r.segment_number, None)).collect(),
+                ..FetchedBatch::default()
+            }
+        }
+    };
+
+    let mut results = Vec::with_capacity(indices.len());
+    for (&index, (position, data)) in indices.iter().zip(fetched.segments) {
+        let request = &requests[index];
+        let result = if fetched.corrupt.contains(&position) {
+            Err(
+                NntpError::YencDecode(format!("{} failed its yEnc checksum", request.message_id))
+                    .into(),
+            )
+        } else if fetched.missing.contains(&position) {
+            Err(NntpError::ArticleNotFound {
+                message_id: request.message_id.clone(),
+            }
+            .into())
+        } else if let Some(data) = data {
+            Ok(data)
+        } else {
+            // No usable reply in the batch; asking again on its own gives the
+            // real error
+            match pool.get_connection().await {
+                Ok(mut conn) => {
+                    conn.download_segment(&request.message_id, &request.group)
+                        .await
+                }
+                Err(e) => Err(e),
+            }
+        };
+        results.push((index, result));
+    }
+    results
 }
 
 #[cfg(test)]
@@ -201,4 +560,107 @@ mod tests {
         // Pool creation should succeed even if we can't connect
         assert!(result.is_ok() || result.is_err());
     }
+
+    #[test]
+    fn test_handshake_stats_average_later_connections() {
+        let timings = |tls_ms| ConnectTimings {
+            tcp: Duration::from_millis(10),
+            tls: Duration::from_millis(tls_ms),
+            greeting: Duration::from_millis(5),
+            auth: Duration::from_millis(20),
+        };
+        let mut stats = HandshakeStats::default();
+        stats.record(timings(90));
+        assert_eq!(stats.first, Some(timings(90)));
+        assert_eq!(stats.later, None);
+
+        for tls_ms in [30, 40, 50] {
+            stats.record(timings(tls_ms));
+        }
+        assert_eq!(stats.connections, 4);
+        assert_eq!(stats.first, Some(timings(90)));
+        assert_eq!(stats.later, Some(timings(40)));
+    }
+
+    #[tokio::test]
+    async fn test_download_segments_types_each_result() {
+        use std::io::{BufRead, BufReader, Write};
+
+        let good = format!(
+            "=ybegin line=128 size=128 name=a.bin\r\n{}\r\n=yend size=128\r\n",
+            "k".repeat(128)
+        );
+        let corrupt = format!(
+            "=ybegin line=128 size=128 name=b.bin\r\n{}\r\n=yend size=128 crc32=00000000\r\n",
+            "k".repeat(128)
+        );
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else { break };
+                let (good, corrupt) = (good.clone(), corrupt.clone());
+                std::thread::spawn(move || {
+                    let mut writer = stream.try_clone().unwrap();
+                    writer.write_all(b"200 ready\r\n").unwrap();
+                    for line in BufReader::new(stream).lines() {
+                        let Ok(line) = line else { break };
+                        let reply = if line.starts_with("AUTHINFO USER") {
+                            "381 more\r\n".to_string()
+                        } else if line.starts_with("AUTHINFO PASS") {
+                            "281 ok\r\n".to_string()
+                        } else if let Some(group) = line.strip_prefix("GROUP ") {
+                            format!("211 2 1 2 {}\r\n", group)
+                        } else if line == "BODY <missing@x>" {
+                            "430 no such article\r\n".to_string()
+                        } else if line == "BODY <corrupt@x>" {
+                            format!("222 0 body\r\n{}.\r\n", corrupt)
+                        } else if line.starts_with("BODY") {
+                            format!("222 0 body\r\n{}.\r\n", good)
+                        } else {
+                            "200 ok\r\n".to_string()
+                        };
+                        if writer.write_all(reply.as_bytes()).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+
+        let config = UsenetConfig {
+            server: "127.0.0.1".to_string(),
+            port,
+            ssl: false,
+            username: "u".to_string(),
+            password: "p".into(),
+            ..UsenetConfig::default()
+        };
+        let pool = NntpPoolBuilder::new(config).max_size(1).build().unwrap();
+        let request = |id: &str, group: &str| SegmentRequest {
+            message_id: id.to_string(),
+            group: group.to_string(),
+            segment_number: 7,
+        };
+        let requests = [
+            request("a@x", "alt.test"),
+            request("missing@x", "alt.test"),
+            request("b@x", "alt.other"),
+            request("corrupt@x", "alt.test"),
+        ];
+        let results = pool.download_segments(&requests).await;
+
+        assert_eq!(results.len(), 4);
+        assert!(results.iter().zip(&requests).all(|((r, _), q)| r == q));
+        assert_eq!(results[0].1.as_ref().map(|d| d.len()).ok(), Some(128));
+        assert!(matches!(
+            results[1].1,
+            Err(DlNzbError::Nntp(NntpError::ArticleNotFound { .. }))
+        ));
+        assert!(results[2].1.is_ok());
+        assert!(matches!(
+            results[3].1,
+            Err(DlNzbError::Nntp(NntpError::YencDecode(_)))
+        ));
+    }
 }
@@ -0,0 +1,148 @@
+//! NNTP status lines (RFC 3977 section 3.2)
+
+use crate::error::NntpError;
+
+/// A status line: three-digit code plus the rest of the line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NntpResponse {
+    pub code: u16,
+    pub message: String,
+}
+
+impl NntpResponse {
+    /// Split a status line (without CRLF) into code and message
+    ///
+    /// The code must be exactly three digits from 100 to 599, followed by
+    /// the end of the line or whitespace.
+    pub fn parse(line: &str) -> Result<Self, NntpError> {
+        let malformed = || NntpError::ProtocolError(format!("Malformed status line: {:?}", line));
+        let digits = line.get(..3).ok_or_else(malformed)?;
+        if !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(malformed());
+        }
+        let rest = &line[3..];
+        if !rest.is_empty() && !rest.starts_with([' ', '\t']) {
+            return Err(malformed());
+        }
+        let code: u16 = digits.parse().map_err(|_| malformed())?;
+        if !(100..600).contains(&code) {
+            return Err(malformed());
+        }
+        Ok(Self {
+            code,
+            message: rest.trim().to_string(),
+        })
+    }
+
+    /// 1xx informational or 2xx success
+    pub fn is_ok(&self) -> bool {
+        self.code < 300
+    }
+
+    /// The error a failed command reports, for the article, group or server
+    /// named by `subject`
+    pub fn into_error(self, subject: &str) -> NntpError {
+        match self.code {
+            // No such article (by Message-ID or by number)
+            430 | 423 => NntpError::ArticleNotFound {
+                message_id: subject.to_string(),
+            },
+            411 => NntpError::GroupNotFound {
+                group: subject.to_string(),
+            },
+            480 => NntpError::AuthRequired,
+            // Only the code: the rest of the line may echo credentials
+            481 | 482 => NntpError::AuthFailed(format!("Authentication failed ({})", self.code)),
+            400 => NntpError::ServerClosing {
+                message: self.message,
+            },
+            502 => NntpError::AccessDenied {
+                message: self.message,
+            },
+            code => NntpError::ServerError {
+                code,
+                message: self.message,
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for NntpResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.message.is_empty() {
+            write!(f, "{}", self.code)
+        } else {
+            write!(f, "{} {}", self.code, self.message)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_status_lines() {
+        let ok = NntpResponse::parse("222 0 <a@b> body follows").unwrap();
+        assert_eq!(ok.code, 222);
+        assert_eq!(ok.message, "0 <a@b> body follows");
+        assert!(ok.is_ok());
+
+        let bare = NntpResponse::parse("205").unwrap();
+        assert_eq!((bare.code, bare.message.as_str()), (205, ""));
+        assert_eq!(
+            NntpResponse::parse("430\tNo such article").unwrap().code,
+            430
+        );
+
+        for line in [
+            "",
+            "22",
+            "2220 too long",
+            "22a x",
+            "-222 x",
+            " 222 x",
+            "099 x",
+            "600 x",
+            "abc",
+            "200x",
+            "２２２ x",
+        ] {
+            assert!(NntpResponse::parse(line).is_err(), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn test_codes_map_to_errors() {
+        let error = |line: &str| NntpResponse::parse(line).unwrap().into_error("subject");
+        assert!(matches!(
+            error("430 no such article"),
+            NntpError::ArticleNotFound { .. }
+        ));
+        assert!(matches!(
+            error("423 no article"),
+            NntpError::ArticleNotFound { .. }
+        ));
+        assert!(matches!(
+            error("411 no such group"),
+            NntpError::GroupNotFound { .. }
+        ));
+        assert!(matches!(
+            error("480 auth required"),
+            NntpError::AuthRequired
+        ));
+        assert!(matches!(error("400 bye"), NntpError::ServerClosing { .. }));
+        assert!(matches!(
+            error("502 too many connections"),
+            NntpError::AccessDenied { .. }
+        ));
+        assert!(matches!(
+            error("503 fault"),
+            NntpError::ServerError { code: 503, .. }
+        ));
+        // The server's text after 481 is never kept
+        let auth = error("481 bad password hunter2");
+        assert!(matches!(auth, NntpError::AuthFailed(_)));
+        assert!(!auth.to_string().contains("hunter2"));
+    }
+}
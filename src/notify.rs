@@ -0,0 +1,222 @@
+//! Notifications when a run finishes
+//!
+//! Two transports, each switched on and filtered by outcome in
+//! `[notifications]`: a webhook that receives the same JSON document as
+//! `--json`, and a desktop notification (behind the `desktop-notifications`
+//! feature). Sending is best-effort and bounded by a timeout; failures are
+//! logged as warnings and never change the outcome of a download.
+
+use human_bytes::human_bytes;
+use std::time::Duration;
+
+use crate::config::{NotificationsConfig, NotifyEvent};
+use crate::download::{redact_filename_password, redact_nzb_path};
+use crate::json_output::RunSummary;
+
+/// How long a desktop notification may take to show before it is given up on
+const DESKTOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Sends a run's outcome to the configured transports
+#[derive(Debug, Clone)]
+pub struct Notifier {
+    config: NotificationsConfig,
+    client: reqwest::Client,
+}
+
+impl Notifier {
+    /// `None` when no transport is switched on
+    pub fn new(config: &NotificationsConfig, user_agent: &str) -> Option<Self> {
+        let mut config = config.clone();
+        if let Some(reason) = unavailable(&config) {
+            // Reported once at startup; see `unavailable`
+            tracing::debug!("Desktop notifications off: {}", reason);
+            config.desktop = false;
+        }
+        if !config.webhook && !config.desktop {
+            return None;
+        }
+
+        let client = reqwest::Client::builder()
+            .user_agent(user_agent)
+            .timeout(Duration::from_secs(config.webhook_timeout.max(1)))
+            .build()
+            .unwrap_or_default();
+        Some(Self { config, client })
+    }
+
+    /// Send `summary` to every transport that wants its outcome
+    pub async fn notify(&self, summary: &RunSummary) {
+        let event = NotifyEvent::of(summary.status);
+
+        let webhook = async {
+            if self.config.webhook && self.config.webhook_events.contains(&event) {
+                if let Err(e) = self.post_webhook(summary).await {
+                    tracing::warn!("Webhook notification failed: {}", e);
+                }
+            }
+        };
+        let desktop = async {
+            if self.config.desktop && self.config.desktop_events.contains(&event) {
+                let (title, body) = desktop_message(summary);
+                let shown = tokio::time::timeout(
+                    DESKTOP_TIMEOUT,
+                    tokio::task::spawn_blocking(move || show_desktop(&title, &body)),
+                )
+                .await;
+                match shown {
+                    Ok(Ok(Ok(()))) => {}
+                    Ok(Ok(Err(e))) => tracing::warn!("Desktop notification failed: {}", e),
+                    Ok(Err(e)) => tracing::warn!("Desktop notification failed: {}", e),
+                    Err(_) => tracing::warn!("Desktop notification timed out"),
+                }
+            }
+        };
+        tokio::join!(webhook, desktop);
+    }
+
+    async fn post_webhook(&self, summary: &RunSummary) -> Result<(), String> {
+        let body = serde_json::to_vec(summary).map_err(|e| e.to_string())?;
+        let mut request = self
+            .client
+            .post(&self.config.webhook_url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body);
+        if !self.config.webhook_token.is_empty() {
+            request = request.bearer_auth(self.config.webhook_token.expose());
+        }
+
+        let response = request.send().await.map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!(
+                "{} answered {}",
+                self.config.webhook_url,
+                response.status()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Why configured desktop notifications can't be shown, if they can't
+pub fn unavailable(config: &NotificationsConfig) -> Option<&'static str> {
+    (config.desktop && !cfg!(feature = "desktop-notifications")).then_some(
+        "notifications.desktop is set, but dl-nzb was built without the desktop-notifications feature",
+    )
+}
+
+/// Title and body for the desktop notification
+fn desktop_message(summary: &RunSummary) -> (String, String) {
+    let title = match NotifyEvent::of(summary.status) {
+        NotifyEvent::Success => "Download complete",
+        NotifyEvent::Partial => "Download finished with problems",
+        NotifyEvent::Failure => "Download failed",
+    };
+
+    let mut lines: Vec<String> = summary
+        .nzbs
+        .iter()
+        .map(|nzb| {
+            let name = nzb
+                .nzb
+                .file_name()
+                .map(|n| redact_filename_password(&n.to_string_lossy()).into_owned())
+                .unwrap_or_else(|| redact_nzb_path(&nzb.nzb).display().to_string());
+            if nzb.status.succeeded() {
+                format!("{} ({})", name, human_bytes(nzb.total_size as f64))
+            } else {
+                format!("{}: {}", name, nzb.status.describe())
+            }
+        })
+        .collect();
+    if let Some(reason) = &summary.reason {
+        lines.push(reason.clone());
+    }
+    (format!("dl-nzb: {}", title), lines.join("\n"))
+}
+
+#[cfg(feature = "desktop-notifications")]
+fn show_desktop(title: &str, body: &str) -> Result<(), String> {
+    notify_rust::Notification::new()
+        .appname("dl-nzb")
+        .summary(title)
+        .body(body)
+        .show()
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "desktop-notifications"))]
+fn show_desktop(_title: &str, _body: &str) -> Result<(), String> {
+    Err("built without the desktop-notifications feature".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Secret;
+    use crate::error::RunStatus;
+    use crate::json_output::DownloadSummary;
+    use std::io::{Read, Write};
+    use std::path::Path;
+
+    fn summary(status: RunStatus) -> RunSummary {
+        let error = crate::error::NzbError::EmptyNzb(None).into();
+        let mut nzb = DownloadSummary::failed(Path::new("/nzbs/show.nzb"), None, &error);
+        nzb.status = status;
+        RunSummary::new(vec![nzb], Vec::new(), Duration::from_secs(1))
+    }
+
+    #[tokio::test]
+    async fn test_webhook_posts_run_summary() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 4096];
+            // Read until the JSON body has arrived
+            while !String::from_utf8_lossy(&request).trim_end().ends_with('}') {
+                let n = stream.read(&mut buf).unwrap();
+                if n == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let config = NotificationsConfig {
+            webhook: true,
+            webhook_url: url,
+            webhook_token: Secret::new("t0ken"),
+            webhook_events: vec![NotifyEvent::Failure],
+            ..NotificationsConfig::default()
+        };
+        let notifier = Notifier::new(&config, "dl-nzb-test").unwrap();
+        notifier.notify(&summary(RunStatus::Aborted)).await;
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /hook "), "{}", request);
+        assert!(
+            request.contains("authorization: Bearer t0ken"),
+            "{}",
+            request
+        );
+        let body = &request[request.find("\r\n\r\n").unwrap() + 4..];
+        let parsed: RunSummary = serde_json::from_str(body).unwrap();
+        assert_eq!(parsed.status, RunStatus::Aborted);
+        assert_eq!(parsed.nzbs.len(), 1);
+    }
+
+    #[test]
+    fn test_disabled_and_filtered() {
+        assert!(Notifier::new(&NotificationsConfig::default(), "ua").is_none());
+
+        let (title, body) = desktop_message(&summary(RunStatus::Incomplete));
+        assert_eq!(title, "dl-nzb: Download finished with problems");
+        assert!(body.starts_with("show.nzb: incomplete"), "{}", body);
+    }
+}
@@ -0,0 +1,203 @@
+//! One call from an NZB to downloaded files
+//!
+//! [`download()`] does what a plain `dl-nzb <nzb>` run does for one NZB:
+//! load it from a path, URL or `-` (stdin), download it into its own folder
+//! under `download.dir`, then repair and extract it as `post_processing`
+//! says. It's a thin layer over [`Downloader`] and [`PostProcessor`]; queues,
+//! history, sidecars and scripts are the binary's business, and anything
+//! more particular is better done with those types directly.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::download::{
+    nzb_temp_dir, remove_empty_folders, Downloader, FolderFields, LoadedNzb, NzbDownload,
+    NzbSource, Permissions,
+};
+use crate::error::{Result, RunStatus};
+use crate::processing::{Manifest, PostProcessResult, PostProcessor};
+use crate::progress::{NoProgress, ProgressSink};
+
+/// How [`download()`] runs
+#[derive(Clone)]
+pub struct DownloadOptions {
+    config: Option<Config>,
+    post_process: bool,
+    progress: Arc<dyn ProgressSink>,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        Self {
+            config: None,
+            post_process: true,
+            progress: Arc::new(NoProgress),
+        }
+    }
+}
+
+impl DownloadOptions {
+    /// The usual config file, post-processing on, no progress reported
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use `config` instead of loading the config file
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Repair and extract after downloading, as `post_processing` says (on by default)
+    pub fn post_process(mut self, post_process: bool) -> Self {
+        self.post_process = post_process;
+        self
+    }
+
+    /// Report progress to `sink` (silent by default)
+    pub fn progress(mut self, sink: Arc<dyn ProgressSink>) -> Self {
+        self.progress = sink;
+        self
+    }
+}
+
+/// What [`download()`] did with one NZB
+#[derive(Debug)]
+pub struct NzbOutcome {
+    /// The NZB's name (file stem, served filename or title)
+    pub name: String,
+    /// Folder the files were downloaded into
+    pub output_dir: PathBuf,
+    pub download: NzbDownload,
+    /// Repair and extraction, unless turned off or nothing was enabled
+    pub post_processing: Option<PostProcessResult>,
+}
+
+impl NzbOutcome {
+    /// Whether every file downloaded whole, or was repaired
+    pub fn is_complete(&self) -> bool {
+        let repaired = self
+            .post_processing
+            .as_ref()
+            .is_some_and(|p| p.par2_repaired || p.par2_verified);
+        self.download.failures.is_empty()
+            && (repaired || self.download.results.iter().all(|r| r.segments_failed == 0))
+    }
+}
+
+/// Download the NZB at `path_or_url` (a file, an `http(s)://` URL,
+/// `indexer:<name>:<id>`, or `-`)
+///
+/// Errors loading the config or the NZB, or before anything downloads, are
+/// returned; files that fail later are in the outcome's results and failures.
+///
+/// ```no_run
+/// #[tokio::main]
+/// async fn main() -> dl_nzb::Result<()> {
+///     let outcome = dl_nzb::download("some.release.nzb", Default::default()).await?;
+///     println!("{} files in {}", outcome.download.results.len(), outcome.output_dir.display());
+///     Ok(())
+/// }
+/// ```
+pub async fn download(
+    path_or_url: impl AsRef<Path>,
+    options: DownloadOptions,
+) -> Result<NzbOutcome> {
+    let config = match options.config {
+        Some(config) => config,
+        None => Config::load()?,
+    };
+    let source = NzbSource::from_arg(path_or_url.as_ref());
+    let LoadedNzb {
+        nzb,
+        name,
+        password,
+        ..
+    } = LoadedNzb::load(&source, &config).await?;
+
+    let output_dir = if config.download.create_subfolders {
+        config
+            .download
+            .dir
+            .join(FolderFields::new(&name, &nzb, None).folder(&config.download.folder_template))
+    } else {
+        config.download.dir.clone()
+    };
+    let base_dir = config.download.dir.clone();
+    let mut nzb_config = config.clone();
+    nzb_config.download.dir = output_dir.clone();
+    nzb_config.download.temp_dir = config
+        .download
+        .temp_dir
+        .as_deref()
+        .map(|temp_dir| nzb_temp_dir(temp_dir, &name));
+
+    let downloader = Downloader::builder(config)
+        .progress(Arc::clone(&options.progress))
+        .build()
+        .await?;
+    let download = match downloader.download_nzb(&nzb, nzb_config.clone()).await {
+        Ok(download) => download,
+        Err(aborted) => {
+            // What it got to goes as a finished download's would, except
+            // that a shutdown keeps it for the next run
+            let cancelled = aborted.error.run_status() == RunStatus::Cancelled;
+            Downloader::cleanup_partial_files(
+                &output_dir,
+                &aborted.download.results,
+                cancelled || nzb_config.download.keep_partial,
+            )
+            .await;
+            // Don't leave the folders the template named behind, empty
+            remove_empty_folders(&base_dir, &output_dir);
+            return Err(aborted.error);
+        }
+    };
+
+    let post = &nzb_config.post_processing;
+    let post_processing = if options.post_process
+        && (post.auto_par2_repair || post.auto_extract_rar || post.verify_media)
+    {
+        let processor = PostProcessor::new(post.clone(), nzb_config.tuning.large_file_threshold)
+            // A password in the NZB's file name goes before its own
+            .with_passwords(
+                password
+                    .iter()
+                    .map(|p| p.expose().to_string())
+                    .chain(nzb.passwords().iter().cloned())
+                    .collect(),
+            )
+            .with_temp_dir(nzb_config.download.temp_dir.clone())
+            .with_fsync(nzb_config.download.fsync)
+            // Already checked when the download started
+            .with_permissions(Permissions::from_config(&nzb_config.download).unwrap_or_default())
+            .with_progress(options.progress);
+        Some(
+            processor
+                .process_downloads(&Manifest::from_results(&download.results))
+                .await?,
+        )
+    } else {
+        None
+    };
+
+    // What PAR2 couldn't complete is deleted unless asked to keep it, as the
+    // binary does
+    let repaired = post_processing
+        .as_ref()
+        .is_some_and(|p| p.par2_repaired || p.par2_verified);
+    Downloader::cleanup_partial_files(
+        &output_dir,
+        if repaired { &[] } else { &download.results },
+        nzb_config.download.keep_partial,
+    )
+    .await;
+
+    Ok(NzbOutcome {
+        name,
+        output_dir,
+        download,
+        post_processing,
+    })
+}
@@ -20,14 +20,11 @@ pub mod rar {
     static OLD_STYLE_SPLIT_REGEX: Lazy<Regex> =
         Lazy::new(|| Regex::new(r"(?i)\.r\d{2}$").expect("valid regex"));
 
-    /// Matches the base name of a RAR archive (before .partNN or .rNN)
-    static BASE_NAME_REGEX: Lazy<Regex> =
-        Lazy::new(|| Regex::new(r"(?i)(.*?)(?:\.part\d+|\.r\d{2})?\.rar$").expect("valid regex"));
-
     /// Check if path is a RAR archive that should be extracted
     /// Returns true for:
     /// - Single RAR files (archive.rar)
     /// - First part of multi-part archives (archive.part01.rar, archive.part001.rar)
+    ///
     /// Does NOT return true for:
     /// - Later parts (.part02.rar, .part003.rar, etc.)
     /// - Old-style splits (.r00, .r01, etc.) - these are handled via the main .rar
@@ -59,25 +56,38 @@ pub mod rar {
 
     /// Extract base name from RAR archive for finding related parts
     pub fn extract_base_name(filename: &str) -> Option<&str> {
-        BASE_NAME_REGEX
-            .captures(filename)
-            .and_then(|caps| caps.get(1))
-            .map(|m| m.as_str())
+        Volume::parse(filename)
+            .filter(|volume| volume.kind == VolumeKind::Rar)
+            .map(|volume| volume.set)
     }
 
-    /// Check if two filenames belong to the same RAR archive set
-    pub fn is_same_archive(base_name: &str, other_filename: &str) -> bool {
-        let lower_base = base_name.to_lowercase();
-        let lower_other = other_filename.to_lowercase();
+    /// Check if a file is a volume of the RAR archive set `base_name`
+    pub fn is_same_archive(base_name: &str, other_filename: &str, matching: Matching) -> bool {
+        Volume::parse(other_filename)
+            .is_some_and(|v| v.kind == VolumeKind::Rar && v.is_in(base_name, matching))
+    }
 
-        // Same base name and is a RAR-related file
-        lower_other.starts_with(&lower_base) && is_rar_related(other_filename)
+    /// Check if a file is a RAR recovery volume (.rev) of the given archive set
+    pub fn is_same_recovery_volume(
+        base_name: &str,
+        other_filename: &str,
+        matching: Matching,
+    ) -> bool {
+        Volume::parse(other_filename)
+            .is_some_and(|v| v.kind == VolumeKind::RarRecovery && v.is_in(base_name, matching))
     }
 }
 
 /// PAR2 file patterns
 pub mod par2 {
-    use std::path::Path;
+    use super::*;
+
+    /// Matches the set name of a PAR2 file (before .volNN+NN)
+    static BASE_NAME_REGEX: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"(?i)^(.*?)(?:\.vol\d+[+-]\d+)?\.par2$").expect("valid regex"));
+
+    /// Every PAR2 packet, and so every PAR2 file, starts with these bytes
+    pub const PACKET_MAGIC: &[u8; 8] = b"PAR2\0PKT";
 
     /// Check if path is a PAR2 file
     pub fn is_par2_file(path: &Path) -> bool {
@@ -96,8 +106,160 @@ pub mod par2 {
                 .map(|name| !name.to_lowercase().contains(".vol"))
                 .unwrap_or(false)
     }
+
+    /// Whether the file at `path` starts with a PAR2 packet, whatever its name
+    pub fn has_packet_magic(path: &Path) -> bool {
+        let mut header = [0u8; 8];
+        std::fs::File::open(path)
+            .and_then(|mut file| std::io::Read::read_exact(&mut file, &mut header))
+            .is_ok_and(|()| &header == PACKET_MAGIC)
+    }
+
+    /// Extract the set name from a PAR2 file name
+    pub fn extract_base_name(filename: &str) -> Option<&str> {
+        BASE_NAME_REGEX
+            .captures(filename)
+            .and_then(|caps| caps.get(1))
+            .map(|m| m.as_str())
+    }
+
+    /// Check if a file is the index or a recovery volume of the PAR2 set `set_name`
+    pub fn is_same_set(set_name: &str, other_filename: &str, matching: Matching) -> bool {
+        Volume::parse(other_filename)
+            .is_some_and(|v| v.kind == VolumeKind::Par2 && v.is_in(set_name, matching))
+    }
+}
+
+/// File name matching shared by set grouping, integrity checks and deletion
+///
+/// Names compare the way the download folder's filesystem would: ignoring
+/// case on macOS and Windows, whose default filesystems do, and exactly
+/// elsewhere, where `show.rar` and `Show.rar` are two files. Volumes are
+/// matched by parsing each name into set name, volume number and extension,
+/// never by prefix, so set "Show.S01" doesn't take in
+/// "Show.S01.Extras.part01.rar".
+pub mod names {
+    use super::*;
+
+    /// Old-style RAR split: `.r00`, `.r01`, ...
+    static OLD_STYLE_SPLIT_REGEX: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"(?i)^(.+)\.(r(\d{2}))$").expect("valid regex"));
+
+    /// `.rar` or `.rev`, with an optional `.partNN`
+    static RAR_VOLUME_REGEX: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"(?i)^(.+?)(?:\.part(\d+))?\.(rar|rev)$").expect("valid regex"));
+
+    /// `.par2`, with an optional `.volNN+NN`
+    static PAR2_VOLUME_REGEX: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r"(?i)^(.+?)(?:\.vol(\d+)[+-]\d+)?\.(par2)$").expect("valid regex")
+    });
+
+    /// How two file names are compared
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Matching {
+        /// Byte for byte, as on Linux filesystems
+        Exact,
+        /// Ignoring case, as on the default macOS and Windows filesystems
+        IgnoreCase,
+    }
+
+    impl Matching {
+        /// How this platform's default filesystem compares names
+        pub const PLATFORM: Self = if cfg!(any(target_os = "macos", windows)) {
+            Self::IgnoreCase
+        } else {
+            Self::Exact
+        };
+
+        /// The form a name is compared in
+        pub fn normalize(self, name: &str) -> String {
+            match self {
+                Self::Exact => name.to_string(),
+                Self::IgnoreCase => name.to_lowercase(),
+            }
+        }
+
+        pub fn same(self, a: &str, b: &str) -> bool {
+            match self {
+                Self::Exact => a == b,
+                Self::IgnoreCase => a.to_lowercase() == b.to_lowercase(),
+            }
+        }
+    }
+
+    impl Default for Matching {
+        fn default() -> Self {
+            Self::PLATFORM
+        }
+    }
+
+    /// What kind of set a volume belongs to
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum VolumeKind {
+        /// `.rar`, `.partNN.rar` or an old-style `.rNN` split
+        Rar,
+        /// `.rev` or `.partNN.rev` recovery volume of a RAR set
+        RarRecovery,
+        /// `.par2` index or `.volNN+NN.par2` recovery volume
+        Par2,
+    }
+
+    /// A volume's file name, taken apart
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Volume<'a> {
+        /// Name of the set, as written
+        pub set: &'a str,
+        pub kind: VolumeKind,
+        /// The `partNN`, `rNN` or first `volNN` number; `None` for a plain
+        /// `.rar`, `.rev` or `.par2`
+        pub number: Option<u32>,
+        /// Extension as written, without the dot
+        pub ext: &'a str,
+    }
+
+    impl<'a> Volume<'a> {
+        /// Take apart a RAR, RAR recovery or PAR2 volume's name
+        pub fn parse(filename: &'a str) -> Option<Self> {
+            let number = |caps: &regex::Captures| caps.get(2).and_then(|m| m.as_str().parse().ok());
+            if let Some(caps) = RAR_VOLUME_REGEX.captures(filename) {
+                let ext = caps.get(3)?.as_str();
+                return Some(Self {
+                    set: caps.get(1)?.as_str(),
+                    kind: if ext.eq_ignore_ascii_case("rev") {
+                        VolumeKind::RarRecovery
+                    } else {
+                        VolumeKind::Rar
+                    },
+                    number: number(&caps),
+                    ext,
+                });
+            }
+            if let Some(caps) = PAR2_VOLUME_REGEX.captures(filename) {
+                return Some(Self {
+                    set: caps.get(1)?.as_str(),
+                    kind: VolumeKind::Par2,
+                    number: number(&caps),
+                    ext: caps.get(3)?.as_str(),
+                });
+            }
+            let caps = OLD_STYLE_SPLIT_REGEX.captures(filename)?;
+            Some(Self {
+                set: caps.get(1)?.as_str(),
+                kind: VolumeKind::Rar,
+                number: caps.get(3).and_then(|m| m.as_str().parse().ok()),
+                ext: caps.get(2)?.as_str(),
+            })
+        }
+
+        /// Whether the volume belongs to the set named `set`
+        pub fn is_in(&self, set: &str, matching: Matching) -> bool {
+            matching.same(self.set, set)
+        }
+    }
 }
 
+pub use names::{Matching, Volume, VolumeKind};
+
 /// Extension checking utilities
 pub mod ext {
     use std::path::Path;
@@ -168,9 +330,156 @@ mod tests {
 
     #[test]
     fn test_is_same_archive() {
-        assert!(rar::is_same_archive("archive", "archive.part02.rar"));
-        assert!(rar::is_same_archive("archive", "archive.r00"));
-        assert!(rar::is_same_archive("archive", "archive.r15"));
-        assert!(!rar::is_same_archive("archive", "other.rar"));
+        for matching in [Matching::Exact, Matching::IgnoreCase] {
+            assert!(rar::is_same_archive(
+                "archive",
+                "archive.part02.rar",
+                matching
+            ));
+            assert!(rar::is_same_archive("archive", "archive.r00", matching));
+            assert!(rar::is_same_archive("archive", "archive.r15", matching));
+            assert!(!rar::is_same_archive("archive", "other.rar", matching));
+            assert!(rar::is_same_recovery_volume(
+                "archive",
+                "archive.part01.rev",
+                matching
+            ));
+            assert!(!rar::is_same_recovery_volume(
+                "archive",
+                "archive.part01.rar",
+                matching
+            ));
+        }
+        assert!(rar::is_same_archive(
+            "archive",
+            "Archive.R00",
+            Matching::IgnoreCase
+        ));
+        assert!(!rar::is_same_archive(
+            "archive",
+            "Archive.R00",
+            Matching::Exact
+        ));
+    }
+
+    #[test]
+    fn test_prefix_collision() {
+        for matching in [Matching::Exact, Matching::IgnoreCase] {
+            for other in [
+                "Show.S01.Extras.part01.rar",
+                "Show.S01.Extras.rar",
+                "Show.S01.Extras.r00",
+            ] {
+                assert!(
+                    !rar::is_same_archive("Show.S01", other, matching),
+                    "{}",
+                    other
+                );
+            }
+            assert!(!rar::is_same_recovery_volume(
+                "Show.S01",
+                "Show.S01.Extras.part01.rev",
+                matching
+            ));
+            assert!(!par2::is_same_set(
+                "Show.S01",
+                "Show.S01.Extras.vol00+01.par2",
+                matching
+            ));
+            assert!(!par2::is_same_set(
+                "Show.S01.Extras",
+                "Show.S01.vol00+01.par2",
+                matching
+            ));
+
+            assert!(rar::is_same_archive(
+                "Show.S01",
+                "Show.S01.part02.rar",
+                matching
+            ));
+            assert!(par2::is_same_set(
+                "Show.S01",
+                "Show.S01.vol03+04.par2",
+                matching
+            ));
+            assert!(par2::is_same_set(
+                "Show.S01.Extras",
+                "Show.S01.Extras.par2",
+                matching
+            ));
+        }
+        // Case only counts where the filesystem ignores it
+        assert!(!rar::is_same_archive(
+            "show.s01",
+            "Show.S01.Extras.part01.rar",
+            Matching::IgnoreCase
+        ));
+        assert!(rar::is_same_archive(
+            "show.s01",
+            "Show.S01.part01.rar",
+            Matching::IgnoreCase
+        ));
+        assert!(!rar::is_same_archive(
+            "show.s01",
+            "Show.S01.part01.rar",
+            Matching::Exact
+        ));
+    }
+
+    #[test]
+    fn test_volume_parse() {
+        let volume = Volume::parse("my.file.name.part05.RAR").unwrap();
+        assert_eq!(volume.set, "my.file.name");
+        assert_eq!(volume.kind, VolumeKind::Rar);
+        assert_eq!(volume.number, Some(5));
+        assert_eq!(volume.ext, "RAR");
+
+        let split = Volume::parse("show.r07").unwrap();
+        assert_eq!(
+            (split.set, split.number, split.ext),
+            ("show", Some(7), "r07")
+        );
+        let par2 = Volume::parse("show.vol031-063.PAR2").unwrap();
+        assert_eq!((par2.kind, par2.number), (VolumeKind::Par2, Some(31)));
+        assert_eq!(
+            Volume::parse("show.rev").unwrap().kind,
+            VolumeKind::RarRecovery
+        );
+        assert_eq!(Volume::parse("show.part.rar").unwrap().set, "show.part");
+        assert_eq!(Volume::parse("show.mkv"), None);
+        assert_eq!(Volume::parse(".rar"), None);
+    }
+
+    #[test]
+    fn test_par2_extract_base_name() {
+        assert_eq!(par2::extract_base_name("show.par2"), Some("show"));
+        assert_eq!(
+            par2::extract_base_name("Show.S01E01.vol00+01.par2"),
+            Some("Show.S01E01")
+        );
+        assert_eq!(
+            par2::extract_base_name("show.vol031-063.PAR2"),
+            Some("show")
+        );
+        assert_eq!(par2::extract_base_name("show.rar"), None);
+    }
+
+    #[test]
+    fn test_par2_packet_magic() {
+        let dir = tempfile::tempdir().unwrap();
+        let hidden = dir.path().join("release [01_45] par2");
+        std::fs::write(&hidden, b"PAR2\0PKT\x5c\0\0\0\0\0\0\0").unwrap();
+        assert!(par2::has_packet_magic(&hidden));
+        assert!(!par2::is_par2_file(&hidden));
+
+        let rar = dir.path().join("show.PAR2");
+        std::fs::write(&rar, b"Rar!\x1a\x07\x01\0").unwrap();
+        assert!(!par2::has_packet_magic(&rar));
+        assert!(par2::is_par2_file(&rar));
+
+        let short = dir.path().join("short");
+        std::fs::write(&short, b"PAR2").unwrap();
+        assert!(!par2::has_packet_magic(&short));
+        assert!(!par2::has_packet_magic(&dir.path().join("absent")));
     }
 }
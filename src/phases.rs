@@ -0,0 +1,225 @@
+//! Where an NZB's time went, step by step
+//!
+//! The download time alone hides a PAR2 repair that took twenty minutes or an
+//! extraction that failed after the download finished. Each step of an NZB
+//! (download, PAR2, extraction, cleanup, script, ...) that runs leaves a
+//! [`PhaseRecord`] with its start and end, outcome and a few counters, which
+//! the JSON summary and the history keep in the order the steps ran.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::download::PostProcessPhase;
+use crate::progress::format_duration;
+
+/// A step of an NZB's download and post-processing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    Download,
+    Refetch,
+    Par2,
+    Extract,
+    Deobfuscate,
+    VerifyMedia,
+    CreatePar2,
+    Cleanup,
+    Script,
+}
+
+impl Phase {
+    /// Name as written in JSON and the summary
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::Download => "download",
+            Phase::Refetch => "refetch",
+            Phase::Par2 => "par2",
+            Phase::Extract => "extract",
+            Phase::Deobfuscate => "deobfuscate",
+            Phase::VerifyMedia => "verify_media",
+            Phase::CreatePar2 => "create_par2",
+            Phase::Cleanup => "cleanup",
+            Phase::Script => "script",
+        }
+    }
+}
+
+impl From<PostProcessPhase> for Phase {
+    fn from(phase: PostProcessPhase) -> Self {
+        match phase {
+            PostProcessPhase::Refetch => Phase::Refetch,
+            PostProcessPhase::Par2 => Phase::Par2,
+            PostProcessPhase::Extract => Phase::Extract,
+            PostProcessPhase::Deobfuscate => Phase::Deobfuscate,
+            PostProcessPhase::VerifyMedia => Phase::VerifyMedia,
+            PostProcessPhase::CreatePar2 => Phase::CreatePar2,
+            PostProcessPhase::Script => Phase::Script,
+        }
+    }
+}
+
+/// How a phase ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PhaseStatus {
+    Success,
+    /// Finished, but left something undone (failed segments, a file that
+    /// wouldn't extract)
+    Incomplete,
+    /// Stopped by an error
+    Failed,
+    /// Ran and found nothing to do
+    Skipped,
+}
+
+/// One phase that ran
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PhaseRecord {
+    pub phase: Phase,
+    pub status: PhaseStatus,
+    /// RFC 3339 times the phase started and finished
+    pub started_at: String,
+    pub finished_at: String,
+    pub seconds: f64,
+    /// What the phase got through, e.g. `files` or `repaired`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub counters: BTreeMap<String, u64>,
+}
+
+/// Collects the phases of one NZB; clones share the same records
+#[derive(Debug, Clone, Default)]
+pub struct PhaseRecorder {
+    records: Arc<Mutex<Vec<PhaseRecord>>>,
+}
+
+impl PhaseRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start timing `phase`; it's recorded when the timer is finished, or as
+    /// failed when it's dropped first (an error cut the phase short)
+    pub fn start(&self, phase: Phase) -> PhaseTimer {
+        PhaseTimer {
+            recorder: self.clone(),
+            phase,
+            started_at: chrono::Local::now(),
+            started: Instant::now(),
+            status: PhaseStatus::Failed,
+            counters: BTreeMap::new(),
+        }
+    }
+
+    /// Phases recorded so far, in the order they finished
+    pub fn records(&self) -> Vec<PhaseRecord> {
+        self.records
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+}
+
+/// A phase being timed, from [`PhaseRecorder::start`]
+#[derive(Debug)]
+pub struct PhaseTimer {
+    recorder: PhaseRecorder,
+    phase: Phase,
+    started_at: chrono::DateTime<chrono::Local>,
+    started: Instant,
+    status: PhaseStatus,
+    counters: BTreeMap<String, u64>,
+}
+
+impl PhaseTimer {
+    /// Set counter `name` for the record
+    pub fn count(&mut self, name: &str, value: impl TryInto<u64>) -> &mut Self {
+        self.counters
+            .insert(name.to_string(), value.try_into().unwrap_or(u64::MAX));
+        self
+    }
+
+    /// Record the phase as ended with `status`
+    pub fn finish(mut self, status: PhaseStatus) {
+        self.status = status;
+    }
+}
+
+impl Drop for PhaseTimer {
+    fn drop(&mut self) {
+        let record = PhaseRecord {
+            phase: self.phase,
+            status: self.status,
+            started_at: self.started_at.to_rfc3339(),
+            finished_at: chrono::Local::now().to_rfc3339(),
+            seconds: self.started.elapsed().as_secs_f64(),
+            counters: std::mem::take(&mut self.counters),
+        };
+        self.recorder
+            .records
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(record);
+    }
+}
+
+/// `download 12m 3s, par2 10m 2s (failed), script 2s`
+pub fn describe(records: &[PhaseRecord]) -> String {
+    records
+        .iter()
+        .map(|record| {
+            let took = format_duration(Duration::from_secs_f64(record.seconds.max(0.0)));
+            let note = match record.status {
+                PhaseStatus::Success => "",
+                PhaseStatus::Incomplete => " (incomplete)",
+                PhaseStatus::Failed => " (failed)",
+                PhaseStatus::Skipped => " (nothing to do)",
+            };
+            format!("{} {}{}", record.phase.name(), took, note)
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recorder_keeps_phases_in_order() {
+        let phases = PhaseRecorder::new();
+        let mut download = phases.start(Phase::Download);
+        download
+            .count("files", 3usize)
+            .count("segments_failed", 0u32);
+        download.finish(PhaseStatus::Incomplete);
+        // A timer dropped without finishing was cut short by an error
+        drop(phases.clone().start(Phase::Par2));
+        phases.start(Phase::Script).finish(PhaseStatus::Success);
+
+        let records = phases.records();
+        let order: Vec<_> = records.iter().map(|r| (r.phase, r.status)).collect();
+        assert_eq!(
+            order,
+            [
+                (Phase::Download, PhaseStatus::Incomplete),
+                (Phase::Par2, PhaseStatus::Failed),
+                (Phase::Script, PhaseStatus::Success),
+            ]
+        );
+        assert_eq!(records[0].counters["files"], 3);
+        assert!(records[2].counters.is_empty());
+        assert!(chrono::DateTime::parse_from_rfc3339(&records[0].started_at).is_ok());
+
+        let json = serde_json::to_value(&records[1]).unwrap();
+        assert_eq!(json["phase"], "par2");
+        assert_eq!(json["status"], "failed");
+        assert!(json.get("counters").is_none());
+
+        assert_eq!(
+            describe(&records),
+            "download 0s (incomplete), par2 0s (failed), script 0s"
+        );
+    }
+}
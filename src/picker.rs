@@ -0,0 +1,103 @@
+//! Interactive file picker for `-l --interactive`
+//!
+//! Shows an NZB's files as a filterable multi-select list and turns the
+//! choice into a [`FileSelection`], so the download that follows takes the
+//! same path as `--select`. The prompt runs the terminal in raw mode and
+//! restores it on every exit, Ctrl-C and Esc included.
+
+use human_bytes::human_bytes;
+use inquire::{InquireError, MultiSelect};
+use std::fmt;
+use std::io::IsTerminal;
+
+use crate::download::{FileSelection, Nzb, NzbFile};
+use crate::error::DlNzbError;
+
+type Result<T> = std::result::Result<T, DlNzbError>;
+
+/// Rows shown at once; the list scrolls past this
+const PAGE_SIZE: usize = 15;
+
+/// Whether a picker can be drawn (stdout is a terminal)
+pub fn available() -> bool {
+    std::io::stdout().is_terminal()
+}
+
+/// One row of the picker, formatted like list mode
+struct Entry {
+    index: usize,
+    kind: String,
+    name: String,
+    size: u64,
+}
+
+impl Entry {
+    fn new(file: &NzbFile) -> Self {
+        Self {
+            index: file.index,
+            kind: file.kind_label(),
+            name: file.filename(),
+            size: file.segments.segment.iter().map(|s| s.bytes).sum(),
+        }
+    }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:>4}  [{}] {} ({})",
+            self.index,
+            self.kind,
+            self.name,
+            human_bytes(self.size as f64)
+        )
+    }
+}
+
+/// Ask which files to download; `None` when nothing was chosen or Esc was pressed
+///
+/// Ctrl-C aborts the run with an `Interrupted` I/O error.
+pub fn pick_files(nzb: &Nzb) -> Result<Option<FileSelection>> {
+    let entries: Vec<Entry> = nzb.files().iter().map(Entry::new).collect();
+    let answer = MultiSelect::new("Files to download:", entries)
+        .with_page_size(PAGE_SIZE)
+        .prompt();
+
+    match answer {
+        Ok(chosen) if chosen.is_empty() => Ok(None),
+        Ok(chosen) => Ok(Some(FileSelection::from_indexes(
+            chosen.iter().map(|entry| entry.index),
+        ))),
+        Err(InquireError::OperationCanceled) => Ok(None),
+        Err(InquireError::OperationInterrupted) => Err(std::io::Error::new(
+            std::io::ErrorKind::Interrupted,
+            "file selection interrupted",
+        )
+        .into()),
+        Err(InquireError::IO(e)) => Err(e.into()),
+        Err(e) => Err(std::io::Error::other(e.to_string()).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_matches_list_format() {
+        let entry = Entry {
+            index: 12,
+            kind: "PAR2 (+4 blocks)".to_string(),
+            name: "show.vol03+04.PAR2".to_string(),
+            size: 2048,
+        };
+        assert_eq!(
+            entry.to_string(),
+            format!(
+                "  12  [PAR2 (+4 blocks)] show.vol03+04.PAR2 ({})",
+                human_bytes(2048.0)
+            )
+        );
+    }
+}
@@ -0,0 +1,722 @@
+//! `--dry-run`: what downloading an NZB would do, without doing it
+//!
+//! A [`Plan`] resolves the output folder the way a run would, works out which
+//! files are already there, `STAT`s a sample of segments on the servers,
+//! estimates the download time from the speeds in the history and checks the
+//! free space. Nothing is written and no article bodies are fetched. Each
+//! finding is a [`Check`] as in `config --check`; the plan is viable when
+//! none of them failed.
+
+use human_bytes::human_bytes;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::config::{Config, DownloadConfig, ExistingFiles, NzbOverrides};
+use crate::doctor::{Check, CheckStatus};
+use crate::download::{
+    check_existing, format_age, partial, redact_nzb_path, serialize_nzb_path, sizes, FolderFields,
+    LoadedNzb, Nzb, NzbFile, Par2Kind, SkipVerdict,
+};
+use crate::history::HistoryEntry;
+use crate::nntp::AsyncNntpConnection;
+use crate::patterns;
+use crate::progress::format_duration;
+
+/// Segments `STAT`ed per NZB
+pub const SAMPLE_SIZE: usize = 100;
+
+/// Downloads in the history whose speed goes into the estimate
+const RECENT_DOWNLOADS: usize = 10;
+
+/// What a download of one NZB would do
+#[derive(Debug, Clone, Serialize)]
+pub struct Plan {
+    #[serde(serialize_with = "serialize_nzb_path")]
+    pub nzb: PathBuf,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    /// Where the files would go
+    pub output_dir: PathBuf,
+    /// Per-NZB settings that would apply, with a `{{password}}` in the
+    /// name masked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sidecar: Option<PathBuf>,
+    /// History entry the NZB would be skipped as a duplicate of
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duplicate_of: Option<u64>,
+    /// Unfinished history entry whose folder would be resumed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resumes: Option<u64>,
+    /// Size of the selected files
+    pub total_size: u64,
+    /// Size of the files that would actually be fetched
+    pub download_size: u64,
+    pub files: Vec<PlannedFile>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub availability: Option<Availability>,
+    /// Expected download time at recent speeds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub estimated_seconds: Option<u64>,
+    pub checks: Vec<Check>,
+    pub viable: bool,
+}
+
+/// One file of the NZB and what would happen to it
+#[derive(Debug, Clone, Serialize)]
+pub struct PlannedFile {
+    pub index: usize,
+    /// Name from the subject; obfuscated subjects only get their name from
+    /// the first segment, which a dry run doesn't fetch
+    pub filename: String,
+    pub name_known: bool,
+    pub size: u64,
+    pub segments: usize,
+    pub action: FileAction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileAction {
+    Download,
+    /// Already there, and passed `download.skip_verification`
+    Skip,
+    /// There, but kept incomplete by an earlier run (`--keep-partial`); only
+    /// the missing segments would be fetched
+    Resume,
+    /// There at another size; moved aside before downloading
+    /// (`download.existing_files`)
+    Rename,
+    /// There at another size; downloaded over
+    Overwrite,
+    /// There at another size; kept and left out
+    Keep,
+}
+
+impl FileAction {
+    /// Whether the file's articles would be fetched, in whole or in part
+    pub fn fetches(self) -> bool {
+        !matches!(self, Self::Skip | Self::Keep)
+    }
+}
+
+/// Result of `STAT`ing a sample of an NZB's segments
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Availability {
+    pub sampled: usize,
+    /// Sampled segments no server had
+    pub missing: usize,
+    /// Servers that couldn't be asked, with why
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unreachable: Vec<String>,
+    /// The sample broken down by file index, for files it reached
+    #[serde(skip)]
+    pub files: BTreeMap<usize, FileSample>,
+}
+
+/// Sampled segments of one file, and how many of them no server had
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FileSample {
+    pub sampled: usize,
+    pub missing: usize,
+}
+
+impl FileSample {
+    /// Estimated share of the file's segments on the servers, in percent
+    pub fn completeness(&self) -> Option<f64> {
+        completeness(self.sampled, self.missing)
+    }
+}
+
+impl Availability {
+    /// Estimated share of all segments on the servers, in percent
+    pub fn completeness(&self) -> Option<f64> {
+        completeness(self.sampled, self.missing)
+    }
+}
+
+fn completeness(sampled: usize, missing: usize) -> Option<f64> {
+    (sampled > 0).then(|| sampled.saturating_sub(missing) as f64 * 100.0 / sampled as f64)
+}
+
+impl Plan {
+    /// Plan the download of `loaded` with `config`, the NZB's own settings
+    ///
+    /// `previous` is the latest history entry for the same post and `speed`
+    /// the recent download speed in bytes per second (see [`recent_speed`]).
+    pub async fn new(
+        nzb_path: &Path,
+        loaded: &LoadedNzb,
+        config: &Config,
+        sidecar: Option<&NzbOverrides>,
+        previous: Option<&HistoryEntry>,
+        speed: Option<u64>,
+    ) -> Self {
+        let nzb = &loaded.nzb;
+        let mut checks = Vec::new();
+
+        let report = nzb.validate();
+        let mut problems = report.gap_warnings();
+        problems.extend(retention_warning(config, nzb));
+        checks.push(if problems.is_empty() {
+            Check::pass(
+                "nzb",
+                format!(
+                    "{} files, {} segments",
+                    nzb.files().len(),
+                    nzb.total_segments()
+                ),
+            )
+        } else {
+            Check::warn("nzb", problems.join("; "))
+        });
+
+        let fields = FolderFields::new(
+            &loaded.name,
+            nzb,
+            sidecar.and_then(|o| o.category.as_deref()),
+        );
+        let (output_dir, duplicate_of, resumes) = output_dir(config, &fields, previous);
+        checks.push(match (duplicate_of, previous) {
+            (Some(id), Some(previous)) => Check::warn(
+                "output dir",
+                format!(
+                    "already downloaded on {} to {} (#{}); would be skipped without --force",
+                    previous.date(),
+                    output_dir.display(),
+                    id
+                ),
+            ),
+            _ => check_output_dir(&output_dir),
+        });
+
+        let files = plan_files(nzb.files(), &output_dir, &config.download).await;
+        let total_size = nzb.total_size();
+        let download_size = files
+            .iter()
+            .filter(|f| f.action.fetches())
+            .map(|f| f.size)
+            .sum();
+
+        let fetched: Vec<NzbFile> = nzb
+            .files()
+            .iter()
+            .zip(&files)
+            .filter(|(_, planned)| planned.action.fetches())
+            .map(|(file, _)| file.clone())
+            .collect();
+        let availability = if duplicate_of.is_some() || fetched.is_empty() {
+            None
+        } else {
+            let availability = sample_availability(config, &fetched, SAMPLE_SIZE).await;
+            checks.push(check_availability(
+                &availability,
+                config.all_servers().count(),
+                &fetched,
+            ));
+            Some(availability)
+        };
+
+        let estimated_seconds = estimate(download_size, speed, config.download.max_speed);
+        if duplicate_of.is_none() {
+            checks.push(match (estimated_seconds, speed) {
+                (Some(seconds), Some(speed)) => Check::pass(
+                    "estimate",
+                    format!(
+                        "about {} at {}/s",
+                        format_duration(Duration::from_secs(seconds)),
+                        human_bytes(speed.min(cap(config.download.max_speed)) as f64)
+                    ),
+                ),
+                _ => Check::warn("estimate", "no download speeds in the history yet"),
+            });
+            let needed = download_size + extraction_size(&files, config);
+            checks.push(check_space(&output_dir, needed));
+        }
+
+        let viable = checks.iter().all(|c| c.status != CheckStatus::Fail);
+        Self {
+            nzb: nzb_path.to_path_buf(),
+            name: loaded.name.clone(),
+            category: fields.category.map(str::to_string),
+            output_dir,
+            sidecar: sidecar.map(|o| redact_nzb_path(&o.path).into_owned()),
+            duplicate_of,
+            resumes,
+            total_size,
+            download_size,
+            files,
+            availability,
+            estimated_seconds,
+            checks,
+            viable,
+        }
+    }
+}
+
+/// The folder a run would download into, with the history entry it would be
+/// skipped as a duplicate of or resume
+///
+/// Mirrors a run: a post that was downloaded before is skipped, an
+/// unfinished one carries on in its folder, and anything else gets the
+/// folder `download.folder_template` names.
+pub fn output_dir(
+    config: &Config,
+    fields: &FolderFields,
+    previous: Option<&HistoryEntry>,
+) -> (PathBuf, Option<u64>, Option<u64>) {
+    match previous {
+        Some(entry) if entry.status.succeeded() && entry.has_files() => {
+            let dir = entry.output_dir.clone().unwrap_or_default();
+            return (dir, Some(entry.id), None);
+        }
+        Some(entry) if !entry.status.succeeded() => {
+            if let Some(dir) = entry.output_dir.as_ref().filter(|dir| dir.is_dir()) {
+                return (dir.clone(), None, Some(entry.id));
+            }
+        }
+        _ => {}
+    }
+    let dir = if config.download.create_subfolders {
+        config
+            .download
+            .dir
+            .join(fields.folder(&config.download.folder_template))
+    } else {
+        config.download.dir.clone()
+    };
+    (dir, None, None)
+}
+
+/// What a run would do with each file, going by what's in `dir`
+async fn plan_files(files: &[NzbFile], dir: &Path, download: &DownloadConfig) -> Vec<PlannedFile> {
+    let mut planned = Vec::with_capacity(files.len());
+    for file in files {
+        let size: u64 = file.segments.segment.iter().map(|s| s.bytes).sum();
+        let name = Nzb::get_filename_from_subject(&file.subject);
+        let action = match &name {
+            Some(name) if !download.force_redownload => {
+                let expected = sizes::recorded_size(dir, name).await.unwrap_or(size);
+                let method = download.skip_verification;
+                match check_existing(dir, name, expected, method).await {
+                    SkipVerdict::Absent => FileAction::Download,
+                    _ if partial::is_marked(dir, name).await => FileAction::Resume,
+                    SkipVerdict::Skip => FileAction::Skip,
+                    SkipVerdict::Rejected(_) => FileAction::Download,
+                    SkipVerdict::OtherSize(_) => match download.existing_files {
+                        ExistingFiles::Rename => FileAction::Rename,
+                        ExistingFiles::Overwrite => FileAction::Overwrite,
+                        ExistingFiles::Skip => FileAction::Keep,
+                    },
+                }
+            }
+            _ => FileAction::Download,
+        };
+        planned.push(PlannedFile {
+            index: file.index,
+            name_known: name.is_some(),
+            filename: name.unwrap_or_else(|| file.subject.clone()),
+            size,
+            segments: file.segments.segment.len(),
+            action,
+        });
+    }
+    planned
+}
+
+/// `STAT` up to `limit` segments spread evenly over `files`
+///
+/// The servers are asked in order, each only for posts within its retention
+/// and only for the segments the ones before it didn't have, as a download
+/// would fall back.
+pub async fn sample_availability(config: &Config, files: &[NzbFile], limit: usize) -> Availability {
+    let segments: Vec<_> = files
+        .iter()
+        .flat_map(|file| file.segments.segment.iter().map(move |s| (file, s)))
+        .collect();
+    let step = segments.len().div_ceil(limit.max(1)).max(1);
+    let mut pending: Vec<_> = segments.into_iter().step_by(step).collect();
+    let mut availability = Availability {
+        sampled: pending.len(),
+        ..Default::default()
+    };
+    for (file, _) in &pending {
+        availability.files.entry(file.index).or_default().sampled += 1;
+    }
+
+    let now = SystemTime::now();
+    for server in config.all_servers() {
+        if pending.is_empty() {
+            break;
+        }
+        if !pending
+            .iter()
+            .any(|(file, _)| server.retains(file.age_days_at(now).unwrap_or(0.0)))
+        {
+            continue;
+        }
+        let mut conn = match AsyncNntpConnection::connect(server, None).await {
+            Ok(conn) => conn,
+            Err(e) => {
+                availability
+                    .unreachable
+                    .push(format!("{}: {}", server.server, e));
+                continue;
+            }
+        };
+        let mut missing = Vec::new();
+        for (file, segment) in pending {
+            let found = server.retains(file.age_days_at(now).unwrap_or(0.0))
+                && match conn.stat(&segment.message_id).await {
+                    Ok(found) => found,
+                    Err(e) => {
+                        tracing::debug!("STAT {} on {}: {}", segment.message_id, server.server, e);
+                        false
+                    }
+                };
+            if !found {
+                missing.push((file, segment));
+            }
+        }
+        let _ = conn.close().await;
+        pending = missing;
+    }
+
+    availability.missing = pending.len();
+    for (file, _) in &pending {
+        availability.files.entry(file.index).or_default().missing += 1;
+    }
+    availability
+}
+
+/// Judge a sample against the PAR2 recovery data that comes with `files`
+fn check_availability(availability: &Availability, servers: usize, files: &[NzbFile]) -> Check {
+    let Availability {
+        sampled,
+        missing,
+        unreachable,
+        ..
+    } = availability;
+    if unreachable.len() >= servers {
+        return Check::fail("availability", unreachable.join("; "));
+    }
+    let mut detail = if *missing == 0 {
+        format!("all {} sampled segments are on the server", sampled)
+    } else {
+        format!("{} of {} sampled segments missing", missing, sampled)
+    };
+    if !unreachable.is_empty() {
+        detail.push_str(&format!(" ({} not reachable)", unreachable.join("; ")));
+    }
+    if *missing == 0 && unreachable.is_empty() {
+        return Check::pass("availability", detail);
+    }
+    if *missing == 0 {
+        return Check::warn("availability", detail);
+    }
+
+    // PAR2 can make up for about as much data as its volumes hold
+    let (recovery, data) = files.iter().fold((0, 0), |(recovery, data), file| {
+        let size: u64 = file.segments.segment.iter().map(|s| s.bytes).sum();
+        match file.par2_kind() {
+            Some(Par2Kind::Volume { .. }) => (recovery + size, data),
+            Some(Par2Kind::Index) => (recovery, data),
+            None => (recovery, data + size),
+        }
+    });
+    let lost = (data as f64 * *missing as f64 / *sampled as f64) as u64;
+    if lost <= recovery {
+        Check::warn(
+            "availability",
+            format!(
+                "{}; about {} lost, within the {} of PAR2 recovery data",
+                detail,
+                human_bytes(lost as f64),
+                human_bytes(recovery as f64)
+            ),
+        )
+    } else {
+        Check::fail(
+            "availability",
+            format!(
+                "{}; about {} lost, more than the {} of PAR2 recovery data can repair",
+                detail,
+                human_bytes(lost as f64),
+                human_bytes(recovery as f64)
+            ),
+        )
+    }
+}
+
+/// Median average speed, in bytes per second, of the latest downloads
+pub fn recent_speed(entries: &[HistoryEntry]) -> Option<u64> {
+    let mut speeds: Vec<u64> = entries
+        .iter()
+        .rev()
+        .filter_map(|entry| entry.speed.map(|s| s.avg))
+        .filter(|&avg| avg > 0)
+        .take(RECENT_DOWNLOADS)
+        .collect();
+    speeds.sort_unstable();
+    speeds.get(speeds.len() / 2).copied()
+}
+
+/// `max_speed` as a cap; 0 means none
+fn cap(max_speed: u64) -> u64 {
+    if max_speed == 0 {
+        u64::MAX
+    } else {
+        max_speed
+    }
+}
+
+/// Seconds to fetch `bytes` at `speed`, held to `max_speed`
+fn estimate(bytes: u64, speed: Option<u64>, max_speed: u64) -> Option<u64> {
+    let speed = speed?.min(cap(max_speed));
+    (speed > 0).then(|| bytes.div_ceil(speed))
+}
+
+/// Space extraction would take on top of the download: about as much as
+/// the archives, which stay until the end even when they're then deleted
+fn extraction_size(files: &[PlannedFile], config: &Config) -> u64 {
+    if !config.post_processing.auto_extract_rar {
+        return 0;
+    }
+    files
+        .iter()
+        .filter(|f| patterns::rar::is_rar_related(&f.filename))
+        .map(|f| f.size)
+        .sum()
+}
+
+/// Whether the nearest existing folder on the way to `dir` looks writable
+///
+/// Nothing is created to find out, so this goes by the permission bits.
+fn check_output_dir(dir: &Path) -> Check {
+    let Some(existing) = dir.ancestors().find(|p| p.exists()) else {
+        return Check::fail(
+            "output dir",
+            format!("{} has no existing parent", dir.display()),
+        );
+    };
+    match std::fs::metadata(existing) {
+        Ok(meta) if !meta.is_dir() => Check::fail(
+            "output dir",
+            format!("{} is not a folder", existing.display()),
+        ),
+        Ok(meta) if meta.permissions().readonly() => {
+            Check::fail("output dir", format!("{} is read-only", existing.display()))
+        }
+        Ok(_) if existing == dir => Check::pass("output dir", dir.display().to_string()),
+        Ok(_) => Check::pass(
+            "output dir",
+            format!("{} (would be created)", dir.display()),
+        ),
+        Err(e) => Check::fail("output dir", format!("{}: {}", existing.display(), e)),
+    }
+}
+
+/// Free space where `dir` is or would be, against `needed` bytes
+fn check_space(dir: &Path, needed: u64) -> Check {
+    let Some(existing) = dir.ancestors().find(|p| p.exists()) else {
+        return Check::warn("disk space", "free space unknown");
+    };
+    match fs2::available_space(existing) {
+        Ok(free) if free < needed => Check::fail(
+            "disk space",
+            format!(
+                "needs {}, only {} free",
+                human_bytes(needed as f64),
+                human_bytes(free as f64)
+            ),
+        ),
+        Ok(free) => Check::pass(
+            "disk space",
+            format!(
+                "needs {}, {} free",
+                human_bytes(needed as f64),
+                human_bytes(free as f64)
+            ),
+        ),
+        Err(e) => Check::warn("disk space", format!("free space unknown: {}", e)),
+    }
+}
+
+/// Warning for an NZB whose oldest post is beyond every server's retention
+pub fn retention_warning(config: &Config, nzb: &Nzb) -> Option<String> {
+    let age_days = nzb.oldest_age_days()?;
+    if !config.servers_for_age(age_days).is_empty() {
+        return None;
+    }
+    Some(format!(
+        "post is {} old, exceeds all configured retention",
+        format_age(age_days)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::download::SpeedStats;
+    use crate::error::RunStatus;
+
+    fn entry(status: RunStatus, output_dir: &Path, avg: u64) -> HistoryEntry {
+        HistoryEntry {
+            id: 7,
+            name: "Some.Post".to_string(),
+            title: None,
+            nzb: PathBuf::from("/nzbs/Some.Post.nzb"),
+            completed_at: "2026-01-02T03:04:05+00:00".to_string(),
+            status,
+            error: None,
+            output_dir: Some(output_dir.to_path_buf()),
+            total_size: 1024,
+            download_time_seconds: 2.0,
+            average_speed_mbps: 0.5,
+            bytes_by_server: Default::default(),
+            transferred: 0,
+            failed_segments: 0,
+            content_hash: "0".repeat(32),
+            failure_cause: None,
+            speed: Some(SpeedStats {
+                min: avg,
+                avg,
+                max: avg,
+                p95: avg,
+            }),
+            files: Vec::new(),
+            xml: None,
+            nzb_file: None,
+            connections: None,
+            parent: None,
+            phases: Vec::new(),
+            completion: Default::default(),
+        }
+    }
+
+    fn file(subject: &str, bytes: u64) -> NzbFile {
+        let xml = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<nzb xmlns="http://www.newzbin.com/DTD/2003/nzb">
+  <file poster="p@test" date="1700000000" subject="{}">
+    <groups><group>alt.binaries.test</group></groups>
+    <segments><segment bytes="{}" number="1">a@test</segment></segments>
+  </file>
+</nzb>"#,
+            subject, bytes
+        );
+        let nzb: Nzb = xml.parse().unwrap();
+        nzb.files()[0].clone()
+    }
+
+    #[test]
+    fn test_output_dir_follows_history() {
+        let root = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.download.dir = root.path().to_path_buf();
+        config.download.create_subfolders = true;
+
+        let mut fields = FolderFields {
+            nzbname: "Some.Post",
+            title: None,
+            category: Some("tv"),
+            date: chrono::NaiveDate::from_ymd_opt(2026, 1, 2).unwrap(),
+            posted: chrono::NaiveDate::from_ymd_opt(2025, 12, 30).unwrap(),
+        };
+        let fresh = output_dir(&config, &fields, None);
+        assert_eq!(fresh, (root.path().join("Some.Post"), None, None));
+
+        config.download.folder_template = "{category}/{nzbname}".to_string();
+        let nested = output_dir(&config, &fields, None);
+        assert_eq!(nested.0, root.path().join("tv").join("Some.Post"));
+        fields.category = None;
+        let uncategorized = output_dir(&config, &fields, None);
+        assert_eq!(uncategorized.0, root.path().join("Some.Post"));
+
+        let earlier = root.path().join("Earlier");
+        std::fs::create_dir(&earlier).unwrap();
+        let failed = entry(RunStatus::Incomplete, &earlier, 1);
+        assert_eq!(
+            output_dir(&config, &fields, Some(&failed)),
+            (earlier.clone(), None, Some(7))
+        );
+
+        std::fs::write(earlier.join("a.mkv"), b"").unwrap();
+        let done = entry(RunStatus::Success, &earlier, 1);
+        assert_eq!(
+            output_dir(&config, &fields, Some(&done)),
+            (earlier, Some(7), None)
+        );
+    }
+
+    #[test]
+    fn test_estimate_uses_recent_speeds() {
+        let dir = Path::new("/downloads");
+        let entries: Vec<_> = [10, 40, 20]
+            .into_iter()
+            .map(|avg| entry(RunStatus::Success, dir, avg))
+            .collect();
+        assert_eq!(recent_speed(&entries), Some(20));
+        assert_eq!(recent_speed(&[]), None);
+
+        assert_eq!(estimate(100, Some(20), 0), Some(5));
+        // download.max_speed holds the estimate down
+        assert_eq!(estimate(100, Some(20), 10), Some(10));
+        assert_eq!(estimate(100, None, 0), None);
+    }
+
+    #[test]
+    fn test_missing_segments_against_recovery_data() {
+        let files = [
+            file("&quot;a.rar&quot; yEnc (1/1)", 1000),
+            file("&quot;a.vol00+10.par2&quot; yEnc (1/1)", 200),
+        ];
+        let sample = |missing| Availability {
+            sampled: 100,
+            missing,
+            ..Availability::default()
+        };
+
+        assert_eq!(
+            check_availability(&sample(0), 1, &files).status,
+            CheckStatus::Pass
+        );
+        // 10% of 1000 bytes is within the 200 bytes of recovery data
+        assert_eq!(
+            check_availability(&sample(10), 1, &files).status,
+            CheckStatus::Warn
+        );
+        assert_eq!(
+            check_availability(&sample(30), 1, &files).status,
+            CheckStatus::Fail
+        );
+
+        let unreachable = Availability {
+            unreachable: vec!["news.example.com: connection refused".to_string()],
+            ..sample(100)
+        };
+        assert_eq!(
+            check_availability(&unreachable, 1, &files).status,
+            CheckStatus::Fail
+        );
+    }
+
+    #[test]
+    fn test_completeness_estimates() {
+        let sample = FileSample {
+            sampled: 8,
+            missing: 2,
+        };
+        assert_eq!(sample.completeness(), Some(75.0));
+        // A file the sample never reached can't be judged
+        assert_eq!(FileSample::default().completeness(), None);
+        let whole = Availability {
+            sampled: 100,
+            missing: 5,
+            ..Availability::default()
+        };
+        assert_eq!(whole.completeness(), Some(95.0));
+    }
+}
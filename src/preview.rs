@@ -0,0 +1,240 @@
+//! `--preview`: a look at a post before committing to all of it
+//!
+//! [`preview_files`] picks the files that say what a post is (NFOs, SFVs and
+//! the first volume of each RAR set, or the first file when nothing is
+//! archived) and cuts them down to a byte budget, segment by segment in
+//! order and mid-file once it runs out. The start of a RAR volume holds the
+//! headers of the files packed into it, so [`Preview::gather`] can usually
+//! list the archive from it; where the listing has to stop is noted rather
+//! than treated as an error.
+
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+use crate::download::{serialize_nzb_path, DownloadResult, NzbFile};
+use crate::nfo;
+use crate::patterns::{ext, rar as rar_patterns};
+use crate::processing::{list_partial_archive, ArchiveListing};
+
+/// Byte budget of `--preview` without a size
+pub const DEFAULT_BUDGET: u64 = 20 * 1024 * 1024;
+
+/// What the start of an NZB's telling files showed
+#[derive(Debug, Clone, Serialize)]
+pub struct Preview {
+    #[serde(serialize_with = "serialize_nzb_path")]
+    pub nzb: PathBuf,
+    /// Where the fetched parts were kept, with `--keep-partial`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kept_in: Option<PathBuf>,
+    pub files: Vec<PreviewedFile>,
+    /// Text of the first NFO, cut to 64 KiB and decoded from CP437
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nfo: Option<String>,
+    /// What each first RAR volume lists, as far as the fetched part goes
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub archives: Vec<PreviewedArchive>,
+}
+
+/// A file fetched, in part or whole, for a preview
+#[derive(Debug, Clone, Serialize)]
+pub struct PreviewedFile {
+    pub filename: String,
+    /// Bytes written
+    pub fetched: u64,
+    pub segments: usize,
+    /// Segments the NZB lists for the file
+    pub total_segments: usize,
+}
+
+impl PreviewedFile {
+    pub fn complete(&self) -> bool {
+        self.segments >= self.total_segments
+    }
+}
+
+/// The files listed by the start of a RAR volume
+#[derive(Debug, Clone, Serialize)]
+pub struct PreviewedArchive {
+    pub volume: String,
+    #[serde(flatten)]
+    pub listing: ArchiveListing,
+}
+
+impl Preview {
+    /// Read what the preview download of `nzb` wrote; `files` are the NZB's
+    /// files, uncut
+    pub fn gather(nzb: &Path, files: &[NzbFile], results: &[DownloadResult]) -> Self {
+        let previewed = results
+            .iter()
+            .map(|result| PreviewedFile {
+                filename: result.filename.clone(),
+                fetched: result.size,
+                segments: result.segments_downloaded,
+                total_segments: files
+                    .iter()
+                    .find(|file| file.filename() == result.filename)
+                    .map_or(result.segments_downloaded, |file| {
+                        file.segments.segment.len()
+                    }),
+            })
+            .collect();
+        let nfo = results
+            .iter()
+            .find(|result| ext::has_extension(&result.path, "nfo"))
+            .and_then(|result| nfo::read(&result.path).ok());
+        let archives = results
+            .iter()
+            .filter(|result| rar_patterns::is_extractable_archive(&result.path))
+            .map(|result| PreviewedArchive {
+                volume: result.filename.clone(),
+                listing: list_partial_archive(&result.path),
+            })
+            .collect();
+        Self {
+            nzb: nzb.to_path_buf(),
+            kept_in: None,
+            files: previewed,
+            nfo,
+            archives,
+        }
+    }
+}
+
+/// Order a file is previewed in, if at all: NFOs, then SFVs, then first
+/// RAR volumes
+fn rank(file: &NzbFile) -> Option<u8> {
+    let name = file.filename();
+    let path = Path::new(&name);
+    if ext::has_extension(path, "nfo") {
+        Some(0)
+    } else if ext::has_extension(path, "sfv") {
+        Some(1)
+    } else if rar_patterns::is_extractable_archive(path) {
+        Some(2)
+    } else {
+        None
+    }
+}
+
+/// The files of a preview, cut down to `budget` bytes of segments
+///
+/// Without a RAR set the first file that isn't PAR2 stands in for the
+/// first volume. Segments are taken in the NZB's order until the budget is
+/// spent; the one that spends it is taken whole.
+pub fn preview_files(files: &[NzbFile], budget: u64) -> Vec<NzbFile> {
+    let mut picked: Vec<(u8, &NzbFile)> = files
+        .iter()
+        .filter_map(|file| Some((rank(file)?, file)))
+        .collect();
+    if !picked.iter().any(|&(rank, _)| rank == 2) {
+        let first = files
+            .iter()
+            .find(|file| rank(file).is_none() && file.par2_kind().is_none());
+        picked.extend(first.map(|file| (2, file)));
+    }
+    picked.sort_by_key(|&(rank, file)| (rank, file.index));
+
+    let mut left = budget;
+    let mut preview = Vec::new();
+    for (_, file) in picked {
+        if left == 0 {
+            break;
+        }
+        let mut cut = file.clone();
+        let mut kept = 0;
+        for segment in &file.segments.segment {
+            if left == 0 {
+                break;
+            }
+            left = left.saturating_sub(segment.bytes);
+            kept += 1;
+        }
+        cut.segments.segment.truncate(kept);
+        preview.push(cut);
+    }
+    preview
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::download::Nzb;
+
+    fn nzb(files: &[(&str, usize)]) -> Nzb {
+        let files: String = files
+            .iter()
+            .map(|&(name, segments)| {
+                let segments: String = (1..=segments)
+                    .map(|n| {
+                        format!(
+                            r#"<segment bytes="100" number="{}">{}.{}@x</segment>"#,
+                            n, name, n
+                        )
+                    })
+                    .collect();
+                format!(
+                    r#"<file poster="p" date="0" subject="&quot;{}&quot; yEnc (1/{})">
+                    <groups><group>alt.binaries.test</group></groups>
+                    <segments>{}</segments></file>"#,
+                    name, segments, segments
+                )
+            })
+            .collect();
+        format!("<nzb>{}</nzb>", files).parse().unwrap()
+    }
+
+    fn picked(files: &[NzbFile]) -> Vec<(String, usize)> {
+        files
+            .iter()
+            .map(|file| (file.filename(), file.segments.segment.len()))
+            .collect()
+    }
+
+    #[test]
+    fn test_preview_takes_telling_files_within_budget() {
+        let nzb = nzb(&[
+            ("show.part01.rar", 50),
+            ("show.part02.rar", 50),
+            ("show.nfo", 1),
+            ("show.par2", 1),
+            ("show.sfv", 1),
+            ("extras.rar", 10),
+        ]);
+        let cut = preview_files(nzb.files(), 1_000);
+        // 100 bytes of NFO and SFV, then eight segments of the first volume
+        assert_eq!(
+            picked(&cut),
+            [
+                ("show.nfo".to_string(), 1),
+                ("show.sfv".to_string(), 1),
+                ("extras.rar".to_string(), 8),
+            ]
+        );
+
+        // A budget not used up by whole segments takes one more
+        let cut = preview_files(nzb.files(), 450);
+        assert_eq!(cut[2].segments.segment.len(), 3);
+        assert_eq!(cut[2].segments.segment[2].number, 3);
+
+        let everything = preview_files(nzb.files(), u64::MAX);
+        assert_eq!(
+            picked(&everything),
+            [
+                ("show.nfo".to_string(), 1),
+                ("show.sfv".to_string(), 1),
+                ("extras.rar".to_string(), 10),
+                ("show.part01.rar".to_string(), 50),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_preview_without_archives_takes_the_first_file() {
+        let nzb = nzb(&[("b.mkv", 40), ("a.mkv.par2", 1), ("a.mkv", 40)]);
+        assert_eq!(
+            picked(&preview_files(nzb.files(), 250)),
+            [("a.mkv".to_string(), 3)]
+        );
+    }
+}
@@ -0,0 +1,126 @@
+//! Removal of ad and placeholder files (`post_processing.cleanup_patterns`)
+//!
+//! Some posters pad a release with `.url` shortcuts and "Downloaded from"
+//! text files. Once repair, extraction and renaming are done, files in the
+//! download folder and the folders extracted into it whose names match a
+//! pattern are deleted. Patterns are globs, or regexes after `re:`, as for
+//! `--only`, and ignore case. Hidden files (dl-nzb's own `.dlnzb-*` records
+//! among them) and names set with `--name-map` are left alone.
+
+use regex::Regex;
+use std::path::Path;
+
+use crate::download::compile_pattern;
+use crate::error::{ConfigError, DlNzbError};
+
+/// `cleanup_patterns` unless the config says otherwise
+pub const DEFAULT_CLEANUP_PATTERNS: &[&str] = &[
+    "*.url",
+    "*.lnk",
+    "*downloaded from*.txt",
+    "*visit *.txt",
+    "www.*.txt",
+];
+
+/// Compile `post_processing.cleanup_patterns`
+pub fn compile_cleanup_patterns(patterns: &[String]) -> Result<Vec<Regex>, DlNzbError> {
+    patterns
+        .iter()
+        .map(|pattern| {
+            compile_pattern(pattern, false).map_err(|reason| {
+                ConfigError::Invalid {
+                    field: "post_processing.cleanup_patterns".to_string(),
+                    reason,
+                }
+                .into()
+            })
+        })
+        .collect()
+}
+
+/// Delete the files under `dir` whose names match `patterns`, except those
+/// named in `keep`; returns how many went
+pub fn remove_junk(dir: &Path, patterns: &[Regex], keep: &[String]) -> usize {
+    if patterns.is_empty() {
+        return 0;
+    }
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return 0;
+    };
+    let mut removed = 0;
+    for entry in entries.filter_map(|e| e.ok()) {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') {
+            continue;
+        }
+        let path = entry.path();
+        match entry.file_type() {
+            Ok(kind) if kind.is_dir() => removed += remove_junk(&path, patterns, keep),
+            Ok(kind) if kind.is_file() => {
+                if keep.contains(&name) || !patterns.iter().any(|re| re.is_match(&name)) {
+                    continue;
+                }
+                match std::fs::remove_file(&path) {
+                    Ok(()) => {
+                        tracing::info!("Removed junk file {}", path.display());
+                        removed += 1;
+                    }
+                    Err(e) => tracing::warn!("Failed to remove {}: {}", path.display(), e),
+                }
+            }
+            _ => {}
+        }
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_patterns_remove_ads_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let extracted = dir.path().join("Show.S01");
+        std::fs::create_dir(&extracted).unwrap();
+        for name in [
+            "Show.S01E01.mkv",
+            "Show.nfo",
+            "readme.txt",
+            "Visit Our Site.URL",
+            "Downloaded From example.txt",
+            ".dlnzb-segments",
+            "mapped.url",
+        ] {
+            std::fs::write(dir.path().join(name), b"x").unwrap();
+        }
+        std::fs::write(extracted.join("www.example.com.txt"), b"x").unwrap();
+
+        let defaults: Vec<String> = DEFAULT_CLEANUP_PATTERNS
+            .iter()
+            .map(|p| p.to_string())
+            .collect();
+        let patterns = compile_cleanup_patterns(&defaults).unwrap();
+        let removed = remove_junk(dir.path(), &patterns, &["mapped.url".to_string()]);
+        assert_eq!(removed, 3);
+
+        let mut left: Vec<String> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        left.sort();
+        assert_eq!(
+            left,
+            [
+                ".dlnzb-segments",
+                "Show.S01",
+                "Show.S01E01.mkv",
+                "Show.nfo",
+                "mapped.url",
+                "readme.txt"
+            ]
+        );
+        assert!(!extracted.join("www.example.com.txt").exists());
+        assert!(compile_cleanup_patterns(&["re:(".to_string()]).is_err());
+    }
+}
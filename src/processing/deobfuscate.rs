@@ -4,6 +4,7 @@
 //! to more meaningful names based on the NZB name.
 
 use super::file_extension;
+use crate::download::{FAILED_MARKER, HASHES_FILE, LOCK_FILE, SEGMENTS_FILE};
 use crate::error::{DlNzbError, PostProcessingError};
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -157,13 +158,23 @@ pub struct DeobfuscateResult {
 /// 1. Adds missing extensions to files based on magic bytes
 /// 2. Renames the largest obfuscated file to a meaningful name
 /// 3. Renames related files (same basename) to match
-pub fn deobfuscate_files(directory: &Path, useful_name: &str) -> Result<DeobfuscateResult> {
+///
+/// Files named in `keep` (names given with `--name-map`) are left alone.
+pub fn deobfuscate_files(
+    directory: &Path,
+    useful_name: &str,
+    keep: &[String],
+) -> Result<DeobfuscateResult> {
     let mut files_renamed = 0;
     let mut extensions_fixed = 0;
 
-    // Get all files in directory (not recursively)
+    // Get all files in directory (not recursively), bar the run's own lock
+    // and records and the files whose names were given
+    let own = [LOCK_FILE, FAILED_MARKER, HASHES_FILE, SEGMENTS_FILE];
     let mut file_list: Vec<PathBuf> = fs::read_dir(directory)?
         .filter_map(|entry| entry.ok())
+        .filter(|entry| !own.iter().any(|name| entry.file_name() == *name))
+        .filter(|entry| !keep.iter().any(|name| entry.file_name() == name.as_str()))
         .map(|entry| entry.path())
         .filter(|path| path.is_file())
         .collect();
@@ -237,7 +248,7 @@ pub fn deobfuscate_files(directory: &Path, useful_name: &str) -> Result<Deobfusc
     };
 
     // Check if biggest file should be excluded
-    let ext = get_ext(&biggest_file);
+    let ext = get_ext(&biggest_file).to_lowercase();
     if file_extension::EXCLUDED_FILE_EXTS.contains(&ext.as_str()) {
         tracing::debug!(
             "Biggest file {} excluded due to extension",
@@ -0,0 +1,160 @@
+//! Cleanup of duplicate files left behind by PAR2 repair
+//!
+//! When a repair renames an obfuscated file onto a name that already exists,
+//! the old copy is kept as `name.ext.1`, `name.ext.2`, ... These stale copies
+//! confuse deobfuscation, so once PAR2 has succeeded we keep whichever copy
+//! matches the hash in the PAR2 FileDescription and remove the others.
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use super::par2_packets::{self, FileDescription, Par2Index};
+
+/// Matches `name.ext.N` duplicates, capturing the original name
+static DUPLICATE_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^(.+\.[^.]+)\.(\d+)$").expect("valid regex"));
+
+/// Remove stale `.N` duplicates of files that verify against the PAR2 set
+///
+/// Only acts when exactly one copy matches the PAR2 hash; when neither copy
+/// can be verified both are left alone. Returns the number of files removed.
+pub fn resolve_par2_duplicates(download_dir: &Path, index: &Par2Index) -> usize {
+    let descriptions = &index.files;
+    if descriptions.is_empty() {
+        return 0;
+    }
+
+    // Ordered, so the same folder is resolved the same way every time
+    let mut groups: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    if let Ok(entries) = std::fs::read_dir(download_dir) {
+        for entry in entries.filter_map(|e| e.ok()) {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if let Some(caps) = DUPLICATE_REGEX.captures(&name) {
+                let original = caps[1].to_string();
+                if descriptions.contains_key(&original) {
+                    groups.entry(original).or_default().push(entry.path());
+                }
+            }
+        }
+    }
+
+    let mut removed = 0;
+    for (original, mut duplicates) in groups {
+        duplicates.sort();
+        removed += resolve_group(
+            download_dir,
+            &original,
+            &descriptions[&original],
+            duplicates,
+        );
+    }
+    removed
+}
+
+fn resolve_group(
+    download_dir: &Path,
+    original: &str,
+    desc: &FileDescription,
+    duplicates: Vec<PathBuf>,
+) -> usize {
+    let target = download_dir.join(original);
+    let mut candidates = duplicates;
+    if target.exists() {
+        candidates.insert(0, target.clone());
+    }
+
+    let verified: Vec<&PathBuf> = candidates
+        .iter()
+        .filter(|p| par2_packets::file_matches(desc, p).unwrap_or(false))
+        .collect();
+
+    let keep = match verified.first() {
+        Some(keep) => (*keep).clone(),
+        None => {
+            tracing::info!(
+                "Leaving {} duplicate(s) of {}: no copy matches the PAR2 hash",
+                candidates.len(),
+                original
+            );
+            return 0;
+        }
+    };
+
+    let mut removed = 0;
+    for path in candidates.iter().filter(|p| **p != keep) {
+        match std::fs::remove_file(path) {
+            Ok(()) => {
+                tracing::info!("Removed stale PAR2 duplicate {}", path.display());
+                removed += 1;
+            }
+            Err(e) => tracing::warn!("Failed to remove {}: {}", path.display(), e),
+        }
+    }
+
+    if keep != target {
+        match std::fs::rename(&keep, &target) {
+            Ok(()) => tracing::info!("Restored {} from {}", original, keep.display()),
+            Err(e) => tracing::warn!("Failed to restore {}: {}", original, e),
+        }
+    }
+
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::par2_packets::file_description_packet;
+
+    #[test]
+    fn test_verified_copy_is_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut par2 = file_description_packet("movie.mkv", b"good movie");
+        par2.extend(file_description_packet("extras.mkv", b"good extras"));
+        std::fs::write(dir.path().join("set.par2"), par2).unwrap();
+
+        // Verified original with a stale duplicate
+        std::fs::write(dir.path().join("movie.mkv"), b"good movie").unwrap();
+        std::fs::write(dir.path().join("movie.mkv.1"), b"stale copy").unwrap();
+
+        // Unverifiable pair: neither copy matches, so both stay
+        std::fs::write(dir.path().join("extras.mkv"), b"bad one").unwrap();
+        std::fs::write(dir.path().join("extras.mkv.1"), b"bad two").unwrap();
+
+        assert_eq!(
+            resolve_par2_duplicates(dir.path(), &Par2Index::read(dir.path())),
+            1
+        );
+        assert!(!dir.path().join("movie.mkv.1").exists());
+        assert_eq!(
+            std::fs::read(dir.path().join("movie.mkv")).unwrap(),
+            b"good movie"
+        );
+        assert!(dir.path().join("extras.mkv").exists());
+        assert!(dir.path().join("extras.mkv.1").exists());
+    }
+
+    #[test]
+    fn test_verified_duplicate_replaces_original() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("set.par2"),
+            file_description_packet("movie.mkv", b"good movie"),
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("movie.mkv"), b"wrong file").unwrap();
+        std::fs::write(dir.path().join("movie.mkv.1"), b"good movie").unwrap();
+
+        assert_eq!(
+            resolve_par2_duplicates(dir.path(), &Par2Index::read(dir.path())),
+            1
+        );
+        assert!(!dir.path().join("movie.mkv.1").exists());
+        assert_eq!(
+            std::fs::read(dir.path().join("movie.mkv")).unwrap(),
+            b"good movie"
+        );
+    }
+}
@@ -0,0 +1,307 @@
+//! Completed files into a library folder (`post_processing.link_dir`)
+//!
+//! Once an NZB is done, its final files (extracted and renamed, without
+//! archives, PAR2 volumes or dl-nzb's own markers) are hard-linked, symlinked,
+//! copied or moved into `link_dir`, keeping the NZB's subfolder and anything
+//! below it, while the download folder stays the working area. A hard link
+//! that can't be made, as across filesystems, becomes a copy.
+
+use std::path::{Path, PathBuf};
+
+use crate::config::{LinkCollision, LinkMode};
+use crate::download::Permissions;
+use crate::patterns;
+
+/// Link the final files under `download_dir` into `target_dir`
+///
+/// Returns where each file ended up; files skipped on a collision or that
+/// failed are left out, the failures logged.
+pub fn link_files(
+    download_dir: &Path,
+    target_dir: &Path,
+    mode: LinkMode,
+    collision: LinkCollision,
+    permissions: Permissions,
+) -> Vec<PathBuf> {
+    let mut linked = Vec::new();
+    let mut warned = false;
+    for source in final_files(download_dir) {
+        let Ok(relative) = source.strip_prefix(download_dir) else {
+            continue;
+        };
+        let target = target_dir.join(relative);
+        if let Some(parent) = target.parent() {
+            if let Err(e) = create_dirs(parent, permissions) {
+                tracing::warn!("Failed to create {}: {}", parent.display(), e);
+                continue;
+            }
+        }
+        let target = match resolve_collision(target, collision) {
+            Some(target) => target,
+            None => {
+                tracing::info!("{} already exists; not linked", relative.display());
+                continue;
+            }
+        };
+        match place(&source, &target, mode, &mut warned) {
+            Ok(copied) => {
+                if copied {
+                    permissions.apply_file(&target);
+                }
+                tracing::debug!("{} -> {}", source.display(), target.display());
+                linked.push(target);
+            }
+            Err(e) => tracing::warn!(
+                "Failed to {} {} to {}: {}",
+                describe(mode),
+                source.display(),
+                target.display(),
+                e
+            ),
+        }
+    }
+    linked
+}
+
+/// Files under `dir` that are the download's result rather than its means
+fn final_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return files;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().to_string();
+        match entry.file_type() {
+            Ok(kind) if kind.is_dir() && !name.starts_with('.') => {
+                files.extend(final_files(&path));
+            }
+            Ok(kind) if kind.is_file() && is_final(&name, &path) => files.push(path),
+            _ => {}
+        }
+    }
+    files.sort();
+    files
+}
+
+/// Whether a file is worth having in the library
+fn is_final(name: &str, path: &Path) -> bool {
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    !name.starts_with('.')
+        && !patterns::rar::is_rar_related(name)
+        && !patterns::par2::is_par2_file(path)
+        && !matches!(extension.as_str(), "rev" | "sfv" | "nzb")
+}
+
+/// `target`, or with [`LinkCollision::Rename`] the first free `name (N).ext`;
+/// `None` when a file that's there is to be kept
+fn resolve_collision(target: PathBuf, collision: LinkCollision) -> Option<PathBuf> {
+    // A dangling symlink is still in the way
+    if std::fs::symlink_metadata(&target).is_err() {
+        return Some(target);
+    }
+    match collision {
+        LinkCollision::Skip => None,
+        LinkCollision::Overwrite => {
+            if let Err(e) = std::fs::remove_file(&target) {
+                tracing::warn!("Failed to replace {}: {}", target.display(), e);
+                return None;
+            }
+            Some(target)
+        }
+        LinkCollision::Rename => {
+            let stem = target.file_stem()?.to_string_lossy().to_string();
+            let extension = target
+                .extension()
+                .map(|e| format!(".{}", e.to_string_lossy()))
+                .unwrap_or_default();
+            (2..)
+                .map(|n| target.with_file_name(format!("{} ({}){}", stem, n, extension)))
+                .find(|candidate| std::fs::symlink_metadata(candidate).is_err())
+        }
+    }
+}
+
+/// Put `source` at `target`; returns whether it was copied rather than linked
+fn place(source: &Path, target: &Path, mode: LinkMode, warned: &mut bool) -> std::io::Result<bool> {
+    match mode {
+        LinkMode::Hardlink => match std::fs::hard_link(source, target) {
+            Ok(()) => Ok(false),
+            Err(e) => {
+                if !std::mem::replace(warned, true) {
+                    tracing::warn!(
+                        "Can't hard link into {} ({}); copying instead",
+                        target.parent().unwrap_or(target).display(),
+                        e
+                    );
+                }
+                std::fs::copy(source, target).map(|_| true)
+            }
+        },
+        LinkMode::Symlink => {
+            let source = std::fs::canonicalize(source)?;
+            symlink(&source, target).map(|()| false)
+        }
+        LinkMode::Copy => std::fs::copy(source, target).map(|_| true),
+        // Across filesystems a rename fails, so copy and remove instead
+        LinkMode::Move => match std::fs::rename(source, target) {
+            Ok(()) => Ok(false),
+            Err(_) => {
+                std::fs::copy(source, target)?;
+                std::fs::remove_file(source)?;
+                Ok(true)
+            }
+        },
+    }
+}
+
+#[cfg(unix)]
+fn symlink(source: &Path, target: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(source, target)
+}
+
+#[cfg(windows)]
+fn symlink(source: &Path, target: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_file(source, target)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn symlink(_source: &Path, _target: &Path) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// Create `dir` and any missing parents, giving each new one `dir_mode`
+fn create_dirs(dir: &Path, permissions: Permissions) -> std::io::Result<()> {
+    if dir.is_dir() {
+        return Ok(());
+    }
+    if let Some(parent) = dir.parent() {
+        create_dirs(parent, permissions)?;
+    }
+    match std::fs::create_dir(dir) {
+        Ok(()) => {
+            permissions.apply_dir(dir);
+            Ok(())
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+fn describe(mode: LinkMode) -> &'static str {
+    match mode {
+        LinkMode::Hardlink => "hard link",
+        LinkMode::Symlink => "symlink",
+        LinkMode::Copy => "copy",
+        LinkMode::Move => "move",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A finished download: a video with subtitles next to what made it
+    fn download() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir(root.join("Subs")).unwrap();
+        for name in [
+            "Show.S01E01.mkv",
+            "Subs/English.srt",
+            "show.part01.rar",
+            "show.r00",
+            "show.par2",
+            "show.vol00+01.par2",
+            "show.sfv",
+            ".dlnzb-failed",
+        ] {
+            std::fs::write(root.join(name), name).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn test_only_final_files_are_linked() {
+        let source = download();
+        let library = tempfile::tempdir().unwrap();
+        let target = library.path().join("Show.S01E01");
+
+        let linked = link_files(
+            source.path(),
+            &target,
+            LinkMode::Hardlink,
+            LinkCollision::Skip,
+            Permissions::default(),
+        );
+
+        assert_eq!(
+            linked,
+            [
+                target.join("Show.S01E01.mkv"),
+                target.join("Subs/English.srt")
+            ]
+        );
+        assert_eq!(
+            std::fs::read_to_string(target.join("Subs/English.srt")).unwrap(),
+            "Subs/English.srt"
+        );
+        // Still in the download folder too
+        assert!(source.path().join("Show.S01E01.mkv").exists());
+    }
+
+    #[test]
+    fn test_collisions() {
+        let source = download();
+        let library = tempfile::tempdir().unwrap();
+        let video = library.path().join("Show.S01E01.mkv");
+        std::fs::write(&video, "older").unwrap();
+        let link = |mode, collision| {
+            link_files(
+                source.path(),
+                library.path(),
+                mode,
+                collision,
+                Permissions::default(),
+            )
+        };
+
+        let linked = link(LinkMode::Copy, LinkCollision::Skip);
+        assert!(!linked.contains(&video));
+        assert_eq!(std::fs::read_to_string(&video).unwrap(), "older");
+
+        let linked = link(LinkMode::Copy, LinkCollision::Rename);
+        assert!(linked.contains(&library.path().join("Show.S01E01 (2).mkv")));
+
+        let linked = link(LinkMode::Move, LinkCollision::Overwrite);
+        assert!(linked.contains(&video));
+        assert_eq!(std::fs::read_to_string(&video).unwrap(), "Show.S01E01.mkv");
+        assert!(!source.path().join("Show.S01E01.mkv").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks_point_into_the_download() {
+        let source = download();
+        let library = tempfile::tempdir().unwrap();
+        link_files(
+            source.path(),
+            library.path(),
+            LinkMode::Symlink,
+            LinkCollision::Skip,
+            Permissions::default(),
+        );
+        let video = library.path().join("Show.S01E01.mkv");
+        assert_eq!(
+            std::fs::read_link(&video).unwrap(),
+            source
+                .path()
+                .canonicalize()
+                .unwrap()
+                .join("Show.S01E01.mkv")
+        );
+    }
+}
@@ -0,0 +1,320 @@
+//! The files a run hands to post-processing
+//!
+//! [`PostProcessor`](super::PostProcessor) works from a [`Manifest`] of what
+//! the run downloaded and how each file came out, not from whatever happens
+//! to be in the download folder: files an earlier run left there neither
+//! hold up extraction nor get extracted along with this download. PAR2
+//! repair can rename files into place, so the manifest follows those renames
+//! (see [`Manifest::follow_renames`]). Only [`Manifest::scan`], behind
+//! `--process-dir`, takes a folder as it finds it.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use crate::download::DownloadResult;
+use crate::patterns::{par2 as par2_patterns, rar as rar_patterns, Matching, Volume, VolumeKind};
+
+/// How a file in the manifest came out of the download
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileOutcome {
+    /// Every segment arrived, or the file was already there
+    Complete,
+    /// Some segments failed; PAR2 may still repair it
+    Damaged { segments_failed: usize },
+}
+
+/// One file of the run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestFile {
+    pub path: PathBuf,
+    pub outcome: FileOutcome,
+}
+
+impl ManifestFile {
+    fn name(&self) -> &str {
+        self.path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default()
+    }
+}
+
+/// The files one run produced in `dir`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Manifest {
+    pub dir: PathBuf,
+    pub files: Vec<ManifestFile>,
+    /// How volumes' set names compare, by default as the platform's
+    /// filesystem does
+    pub matching: Matching,
+}
+
+impl Manifest {
+    /// An empty manifest for `dir`
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            files: Vec::new(),
+            matching: Matching::PLATFORM,
+        }
+    }
+
+    /// Compare set names with `matching` rather than as the platform does
+    pub fn with_matching(mut self, matching: Matching) -> Self {
+        self.matching = matching;
+        self
+    }
+
+    /// Add the file `name` in the manifest's folder
+    pub fn with_file(mut self, name: impl AsRef<Path>, outcome: FileOutcome) -> Self {
+        self.files.push(ManifestFile {
+            path: self.dir.join(name),
+            outcome,
+        });
+        self
+    }
+
+    /// The files a download produced, in the folder of the first one
+    pub fn from_results(results: &[DownloadResult]) -> Self {
+        let dir = results
+            .first()
+            .and_then(|r| r.path.parent())
+            .unwrap_or(Path::new("."))
+            .to_path_buf();
+        let files = results
+            .iter()
+            .map(|result| ManifestFile {
+                path: result.path.clone(),
+                outcome: match result.segments_failed {
+                    0 => FileOutcome::Complete,
+                    segments_failed => FileOutcome::Damaged { segments_failed },
+                },
+            })
+            .collect();
+        Self {
+            files,
+            ..Self::new(dir)
+        }
+    }
+
+    /// Every file in `dir`, taken as complete (`--process-dir`)
+    pub fn scan(dir: &Path) -> std::io::Result<Self> {
+        let mut files: Vec<ManifestFile> = std::fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_ok_and(|t| t.is_file()))
+            .map(|entry| ManifestFile {
+                path: entry.path(),
+                outcome: FileOutcome::Complete,
+            })
+            .collect();
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(Self {
+            files,
+            ..Self::new(dir)
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// The PAR2 files among the manifest's
+    pub fn par2_files(&self) -> Vec<PathBuf> {
+        self.files
+            .iter()
+            .filter(|f| par2_patterns::is_par2_file(&f.path))
+            .map(|f| f.path.clone())
+            .collect()
+    }
+
+    /// RAR volumes by archive set, named by the base name in the form
+    /// [`Manifest::matching`] compares it (lowercased where case is ignored)
+    pub fn archive_sets(&self) -> BTreeMap<String, Vec<&ManifestFile>> {
+        let mut sets: BTreeMap<String, Vec<&ManifestFile>> = BTreeMap::new();
+        for file in &self.files {
+            if let Some(set) = archive_set(file.name(), self.matching) {
+                sets.entry(set).or_default().push(file);
+            }
+        }
+        sets
+    }
+
+    /// The archives to extract: the first volume of each set, sorted
+    pub fn archives(&self) -> Vec<PathBuf> {
+        let mut archives: Vec<PathBuf> = self
+            .files
+            .iter()
+            .filter(|f| rar_patterns::is_extractable_archive(&f.path))
+            .map(|f| f.path.clone())
+            .collect();
+        archives.sort();
+        archives
+    }
+
+    /// Archive sets with a volume that came out damaged
+    pub fn damaged_archive_sets(&self) -> Vec<String> {
+        self.archive_sets()
+            .into_iter()
+            .filter(|(_, volumes)| {
+                volumes
+                    .iter()
+                    .any(|v| matches!(v.outcome, FileOutcome::Damaged { .. }))
+            })
+            .map(|(set, _)| set)
+            .collect()
+    }
+
+    /// Names and sizes of the files in the manifest's folder, to compare
+    /// with after a step that renames files
+    pub fn listing(&self) -> HashMap<OsString, u64> {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return HashMap::new();
+        };
+        entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let metadata = entry.metadata().ok().filter(|m| m.is_file())?;
+                Some((entry.file_name(), metadata.len()))
+            })
+            .collect()
+    }
+
+    /// Catch up with a step that renamed or wrote files since `before` was
+    /// taken with [`listing`](Self::listing)
+    ///
+    /// An entry whose file is gone moves to a new file of the same size;
+    /// new files left over (a PAR2 repair writing a file out afresh) join
+    /// the manifest as complete.
+    pub fn follow_renames(&mut self, before: &HashMap<OsString, u64>) {
+        let mut new: Vec<(OsString, u64)> = self
+            .listing()
+            .into_iter()
+            .filter(|(name, _)| !before.contains_key(name))
+            .collect();
+        new.sort();
+
+        for file in &mut self.files {
+            if file.path.exists() {
+                continue;
+            }
+            let size = file.path.file_name().and_then(|name| before.get(name));
+            let Some(position) = new.iter().position(|(_, len)| Some(len) == size) else {
+                continue;
+            };
+            let (name, _) = new.remove(position);
+            tracing::debug!(
+                "{} was renamed to {}",
+                file.path.display(),
+                name.to_string_lossy()
+            );
+            file.path = self.dir.join(name);
+        }
+        for (name, _) in new {
+            self.files.push(ManifestFile {
+                path: self.dir.join(name),
+                outcome: FileOutcome::Complete,
+            });
+        }
+    }
+}
+
+/// The set name of a RAR volume (`.rar`, `.partNN.rar`, `.rNN`), normalized
+/// for `matching`
+fn archive_set(filename: &str, matching: Matching) -> Option<String> {
+    Volume::parse(filename)
+        .filter(|volume| volume.kind == VolumeKind::Rar)
+        .map(|volume| matching.normalize(volume.set))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sets_and_damaged_sets() {
+        let manifest = Manifest::new("/dl")
+            .with_matching(Matching::IgnoreCase)
+            .with_file("show.part01.rar", FileOutcome::Complete)
+            .with_file(
+                "show.part02.rar",
+                FileOutcome::Damaged { segments_failed: 3 },
+            )
+            .with_file("Extras.rar", FileOutcome::Complete)
+            .with_file("extras.r00", FileOutcome::Complete)
+            .with_file("show.par2", FileOutcome::Complete)
+            .with_file("show.nfo", FileOutcome::Complete);
+
+        let sets: Vec<(String, usize)> = manifest
+            .archive_sets()
+            .into_iter()
+            .map(|(set, volumes)| (set, volumes.len()))
+            .collect();
+        assert_eq!(sets, [("extras".to_string(), 2), ("show".to_string(), 2)]);
+        assert_eq!(manifest.damaged_archive_sets(), ["show"]);
+        assert_eq!(
+            manifest.archives(),
+            [
+                PathBuf::from("/dl/Extras.rar"),
+                PathBuf::from("/dl/show.part01.rar")
+            ]
+        );
+        assert_eq!(manifest.par2_files(), [PathBuf::from("/dl/show.par2")]);
+    }
+
+    #[test]
+    fn test_set_named_after_another() {
+        for (matching, show, extras) in [
+            (Matching::Exact, "Show.S01", "Show.S01.Extras"),
+            (Matching::IgnoreCase, "show.s01", "show.s01.extras"),
+        ] {
+            let manifest = Manifest::new("/dl")
+                .with_matching(matching)
+                .with_file("Show.S01.part01.rar", FileOutcome::Complete)
+                .with_file("Show.S01.part02.rar", FileOutcome::Complete)
+                .with_file(
+                    "Show.S01.Extras.part01.rar",
+                    FileOutcome::Damaged { segments_failed: 1 },
+                )
+                .with_file("Show.S01.Extras.r00", FileOutcome::Complete);
+
+            let sets: Vec<(String, usize)> = manifest
+                .archive_sets()
+                .into_iter()
+                .map(|(set, volumes)| (set, volumes.len()))
+                .collect();
+            assert_eq!(sets, [(show.to_string(), 2), (extras.to_string(), 2)]);
+            assert_eq!(manifest.damaged_archive_sets(), [extras]);
+        }
+
+        // Sets differing only in case are two sets where case counts
+        let manifest = Manifest::new("/dl")
+            .with_matching(Matching::Exact)
+            .with_file("Show.rar", FileOutcome::Complete)
+            .with_file("show.r00", FileOutcome::Complete);
+        assert_eq!(manifest.archive_sets().len(), 2);
+    }
+
+    #[test]
+    fn test_follow_renames() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a8f3e1.bin"), [0u8; 10]).unwrap();
+        std::fs::write(dir.path().join("old.rar"), [0u8; 20]).unwrap();
+        let mut manifest = Manifest::new(dir.path()).with_file("a8f3e1.bin", FileOutcome::Complete);
+        let before = manifest.listing();
+
+        // As PAR2 would: the obfuscated file renamed, another written afresh
+        std::fs::rename(
+            dir.path().join("a8f3e1.bin"),
+            dir.path().join("show.part01.rar"),
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("show.part02.rar"), [0u8; 5]).unwrap();
+        manifest.follow_renames(&before);
+
+        let names: Vec<&str> = manifest.files.iter().map(|f| f.name()).collect();
+        assert_eq!(names, ["show.part01.rar", "show.part02.rar"]);
+        // What was there before isn't taken in
+        assert_eq!(manifest.archives(), [dir.path().join("show.part01.rar")]);
+    }
+}
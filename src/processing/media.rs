@@ -0,0 +1,394 @@
+//! Container-level sanity checks for extracted media files
+//!
+//! These checks only read headers and a few bytes at computed offsets, so they are
+//! cheap even for multi-gigabyte files. They catch the common case of a truncated
+//! file produced from a damaged archive that PAR2 could not see.
+
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// Media extensions that have a container check
+const VERIFIABLE_EXTENSIONS: &[&str] = &["mkv", "webm", "mp4", "m4v", "mov", "avi"];
+
+/// Maximum directory depth scanned for media files
+const MAX_SCAN_DEPTH: usize = 4;
+
+/// EBML element IDs (with the length marker bits kept, as they appear on disk)
+const EBML_HEADER_ID: u64 = 0x1A45_DFA3;
+const SEGMENT_ID: u64 = 0x1853_8067;
+const SEEK_HEAD_ID: u64 = 0x114D_9B74;
+const SEEK_ID: u64 = 0x4DBB;
+const SEEK_ID_ID: u64 = 0x53AB;
+const SEEK_POSITION_ID: u64 = 0x53AC;
+const CUES_ID: u64 = 0x1C53_BB6B;
+
+/// Result of verifying a single media file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MediaCheck {
+    pub path: PathBuf,
+    pub format: String,
+    pub passed: bool,
+    pub reason: Option<String>,
+}
+
+/// Verify all media files below a directory
+pub fn verify_directory(dir: &Path) -> Vec<MediaCheck> {
+    let mut files = Vec::new();
+    collect_media_files(dir, 0, &mut files);
+    files.sort();
+
+    files.iter().filter_map(|path| verify_file(path)).collect()
+}
+
+fn collect_media_files(dir: &Path, depth: usize, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+        if path.is_dir() {
+            if depth < MAX_SCAN_DEPTH {
+                collect_media_files(&path, depth + 1, files);
+            }
+        } else if media_format(&path).is_some() {
+            files.push(path);
+        }
+    }
+}
+
+fn media_format(path: &Path) -> Option<String> {
+    let ext = path.extension()?.to_str()?.to_lowercase();
+    VERIFIABLE_EXTENSIONS.contains(&ext.as_str()).then_some(ext)
+}
+
+/// Verify a single media file, returning None if the format has no check
+pub fn verify_file(path: &Path) -> Option<MediaCheck> {
+    let format = media_format(path)?;
+
+    let outcome = match format.as_str() {
+        "mkv" | "webm" => check_matroska(path),
+        "mp4" | "m4v" | "mov" => check_mp4(path),
+        "avi" => check_avi(path),
+        _ => return None,
+    };
+
+    let (passed, reason) = match outcome {
+        Ok(()) => (true, None),
+        Err(reason) => (false, Some(reason)),
+    };
+
+    Some(MediaCheck {
+        path: path.to_path_buf(),
+        format,
+        passed,
+        reason,
+    })
+}
+
+type CheckResult = std::result::Result<(), String>;
+
+fn open_with_len(path: &Path) -> std::result::Result<(File, u64), String> {
+    let file = File::open(path).map_err(|e| format!("cannot open: {}", e))?;
+    let len = file
+        .metadata()
+        .map_err(|e| format!("cannot stat: {}", e))?
+        .len();
+    Ok((file, len))
+}
+
+fn read_at(file: &mut File, offset: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+    file.seek(SeekFrom::Start(offset))?;
+    let mut total = 0;
+    while total < buf.len() {
+        let n = file.read(&mut buf[total..])?;
+        if n == 0 {
+            break;
+        }
+        total += n;
+    }
+    Ok(total)
+}
+
+/// Read an EBML variable-length integer, returning (value, length)
+///
+/// When `keep_marker` is true the length marker bit is preserved (element IDs).
+fn read_vint(data: &[u8], keep_marker: bool) -> Option<(u64, usize)> {
+    let first = *data.first()?;
+    if first == 0 {
+        return None;
+    }
+    let len = first.leading_zeros() as usize + 1;
+    if len > 8 || data.len() < len {
+        return None;
+    }
+
+    let mut value = if keep_marker {
+        first as u64
+    } else {
+        (first as u64) & (0xFF >> len)
+    };
+    for &b in &data[1..len] {
+        value = (value << 8) | b as u64;
+    }
+    Some((value, len))
+}
+
+/// Whether a size vint has all value bits set ("unknown size")
+fn is_unknown_size(value: u64, len: usize) -> bool {
+    value == (1u64 << (7 * len)) - 1
+}
+
+/// Read an element header at `pos`, returning (id, size, header length)
+fn read_element(data: &[u8], pos: usize) -> Option<(u64, Option<u64>, usize)> {
+    let (id, id_len) = read_vint(data.get(pos..)?, true)?;
+    let (size, size_len) = read_vint(data.get(pos + id_len..)?, false)?;
+    let size = (!is_unknown_size(size, size_len)).then_some(size);
+    Some((id, size, id_len + size_len))
+}
+
+fn read_uint(data: &[u8]) -> u64 {
+    data.iter().fold(0u64, |acc, &b| (acc << 8) | b as u64)
+}
+
+fn check_matroska(path: &Path) -> CheckResult {
+    let (mut file, len) = open_with_len(path)?;
+    let mut buf = vec![0u8; 64 * 1024];
+    let read = read_at(&mut file, 0, &mut buf).map_err(|e| e.to_string())?;
+    let data = &buf[..read];
+
+    let (id, header_size, header_len) =
+        read_element(data, 0).ok_or("file too short for an EBML header")?;
+    if id != EBML_HEADER_ID {
+        return Err("missing EBML header".to_string());
+    }
+    let header_size = header_size.ok_or("EBML header has unknown size")?;
+
+    let segment_pos = header_len + header_size as usize;
+    let (id, segment_size, segment_header_len) =
+        read_element(data, segment_pos).ok_or("missing Segment element")?;
+    if id != SEGMENT_ID {
+        return Err("missing Segment element".to_string());
+    }
+    let segment_data_start = (segment_pos + segment_header_len) as u64;
+
+    if let Some(size) = segment_size {
+        if segment_data_start + size > len {
+            return Err(format!(
+                "truncated: Segment declares {} bytes but only {} present",
+                size,
+                len.saturating_sub(segment_data_start)
+            ));
+        }
+    }
+
+    // Follow the SeekHead to the Cues element, which muxers write at the end
+    if let Some(cues_offset) = find_cues_offset(data, segment_data_start as usize) {
+        let cues_pos = segment_data_start + cues_offset;
+        if cues_pos >= len {
+            return Err("truncated: Cues element lies beyond end of file".to_string());
+        }
+        let mut id_buf = [0u8; 4];
+        let n = read_at(&mut file, cues_pos, &mut id_buf).map_err(|e| e.to_string())?;
+        if n < 4 || read_uint(&id_buf) != CUES_ID {
+            return Err("Cues element not found at its indexed position".to_string());
+        }
+    }
+
+    Ok(())
+}
+
+/// Locate the Cues position (relative to segment data) listed in the SeekHead
+fn find_cues_offset(data: &[u8], segment_data_start: usize) -> Option<u64> {
+    let (id, size, header_len) = read_element(data, segment_data_start)?;
+    if id != SEEK_HEAD_ID {
+        return None;
+    }
+
+    let mut pos = segment_data_start + header_len;
+    let end = (pos + size? as usize).min(data.len());
+
+    while pos < end {
+        let (id, size, header_len) = read_element(data, pos)?;
+        let size = size? as usize;
+        let body_start = pos + header_len;
+        pos = body_start + size;
+
+        if id != SEEK_ID || pos > data.len() {
+            continue;
+        }
+
+        let mut seek_id = None;
+        let mut seek_position = None;
+        let mut inner = body_start;
+        while inner < pos {
+            let (child_id, child_size, child_header) = read_element(data, inner)?;
+            let child_size = child_size? as usize;
+            let value = data.get(inner + child_header..inner + child_header + child_size)?;
+            match child_id {
+                SEEK_ID_ID => seek_id = Some(read_uint(value)),
+                SEEK_POSITION_ID => seek_position = Some(read_uint(value)),
+                _ => {}
+            }
+            inner += child_header + child_size;
+        }
+
+        if seek_id == Some(CUES_ID) {
+            return seek_position;
+        }
+    }
+
+    None
+}
+
+fn check_mp4(path: &Path) -> CheckResult {
+    let (mut file, len) = open_with_len(path)?;
+    let mut offset = 0u64;
+    let mut found_moov = false;
+    let mut boxes = 0;
+
+    while offset < len {
+        boxes += 1;
+        if boxes > 10_000 {
+            return Err("too many top-level boxes".to_string());
+        }
+
+        let mut header = [0u8; 16];
+        let n = read_at(&mut file, offset, &mut header).map_err(|e| e.to_string())?;
+        if n < 8 {
+            return Err(format!("truncated box header at offset {}", offset));
+        }
+
+        let size32 = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+        let box_type = &header[4..8];
+        let box_size = match size32 {
+            0 => len - offset,
+            1 => {
+                if n < 16 {
+                    return Err(format!("truncated box header at offset {}", offset));
+                }
+                u64::from_be_bytes(header[8..16].try_into().expect("8 byte slice"))
+            }
+            size => size,
+        };
+
+        if box_size < 8 {
+            return Err(format!(
+                "invalid box size {} at offset {}",
+                box_size, offset
+            ));
+        }
+        let Some(end) = offset.checked_add(box_size).filter(|end| *end <= len) else {
+            return Err(format!(
+                "truncated: '{}' box declares {} bytes but only {} remain",
+                String::from_utf8_lossy(box_type),
+                box_size,
+                len - offset
+            ));
+        };
+
+        if box_type == b"moov" {
+            found_moov = true;
+        }
+        offset = end;
+    }
+
+    if found_moov {
+        Ok(())
+    } else {
+        Err("missing 'moov' atom".to_string())
+    }
+}
+
+fn check_avi(path: &Path) -> CheckResult {
+    let (mut file, len) = open_with_len(path)?;
+    let mut header = [0u8; 12];
+    let n = read_at(&mut file, 0, &mut header).map_err(|e| e.to_string())?;
+
+    if n < 12 || &header[0..4] != b"RIFF" || &header[8..12] != b"AVI " {
+        return Err("missing RIFF AVI header".to_string());
+    }
+
+    let riff_size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as u64;
+    if riff_size + 8 > len {
+        return Err(format!(
+            "truncated: RIFF declares {} bytes but only {} present",
+            riff_size + 8,
+            len
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn write_temp(dir: &Path, name: &str, data: &[u8]) -> PathBuf {
+        let path = dir.join(name);
+        File::create(&path).unwrap().write_all(data).unwrap();
+        path
+    }
+
+    fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut out = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(kind);
+        out.extend_from_slice(payload);
+        out
+    }
+
+    #[test]
+    fn test_mp4_checks() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut good = mp4_box(b"ftyp", b"isom0000");
+        good.extend(mp4_box(b"moov", &[0u8; 32]));
+        good.extend(mp4_box(b"mdat", &[0u8; 64]));
+        let path = write_temp(dir.path(), "good.mp4", &good);
+        assert!(verify_file(&path).unwrap().passed);
+
+        let truncated = &good[..good.len() - 10];
+        let path = write_temp(dir.path(), "short.mp4", truncated);
+        assert!(!verify_file(&path).unwrap().passed);
+
+        let mut no_moov = mp4_box(b"ftyp", b"isom0000");
+        no_moov.extend(mp4_box(b"mdat", &[0u8; 64]));
+        let path = write_temp(dir.path(), "nomoov.mp4", &no_moov);
+        assert!(!verify_file(&path).unwrap().passed);
+
+        let mut huge = mp4_box(b"ftyp", b"isom0000");
+        huge.extend_from_slice(&1u32.to_be_bytes());
+        huge.extend_from_slice(b"mdat");
+        huge.extend_from_slice(&u64::MAX.to_be_bytes());
+        let path = write_temp(dir.path(), "huge.mp4", &huge);
+        assert!(!verify_file(&path).unwrap().passed);
+    }
+
+    #[test]
+    fn test_matroska_segment_size() {
+        let dir = tempfile::tempdir().unwrap();
+
+        // EBML header with an empty body, then a Segment declaring 16 bytes
+        let mut mkv = vec![0x1A, 0x45, 0xDF, 0xA3, 0x80];
+        mkv.extend([0x18, 0x53, 0x80, 0x67, 0x90]);
+        mkv.extend([0u8; 16]);
+        let path = write_temp(dir.path(), "good.mkv", &mkv);
+        assert!(verify_file(&path).unwrap().passed);
+
+        let path = write_temp(dir.path(), "short.mkv", &mkv[..mkv.len() - 4]);
+        let check = verify_file(&path).unwrap();
+        assert!(!check.passed);
+        assert!(check.reason.unwrap().contains("truncated"));
+
+        let path = write_temp(dir.path(), "bad.mkv", &[0u8; 32]);
+        assert!(!verify_file(&path).unwrap().passed);
+    }
+
+    #[test]
+    fn test_unsupported_extension_is_skipped() {
+        assert!(verify_file(Path::new("notes.txt")).is_none());
+    }
+}
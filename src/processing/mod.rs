@@ -1,11 +1,39 @@
 //! Post-processing functionality
 //!
-//! This module handles PAR2 verification/repair, RAR extraction, and file deobfuscation.
+//! This module handles PAR2 verification/repair, RAR extraction, file deobfuscation,
+//! removal of ad files, media container verification, PAR2 creation for the
+//! finished files, and the external post-processing script hook.
 
+mod cleanup;
 mod deobfuscate;
+mod duplicates;
 mod file_extension;
+mod library;
+mod manifest;
+mod media;
+mod names;
 mod par2;
+#[cfg(feature = "builtin-par2")]
+mod par2_builtin;
+mod par2_cli;
+mod par2_estimate;
+mod par2_packets;
+mod password;
 mod post_processor;
 mod rar;
+mod script;
 
-pub use post_processor::PostProcessor;
+pub use cleanup::{compile_cleanup_patterns, DEFAULT_CLEANUP_PATTERNS};
+pub use library::link_files;
+pub use manifest::{FileOutcome, Manifest, ManifestFile};
+pub use media::MediaCheck;
+pub use names::NAMES_FILE;
+pub use par2::{create_par2, self_test as par2_self_test, CreatedPar2, Par2CreateOptions};
+pub use par2_estimate::{par2_shortfalls, Par2Shortfall};
+pub use par2_packets::{
+    descriptions_in_dir, file_matches, find_damaged_files, BlockChecksum, FileDescription,
+    Par2Index,
+};
+pub use post_processor::{PostProcessResult, PostProcessor};
+pub use rar::{list_partial_archive, ArchiveEntry, ArchiveListing, SpaceShortfall};
+pub use script::{run_script, ScriptContext, ScriptOutcome};
@@ -0,0 +1,132 @@
+//! Keeping the names files were posted under (`post_processing.preserve_names`)
+//!
+//! PAR2 repair renames misnamed files to the names in the recovery set, and
+//! deobfuscation renames more. With `preserve_names` deobfuscation is
+//! skipped and whatever PAR2 renamed or wrote out afresh is moved back under
+//! its posted name; the names the recovery set gives are written to
+//! [`NAMES_FILE`] in the download folder instead, so nothing is lost.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{OsStr, OsString};
+use std::path::Path;
+
+use super::manifest::Manifest;
+use super::par2_packets::{self, Par2Index};
+
+/// Posted name → name in the PAR2 set, written next to the files
+pub const NAMES_FILE: &str = "names.json";
+
+/// Files of the manifest posted under a name the PAR2 set doesn't know,
+/// with the name it gives them
+///
+/// A file is matched the way PAR2 matches it, by the hash of its first
+/// 16 KiB, so a file damaged further in is still found.
+pub(super) fn discover(manifest: &Manifest, index: &Par2Index) -> BTreeMap<String, String> {
+    let descriptions = &index.files;
+    let mut names = BTreeMap::new();
+    if descriptions.is_empty() {
+        return names;
+    }
+    let mut candidates: Vec<&par2_packets::FileDescription> = descriptions.values().collect();
+    candidates.sort_by(|a, b| a.name.cmp(&b.name));
+
+    for file in &manifest.files {
+        let Some(posted) = file.path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        if descriptions.contains_key(posted) || !file.path.is_file() {
+            continue;
+        }
+        let real = candidates
+            .iter()
+            .find(|desc| par2_packets::file_starts_like(desc, &file.path).unwrap_or(false));
+        if let Some(desc) = real {
+            tracing::debug!("{} is {} in the PAR2 set", posted, desc.name);
+            names.insert(posted.to_string(), desc.name.clone());
+        }
+    }
+    names
+}
+
+/// Move what PAR2 renamed or repaired back under the posted names,
+/// returning how many files were moved
+///
+/// `before` is the folder's [`Manifest::listing`] from before repair. A
+/// file under its PAR2 name goes back only when it's the size the posted
+/// file was, so it takes the posted file's place byte for byte; otherwise
+/// it's left where PAR2 put it and [`NAMES_FILE`] records the rename.
+pub(super) fn restore(
+    dir: &Path,
+    names: &BTreeMap<String, String>,
+    before: &HashMap<OsString, u64>,
+) -> usize {
+    let mut restored = 0;
+    for (posted, real) in names {
+        let from = dir.join(real);
+        let Ok(metadata) = std::fs::metadata(&from) else {
+            continue;
+        };
+        if before.get(OsStr::new(posted)) != Some(&metadata.len()) {
+            tracing::debug!(
+                "Left {} under its PAR2 name: not the size {} was",
+                real,
+                posted
+            );
+            continue;
+        }
+        match std::fs::rename(&from, dir.join(posted)) {
+            Ok(()) => restored += 1,
+            Err(e) => tracing::warn!("Failed to move {} back to {}: {}", real, posted, e),
+        }
+    }
+    restored
+}
+
+/// Write `names` to [`NAMES_FILE`] in `dir`, if there are any
+pub(super) fn write(dir: &Path, names: &BTreeMap<String, String>) -> std::io::Result<()> {
+    if names.is_empty() {
+        return Ok(());
+    }
+    let json = serde_json::to_string_pretty(names).map_err(std::io::Error::other)?;
+    std::fs::write(dir.join(NAMES_FILE), json + "\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::FileOutcome;
+
+    #[test]
+    fn test_discover_restore_and_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let contents: Vec<u8> = (0..40_000u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(dir.path().join("a8f3e1"), &contents).unwrap();
+        std::fs::write(
+            dir.path().join("show.par2"),
+            par2_packets::recovery_set_packets("show.mkv", &contents, 4096),
+        )
+        .unwrap();
+        let manifest = Manifest::new(dir.path())
+            .with_file("a8f3e1", FileOutcome::Complete)
+            .with_file("show.par2", FileOutcome::Complete);
+
+        let names = discover(&manifest, &Par2Index::read(dir.path()));
+        assert_eq!(
+            names,
+            BTreeMap::from([("a8f3e1".to_string(), "show.mkv".to_string())])
+        );
+
+        // As PAR2 would, then back again
+        let before = manifest.listing();
+        std::fs::rename(dir.path().join("a8f3e1"), dir.path().join("show.mkv")).unwrap();
+        assert_eq!(restore(dir.path(), &names, &before), 1);
+        assert!(dir.path().join("a8f3e1").is_file());
+        assert!(!dir.path().join("show.mkv").exists());
+
+        write(dir.path(), &names).unwrap();
+        let written: BTreeMap<String, String> =
+            serde_json::from_str(&std::fs::read_to_string(dir.path().join(NAMES_FILE)).unwrap())
+                .unwrap();
+        assert_eq!(written, names);
+    }
+}
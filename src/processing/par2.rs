@@ -1,42 +1,210 @@
 //! PAR2 verification and repair functionality
+//!
+//! Repair runs on a [`Par2Backend`]: the built-in engine when compiled with
+//! the `builtin-par2` feature, otherwise a `par2` (par2cmdline) binary found
+//! on `PATH`. Creating a recovery set for finished files ([`create_par2`])
+//! always takes the binary, as the built-in engine only verifies and repairs.
 
-use indicatif::ProgressBar;
+use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
+#[cfg(feature = "builtin-par2")]
+use super::par2_builtin::BuiltinPar2;
+use super::par2_cli::CliPar2;
 use crate::config::PostProcessingConfig;
 use crate::error::{DlNzbError, PostProcessingError};
 use crate::patterns::par2 as par2_patterns;
-use crate::progress;
-use par2_rs::{MessageCallback, MessageLevel, Par2Operation, Par2Repairer, ProgressCallback};
+use crate::progress::{NoProgress, PhaseEnd, PhaseStep, ProgressSink};
 
 type Result<T> = std::result::Result<T, DlNzbError>;
 
 /// Result of PAR2 repair attempt
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Par2Status {
     /// No PAR2 files found - safe to proceed with extraction
     NoPar2Files,
-    /// PAR2 repair succeeded - files verified/repaired, safe to extract
+    /// PAR2 verification succeeded without needing repair, safe to extract
     Success,
+    /// PAR2 repaired damaged or missing files, safe to extract
+    Repaired,
     /// PAR2 repair failed - files may be corrupted, NOT safe to extract
-    Failed,
+    Failed { reason: String },
+}
+
+impl Par2Status {
+    /// Whether the files are complete (verified or repaired)
+    pub fn is_ok(&self) -> bool {
+        matches!(self, Par2Status::Success | Par2Status::Repaired)
+    }
+}
+
+/// What verification and repair have found so far
+#[derive(Debug, Default)]
+pub(super) struct Par2Counts {
+    pub damaged: usize,
+    pub missing: usize,
+    /// Files found under another name
+    pub obfuscated: usize,
+    pub repaired: usize,
+}
+
+impl Par2Counts {
+    /// Progress step and message while verifying
+    pub fn verifying(&self) -> (PhaseStep, String) {
+        let mut parts = Vec::new();
+        if self.obfuscated > 0 {
+            parts.push(format!("{} found", self.obfuscated));
+        }
+        if self.damaged > 0 {
+            parts.push(format!("{} damaged", self.damaged));
+        }
+        if self.missing > 0 {
+            parts.push(format!("{} missing", self.missing));
+        }
+        let step = if self.damaged > 0 || self.missing > 0 {
+            PhaseStep::VerifyingDamaged
+        } else {
+            PhaseStep::Verifying
+        };
+        if parts.is_empty() {
+            (step, "Verifying...".to_string())
+        } else {
+            (step, format!("Verifying... ({})", parts.join(", ")))
+        }
+    }
+}
+
+/// How much recovery data [`create_par2`] makes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Par2CreateOptions {
+    /// Recovery data as a percentage of the files' size
+    pub redundancy: u32,
+    /// Bytes per block, a multiple of 4; 0 lets par2 choose
+    pub block_size: u64,
+}
+
+impl Par2CreateOptions {
+    /// The `post_processing.par2_redundancy` and `par2_block_size` settings
+    pub fn from_config(config: &PostProcessingConfig) -> Self {
+        Self {
+            redundancy: config.par2_redundancy,
+            block_size: config.par2_block_size,
+        }
+    }
+}
+
+/// A recovery set made by [`create_par2`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CreatedPar2 {
+    /// The index file
+    pub index: PathBuf,
+    /// Recovery volumes, in name order
+    pub volumes: Vec<PathBuf>,
+    /// Files the set protects
+    pub files: usize,
+    /// Percentage of recovery data
+    pub redundancy: u32,
+}
+
+/// A PAR2 engine that can verify and repair a recovery set
+///
+/// Backends report progress to the sink and keep the counts up to date as
+/// they go; an error is the reason the set couldn't be verified or repaired.
+pub(super) trait Par2Backend: Send + Sync {
+    /// How the backend is described to the user, e.g. `built-in`
+    fn name(&self) -> &str;
+
+    /// Verify the set `main_par2` belongs to, repairing it if needed
+    fn repair(
+        &self,
+        main_par2: &Path,
+        progress: &Arc<dyn ProgressSink>,
+        counts: &Arc<Mutex<Par2Counts>>,
+    ) -> std::result::Result<(), String>;
+
+    /// Create the recovery set `index` for `files`, both relative to `dir`
+    fn create(
+        &self,
+        _dir: &Path,
+        _index: &Path,
+        _files: &[PathBuf],
+        _options: &Par2CreateOptions,
+        _progress: &Arc<dyn ProgressSink>,
+    ) -> std::result::Result<(), String> {
+        Err(format!(
+            "the {} PAR2 engine can't create recovery sets",
+            self.name()
+        ))
+    }
+}
+
+/// The backend to repair with, if any is usable
+///
+/// The built-in engine is used when compiled in; otherwise a `par2` binary
+/// on `PATH`.
+pub(super) fn backend() -> Option<Box<dyn Par2Backend>> {
+    #[cfg(feature = "builtin-par2")]
+    let builtin: Option<Box<dyn Par2Backend>> = Some(Box::new(BuiltinPar2));
+    #[cfg(not(feature = "builtin-par2"))]
+    let builtin: Option<Box<dyn Par2Backend>> = None;
+
+    builtin.or_else(|| CliPar2::find().map(|cli| Box::new(cli) as Box<dyn Par2Backend>))
+}
+
+/// The backend to create recovery sets with: a `par2` binary on `PATH`,
+/// whether or not the built-in engine is compiled in
+pub(super) fn creator() -> Option<Box<dyn Par2Backend>> {
+    CliPar2::find().map(|cli| Box::new(cli) as Box<dyn Par2Backend>)
+}
+
+/// Verify a tiny generated recovery set with the PAR2 backend
+///
+/// Used by `config --check` to confirm PAR2 support works on this machine;
+/// returns the backend's name.
+pub fn self_test() -> Result<String> {
+    let backend = backend().ok_or_else(|| PostProcessingError::ToolNotFound {
+        tool: "par2".to_string(),
+    })?;
+
+    let dir = tempfile::tempdir()?;
+    let contents: Vec<u8> = (0..4096u32).map(|i| (i % 251) as u8).collect();
+    std::fs::write(dir.path().join("selftest.bin"), &contents)?;
+    let par2_path = dir.path().join("selftest.par2");
+    std::fs::write(
+        &par2_path,
+        super::par2_packets::recovery_set_packets("selftest.bin", &contents, 1024),
+    )?;
+
+    let progress: Arc<dyn ProgressSink> = Arc::new(NoProgress);
+    backend
+        .repair(&par2_path, &progress, &Arc::default())
+        .map_err(|reason| PostProcessingError::Par2Failed { reason })?;
+    Ok(backend.name().to_string())
 }
 
 /// Run PAR2 verification and repair on downloaded files
-pub async fn repair_with_par2(
+pub(super) async fn repair_with_par2(
     config: &PostProcessingConfig,
     download_dir: &Path,
     downloaded_par2_files: &[PathBuf],
-    progress_bar: &ProgressBar,
+    backend: &dyn Par2Backend,
+    progress: &Arc<dyn ProgressSink>,
 ) -> Result<Par2Status> {
-    progress_bar.set_message("Searching for PAR2 files...");
+    progress.on_phase_progress(PhaseStep::Scanning, 0, 0, "Searching for PAR2 files...");
 
-    if downloaded_par2_files.is_empty() {
-        progress_bar.finish_and_clear();
+    // Find the main PAR2 file (index file without .vol), else the smallest one
+    let mut par2_files = downloaded_par2_files.to_vec();
+    par2_files.sort_by_key(|p| p.metadata().ok().map(|m| m.len()).unwrap_or(u64::MAX));
+    let Some(main_par2) = par2_files
+        .iter()
+        .find(|p| par2_patterns::is_main_par2(p))
+        .or(par2_files.first())
+    else {
+        progress.on_phase_done(PhaseEnd::Skipped);
         return Ok(Par2Status::NoPar2Files);
-    }
+    };
 
     // Get list of files before PAR2 repair (to detect renames)
     let files_before: HashSet<String> = std::fs::read_dir(download_dir)?
@@ -44,115 +212,13 @@ pub async fn repair_with_par2(
         .map(|entry| entry.file_name().to_string_lossy().to_string())
         .collect();
 
-    let mut par2_files = downloaded_par2_files.to_vec();
-
     // Count total files to scan for progress tracking
     let total_files = files_before.len() as u64;
-    progress_bar.set_length(total_files);
-    progress::apply_style(progress_bar, progress::ProgressStyle::Par2);
-
-    // Find the main PAR2 file (index file without .vol)
-    let main_par2 = if let Some(main) = par2_files.iter().find(|p| par2_patterns::is_main_par2(p)) {
-        main
-    } else {
-        // Fall back to smallest file
-        par2_files.sort_by_key(|p| p.metadata().ok().map(|m| m.len()).unwrap_or(u64::MAX));
-        par2_files
-            .first()
-            .ok_or_else(|| PostProcessingError::Par2(par2_rs::Par2Error::NotFound))?
-    };
-
-    progress_bar.set_position(0);
-    progress_bar.set_message("Verifying files...");
-
-    let repairer = Par2Repairer::new(main_par2).map_err(PostProcessingError::Par2)?;
-
-    // Track counts for live status updates
-    #[derive(Default)]
-    struct Par2Counts {
-        damaged: usize,
-        missing: usize,
-        obfuscated: usize,
-        repaired: usize,
-    }
-    let counts = Arc::new(std::sync::Mutex::new(Par2Counts::default()));
-    let messages: Arc<std::sync::Mutex<Vec<(MessageLevel, String)>>> =
-        Arc::new(std::sync::Mutex::new(Vec::new()));
-
-    // Progress callback updates the progress bar
-    let pb_clone = progress_bar.clone();
-    let counts_for_progress = counts.clone();
-    let progress_callback: ProgressCallback = Arc::new(move |operation, current, total| {
-        pb_clone.set_length(total);
-        pb_clone.set_position(current);
-
-        match operation {
-            Par2Operation::Scanning => {
-                pb_clone.set_message("Scanning files...");
-                progress::apply_style(&pb_clone, progress::ProgressStyle::Par2);
-            }
-            Par2Operation::Loading => {
-                pb_clone.set_message("Loading PAR2 data...");
-                progress::apply_style(&pb_clone, progress::ProgressStyle::Par2);
-            }
-            Par2Operation::Verifying => {
-                if let Ok(c) = counts_for_progress.lock() {
-                    let mut parts = Vec::new();
-                    if c.obfuscated > 0 {
-                        parts.push(format!("{} found", c.obfuscated));
-                    }
-                    if c.damaged > 0 {
-                        parts.push(format!("{} damaged", c.damaged));
-                    }
-                    if c.missing > 0 {
-                        parts.push(format!("{} missing", c.missing));
-                    }
-                    if parts.is_empty() {
-                        pb_clone.set_message("Verifying...");
-                    } else {
-                        pb_clone.set_message(format!("Verifying... ({})", parts.join(", ")));
-                    }
-                } else {
-                    pb_clone.set_message("Verifying...");
-                }
-                progress::apply_style(&pb_clone, progress::ProgressStyle::Par2Verify);
-            }
-            Par2Operation::Repairing => {
-                pb_clone.set_message("Repairing...");
-                progress::apply_style(&pb_clone, progress::ProgressStyle::Par2Repair);
-            }
-        }
-    });
-
-    // Message callback collects messages and updates counts
-    // Note: Message patterns are coupled to par2-rs message format
-    let messages_clone = messages.clone();
-    let counts_clone = counts.clone();
-    let message_callback: MessageCallback = Arc::new(move |level, message| {
-        if let Ok(mut msgs) = messages_clone.lock() {
-            msgs.push((level, message.to_string()));
-        }
+    progress.on_phase_progress(PhaseStep::Scanning, 0, total_files, "Verifying files...");
 
-        if let Ok(mut c) = counts_clone.lock() {
-            match level {
-                MessageLevel::Warning if message.contains("damaged") => c.damaged += 1,
-                MessageLevel::Error if message.contains("Missing") => c.missing += 1,
-                MessageLevel::Info if message.contains("obfuscated") => c.obfuscated += 1,
-                MessageLevel::Info if message.contains("Repairing") => c.repaired += 1,
-                _ => {}
-            }
-        }
-    });
-
-    match repairer.repair_with_callbacks(
-        true,
-        false,
-        Some(progress_callback),
-        Some(message_callback),
-    ) {
+    let counts = Arc::new(Mutex::new(Par2Counts::default()));
+    match backend.repair(main_par2, progress, &counts) {
         Ok(()) => {
-            progress_bar.set_position(total_files);
-
             // Check if any files were renamed
             let files_after: HashSet<String> = std::fs::read_dir(download_dir)?
                 .filter_map(|entry| entry.ok())
@@ -170,9 +236,10 @@ pub async fn repair_with_par2(
                 }
             }
 
-            progress_bar.finish_with_message("  ");
+            progress.on_phase_done(PhaseEnd::Finished);
 
             // Build summary from counts
+            let repaired = counts.lock().map(|c| c.repaired > 0).unwrap_or(false);
             let mut summary_parts = Vec::new();
             if renamed_count > 0 {
                 summary_parts.push(format!("{} renamed", renamed_count));
@@ -187,21 +254,22 @@ pub async fn repair_with_par2(
             }
 
             if summary_parts.is_empty() {
-                println!("  └─ \x1b[33m✓ PAR2 verified\x1b[0m");
+                progress.on_status("  └─ \x1b[33m✓ PAR2 verified\x1b[0m");
             } else {
-                println!(
+                progress.on_status(&format!(
                     "  └─ \x1b[33m✓ PAR2 verified ({})\x1b[0m",
                     summary_parts.join(", ")
-                );
+                ));
             }
 
-            Ok(Par2Status::Success)
+            Ok(if repaired {
+                Par2Status::Repaired
+            } else {
+                Par2Status::Success
+            })
         }
-        Err(e) => {
-            let error_msg = e.to_string();
-
-            progress::apply_style(progress_bar, progress::ProgressStyle::Par2Error);
-            progress_bar.finish_with_message("  ");
+        Err(error_msg) => {
+            progress.on_phase_done(PhaseEnd::Failed);
 
             if let Ok(c) = counts.lock() {
                 let mut issue_parts = Vec::new();
@@ -213,10 +281,10 @@ pub async fn repair_with_par2(
                 }
 
                 if !issue_parts.is_empty() {
-                    println!(
+                    progress.on_status(&format!(
                         "  \x1b[33m⚠ {} files with issues\x1b[0m",
                         issue_parts.join(", ")
-                    );
+                    ));
                 }
             }
 
@@ -227,9 +295,149 @@ pub async fn repair_with_par2(
                 &error_msg
             };
 
-            println!("  └─ \x1b[31m✗ PAR2 failed: {}\x1b[0m", short_error);
+            progress.on_status(&format!(
+                "  └─ \x1b[31m✗ PAR2 failed: {}\x1b[0m",
+                short_error
+            ));
+
+            Ok(Par2Status::Failed {
+                reason: short_error.to_string(),
+            })
+        }
+    }
+}
+
+/// Create a recovery set for the files under `dir`
+///
+/// Every file is covered, those in subfolders included, except hidden ones
+/// and PAR2 files already there. The set is named after the folder, with a
+/// number added when a set of that name is already in it.
+pub async fn create_par2(
+    dir: &Path,
+    options: Par2CreateOptions,
+    progress: &Arc<dyn ProgressSink>,
+) -> Result<CreatedPar2> {
+    let backend = creator().ok_or_else(|| PostProcessingError::ToolNotFound {
+        tool: "par2".to_string(),
+    })?;
+    progress.on_phase_progress(PhaseStep::Scanning, 0, 0, "Listing files...");
+
+    let mut files = Vec::new();
+    source_files(dir, Path::new(""), &mut files)?;
+    files.sort();
+    if files.is_empty() {
+        progress.on_phase_done(PhaseEnd::Skipped);
+        return Err(PostProcessingError::Par2Failed {
+            reason: format!("no files to protect in {}", dir.display()),
+        }
+        .into());
+    }
+
+    let before = par2_names(dir)?;
+    let named = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+    let stem = named
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("recovery");
+    let index = free_set_name(stem, &before);
+
+    if let Err(reason) = backend.create(dir, Path::new(&index), &files, &options, progress) {
+        progress.on_phase_done(PhaseEnd::Failed);
+        return Err(PostProcessingError::Par2Failed { reason }.into());
+    }
+    progress.on_phase_done(PhaseEnd::Finished);
+
+    let mut volumes: Vec<PathBuf> = par2_names(dir)?
+        .difference(&before)
+        .filter(|name| **name != index)
+        .map(|name| dir.join(name))
+        .collect();
+    volumes.sort();
+    Ok(CreatedPar2 {
+        index: dir.join(index),
+        volumes,
+        files: files.len(),
+        redundancy: options.redundancy,
+    })
+}
+
+/// Collect the files under `dir/relative` a new recovery set covers
+fn source_files(dir: &Path, relative: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir.join(relative))? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let path = relative.join(entry.file_name());
+        let kind = entry.file_type()?;
+        if kind.is_dir() {
+            source_files(dir, &path, files)?;
+        } else if kind.is_file() && !par2_patterns::is_par2_file(&path) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Names of the PAR2 files directly in `dir`
+fn par2_names(dir: &Path) -> std::io::Result<HashSet<String>> {
+    Ok(std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| par2_patterns::is_par2_file(Path::new(name)))
+        .collect())
+}
 
-            Ok(Par2Status::Failed)
+/// Index file name for a new set called `stem` that no PAR2 file in
+/// `existing` belongs to
+fn free_set_name(stem: &str, existing: &HashSet<String>) -> String {
+    let taken: HashSet<String> = existing
+        .iter()
+        .filter_map(|name| par2_patterns::extract_base_name(name))
+        .map(|base| base.to_lowercase())
+        .collect();
+    (1..)
+        .map(|n| match n {
+            1 => stem.to_string(),
+            n => format!("{}.{}", stem, n),
+        })
+        .find(|name| !taken.contains(&name.to_lowercase()))
+        .map(|name| format!("{}.par2", name))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_set_skips_par2_files_and_taken_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let extracted = dir.path().join("Show");
+        std::fs::create_dir(&extracted).unwrap();
+        for name in [
+            "show.mkv",
+            "Show.par2",
+            "show.vol00+01.PAR2",
+            ".dlnzb-segments",
+        ] {
+            std::fs::write(dir.path().join(name), b"x").unwrap();
         }
+        std::fs::write(extracted.join("show.nfo"), b"x").unwrap();
+
+        let mut files = Vec::new();
+        source_files(dir.path(), Path::new(""), &mut files).unwrap();
+        files.sort();
+        assert_eq!(
+            files,
+            [
+                PathBuf::from("Show").join("show.nfo"),
+                PathBuf::from("show.mkv")
+            ]
+        );
+
+        let existing = par2_names(dir.path()).unwrap();
+        assert_eq!(free_set_name("show", &existing), "show.2.par2");
+        assert_eq!(free_set_name("Other", &existing), "Other.par2");
     }
 }
@@ -0,0 +1,65 @@
+//! The built-in PAR2 engine (par2-rs), behind the `builtin-par2` feature
+
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use super::par2::{Par2Backend, Par2Counts};
+use crate::progress::{PhaseStep, ProgressSink};
+use par2_rs::{MessageCallback, MessageLevel, Par2Operation, Par2Repairer, ProgressCallback};
+
+/// Verifies and repairs in-process with par2-rs
+pub(super) struct BuiltinPar2;
+
+impl Par2Backend for BuiltinPar2 {
+    fn name(&self) -> &str {
+        "built-in"
+    }
+
+    fn repair(
+        &self,
+        main_par2: &Path,
+        progress: &Arc<dyn ProgressSink>,
+        counts: &Arc<Mutex<Par2Counts>>,
+    ) -> Result<(), String> {
+        let repairer = Par2Repairer::new(main_par2).map_err(|e| e.to_string())?;
+
+        // Progress callback reports the operation and the live counts
+        let sink = progress.clone();
+        let counts_for_progress = counts.clone();
+        let progress_callback: ProgressCallback = Arc::new(move |operation, current, total| {
+            let report =
+                |step, message: &str| sink.on_phase_progress(step, current, total, message);
+            match operation {
+                Par2Operation::Scanning => report(PhaseStep::Scanning, "Scanning files..."),
+                Par2Operation::Loading => report(PhaseStep::Scanning, "Loading PAR2 data..."),
+                Par2Operation::Verifying => match counts_for_progress.lock() {
+                    Ok(c) => {
+                        let (step, message) = c.verifying();
+                        report(step, &message);
+                    }
+                    Err(_) => report(PhaseStep::Verifying, "Verifying..."),
+                },
+                Par2Operation::Repairing => report(PhaseStep::Repairing, "Repairing..."),
+            }
+        });
+
+        // Message callback updates the counts
+        // Note: Message patterns are coupled to par2-rs message format
+        let counts_for_messages = counts.clone();
+        let message_callback: MessageCallback = Arc::new(move |level, message| {
+            if let Ok(mut c) = counts_for_messages.lock() {
+                match level {
+                    MessageLevel::Warning if message.contains("damaged") => c.damaged += 1,
+                    MessageLevel::Error if message.contains("Missing") => c.missing += 1,
+                    MessageLevel::Info if message.contains("obfuscated") => c.obfuscated += 1,
+                    MessageLevel::Info if message.contains("Repairing") => c.repaired += 1,
+                    _ => {}
+                }
+            }
+        });
+
+        repairer
+            .repair_with_callbacks(true, false, Some(progress_callback), Some(message_callback))
+            .map_err(|e| e.to_string())
+    }
+}
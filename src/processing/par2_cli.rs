@@ -0,0 +1,311 @@
+//! PAR2 through an external `par2` (par2cmdline) binary
+//!
+//! Used when the crate is built without the built-in engine, and always for
+//! creating recovery sets, which the built-in engine can't do. Progress and
+//! the damaged/missing counts are read from par2's output, which redraws its
+//! percentages with carriage returns.
+
+use std::ffi::OsStr;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+
+use super::par2::{Par2Backend, Par2Counts, Par2CreateOptions};
+use crate::progress::{PhaseStep, ProgressSink};
+
+/// Names the binary goes by
+#[cfg(windows)]
+const PROGRAMS: &[&str] = &["par2.exe"];
+#[cfg(not(windows))]
+const PROGRAMS: &[&str] = &["par2"];
+
+/// How to get a `par2` binary, for the message when there's no PAR2 support
+#[cfg(windows)]
+pub(super) const INSTALL_HINT: &str =
+    "put par2.exe from https://github.com/animetosho/par2cmdline-turbo/releases on PATH";
+#[cfg(not(windows))]
+pub(super) const INSTALL_HINT: &str = "install par2";
+
+/// Verifies, repairs and creates with a `par2` binary
+pub(super) struct CliPar2 {
+    program: PathBuf,
+}
+
+impl CliPar2 {
+    /// The `par2` binary on `PATH`, if there is one
+    pub fn find() -> Option<Self> {
+        let path = std::env::var_os("PATH")?;
+        std::env::split_paths(&path)
+            .flat_map(|dir| PROGRAMS.iter().map(move |name| dir.join(name)))
+            .find(|candidate| candidate.is_file())
+            .map(|program| Self { program })
+    }
+}
+
+impl Par2Backend for CliPar2 {
+    fn name(&self) -> &str {
+        "par2"
+    }
+
+    fn repair(
+        &self,
+        main_par2: &Path,
+        progress: &Arc<dyn ProgressSink>,
+        counts: &Arc<Mutex<Par2Counts>>,
+    ) -> Result<(), String> {
+        let mut reason = None;
+        let mut done = 0;
+        let dir = main_par2.parent().unwrap_or(Path::new("."));
+        let args = [OsStr::new("r"), main_par2.as_os_str()];
+        self.run(&args, dir, |line| {
+            if let Some(failure) = failure_reason(line) {
+                reason = Some(failure);
+            }
+            let report = counts
+                .lock()
+                .ok()
+                .and_then(|mut c| parse_line(line, &mut c));
+            if let Some((step, permille, message)) = report {
+                done = permille.unwrap_or(done);
+                progress.on_phase_progress(step, done, 1000, &message);
+            }
+        })
+        .map_err(|error| reason.take().unwrap_or(error))
+    }
+
+    fn create(
+        &self,
+        dir: &Path,
+        index: &Path,
+        files: &[PathBuf],
+        options: &Par2CreateOptions,
+        progress: &Arc<dyn ProgressSink>,
+    ) -> Result<(), String> {
+        let redundancy = format!("-r{}", options.redundancy);
+        let block_size = format!("-s{}", options.block_size);
+        let mut args = vec![OsStr::new("c"), OsStr::new(&redundancy)];
+        if options.block_size > 0 {
+            args.push(OsStr::new(&block_size));
+        }
+        args.push(index.as_os_str());
+        args.push(OsStr::new("--"));
+        args.extend(files.iter().map(|file| file.as_os_str()));
+
+        let mut done = 0;
+        self.run(&args, dir, |line| {
+            if let Some((permille, message)) = parse_create_line(line) {
+                done = permille.unwrap_or(done);
+                progress.on_phase_progress(PhaseStep::Creating, done, 1000, message);
+            }
+        })
+    }
+}
+
+impl CliPar2 {
+    /// Run par2 with `args` in `dir`, handing each line of its output to
+    /// `on_line`; an error is the last thing it said on stderr
+    fn run(
+        &self,
+        args: &[&OsStr],
+        dir: &Path,
+        mut on_line: impl FnMut(&str),
+    ) -> Result<(), String> {
+        let mut child = Command::new(&self.program)
+            .args(args)
+            .current_dir(dir)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Failed to run {}: {}", self.program.display(), e))?;
+
+        // Drained on its own thread so a chatty stderr can't stall stdout
+        let stderr = child.stderr.take().map(|mut stderr| {
+            std::thread::spawn(move || {
+                let mut text = String::new();
+                let _ = stderr.read_to_string(&mut text);
+                text
+            })
+        });
+
+        if let Some(mut stdout) = child.stdout.take() {
+            let mut pending = Vec::new();
+            let mut buf = [0u8; 4096];
+            while let Ok(n @ 1..) = stdout.read(&mut buf) {
+                pending.extend_from_slice(&buf[..n]);
+                while let Some(end) = pending.iter().position(|&b| b == b'\r' || b == b'\n') {
+                    let line: Vec<u8> = pending.drain(..=end).collect();
+                    on_line(String::from_utf8_lossy(&line).trim());
+                }
+            }
+        }
+
+        let status = child.wait().map_err(|e| e.to_string())?;
+        let stderr = stderr
+            .and_then(|handle| handle.join().ok())
+            .unwrap_or_default();
+        if status.success() {
+            return Ok(());
+        }
+        Err(stderr
+            .lines()
+            .rev()
+            .find(|line| !line.trim().is_empty())
+            .map(|line| line.trim().to_string())
+            .unwrap_or_else(|| format!("par2 exited with {}", status)))
+    }
+}
+
+/// Progress to report for a line of par2 output, updating `counts`
+///
+/// Returns the step, how far along it is in thousandths when the line says,
+/// and a message.
+fn parse_line(line: &str, counts: &mut Par2Counts) -> Option<(PhaseStep, Option<u64>, String)> {
+    if let Some(target) = line.strip_prefix("Target: ") {
+        if target.contains(" - damaged.") {
+            counts.damaged += 1;
+        } else if target.contains(" - missing.") {
+            counts.missing += 1;
+        }
+        let (step, message) = counts.verifying();
+        return Some((step, None, message));
+    }
+    if line.starts_with("File: ") && line.contains(" - is a match for ") {
+        counts.obfuscated += 1;
+        let (step, message) = counts.verifying();
+        return Some((step, None, message));
+    }
+    if line == "Repair complete." {
+        counts.repaired = (counts.damaged + counts.missing).max(1);
+        return None;
+    }
+
+    let permille = Some(permille(line)?);
+    if line.starts_with("Loading") {
+        Some((
+            PhaseStep::Scanning,
+            permille,
+            "Loading PAR2 data...".to_string(),
+        ))
+    } else if line.starts_with("Scanning") || line.starts_with("Opening") {
+        let (step, message) = counts.verifying();
+        Some((step, permille, message))
+    } else if line.starts_with("Repairing") {
+        Some((PhaseStep::Repairing, permille, "Repairing...".to_string()))
+    } else {
+        None
+    }
+}
+
+/// Progress to report for a line of `par2 c` output: thousandths done when
+/// the line says, and a message
+fn parse_create_line(line: &str) -> Option<(Option<u64>, &'static str)> {
+    if line.starts_with("Opening: ") {
+        Some((None, "Reading files..."))
+    } else if line.starts_with("Computing Reed Solomon matrix") {
+        Some((None, "Computing recovery data..."))
+    } else if line.starts_with("Processing") || line.starts_with("Constructing") {
+        Some((Some(permille(line)?), "Computing recovery data..."))
+    } else if line.starts_with("Writing") {
+        Some((None, "Writing recovery files..."))
+    } else {
+        None
+    }
+}
+
+/// Thousandths done, from a line ending in a percentage ("Loading: 42.5%")
+fn permille(line: &str) -> Option<u64> {
+    let percent = line
+        .rsplit_once(": ")
+        .and_then(|(_, value)| value.strip_suffix('%'))
+        .and_then(|value| value.trim().parse::<f64>().ok())?;
+    Some((percent * 10.0).clamp(0.0, 1000.0) as u64)
+}
+
+/// Why repair isn't possible, from a line of par2 output
+fn failure_reason(line: &str) -> Option<String> {
+    let blocks = line
+        .strip_prefix("You need ")?
+        .split_whitespace()
+        .next()?
+        .parse::<u64>()
+        .ok()?;
+    Some(format!("Need {} more recovery blocks", blocks))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_par2_output() {
+        let mut counts = Par2Counts::default();
+        let mut parse = |line| parse_line(line, &mut counts);
+
+        assert_eq!(
+            parse("Loading: 42.5%"),
+            Some((
+                PhaseStep::Scanning,
+                Some(425),
+                "Loading PAR2 data...".to_string()
+            ))
+        );
+        assert_eq!(
+            parse("Target: \"a.bin\" - found.").map(|r| r.0),
+            Some(PhaseStep::Verifying)
+        );
+        assert_eq!(
+            parse("Target: \"b.bin\" - damaged. Found 95 of 100 data blocks."),
+            Some((
+                PhaseStep::VerifyingDamaged,
+                None,
+                "Verifying... (1 damaged)".to_string()
+            ))
+        );
+        parse("Target: \"c.bin\" - missing.");
+        parse("File: \"x7f3a\" - is a match for \"c.bin\".");
+        assert_eq!(
+            parse("Repairing: 100.0%").map(|r| (r.0, r.1)),
+            Some((PhaseStep::Repairing, Some(1000)))
+        );
+        assert_eq!(parse("Repair is required."), None);
+        assert_eq!(parse("Repair complete."), None);
+
+        assert_eq!(
+            (
+                counts.damaged,
+                counts.missing,
+                counts.obfuscated,
+                counts.repaired
+            ),
+            (1, 1, 1, 2)
+        );
+    }
+
+    #[test]
+    fn test_parse_par2_create_output() {
+        assert_eq!(
+            parse_create_line("Opening: \"show.mkv\""),
+            Some((None, "Reading files..."))
+        );
+        assert_eq!(
+            parse_create_line("Processing: 37.5%"),
+            Some((Some(375), "Computing recovery data..."))
+        );
+        assert_eq!(
+            parse_create_line("Writing recovery packets"),
+            Some((None, "Writing recovery files..."))
+        );
+        assert_eq!(parse_create_line("Block size: 768000"), None);
+    }
+
+    #[test]
+    fn test_failure_reason() {
+        assert_eq!(
+            failure_reason("You need 3 more recovery blocks to be able to repair."),
+            Some("Need 3 more recovery blocks".to_string())
+        );
+        assert_eq!(failure_reason("Repair is not possible."), None);
+    }
+}
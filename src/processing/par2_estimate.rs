@@ -0,0 +1,299 @@
+//! Whether PAR2 can make up for what failed to download
+//!
+//! A recovery set repairs as many damaged blocks as it has recovery blocks.
+//! Once the files are down, the failed segments' spans (from each file's
+//! segment map) are laid over the set's blocks, with the block size from the
+//! [`Par2Index`] when the index is on disk; when the damage already needs
+//! more blocks than the volumes that arrived carry, repair is bound to fail, and
+//! [`PostProcessor`](super::PostProcessor) skips it rather than leave the
+//! user waiting through a long verify first.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+use super::par2_packets::Par2Index;
+use crate::download::{DownloadResult, Nzb, NzbFile, Par2Set};
+
+/// A PAR2 set with more damage than recovery blocks
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Par2Shortfall {
+    /// Name of the set
+    pub set: String,
+    /// Segments of the set's data files that failed
+    pub segments_failed: usize,
+    /// Blocks those segments fall in, all of which need repair
+    pub damaged_blocks: u64,
+    /// Recovery blocks in the volumes that downloaded
+    pub recovery_blocks: u64,
+}
+
+impl Par2Shortfall {
+    /// `show: 14 segments failed (~12 blocks) but PAR2 can only repair ~9 blocks`
+    pub fn describe(&self) -> String {
+        format!(
+            "{}: {} segment{} failed (~{} block{}) but PAR2 can only repair ~{} block{}",
+            self.set,
+            self.segments_failed,
+            if self.segments_failed == 1 { "" } else { "s" },
+            self.damaged_blocks,
+            if self.damaged_blocks == 1 { "" } else { "s" },
+            self.recovery_blocks,
+            if self.recovery_blocks == 1 { "" } else { "s" }
+        )
+    }
+}
+
+/// The PAR2 sets of `nzb` that can't repair what failed in `results`
+///
+/// The block size comes from `index` when the NZB has a single set whose
+/// index downloaded, else from the volume sizes; a volume that arrived
+/// counts the recovery blocks `index` found in it.
+pub fn par2_shortfalls(
+    nzb: &Nzb,
+    results: &[DownloadResult],
+    index: &Par2Index,
+) -> Vec<Par2Shortfall> {
+    let sets = nzb.par2_sets();
+    let indexed = match sets.as_slice() {
+        [_] if index.has_slices() => Some(index.slice_size),
+        _ => None,
+    };
+    sets.iter()
+        .filter_map(|set| {
+            let block_size = indexed.or_else(|| set.block_size())?;
+            shortfall(set, results, index, block_size)
+        })
+        .collect()
+}
+
+/// How `set` stands against what failed, if it can't repair it
+fn shortfall(
+    set: &Par2Set,
+    results: &[DownloadResult],
+    index: &Par2Index,
+    block_size: u64,
+) -> Option<Par2Shortfall> {
+    let mut segments_failed = 0;
+    let mut damaged_blocks = 0;
+    for file in &set.data {
+        match result_for(file, results) {
+            Some(result) => {
+                segments_failed += result.segments_failed;
+                damaged_blocks += damaged_blocks_of(result, block_size);
+            }
+            // Never downloaded: every block is missing
+            None => {
+                segments_failed += file.segments.segment.len();
+                damaged_blocks += file.size().div_ceil(block_size);
+            }
+        }
+    }
+    let recovery_blocks = set
+        .volumes
+        .iter()
+        .map(|volume| {
+            let blocks = volume.par2_blocks().unwrap_or(0) as u64;
+            let Some(result) = result_for(volume, results) else {
+                return 0;
+            };
+            let found = result
+                .path
+                .file_name()
+                .and_then(|name| index.recovery_blocks.get(name.to_str()?));
+            match found {
+                Some(&found) => blocks.min(found as u64),
+                None => usable_blocks(blocks, result),
+            }
+        })
+        .sum();
+
+    (damaged_blocks > recovery_blocks).then(|| Par2Shortfall {
+        set: set.name.clone(),
+        segments_failed,
+        damaged_blocks,
+        recovery_blocks,
+    })
+}
+
+/// The download of `file`, by name or by its articles
+fn result_for<'a>(file: &NzbFile, results: &'a [DownloadResult]) -> Option<&'a DownloadResult> {
+    let name = file.filename();
+    results.iter().find(|result| {
+        result.filename == name
+            || result.segment_map.first().is_some_and(|span| {
+                file.segments
+                    .segment
+                    .iter()
+                    .any(|segment| segment.message_id == span.message_id)
+            })
+    })
+}
+
+/// Blocks of `block_size` that a file's failed segments fall in
+fn damaged_blocks_of(result: &DownloadResult, block_size: u64) -> u64 {
+    let mut blocks = BTreeSet::new();
+    for span in &result.segment_map {
+        if !result.failed_message_ids.contains(&span.message_id) {
+            continue;
+        }
+        // A missing final segment of unknown size touches one block at least
+        let end = span.offset + span.len.max(1);
+        blocks.extend(span.offset / block_size..=(end - 1) / block_size);
+    }
+    blocks.len() as u64
+}
+
+/// Recovery blocks a volume still provides when it wasn't read: all of them
+/// when it arrived whole, else about the share of its segments that did
+fn usable_blocks(blocks: u64, result: &DownloadResult) -> u64 {
+    if result.segments_failed == 0 {
+        return blocks;
+    }
+    let total = (result.segments_downloaded + result.segments_failed) as u64;
+    blocks * result.segments_downloaded as u64 / total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::download::SegmentSpan;
+    use std::path::PathBuf;
+
+    const SEGMENT: u64 = 1000;
+    const BLOCK: u64 = 2550;
+
+    /// An NZB of `show.mkv` in ten segments with volumes of `volumes` blocks
+    fn nzb(volumes: &[u32]) -> Nzb {
+        let segments: String = (1..=10)
+            .map(|n| {
+                format!(
+                    r#"<segment bytes="{}" number="{}">m{}@x</segment>"#,
+                    SEGMENT, n, n
+                )
+            })
+            .collect();
+        let mut files = format!(
+            r#"<file poster="p" date="0" subject="&quot;show.mkv&quot; yEnc (1/10)">
+            <groups><group>alt.binaries.test</group></groups>
+            <segments>{}</segments></file>"#,
+            segments
+        );
+        let mut first = 0;
+        for (i, blocks) in volumes.iter().enumerate() {
+            files += &format!(
+                r#"<file poster="p" date="0" subject="&quot;show.vol{:02}+{:02}.par2&quot; yEnc (1/1)">
+                <groups><group>alt.binaries.test</group></groups>
+                <segments><segment bytes="{}" number="1">v{}@x</segment></segments></file>"#,
+                first,
+                blocks,
+                *blocks as u64 * BLOCK,
+                i
+            );
+            first += blocks;
+        }
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+            <nzb xmlns="http://www.newzbin.com/DTD/2003/nzb">{}</nzb>"#,
+            files
+        )
+        .parse()
+        .unwrap()
+    }
+
+    fn result(name: &str, ids: &[&str], failed: &[&str]) -> DownloadResult {
+        DownloadResult {
+            filename: name.to_string(),
+            path: PathBuf::from(name),
+            size: ids.len() as u64 * SEGMENT,
+            segments_downloaded: ids.len() - failed.len(),
+            segments_failed: failed.len(),
+            segments_missing: failed.len(),
+            failed_message_ids: failed.iter().map(|id| id.to_string()).collect(),
+            group: "alt.binaries.test".to_string(),
+            segment_map: ids
+                .iter()
+                .enumerate()
+                .map(|(i, id)| SegmentSpan {
+                    number: i as u32 + 1,
+                    message_id: id.to_string(),
+                    offset: i as u64 * SEGMENT,
+                    len: SEGMENT,
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn show(failed: &[&str]) -> DownloadResult {
+        let ids: Vec<String> = (1..=10).map(|n| format!("m{}@x", n)).collect();
+        let ids: Vec<&str> = ids.iter().map(String::as_str).collect();
+        result("show.mkv", &ids, failed)
+    }
+
+    #[test]
+    fn test_damage_within_recovery_is_fine() {
+        let nzb = nzb(&[1, 2]);
+        // Segments 2 and 3 (bytes 1000..3000) fall in blocks 0 and 1 of three
+        let results = [
+            show(&["m2@x", "m3@x"]),
+            result("show.vol00+01.par2", &["v0@x"], &[]),
+            result("show.vol01+02.par2", &["v1@x"], &[]),
+        ];
+        assert!(par2_shortfalls(&nzb, &results, &Par2Index::default()).is_empty());
+
+        // Unless the second volume, read back, turns out to be broken before
+        // its first block
+        let index = Par2Index {
+            recovery_blocks: [("show.vol01+02.par2".to_string(), 0)].into(),
+            ..Par2Index::default()
+        };
+        let shortfall = &par2_shortfalls(&nzb, &results, &index)[0];
+        assert_eq!(
+            (shortfall.damaged_blocks, shortfall.recovery_blocks),
+            (2, 1)
+        );
+    }
+
+    #[test]
+    fn test_shortfall_counts_damaged_blocks_and_usable_volumes() {
+        let nzb = nzb(&[1, 2]);
+        // Segments 1, 4 and 9 land in blocks 0, 1 and 3; the second volume
+        // didn't arrive, leaving one recovery block
+        let results = [
+            show(&["m1@x", "m4@x", "m9@x"]),
+            result("show.vol00+01.par2", &["v0@x"], &[]),
+        ];
+        assert_eq!(
+            par2_shortfalls(&nzb, &results, &Par2Index::default()),
+            [Par2Shortfall {
+                set: "show".to_string(),
+                segments_failed: 3,
+                damaged_blocks: 3,
+                recovery_blocks: 1,
+            }]
+        );
+
+        // A data file that never arrived needs all its blocks
+        let results = [
+            result("show.vol00+01.par2", &["v0@x"], &[]),
+            result("show.vol01+02.par2", &["v1@x"], &[]),
+        ];
+        let shortfall = &par2_shortfalls(&nzb, &results, &Par2Index::default())[0];
+        assert_eq!(
+            (shortfall.damaged_blocks, shortfall.recovery_blocks),
+            (4, 3)
+        );
+        assert_eq!(
+            shortfall.describe(),
+            "show: 10 segments failed (~4 blocks) but PAR2 can only repair ~3 blocks"
+        );
+    }
+
+    #[test]
+    fn test_usable_blocks_of_damaged_volume() {
+        let mut volume = result("v.par2", &["a", "b", "c", "d"], &["c"]);
+        assert_eq!(usable_blocks(8, &volume), 6);
+        volume.segments_failed = 0;
+        assert_eq!(usable_blocks(8, &volume), 8);
+    }
+}
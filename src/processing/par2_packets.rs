@@ -0,0 +1,654 @@
+//! Minimal PAR2 packet reader
+//!
+//! Reads the FileDescription, Main and IFSC packets from PAR2 files so files
+//! and individual blocks can be checked against the hashes recorded in the
+//! recovery set, independent of the repair engine, and counts the recovery
+//! blocks in each volume. A folder's PAR2 files are read once into a
+//! [`Par2Index`], which the re-fetch, the shortfall check and post-processing
+//! share.
+
+use md5::{Digest, Md5};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::patterns::par2::PACKET_MAGIC;
+
+const FILE_DESC_TYPE: &[u8; 16] = b"PAR 2.0\0FileDesc";
+const MAIN_TYPE: &[u8; 16] = b"PAR 2.0\0Main\0\0\0\0";
+const IFSC_TYPE: &[u8; 16] = b"PAR 2.0\0IFSC\0\0\0\0";
+const RECOVERY_SLICE_TYPE: &[u8; 16] = b"PAR 2.0\0RecvSlic";
+/// magic + length + packet hash + recovery set id + type
+const HEADER_LEN: u64 = 64;
+/// Largest body of a packet type that is read: an IFSC packet for the most
+/// blocks PAR2 allows (32768) is about 640 KiB, the others far less
+const MAX_READ_BODY: u64 = 1 << 20;
+
+/// A file entry from a PAR2 FileDescription packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileDescription {
+    pub file_id: [u8; 16],
+    pub hash_full: [u8; 16],
+    pub hash_16k: [u8; 16],
+    pub length: u64,
+    pub name: String,
+}
+
+/// Checksums of one block (slice) from an IFSC packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockChecksum {
+    pub md5: [u8; 16],
+    pub crc32: u32,
+}
+
+/// What the PAR2 files in a folder say about their recovery set
+///
+/// Read once with [`Par2Index::read`] and passed along, so the stages that
+/// need the packets don't each parse the files again. Several sets in one
+/// folder are merged.
+#[derive(Debug, Clone, Default)]
+pub struct Par2Index {
+    /// Block size from the Main packet; 0 when no Main packet was read
+    pub slice_size: u64,
+    /// File descriptions by file name
+    pub files: HashMap<String, FileDescription>,
+    /// Block checksums by file id
+    pub checksums: HashMap<[u8; 16], Vec<BlockChecksum>>,
+    /// Recovery blocks found in each PAR2 file, by file name (0 for the index
+    /// and for volumes broken before their first block)
+    pub recovery_blocks: BTreeMap<String, u32>,
+}
+
+impl Par2Index {
+    /// Read every PAR2 file in `dir`; empty when there are none
+    ///
+    /// Unknown packet types are passed over, and a file is read up to its
+    /// first damaged or truncated packet.
+    pub fn read(dir: &Path) -> Self {
+        let mut index = Self::default();
+
+        for path in par2_files_in(dir) {
+            let mut recovery_blocks = 0;
+            let result = for_each_packet(
+                &path,
+                &[FILE_DESC_TYPE, MAIN_TYPE, IFSC_TYPE],
+                |ty, body| {
+                    let Some(body) = body else {
+                        if ty == RECOVERY_SLICE_TYPE {
+                            recovery_blocks += 1;
+                        }
+                        return;
+                    };
+                    if ty == FILE_DESC_TYPE {
+                        if let Some(desc) = parse_file_description(body) {
+                            index.files.entry(desc.name.clone()).or_insert(desc);
+                        }
+                    } else if ty == MAIN_TYPE && body.len() >= 8 {
+                        index.slice_size =
+                            u64::from_le_bytes(body[0..8].try_into().expect("8 bytes"));
+                    } else if ty == IFSC_TYPE && body.len() >= 16 {
+                        let file_id: [u8; 16] = body[0..16].try_into().expect("16 bytes");
+                        let blocks = body[16..]
+                            .chunks_exact(20)
+                            .map(|entry| BlockChecksum {
+                                md5: entry[0..16].try_into().expect("16 bytes"),
+                                crc32: u32::from_le_bytes(
+                                    entry[16..20].try_into().expect("4 bytes"),
+                                ),
+                            })
+                            .collect();
+                        index.checksums.entry(file_id).or_insert(blocks);
+                    }
+                },
+            );
+            if let Err(e) = result {
+                tracing::debug!("Failed to read {}: {}", path.display(), e);
+            }
+            if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
+                index
+                    .recovery_blocks
+                    .insert(name.to_string(), recovery_blocks);
+            }
+        }
+
+        index
+    }
+
+    /// Whether no PAR2 file was found
+    pub fn is_empty(&self) -> bool {
+        self.recovery_blocks.is_empty()
+    }
+
+    /// Whether the Main packet was read, so blocks can be checked
+    pub fn has_slices(&self) -> bool {
+        self.slice_size > 0
+    }
+
+    /// Block-verify every file of the set found in `dir`
+    ///
+    /// A file not there under its PAR2 name is looked for among the files the
+    /// set doesn't name, by its first 16 KiB, so obfuscated names are checked
+    /// too. Returns each damaged file with its damaged byte ranges.
+    pub fn damaged_files(&self, dir: &Path) -> Vec<(PathBuf, Vec<Range<u64>>)> {
+        if !self.has_slices() {
+            return Vec::new();
+        }
+
+        let mut unnamed: Vec<PathBuf> = std::fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .map(|entry| entry.path())
+                    .filter(|path| {
+                        path.is_file()
+                            && path
+                                .file_name()
+                                .and_then(|n| n.to_str())
+                                .is_some_and(|name| {
+                                    !self.files.contains_key(name)
+                                        && !name.to_ascii_lowercase().ends_with(".par2")
+                                })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let mut damaged = Vec::new();
+        for desc in self.files.values() {
+            let named = dir.join(&desc.name);
+            let path = if named.is_file() {
+                named
+            } else {
+                match unnamed
+                    .iter()
+                    .position(|path| file_starts_like(desc, path).unwrap_or(false))
+                {
+                    Some(i) => unnamed.swap_remove(i),
+                    None => continue,
+                }
+            };
+            match damaged_ranges(self, desc, &path) {
+                Ok(ranges) if !ranges.is_empty() => damaged.push((path, ranges)),
+                Ok(_) => {}
+                Err(e) => tracing::debug!("Failed to verify {}: {}", path.display(), e),
+            }
+        }
+        damaged
+    }
+}
+
+/// Call `f` with the type of every packet, and the body of every valid
+/// packet of the wanted types (`None` for the others, which aren't read)
+///
+/// Packets with a bad hash are skipped; reading stops at the first byte that
+/// isn't a packet header, and at a packet cut short by the end of the file.
+fn for_each_packet(
+    path: &Path,
+    wanted: &[&[u8; 16]],
+    mut f: impl FnMut(&[u8], Option<&[u8]>),
+) -> std::io::Result<()> {
+    let file = File::open(path)?;
+    let file_len = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+    let mut header = [0u8; HEADER_LEN as usize];
+    let mut position = 0u64;
+
+    loop {
+        match reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+
+        if &header[0..8] != PACKET_MAGIC {
+            break;
+        }
+
+        let length = u64::from_le_bytes(header[8..16].try_into().expect("8 bytes"));
+        if length < HEADER_LEN || length % 4 != 0 {
+            break;
+        }
+        if position.saturating_add(length) > file_len {
+            tracing::debug!("Truncated PAR2 packet at the end of {}", path.display());
+            break;
+        }
+        position += length;
+        let body_len = length - HEADER_LEN;
+        let packet_type = &header[48..64];
+
+        if !wanted.iter().any(|t| t.as_slice() == packet_type) {
+            reader.seek(SeekFrom::Current(body_len as i64))?;
+            f(packet_type, None);
+            continue;
+        }
+        // No valid packet of these types is this large; a forged length
+        // would have the whole volume read into memory
+        if body_len > MAX_READ_BODY {
+            tracing::debug!("Skipping oversized PAR2 packet in {}", path.display());
+            reader.seek(SeekFrom::Current(body_len as i64))?;
+            continue;
+        }
+
+        let mut body = vec![0u8; body_len as usize];
+        reader.read_exact(&mut body)?;
+
+        // Packet hash covers everything from the recovery set id onwards
+        let mut hasher = Md5::new();
+        hasher.update(&header[32..64]);
+        hasher.update(&body);
+        if hasher.finalize().as_slice() != &header[16..32] {
+            tracing::debug!("Skipping corrupt PAR2 packet in {}", path.display());
+            continue;
+        }
+
+        f(packet_type, Some(&body));
+    }
+
+    Ok(())
+}
+
+/// Read all FileDescription packets from a PAR2 file
+pub fn read_file_descriptions(path: &Path) -> std::io::Result<Vec<FileDescription>> {
+    let mut descriptions = Vec::new();
+    for_each_packet(path, &[FILE_DESC_TYPE], |_, body| {
+        if let Some(desc) = body.and_then(parse_file_description) {
+            descriptions.push(desc);
+        }
+    })?;
+    Ok(descriptions)
+}
+
+/// Find the byte ranges of a file whose blocks don't match the recovery set
+///
+/// Blocks beyond the end of a short file count as damaged. Adjacent damaged
+/// blocks are merged into a single range.
+pub fn damaged_ranges(
+    set: &Par2Index,
+    desc: &FileDescription,
+    path: &Path,
+) -> std::io::Result<Vec<Range<u64>>> {
+    let checksums = match set.checksums.get(&desc.file_id) {
+        Some(checksums) => checksums,
+        None => return Ok(Vec::new()),
+    };
+
+    let slice = set.slice_size as usize;
+    let mut reader = BufReader::with_capacity(1024 * 1024, File::open(path)?);
+    let mut block = vec![0u8; slice];
+    let mut ranges: Vec<Range<u64>> = Vec::new();
+
+    for (index, expected) in checksums.iter().enumerate() {
+        let start = index as u64 * set.slice_size;
+        let end = (start + set.slice_size).min(desc.length);
+
+        let read = read_up_to(&mut reader, &mut block)?;
+        // Short final blocks are zero-padded to the slice size before hashing
+        block[read..].fill(0);
+
+        let intact = read as u64 >= end - start
+            && crc32fast::hash(&block) == expected.crc32
+            && Md5::digest(&block).as_slice() == expected.md5;
+
+        if !intact {
+            match ranges.last_mut() {
+                Some(last) if last.end == start => last.end = end,
+                _ => ranges.push(start..end),
+            }
+        }
+    }
+
+    Ok(ranges)
+}
+
+/// Block-verify every file of the recovery set found in `dir` (see
+/// [`Par2Index::damaged_files`])
+pub fn find_damaged_files(dir: &Path) -> Vec<(PathBuf, Vec<Range<u64>>)> {
+    Par2Index::read(dir).damaged_files(dir)
+}
+
+/// Fill `buf` as far as possible, returning the number of bytes read
+fn read_up_to(reader: &mut impl Read, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+fn par2_files_in(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| crate::patterns::par2::is_par2_file(p))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    files.sort();
+    files
+}
+
+/// Collect FileDescriptions from every PAR2 file in a directory, keyed by file name
+pub fn descriptions_in_dir(dir: &Path) -> HashMap<String, FileDescription> {
+    Par2Index::read(dir).files
+}
+
+/// Check a file's length and full MD5 against its FileDescription
+pub fn file_matches(desc: &FileDescription, path: &Path) -> std::io::Result<bool> {
+    if std::fs::metadata(path)?.len() != desc.length {
+        return Ok(false);
+    }
+
+    let mut reader = BufReader::with_capacity(1024 * 1024, File::open(path)?);
+    let mut hasher = Md5::new();
+    let mut buf = vec![0u8; 1024 * 1024];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+
+    Ok(hasher.finalize().as_slice() == desc.hash_full)
+}
+
+/// Whether a file starts the way a FileDescription says, by the MD5 of its
+/// first 16 KiB: how PAR2 finds a file posted under another name
+pub fn file_starts_like(desc: &FileDescription, path: &Path) -> std::io::Result<bool> {
+    let mut buf = vec![0u8; 16 * 1024];
+    let n = read_up_to(&mut File::open(path)?, &mut buf)?;
+    Ok(Md5::digest(&buf[..n]).as_slice() == desc.hash_16k)
+}
+
+fn parse_file_description(body: &[u8]) -> Option<FileDescription> {
+    if body.len() < 56 {
+        return None;
+    }
+
+    let name_bytes = &body[56..];
+    let name_end = name_bytes
+        .iter()
+        .position(|&b| b == 0)
+        .unwrap_or(name_bytes.len());
+
+    Some(FileDescription {
+        file_id: body[0..16].try_into().ok()?,
+        hash_full: body[16..32].try_into().ok()?,
+        hash_16k: body[32..48].try_into().ok()?,
+        length: u64::from_le_bytes(body[48..56].try_into().ok()?),
+        name: String::from_utf8_lossy(&name_bytes[..name_end]).to_string(),
+    })
+}
+
+/// Wrap a packet body with a valid header
+fn packet(packet_type: &[u8; 16], body: &[u8]) -> Vec<u8> {
+    let mut hashed = vec![0u8; 16]; // recovery set id
+    hashed.extend_from_slice(packet_type);
+    hashed.extend_from_slice(body);
+
+    let mut packet = PACKET_MAGIC.to_vec();
+    packet.extend_from_slice(&(HEADER_LEN + body.len() as u64).to_le_bytes());
+    packet.extend_from_slice(&Md5::digest(&hashed));
+    packet.extend_from_slice(&hashed);
+    packet
+}
+
+/// Build Main + FileDesc + IFSC packets for a file (test fixtures, self-test)
+pub(crate) fn recovery_set_packets(name: &str, contents: &[u8], slice_size: u64) -> Vec<u8> {
+    let file_id: [u8; 16] = Md5::digest(contents).into();
+
+    let mut main = slice_size.to_le_bytes().to_vec();
+    main.extend_from_slice(&1u32.to_le_bytes());
+    main.extend_from_slice(&file_id);
+
+    let mut packets = packet(MAIN_TYPE, &main);
+    packets.extend(file_description_packet(name, contents));
+    packets.extend(ifsc_packet(contents, slice_size));
+    packets
+}
+
+/// Build an IFSC packet with the block checksums of the given file contents
+fn ifsc_packet(contents: &[u8], slice_size: u64) -> Vec<u8> {
+    let file_id: [u8; 16] = Md5::digest(contents).into();
+    let mut ifsc = file_id.to_vec();
+    for chunk in contents.chunks(slice_size as usize) {
+        let mut block = chunk.to_vec();
+        block.resize(slice_size as usize, 0);
+        ifsc.extend_from_slice(&Md5::digest(&block));
+        ifsc.extend_from_slice(&crc32fast::hash(&block).to_le_bytes());
+    }
+    packet(IFSC_TYPE, &ifsc)
+}
+
+/// Build a FileDescription packet for the given file contents
+pub(crate) fn file_description_packet(name: &str, contents: &[u8]) -> Vec<u8> {
+    let hash_full: [u8; 16] = Md5::digest(contents).into();
+    let hash_16k: [u8; 16] = Md5::digest(&contents[..contents.len().min(16384)]).into();
+
+    let mut body = Vec::new();
+    body.extend_from_slice(&hash_full); // file id (any 16 bytes)
+    body.extend_from_slice(&hash_full);
+    body.extend_from_slice(&hash_16k);
+    body.extend_from_slice(&(contents.len() as u64).to_le_bytes());
+    body.extend_from_slice(name.as_bytes());
+    while body.len() % 4 != 0 {
+        body.push(0);
+    }
+
+    packet(FILE_DESC_TYPE, &body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_file_descriptions_and_match() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut par2 = file_description_packet("movie.mkv", b"good data");
+        par2.extend(file_description_packet("movie.nfo", b"info"));
+        std::fs::write(dir.path().join("movie.par2"), &par2).unwrap();
+        std::fs::write(dir.path().join("movie.mkv"), b"good data").unwrap();
+        std::fs::write(dir.path().join("movie.nfo"), b"INFO").unwrap();
+
+        let descriptions = read_file_descriptions(&dir.path().join("movie.par2")).unwrap();
+        assert_eq!(descriptions.len(), 2);
+        assert_eq!(descriptions[0].name, "movie.mkv");
+        assert_eq!(descriptions[0].length, 9);
+
+        let map = descriptions_in_dir(dir.path());
+        assert!(file_matches(&map["movie.mkv"], &dir.path().join("movie.mkv")).unwrap());
+        assert!(!file_matches(&map["movie.nfo"], &dir.path().join("movie.nfo")).unwrap());
+    }
+
+    #[test]
+    fn test_oversized_packet_is_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let body_len = MAX_READ_BODY + 4;
+        let mut bytes = packet(FILE_DESC_TYPE, &[]);
+        bytes[8..16].copy_from_slice(&(HEADER_LEN + body_len).to_le_bytes());
+        bytes.resize((HEADER_LEN + body_len) as usize, 0);
+        bytes.extend(file_description_packet("movie.mkv", b"data"));
+        let path = dir.path().join("forged.par2");
+        std::fs::write(&path, &bytes).unwrap();
+
+        let descriptions = read_file_descriptions(&path).unwrap();
+        assert_eq!(descriptions.len(), 1);
+        assert_eq!(descriptions[0].name, "movie.mkv");
+    }
+
+    #[test]
+    fn test_corrupt_packet_is_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut packet = file_description_packet("movie.mkv", b"data");
+        let last = packet.len() - 1;
+        packet[last] ^= 0xff;
+        std::fs::write(dir.path().join("bad.par2"), &packet).unwrap();
+
+        assert!(read_file_descriptions(&dir.path().join("bad.par2"))
+            .unwrap()
+            .is_empty());
+    }
+
+    const CREATOR_TYPE: &[u8; 16] = b"PAR 2.0\0Creator\0";
+
+    /// A recovery set laid out the way par2cmdline writes one: the index
+    /// holds the Main, FileDesc, IFSC and Creator packets, and each volume
+    /// its RecvSlic packets followed by the same
+    fn write_set(dir: &Path, files: &[(&str, &[u8])], slice_size: u64, volumes: &[u32]) {
+        let mut main = slice_size.to_le_bytes().to_vec();
+        main.extend_from_slice(&(files.len() as u32).to_le_bytes());
+        for (_, contents) in files {
+            main.extend_from_slice(&Md5::digest(contents));
+        }
+        let mut critical = packet(MAIN_TYPE, &main);
+        for (name, contents) in files {
+            critical.extend(file_description_packet(name, contents));
+            critical.extend(ifsc_packet(contents, slice_size));
+        }
+        critical.extend(packet(CREATOR_TYPE, b"dl-nzb test\0"));
+        std::fs::write(dir.join("set.par2"), &critical).unwrap();
+
+        let mut exponent = 0u32;
+        for &blocks in volumes {
+            let mut volume = Vec::new();
+            for _ in 0..blocks {
+                let mut body = exponent.to_le_bytes().to_vec();
+                body.resize(4 + slice_size as usize, 0x5a);
+                volume.extend(packet(RECOVERY_SLICE_TYPE, &body));
+                exponent += 1;
+            }
+            volume.extend_from_slice(&critical);
+            let name = format!("set.vol{:02}+{:02}.par2", exponent - blocks, blocks);
+            std::fs::write(dir.join(name), volume).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_index_reads_every_field() {
+        let dir = tempfile::tempdir().unwrap();
+        let movie: Vec<u8> = (0..20_000u32).map(|i| (i % 251) as u8).collect();
+        let nfo = b"release notes".to_vec();
+        write_set(
+            dir.path(),
+            &[("movie.mkv", &movie[..]), ("movie.nfo", &nfo[..])],
+            4096,
+            &[1, 2],
+        );
+
+        let index = Par2Index::read(dir.path());
+        assert!(!index.is_empty());
+        assert_eq!(index.slice_size, 4096);
+
+        let desc = &index.files["movie.mkv"];
+        let movie_id: [u8; 16] = Md5::digest(&movie).into();
+        assert_eq!(desc.name, "movie.mkv");
+        assert_eq!(desc.length, 20_000);
+        assert_eq!(desc.file_id, movie_id);
+        assert_eq!(desc.hash_full, movie_id);
+        assert_eq!(
+            desc.hash_16k,
+            <[u8; 16]>::from(Md5::digest(&movie[..16384]))
+        );
+        assert_eq!(index.files["movie.nfo"].length, nfo.len() as u64);
+
+        // Five blocks, the last zero-padded to the block size
+        let checksums = &index.checksums[&movie_id];
+        assert_eq!(checksums.len(), 5);
+        let mut last = movie[16384..].to_vec();
+        last.resize(4096, 0);
+        assert_eq!(checksums[4].crc32, crc32fast::hash(&last));
+        assert_eq!(checksums[4].md5, <[u8; 16]>::from(Md5::digest(&last)));
+        assert_eq!(checksums[0].crc32, crc32fast::hash(&movie[..4096]));
+
+        assert_eq!(
+            index.recovery_blocks,
+            BTreeMap::from([
+                ("set.par2".to_string(), 0),
+                ("set.vol00+01.par2".to_string(), 1),
+                ("set.vol01+02.par2".to_string(), 2),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_index_skips_unknown_and_truncated_packets() {
+        let dir = tempfile::tempdir().unwrap();
+        let contents = vec![7u8; 3000];
+        write_set(dir.path(), &[("data.bin", &contents[..])], 1024, &[3]);
+
+        // A packet type from a newer client ahead of the rest
+        let volume = dir.path().join("set.vol00+03.par2");
+        let mut bytes = packet(b"PAR 3.0\0Unknown\0", &[1, 2, 3, 4]);
+        let unknown = bytes.len();
+        bytes.extend(std::fs::read(&volume).unwrap());
+        // Cut off in the middle of the third recovery block
+        let slice_packet = HEADER_LEN as usize + 4 + 1024;
+        bytes.truncate(unknown + 2 * slice_packet + 100);
+        std::fs::write(&volume, &bytes).unwrap();
+
+        let index = Par2Index::read(dir.path());
+        assert_eq!(index.recovery_blocks["set.vol00+03.par2"], 2);
+        assert_eq!(index.slice_size, 1024);
+        assert_eq!(index.files["data.bin"].length, 3000);
+
+        assert!(Par2Index::read(tempfile::tempdir().unwrap().path()).is_empty());
+    }
+
+    #[test]
+    fn test_damaged_ranges_finds_bad_blocks() {
+        let dir = tempfile::tempdir().unwrap();
+        let contents: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(
+            dir.path().join("data.par2"),
+            recovery_set_packets("data.bin", &contents, 128),
+        )
+        .unwrap();
+
+        // Corrupt blocks 2 and 3, and truncate the final block
+        let mut damaged = contents.clone();
+        damaged[300] ^= 0xff;
+        damaged[400] ^= 0xff;
+        damaged.truncate(990);
+        let path = dir.path().join("data.bin");
+        std::fs::write(&path, &damaged).unwrap();
+
+        let set = Par2Index::read(dir.path());
+        assert_eq!(set.slice_size, 128);
+        let desc = &set.files["data.bin"];
+        assert_eq!(
+            damaged_ranges(&set, desc, &path).unwrap(),
+            vec![256..512, 896..1000]
+        );
+
+        std::fs::write(&path, &contents).unwrap();
+        assert!(damaged_ranges(&set, desc, &path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_damaged_files_finds_obfuscated_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let contents: Vec<u8> = (0..40000u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(
+            dir.path().join("data.par2"),
+            recovery_set_packets("data.bin", &contents, 4096),
+        )
+        .unwrap();
+
+        // Damage past the first 16 KiB, which is what the file is matched by
+        let mut damaged = contents.clone();
+        damaged[20000] ^= 0xff;
+        let path = dir.path().join("a1b2c3d4");
+        std::fs::write(&path, &damaged).unwrap();
+        std::fs::write(dir.path().join("other.nfo"), b"not part of the set").unwrap();
+
+        let found = Par2Index::read(dir.path()).damaged_files(dir.path());
+        assert_eq!(found, vec![(path, vec![16384..20480])]);
+    }
+}
@@ -0,0 +1,83 @@
+//! Password handling for encrypted RAR archives
+//!
+//! Candidates are tried in order: passwords that already opened a set of the
+//! same download, the NZB `<meta type="password">` entries, then
+//! `post_processing.password_file`. Each is tried by extracting with it, since
+//! a wrong one fails on the first file (at the RAR5 password check, or as a
+//! CRC error for older archives) and a right one shouldn't unpack the set
+//! twice. In interactive sessions the user is prompted when every candidate
+//! fails; otherwise the set is reported as needing a password.
+
+use std::path::Path;
+use unrar::error::Code;
+use unrar::Archive;
+
+/// Check whether an archive is encrypted (file data or headers)
+pub fn needs_password(archive_path: &Path) -> bool {
+    match Archive::new(archive_path).open_for_listing() {
+        Ok(listing) => {
+            for entry in listing {
+                match entry {
+                    Ok(header) if header.is_encrypted() => return true,
+                    Ok(_) => {}
+                    Err(e) => return e.code == Code::MissingPassword,
+                }
+            }
+            false
+        }
+        Err(e) => e.code == Code::MissingPassword,
+    }
+}
+
+/// Whether an error opening or unpacking the first file of an encrypted
+/// archive means the password was wrong
+pub fn is_wrong_password(code: &Code) -> bool {
+    matches!(
+        code,
+        Code::BadPassword | Code::MissingPassword | Code::BadData
+    )
+}
+
+/// Read a newline-separated password list, ignoring blank lines
+pub fn load_password_file(path: &Path) -> std::io::Result<Vec<String>> {
+    Ok(std::fs::read_to_string(path)?
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Ask for a password on the terminal, after saying the last one was wrong
+/// when `retry` is set
+///
+/// An empty answer skips the archive set.
+pub fn prompt_password(archive_path: &Path, retry: bool) -> Option<String> {
+    let name = archive_path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    if retry {
+        crate::progress::style::eprint("  \x1b[31m✗ Wrong password\x1b[0m");
+    }
+    let prompt = format!("  Password for {} (empty to skip): ", name);
+    let password = rpassword::prompt_password(prompt).ok()?;
+    (!password.is_empty()).then_some(password)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_password_file_skips_blank_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("passwords.txt");
+        std::fs::write(&path, "first\n\n second \r\nthird").unwrap();
+
+        assert_eq!(
+            load_password_file(&path).unwrap(),
+            vec!["first", " second ", "third"]
+        );
+    }
+}
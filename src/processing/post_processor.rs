@@ -1,23 +1,124 @@
 //! Post-processing orchestration for downloaded files
 //!
 //! Coordinates PAR2 verification/repair, RAR extraction, and deobfuscation.
+//! PAR2 and extraction go by the [`Manifest`] of files the run produced,
+//! never a listing of the whole folder.
+//! With `io_priority = "exclusive"` the PAR2 and extraction phases hold the
+//! download folder's disk, pausing downloads writing to it (see
+//! [`DiskGate`]).
 
-use indicatif::ProgressBar;
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+use std::panic::AssertUnwindSafe;
 use std::path::{Path, PathBuf};
-use std::time::Duration;
+use std::sync::Arc;
 
-use super::par2::{self, Par2Status};
-use super::rar::{self, RarExtractor};
-use crate::config::PostProcessingConfig;
-use crate::download::DownloadResult;
-use crate::error::DlNzbError;
-use crate::patterns::par2 as par2_patterns;
+use super::duplicates;
+use super::manifest::Manifest;
+use super::media::{self, MediaCheck};
+use super::names;
+use super::par2::{self, CreatedPar2, Par2CreateOptions, Par2Status};
+use super::par2_cli;
+use super::par2_estimate::Par2Shortfall;
+use super::par2_packets::Par2Index;
+use super::rar::{RarExtractor, SpaceShortfall};
+use crate::config::{FsyncPolicy, IoPriority, PostProcessingConfig};
+use crate::download::{
+    sync_dir, DiskGate, DiskHold, DownloadEvent, Events, Permissions, PostProcessPhase,
+};
+use crate::error::{panic_message, DlNzbError, PostProcessingError};
+use crate::phases::{PhaseRecorder, PhaseStatus, PhaseTimer};
+use crate::progress::{NoProgress, PhaseEnd, PhaseStep, ProgressSink};
 
 type Result<T> = std::result::Result<T, DlNzbError>;
 
+/// Structured outcome of post-processing a download
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PostProcessResult {
+    pub par2_verified: bool,
+    pub par2_repaired: bool,
+    pub rar_extracted: bool,
+    pub files_renamed: usize,
+    /// Segments re-downloaded to fix damaged blocks before PAR2 repair
+    #[serde(default)]
+    pub segments_refetched: usize,
+    /// Stale `.N` copies removed after PAR2 repair
+    #[serde(default)]
+    pub duplicates_removed: usize,
+    /// RAR volumes removed after extraction
+    #[serde(default)]
+    pub rar_files_deleted: usize,
+    /// PAR2 and `.rev` volumes removed after extraction
+    #[serde(default)]
+    pub par2_files_deleted: usize,
+    /// Archive sets skipped because the destination lacked free space
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub space_shortfalls: Vec<SpaceShortfall>,
+    /// Encrypted archives skipped because no password worked
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub password_required: Vec<PathBuf>,
+    /// Why PAR2 repair failed, if it did
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub par2_error: Option<String>,
+    /// Archives that could not be opened or extracted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extract_failed: Vec<PathBuf>,
+    /// Container checks for media files (only when `verify_media` is enabled)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub media_checks: Vec<MediaCheck>,
+    /// PAR2 sets with more failed blocks than recovery blocks, whose repair
+    /// was skipped
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub par2_shortfalls: Vec<Par2Shortfall>,
+    /// Files matching `cleanup_patterns` that were deleted
+    #[serde(default)]
+    pub junk_removed: usize,
+    /// Recovery set made for the finished files (`create_par2_after_extract`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub par2_created: Option<CreatedPar2>,
+    /// Why the recovery set couldn't be made, if it couldn't
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub par2_create_error: Option<String>,
+}
+
+impl PostProcessResult {
+    /// Media files that failed container verification
+    pub fn failed_media(&self) -> impl Iterator<Item = &MediaCheck> {
+        self.media_checks.iter().filter(|c| !c.passed)
+    }
+
+    /// Why the files shouldn't be given a new recovery set, if anything
+    /// about them failed
+    fn unverified(&self) -> Option<&'static str> {
+        if self.par2_error.is_some() {
+            Some("PAR2 verification failed")
+        } else if !self.extract_failed.is_empty()
+            || !self.password_required.is_empty()
+            || !self.space_shortfalls.is_empty()
+        {
+            Some("extraction failed")
+        } else if self.failed_media().next().is_some() {
+            Some("media verification failed")
+        } else {
+            None
+        }
+    }
+}
+
 pub struct PostProcessor {
     config: PostProcessingConfig,
     large_file_threshold: u64,
+    passwords: Vec<String>,
+    interactive: bool,
+    temp_dir: Option<PathBuf>,
+    fsync: FsyncPolicy,
+    permissions: Permissions,
+    events: Events,
+    progress: Arc<dyn ProgressSink>,
+    par2_shortfalls: Vec<Par2Shortfall>,
+    par2_index: Option<Par2Index>,
+    mapped_names: Vec<String>,
+    phases: PhaseRecorder,
 }
 
 impl PostProcessor {
@@ -25,113 +126,474 @@ impl PostProcessor {
         Self {
             config,
             large_file_threshold,
+            passwords: Vec::new(),
+            interactive: false,
+            temp_dir: None,
+            fsync: FsyncPolicy::Never,
+            permissions: Permissions::default(),
+            events: Events::default(),
+            progress: Arc::new(NoProgress),
+            par2_shortfalls: Vec::new(),
+            par2_index: None,
+            mapped_names: Vec::new(),
+            phases: PhaseRecorder::new(),
         }
     }
 
-    pub async fn process_downloads(&self, results: &[DownloadResult]) -> Result<()> {
-        if results.is_empty() {
-            return Ok(());
+    /// Archive passwords to try (e.g. from the NZB metadata)
+    pub fn with_passwords(mut self, passwords: Vec<String>) -> Self {
+        self.passwords = passwords;
+        self
+    }
+
+    /// Allow prompting for archive passwords on the terminal
+    pub fn interactive(mut self, interactive: bool) -> Self {
+        self.interactive = interactive;
+        self
+    }
+
+    /// Scratch directory for extraction staging (see `download.temp_dir`)
+    pub fn with_temp_dir(mut self, temp_dir: Option<PathBuf>) -> Self {
+        self.temp_dir = temp_dir;
+        self
+    }
+
+    /// Sync the download directory after renames and moves unless `never`
+    /// (see `download.fsync`)
+    pub fn with_fsync(mut self, fsync: FsyncPolicy) -> Self {
+        self.fsync = fsync;
+        self
+    }
+
+    /// Modes and owner for extracted files and everything else in the
+    /// download directory once processing is done (see `download.file_mode`)
+    pub fn with_permissions(mut self, permissions: Permissions) -> Self {
+        self.permissions = permissions;
+        self
+    }
+
+    /// Report each phase on an event channel as it starts
+    pub fn with_events(mut self, events: Events) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Report each phase's progress to `sink` (silent by default)
+    pub fn with_progress(mut self, sink: Arc<dyn ProgressSink>) -> Self {
+        self.progress = sink;
+        self
+    }
+
+    /// PAR2 sets known to have failed past repair (see
+    /// [`par2_shortfalls`](super::par2_shortfalls)): repair is skipped rather
+    /// than run to fail, and extraction with it
+    pub fn with_par2_shortfalls(mut self, shortfalls: Vec<Par2Shortfall>) -> Self {
+        self.par2_shortfalls = shortfalls;
+        self
+    }
+
+    /// The folder's PAR2 packets, already read (read again when not given)
+    pub fn with_par2_index(mut self, index: Par2Index) -> Self {
+        self.par2_index = Some(index);
+        self
+    }
+
+    /// Files named with `--name-map`, which deobfuscation leaves alone
+    pub fn with_mapped_names(mut self, names: Vec<String>) -> Self {
+        self.mapped_names = names;
+        self
+    }
+
+    /// Record each phase that runs in `phases`, for the summary and history
+    pub fn with_phases(mut self, phases: PhaseRecorder) -> Self {
+        self.phases = phases;
+        self
+    }
+
+    fn phase(&self, phase: PostProcessPhase) -> PhaseTimer {
+        self.events.send(DownloadEvent::PostProcessing(phase));
+        self.progress.on_phase(phase);
+        self.phases.start(phase.into())
+    }
+
+    /// Take `dir`'s disk from downloads for `what`, when `io_priority` says to
+    async fn hold_disk(&self, dir: &Path, what: &str) -> Option<DiskHold> {
+        if self.config.io_priority != IoPriority::Exclusive {
+            return None;
+        }
+        let hold = DiskGate::for_path(dir).hold().await;
+        if hold.paused_writers() > 0 {
+            self.progress
+                .on_message(&format!("Downloads to this disk paused during {}", what));
+        }
+        Some(hold)
+    }
+
+    fn release_disk(&self, hold: Option<DiskHold>) {
+        if hold.is_some_and(|hold| hold.paused_writers() > 0) {
+            self.progress.on_message("Downloads resumed");
         }
+    }
 
-        let download_dir = results[0].path.parent().unwrap_or(Path::new("."));
+    /// Repair, extract and tidy up the files in `manifest`
+    ///
+    /// Build the manifest with [`Manifest::from_results`] after a download,
+    /// or [`Manifest::scan`] to take a folder as it is.
+    pub async fn process_downloads(&self, manifest: &Manifest) -> Result<PostProcessResult> {
+        let mut outcome = PostProcessResult::default();
 
-        // Collect PAR2 files from download results
-        let downloaded_par2_files: Vec<PathBuf> = results
-            .iter()
-            .filter(|r| par2_patterns::is_par2_file(&r.path))
-            .map(|r| r.path.clone())
-            .collect();
+        if manifest.is_empty() {
+            return Ok(outcome);
+        }
+
+        let mut manifest = manifest.clone();
+        let dir = manifest.dir.clone();
+        let download_dir = dir.as_path();
+        let downloaded_par2_files = manifest.par2_files();
 
         let useful_name = download_dir
             .file_name()
             .and_then(|n| n.to_str())
             .unwrap_or("download");
 
+        let read_index;
+        let par2_index = match &self.par2_index {
+            Some(index) => index,
+            None => {
+                read_index = Par2Index::read(download_dir);
+                &read_index
+            }
+        };
+
+        // Real names of files posted under others, kept aside rather than applied
+        let posted_names = if self.config.preserve_names {
+            names::discover(&manifest, par2_index)
+        } else {
+            Default::default()
+        };
+        if let Err(e) = names::write(download_dir, &posted_names) {
+            tracing::warn!("Failed to write {}: {}", names::NAMES_FILE, e);
+        }
+
         // Run PAR2 repair if configured
         let par2_status = if self.config.auto_par2_repair {
-            let bar = ProgressBar::new(100);
-            bar.enable_steady_tick(Duration::from_millis(100));
-
-            par2::repair_with_par2(&self.config, download_dir, &downloaded_par2_files, &bar).await?
+            let mut timer = self.phase(PostProcessPhase::Par2);
+            let before = manifest.listing();
+            let hold = self.hold_disk(download_dir, "PAR2").await;
+            let status = match par2::backend() {
+                Some(_) if !self.par2_shortfalls.is_empty() => self.skip_doomed_repair(),
+                Some(backend) => {
+                    let repair = par2::repair_with_par2(
+                        &self.config,
+                        download_dir,
+                        &downloaded_par2_files,
+                        backend.as_ref(),
+                        &self.progress,
+                    );
+                    // A panic in the repair fails it like any other error
+                    // instead of taking the run with it
+                    match AssertUnwindSafe(repair).catch_unwind().await {
+                        Ok(status) => status?,
+                        Err(payload) => {
+                            let reason = format!(
+                                "PAR2 repair panicked: {}",
+                                panic_message(payload.as_ref())
+                            );
+                            tracing::error!("{}", reason);
+                            self.progress
+                                .on_status(&format!("  └─ \x1b[31m✗ {}\x1b[0m", reason));
+                            Par2Status::Failed { reason }
+                        }
+                    }
+                }
+                None => {
+                    // Carry on as if there were no PAR2 files rather than fail the run
+                    if !downloaded_par2_files.is_empty() {
+                        tracing::warn!(
+                            "PAR2 repair skipped: built without builtin-par2 and no par2 binary on PATH"
+                        );
+                        self.progress.on_status(&format!(
+                            "  └─ \x1b[33m⚠ PAR2 skipped: no PAR2 support ({} or build with the builtin-par2 feature)\x1b[0m",
+                            par2_cli::INSTALL_HINT
+                        ));
+                    }
+                    Par2Status::NoPar2Files
+                }
+            };
+            self.release_disk(hold);
+            if !posted_names.is_empty() {
+                names::restore(download_dir, &posted_names, &before);
+            }
+            // Repairs rename files into place
+            manifest.follow_renames(&before);
+            timer.count("par2_files", downloaded_par2_files.len());
+            timer.finish(match &status {
+                Par2Status::NoPar2Files => PhaseStatus::Skipped,
+                Par2Status::Success | Par2Status::Repaired => PhaseStatus::Success,
+                Par2Status::Failed { .. } => PhaseStatus::Failed,
+            });
+            status
         } else {
             Par2Status::NoPar2Files
         };
+        outcome.par2_verified = par2_status.is_ok();
+        outcome.par2_repaired = par2_status == Par2Status::Repaired;
+        if let Par2Status::Failed { reason } = &par2_status {
+            outcome.par2_error = Some(reason.clone());
+        }
+        outcome.par2_shortfalls = self.par2_shortfalls.clone();
+
+        if outcome.par2_verified {
+            outcome.duplicates_removed =
+                duplicates::resolve_par2_duplicates(download_dir, par2_index);
+        }
 
-        // Check archive integrity
-        let archive_files_with_failures = self.check_archive_integrity(results, download_dir)?;
+        // Archive sets with volumes that didn't download whole
+        let damaged_sets = manifest.damaged_archive_sets();
 
         // Extract RAR archives only if safe
         let should_extract = self.config.auto_extract_rar
-            && ((archive_files_with_failures.is_empty() && par2_status == Par2Status::NoPar2Files)
-                || par2_status == Par2Status::Success);
+            && ((damaged_sets.is_empty() && par2_status == Par2Status::NoPar2Files)
+                || par2_status.is_ok());
 
         if should_extract {
-            let bar = ProgressBar::new(100);
-            bar.enable_steady_tick(Duration::from_millis(100));
-
-            let extractor = RarExtractor::new(self.config.clone(), self.large_file_threshold);
-            extractor.extract_archives(download_dir, &bar).await?;
+            let mut timer = self.phase(PostProcessPhase::Extract);
+            let extractor = RarExtractor::new(self.config.clone(), self.large_file_threshold)
+                .with_passwords(self.passwords.clone())
+                .interactive(self.interactive)
+                .with_temp_dir(self.temp_dir.clone());
+            let hold = self.hold_disk(download_dir, "extraction").await;
+            let stats = extractor
+                .extract_archives(download_dir, &manifest.archives(), self.progress.as_ref())
+                .await?;
+            self.release_disk(hold);
+            outcome.rar_extracted = stats.extracted > 0;
+            outcome.rar_files_deleted = stats.rar_files_deleted;
+            outcome.par2_files_deleted = stats.par2_files_deleted;
+            outcome.space_shortfalls = stats.space_shortfalls;
+            outcome.password_required = stats.password_required;
+            outcome.extract_failed = stats.failed;
+            let failed = outcome.extract_failed.len()
+                + outcome.password_required.len()
+                + outcome.space_shortfalls.len();
+            timer
+                .count("extracted", stats.extracted)
+                .count("failed", failed)
+                .count("volumes_deleted", outcome.rar_files_deleted);
+            timer.finish(if failed > 0 {
+                PhaseStatus::Incomplete
+            } else if stats.extracted == 0 {
+                PhaseStatus::Skipped
+            } else {
+                PhaseStatus::Success
+            });
         }
 
         // Deobfuscate file names if configured
-        if self.config.deobfuscate_file_names {
-            self.run_deobfuscation(download_dir, useful_name)?;
+        if self.config.deobfuscate_file_names && !self.config.preserve_names {
+            let mut timer = self.phase(PostProcessPhase::Deobfuscate);
+            outcome.files_renamed = self.run_deobfuscation(download_dir, useful_name)?;
+            timer.count("renamed", outcome.files_renamed);
+            timer.finish(PhaseStatus::Success);
+        }
+
+        // Ads and placeholders, now that extraction has put out everything
+        outcome.junk_removed = self.remove_junk(download_dir);
+
+        // Verify media containers last so checks see final file names
+        if self.config.verify_media {
+            let mut timer = self.phase(PostProcessPhase::VerifyMedia);
+            outcome.media_checks = self.run_media_verification(download_dir);
+            let failed = outcome.failed_media().count();
+            timer
+                .count("checked", outcome.media_checks.len())
+                .count("failed", failed);
+            timer.finish(if failed > 0 {
+                PhaseStatus::Incomplete
+            } else {
+                PhaseStatus::Success
+            });
+        }
+
+        // Protection for the finished files, which must be known good
+        if self.config.create_par2_after_extract {
+            let damaged = !damaged_sets.is_empty() && !par2_status.is_ok();
+            match outcome
+                .unverified()
+                .or(damaged.then_some("archives are damaged"))
+            {
+                Some(reason) => {
+                    tracing::warn!("PAR2 creation skipped: {}", reason);
+                    self.progress.on_status(&format!(
+                        "  └─ \x1b[33m⚠ PAR2 creation skipped, {}\x1b[0m",
+                        reason
+                    ));
+                }
+                None => {
+                    let mut timer = self.phase(PostProcessPhase::CreatePar2);
+                    match self.create_par2(download_dir).await {
+                        Ok(created) => {
+                            timer.count("volumes", created.volumes.len());
+                            timer.finish(PhaseStatus::Success);
+                            outcome.par2_created = Some(created);
+                        }
+                        Err(reason) => {
+                            timer.finish(PhaseStatus::Failed);
+                            outcome.par2_create_error = Some(reason);
+                        }
+                    }
+                }
+            }
+        }
+
+        // Covers extracted files and folders as well as repaired ones
+        self.permissions.apply_tree(download_dir);
+
+        // Repairs, extraction and deobfuscation rename files into place
+        if self.fsync != FsyncPolicy::Never {
+            if let Err(e) = sync_dir(download_dir) {
+                tracing::warn!("Failed to sync {}: {}", download_dir.display(), e);
+            }
         }
 
-        Ok(())
+        Ok(outcome)
     }
 
-    /// Check if any RAR files have failed segments
-    fn check_archive_integrity(
-        &self,
-        results: &[DownloadResult],
-        download_dir: &Path,
-    ) -> Result<Vec<String>> {
-        let mut failed_rar_files = Vec::new();
+    /// Report the sets that can't be repaired instead of running PAR2
+    fn skip_doomed_repair(&self) -> Par2Status {
+        for shortfall in &self.par2_shortfalls {
+            tracing::warn!("PAR2 repair skipped: {}", shortfall.describe());
+        }
+        let reason = match self.par2_shortfalls.as_slice() {
+            [shortfall] => format!("not enough recovery blocks: {}", shortfall.describe()),
+            shortfalls => format!(
+                "not enough recovery blocks in {} PAR2 sets",
+                shortfalls.len()
+            ),
+        };
+        self.progress.on_status(&format!(
+            "  └─ \x1b[31m✗ PAR2 repair skipped, {}\x1b[0m",
+            reason
+        ));
+        Par2Status::Failed { reason }
+    }
 
-        let rar_files: Vec<PathBuf> = std::fs::read_dir(download_dir)?
-            .filter_map(|entry| entry.ok())
-            .map(|entry| entry.path())
-            .filter(|path| rar::is_rar_archive(path))
-            .collect();
+    /// Check extracted media files for truncation or broken containers
+    fn run_media_verification(&self, download_dir: &Path) -> Vec<MediaCheck> {
+        let checks = media::verify_directory(download_dir);
+        let failed: Vec<&MediaCheck> = checks.iter().filter(|c| !c.passed).collect();
 
-        for rar_path in rar_files {
-            let filename = rar_path
-                .file_name()
-                .and_then(|n| n.to_str())
-                .unwrap_or("unknown");
+        if checks.is_empty() {
+            return checks;
+        }
 
-            if let Some(result) = results.iter().find(|r| {
-                r.path
+        if failed.is_empty() {
+            self.progress.on_status(&format!(
+                "  └─ \x1b[32m✓ Media verified ({} file{})\x1b[0m",
+                checks.len(),
+                if checks.len() == 1 { "" } else { "s" }
+            ));
+        } else {
+            for check in &failed {
+                let name = check
+                    .path
                     .file_name()
-                    .and_then(|n| n.to_str())
-                    .map(|n| n == filename)
-                    .unwrap_or(false)
-            }) {
-                if result.segments_failed > 0 {
-                    failed_rar_files.push(filename.to_string());
-                }
+                    .map(|n| n.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                self.progress.on_status(&format!(
+                    "  \x1b[31m✗ {}: {}\x1b[0m",
+                    name,
+                    check.reason.as_deref().unwrap_or("verification failed")
+                ));
             }
+            self.progress.on_status(&format!(
+                "  └─ \x1b[31m✗ Media verification failed for {} file{} (source archive may be damaged)\x1b[0m",
+                failed.len(),
+                if failed.len() == 1 { "" } else { "s" }
+            ));
         }
 
-        Ok(failed_rar_files)
+        checks
     }
 
-    /// Run deobfuscation on extracted files
-    fn run_deobfuscation(&self, download_dir: &Path, useful_name: &str) -> Result<()> {
-        use indicatif::ProgressStyle as IndicatifStyle;
+    /// Make a recovery set for the files in `download_dir`; an error is why
+    /// it couldn't be made
+    async fn create_par2(&self, download_dir: &Path) -> std::result::Result<CreatedPar2, String> {
+        let hold = self.hold_disk(download_dir, "PAR2 creation").await;
+        let created = par2::create_par2(
+            download_dir,
+            Par2CreateOptions::from_config(&self.config),
+            &self.progress,
+        )
+        .await;
+        self.release_disk(hold);
+        match created {
+            Ok(created) => {
+                self.progress.on_status(&format!(
+                    "  └─ \x1b[33m✓ PAR2 created ({} file{}, {} volume{}, {}%)\x1b[0m",
+                    created.files,
+                    if created.files == 1 { "" } else { "s" },
+                    created.volumes.len(),
+                    if created.volumes.len() == 1 { "" } else { "s" },
+                    created.redundancy
+                ));
+                Ok(created)
+            }
+            Err(DlNzbError::PostProcessing(PostProcessingError::ToolNotFound { .. })) => {
+                tracing::warn!("PAR2 creation skipped: no par2 binary on PATH");
+                self.progress.on_status(&format!(
+                    "  └─ \x1b[31m✗ PAR2 not created: no par2 binary ({})\x1b[0m",
+                    par2_cli::INSTALL_HINT
+                ));
+                Err("no par2 binary on PATH".to_string())
+            }
+            Err(e) => {
+                let reason = match e {
+                    DlNzbError::PostProcessing(PostProcessingError::Par2Failed { reason }) => {
+                        reason
+                    }
+                    e => e.to_string(),
+                };
+                tracing::warn!("PAR2 creation failed: {}", reason);
+                self.progress.on_status(&format!(
+                    "  └─ \x1b[31m✗ PAR2 creation failed: {}\x1b[0m",
+                    reason
+                ));
+                Err(reason)
+            }
+        }
+    }
 
-        let spinner = ProgressBar::new_spinner();
-        spinner.set_style(
-            IndicatifStyle::with_template("{spinner:.cyan} {msg}")
-                .unwrap()
-                .tick_strings(&["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"]),
-        );
-        spinner.enable_steady_tick(Duration::from_millis(80));
-        spinner.set_message("Deobfuscating...");
+    /// Delete files matching `cleanup_patterns`, returning how many went
+    fn remove_junk(&self, download_dir: &Path) -> usize {
+        let patterns = match super::cleanup::compile_cleanup_patterns(&self.config.cleanup_patterns)
+        {
+            Ok(patterns) => patterns,
+            Err(e) => {
+                tracing::warn!("Not removing junk files: {}", e);
+                return 0;
+            }
+        };
+        let removed = super::cleanup::remove_junk(download_dir, &patterns, &self.mapped_names);
+        if removed > 0 {
+            self.progress.on_status(&format!(
+                "  \x1b[36m✓ Removed {} junk file{}\x1b[0m",
+                removed,
+                if removed == 1 { "" } else { "s" }
+            ));
+        }
+        removed
+    }
 
-        match super::deobfuscate::deobfuscate_files(download_dir, useful_name) {
+    /// Run deobfuscation on extracted files, returning the number of renamed files
+    fn run_deobfuscation(&self, download_dir: &Path, useful_name: &str) -> Result<usize> {
+        self.progress
+            .on_phase_progress(PhaseStep::Working, 0, 0, "Deobfuscating...");
+
+        let mut renamed = 0;
+        match super::deobfuscate::deobfuscate_files(download_dir, useful_name, &self.mapped_names) {
             Ok(result) => {
+                renamed = result.files_renamed + result.extensions_fixed;
                 if result.files_renamed > 0 || result.extensions_fixed > 0 {
                     let mut msg = Vec::new();
                     if result.extensions_fixed > 0 {
@@ -140,18 +602,103 @@ impl PostProcessor {
                     if result.files_renamed > 0 {
                         msg.push(format!("{} renamed", result.files_renamed));
                     }
-                    spinner.finish_and_clear();
-                    println!("  \x1b[36m✓ Deobfuscated ({})\x1b[0m", msg.join(", "));
+                    self.progress.on_phase_done(PhaseEnd::Skipped);
+                    self.progress.on_status(&format!(
+                        "  \x1b[36m✓ Deobfuscated ({})\x1b[0m",
+                        msg.join(", ")
+                    ));
                 } else {
-                    spinner.finish_and_clear();
+                    self.progress.on_phase_done(PhaseEnd::Skipped);
                 }
             }
             Err(e) => {
                 tracing::debug!("Deobfuscation failed: {}", e);
-                spinner.finish_and_clear();
+                self.progress.on_phase_done(PhaseEnd::Skipped);
             }
         }
 
-        Ok(())
+        Ok(renamed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::download::DownloadResult;
+    use std::sync::Mutex;
+
+    /// Sink that keeps a line per callback
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl ProgressSink for Recorder {
+        fn on_phase(&self, phase: PostProcessPhase) {
+            self.0.lock().unwrap().push(format!("phase {:?}", phase));
+        }
+
+        fn on_phase_progress(&self, step: PhaseStep, _done: u64, _total: u64, message: &str) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("{:?} {}", step, message));
+        }
+
+        fn on_phase_done(&self, end: PhaseEnd) {
+            self.0.lock().unwrap().push(format!("done {:?}", end));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_phases_reach_progress_sink() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("episode.mkv");
+        std::fs::write(&path, b"data").unwrap();
+        let results = [DownloadResult {
+            filename: "episode.mkv".to_string(),
+            path,
+            size: 4,
+            segments_downloaded: 1,
+            group: "alt.test".to_string(),
+            ..Default::default()
+        }];
+        let config = PostProcessingConfig {
+            auto_par2_repair: true,
+            auto_extract_rar: true,
+            deobfuscate_file_names: true,
+            verify_media: false,
+            ..PostProcessingConfig::default()
+        };
+
+        let recorder = Arc::new(Recorder::default());
+        PostProcessor::new(config, u64::MAX)
+            .with_progress(recorder.clone())
+            .process_downloads(&Manifest::from_results(&results))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            [
+                "phase Par2",
+                "Scanning Searching for PAR2 files...",
+                "done Skipped",
+                "phase Extract",
+                "Scanning Scanning for RAR archives...",
+                "done Skipped",
+                "phase Deobfuscate",
+                "Working Deobfuscating...",
+                "done Skipped",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_par2_creation_needs_verified_content() {
+        let mut outcome = PostProcessResult::default();
+        assert_eq!(outcome.unverified(), None);
+        outcome.password_required.push(PathBuf::from("show.rar"));
+        assert_eq!(outcome.unverified(), Some("extraction failed"));
+        outcome.par2_error = Some("not enough recovery blocks".to_string());
+        assert_eq!(outcome.unverified(), Some("PAR2 verification failed"));
     }
 }
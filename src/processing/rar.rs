@@ -1,21 +1,70 @@
 //! RAR archive extraction functionality
 
-use indicatif::ProgressBar;
+use futures::FutureExt;
+use human_bytes::human_bytes;
+use serde::{Deserialize, Serialize};
+use std::panic::AssertUnwindSafe;
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 use std::time::Duration;
+use unrar::error::UnrarError;
 use unrar::Archive;
 
 use crate::config::PostProcessingConfig;
-use crate::error::DlNzbError;
-use crate::patterns::rar as rar_patterns;
-use crate::progress;
+use crate::download::same_filesystem;
+use crate::error::{panic_message, DlNzbError, PostProcessingError};
+use crate::patterns::{par2 as par2_patterns, rar as rar_patterns, Matching};
+use crate::progress::{PhaseEnd, PhaseStep, ProgressSink};
+
+use super::password;
 
 type Result<T> = std::result::Result<T, DlNzbError>;
 
+/// An archive set skipped because the destination lacked free space
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpaceShortfall {
+    pub archive: PathBuf,
+    pub required: u64,
+    pub available: u64,
+}
+
+impl SpaceShortfall {
+    /// Bytes missing on the destination
+    pub fn missing(&self) -> u64 {
+        self.required.saturating_sub(self.available)
+    }
+}
+
+/// Counts from an extraction run
+#[derive(Debug, Clone, Default)]
+pub struct ExtractionStats {
+    pub extracted: usize,
+    pub rar_files_deleted: usize,
+    pub par2_files_deleted: usize,
+    pub space_shortfalls: Vec<SpaceShortfall>,
+    pub password_required: Vec<PathBuf>,
+    /// Archives that could not be opened or extracted
+    pub failed: Vec<PathBuf>,
+}
+
 /// RAR extraction configuration
 pub struct RarExtractor {
     config: PostProcessingConfig,
     large_file_threshold: u64,
+    passwords: Vec<String>,
+    interactive: bool,
+    temp_dir: Option<PathBuf>,
+    /// Passwords that opened a set earlier, most recent first
+    known_good: Mutex<Vec<String>>,
+}
+
+/// How extracting one archive set ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Extraction {
+    Extracted,
+    Failed,
+    /// The first file failed the way a wrong password makes it
+    WrongPassword,
 }
 
 impl RarExtractor {
@@ -23,102 +72,342 @@ impl RarExtractor {
         Self {
             config,
             large_file_threshold,
+            passwords: Vec::new(),
+            interactive: false,
+            temp_dir: None,
+            known_good: Mutex::new(Vec::new()),
         }
     }
 
-    /// Extract all RAR archives in the directory
+    /// Passwords to try for encrypted archives (e.g. from the NZB metadata)
+    pub fn with_passwords(mut self, passwords: Vec<String>) -> Self {
+        self.passwords = passwords;
+        self
+    }
+
+    /// Allow prompting on the terminal when no known password works
+    pub fn interactive(mut self, interactive: bool) -> Self {
+        self.interactive = interactive;
+        self
+    }
+
+    /// Directory for `<set>.extracting` staging folders (defaults to the download directory)
+    pub fn with_temp_dir(mut self, temp_dir: Option<PathBuf>) -> Self {
+        self.temp_dir = temp_dir;
+        self
+    }
+
+    /// Extract `archives`, the first volumes of RAR sets in `download_dir`
+    ///
+    /// Callers must only invoke this once PAR2 has passed or wasn't needed, since
+    /// PAR2 volumes of extracted sets may be deleted afterwards.
     pub async fn extract_archives(
         &self,
         download_dir: &Path,
-        progress_bar: &ProgressBar,
-    ) -> Result<()> {
-        progress_bar.set_message("Scanning for RAR archives...");
-
-        let rar_files: Vec<PathBuf> = std::fs::read_dir(download_dir)?
-            .filter_map(|entry| entry.ok())
-            .map(|entry| entry.path())
-            .filter(|path| is_rar_archive(path))
+        archives: &[PathBuf],
+        progress: &dyn ProgressSink,
+    ) -> Result<ExtractionStats> {
+        progress.on_phase_progress(PhaseStep::Scanning, 0, 0, "Scanning for RAR archives...");
+
+        let mut rar_files: Vec<PathBuf> = archives
+            .iter()
+            .filter(|path| is_rar_archive(path) && path.is_file())
+            .cloned()
             .collect();
+        rar_files.sort();
 
         if rar_files.is_empty() {
-            progress_bar.finish_and_clear();
-            return Ok(());
+            progress.on_phase_done(PhaseEnd::Skipped);
+            return Ok(ExtractionStats::default());
         }
 
         let total_archives = rar_files.len() as u64;
-        progress_bar.set_length(total_archives);
-        progress::apply_style(progress_bar, progress::ProgressStyle::Extract);
 
-        let mut extracted_count = 0;
+        let mut stats = ExtractionStats::default();
 
-        for (index, rar_path) in rar_files.iter().enumerate() {
+        'archives: for (index, rar_path) in rar_files.iter().enumerate() {
             let filename = rar_path
                 .file_name()
                 .and_then(|n| n.to_str())
                 .unwrap_or("unknown");
 
-            progress_bar.set_position(index as u64);
-            progress_bar.set_message(format!("Extracting {}", filename));
+            progress.on_phase_progress(
+                PhaseStep::Scanning,
+                index as u64,
+                total_archives,
+                &format!("Extracting {}", filename),
+            );
+
+            // An encrypted set is extracted with each candidate in turn until
+            // one gets past the first file
+            let mut candidates =
+                password::needs_password(rar_path).then(|| self.password_candidates().into_iter());
+            let mut prompted = false;
+            let (staging, extracted) = loop {
+                let password = match candidates.as_mut() {
+                    None => None,
+                    Some(candidates) => {
+                        match self.next_password(candidates, rar_path, &mut prompted, progress) {
+                            Some(password) => Some(password),
+                            None => {
+                                let error = PostProcessingError::PasswordRequired {
+                                    archive: rar_path.clone(),
+                                };
+                                tracing::warn!("{}", error);
+                                progress.on_message(&format!(
+                                    "  \x1b[31m✗ Skipped {}: password required\x1b[0m",
+                                    filename
+                                ));
+                                stats.password_required.push(rar_path.clone());
+                                continue 'archives;
+                            }
+                        }
+                    }
+                };
 
-            if self
-                .extract_archive(rar_path, download_dir, progress_bar)
-                .await?
-            {
-                extracted_count += 1;
+                let listing = match list_archive(rar_path, password.as_deref()) {
+                    Ok(Some(listing)) => listing,
+                    // Encrypted headers don't list with a wrong password
+                    Err(e) if password.is_some() && password::is_wrong_password(&e.code) => {
+                        continue
+                    }
+                    Ok(None) | Err(_) => {
+                        stats.failed.push(rar_path.clone());
+                        continue 'archives;
+                    }
+                };
+
+                // Leave the archives intact rather than failing halfway through
+                if let Some(shortfall) = self.check_free_space(rar_path, download_dir, listing.1) {
+                    let error = PostProcessingError::InsufficientDiskSpace {
+                        archive: shortfall.archive.clone(),
+                        required: shortfall.required,
+                        available: shortfall.available,
+                    };
+                    tracing::warn!("{}", error);
+                    progress.on_message(&format!(
+                        "  \x1b[31m✗ Skipped {}: needs {}, {} free ({} short)\x1b[0m",
+                        filename,
+                        human_bytes(shortfall.required as f64),
+                        human_bytes(shortfall.available as f64),
+                        human_bytes(shortfall.missing() as f64)
+                    ));
+                    stats.space_shortfalls.push(shortfall);
+                    continue 'archives;
+                }
+
+                let staging = self
+                    .config
+                    .extract_to_temp
+                    .then(|| self.staging_dir(rar_path, download_dir));
+
+                // Clear leftovers from an earlier interrupted run
+                if let Some(ref staging) = staging {
+                    if staging.exists() {
+                        std::fs::remove_dir_all(staging)?;
+                    }
+                }
+
+                let target_dir = staging.as_deref().unwrap_or(download_dir);
+                // A panic extracting one archive set fails that set; the others
+                // are still extracted
+                let extraction =
+                    self.extract_archive(rar_path, target_dir, listing, password.clone(), progress);
+                let extraction = match AssertUnwindSafe(extraction).catch_unwind().await {
+                    Ok(extraction) => extraction?,
+                    Err(payload) => {
+                        tracing::error!(
+                            "Extracting {} panicked: {}",
+                            rar_path.display(),
+                            panic_message(payload.as_ref())
+                        );
+                        progress.on_message(&format!(
+                            "  \x1b[31m✗ Extracting {} failed unexpectedly\x1b[0m",
+                            filename
+                        ));
+                        Extraction::Failed
+                    }
+                };
+
+                match (extraction, password) {
+                    (Extraction::WrongPassword, Some(_)) => {
+                        if let Some(ref staging) = staging {
+                            let _ = std::fs::remove_dir_all(staging);
+                        }
+                    }
+                    (extraction, password) => {
+                        let extracted = extraction == Extraction::Extracted;
+                        if let Some(password) = password.filter(|_| extracted) {
+                            self.remember_password(password);
+                        }
+                        break (staging, extracted);
+                    }
+                }
+            };
+
+            if let Some(ref staging) = staging {
+                let moved = extracted && {
+                    let result = move_into_place(staging, download_dir);
+                    if let Err(ref e) = result {
+                        tracing::warn!(
+                            "Failed to move extracted files from {}: {}",
+                            staging.display(),
+                            e
+                        );
+                    }
+                    result.is_ok()
+                };
+                let _ = std::fs::remove_dir_all(staging);
+                if !moved {
+                    stats.failed.push(rar_path.clone());
+                    continue;
+                }
+            }
+
+            if !extracted {
+                stats.failed.push(rar_path.clone());
+            } else {
+                stats.extracted += 1;
                 if self.config.delete_rar_after_extract {
-                    delete_rar_parts(rar_path, download_dir)?;
+                    stats.rar_files_deleted += delete_rar_parts(rar_path, download_dir)?;
+                }
+                if self.config.should_delete_par2_after_extract() {
+                    stats.par2_files_deleted += delete_par2_for_set(rar_path, download_dir)?;
                 }
             }
         }
 
-        progress_bar.set_position(total_archives);
-        progress_bar.finish_with_message("  ");
-        println!(
+        progress.on_phase_done(PhaseEnd::Finished);
+        progress.on_status(&format!(
             "  └─ \x1b[32m✓ Extracted {} archive{}\x1b[0m",
-            extracted_count,
-            if extracted_count == 1 { "" } else { "s" }
-        );
-        Ok(())
+            stats.extracted,
+            if stats.extracted == 1 { "" } else { "s" }
+        ));
+        Ok(stats)
+    }
+
+    /// Passwords to try on an encrypted set, each once: those that opened an
+    /// earlier set of this download, the NZB's, then `password_file`
+    fn password_candidates(&self) -> Vec<String> {
+        let mut candidates = self
+            .known_good
+            .lock()
+            .map(|known| known.clone())
+            .unwrap_or_default();
+        candidates.extend(self.passwords.iter().cloned());
+        if let Some(path) = self.config.password_file.as_deref() {
+            match password::load_password_file(path) {
+                Ok(list) => candidates.extend(list),
+                Err(e) => tracing::warn!("Failed to read {}: {}", path.display(), e),
+            }
+        }
+
+        let mut seen = std::collections::HashSet::new();
+        candidates.retain(|candidate| seen.insert(candidate.clone()));
+        candidates
+    }
+
+    /// Next password to extract an encrypted set with: a candidate, then what
+    /// the user enters when prompting is allowed; `None` once there are none
+    fn next_password(
+        &self,
+        candidates: &mut impl Iterator<Item = String>,
+        archive_path: &Path,
+        prompted: &mut bool,
+        progress: &dyn ProgressSink,
+    ) -> Option<String> {
+        if let Some(candidate) = candidates.next() {
+            return Some(candidate);
+        }
+        if !self.interactive {
+            return None;
+        }
+
+        let retry = std::mem::replace(prompted, true);
+        let mut entered = None;
+        progress.suspend(&mut || entered = password::prompt_password(archive_path, retry));
+        entered
+    }
+
+    /// Try a password that opened a set first on the sets after it
+    fn remember_password(&self, password: String) {
+        if let Ok(mut known) = self.known_good.lock() {
+            known.retain(|p| *p != password);
+            known.insert(0, password);
+        }
+    }
+
+    /// Staging directory for an archive set: `<temp_dir>/<set-name>.extracting`
+    fn staging_dir(&self, archive_path: &Path, download_dir: &Path) -> PathBuf {
+        let filename = archive_path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("archive");
+        let set_name = rar_patterns::extract_base_name(filename).unwrap_or(filename);
+        let base = self.temp_dir.as_deref().unwrap_or(download_dir);
+        base.join(format!("{}.extracting", set_name))
+    }
+
+    /// Compare the unpacked size against free space where extraction will write
+    ///
+    /// Archives are only deleted after extraction completes, so their size is not
+    /// subtracted from the estimate even when `delete_rar_after_extract` is set.
+    fn check_free_space(
+        &self,
+        archive_path: &Path,
+        download_dir: &Path,
+        required: u64,
+    ) -> Option<SpaceShortfall> {
+        let mut targets = vec![download_dir];
+        if self.config.extract_to_temp {
+            if let Some(temp_dir) = self.temp_dir.as_deref() {
+                // A staging area on another filesystem needs its own copy
+                if !same_filesystem(temp_dir, download_dir) {
+                    targets.push(temp_dir);
+                }
+            }
+        }
+
+        targets.into_iter().find_map(|target| {
+            let available = fs2::available_space(target).ok()?;
+            (available < required).then(|| SpaceShortfall {
+                archive: archive_path.to_path_buf(),
+                required,
+                available,
+            })
+        })
     }
 
     /// Extract a single RAR archive with progress tracking
+    ///
+    /// `listing` is the `(file_count, total_bytes)` pair from [`list_archive`].
+    /// Succeeds only when every entry was extracted without error; with a
+    /// password, failing on the first file the way a wrong one does is told
+    /// apart so the next can be tried.
     async fn extract_archive(
         &self,
         archive_path: &Path,
         output_dir: &Path,
-        progress_bar: &ProgressBar,
-    ) -> Result<bool> {
+        listing: (u64, u64),
+        password: Option<String>,
+        progress: &dyn ProgressSink,
+    ) -> Result<Extraction> {
         use tokio::sync::mpsc;
 
-        // First pass: Get total unpacked size for byte-level progress
-        let (file_count, total_bytes) = match Archive::new(archive_path).open_for_listing() {
-            Ok(mut listing) => {
-                let mut count = 0u64;
-                let mut bytes = 0u64;
-
-                while let Some(entry_result) = listing.next() {
-                    match entry_result {
-                        Ok(entry) => {
-                            if !entry.is_directory() {
-                                count += 1;
-                                bytes += entry.unpacked_size;
-                            }
-                        }
-                        Err(_) => return Ok(false),
-                    }
-                }
+        let (file_count, total_bytes) = listing;
 
-                if count == 0 {
-                    return Ok(false);
-                }
-
-                (count, bytes)
-            }
-            Err(_) => return Ok(false),
+        let mut message = format!(
+            "Extracting {}",
+            archive_path
+                .file_name()
+                .map(|n| n.to_string_lossy())
+                .unwrap_or_default()
+        );
+        let report = |position: u64, message: &str| {
+            progress.on_phase_progress(PhaseStep::Extracting, position, total_bytes, message)
         };
-
-        progress_bar.set_length(total_bytes);
-        progress_bar.set_position(0);
+        let mut position = 0;
+        report(position, &message);
 
         std::fs::create_dir_all(output_dir)?;
 
@@ -136,7 +425,7 @@ impl RarExtractor {
                 base_bytes: u64,
             },
             Done {
-                success: bool,
+                extraction: Extraction,
             },
         }
 
@@ -148,11 +437,26 @@ impl RarExtractor {
         let extraction_handle = tokio::task::spawn_blocking(move || {
             let mut bytes_extracted = 0u64;
             let mut extracted_files = 0u64;
+            let mut failed = false;
+            let mut wrong_password = false;
+            // Whether an error before any file was written means a wrong password
+            let wrong = |e: &UnrarError, extracted_files: u64| {
+                password.is_some() && extracted_files == 0 && password::is_wrong_password(&e.code)
+            };
 
-            let mut archive = match Archive::new(&archive_path).open_for_processing() {
+            let archive = match &password {
+                Some(password) => Archive::with_password(&archive_path, password.as_bytes()),
+                None => Archive::new(&archive_path),
+            };
+            let mut archive = match archive.open_for_processing() {
                 Ok(a) => a,
-                Err(_) => {
-                    let _ = tx.blocking_send(ProgressMsg::Done { success: false });
+                Err(e) => {
+                    let extraction = if wrong(&e, 0) {
+                        Extraction::WrongPassword
+                    } else {
+                        Extraction::Failed
+                    };
+                    let _ = tx.blocking_send(ProgressMsg::Done { extraction });
                     return;
                 }
             };
@@ -170,7 +474,10 @@ impl RarExtractor {
                                     archive = next;
                                     continue;
                                 }
-                                Err(_) => break,
+                                Err(_) => {
+                                    failed = true;
+                                    break;
+                                }
                             }
                         }
 
@@ -197,7 +504,10 @@ impl RarExtractor {
                                     archive = next;
                                     continue;
                                 }
-                                Err(_) => break,
+                                Err(_) => {
+                                    failed = true;
+                                    break;
+                                }
                             }
                         }
 
@@ -222,21 +532,38 @@ impl RarExtractor {
                                     bytes: bytes_extracted,
                                 });
                             }
-                            Err(_) => break,
+                            Err(e) => {
+                                // Don't leave what a wrong password unpacked
+                                if wrong(&e, extracted_files) {
+                                    wrong_password = true;
+                                    let _ = std::fs::remove_file(&output_path);
+                                }
+                                failed = true;
+                                break;
+                            }
                         }
                     }
                     Ok(None) => break,
-                    Err(_) => break,
+                    Err(e) => {
+                        wrong_password = wrong(&e, extracted_files);
+                        failed = true;
+                        break;
+                    }
                 }
             }
 
-            let _ = tx.blocking_send(ProgressMsg::Done {
-                success: extracted_files > 0,
-            });
+            let extraction = if wrong_password {
+                Extraction::WrongPassword
+            } else if !failed && extracted_files > 0 {
+                Extraction::Extracted
+            } else {
+                Extraction::Failed
+            };
+            let _ = tx.blocking_send(ProgressMsg::Done { extraction });
         });
 
         let mut current_monitor: Option<(PathBuf, u64)> = None;
-        let mut result = false;
+        let mut result = Extraction::Failed;
 
         loop {
             if let Some((ref path, base_bytes)) = current_monitor {
@@ -244,17 +571,19 @@ impl RarExtractor {
                     msg = rx.recv() => {
                         match msg {
                             Some(ProgressMsg::StartFile { name, index, total }) => {
-                                progress_bar.set_message(format!("Extracting {} [{}/{}]", name, index, total));
+                                message = format!("Extracting {} [{}/{}]", name, index, total);
+                                report(position, &message);
                             }
                             Some(ProgressMsg::FileComplete { bytes }) => {
-                                progress_bar.set_position(bytes);
+                                position = bytes;
+                                report(position, &message);
                                 current_monitor = None;
                             }
                             Some(ProgressMsg::MonitorFile { path, base_bytes }) => {
                                 current_monitor = Some((path, base_bytes));
                             }
-                            Some(ProgressMsg::Done { success }) => {
-                                result = success;
+                            Some(ProgressMsg::Done { extraction }) => {
+                                result = extraction;
                                 break;
                             }
                             None => break,
@@ -262,24 +591,26 @@ impl RarExtractor {
                     }
                     _ = tokio::time::sleep(Duration::from_millis(50)) => {
                         if let Ok(meta) = std::fs::metadata(path) {
-                            progress_bar.set_position(base_bytes + meta.len());
+                            position = base_bytes + meta.len();
+                            report(position, &message);
                         }
                     }
                 }
             } else {
                 match rx.recv().await {
                     Some(ProgressMsg::StartFile { name, index, total }) => {
-                        progress_bar
-                            .set_message(format!("Extracting {} [{}/{}]", name, index, total));
+                        message = format!("Extracting {} [{}/{}]", name, index, total);
+                        report(position, &message);
                     }
                     Some(ProgressMsg::FileComplete { bytes }) => {
-                        progress_bar.set_position(bytes);
+                        position = bytes;
+                        report(position, &message);
                     }
                     Some(ProgressMsg::MonitorFile { path, base_bytes }) => {
                         current_monitor = Some((path, base_bytes));
                     }
-                    Some(ProgressMsg::Done { success }) => {
-                        result = success;
+                    Some(ProgressMsg::Done { extraction }) => {
+                        result = extraction;
                         break;
                     }
                     None => break,
@@ -287,35 +618,273 @@ impl RarExtractor {
             }
         }
 
-        let _ = extraction_handle.await;
-        progress_bar.set_position(total_bytes);
+        // A panic in the extraction thread fails this archive alone
+        if let Err(e) = extraction_handle.await {
+            if let Ok(payload) = e.try_into_panic() {
+                tracing::error!(
+                    "Extracting {} panicked: {}",
+                    archive_path.display(),
+                    panic_message(payload.as_ref())
+                );
+                result = Extraction::Failed;
+            }
+        }
+        report(total_bytes, &message);
 
         Ok(result)
     }
 }
 
+/// List an archive, returning `(file_count, total_unpacked_bytes)`
+///
+/// `None` if the archive contains no files.
+fn list_archive(
+    archive_path: &Path,
+    password: Option<&str>,
+) -> std::result::Result<Option<(u64, u64)>, UnrarError> {
+    let archive = match password {
+        Some(password) => Archive::with_password(archive_path, password.as_bytes()),
+        None => Archive::new(archive_path),
+    };
+    let listing = archive.open_for_listing()?;
+    let mut count = 0u64;
+    let mut bytes = 0u64;
+
+    for entry_result in listing {
+        let entry = entry_result?;
+        if !entry.is_directory() {
+            count += 1;
+            bytes += entry.unpacked_size;
+        }
+    }
+
+    Ok((count > 0).then_some((count, bytes)))
+}
+
+/// A file listed in an archive
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveEntry {
+    pub name: String,
+    /// Unpacked size
+    pub size: u64,
+}
+
+/// What could be listed of an archive that may be cut short
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ArchiveListing {
+    pub entries: Vec<ArchiveEntry>,
+    /// Why listing stopped before the end, if it did; past the downloaded
+    /// part of a volume, or at the next volume, that's expected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stopped: Option<String>,
+}
+
+/// List as much of an archive as can be read
+///
+/// Entries are collected up to the first header that can't be read, so a
+/// volume with only its first megabytes on disk still lists the files those
+/// hold. Directories are left out.
+pub fn list_partial_archive(archive_path: &Path) -> ArchiveListing {
+    let mut listing = ArchiveListing::default();
+    let entries = match Archive::new(archive_path).open_for_listing() {
+        Ok(entries) => entries,
+        Err(e) => {
+            listing.stopped = Some(e.to_string());
+            return listing;
+        }
+    };
+    for entry in entries {
+        match entry {
+            Ok(entry) if entry.is_directory() => {}
+            Ok(entry) => listing.entries.push(ArchiveEntry {
+                name: entry.filename.display().to_string(),
+                size: entry.unpacked_size,
+            }),
+            Err(e) => {
+                listing.stopped = Some(e.to_string());
+                break;
+            }
+        }
+    }
+    listing
+}
+
 /// Check if a path is a RAR archive (first part only for multi-part)
 pub fn is_rar_archive(path: &Path) -> bool {
     rar_patterns::is_extractable_archive(path)
 }
 
-/// Delete all parts of a RAR archive
-fn delete_rar_parts(rar_path: &Path, download_dir: &Path) -> Result<()> {
+/// Move extracted files from a staging directory into their final location
+///
+/// Uses a plain rename where possible and falls back to copy + rename when the
+/// staging directory lives on a different filesystem.
+fn move_into_place(staging: &Path, dest: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(dest)?;
+
+    for entry in std::fs::read_dir(staging)? {
+        let entry = entry?;
+        let src = entry.path();
+        let target = dest.join(entry.file_name());
+
+        if entry.file_type()?.is_dir() {
+            move_into_place(&src, &target)?;
+            continue;
+        }
+
+        if std::fs::rename(&src, &target).is_err() {
+            // Copy next to the target first so the final rename stays atomic
+            let partial = dest.join(format!(".{}.partial", entry.file_name().to_string_lossy()));
+            if let Err(e) =
+                std::fs::copy(&src, &partial).and_then(|_| std::fs::rename(&partial, &target))
+            {
+                let _ = std::fs::remove_file(&partial);
+                return Err(e);
+            }
+            std::fs::remove_file(&src)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Delete all parts of a RAR archive, returning how many files were removed
+fn delete_rar_parts(rar_path: &Path, download_dir: &Path) -> Result<usize> {
     let filename = match rar_path.file_name().and_then(|n| n.to_str()) {
         Some(name) => name,
-        None => return Ok(()),
+        None => return Ok(0),
     };
 
     let base_name = rar_patterns::extract_base_name(filename).unwrap_or(filename);
+    let mut deleted = 0;
 
     if let Ok(entries) = std::fs::read_dir(download_dir) {
         for entry in entries.filter_map(|e| e.ok()) {
             let entry_name = entry.file_name().to_string_lossy().to_string();
-            if rar_patterns::is_same_archive(base_name, &entry_name) {
-                let _ = std::fs::remove_file(entry.path());
+            if rar_patterns::is_same_archive(base_name, &entry_name, Matching::PLATFORM)
+                && std::fs::remove_file(entry.path()).is_ok()
+            {
+                deleted += 1;
             }
         }
     }
 
-    Ok(())
+    Ok(deleted)
+}
+
+/// Delete PAR2 volumes and `.rev` recovery volumes belonging to an extracted set
+fn delete_par2_for_set(rar_path: &Path, download_dir: &Path) -> Result<usize> {
+    let filename = match rar_path.file_name().and_then(|n| n.to_str()) {
+        Some(name) => name,
+        None => return Ok(0),
+    };
+
+    let base_name = rar_patterns::extract_base_name(filename).unwrap_or(filename);
+    let mut deleted = 0;
+
+    if let Ok(entries) = std::fs::read_dir(download_dir) {
+        for entry in entries.filter_map(|e| e.ok()) {
+            let entry_name = entry.file_name().to_string_lossy().to_string();
+            if (par2_patterns::is_same_set(base_name, &entry_name, Matching::PLATFORM)
+                || rar_patterns::is_same_recovery_volume(
+                    base_name,
+                    &entry_name,
+                    Matching::PLATFORM,
+                ))
+                && std::fs::remove_file(entry.path()).is_ok()
+            {
+                deleted += 1;
+            }
+        }
+    }
+
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deleting_a_set_spares_one_named_after_it() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in [
+            "Show.S01.part01.rar",
+            "Show.S01.part02.rar",
+            "Show.S01.vol00+01.par2",
+            "Show.S01.Extras.part01.rar",
+            "Show.S01.Extras.r00",
+            "Show.S01.Extras.par2",
+            "Show.S01.Extras.part01.rev",
+        ] {
+            std::fs::write(dir.path().join(name), b"x").unwrap();
+        }
+        let first = dir.path().join("Show.S01.part01.rar");
+
+        assert_eq!(delete_rar_parts(&first, dir.path()).unwrap(), 2);
+        assert_eq!(delete_par2_for_set(&first, dir.path()).unwrap(), 1);
+        let mut left: Vec<String> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        left.sort();
+        assert_eq!(
+            left,
+            [
+                "Show.S01.Extras.par2",
+                "Show.S01.Extras.part01.rar",
+                "Show.S01.Extras.part01.rev",
+                "Show.S01.Extras.r00",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_partial_listing_of_a_non_archive() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("show.part01.rar");
+        std::fs::write(&path, [0u8; 512]).unwrap();
+
+        let listing = list_partial_archive(&path);
+        assert!(listing.entries.is_empty());
+        assert!(listing.stopped.is_some());
+    }
+
+    #[test]
+    fn test_move_into_place_merges_nested_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        let staging = dir.path().join("show.extracting");
+        let dest = dir.path().join("out");
+        std::fs::create_dir_all(staging.join("Subs")).unwrap();
+        std::fs::create_dir_all(dest.join("Subs")).unwrap();
+        std::fs::write(staging.join("show.mkv"), b"video").unwrap();
+        std::fs::write(staging.join("Subs/en.srt"), b"subs").unwrap();
+
+        move_into_place(&staging, &dest).unwrap();
+
+        assert_eq!(std::fs::read(dest.join("show.mkv")).unwrap(), b"video");
+        assert_eq!(std::fs::read(dest.join("Subs/en.srt")).unwrap(), b"subs");
+        assert!(!staging.join("show.mkv").exists());
+    }
+
+    #[test]
+    fn test_delete_par2_for_set_only_touches_same_set() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in [
+            "show.part01.rar",
+            "show.par2",
+            "Show.vol00+01.par2",
+            "show.part01.rev",
+            "other.par2",
+            "other.vol00+01.par2",
+        ] {
+            std::fs::write(dir.path().join(name), b"x").unwrap();
+        }
+
+        let deleted = delete_par2_for_set(&dir.path().join("show.part01.rar"), dir.path()).unwrap();
+
+        assert_eq!(deleted, 3);
+        assert!(dir.path().join("show.part01.rar").exists());
+        assert!(dir.path().join("other.par2").exists());
+        assert!(dir.path().join("other.vol00+01.par2").exists());
+    }
 }
@@ -0,0 +1,222 @@
+//! External post-processing script hook
+//!
+//! The script runs after all built-in steps. Context is passed via environment
+//! variables and the structured post-processing result is written to stdin as JSON:
+//!
+//! | Variable                | Value                                        |
+//! |-------------------------|----------------------------------------------|
+//! | `DLNZB_NZB_NAME`        | NZB file name without extension              |
+//! | `DLNZB_OUTPUT_DIR`      | Directory the download was written to        |
+//! | `DLNZB_STATUS`          | `success` or `failed`                        |
+//! | `DLNZB_BYTES`           | Total bytes downloaded                       |
+//! | `DLNZB_CATEGORY`        | Category from the NZB metadata (may be empty)|
+//! | `DLNZB_FAILED_SEGMENTS` | Number of segments that could not be fetched |
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use super::PostProcessResult;
+use crate::error::{DlNzbError, PostProcessingError};
+
+type Result<T> = std::result::Result<T, DlNzbError>;
+
+/// Download context exposed to the script
+#[derive(Debug, Clone)]
+pub struct ScriptContext {
+    pub nzb_name: String,
+    pub output_dir: PathBuf,
+    pub success: bool,
+    pub bytes: u64,
+    pub category: Option<String>,
+    pub failed_segments: usize,
+}
+
+/// Outcome of running the post-processing script
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScriptOutcome {
+    pub script: PathBuf,
+    pub exit_code: Option<i32>,
+    pub timed_out: bool,
+}
+
+impl ScriptOutcome {
+    pub fn success(&self) -> bool {
+        !self.timed_out && self.exit_code == Some(0)
+    }
+
+    /// How the script ended, e.g. "exited with code 1"
+    pub fn status(&self) -> String {
+        if self.timed_out {
+            "timed out".to_string()
+        } else {
+            match self.exit_code {
+                Some(code) => format!("exited with code {}", code),
+                None => "was terminated by a signal".to_string(),
+            }
+        }
+    }
+}
+
+/// Run the post-processing script with the given context
+///
+/// Failing to spawn the script is an error; a non-zero exit or timeout is
+/// reported through the returned [`ScriptOutcome`].
+pub async fn run_script(
+    script: &Path,
+    timeout: Duration,
+    context: &ScriptContext,
+    result: &PostProcessResult,
+) -> Result<ScriptOutcome> {
+    let payload = serde_json::to_vec(result)?;
+
+    let mut child = Command::new(script)
+        .current_dir(&context.output_dir)
+        .env("DLNZB_NZB_NAME", &context.nzb_name)
+        .env("DLNZB_OUTPUT_DIR", &context.output_dir)
+        .env(
+            "DLNZB_STATUS",
+            if context.success { "success" } else { "failed" },
+        )
+        .env("DLNZB_BYTES", context.bytes.to_string())
+        .env(
+            "DLNZB_CATEGORY",
+            context.category.as_deref().unwrap_or_default(),
+        )
+        .env("DLNZB_FAILED_SEGMENTS", context.failed_segments.to_string())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| PostProcessingError::ScriptFailed {
+            script: script.to_path_buf(),
+            reason: e.to_string(),
+        })?;
+
+    // Feed stdin while the output is drained, so a script that prints before
+    // reading can't block on a full pipe, and both count against the timeout
+    let stdin = child.stdin.take();
+    let write_stdin = async move {
+        if let Some(mut stdin) = stdin {
+            // Scripts that ignore stdin may close it early; that's fine
+            let _ = stdin.write_all(&payload).await;
+        }
+    };
+    let run = async {
+        let ((), output) = tokio::join!(write_stdin, child.wait_with_output());
+        output
+    };
+
+    let output = match tokio::time::timeout(timeout, run).await {
+        Ok(output) => output?,
+        Err(_) => {
+            tracing::warn!(
+                "Post-processing script {} timed out after {}s",
+                script.display(),
+                timeout.as_secs()
+            );
+            return Ok(ScriptOutcome {
+                script: script.to_path_buf(),
+                exit_code: None,
+                timed_out: true,
+            });
+        }
+    };
+
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        tracing::info!(target: "dl_nzb::script", "{}", line);
+    }
+    for line in String::from_utf8_lossy(&output.stderr).lines() {
+        tracing::warn!(target: "dl_nzb::script", "{}", line);
+    }
+
+    let outcome = ScriptOutcome {
+        script: script.to_path_buf(),
+        exit_code: output.status.code(),
+        timed_out: false,
+    };
+
+    if !outcome.success() {
+        tracing::warn!(
+            "Post-processing script {} exited with status {}",
+            script.display(),
+            output.status
+        );
+    }
+
+    Ok(outcome)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn write_script(dir: &Path, name: &str, body: &str) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    fn context(dir: &Path) -> ScriptContext {
+        ScriptContext {
+            nzb_name: "Show.S01E01".to_string(),
+            output_dir: dir.to_path_buf(),
+            success: true,
+            bytes: 1234,
+            category: Some("tv".to_string()),
+            failed_segments: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_script_receives_env_and_stdin() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = write_script(
+            dir.path(),
+            "env.sh",
+            "echo \"$DLNZB_NZB_NAME $DLNZB_STATUS $DLNZB_BYTES $DLNZB_CATEGORY\" > env.txt\ncat > result.json",
+        );
+
+        let outcome = run_script(
+            &script,
+            Duration::from_secs(10),
+            &context(dir.path()),
+            &PostProcessResult::default(),
+        )
+        .await
+        .unwrap();
+
+        assert!(outcome.success());
+        let env = std::fs::read_to_string(dir.path().join("env.txt")).unwrap();
+        assert_eq!(env.trim(), "Show.S01E01 success 1234 tv");
+        let json = std::fs::read_to_string(dir.path().join("result.json")).unwrap();
+        let parsed: PostProcessResult = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.files_renamed, 0);
+    }
+
+    #[tokio::test]
+    async fn test_script_failure_and_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let ctx = context(dir.path());
+        let result = PostProcessResult::default();
+
+        let script = write_script(dir.path(), "fail.sh", "exit 3");
+        let outcome = run_script(&script, Duration::from_secs(10), &ctx, &result)
+            .await
+            .unwrap();
+        assert_eq!(outcome.exit_code, Some(3));
+        assert!(!outcome.success());
+
+        let script = write_script(dir.path(), "slow.sh", "sleep 5");
+        let outcome = run_script(&script, Duration::from_millis(100), &ctx, &result)
+            .await
+            .unwrap();
+        assert!(outcome.timed_out);
+    }
+}
@@ -0,0 +1,423 @@
+//! Centralized progress reporting
+//!
+//! Provides a unified interface for displaying progress across downloads and post-processing.
+//! What gets shown is decided by one process-wide [`OutputMode`], set from the CLI flags.
+//! When stderr isn't a terminal (or with `--no-progress`) bars and spinners are
+//! replaced by a plain text line logged every few seconds; see [`set_plain`].
+//! Speeds and ETAs come from a [`RateWindow`] over the last few seconds
+//! rather than the whole run, so they follow changes in throughput.
+//! Colors and glyphs are dropped where the terminal can't show them; see
+//! [`style`].
+//!
+//! The downloader and post-processor report to a [`ProgressSink`]; the CLI
+//! draws it with `TerminalProgress` (behind the `cli` feature), and library
+//! users can supply their own or keep the silent [`NoProgress`] default.
+
+use human_bytes::human_bytes;
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::download::{DownloadResult, PostProcessPhase};
+
+pub mod style;
+#[cfg(feature = "cli")]
+mod terminal;
+
+#[cfg(feature = "cli")]
+pub use terminal::*;
+
+/// How much the terminal output shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(u8)]
+pub enum OutputMode {
+    /// Progress bars, status lines and summaries
+    #[default]
+    Normal,
+    /// As normal; logging is more detailed (`-v`)
+    Verbose,
+    /// Errors on stderr and one final line per NZB (`--quiet`)
+    Quiet,
+    /// stdout carries only the JSON document (`--json`)
+    Json,
+}
+
+static MODE: AtomicU8 = AtomicU8::new(OutputMode::Normal as u8);
+
+/// Set the output mode for the rest of the process
+pub fn set_mode(mode: OutputMode) {
+    MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+pub fn mode() -> OutputMode {
+    match MODE.load(Ordering::Relaxed) {
+        1 => OutputMode::Verbose,
+        2 => OutputMode::Quiet,
+        3 => OutputMode::Json,
+        _ => OutputMode::Normal,
+    }
+}
+
+/// Whether progress bars, spinners and decorative status lines are hidden
+pub fn is_hidden() -> bool {
+    matches!(mode(), OutputMode::Quiet | OutputMode::Json)
+}
+
+/// Milliseconds between plain progress lines; 0 while bars are drawn
+static PLAIN_INTERVAL_MS: AtomicU64 = AtomicU64::new(0);
+
+/// Log download progress as text every `interval` instead of drawing bars
+/// (`None` draws bars again)
+pub fn set_plain(interval: Option<Duration>) {
+    let ms = interval.map_or(0, |i| (i.as_millis() as u64).max(1));
+    PLAIN_INTERVAL_MS.store(ms, Ordering::Relaxed);
+}
+
+/// Interval between plain progress lines, when bars are replaced by them
+pub fn plain_interval() -> Option<Duration> {
+    match PLAIN_INTERVAL_MS.load(Ordering::Relaxed) {
+        0 => None,
+        ms => Some(Duration::from_millis(ms)),
+    }
+}
+
+/// Milliseconds of history behind speed and ETA figures
+static SPEED_WINDOW_MS: AtomicU64 = AtomicU64::new(10_000);
+
+/// Compute speeds and ETAs over the last `window` (`logging.speed_window`)
+pub fn set_speed_window(window: Duration) {
+    SPEED_WINDOW_MS.store((window.as_millis() as u64).max(1), Ordering::Relaxed);
+}
+
+pub fn speed_window() -> Duration {
+    Duration::from_millis(SPEED_WINDOW_MS.load(Ordering::Relaxed))
+}
+
+/// Rate of a growing total over a sliding time window
+#[derive(Debug, Clone)]
+pub struct RateWindow {
+    window: Duration,
+    /// `(time, running total)`, oldest first; the first one is the baseline
+    /// at or just before the start of the window
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl RateWindow {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Note the running total at `now`
+    pub fn record(&mut self, total: u64, now: Instant) {
+        if self.samples.back().is_some_and(|&(_, last)| last > total) {
+            // The total went backwards (a reused bar); start over
+            self.samples.clear();
+        }
+        self.samples.push_back((now, total));
+        while self.samples.len() > 2
+            && now.saturating_duration_since(self.samples[1].0) >= self.window
+        {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Units per second over the window ending at `now`; decays while nothing is recorded
+    pub fn rate(&self, now: Instant) -> f64 {
+        let (Some(&(start, first)), Some(&(_, last))) = (self.samples.front(), self.samples.back())
+        else {
+            return 0.0;
+        };
+        let elapsed = now.saturating_duration_since(start).as_secs_f64();
+        if elapsed > 0.0 {
+            (last - first) as f64 / elapsed
+        } else {
+            0.0
+        }
+    }
+
+    /// Seconds until `remaining` more at the current rate
+    pub fn eta(&self, remaining: u64, now: Instant) -> Option<Duration> {
+        let rate = self.rate(now);
+        (rate > 0.0).then(|| Duration::from_secs_f64(remaining as f64 / rate))
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+}
+
+/// `line` without ANSI color codes
+pub fn strip_ansi(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // CSI sequence: ESC [ parameters final-byte
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Short duration such as `45s`, `2m 5s` or `1h 12m`
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m {}s", secs / 60, secs % 60),
+        _ => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// Bytes per server, most first: `primary: 41.2 GiB, block: 318 MiB`
+pub fn format_server_bytes(bytes: &BTreeMap<String, u64>) -> String {
+    let mut servers: Vec<(&String, &u64)> = bytes.iter().collect();
+    servers.sort_by(|a, b| b.1.cmp(a.1));
+    servers
+        .iter()
+        .map(|(server, &bytes)| format!("{}: {}", server, human_bytes(bytes as f64)))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Bytes read from servers against bytes kept:
+/// `transferred 42.1 GiB, wrote 40.8 GiB (3.1% overhead)`
+///
+/// The overhead is yEnc encoding plus whatever was thrown away: articles
+/// fetched twice, damaged or cut short.
+pub fn format_transfer(transferred: u64, written: u64) -> String {
+    let overhead = if transferred > 0 {
+        transferred.saturating_sub(written) as f64 * 100.0 / transferred as f64
+    } else {
+        0.0
+    };
+    format!(
+        "transferred {}, wrote {} ({:.1}% overhead)",
+        human_bytes(transferred as f64),
+        human_bytes(written as f64),
+        overhead
+    )
+}
+
+/// The file a download is most likely for: the largest in `dir` that isn't
+/// a PAR2, RAR, NFO or SFV file or smaller than `min_size` bytes, the first
+/// by name among equals
+pub fn main_file(dir: &Path, min_size: u64) -> Option<PathBuf> {
+    let entries = std::fs::read_dir(dir).ok()?;
+    entries
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().is_file())
+        .filter(|e| {
+            let name = e.file_name().to_string_lossy().to_lowercase();
+            !name.ends_with(".par2")
+                && !name.ends_with(".rar")
+                && !name.ends_with(".nfo")
+                && !name.ends_with(".sfv")
+        })
+        .map(|e| {
+            let size = e.metadata().map(|m| m.len()).unwrap_or(0);
+            (size, std::cmp::Reverse(e.file_name()), e.path())
+        })
+        .filter(|&(size, _, _)| size >= min_size)
+        .max()
+        .map(|(_, _, path)| path)
+}
+
+/// Receives progress from a `Downloader` and a `PostProcessor`
+///
+/// Every method has a no-op default, so an implementation only overrides what
+/// it needs. Download callbacks come from concurrent tasks.
+pub trait ProgressSink: Send + Sync {
+    /// `files` files totalling `total_bytes` (as sized in the NZB) are about to download
+    fn on_start(&self, _files: usize, _total_bytes: u64) {}
+
+    /// A file is starting to download (not called for files already complete)
+    fn on_file_start(&self, _filename: &str, _size: u64, _segments: usize) {}
+
+    /// Encoded bytes read from the server
+    fn on_bytes(&self, _bytes: u64) {}
+
+    /// Segments of a file are finished with, fetched or not
+    fn on_segments(&self, _done: &SegmentsDone<'_>) {}
+
+    fn on_file_done(&self, _result: &DownloadResult) {}
+
+    /// The download is over; `results` has every file that didn't error out
+    fn on_finish(&self, _results: &[DownloadResult]) {}
+
+    /// The download stopped before every file was done (a shutdown, or an
+    /// error that ends the whole NZB); `results` has the files it got to
+    fn on_abort(&self, results: &[DownloadResult]) {
+        self.on_finish(results);
+    }
+
+    /// A post-processing phase is starting
+    fn on_phase(&self, _phase: PostProcessPhase) {}
+
+    /// Progress within the current phase; `total` is 0 when unknown
+    fn on_phase_progress(&self, _step: PhaseStep, _done: u64, _total: u64, _message: &str) {}
+
+    /// The current phase's progress is over
+    fn on_phase_done(&self, _end: PhaseEnd) {}
+
+    /// A line for the user, such as a skipped file or a connection problem
+    fn on_message(&self, _message: &str) {}
+
+    /// The result of a finished step, such as `✓ PAR2 verified` or a skipped
+    /// archive; may carry ANSI color codes (see [`strip_ansi`])
+    fn on_status(&self, _line: &str) {}
+
+    /// Where the download in progress has got to; `None` between downloads
+    /// and for sinks that don't keep count
+    fn snapshot(&self) -> Option<DownloadSnapshot> {
+        None
+    }
+
+    /// Run `f`, which talks to the user on the terminal, with progress paused
+    fn suspend(&self, f: &mut dyn FnMut()) {
+        f()
+    }
+}
+
+/// Segments of one file that are finished with, fetched or not
+#[derive(Debug, Clone, Copy)]
+pub struct SegmentsDone<'a> {
+    pub filename: &'a str,
+    pub segments: usize,
+    /// Their size according to the NZB
+    pub declared: u64,
+    /// Encoded bytes read from the server for them; 0 when they failed
+    pub transferred: u64,
+}
+
+/// The download in progress as a [`ProgressSink`] counts it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct DownloadSnapshot {
+    pub files: usize,
+    pub files_done: usize,
+    /// Encoded bytes read so far, and in all as far as it can be told yet
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    /// Over the last [`speed_window`]
+    pub bytes_per_sec: u64,
+}
+
+/// What a post-processing phase is doing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PhaseStep {
+    /// Looking for or loading input
+    Scanning,
+    Verifying,
+    /// Verification found damaged or missing files
+    VerifyingDamaged,
+    /// Counts recovery blocks
+    Repairing,
+    Extracting,
+    /// Computing and writing new recovery data
+    Creating,
+    /// No measurable progress
+    Working,
+}
+
+/// How a phase's progress ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PhaseEnd {
+    /// There was nothing to do
+    Skipped,
+    Finished,
+    Failed,
+}
+
+/// A [`ProgressSink`] that ignores everything
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoProgress;
+
+impl ProgressSink for NoProgress {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_window_follows_recent_speed() {
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+        let mut rate = RateWindow::new(Duration::from_secs(10));
+        assert_eq!(rate.rate(start), 0.0);
+
+        // Slow start: 100 B/s for a minute, then 1000 B/s
+        for t in 0..=60 {
+            rate.record(t * 100, at(t));
+        }
+        for t in 61..=80 {
+            rate.record(6000 + (t - 60) * 1000, at(t));
+        }
+        assert_eq!(rate.rate(at(80)), 1000.0);
+        assert_eq!(rate.eta(5000, at(80)), Some(Duration::from_secs(5)));
+
+        // Stalled: the rate decays instead of freezing
+        assert!(rate.rate(at(85)) < 1000.0);
+        assert!(rate.eta(5000, at(85)).unwrap() > Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_main_file_breaks_ties_by_name() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["b.mkv", "a.mkv", "c.mkv"] {
+            std::fs::write(dir.path().join(name), [0u8; 100]).unwrap();
+        }
+        std::fs::write(dir.path().join("big.par2"), [0u8; 500]).unwrap();
+        std::fs::write(dir.path().join("small.nfo"), [0u8; 10]).unwrap();
+        assert_eq!(main_file(dir.path(), 0), Some(dir.path().join("a.mkv")));
+
+        std::fs::write(dir.path().join("d.mkv"), [0u8; 101]).unwrap();
+        assert_eq!(main_file(dir.path(), 0), Some(dir.path().join("d.mkv")));
+        // Nothing left at the minimum size
+        assert_eq!(main_file(dir.path(), 200), None);
+    }
+
+    #[test]
+    fn test_format_server_bytes() {
+        let bytes = BTreeMap::from([
+            ("block".to_string(), 318 * 1024 * 1024),
+            ("primary".to_string(), 2 * 1024 * 1024 * 1024),
+        ]);
+        assert_eq!(
+            format_server_bytes(&bytes),
+            "primary: 2 GiB, block: 318 MiB"
+        );
+        assert_eq!(format_server_bytes(&BTreeMap::new()), "");
+    }
+
+    #[test]
+    fn test_format_transfer() {
+        assert_eq!(
+            format_transfer(1000 * 1024 * 1024, 969 * 1024 * 1024),
+            "transferred 1000 MiB, wrote 969 MiB (3.1% overhead)"
+        );
+        // Nothing read: every file was already there
+        assert_eq!(
+            format_transfer(0, 2048),
+            "transferred 0 B, wrote 2 KiB (0.0% overhead)"
+        );
+    }
+
+    #[test]
+    fn test_strip_ansi() {
+        assert_eq!(
+            strip_ansi("  └─ \x1b[1;32m✓ Downloaded\x1b[0m 1 GiB"),
+            "  └─ ✓ Downloaded 1 GiB"
+        );
+    }
+}
@@ -0,0 +1,239 @@
+//! What the terminal can show: colors and non-ASCII glyphs
+//!
+//! Output is written with ANSI color codes and glyphs such as `✓`, `⚠` and
+//! `└─` already in it; [`render`] takes them back out where they'd come
+//! out as garbage. Colors are dropped for a stream that isn't a terminal,
+//! with `NO_COLOR` set, `TERM=dumb` or `--no-color`, and on Windows consoles
+//! that can't be switched to ANSI processing. Glyphs become ASCII on Windows
+//! consoles whose code page isn't UTF-8. [`detect`] decides both once, at
+//! startup; until then everything is written as is.
+
+use std::borrow::Cow;
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Where a line is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+static STDOUT_COLOR: AtomicBool = AtomicBool::new(true);
+static STDERR_COLOR: AtomicBool = AtomicBool::new(true);
+static ASCII: AtomicBool = AtomicBool::new(false);
+
+/// Decide colors and glyphs for the rest of the process; `no_color` is the
+/// `--no-color` flag
+pub fn detect(no_color: bool) {
+    let allowed = !no_color && !no_color_env() && !dumb_terminal();
+    set_color(
+        Stream::Stdout,
+        allowed && std::io::stdout().is_terminal() && console::enable_ansi(Stream::Stdout),
+    );
+    set_color(
+        Stream::Stderr,
+        allowed && std::io::stderr().is_terminal() && console::enable_ansi(Stream::Stderr),
+    );
+    set_ascii(!console::utf8());
+}
+
+/// Whether `NO_COLOR` asks for no colors (set and not empty, per no-color.org)
+pub fn no_color_env() -> bool {
+    std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty())
+}
+
+fn dumb_terminal() -> bool {
+    std::env::var_os("TERM").is_some_and(|term| term == "dumb")
+}
+
+pub fn set_color(stream: Stream, enabled: bool) {
+    match stream {
+        Stream::Stdout => STDOUT_COLOR.store(enabled, Ordering::Relaxed),
+        Stream::Stderr => STDERR_COLOR.store(enabled, Ordering::Relaxed),
+    }
+}
+
+/// Whether color codes written to `stream` are kept
+pub fn color(stream: Stream) -> bool {
+    match stream {
+        Stream::Stdout => STDOUT_COLOR.load(Ordering::Relaxed),
+        Stream::Stderr => STDERR_COLOR.load(Ordering::Relaxed),
+    }
+}
+
+/// Write ASCII in place of symbols and box drawing
+pub fn set_ascii(enabled: bool) {
+    ASCII.store(enabled, Ordering::Relaxed);
+}
+
+pub fn ascii() -> bool {
+    ASCII.load(Ordering::Relaxed)
+}
+
+/// `line` as it can be shown on `stream`
+pub fn render(line: &str, stream: Stream) -> Cow<'_, str> {
+    let mut line = Cow::Borrowed(line);
+    if !color(stream) && line.contains('\x1b') {
+        line = Cow::Owned(super::strip_ansi(&line));
+    }
+    if ascii() && !line.is_ascii() {
+        line = Cow::Owned(to_ascii(&line));
+    }
+    line
+}
+
+/// Print `line` to stdout, as it can be shown there
+pub fn print(line: &str) {
+    println!("{}", render(line, Stream::Stdout));
+}
+
+/// Print `line` to stderr, as it can be shown there
+pub fn eprint(line: &str) {
+    eprintln!("{}", render(line, Stream::Stderr));
+}
+
+/// `text` with the glyphs this crate writes spelled in ASCII; other
+/// characters are kept
+pub fn to_ascii(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '✓' => out.push('+'),
+            '✗' | '❌' | '×' => out.push('x'),
+            '⚠' => out.push('!'),
+            '└' => out.push('`'),
+            '─' | '—' => out.push('-'),
+            '│' => out.push('|'),
+            '━' => out.push('='),
+            '╸' => out.push('>'),
+            '•' => out.push('*'),
+            '·' => out.push('.'),
+            '…' => out.push_str("..."),
+            '↳' | '→' => out.push_str("->"),
+            '↷' => out.push('~'),
+            '⏳' => out.push_str(".."),
+            // Emoji used as markers, and the selector that makes one colorful
+            '📄' | '📝' | '⚙' => out.push('*'),
+            '\u{fe0f}' => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// An indicatif template without its color codes: ANSI escapes and the
+/// `.color` part of placeholders such as `{bar:40.cyan/blue}` are dropped
+pub fn plain_template(template: &str) -> String {
+    let template = super::strip_ansi(template);
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template.as_str();
+    while let Some(open) = rest.find('{') {
+        let Some(close) = rest[open..].find('}').map(|close| open + close) else {
+            break;
+        };
+        let placeholder = &rest[open..close];
+        let kept = match placeholder.find(':') {
+            Some(colon) => match placeholder[colon..].find('.') {
+                Some(dot) => placeholder[..colon + dot].trim_end_matches(':'),
+                None => placeholder,
+            },
+            None => placeholder,
+        };
+        out.push_str(&rest[..open]);
+        out.push_str(kept);
+        out.push('}');
+        rest = &rest[close + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(windows)]
+mod console {
+    use super::Stream;
+    use std::ffi::c_void;
+
+    const STD_OUTPUT_HANDLE: u32 = -11i32 as u32;
+    const STD_ERROR_HANDLE: u32 = -12i32 as u32;
+    const ENABLE_VIRTUAL_TERMINAL_PROCESSING: u32 = 0x0004;
+    const CP_UTF8: u32 = 65001;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetConsoleOutputCP() -> u32;
+        fn GetStdHandle(std_handle: u32) -> *mut c_void;
+        fn GetConsoleMode(handle: *mut c_void, mode: *mut u32) -> i32;
+        fn SetConsoleMode(handle: *mut c_void, mode: u32) -> i32;
+    }
+
+    /// Whether the console shows UTF-8 (legacy code pages such as 437 or
+    /// 1252 don't have the glyphs)
+    pub fn utf8() -> bool {
+        // SAFETY: takes no arguments and only reads console state
+        let code_page = unsafe { GetConsoleOutputCP() };
+        // 0 means no console, so output goes to a file or pipe
+        code_page == 0 || code_page == CP_UTF8
+    }
+
+    /// Turn on ANSI processing for `stream`'s console; false on consoles
+    /// older than Windows 10 that don't have it
+    pub fn enable_ansi(stream: Stream) -> bool {
+        let which = match stream {
+            Stream::Stdout => STD_OUTPUT_HANDLE,
+            Stream::Stderr => STD_ERROR_HANDLE,
+        };
+        // SAFETY: the handle comes from GetStdHandle and `mode` outlives the calls
+        unsafe {
+            let handle = GetStdHandle(which);
+            let mut mode = 0;
+            if handle.is_null() || GetConsoleMode(handle, &mut mode) == 0 {
+                return false;
+            }
+            mode & ENABLE_VIRTUAL_TERMINAL_PROCESSING != 0
+                || SetConsoleMode(handle, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING) != 0
+        }
+    }
+}
+
+#[cfg(not(windows))]
+mod console {
+    use super::Stream;
+
+    pub fn utf8() -> bool {
+        true
+    }
+
+    pub fn enable_ansi(_stream: Stream) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_ascii() {
+        assert_eq!(
+            to_ascii("  └─ ✓ Downloaded 1 GiB — ⚠ 2 files…"),
+            "  `- + Downloaded 1 GiB - ! 2 files..."
+        );
+        assert_eq!(to_ascii("⚙️  Edit"), "*  Edit");
+        // Names keep their letters
+        assert_eq!(to_ascii("Équipe ✗"), "Équipe x");
+    }
+
+    #[test]
+    fn test_plain_template() {
+        assert_eq!(
+            plain_template("[{bar:40.cyan/blue}] \x1b[1m{percent:>3}%\x1b[0m {msg}"),
+            "[{bar:40}] {percent:>3}% {msg}"
+        );
+        assert_eq!(plain_template("{spinner:.cyan} {msg}"), "{spinner} {msg}");
+        assert_eq!(
+            plain_template("{prefix} {wide_msg:.dim}"),
+            "{prefix} {wide_msg}"
+        );
+    }
+}
@@ -0,0 +1,1192 @@
+//! Progress bars and status lines on the terminal
+//!
+//! Draws a [`ProgressSink`] with indicatif, following the process-wide
+//! [`OutputMode`](super::OutputMode). Only built with the `cli` feature.
+
+use human_bytes::human_bytes;
+use indicatif::{MultiProgress, ProgressBar, ProgressState, ProgressStyle as IndicatifStyle};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::style::{self, Stream};
+use super::{
+    format_duration, is_hidden, plain_interval, speed_window, strip_ansi, DownloadSnapshot,
+    PhaseEnd, PhaseStep, ProgressSink, RateWindow, SegmentsDone,
+};
+use crate::config::ProgressLayout;
+use crate::download::{DownloadResult, Limits, PostProcessPhase};
+
+/// Template key backed by a [`RateWindow`] over the bar's position
+#[derive(Clone)]
+struct WindowedKey {
+    rate: RateWindow,
+    write: fn(&RateWindow, &ProgressState, &mut dyn std::fmt::Write),
+}
+
+impl WindowedKey {
+    fn new(write: fn(&RateWindow, &ProgressState, &mut dyn std::fmt::Write)) -> Self {
+        Self {
+            rate: RateWindow::new(speed_window()),
+            write,
+        }
+    }
+}
+
+impl indicatif::style::ProgressTracker for WindowedKey {
+    fn clone_box(&self) -> Box<dyn indicatif::style::ProgressTracker> {
+        Box::new(self.clone())
+    }
+
+    fn tick(&mut self, state: &ProgressState, now: Instant) {
+        self.rate.record(state.pos(), now);
+    }
+
+    fn reset(&mut self, _state: &ProgressState, _now: Instant) {
+        self.rate.clear();
+    }
+
+    fn write(&self, state: &ProgressState, w: &mut dyn std::fmt::Write) {
+        let mut value = String::new();
+        (self.write)(&self.rate, state, &mut value);
+        let _ = w.write_str(&style::render(&value, Stream::Stderr));
+    }
+}
+
+/// `42.10 MiB/s` with a fixed width
+fn format_speed(bytes_per_sec: f64) -> String {
+    if bytes_per_sec > 1_048_576.0 {
+        format!("{:>6.2} MiB/s", bytes_per_sec / 1_048_576.0)
+    } else if bytes_per_sec > 1024.0 {
+        format!("{:>6.2} KiB/s", bytes_per_sec / 1024.0)
+    } else {
+        format!("{:>6.0}  B/s", bytes_per_sec)
+    }
+}
+
+/// `println!` for status lines; prints nothing while progress output is hidden
+/// and drops color codes in plain mode
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if !$crate::progress::is_hidden() {
+            $crate::progress::print_status(&format!($($arg)*));
+        }
+    };
+}
+
+/// Print a status line to stdout, without color codes in plain mode or
+/// where the terminal can't show them
+pub fn print_status(line: &str) {
+    if plain_interval().is_some() {
+        style::print(&strip_ansi(line));
+    } else {
+        style::print(line);
+    }
+}
+
+/// An indicatif style from `template`, its colors and glyphs cut down to what
+/// stderr can show
+fn bar_style(template: &str) -> Result<IndicatifStyle, indicatif::style::TemplateError> {
+    let mut template = if style::color(Stream::Stderr) {
+        template.to_string()
+    } else {
+        style::plain_template(template)
+    };
+    if style::ascii() {
+        template = style::to_ascii(&template);
+    }
+    IndicatifStyle::with_template(&template)
+}
+
+/// Filled, current and empty characters of a bar
+fn bar_chars() -> &'static str {
+    if style::ascii() {
+        "==> "
+    } else {
+        "━━╸ "
+    }
+}
+
+fn spinner_ticks() -> &'static [&'static str] {
+    if style::ascii() {
+        &["-", "\\", "|", "/"]
+    } else {
+        &["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"]
+    }
+}
+
+/// Columns bars may use: stderr's width, else `$COLUMNS`, else wide enough
+/// for every part of every bar
+fn term_width() -> usize {
+    indicatif::ProgressDrawTarget::stderr()
+        .width()
+        .map(usize::from)
+        .or_else(|| std::env::var("COLUMNS").ok()?.parse().ok())
+        .unwrap_or(usize::MAX)
+}
+
+/// Widest a bar is drawn
+const MAX_BAR_WIDTH: usize = 40;
+
+/// A bar narrower than this isn't worth keeping other parts of the line for
+const MIN_BAR_WIDTH: usize = 20;
+
+/// Room left for the download line's message, the file count
+const COUNT_WIDTH: usize = 10;
+
+/// How much of the download line fits the terminal
+///
+/// A line longer than the terminal wraps, and every redraw then leaves a
+/// copy behind, so parts are dropped as the terminal narrows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DownloadFit {
+    /// Bar, percentage, bytes of total, speed, ETA and file count
+    Full,
+    /// Without the ETA
+    NoEta,
+    /// Without the ETA and the byte totals
+    NoTotals,
+    /// The bar and percentage alone
+    BarOnly,
+}
+
+impl DownloadFit {
+    /// Columns the line takes besides the bar
+    fn fixed_width(self) -> usize {
+        match self {
+            DownloadFit::Full => 57 + COUNT_WIDTH,
+            DownloadFit::NoEta => 45 + COUNT_WIDTH,
+            DownloadFit::NoTotals => 21 + COUNT_WIDTH,
+            DownloadFit::BarOnly => 7,
+        }
+    }
+
+    /// The most that fits `width` columns, and the width of its bar
+    fn choose(width: usize) -> (Self, usize) {
+        // A line that reaches the last column wraps on some terminals
+        let usable = width.saturating_sub(1);
+        for fit in [DownloadFit::Full, DownloadFit::NoEta, DownloadFit::NoTotals] {
+            let room = usable.saturating_sub(fit.fixed_width());
+            if room >= MIN_BAR_WIDTH {
+                return (fit, room.min(MAX_BAR_WIDTH));
+            }
+        }
+        let room = usable.saturating_sub(DownloadFit::BarOnly.fixed_width());
+        (DownloadFit::BarOnly, room.clamp(1, MAX_BAR_WIDTH))
+    }
+
+    /// The download template, with a bar `bar_width` columns wide
+    fn template(self, bar_width: usize) -> String {
+        let mut template = format!(
+            "[{{bar:{}.cyan/blue}}] \x1b[1m{{percent:>3}}%\x1b[0m",
+            bar_width
+        );
+        if matches!(self, DownloadFit::Full | DownloadFit::NoEta) {
+            template.push_str(" \x1b[36m{bytes:>10}\x1b[0m\x1b[90m/\x1b[0m\x1b[90m{total_bytes:<10}\x1b[0m \x1b[90m│\x1b[0m");
+        }
+        if self != DownloadFit::BarOnly {
+            template.push_str(" {bytes_per_sec}");
+        }
+        if self == DownloadFit::Full {
+            template.push_str(" \x1b[90m│\x1b[0m {eta}");
+        }
+        if self != DownloadFit::BarOnly {
+            template.push_str(" \x1b[36m{msg}\x1b[0m");
+        }
+        template
+    }
+}
+
+/// Room a post-processing line needs besides its bar and message: the
+/// brackets, a `⚠` and the percentage or `12345/12345 blocks`
+const PHASE_FIXED_WIDTH: usize = 22;
+
+/// A post-processing message (usually a file name) isn't squeezed below this
+/// to make the bar wider
+const MIN_MESSAGE_WIDTH: usize = 24;
+
+/// Bar and message widths of a post-processing line `width` columns wide
+fn phase_layout(width: usize) -> (usize, usize) {
+    let usable = width.saturating_sub(1);
+    let bar = usable
+        .saturating_sub(PHASE_FIXED_WIDTH + MIN_MESSAGE_WIDTH)
+        .clamp(10, MAX_BAR_WIDTH);
+    let message = usable.saturating_sub(PHASE_FIXED_WIDTH + bar).max(8);
+    (bar, message)
+}
+
+/// Width of the file name column of per-file bars `width` columns wide,
+/// which also hold a 20-column bar, the speed and a segment count
+fn file_name_width(width: usize) -> usize {
+    width.saturating_sub(1 + 52).clamp(12, FILE_NAME_WIDTH)
+}
+
+/// A template written for a 40-column bar, with a `width`-column one instead
+fn with_bar_width(template: &str, width: usize) -> String {
+    template.replacen("{bar:40", &format!("{{bar:{}", width), 1)
+}
+
+/// Re-apply a download bar's style when the terminal is resized, until the
+/// bar finishes or is dropped
+fn spawn_resize_watcher(bar: &ProgressBar) {
+    let bar = bar.downgrade();
+    let mut shown = DownloadFit::choose(term_width());
+    std::thread::spawn(move || loop {
+        std::thread::sleep(RESIZE_CHECK_INTERVAL);
+        let Some(bar) = bar.upgrade() else { break };
+        if bar.is_finished() {
+            break;
+        }
+        let fit = DownloadFit::choose(term_width());
+        if fit != shown {
+            apply_style(&bar, ProgressStyle::Download);
+            shown = fit;
+        }
+    });
+}
+
+/// How often [`spawn_resize_watcher`] looks at the terminal's width
+const RESIZE_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A progress bar of `len` steps (hidden while progress output is hidden or plain)
+pub fn new_bar(len: u64) -> ProgressBar {
+    if is_hidden() || plain_interval().is_some() {
+        ProgressBar::with_draw_target(Some(len), indicatif::ProgressDrawTarget::hidden())
+    } else {
+        ProgressBar::new(len)
+    }
+}
+
+/// A spinner (hidden while progress output is hidden or plain)
+pub fn new_spinner() -> ProgressBar {
+    if is_hidden() || plain_interval().is_some() {
+        ProgressBar::hidden()
+    } else {
+        ProgressBar::new_spinner()
+    }
+}
+
+/// A ticking [`ProgressStyle::Spinner`] showing `msg`
+pub fn create_spinner(msg: impl Into<std::borrow::Cow<'static, str>>) -> ProgressBar {
+    let spinner = new_spinner();
+    apply_style(&spinner, ProgressStyle::Spinner);
+    spinner.enable_steady_tick(Duration::from_millis(80));
+    spinner.set_message(msg);
+    spinner
+}
+
+/// Log a download bar's progress every `interval` until it finishes or is dropped
+fn spawn_plain_reporter(bar: &ProgressBar, interval: Duration) {
+    let bar = bar.downgrade();
+    let mut rate = RateWindow::new(speed_window().max(interval));
+    rate.record(0, Instant::now());
+    std::thread::spawn(move || loop {
+        std::thread::sleep(interval);
+        let Some(bar) = bar.upgrade() else { break };
+        if bar.is_finished() {
+            break;
+        }
+        let now = Instant::now();
+        let (done, total) = (bar.position(), bar.length().unwrap_or(0));
+        rate.record(done, now);
+        let eta = rate
+            .eta(total.saturating_sub(done), now)
+            .unwrap_or_default();
+        tracing::info!("{}", plain_line(done, total, rate.rate(now), eta));
+    });
+}
+
+/// `downloaded 1.2 GiB / 8 GiB, 42 MiB/s, eta 2m 5s`
+fn plain_line(done: u64, total: u64, per_sec: f64, eta: Duration) -> String {
+    format!(
+        "downloaded {} / {}, {}/s, eta {}",
+        human_bytes(done as f64),
+        human_bytes(total as f64),
+        human_bytes(per_sec),
+        format_duration(eta)
+    )
+}
+
+/// Progress display style
+#[derive(Debug, Clone, Copy)]
+pub enum ProgressStyle {
+    Download,
+    /// One file beneath the download bar (`--progress-per-file`)
+    FileDownload,
+    /// Scanning and loading PAR2 data
+    Par2,
+    Par2Verify,
+    /// Counts recovery blocks instead of a percentage
+    Par2Repair,
+    /// Verification found damaged or missing files
+    Par2Warning,
+    Par2Error,
+    Extract,
+    /// Indeterminate work such as connecting or deobfuscating
+    Spinner,
+}
+
+/// Create a progress bar with the specified style
+///
+/// In plain mode a download bar reports itself as periodic log lines instead.
+pub fn create_progress_bar(total: u64, style: ProgressStyle) -> ProgressBar {
+    let bar = new_bar(total);
+    apply_style(&bar, style);
+    bar.enable_steady_tick(Duration::from_millis(100));
+    match (is_hidden(), plain_interval(), style) {
+        (false, Some(interval), ProgressStyle::Download) => spawn_plain_reporter(&bar, interval),
+        (false, None, ProgressStyle::Download) => spawn_resize_watcher(&bar),
+        _ => {}
+    }
+    bar
+}
+
+/// Apply a style to an existing progress bar, fitted to the terminal's width
+pub fn apply_style(bar: &ProgressBar, style: ProgressStyle) {
+    let width = term_width();
+    let phase_bar = phase_layout(width).0;
+    match style {
+        ProgressStyle::Download => {
+            let (fit, bar_width) = DownloadFit::choose(width);
+            bar.set_style(
+                bar_style(&fit.template(bar_width))
+                    .expect("invalid download progress template")
+                    .progress_chars(bar_chars())
+                    .with_key(
+                        "eta",
+                        WindowedKey::new(|rate, state, w| {
+                            let remaining = state.len().unwrap_or(0).saturating_sub(state.pos());
+                            let eta = rate.eta(remaining, Instant::now()).unwrap_or_default();
+                            let _ = write!(w, "\x1b[33mETA {:>4.0}s\x1b[0m", eta.as_secs_f64());
+                        }),
+                    )
+                    .with_key(
+                        "bytes_per_sec",
+                        WindowedKey::new(|rate, _, w| {
+                            let _ = write!(
+                                w,
+                                "\x1b[1;32m{}\x1b[0m",
+                                format_speed(rate.rate(Instant::now()))
+                            );
+                        }),
+                    ),
+            );
+        }
+        ProgressStyle::FileDownload => {
+            bar.set_style(
+                bar_style("  {prefix} [{bar:20.cyan/blue}] {speed} \x1b[90m{msg}\x1b[0m")
+                    .expect("invalid file download progress template")
+                    .progress_chars(bar_chars())
+                    .with_key(
+                        "speed",
+                        WindowedKey::new(|rate, _, w| {
+                            let _ = write!(
+                                w,
+                                "\x1b[36m{}\x1b[0m",
+                                format_speed(rate.rate(Instant::now()))
+                            );
+                        }),
+                    ),
+            );
+        }
+        ProgressStyle::Par2 => {
+            bar.set_style(
+                bar_style(&with_bar_width(
+                    "[{bar:40.cyan/blue}] \x1b[1m{percent:>3}%\x1b[0m \x1b[36m{msg}\x1b[0m",
+                    phase_bar,
+                ))
+                .expect("invalid par2 progress template")
+                .progress_chars(bar_chars()),
+            );
+        }
+        ProgressStyle::Par2Verify => {
+            bar.set_style(
+                bar_style(&with_bar_width(
+                    "[{bar:40.yellow}] \x1b[1m{percent:>3}%\x1b[0m \x1b[33m{msg}\x1b[0m",
+                    phase_bar,
+                ))
+                .expect("invalid par2 verify progress template")
+                .progress_chars(bar_chars()),
+            );
+        }
+        ProgressStyle::Par2Repair => {
+            bar.set_style(
+                bar_style(&with_bar_width(
+                    "[{bar:40.magenta}] \x1b[1m{pos}/{len} blocks\x1b[0m \x1b[35m{msg}\x1b[0m",
+                    phase_bar,
+                ))
+                .expect("invalid par2 repair progress template")
+                .progress_chars(bar_chars()),
+            );
+        }
+        ProgressStyle::Par2Warning => {
+            bar.set_style(
+                bar_style(&with_bar_width(
+                    "\x1b[33m⚠\x1b[0m [{bar:40.yellow/red}] \x1b[1m{percent:>3}%\x1b[0m \x1b[33m{msg}\x1b[0m",
+                    phase_bar,
+                ))
+                .expect("invalid par2 warning progress template")
+                .progress_chars(bar_chars()),
+            );
+        }
+        ProgressStyle::Par2Error => {
+            bar.set_style(
+                bar_style(&with_bar_width(
+                    "\x1b[31m✗\x1b[0m [{bar:40.red}] \x1b[1m{percent:>3}%\x1b[0m \x1b[31m{msg}\x1b[0m",
+                    phase_bar,
+                ))
+                .expect("invalid par2 error progress template")
+                .progress_chars(bar_chars()),
+            );
+        }
+        ProgressStyle::Extract => {
+            bar.set_style(
+                bar_style(&with_bar_width(
+                    "[{bar:40.green}] \x1b[1m{percent:>3}%\x1b[0m \x1b[32m{msg}\x1b[0m",
+                    phase_bar,
+                ))
+                .expect("invalid extract progress template")
+                .progress_chars(bar_chars()),
+            );
+        }
+        ProgressStyle::Spinner => {
+            bar.set_style(
+                bar_style("{spinner:.cyan} {msg}")
+                    .expect("invalid spinner template")
+                    .tick_strings(spinner_ticks()),
+            );
+        }
+    }
+}
+
+/// Move a download bar on by a finished batch
+///
+/// Bars count encoded bytes: `transferred` is what was read from the socket for
+/// segments the NZB sizes at `declared` bytes. Only transferred bytes advance
+/// the position, so speed and ETA reflect real traffic; the length takes up
+/// the difference, so failed or skipped segments (nothing transferred) still
+/// bring the bar to 100% without faking speed.
+pub fn advance(bar: &ProgressBar, transferred: u64, declared: u64) {
+    if transferred > declared {
+        bar.inc_length(transferred - declared);
+    } else {
+        bar.dec_length(declared - transferred);
+    }
+    bar.inc(transferred);
+}
+
+/// Widest the file name column of per-file bars gets
+const FILE_NAME_WIDTH: usize = 32;
+
+/// Shortest time between two redraws of the download bars
+///
+/// With dozens of connections batches finish far more often than a terminal
+/// (least of all one over SSH) needs redrawing; what they count in between is
+/// added up and drawn in one go.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Segments counted but not drawn yet
+#[derive(Debug, Default, Clone, Copy)]
+struct Pending {
+    transferred: u64,
+    declared: u64,
+    segments: usize,
+}
+
+impl Pending {
+    fn add(&mut self, transferred: u64, declared: u64, segments: usize) {
+        self.transferred += transferred;
+        self.declared += declared;
+        self.segments += segments;
+    }
+
+    /// What was counted, leaving nothing; `None` when nothing was
+    fn take(&mut self) -> Option<Self> {
+        Some(std::mem::take(self)).filter(|p| p.segments > 0)
+    }
+}
+
+/// Per-file bars beneath the download bar
+///
+/// Bars are recycled as files finish, so there are never more than the
+/// number of files downloading at once.
+#[derive(Clone)]
+pub struct FileBars {
+    multi: MultiProgress,
+    idle: Arc<Mutex<Vec<ProgressBar>>>,
+}
+
+impl FileBars {
+    /// Draw `aggregate` with per-file bars beneath it; `None` when bars aren't drawn
+    pub fn new(aggregate: &ProgressBar) -> Option<Self> {
+        if is_hidden() || plain_interval().is_some() {
+            return None;
+        }
+        let multi = MultiProgress::new();
+        multi.add(aggregate.clone());
+        Some(Self {
+            multi,
+            idle: Arc::new(Mutex::new(Vec::new())),
+        })
+    }
+
+    /// Show a bar for a file of `size` bytes in `segments` segments
+    pub fn start(&self, filename: &str, size: u64, segments: usize) -> FileBar {
+        let idle = self.idle.lock().ok().and_then(|mut idle| idle.pop());
+        let bar = match idle {
+            Some(bar) => {
+                bar.reset();
+                bar.set_length(size);
+                bar
+            }
+            None => {
+                let bar = self.multi.add(ProgressBar::new(size));
+                apply_style(&bar, ProgressStyle::FileDownload);
+                bar.enable_steady_tick(Duration::from_millis(100));
+                bar
+            }
+        };
+        bar.set_prefix(fit_name(filename, file_name_width(term_width())));
+        bar.set_message(format!("0/{} seg", segments));
+        FileBar {
+            bar,
+            segments,
+            done: 0,
+            pending: Pending::default(),
+            idle: self.idle.clone(),
+        }
+    }
+}
+
+/// A file's bar; cleared and handed back for reuse when dropped
+pub struct FileBar {
+    bar: ProgressBar,
+    segments: usize,
+    done: usize,
+    pending: Pending,
+    idle: Arc<Mutex<Vec<ProgressBar>>>,
+}
+
+impl FileBar {
+    /// Count `segments` more segments (fetched or not), drawn at the next
+    /// [`FileBar::redraw`]
+    pub fn inc(&mut self, transferred: u64, declared: u64, segments: usize) {
+        self.done += segments;
+        self.pending.add(transferred, declared, segments);
+    }
+
+    /// Draw the segments counted since the last redraw; see [`advance`]
+    pub fn redraw(&mut self) {
+        if let Some(pending) = self.pending.take() {
+            advance(&self.bar, pending.transferred, pending.declared);
+            self.bar
+                .set_message(format!("{}/{} seg", self.done, self.segments));
+        }
+    }
+
+    /// Print the file's one-line result above the bars
+    pub fn finish(&self, size: u64, segments_failed: usize) {
+        let name = self.bar.prefix();
+        let line = if segments_failed == 0 {
+            format!(
+                "  \x1b[32m✓\x1b[0m {} {} in {}",
+                name,
+                human_bytes(size as f64),
+                format_duration(self.bar.elapsed())
+            )
+        } else {
+            format!(
+                "  \x1b[33m!\x1b[0m {} {}/{} segments missing",
+                name, segments_failed, self.segments
+            )
+        };
+        self.bar.println(style::render(&line, Stream::Stderr));
+    }
+}
+
+impl Drop for FileBar {
+    fn drop(&mut self) {
+        self.bar.finish_and_clear();
+        if let Ok(mut idle) = self.idle.lock() {
+            idle.push(self.bar.clone());
+        }
+    }
+}
+
+/// `name` padded or shortened to exactly `width` characters
+///
+/// Long names keep their start and their extension (including volume
+/// numbers such as `.part01.rar`), with `…` in between.
+pub fn fit_name(name: &str, width: usize) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    if chars.len() <= width {
+        return format!("{:<width$}", name, width = width);
+    }
+    // Keep up to two extensions, but never more than half the width
+    let tail_len = chars
+        .iter()
+        .rev()
+        .enumerate()
+        .filter(|(_, c)| **c == '.')
+        .map(|(i, _)| i + 1)
+        .take(2)
+        .filter(|&len| len <= width / 2)
+        .last()
+        .unwrap_or(width / 3);
+    let head_len = width - 1 - tail_len;
+    let head: String = chars[..head_len].iter().collect();
+    let tail: String = chars[chars.len() - tail_len..].iter().collect();
+    format!("{}…{}", head, tail)
+}
+
+/// `text` shortened like [`fit_name`] when it's longer than `width`
+/// characters, and left as it is otherwise
+pub fn shorten_middle(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        text.to_string()
+    } else if width == 0 {
+        String::new()
+    } else {
+        fit_name(text, width)
+    }
+}
+
+/// Progress bars on the terminal, following the process-wide
+/// [`OutputMode`](super::OutputMode)
+pub struct TerminalProgress {
+    layout: ProgressLayout,
+    limits: Option<Limits>,
+    download: Mutex<Option<DownloadBars>>,
+    phase: Mutex<Option<PhaseBar>>,
+}
+
+/// Bars of the download in progress
+struct DownloadBars {
+    bar: ProgressBar,
+    file_bars: Option<FileBars>,
+    active: HashMap<String, FileBar>,
+    files: usize,
+    files_done: usize,
+    pending: Pending,
+    /// The bar's message, redrawn when the file count or limits change
+    message: String,
+    last_redraw: Instant,
+    /// Speed as of the redraws, for [`ProgressSink::snapshot`]
+    rate: RateWindow,
+}
+
+/// Bar of the post-processing phase in progress
+struct PhaseBar {
+    phase: Option<PostProcessPhase>,
+    bar: Option<(ProgressBar, PhaseStep)>,
+}
+
+impl TerminalProgress {
+    pub fn new(layout: ProgressLayout) -> Self {
+        Self {
+            layout,
+            limits: None,
+            download: Mutex::new(None),
+            phase: Mutex::new(None),
+        }
+    }
+
+    /// Show these limits next to the file count, as they change
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = Some(limits);
+        self
+    }
+
+    /// Bar message: file count plus any active limits
+    fn file_count(&self, done: usize, total: usize) -> String {
+        match self.limits.as_ref().and_then(Limits::status) {
+            Some(status) => format!("({}/{}) · {}", done, total, status),
+            None => format!("({}/{})", done, total),
+        }
+    }
+
+    /// Draw what the download bars counted since they were last drawn
+    fn redraw(&self, download: &mut DownloadBars) {
+        if let Some(pending) = download.pending.take() {
+            advance(&download.bar, pending.transferred, pending.declared);
+        }
+        for bar in download.active.values_mut() {
+            bar.redraw();
+        }
+        let message = self.file_count(download.files_done, download.files);
+        if download.message != message {
+            download.bar.set_message(message.clone());
+            download.message = message;
+        }
+        download.last_redraw = Instant::now();
+        download
+            .rate
+            .record(download.bar.position(), download.last_redraw);
+    }
+
+    fn phase_style(phase: Option<PostProcessPhase>, step: PhaseStep) -> ProgressStyle {
+        match step {
+            PhaseStep::Scanning if phase == Some(PostProcessPhase::Extract) => {
+                ProgressStyle::Extract
+            }
+            PhaseStep::Scanning => ProgressStyle::Par2,
+            PhaseStep::Verifying => ProgressStyle::Par2Verify,
+            PhaseStep::VerifyingDamaged => ProgressStyle::Par2Warning,
+            PhaseStep::Repairing => ProgressStyle::Par2Repair,
+            PhaseStep::Extracting => ProgressStyle::Extract,
+            PhaseStep::Creating => ProgressStyle::Par2,
+            PhaseStep::Working => ProgressStyle::Spinner,
+        }
+    }
+}
+
+impl ProgressSink for TerminalProgress {
+    fn on_start(&self, files: usize, total_bytes: u64) {
+        let bar = create_progress_bar(total_bytes, ProgressStyle::Download);
+        let message = self.file_count(0, files);
+        bar.set_message(message.clone());
+        let file_bars = match self.layout {
+            ProgressLayout::PerFile => FileBars::new(&bar),
+            ProgressLayout::Aggregate => None,
+        };
+        if let Ok(mut download) = self.download.lock() {
+            *download = Some(DownloadBars {
+                bar,
+                file_bars,
+                active: HashMap::new(),
+                files,
+                files_done: 0,
+                pending: Pending::default(),
+                message,
+                last_redraw: Instant::now(),
+                rate: RateWindow::new(speed_window()),
+            });
+        }
+    }
+
+    fn on_file_start(&self, filename: &str, size: u64, segments: usize) {
+        if let Ok(mut download) = self.download.lock() {
+            if let Some(download) = download.as_mut() {
+                if let Some(file_bars) = &download.file_bars {
+                    let bar = file_bars.start(filename, size, segments);
+                    download.active.insert(filename.to_string(), bar);
+                }
+            }
+        }
+    }
+
+    fn on_segments(&self, done: &SegmentsDone<'_>) {
+        if let Ok(mut download) = self.download.lock() {
+            if let Some(download) = download.as_mut() {
+                download
+                    .pending
+                    .add(done.transferred, done.declared, done.segments);
+                if let Some(bar) = download.active.get_mut(done.filename) {
+                    bar.inc(done.transferred, done.declared, done.segments);
+                }
+                if download.last_redraw.elapsed() >= REDRAW_INTERVAL {
+                    self.redraw(download);
+                }
+            }
+        }
+    }
+
+    fn on_file_done(&self, result: &DownloadResult) {
+        if let Ok(mut download) = self.download.lock() {
+            if let Some(download) = download.as_mut() {
+                if let Some(mut bar) = download.active.remove(&result.filename) {
+                    bar.redraw();
+                    bar.finish(result.size, result.segments_failed);
+                }
+                download.files_done += 1;
+                if download.files_done == download.files
+                    || download.last_redraw.elapsed() >= REDRAW_INTERVAL
+                {
+                    self.redraw(download);
+                }
+            }
+        }
+    }
+
+    fn on_finish(&self, results: &[DownloadResult]) {
+        let Some(mut download) = self.download.lock().ok().and_then(|mut d| d.take()) else {
+            return;
+        };
+        self.redraw(&mut download);
+        drop(download.active);
+
+        let total_downloaded: u64 = results.iter().map(|r| r.size).sum();
+        let failed_files = results.iter().filter(|r| r.segments_failed > 0).count();
+        download
+            .bar
+            .finish_with_message(format!("({}/{})  ", download.files, download.files));
+
+        if failed_files == 0 {
+            status!(
+                "  └─ \x1b[32m✓ Downloaded {}\x1b[0m",
+                human_bytes(total_downloaded as f64)
+            );
+        } else {
+            status!(
+                "  └─ \x1b[33m! Downloaded {} ({} file{} with errors)\x1b[0m",
+                human_bytes(total_downloaded as f64),
+                failed_files,
+                if failed_files == 1 { "" } else { "s" }
+            );
+        }
+    }
+
+    fn on_abort(&self, results: &[DownloadResult]) {
+        let Some(mut download) = self.download.lock().ok().and_then(|mut d| d.take()) else {
+            return;
+        };
+        self.redraw(&mut download);
+        drop(download.active);
+
+        // Cleared rather than finished: a full bar would read as done, and
+        // the reason it stopped is printed after it
+        download.bar.finish_and_clear();
+        let total_downloaded: u64 = results.iter().map(|r| r.size).sum();
+        status!(
+            "  └─ \x1b[31m✗ Stopped after {} of {} files ({})\x1b[0m",
+            download.files_done,
+            download.files,
+            human_bytes(total_downloaded as f64)
+        );
+    }
+
+    fn on_phase(&self, phase: PostProcessPhase) {
+        if let Ok(mut current) = self.phase.lock() {
+            // A phase that never reported its end leaves nothing behind
+            if let Some((bar, _)) = current.take().and_then(|p| p.bar) {
+                bar.finish_and_clear();
+            }
+            *current = Some(PhaseBar {
+                phase: Some(phase),
+                bar: None,
+            });
+        }
+    }
+
+    fn on_phase_progress(&self, step: PhaseStep, done: u64, total: u64, message: &str) {
+        let Ok(mut current) = self.phase.lock() else {
+            return;
+        };
+        let current = current.get_or_insert(PhaseBar {
+            phase: None,
+            bar: None,
+        });
+        let style = Self::phase_style(current.phase, step);
+        match &mut current.bar {
+            Some((bar, shown)) => {
+                if *shown != step {
+                    apply_style(bar, style);
+                    *shown = step;
+                }
+            }
+            None => {
+                // An unknown total starts out empty rather than full
+                let bar = new_bar(total.max(1));
+                apply_style(&bar, style);
+                bar.enable_steady_tick(Duration::from_millis(80));
+                current.bar = Some((bar, step));
+            }
+        }
+        if let Some((bar, _)) = &current.bar {
+            if step != PhaseStep::Working && total > 0 {
+                bar.set_length(total);
+                bar.set_position(done);
+            }
+            bar.set_message(shorten_middle(message, phase_layout(term_width()).1));
+        }
+    }
+
+    fn on_phase_done(&self, end: PhaseEnd) {
+        let Some((bar, _)) = self
+            .phase
+            .lock()
+            .ok()
+            .and_then(|mut current| current.as_mut().and_then(|p| p.bar.take()))
+        else {
+            return;
+        };
+        match end {
+            PhaseEnd::Skipped => bar.finish_and_clear(),
+            PhaseEnd::Finished => {
+                if let Some(len) = bar.length() {
+                    bar.set_position(len);
+                }
+                bar.finish_with_message("  ");
+            }
+            PhaseEnd::Failed => {
+                apply_style(&bar, ProgressStyle::Par2Error);
+                bar.finish_with_message("  ");
+            }
+        }
+    }
+
+    fn on_message(&self, message: &str) {
+        if is_hidden() {
+            tracing::info!("{}", strip_ansi(message).trim());
+            return;
+        }
+        let bar = self
+            .phase
+            .lock()
+            .ok()
+            .and_then(|p| {
+                p.as_ref()
+                    .and_then(|p| p.bar.as_ref().map(|(bar, _)| bar.clone()))
+            })
+            .or_else(|| {
+                self.download
+                    .lock()
+                    .ok()
+                    .and_then(|d| d.as_ref().map(|d| d.bar.clone()))
+            });
+        match bar {
+            Some(bar) => bar.println(style::render(message, Stream::Stderr)),
+            None => print_status(message),
+        }
+    }
+
+    fn on_status(&self, line: &str) {
+        status!("{}", line);
+    }
+
+    fn snapshot(&self) -> Option<DownloadSnapshot> {
+        let download = self.download.lock().ok()?;
+        let download = download.as_ref()?;
+        // Counts not drawn yet are left for the next redraw; a quarter
+        // second behind is close enough
+        Some(DownloadSnapshot {
+            files: download.files,
+            files_done: download.files_done,
+            bytes_done: download.bar.position(),
+            bytes_total: download.bar.length().unwrap_or_default(),
+            bytes_per_sec: download.rate.rate(Instant::now()) as u64,
+        })
+    }
+
+    fn suspend(&self, f: &mut dyn FnMut()) {
+        let bar = self.phase.lock().ok().and_then(|p| {
+            p.as_ref()
+                .and_then(|p| p.bar.as_ref().map(|(bar, _)| bar.clone()))
+        });
+        match bar {
+            Some(bar) => bar.suspend(f),
+            None => f(),
+        }
+    }
+}
+
+/// Format a download summary message
+pub fn format_download_summary(
+    files_count: usize,
+    total_files: usize,
+    bytes_downloaded: u64,
+    failed_files: usize,
+) -> String {
+    if failed_files == 0 {
+        format!(
+            "({}/{})✓ Downloaded {}",
+            files_count,
+            total_files,
+            human_bytes(bytes_downloaded as f64)
+        )
+    } else {
+        format!(
+            "({}/{})! Downloaded {} ({} files with errors)",
+            files_count,
+            total_files,
+            human_bytes(bytes_downloaded as f64),
+            failed_files
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_line() {
+        assert_eq!(
+            plain_line(0, 2048, 0.0, Duration::from_secs(125)),
+            format!(
+                "downloaded {} / {}, {}/s, eta 2m 5s",
+                human_bytes(0.0),
+                human_bytes(2048.0),
+                human_bytes(0.0)
+            )
+        );
+        assert!(plain_line(1, 2, 1.0, Duration::from_secs(3700)).ends_with("eta 1h 1m"));
+    }
+
+    /// Every style must have a valid template; the exhaustive match fails to
+    /// compile when a variant is added without being listed here
+
+    #[test]
+    fn test_every_style_applies() {
+        let all = [
+            ProgressStyle::Download,
+            ProgressStyle::FileDownload,
+            ProgressStyle::Par2,
+            ProgressStyle::Par2Verify,
+            ProgressStyle::Par2Repair,
+            ProgressStyle::Par2Warning,
+            ProgressStyle::Par2Error,
+            ProgressStyle::Extract,
+            ProgressStyle::Spinner,
+        ];
+        for style in all {
+            match style {
+                ProgressStyle::Download
+                | ProgressStyle::FileDownload
+                | ProgressStyle::Par2
+                | ProgressStyle::Par2Verify
+                | ProgressStyle::Par2Repair
+                | ProgressStyle::Par2Warning
+                | ProgressStyle::Par2Error
+                | ProgressStyle::Extract
+                | ProgressStyle::Spinner => {}
+            }
+            let bar = ProgressBar::hidden();
+            apply_style(&bar, style);
+        }
+    }
+
+    #[test]
+    fn test_advance_counts_only_transferred_bytes() {
+        let bar = ProgressBar::hidden();
+        bar.set_length(300);
+        // Encoded articles slightly larger than the NZB says
+        advance(&bar, 105, 100);
+        // Failed: nothing transferred
+        advance(&bar, 0, 100);
+        assert_eq!(bar.position(), 105);
+        assert_eq!(bar.length(), Some(205));
+        advance(&bar, 98, 100);
+        assert_eq!(bar.position(), bar.length().unwrap());
+    }
+
+    #[test]
+    fn test_segments_are_drawn_coalesced() {
+        let progress = TerminalProgress::new(ProgressLayout::Aggregate);
+        progress.on_start(2, 300);
+        let segments = |transferred| SegmentsDone {
+            filename: "a.bin",
+            segments: 1,
+            declared: 100,
+            transferred,
+        };
+        let drawn = || {
+            let download = progress.download.lock().unwrap();
+            let bar = &download.as_ref().unwrap().bar;
+            (bar.position(), bar.message())
+        };
+
+        // Within the redraw interval nothing is drawn yet
+        progress.on_segments(&segments(100));
+        progress.on_segments(&segments(0));
+        assert_eq!(drawn().0, 0);
+
+        // The next batch after it draws everything counted so far
+        if let Some(download) = progress.download.lock().unwrap().as_mut() {
+            download.last_redraw -= REDRAW_INTERVAL;
+        }
+        progress.on_segments(&segments(100));
+        assert_eq!(drawn().0, 200);
+
+        // The last file always brings the count up to date
+        let result = |name: &str| DownloadResult {
+            filename: name.to_string(),
+            path: name.into(),
+            ..Default::default()
+        };
+        progress.on_file_done(&result("a.bin"));
+        assert_eq!(drawn().1, "(0/2)");
+        progress.on_file_done(&result("b.bin"));
+        assert_eq!(drawn().1, "(2/2)");
+    }
+
+    #[test]
+    fn test_download_line_fits_the_terminal() {
+        let cases = [
+            (200, DownloadFit::Full, 40),
+            (100, DownloadFit::Full, 32),
+            (80, DownloadFit::NoEta, 24),
+            (60, DownloadFit::NoTotals, 28),
+            (40, DownloadFit::BarOnly, 32),
+            (5, DownloadFit::BarOnly, 1),
+        ];
+        for (width, fit, bar) in cases {
+            assert_eq!(DownloadFit::choose(width), (fit, bar), "{} columns", width);
+        }
+        assert_eq!(DownloadFit::choose(usize::MAX), (DownloadFit::Full, 40));
+
+        // The parts left out are gone from the template, and what's left
+        // (bar and message included) fits
+        for width in [200, 100, 80, 60, 40] {
+            let (fit, bar) = DownloadFit::choose(width);
+            let template = fit.template(bar);
+            IndicatifStyle::with_template(&template).unwrap();
+            assert_eq!(template.contains("{eta}"), fit == DownloadFit::Full);
+            assert_eq!(
+                template.contains("{total_bytes"),
+                matches!(fit, DownloadFit::Full | DownloadFit::NoEta)
+            );
+            assert!(bar + fit.fixed_width() < width, "{} columns", width);
+            let plain = style::plain_template(&template);
+            assert!(
+                plain.starts_with(&format!("[{{bar:{}}}]", bar)),
+                "{}",
+                plain
+            );
+        }
+        assert_eq!(
+            style::plain_template(&DownloadFit::Full.template(40)),
+            "[{bar:40}] {percent:>3}% {bytes:>10}/{total_bytes:<10} │ {bytes_per_sec} │ {eta} {msg}"
+        );
+    }
+
+    #[test]
+    fn test_post_processing_lines_fit() {
+        assert_eq!(phase_layout(usize::MAX).0, 40);
+        assert_eq!(phase_layout(80), (33, 24));
+        assert_eq!(phase_layout(50), (10, 17));
+        assert_eq!(file_name_width(120), 32);
+        assert_eq!(file_name_width(80), 27);
+        assert_eq!(file_name_width(40), 12);
+        assert_eq!(
+            with_bar_width("[{bar:40.green}] {msg}", 12),
+            "[{bar:12.green}] {msg}"
+        );
+
+        assert_eq!(shorten_middle("short.rar", 24), "short.rar");
+        assert_eq!(
+            shorten_middle("Some.Show.S01E01.1080p.WEB.part01.rar", 24),
+            "Some.Show.S0….part01.rar"
+        );
+        assert_eq!(shorten_middle("anything", 0), "");
+    }
+
+    #[test]
+    fn test_fit_name() {
+        assert_eq!(fit_name("a.rar", 8), "a.rar   ");
+        assert_eq!(
+            fit_name("Some.Show.S01E01.1080p.WEB.part01.rar", 24),
+            "Some.Show.S0….part01.rar"
+        );
+        assert_eq!(
+            fit_name("averyveryverylongnamewithoutdots", 10),
+            "averyv…ots"
+        );
+        assert_eq!(
+            fit_name("Some.Show.S01E01.1080p.WEB.part01.rar", 24)
+                .chars()
+                .count(),
+            24
+        );
+    }
+}
@@ -0,0 +1,441 @@
+//! Download queue
+//!
+//! The NZBs of a run are written to `queue.jsonl` next to the history before
+//! the first one starts, and each entry is rewritten as it goes from pending
+//! to active to done or failed. If the process dies part way (a reboot, the
+//! OOM killer), the next run finds the unfinished entries and can feed them
+//! back through the normal download path, where complete files are kept.
+//! Finished entries are in the history by then and are pruned.
+//!
+//! Every run holds a lock on its own `queue.jsonl.<pid>.lock` for as long as
+//! it has the queue open, so the entries of a run that is still going are
+//! never mistaken for abandoned ones.
+
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use crate::download::{redact_filename_password, redact_nzb_path, NzbSource};
+use crate::error::{DlNzbError, HistoryError};
+
+type Result<T> = std::result::Result<T, DlNzbError>;
+
+/// Where a queued NZB has got to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueueStatus {
+    Pending,
+    Active,
+    Done,
+    Failed,
+}
+
+/// One queued NZB
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueEntry {
+    /// Assigned when the entry is queued; 0 for an entry that isn't stored
+    pub id: u64,
+    /// NZB to download: an absolute file path, URL or `-`
+    pub nzb: PathBuf,
+    pub status: QueueStatus,
+    /// Folder name, once the NZB has loaded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Where the files go, once the NZB has loaded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_dir: Option<PathBuf>,
+    /// NZB XML for URL and stdin NZBs, once loaded, so they can be resumed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub xml: Option<String>,
+    /// History id of the download this NZB was found in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<u64>,
+    /// How many downloads deep the NZB was found (0 for one queued directly)
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub depth: u8,
+    /// Folder of the download it was found in, which its own folder goes into
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_dir: Option<PathBuf>,
+    /// Process id of the run working through the entry
+    pub owner: u32,
+    /// RFC 3339 time the entry was queued
+    pub queued_at: String,
+}
+
+impl QueueEntry {
+    /// Pending entry for `nzb` (a path, URL or `-` as given on the command line)
+    pub fn new(nzb: &Path) -> Self {
+        // Local NZBs are stored by absolute path so another run can find them
+        let nzb = match NzbSource::from_arg(nzb) {
+            NzbSource::File(path) => std::fs::canonicalize(&path).unwrap_or(path),
+            _ => nzb.to_path_buf(),
+        };
+        Self {
+            id: 0,
+            nzb,
+            status: QueueStatus::Pending,
+            name: None,
+            output_dir: None,
+            xml: None,
+            parent: None,
+            depth: 0,
+            parent_dir: None,
+            owner: std::process::id(),
+            queued_at: chrono::Local::now().to_rfc3339(),
+        }
+    }
+
+    /// Done or failed: the history has it
+    pub fn is_finished(&self) -> bool {
+        matches!(self.status, QueueStatus::Done | QueueStatus::Failed)
+    }
+
+    /// Display name: the folder name once known, else the file name or URL
+    pub fn display_name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| {
+            self.nzb
+                .file_name()
+                .filter(|_| !NzbSource::from_arg(&self.nzb).is_remote())
+                .map(|n| redact_filename_password(&n.to_string_lossy()).into_owned())
+                .unwrap_or_else(|| redact_nzb_path(&self.nzb).display().to_string())
+        })
+    }
+}
+
+/// The queue file, open for one run
+#[derive(Debug)]
+pub struct Queue {
+    path: PathBuf,
+    owner: u32,
+    /// This run's owner lock, held until the queue is dropped
+    owner_lock: File,
+}
+
+impl Queue {
+    /// Open the queue at `path` for this process
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        Self::open_as(path.into(), std::process::id())
+    }
+
+    /// `queue.jsonl` in the standard config directory
+    pub fn open_default() -> Result<Self> {
+        let dir = dirs::config_dir().ok_or(HistoryError::NoLocation)?;
+        Self::open(dir.join("dl-nzb").join("queue.jsonl"))
+    }
+
+    fn open_as(path: PathBuf, owner: u32) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let owner_lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(owner_lock_path(&path, owner))?;
+        FileExt::try_lock_exclusive(&owner_lock)?;
+        Ok(Self {
+            path,
+            owner,
+            owner_lock,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// All entries, oldest first
+    pub fn entries(&self) -> Result<Vec<QueueEntry>> {
+        let _lock = self.lock(false)?;
+        self.read()
+    }
+
+    /// Queue NZBs for this run and return their entries
+    ///
+    /// An NZB this run already has unfinished keeps its entry, so `watch`
+    /// finding the same file again doesn't queue it twice.
+    pub fn push(&self, nzbs: &[PathBuf]) -> Result<Vec<QueueEntry>> {
+        let _lock = self.lock(true)?;
+        let mut entries = self.read()?;
+        let mut next_id = entries.iter().map(|e| e.id).max().unwrap_or(0) + 1;
+        let mut queued = Vec::with_capacity(nzbs.len());
+        for nzb in nzbs {
+            let entry = QueueEntry {
+                owner: self.owner,
+                ..QueueEntry::new(nzb)
+            };
+            let existing = entries.iter().find(|e| {
+                e.owner == self.owner
+                    && !e.is_finished()
+                    && e.nzb == entry.nzb
+                    && !matches!(NzbSource::from_arg(&e.nzb), NzbSource::Stdin)
+            });
+            if let Some(existing) = existing {
+                queued.push(existing.clone());
+                continue;
+            }
+            let entry = QueueEntry {
+                id: next_id,
+                ..entry
+            };
+            next_id += 1;
+            queued.push(entry.clone());
+            entries.push(entry);
+        }
+        self.rewrite(&entries)?;
+        Ok(queued)
+    }
+
+    /// Change one entry of the queue; an entry that is gone is left alone
+    pub fn update(&self, id: u64, change: impl FnOnce(&mut QueueEntry)) -> Result<()> {
+        let _lock = self.lock(true)?;
+        let mut entries = self.read()?;
+        if let Some(entry) = entries.iter_mut().find(|e| e.id == id) {
+            change(entry);
+            self.rewrite(&entries)?;
+        }
+        Ok(())
+    }
+
+    /// Take entry `id` off the queue unless it is being downloaded
+    ///
+    /// Returns the entry as it was, or `None` when there is no such entry;
+    /// an active one comes back still queued.
+    pub fn remove(&self, id: u64) -> Result<Option<QueueEntry>> {
+        let _lock = self.lock(true)?;
+        let mut entries = self.read()?;
+        let Some(index) = entries.iter().position(|e| e.id == id) else {
+            return Ok(None);
+        };
+        if entries[index].status == QueueStatus::Active {
+            return Ok(Some(entries[index].clone()));
+        }
+        let removed = entries.remove(index);
+        self.rewrite(&entries)?;
+        Ok(Some(removed))
+    }
+
+    /// Unfinished entries of runs that are no longer running
+    pub fn abandoned(&self) -> Result<Vec<QueueEntry>> {
+        let _lock = self.lock(false)?;
+        Ok(self
+            .read()?
+            .into_iter()
+            .filter(|e| !e.is_finished() && !self.owner_alive(e.owner))
+            .collect())
+    }
+
+    /// Take over the abandoned entries as pending entries of this run
+    pub fn adopt(&self) -> Result<Vec<QueueEntry>> {
+        let _lock = self.lock(true)?;
+        let mut entries = self.read()?;
+        let mut adopted = Vec::new();
+        for entry in &mut entries {
+            if !entry.is_finished() && !self.owner_alive(entry.owner) {
+                entry.owner = self.owner;
+                entry.status = QueueStatus::Pending;
+                adopted.push(entry.clone());
+            }
+        }
+        self.rewrite(&entries)?;
+        Ok(adopted)
+    }
+
+    /// Drop the abandoned entries; returns how many there were
+    pub fn discard_abandoned(&self) -> Result<usize> {
+        self.retain(|queue, e| e.is_finished() || queue.owner_alive(e.owner))
+    }
+
+    /// Drop finished entries; returns how many there were
+    pub fn prune(&self) -> Result<usize> {
+        self.retain(|_, e| !e.is_finished())
+    }
+
+    fn retain(&self, keep: impl Fn(&Self, &QueueEntry) -> bool) -> Result<usize> {
+        let _lock = self.lock(true)?;
+        let mut entries = self.read()?;
+        let before = entries.len();
+        entries.retain(|e| keep(self, e));
+        let removed = before - entries.len();
+        if removed > 0 {
+            self.rewrite(&entries)?;
+        }
+        Ok(removed)
+    }
+
+    /// Whether the run that owns an entry still holds its owner lock
+    fn owner_alive(&self, owner: u32) -> bool {
+        if owner == self.owner {
+            return true;
+        }
+        let path = owner_lock_path(&self.path, owner);
+        let Ok(file) = OpenOptions::new().write(true).open(&path) else {
+            return false;
+        };
+        if FileExt::try_lock_exclusive(&file).is_err() {
+            return true;
+        }
+        let _ = FileExt::unlock(&file);
+        let _ = std::fs::remove_file(&path);
+        false
+    }
+
+    /// Lock file held for the duration of a read (shared) or write (exclusive)
+    fn lock(&self, exclusive: bool) -> Result<File> {
+        let mut lock_path = self.path.clone().into_os_string();
+        lock_path.push(".lock");
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(lock_path)?;
+        if exclusive {
+            FileExt::lock_exclusive(&file)?;
+        } else {
+            FileExt::lock_shared(&file)?;
+        }
+        Ok(file)
+    }
+
+    fn read(&self) -> Result<Vec<QueueEntry>> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut entries = Vec::new();
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(&line) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => tracing::warn!(
+                    "Skipping unreadable line {} of {}: {}",
+                    number + 1,
+                    self.path.display(),
+                    e
+                ),
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Replace the file's contents atomically
+    fn rewrite(&self, entries: &[QueueEntry]) -> Result<()> {
+        let dir = self.path.parent().unwrap_or(Path::new("."));
+        let mut temp = tempfile::NamedTempFile::new_in(dir)?;
+        for entry in entries {
+            writeln!(temp, "{}", serde_json::to_string(entry)?)?;
+        }
+        temp.persist(&self.path).map_err(|e| e.error)?;
+        Ok(())
+    }
+}
+
+impl Drop for Queue {
+    fn drop(&mut self) {
+        let _ = FileExt::unlock(&self.owner_lock);
+        let _ = std::fs::remove_file(owner_lock_path(&self.path, self.owner));
+    }
+}
+
+fn is_zero(depth: &u8) -> bool {
+    *depth == 0
+}
+
+fn owner_lock_path(queue: &Path, owner: u32) -> PathBuf {
+    let mut path = queue.as_os_str().to_owned();
+    path.push(format!(".{}.lock", owner));
+    PathBuf::from(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entries_move_through_the_queue() {
+        let dir = tempfile::tempdir().unwrap();
+        let nzb = dir.path().join("show.nzb");
+        std::fs::write(&nzb, "<nzb/>").unwrap();
+        let queue = Queue::open(dir.path().join("queue.jsonl")).unwrap();
+
+        let url = PathBuf::from("https://indexer.example/getnzb/1");
+        let queued = queue.push(&[nzb.clone(), url.clone()]).unwrap();
+        assert_eq!(queued.iter().map(|e| e.id).collect::<Vec<_>>(), [1, 2]);
+        assert!(queued[0].nzb.is_absolute());
+        assert_eq!(queued[1].nzb, url);
+
+        // Found again while still unfinished: same entry
+        assert_eq!(queue.push(std::slice::from_ref(&nzb)).unwrap()[0].id, 1);
+
+        queue
+            .update(1, |e| {
+                e.status = QueueStatus::Active;
+                e.output_dir = Some(dir.path().join("show"));
+            })
+            .unwrap();
+        queue.update(2, |e| e.status = QueueStatus::Done).unwrap();
+        assert_eq!(queue.prune().unwrap(), 1);
+
+        let entries = queue.entries().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].status, QueueStatus::Active);
+        assert_eq!(entries[0].output_dir, Some(dir.path().join("show")));
+        // Nothing of a live run is abandoned, this one's included
+        assert!(queue.abandoned().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_remove_leaves_active_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let queue = Queue::open(dir.path().join("queue.jsonl")).unwrap();
+        queue
+            .push(&[PathBuf::from("-"), PathBuf::from("-")])
+            .unwrap();
+        queue.update(1, |e| e.status = QueueStatus::Active).unwrap();
+
+        let active = queue.remove(1).unwrap().unwrap();
+        assert_eq!(active.status, QueueStatus::Active);
+        assert_eq!(queue.remove(2).unwrap().unwrap().id, 2);
+        assert!(queue.remove(2).unwrap().is_none());
+        let entries = queue.entries().unwrap();
+        assert_eq!(entries.iter().map(|e| e.id).collect::<Vec<_>>(), [1]);
+    }
+
+    #[test]
+    fn test_entries_of_a_dead_run_are_adopted() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("queue.jsonl");
+        let first = Queue::open_as(path.clone(), 1).unwrap();
+        first
+            .push(&[PathBuf::from("-"), PathBuf::from("-")])
+            .unwrap();
+        first.update(1, |e| e.status = QueueStatus::Active).unwrap();
+
+        // While the first run holds its lock its entries are left alone
+        let second = Queue::open_as(path.clone(), 2).unwrap();
+        assert!(second.abandoned().unwrap().is_empty());
+        assert_eq!(second.discard_abandoned().unwrap(), 0);
+
+        drop(first);
+        let abandoned = second.abandoned().unwrap();
+        assert_eq!(abandoned.len(), 2);
+        let adopted = second.adopt().unwrap();
+        assert!(adopted
+            .iter()
+            .all(|e| e.owner == 2 && e.status == QueueStatus::Pending));
+        assert!(second.abandoned().unwrap().is_empty());
+
+        // A third run sees them as the second run's until it stops
+        let third = Queue::open_as(path, 3).unwrap();
+        assert!(third.abandoned().unwrap().is_empty());
+        drop(second);
+        assert_eq!(third.discard_abandoned().unwrap(), 2);
+        assert!(third.entries().unwrap().is_empty());
+    }
+}
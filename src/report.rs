@@ -0,0 +1,545 @@
+//! End-of-run error report
+//!
+//! Failures are collected per NZB while it downloads and post-processes and
+//! kept in its [`DownloadSummary`], so the `--json` document carries them
+//! too. [`render`] turns a run's summaries into the section printed when the
+//! run ends, and [`FailureCause`] guesses why articles didn't arrive.
+
+use human_bytes::human_bytes;
+use serde::{Deserialize, Serialize};
+
+use crate::download::{redact_filename_password, redact_nzb_path, DownloadResult, Par2Kind};
+use crate::error::{DlNzbError, ErrorClass};
+use crate::json_output::{DownloadSummary, PostProcessingResult};
+use crate::processing::ScriptOutcome;
+
+/// Files listed per error class before the rest are only counted
+const FILES_PER_CLASS: usize = 5;
+
+/// Files of at most this many segments (NFO, SFV, ...) come through a
+/// takedown along with the PAR2 files
+const SMALL_FILE_SEGMENTS: usize = 2;
+
+/// Gap in post dates between files missing articles and complete files
+/// that counts as the failures following age
+const AGE_GAP_SECONDS: u64 = 3600;
+
+/// Most likely reason an NZB's articles didn't all arrive
+///
+/// A heuristic over the per-file failure counts, so scripts can tell an NZB
+/// worth retrying from one better replaced by another indexer's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureCause {
+    /// Data files mostly gone while the PAR2 and small files came through,
+    /// the pattern a DMCA or NTD takedown leaves
+    LikelyRemoved,
+    /// Missing articles follow the newsgroup or the post date, or everything
+    /// is gone alike: not propagated yet, or past retention
+    Propagation,
+    /// Scattered missing or damaged articles and connection errors
+    TransferErrors,
+}
+
+/// Segment counts over a group of files
+#[derive(Default)]
+struct Tally {
+    total: usize,
+    missing: usize,
+}
+
+impl Tally {
+    fn add(&mut self, result: &DownloadResult) {
+        self.total += result.segments_downloaded + result.segments_failed;
+        self.missing += result.segments_missing;
+    }
+
+    /// Whether at least `percent` of the segments are missing
+    fn missing_at_least(&self, percent: usize) -> bool {
+        self.total > 0 && self.missing * 100 >= self.total * percent
+    }
+
+    fn missing_at_most(&self, percent: usize) -> bool {
+        self.missing * 100 <= self.total * percent
+    }
+}
+
+impl FailureCause {
+    /// Classify a finished download; `None` when nothing failed
+    pub fn classify(results: &[DownloadResult]) -> Option<Self> {
+        let failed: usize = results
+            .iter()
+            .map(|r| r.segments_failed + r.segments_corrupt)
+            .sum();
+        if failed == 0 {
+            return None;
+        }
+
+        let (mut all, mut data, mut support) =
+            (Tally::default(), Tally::default(), Tally::default());
+        for result in results {
+            all.add(result);
+            let small = result.segments_downloaded + result.segments_failed <= SMALL_FILE_SEGMENTS;
+            if small || Par2Kind::of(&result.filename).is_some() {
+                support.add(result);
+            } else {
+                data.add(result);
+            }
+        }
+        // Mostly 430s rather than dropped connections or bad checksums
+        if all.missing * 100 < failed * 80 {
+            return Some(Self::TransferErrors);
+        }
+
+        if data.missing_at_least(50) && support.total > 0 && support.missing_at_most(10) {
+            return Some(Self::LikelyRemoved);
+        }
+        // Gone alike, small files and PAR2 included (or nothing to compare)
+        if all.missing_at_least(50) && (support.total == 0 || support.missing_at_least(50)) {
+            return Some(Self::Propagation);
+        }
+
+        // One group has the post and another doesn't
+        let mut groups: Vec<(&str, Tally)> = Vec::new();
+        for result in results {
+            match groups.iter_mut().find(|(g, _)| *g == result.group) {
+                Some((_, tally)) => tally.add(result),
+                None => {
+                    let mut tally = Tally::default();
+                    tally.add(result);
+                    groups.push((result.group.as_str(), tally));
+                }
+            }
+        }
+        if groups.iter().any(|(_, t)| t.missing_at_least(50))
+            && groups.iter().any(|(_, t)| t.missing_at_most(10))
+        {
+            return Some(Self::Propagation);
+        }
+
+        // Every file missing articles posted well before, or well after,
+        // every complete one
+        let (gappy, whole): (Vec<&DownloadResult>, Vec<&DownloadResult>) =
+            results.iter().partition(|r| r.segments_missing > 0);
+        let dates = |files: &[&DownloadResult]| {
+            let posted = files.iter().map(|r| r.posted);
+            (posted.clone().min().unwrap_or(0), posted.max().unwrap_or(0))
+        };
+        if !gappy.is_empty() && !whole.is_empty() {
+            let ((gappy_first, gappy_last), (whole_first, whole_last)) =
+                (dates(&gappy), dates(&whole));
+            if gappy_last + AGE_GAP_SECONDS <= whole_first
+                || whole_last + AGE_GAP_SECONDS <= gappy_first
+            {
+                return Some(Self::Propagation);
+            }
+        }
+
+        Some(Self::TransferErrors)
+    }
+
+    /// Short lowercase description for messages
+    pub fn describe(self) -> &'static str {
+        match self {
+            Self::LikelyRemoved => "likely removed from server",
+            Self::Propagation => "propagation or retention issue",
+            Self::TransferErrors => "random transfer errors",
+        }
+    }
+
+    /// What to do about it
+    pub fn advice(self) -> &'static str {
+        match self {
+            Self::LikelyRemoved => "try an NZB from another indexer rather than retrying",
+            Self::Propagation => "retry later, or add a server with longer retention",
+            Self::TransferErrors => "a retry will likely fetch the rest",
+        }
+    }
+}
+
+/// Something that went wrong with an NZB or one of its files
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Failure {
+    /// File it concerns; `None` for the NZB as a whole
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    pub class: ErrorClass,
+    pub message: String,
+    /// RFC 3339 time it was recorded
+    pub time: String,
+}
+
+impl Failure {
+    pub fn new(file: Option<String>, class: ErrorClass, message: impl Into<String>) -> Self {
+        Self {
+            file,
+            class,
+            message: message.into(),
+            time: chrono::Local::now().to_rfc3339(),
+        }
+    }
+
+    pub fn from_error(file: Option<String>, error: &DlNzbError) -> Self {
+        Self::new(file, error.class(), error.to_string())
+    }
+
+    /// Failures shown by a finished download's files and post-processing
+    pub fn of_download(
+        results: &[DownloadResult],
+        post_processing: &PostProcessingResult,
+        script: Option<&ScriptOutcome>,
+    ) -> Vec<Self> {
+        let mut failures = Vec::new();
+        for result in results {
+            let total = result.segments_downloaded + result.segments_failed;
+            let file = || Some(result.filename.clone());
+            let lost = result.segments_failed - result.segments_missing;
+            if result.segments_missing > 0 {
+                failures.push(Self::new(
+                    file(),
+                    ErrorClass::MissingArticles,
+                    format!("{} of {} segments missing", result.segments_missing, total),
+                ));
+            }
+            if lost > 0 {
+                failures.push(Self::new(
+                    file(),
+                    ErrorClass::Connection,
+                    format!("{} of {} segments lost to connection errors", lost, total),
+                ));
+            }
+            if result.segments_corrupt > 0 {
+                failures.push(Self::new(
+                    file(),
+                    ErrorClass::Corrupt,
+                    format!(
+                        "{} of {} segments failed their checksum",
+                        result.segments_corrupt, total
+                    ),
+                ));
+            }
+        }
+
+        let name = |path: &std::path::Path| {
+            path.file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .or_else(|| Some(path.display().to_string()))
+        };
+        let post = ErrorClass::PostProcessing;
+        if let Some(reason) = &post_processing.par2_error {
+            failures.push(Self::new(
+                None,
+                post,
+                format!("PAR2 repair failed: {}", reason),
+            ));
+        }
+        for archive in &post_processing.extract_failed {
+            failures.push(Self::new(name(archive), post, "extraction failed"));
+        }
+        for archive in &post_processing.password_required {
+            failures.push(Self::new(
+                name(archive),
+                post,
+                "not extracted: password required",
+            ));
+        }
+        for shortfall in &post_processing.space_shortfalls {
+            failures.push(Self::new(
+                name(&shortfall.archive),
+                ErrorClass::Disk,
+                format!(
+                    "not extracted: {} more free space needed",
+                    human_bytes(shortfall.missing() as f64)
+                ),
+            ));
+        }
+        for check in post_processing.failed_media() {
+            failures.push(Self::new(
+                name(&check.path),
+                post,
+                check
+                    .reason
+                    .as_deref()
+                    .unwrap_or("media verification failed"),
+            ));
+        }
+        if let Some(reason) = &post_processing.par2_create_error {
+            failures.push(Self::new(
+                None,
+                post,
+                format!("PAR2 creation failed: {}", reason),
+            ));
+        }
+        if let Some(outcome) = script.filter(|o| !o.success()) {
+            failures.push(Self::new(
+                None,
+                post,
+                format!("script {} {}", outcome.script.display(), outcome.status()),
+            ));
+        }
+        failures
+    }
+}
+
+/// The end-of-run report: a section per NZB that had failures, empty when
+/// none did
+pub fn render(nzbs: &[DownloadSummary]) -> Vec<String> {
+    let troubled: Vec<&DownloadSummary> = nzbs.iter().filter(|n| !n.failures.is_empty()).collect();
+    if troubled.is_empty() {
+        return Vec::new();
+    }
+
+    let mut lines = vec![format!(
+        "Problems in {} of {} NZB{}:",
+        troubled.len(),
+        nzbs.len(),
+        if nzbs.len() == 1 { "" } else { "s" }
+    )];
+    for nzb in troubled {
+        let name = nzb
+            .nzb
+            .file_name()
+            .map(|n| redact_filename_password(&n.to_string_lossy()).into_owned())
+            .unwrap_or_else(|| redact_nzb_path(&nzb.nzb).display().to_string());
+        let salvage = if nzb.salvaged {
+            ", repaired by PAR2"
+        } else if nzb.files.iter().any(|f| f.segments_failed > 0) {
+            ", not repaired"
+        } else {
+            ""
+        };
+        let cause = nzb
+            .failure_cause
+            .map(|cause| format!(" ({})", cause.describe()))
+            .unwrap_or_default();
+        lines.push(format!(
+            "  {}: {}{}{}",
+            name,
+            nzb.status.describe(),
+            salvage,
+            cause
+        ));
+
+        let mut classes: Vec<ErrorClass> = nzb.failures.iter().map(|f| f.class).collect();
+        classes.sort();
+        classes.dedup();
+        for class in classes {
+            lines.push(format!("    {}:", class.describe()));
+            let failures: Vec<&Failure> =
+                nzb.failures.iter().filter(|f| f.class == class).collect();
+            for failure in failures.iter().take(FILES_PER_CLASS) {
+                lines.push(match &failure.file {
+                    Some(file) => format!("      {}: {}", file, failure.message),
+                    None => format!("      {}", failure.message),
+                });
+            }
+            if failures.len() > FILES_PER_CLASS {
+                lines.push(format!(
+                    "      … and {} more",
+                    failures.len() - FILES_PER_CLASS
+                ));
+            }
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::NntpError;
+    use crate::json_output::RunStatus;
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    fn result(name: &str, failed: usize, missing: usize, corrupt: usize) -> DownloadResult {
+        placed(name, failed, missing, corrupt, "alt.test", 0)
+    }
+
+    /// A ten-segment file posted to `group` at `posted`
+    fn placed(
+        name: &str,
+        failed: usize,
+        missing: usize,
+        corrupt: usize,
+        group: &str,
+        posted: u64,
+    ) -> DownloadResult {
+        DownloadResult {
+            filename: name.to_string(),
+            path: PathBuf::from(name),
+            size: 100,
+            segments_downloaded: 10 - failed,
+            segments_failed: failed,
+            segments_missing: missing,
+            segments_corrupt: corrupt,
+            group: group.to_string(),
+            posted,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_report_groups_failures_per_nzb() {
+        let results = [
+            result("a.r00", 3, 2, 0),
+            result("a.r01", 0, 0, 1),
+            result("a.r02", 0, 0, 0),
+        ];
+        let post_processing = PostProcessingResult {
+            par2_repaired: true,
+            ..Default::default()
+        };
+        let repaired = DownloadSummary::new(
+            Path::new("show.nzb"),
+            Path::new("out"),
+            RunStatus::Incomplete,
+            &results,
+            Duration::ZERO,
+            post_processing,
+            None,
+        )
+        .with_failures([Failure::from_error(
+            Some("a.r03".to_string()),
+            &NntpError::Timeout { seconds: 30 }.into(),
+        )]);
+        assert!(repaired.salvaged);
+
+        let error = NntpError::AuthRequired.into();
+        let aborted = DownloadSummary::failed(Path::new("other.nzb"), None, &error);
+        let clean = DownloadSummary::new(
+            Path::new("clean.nzb"),
+            Path::new("out"),
+            RunStatus::Success,
+            &results[2..],
+            Duration::ZERO,
+            PostProcessingResult::default(),
+            None,
+        );
+
+        let lines = render(&[repaired, aborted, clean]);
+        assert_eq!(lines[0], "Problems in 2 of 3 NZBs:");
+        assert_eq!(
+            lines[1],
+            "  show.nzb: incomplete, repaired by PAR2 (random transfer errors)"
+        );
+        assert_eq!(
+            lines[2..7],
+            [
+                "    missing articles:",
+                "      a.r00: 2 of 10 segments missing",
+                "    corrupt articles:",
+                "      a.r01: 1 of 10 segments failed their checksum",
+                "    connection:",
+            ]
+        );
+        assert_eq!(
+            lines[7],
+            "      a.r00: 1 of 10 segments lost to connection errors"
+        );
+        assert!(lines[8].starts_with("      a.r03: "));
+        assert_eq!(lines[9], "  other.nzb: configuration error");
+        assert_eq!(lines[10], "    authentication:");
+        assert_eq!(lines.len(), 12);
+
+        assert!(render(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_failures_serialize_with_class_and_time() {
+        let failure = Failure::new(None, ErrorClass::MissingArticles, "gone");
+        let json = serde_json::to_value(&failure).unwrap();
+        assert_eq!(json["class"], "missing_articles");
+        assert!(json.get("file").is_none());
+        assert!(chrono::DateTime::parse_from_rfc3339(json["time"].as_str().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_failure_cause_takedown() {
+        // RARs mostly 430 while the PAR2 and NFO came through
+        let mut results = vec![
+            result("show.r00", 9, 9, 0),
+            result("show.r01", 8, 8, 0),
+            result("show.r02", 10, 10, 0),
+            result("show.par2", 0, 0, 0),
+            result("show.vol00+01.par2", 0, 0, 0),
+        ];
+        let mut nfo = result("show.nfo", 0, 0, 0);
+        nfo.segments_downloaded = 1;
+        results.push(nfo);
+        assert_eq!(
+            FailureCause::classify(&results),
+            Some(FailureCause::LikelyRemoved)
+        );
+
+        // Without anything that survived there's no telling it from expiry
+        assert_eq!(
+            FailureCause::classify(&results[..3]),
+            Some(FailureCause::Propagation)
+        );
+        assert_eq!(FailureCause::classify(&results[3..]), None);
+    }
+
+    #[test]
+    fn test_failure_cause_propagation() {
+        // Everything expired alike, PAR2 included
+        let expired = [
+            result("show.r00", 10, 10, 0),
+            result("show.r01", 9, 9, 0),
+            result("show.vol00+01.par2", 8, 8, 0),
+        ];
+        assert_eq!(
+            FailureCause::classify(&expired),
+            Some(FailureCause::Propagation)
+        );
+
+        // Missing on one group, there on another
+        let groups = [
+            placed("show.r00", 8, 8, 0, "alt.binaries.a", 0),
+            placed("show.r01", 7, 7, 0, "alt.binaries.a", 0),
+            placed("show.r02", 0, 0, 0, "alt.binaries.b", 0),
+            placed("show.r03", 0, 0, 0, "alt.binaries.b", 0),
+            placed("show.par2", 1, 1, 0, "alt.binaries.b", 0),
+        ];
+        assert_eq!(
+            FailureCause::classify(&groups),
+            Some(FailureCause::Propagation)
+        );
+
+        // The newest files haven't arrived yet
+        let fresh = [
+            placed("show.r00", 0, 0, 0, "alt.test", 1000),
+            placed("show.r01", 0, 0, 0, "alt.test", 1000),
+            placed("show.r02", 3, 3, 0, "alt.test", 9000),
+            placed("show.par2", 0, 0, 0, "alt.test", 1000),
+        ];
+        assert_eq!(
+            FailureCause::classify(&fresh),
+            Some(FailureCause::Propagation)
+        );
+    }
+
+    #[test]
+    fn test_failure_cause_transfer_errors() {
+        // A few scattered 430s
+        let scattered = [
+            result("show.r00", 1, 1, 0),
+            result("show.r01", 0, 0, 0),
+            result("show.r02", 2, 2, 0),
+            result("show.par2", 0, 0, 0),
+        ];
+        assert_eq!(
+            FailureCause::classify(&scattered),
+            Some(FailureCause::TransferErrors)
+        );
+
+        // Mostly dropped connections and bad checksums, not 430s
+        let flaky = [result("show.r00", 6, 1, 0), result("show.r01", 0, 0, 4)];
+        assert_eq!(
+            FailureCause::classify(&flaky),
+            Some(FailureCause::TransferErrors)
+        );
+        let json = serde_json::to_value(FailureCause::LikelyRemoved).unwrap();
+        assert_eq!(json, "likely_removed");
+    }
+}
@@ -0,0 +1,234 @@
+//! Retry policy for transient failures
+//!
+//! Which errors are worth another attempt is decided by `is_retryable()` on
+//! the error types in [`crate::error`]; this module decides how many attempts
+//! there are and how long to wait between them.
+
+use std::collections::hash_map::RandomState;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, Instant};
+
+use crate::config::UsenetConfig;
+
+/// Longest wait between two attempts unless a policy says otherwise
+const DEFAULT_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// How often to retry and how long to wait in between
+///
+/// The wait doubles after every attempt, starting at `base_delay` and capped
+/// at `max_delay`, and is spread by up to `jitter` (a fraction) either way so
+/// that connections failing together don't retry together.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt
+    pub attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub jitter: f64,
+    /// Stop retrying once this much time has passed since the first attempt
+    pub deadline: Option<Duration>,
+}
+
+impl RetryPolicy {
+    pub fn new(attempts: u32, base_delay: Duration) -> Self {
+        Self {
+            attempts,
+            base_delay,
+            max_delay: DEFAULT_MAX_DELAY,
+            jitter: 0.2,
+            deadline: None,
+        }
+    }
+
+    /// `retry_attempts` and `retry_delay` of a server
+    pub fn from_config(config: &UsenetConfig) -> Self {
+        Self::new(
+            config.retry_attempts.into(),
+            Duration::from_millis(config.retry_delay),
+        )
+    }
+
+    /// Never retry
+    pub fn none() -> Self {
+        Self::new(0, Duration::ZERO)
+    }
+
+    pub fn with_max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Keep retrying (regardless of `attempts`) until `deadline` has passed
+    pub fn until(mut self, deadline: Duration) -> Self {
+        self.attempts = u32::MAX;
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Wait before retry number `retry` (0 for the first retry)
+    pub fn delay(&self, retry: u32) -> Duration {
+        let delay = self
+            .base_delay
+            .saturating_mul(1 << retry.min(16))
+            .min(self.max_delay);
+        if self.jitter == 0.0 {
+            return delay;
+        }
+        // Uniform in [-jitter, +jitter]
+        let unit = random_u64() as f64 / u64::MAX as f64;
+        delay.mul_f64(1.0 + self.jitter * (2.0 * unit - 1.0))
+    }
+
+    fn gives_up(&self, retry: u32, started: Instant) -> bool {
+        retry >= self.attempts || self.deadline.is_some_and(|d| started.elapsed() >= d)
+    }
+}
+
+/// Run `attempt` until it succeeds, fails with an error `retryable` rejects,
+/// or `policy` runs out of retries; the last error is returned
+///
+/// `attempt` is passed the number of the attempt, starting at 0.
+pub async fn retry_with_backoff<T, E, F, Fut>(
+    policy: &RetryPolicy,
+    retryable: impl Fn(&E) -> bool,
+    mut attempt: F,
+) -> Result<T, E>
+where
+    F: FnMut(u32) -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let started = Instant::now();
+    let mut retry = 0;
+    loop {
+        match attempt(retry).await {
+            Ok(value) => return Ok(value),
+            Err(e) if !retryable(&e) || policy.gives_up(retry, started) => return Err(e),
+            Err(_) => {
+                tokio::time::sleep(policy.delay(retry)).await;
+                retry += 1;
+            }
+        }
+    }
+}
+
+/// Doubling delay between attempts to reach a server that is down
+///
+/// Unlike [`RetryPolicy`] this never gives up; `watch` uses it to wait out
+/// outages of any length.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    min: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    pub fn new(min: Duration, max: Duration) -> Self {
+        Self {
+            min,
+            max,
+            current: min,
+        }
+    }
+
+    /// Delay before the next attempt; each call doubles the following one
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = (self.current * 2).min(self.max);
+        delay
+    }
+
+    /// Back to the shortest delay after a success
+    pub fn reset(&mut self) {
+        self.current = self.min;
+    }
+}
+
+/// A fresh random number from the standard library's per-process hash keys
+fn random_u64() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn test_delay_doubles_with_jitter_bounds() {
+        let policy = RetryPolicy::new(5, Duration::from_millis(100))
+            .with_max_delay(Duration::from_millis(350))
+            .with_jitter(0.0);
+        let delays: Vec<u128> = (0..4).map(|r| policy.delay(r).as_millis()).collect();
+        assert_eq!(delays, [100, 200, 350, 350]);
+
+        let jittered = policy.with_jitter(0.5);
+        for _ in 0..50 {
+            let delay = jittered.delay(0).as_millis();
+            assert!((50..=150).contains(&delay), "{}", delay);
+        }
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_max() {
+        let mut backoff = Backoff::new(Duration::from_secs(5), Duration::from_secs(18));
+        let delays: Vec<u64> = (0..4).map(|_| backoff.next_delay().as_secs()).collect();
+        assert_eq!(delays, [5, 10, 18, 18]);
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_retry_stops_on_fatal_errors_and_exhaustion() {
+        let policy = RetryPolicy::new(2, Duration::from_millis(10));
+        let calls = Cell::new(0);
+
+        // Retryable errors: one attempt plus two retries
+        let result: Result<(), &str> = retry_with_backoff(
+            &policy,
+            |_| true,
+            |_| {
+                calls.set(calls.get() + 1);
+                async { Err("timeout") }
+            },
+        )
+        .await;
+        assert_eq!(result, Err("timeout"));
+        assert_eq!(calls.get(), 3);
+
+        // A fatal error ends it at once
+        calls.set(0);
+        let result: Result<(), &str> = retry_with_backoff(
+            &policy,
+            |e| *e != "430",
+            |_| {
+                calls.set(calls.get() + 1);
+                async { Err("430") }
+            },
+        )
+        .await;
+        assert_eq!(result, Err("430"));
+        assert_eq!(calls.get(), 1);
+
+        // Success on the second attempt
+        let result = retry_with_backoff(
+            &policy,
+            |_| true,
+            |attempt| async move {
+                if attempt == 0 {
+                    Err("reset")
+                } else {
+                    Ok(attempt)
+                }
+            },
+        )
+        .await;
+        assert_eq!(result, Ok(1));
+    }
+}
@@ -0,0 +1,89 @@
+//! Stopping a run part way, on SIGTERM or Ctrl-C
+//!
+//! A [`Shutdown`] is shared by everything a run starts. Once it trips, no
+//! NZB, file or batch of articles starts, and batches already fetching get
+//! [`DRAIN_TIMEOUT`] to finish before they're abandoned. The downloader then
+//! flushes and syncs the files it was writing, records the segment sizes of
+//! the finished ones and lists the rest in `.dlnzb-failed`, so the next run
+//! keeps the former and fetches the latter again, and the NZB stays in the
+//! queue for `--resume-queue`. `watch` and its API stop taking new NZBs
+//! meanwhile.
+
+use std::future::Future;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// How long batches already fetching may take to finish once a shutdown
+/// starts
+pub const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Cancellation shared by the parts of a run; clones trip together
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown {
+    token: CancellationToken,
+}
+
+impl Shutdown {
+    /// A shutdown that trips only when told to
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start shutting down; later calls do nothing
+    pub fn trigger(&self) {
+        self.token.cancel();
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Wait for the shutdown to start
+    pub async fn triggered(&self) {
+        self.token.cancelled().await
+    }
+
+    /// Run `work` to the end, unless a shutdown starts and it takes more than
+    /// [`DRAIN_TIMEOUT`] longer; `None` when it was cut off
+    pub async fn drain<F: Future>(&self, work: F) -> Option<F::Output> {
+        self.drain_within(DRAIN_TIMEOUT, work).await
+    }
+
+    /// [`Shutdown::drain`] with `grace` in place of [`DRAIN_TIMEOUT`]
+    pub async fn drain_within<F: Future>(&self, grace: Duration, work: F) -> Option<F::Output> {
+        tokio::pin!(work);
+        tokio::select! {
+            output = &mut work => return Some(output),
+            _ = self.token.cancelled() => {}
+        }
+        tokio::time::timeout(grace, work).await.ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slow(ms: u64) -> tokio::time::Sleep {
+        tokio::time::sleep(Duration::from_millis(ms))
+    }
+
+    #[tokio::test]
+    async fn test_drain_gives_work_a_grace_period() {
+        let grace = Duration::from_millis(200);
+        let shutdown = Shutdown::new();
+        // Nothing to cut short before a shutdown
+        assert_eq!(shutdown.drain_within(grace, slow(300)).await, Some(()));
+
+        let clone = shutdown.clone();
+        tokio::spawn(async move {
+            slow(50).await;
+            clone.trigger();
+        });
+        assert_eq!(shutdown.drain_within(grace, slow(100)).await, Some(()));
+        assert!(shutdown.is_triggered());
+
+        assert_eq!(shutdown.drain_within(grace, slow(1_000)).await, None);
+        assert_eq!(shutdown.drain_within(grace, slow(20)).await, Some(()));
+    }
+}
@@ -0,0 +1,330 @@
+//! Throughput measurement for `dl-nzb test --speed`
+//!
+//! Opens the configured number of connections through the same pool the
+//! downloader uses, then has every connection fetch test articles in turn
+//! until the window ends. Bodies are counted and dropped as they arrive, so
+//! nothing is written to disk. Nothing here prints; the caller renders the
+//! report.
+
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+use crate::config::{SpeedTestConfig, UsenetConfig};
+use crate::error::{DlNzbError, NntpError};
+use crate::nntp::{ConnectTimings, HandshakeStats, NntpPoolBuilder, NntpPoolExt, PooledConnection};
+
+type Result<T> = std::result::Result<T, DlNzbError>;
+
+/// How many of a group's newest posts are used when no articles are configured
+const RECENT_ARTICLES: u64 = 500;
+
+/// Speed of one connection over the window
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConnectionSpeed {
+    pub bytes: u64,
+    pub articles: u64,
+    /// MiB/s
+    pub mbps: f64,
+    /// Why the connection stopped early
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Connect-and-login times in milliseconds
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Latency {
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl Latency {
+    fn of(mut samples: Vec<Duration>) -> Self {
+        samples.sort_unstable();
+        // Nearest-rank percentile
+        let at = |p: f64| {
+            let rank = ((p * samples.len() as f64).ceil() as usize).max(1);
+            samples
+                .get(rank - 1)
+                .map_or(0.0, |d| d.as_secs_f64() * 1000.0)
+        };
+        Self {
+            p50: at(0.50),
+            p90: at(0.90),
+            p99: at(0.99),
+            max: at(1.0),
+        }
+    }
+}
+
+/// [`Latency`] of each step of connecting
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConnectSteps {
+    pub tcp: Latency,
+    pub tls: Latency,
+    pub greeting: Latency,
+    pub auth: Latency,
+}
+
+/// Result of a speed test
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpeedReport {
+    /// Length of the measured window
+    pub seconds: f64,
+    pub connections_requested: usize,
+    /// Connections that opened
+    pub connections: usize,
+    pub bytes: u64,
+    /// Aggregate MiB/s
+    pub mbps: f64,
+    pub articles: u64,
+    /// Test articles the server didn't have
+    pub missing: u64,
+    pub connect_ms: Latency,
+    /// `connect_ms` split into TCP connect, TLS handshake, greeting and login
+    #[serde(default)]
+    pub connect_steps_ms: ConnectSteps,
+    /// First connection against the mean of the rest, to show TLS session reuse
+    #[serde(skip)]
+    pub handshakes: HandshakeStats,
+    pub per_connection: Vec<ConnectionSpeed>,
+    /// Connections that failed to open
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub connect_errors: Vec<String>,
+}
+
+/// A configured speed test
+pub struct SpeedTest {
+    server: UsenetConfig,
+    articles: SpeedTestConfig,
+    duration: Duration,
+}
+
+impl SpeedTest {
+    pub fn new(server: UsenetConfig, articles: SpeedTestConfig, duration: Duration) -> Self {
+        Self {
+            server,
+            articles,
+            duration,
+        }
+    }
+
+    /// Run the test; fails only if no connection could be opened or there is
+    /// nothing to download
+    pub async fn run(&self) -> Result<SpeedReport> {
+        let requested = (self.server.connections as usize).max(1);
+        let pool = NntpPoolBuilder::new(self.server.clone())
+            .max_size(requested)
+            .build()?;
+
+        let opened = join_all((0..requested).map(|_| pool.get_connection())).await;
+        let mut connections = Vec::new();
+        let mut connect_errors = Vec::new();
+        let mut first_error = None;
+        for result in opened {
+            match result {
+                Ok(conn) => connections.push(conn),
+                Err(e) => {
+                    connect_errors.push(e.to_string());
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        if connections.is_empty() {
+            return Err(first_error.unwrap_or_else(|| NntpError::UnhealthyConnection.into()));
+        }
+        let connect_ms = Latency::of(connections.iter().map(|c| c.connect_time()).collect());
+        let step = |of: fn(ConnectTimings) -> Duration| {
+            Latency::of(connections.iter().map(|c| of(c.timings())).collect())
+        };
+        let connect_steps_ms = ConnectSteps {
+            tcp: step(|t| t.tcp),
+            tls: step(|t| t.tls),
+            greeting: step(|t| t.greeting),
+            auth: step(|t| t.auth),
+        };
+        let handshakes = pool.handshakes();
+
+        let measured = self.measure(&mut connections).await;
+        // Back into the pool, then QUIT on every one of them
+        drop(connections);
+        pool.close_all().await;
+        let (per_connection, elapsed) = measured?;
+
+        let bytes = per_connection.iter().map(|c| c.speed.bytes).sum();
+        Ok(SpeedReport {
+            seconds: elapsed.as_secs_f64(),
+            connections_requested: requested,
+            connections: per_connection.len(),
+            bytes,
+            mbps: mib_per_second(bytes, elapsed),
+            articles: per_connection.iter().map(|c| c.speed.articles).sum(),
+            missing: per_connection.iter().map(|c| c.missing).sum(),
+            connect_ms,
+            connect_steps_ms,
+            handshakes,
+            per_connection: per_connection.into_iter().map(|c| c.speed).collect(),
+            connect_errors,
+        })
+    }
+
+    /// Download on every connection until the window closes
+    async fn measure(
+        &self,
+        connections: &mut [PooledConnection],
+    ) -> Result<(Vec<Worker>, Duration)> {
+        let group = self.articles.group.as_str();
+        let articles = if self.articles.articles.is_empty() {
+            let range = connections[0].select_group(group).await?;
+            let last = *range.end();
+            let first = (*range.start()).max(last.saturating_sub(RECENT_ARTICLES - 1));
+            if last == 0 || first > last {
+                return Err(NntpError::ProtocolError(format!("{} has no articles", group)).into());
+            }
+            (first..=last).rev().map(|n| n.to_string()).collect()
+        } else {
+            self.articles.articles.clone()
+        };
+
+        let start = Instant::now();
+        let deadline = start + self.duration;
+        let stride = connections.len();
+        let workers = connections.iter_mut().enumerate().map(|(i, conn)| {
+            let articles = &articles;
+            async move {
+                let mut worker = Worker::default();
+                let started = Instant::now();
+                // Each connection walks its own slice of the list so they don't overlap
+                let mut next = i;
+                while Instant::now() < deadline {
+                    let article = &articles[next % articles.len()];
+                    next += stride;
+                    match conn.discard_body(article, group).await {
+                        Ok(Some(size)) => {
+                            worker.speed.bytes += size;
+                            worker.speed.articles += 1;
+                        }
+                        Ok(None) => worker.missing += 1,
+                        Err(e) => {
+                            worker.speed.error = Some(e.to_string());
+                            break;
+                        }
+                    }
+                    if worker.speed.articles == 0 && worker.missing >= articles.len() as u64 {
+                        worker.speed.error = Some("none of the test articles are available".into());
+                        break;
+                    }
+                }
+                worker.speed.mbps = mib_per_second(worker.speed.bytes, started.elapsed());
+                worker
+            }
+        });
+        let workers = join_all(workers).await;
+        Ok((workers, start.elapsed()))
+    }
+}
+
+/// Per-connection tally while measuring
+#[derive(Default)]
+struct Worker {
+    speed: ConnectionSpeed,
+    missing: u64,
+}
+
+fn mib_per_second(bytes: u64, elapsed: Duration) -> f64 {
+    let seconds = elapsed.as_secs_f64();
+    if seconds > 0.0 {
+        bytes as f64 / 1024.0 / 1024.0 / seconds
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// NNTP server with articles 1-3 in `alt.test`, each a 1000-byte body
+    fn spawn_server(quits: Arc<AtomicUsize>) -> u16 {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let quits = quits.clone();
+                std::thread::spawn(move || {
+                    let mut writer = stream.try_clone().unwrap();
+                    let _ = writer.write_all(b"200 ready\r\n");
+                    for line in BufReader::new(stream).lines() {
+                        let Ok(line) = line else { break };
+                        let reply = if line.starts_with("AUTHINFO USER") {
+                            "381 more".to_string()
+                        } else if line.starts_with("AUTHINFO PASS") {
+                            "281 ok".to_string()
+                        } else if line == "GROUP alt.test" {
+                            "211 3 1 3 alt.test".to_string()
+                        } else if line.starts_with("BODY ") {
+                            format!("222 0 body\r\n{}\r\n.", "x".repeat(998))
+                        } else if line == "QUIT" {
+                            quits.fetch_add(1, Ordering::SeqCst);
+                            let _ = writer.write_all(b"205 bye\r\n");
+                            break;
+                        } else {
+                            "500 what".to_string()
+                        };
+                        if writer
+                            .write_all(format!("{}\r\n", reply).as_bytes())
+                            .is_err()
+                        {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        port
+    }
+
+    #[tokio::test]
+    async fn test_speed_test_measures_and_closes() {
+        let quits = Arc::new(AtomicUsize::new(0));
+        let server = UsenetConfig {
+            server: "127.0.0.1".to_string(),
+            port: spawn_server(quits.clone()),
+            ssl: false,
+            username: "u".to_string(),
+            password: "p".into(),
+            connections: 2,
+            ..UsenetConfig::default()
+        };
+        let articles = SpeedTestConfig {
+            group: "alt.test".to_string(),
+            articles: Vec::new(),
+        };
+
+        let report = SpeedTest::new(server, articles, Duration::from_millis(300))
+            .run()
+            .await
+            .unwrap();
+        assert_eq!(report.connections, 2);
+        assert_eq!(report.per_connection.len(), 2);
+        assert!(report.articles > 0);
+        assert_eq!(report.bytes, report.articles * 1000);
+        assert!(report.per_connection.iter().all(|c| c.error.is_none()));
+        assert_eq!(quits.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_latency_percentiles() {
+        let samples = (1..=10).map(Duration::from_millis).collect();
+        let latency = Latency::of(samples);
+        assert_eq!(latency.p50, 5.0);
+        assert_eq!(latency.p90, 9.0);
+        assert_eq!(latency.max, 10.0);
+        assert_eq!(Latency::of(Vec::new()).p50, 0.0);
+    }
+}
@@ -0,0 +1,432 @@
+//! Live state of a download run, for the API, metrics and notifications
+//!
+//! While `watch` downloads, the HTTP API and `/metrics` answer from their own
+//! tasks and notifications go out from others. [`AppState`] is what they all
+//! read: the run's [`Events`](crate::download::Events) fill it in as the
+//! downloader sends them, and [`AppState::finish_nzb`] as each NZB ends.
+//!
+//! Counters are atomics, so a reader never waits on a download task. Only
+//! the queue, the current NZB and the recent NZBs sit behind locks, held just
+//! long enough to copy or replace them. A task that panics while holding one
+//! can't have left the data half-changed, so the lock is read through the
+//! poison rather than failing every request after it.
+
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, Instant};
+
+use crate::download::{
+    serialize_nzb_path, DownloadEvent, Limits, LimitsSnapshot, ServerStats, StatsHandle,
+};
+use crate::error::RunStatus;
+use crate::progress::DownloadSnapshot;
+use crate::queue::Queue;
+use crate::shutdown::Shutdown;
+
+/// Finished NZBs kept for `/status`
+pub const RECENT_NZBS: usize = 20;
+
+/// Shortest time the speed is measured over
+const SPEED_WINDOW: Duration = Duration::from_secs(2);
+
+/// What a run is doing, shared between its tasks
+pub struct AppState {
+    stats: Option<StatsHandle>,
+    /// The downloader's speed and connection limits, changed by the API
+    limits: Option<Limits>,
+    queue: RwLock<Option<Arc<Queue>>>,
+    /// The NZB downloading, between its `NzbStarted` and `finish_nzb`
+    current: RwLock<Option<PathBuf>>,
+    /// Newest last
+    recent: RwLock<VecDeque<FinishedNzb>>,
+    files: AtomicUsize,
+    files_done: AtomicUsize,
+    bytes_done: AtomicU64,
+    bytes_total: AtomicU64,
+    /// Bytes read from the server over the whole run, for the speed
+    transferred: AtomicU64,
+    nzbs_completed: AtomicU64,
+    nzbs_failed: AtomicU64,
+    speed: SpeedGauge,
+    /// Tripped when the run starts shutting down
+    shutdown: Shutdown,
+}
+
+/// An NZB the run has finished with
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FinishedNzb {
+    #[serde(serialize_with = "serialize_nzb_path")]
+    pub nzb: PathBuf,
+    pub status: RunStatus,
+    pub total_size: u64,
+}
+
+impl AppState {
+    pub fn new() -> Self {
+        Self {
+            stats: None,
+            limits: None,
+            queue: RwLock::new(None),
+            current: RwLock::new(None),
+            recent: RwLock::new(VecDeque::new()),
+            files: AtomicUsize::new(0),
+            files_done: AtomicUsize::new(0),
+            bytes_done: AtomicU64::new(0),
+            bytes_total: AtomicU64::new(0),
+            transferred: AtomicU64::new(0),
+            nzbs_completed: AtomicU64::new(0),
+            nzbs_failed: AtomicU64::new(0),
+            speed: SpeedGauge::new(),
+            shutdown: Shutdown::new(),
+        }
+    }
+
+    /// Read the per-server counters through the downloader's handle
+    pub fn with_stats(mut self, stats: StatsHandle) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+
+    /// Let the API read and change the downloader's limits
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = Some(limits);
+        self
+    }
+
+    /// Follow the run's shutdown, so the API stops taking NZBs with it
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    pub fn shutdown(&self) -> &Shutdown {
+        &self.shutdown
+    }
+
+    pub fn limits(&self) -> Option<&Limits> {
+        self.limits.as_ref()
+    }
+
+    /// Limits in force, for `/status`
+    pub fn limits_snapshot(&self) -> Option<LimitsSnapshot> {
+        self.limits.as_ref().map(Limits::snapshot)
+    }
+
+    pub fn stats(&self) -> Option<&StatsHandle> {
+        self.stats.as_ref()
+    }
+
+    /// Article counts per server, as in the run summary
+    pub fn server_stats(&self) -> Vec<ServerStats> {
+        self.stats
+            .as_ref()
+            .map(StatsHandle::server_stats)
+            .unwrap_or_default()
+    }
+
+    pub fn set_queue(&self, queue: Arc<Queue>) {
+        *write(&self.queue) = Some(queue);
+    }
+
+    pub fn queue(&self) -> Option<Arc<Queue>> {
+        read(&self.queue).clone()
+    }
+
+    /// Follow a download event
+    pub fn record(&self, event: &DownloadEvent) {
+        match event {
+            DownloadEvent::NzbStarted { nzb } => {
+                *write(&self.current) = Some(nzb.clone());
+                self.reset(0, 0);
+            }
+            DownloadEvent::QueueStarted { files, total_bytes } => {
+                self.reset(*files, *total_bytes);
+            }
+            DownloadEvent::Progress { bytes, transferred } => {
+                self.transferred.fetch_add(*transferred, Ordering::Relaxed);
+                self.bytes_done.fetch_add(*bytes, Ordering::Release);
+            }
+            DownloadEvent::FileCompleted { .. } => {
+                self.files_done.fetch_add(1, Ordering::Release);
+            }
+            DownloadEvent::FileStarted { .. } | DownloadEvent::PostProcessing(_) => {}
+        }
+    }
+
+    /// Start counting an NZB's files from nothing
+    fn reset(&self, files: usize, total_bytes: u64) {
+        self.bytes_done.store(0, Ordering::Release);
+        self.files_done.store(0, Ordering::Release);
+        self.files.store(files, Ordering::Release);
+        self.bytes_total.store(total_bytes, Ordering::Release);
+    }
+
+    /// Count an NZB as finished and keep it among the recent ones
+    pub fn finish_nzb(&self, nzb: FinishedNzb) {
+        let counter = if nzb.status.succeeded() {
+            &self.nzbs_completed
+        } else {
+            &self.nzbs_failed
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        *write(&self.current) = None;
+        let mut recent = write(&self.recent);
+        if recent.len() == RECENT_NZBS {
+            recent.pop_front();
+        }
+        recent.push_back(nzb);
+    }
+
+    /// An NZB left queued to be tried again is no longer current, but not
+    /// finished either
+    pub fn pause_nzb(&self) {
+        *write(&self.current) = None;
+    }
+
+    pub fn current(&self) -> Option<PathBuf> {
+        read(&self.current).clone()
+    }
+
+    /// Finished NZBs, newest first
+    pub fn recent(&self) -> Vec<FinishedNzb> {
+        read(&self.recent).iter().rev().cloned().collect()
+    }
+
+    /// `(completed, failed)` NZBs so far
+    pub fn nzbs(&self) -> (u64, u64) {
+        (
+            self.nzbs_completed.load(Ordering::Relaxed),
+            self.nzbs_failed.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Bytes read from the server per second, measured since the previous
+    /// reading (or over [`SPEED_WINDOW`], if that was more recent)
+    pub fn speed(&self) -> u64 {
+        self.speed.read(self.transferred.load(Ordering::Relaxed))
+    }
+
+    /// Progress of the current NZB; `None` between NZBs
+    pub fn snapshot(&self) -> Option<DownloadSnapshot> {
+        // Done before total: the total is set before any progress, so within
+        // an NZB this never reads more done than in all. The `min`s cover a
+        // read that straddles two NZBs.
+        let files_done = self.files_done.load(Ordering::Acquire);
+        let bytes_done = self.bytes_done.load(Ordering::Acquire);
+        let files = self.files.load(Ordering::Acquire);
+        let bytes_total = self.bytes_total.load(Ordering::Acquire);
+        self.current().map(|_| DownloadSnapshot {
+            files,
+            files_done: files_done.min(files),
+            bytes_done: bytes_done.min(bytes_total),
+            bytes_total,
+            bytes_per_sec: self.speed(),
+        })
+    }
+}
+
+impl Default for AppState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for AppState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AppState")
+            .field("current", &self.current())
+            .field("progress", &self.snapshot())
+            .field("nzbs", &self.nzbs())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Bytes per second, recomputed by whichever reader finds the last
+/// measurement at least [`SPEED_WINDOW`] old
+#[derive(Debug)]
+struct SpeedGauge {
+    start: Instant,
+    /// Milliseconds after `start` of the last measurement
+    measured_at: AtomicU64,
+    /// Running total at the last measurement
+    measured: AtomicU64,
+    speed: AtomicU64,
+}
+
+impl SpeedGauge {
+    fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            measured_at: AtomicU64::new(0),
+            measured: AtomicU64::new(0),
+            speed: AtomicU64::new(0),
+        }
+    }
+
+    fn read(&self, total: u64) -> u64 {
+        let now = self.start.elapsed().as_millis() as u64;
+        let last = self.measured_at.load(Ordering::Acquire);
+        let elapsed = now.saturating_sub(last);
+        // Only the reader that claims the measurement updates the gauge
+        if elapsed >= SPEED_WINDOW.as_millis() as u64
+            && self
+                .measured_at
+                .compare_exchange(last, now, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+        {
+            let before = self.measured.swap(total, Ordering::AcqRel);
+            let speed = total.saturating_sub(before) * 1000 / elapsed;
+            self.speed.store(speed, Ordering::Release);
+        }
+        self.speed.load(Ordering::Acquire)
+    }
+}
+
+fn read<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(|e| e.into_inner())
+}
+
+fn write<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::download::Events;
+    use std::sync::atomic::AtomicBool;
+
+    const FILES: usize = 8;
+    const SEGMENTS: usize = 500;
+    const SEGMENT_BYTES: u64 = 700;
+
+    fn finished(name: &str, status: RunStatus) -> FinishedNzb {
+        FinishedNzb {
+            nzb: PathBuf::from(name),
+            status,
+            total_size: 10,
+        }
+    }
+
+    #[test]
+    fn test_reads_during_a_download() {
+        let state = Arc::new(AppState::new());
+        let events = Events::default().with_state(state.clone());
+        let done = Arc::new(AtomicBool::new(false));
+
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let state = state.clone();
+                let done = done.clone();
+                std::thread::spawn(move || {
+                    let mut last = 0;
+                    let mut reads = 0;
+                    while !done.load(Ordering::Acquire) {
+                        if let Some(progress) = state.snapshot() {
+                            assert!(progress.files_done <= progress.files);
+                            assert!(progress.bytes_done <= progress.bytes_total);
+                            // One NZB, so nothing goes backwards
+                            assert!(progress.bytes_done >= last, "{:?}", progress);
+                            last = progress.bytes_done;
+                        }
+                        let _ = state.recent();
+                        reads += 1;
+                    }
+                    reads
+                })
+            })
+            .collect();
+
+        events.send(DownloadEvent::NzbStarted {
+            nzb: PathBuf::from("show.nzb"),
+        });
+        events.send(DownloadEvent::QueueStarted {
+            files: FILES,
+            total_bytes: FILES as u64 * SEGMENTS as u64 * SEGMENT_BYTES,
+        });
+        // One task per file, as the downloader has
+        let writers: Vec<_> = (0..FILES)
+            .map(|i| {
+                let events = events.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..SEGMENTS {
+                        events.send(DownloadEvent::Progress {
+                            bytes: SEGMENT_BYTES,
+                            transferred: SEGMENT_BYTES + 20,
+                        });
+                    }
+                    events.send(DownloadEvent::FileCompleted {
+                        filename: format!("show.part{}.rar", i + 1),
+                        size: SEGMENTS as u64 * SEGMENT_BYTES,
+                        segments_failed: 0,
+                        skipped: false,
+                    });
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let progress = state.snapshot().unwrap();
+        assert_eq!(progress.files_done, FILES);
+        assert_eq!(progress.bytes_done, progress.bytes_total);
+        assert_eq!(
+            state.transferred.load(Ordering::Relaxed),
+            (FILES * SEGMENTS) as u64 * (SEGMENT_BYTES + 20)
+        );
+
+        state.finish_nzb(finished("show.nzb", RunStatus::Success));
+        done.store(true, Ordering::Release);
+        for reader in readers {
+            assert!(reader.join().unwrap() > 0);
+        }
+        assert!(state.snapshot().is_none());
+        assert_eq!(state.nzbs(), (1, 0));
+    }
+
+    #[test]
+    fn test_panic_holding_a_lock_does_not_wedge_readers() {
+        let state = Arc::new(AppState::new());
+        state.record(&DownloadEvent::NzbStarted {
+            nzb: PathBuf::from("show.nzb"),
+        });
+        let panicked = std::thread::spawn({
+            let state = state.clone();
+            move || {
+                let _recent = state.recent.write().unwrap();
+                let _current = state.current.write().unwrap();
+                panic!("task died holding the locks");
+            }
+        })
+        .join();
+        assert!(panicked.is_err());
+        assert!(state.current.is_poisoned());
+
+        assert_eq!(state.current(), Some(PathBuf::from("show.nzb")));
+        assert!(state.snapshot().is_some());
+        state.finish_nzb(finished("show.nzb", RunStatus::Incomplete));
+        assert_eq!(
+            state.recent(),
+            [finished("show.nzb", RunStatus::Incomplete)]
+        );
+        assert_eq!(state.nzbs(), (0, 1));
+    }
+
+    #[test]
+    fn test_recent_nzbs_are_capped() {
+        let state = AppState::new();
+        for i in 0..RECENT_NZBS + 5 {
+            state.finish_nzb(finished(&format!("{}.nzb", i), RunStatus::Success));
+        }
+        let recent = state.recent();
+        assert_eq!(recent.len(), RECENT_NZBS);
+        assert_eq!(
+            recent[0].nzb,
+            PathBuf::from(format!("{}.nzb", RECENT_NZBS + 4))
+        );
+    }
+}
@@ -0,0 +1,359 @@
+//! `--strict`: an NZB succeeds only when everything did
+//!
+//! Normally an NZB whose files all arrived counts as downloaded even when
+//! PAR2 repair or an archive's extraction failed. Under `download.strict`
+//! every shortfall found by [`violations`] fails the NZB, and [`mark_failed`] leaves its output
+//! folder looking failed, so nothing downstream mistakes it for a finished
+//! download.
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::config::StrictMark;
+use crate::download::DownloadResult;
+use crate::error::RunStatus;
+use crate::json_output::PostProcessingResult;
+use crate::processing::ScriptOutcome;
+
+/// Marker written into an output folder that failed under `--strict`
+pub const STRICT_MARKER: &str = ".dlnzb-strict-failed";
+
+/// Suffix given to an output folder that failed under `--strict`
+pub const FAILED_SUFFIX: &str = ".failed";
+
+/// Something that keeps an NZB from being perfect
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Violation {
+    /// Files that couldn't be downloaded at all
+    MissingFiles { count: usize },
+    /// Files still missing segments after PAR2
+    UnrepairedSegments { files: usize },
+    /// Files with segments that failed their checksum and that PAR2 didn't verify
+    UnverifiedCorruption { files: usize },
+    /// PAR2 repair ran and failed
+    Par2Failed { reason: String },
+    /// Archives that failed to extract, needed a password or didn't fit
+    ExtractFailed { archives: usize },
+    /// Media files that failed container verification
+    MediaFailed { files: usize },
+    /// The post-processing script failed or timed out
+    ScriptFailed,
+    /// Post-processing stopped with an error
+    PostProcessingError,
+}
+
+impl Violation {
+    /// Status this violation holds the NZB to at best
+    pub fn status(&self) -> RunStatus {
+        match self {
+            Self::MissingFiles { .. }
+            | Self::UnrepairedSegments { .. }
+            | Self::UnverifiedCorruption { .. } => RunStatus::Incomplete,
+            Self::Par2Failed { .. }
+            | Self::ExtractFailed { .. }
+            | Self::MediaFailed { .. }
+            | Self::ScriptFailed
+            | Self::PostProcessingError => RunStatus::PostProcessingFailed,
+        }
+    }
+
+    /// Short description for messages and the marker file
+    pub fn describe(&self) -> String {
+        let plural = |n: usize| if n == 1 { "" } else { "s" };
+        match self {
+            Self::MissingFiles { count } => {
+                format!("{} file{} not downloaded", count, plural(*count))
+            }
+            Self::UnrepairedSegments { files } => format!(
+                "{} file{} missing segments PAR2 didn't repair",
+                files,
+                plural(*files)
+            ),
+            Self::UnverifiedCorruption { files } => format!(
+                "{} file{} with corrupt segments PAR2 didn't verify",
+                files,
+                plural(*files)
+            ),
+            Self::Par2Failed { reason } => format!("PAR2 repair failed: {}", reason),
+            Self::ExtractFailed { archives } => {
+                format!("{} archive{} not extracted", archives, plural(*archives))
+            }
+            Self::MediaFailed { files } => {
+                format!("{} media file{} failed verification", files, plural(*files))
+            }
+            Self::ScriptFailed => "post-processing script failed".to_string(),
+            Self::PostProcessingError => "post-processing stopped with an error".to_string(),
+        }
+    }
+}
+
+/// What a finished NZB came to
+pub struct Outcome<'a> {
+    pub results: &'a [DownloadResult],
+    /// Files selected for download, including any that failed outright
+    pub expected_files: usize,
+    pub post_processing: &'a PostProcessingResult,
+    pub script: Option<&'a ScriptOutcome>,
+    /// Post-processing or the script returned an error
+    pub post_failed: bool,
+}
+
+/// Everything that keeps `outcome` from being perfect
+pub fn violations(outcome: &Outcome) -> Vec<Violation> {
+    let post = outcome.post_processing;
+    let count = |damaged: fn(&DownloadResult) -> bool| {
+        outcome.results.iter().filter(|r| damaged(r)).count()
+    };
+    let mut violations = Vec::new();
+
+    let missing_files = outcome.expected_files.saturating_sub(outcome.results.len());
+    if missing_files > 0 {
+        violations.push(Violation::MissingFiles {
+            count: missing_files,
+        });
+    }
+    // Damage only stands if PAR2 didn't vouch for the set afterwards
+    if !post.par2_verified {
+        let files = count(|r| r.segments_failed > 0);
+        if files > 0 {
+            violations.push(Violation::UnrepairedSegments { files });
+        }
+        let files = count(|r| r.segments_corrupt > 0);
+        if files > 0 {
+            violations.push(Violation::UnverifiedCorruption { files });
+        }
+    }
+
+    if let Some(reason) = &post.par2_error {
+        violations.push(Violation::Par2Failed {
+            reason: reason.clone(),
+        });
+    }
+    let archives =
+        post.extract_failed.len() + post.password_required.len() + post.space_shortfalls.len();
+    if archives > 0 {
+        violations.push(Violation::ExtractFailed { archives });
+    }
+    let files = post.failed_media().count();
+    if files > 0 {
+        violations.push(Violation::MediaFailed { files });
+    }
+    if outcome.script.is_some_and(|s| !s.success()) {
+        violations.push(Violation::ScriptFailed);
+    }
+    if outcome.post_failed {
+        violations.push(Violation::PostProcessingError);
+    }
+    violations
+}
+
+/// The best status an NZB with `violations` can have
+pub fn status(violations: &[Violation]) -> RunStatus {
+    violations
+        .iter()
+        .map(Violation::status)
+        .max()
+        .unwrap_or(RunStatus::Success)
+}
+
+/// Leave `dir` looking failed and return where it now is
+///
+/// With [`StrictMark::Rename`] the folder becomes `<name>.failed`, unless it
+/// isn't the NZB's own (`own_folder`) or that name is taken; otherwise, and
+/// with [`StrictMark::Marker`], a [`STRICT_MARKER`] file listing `reasons`
+/// is written into it.
+pub fn mark_failed(
+    dir: &Path,
+    mark: StrictMark,
+    own_folder: bool,
+    reasons: &[String],
+) -> std::io::Result<PathBuf> {
+    if mark == StrictMark::Rename && own_folder {
+        let mut name = dir.file_name().unwrap_or_default().to_os_string();
+        name.push(FAILED_SUFFIX);
+        let renamed = dir.with_file_name(name);
+        if !renamed.exists() {
+            std::fs::rename(dir, &renamed)?;
+            return Ok(renamed);
+        }
+        tracing::warn!(
+            "{} already exists; marking {} as failed instead",
+            renamed.display(),
+            dir.display()
+        );
+    }
+    std::fs::create_dir_all(dir)?;
+    let mut text = reasons.join("\n");
+    text.push('\n');
+    std::fs::write(dir.join(STRICT_MARKER), text)?;
+    Ok(dir.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::{MediaCheck, SpaceShortfall};
+
+    fn file(failed: usize, corrupt: usize) -> DownloadResult {
+        DownloadResult {
+            filename: "a.rar".to_string(),
+            path: PathBuf::from("a.rar"),
+            size: 1,
+            segments_downloaded: 10 - failed,
+            segments_failed: failed,
+            segments_missing: failed,
+            segments_corrupt: corrupt,
+            ..Default::default()
+        }
+    }
+
+    fn check(
+        results: &[DownloadResult],
+        expected_files: usize,
+        post: &PostProcessingResult,
+        post_failed: bool,
+    ) -> Vec<Violation> {
+        violations(&Outcome {
+            results,
+            expected_files,
+            post_processing: post,
+            script: None,
+            post_failed,
+        })
+    }
+
+    #[test]
+    fn test_perfect_download_passes() {
+        let post = PostProcessingResult {
+            par2_verified: true,
+            rar_extracted: true,
+            ..Default::default()
+        };
+        assert!(check(&[file(0, 0)], 1, &post, false).is_empty());
+        // No PAR2 at all is fine when nothing was damaged
+        let post = PostProcessingResult::default();
+        let found = check(&[file(0, 0), file(0, 0)], 2, &post, false);
+        assert!(found.is_empty());
+        assert_eq!(status(&found), RunStatus::Success);
+    }
+
+    #[test]
+    fn test_par2_vouches_for_damage() {
+        let repaired = PostProcessingResult {
+            par2_verified: true,
+            par2_repaired: true,
+            ..Default::default()
+        };
+        assert!(check(&[file(2, 1)], 1, &repaired, false).is_empty());
+
+        let unrepaired = PostProcessingResult {
+            par2_error: Some("not enough recovery blocks".to_string()),
+            ..Default::default()
+        };
+        let found = check(&[file(2, 0), file(0, 1)], 2, &unrepaired, false);
+        assert_eq!(
+            found,
+            [
+                Violation::UnrepairedSegments { files: 1 },
+                Violation::UnverifiedCorruption { files: 1 },
+                Violation::Par2Failed {
+                    reason: "not enough recovery blocks".to_string()
+                },
+            ]
+        );
+        assert_eq!(status(&found), RunStatus::Incomplete);
+    }
+
+    #[test]
+    fn test_missing_files_are_incomplete() {
+        let post = PostProcessingResult::default();
+        let found = check(&[file(0, 0)], 3, &post, false);
+        assert_eq!(found, [Violation::MissingFiles { count: 2 }]);
+        assert_eq!(status(&found), RunStatus::Incomplete);
+    }
+
+    #[test]
+    fn test_post_processing_shortfalls_fail() {
+        let post = PostProcessingResult {
+            par2_verified: true,
+            extract_failed: vec![PathBuf::from("a.rar")],
+            password_required: vec![PathBuf::from("b.rar")],
+            space_shortfalls: vec![SpaceShortfall {
+                archive: PathBuf::from("c.rar"),
+                required: 2,
+                available: 1,
+            }],
+            media_checks: vec![
+                MediaCheck {
+                    path: PathBuf::from("a.mkv"),
+                    format: "mkv".to_string(),
+                    passed: false,
+                    reason: Some("truncated".to_string()),
+                },
+                MediaCheck {
+                    path: PathBuf::from("b.mkv"),
+                    format: "mkv".to_string(),
+                    passed: true,
+                    reason: None,
+                },
+            ],
+            ..Default::default()
+        };
+        let found = check(&[file(0, 0)], 1, &post, true);
+        assert_eq!(
+            found,
+            [
+                Violation::ExtractFailed { archives: 3 },
+                Violation::MediaFailed { files: 1 },
+                Violation::PostProcessingError,
+            ]
+        );
+        assert_eq!(status(&found), RunStatus::PostProcessingFailed);
+        assert_eq!(found[0].describe(), "3 archives not extracted");
+    }
+
+    #[test]
+    fn test_failed_script_fails() {
+        let post = PostProcessingResult::default();
+        let script = ScriptOutcome {
+            script: PathBuf::from("notify.sh"),
+            exit_code: Some(1),
+            timed_out: false,
+        };
+        let found = violations(&Outcome {
+            results: &[file(0, 0)],
+            expected_files: 1,
+            post_processing: &post,
+            script: Some(&script),
+            post_failed: false,
+        });
+        assert_eq!(found, [Violation::ScriptFailed]);
+    }
+
+    #[test]
+    fn test_mark_failed() {
+        let root = tempfile::tempdir().unwrap();
+        let reasons = ["1 archive not extracted".to_string()];
+
+        let dir = root.path().join("Some.Release");
+        std::fs::create_dir(&dir).unwrap();
+        let moved = mark_failed(&dir, StrictMark::Rename, true, &reasons).unwrap();
+        assert_eq!(moved, root.path().join("Some.Release.failed"));
+        assert!(moved.is_dir() && !dir.exists());
+
+        // The name is taken now, so the second one gets a marker
+        std::fs::create_dir(&dir).unwrap();
+        let marked = mark_failed(&dir, StrictMark::Rename, true, &reasons).unwrap();
+        assert_eq!(marked, dir);
+        assert_eq!(
+            std::fs::read_to_string(dir.join(STRICT_MARKER)).unwrap(),
+            "1 archive not extracted\n"
+        );
+
+        // A shared download folder is never renamed
+        let marked = mark_failed(root.path(), StrictMark::Rename, false, &reasons).unwrap();
+        assert_eq!(marked, root.path());
+        assert!(root.path().join(STRICT_MARKER).exists());
+    }
+}
@@ -0,0 +1,470 @@
+//! `verify`: whether what's on disk for an NZB is complete
+//!
+//! Each of the NZB's files is looked for in the folder under the name its
+//! subject gives. Files a run renamed (deobfuscation, a PAR2 repair) are
+//! found through the PAR2 set's file names instead, by the length closest to
+//! what the NZB declares. A file that's there is held to the decoded size
+//! recorded in `.dlnzb-segments` when it finished, else to its length in the
+//! PAR2 set, else to the NZB's total within
+//! [`SIZE_TOLERANCE_PERCENT`](crate::download::SIZE_TOLERANCE_PERCENT).
+//!
+//! With checksums on, files of the right size are hashed too: against the
+//! PAR2 set's MD5s, or the CRC32s of an SFV file, several files at a time
+//! (see [`checksum`](crate::checksum)). Nothing is fetched;
+//! [`sample_availability`] `STAT`s the missing files' segments only when
+//! asked to.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::checksum;
+use crate::config::Config;
+use crate::download::sizes::{self, recorded_sizes};
+use crate::download::{serialize_nzb_path, Nzb, NzbFile};
+use crate::plan::{sample_availability, Availability, SAMPLE_SIZE};
+use crate::processing::{descriptions_in_dir, file_matches, FileDescription};
+use crate::progress::{PhaseEnd, PhaseStep, ProgressSink};
+
+/// What was found for one NZB in a folder
+#[derive(Debug, Clone, Serialize)]
+pub struct Verification {
+    #[serde(serialize_with = "serialize_nzb_path")]
+    pub nzb: PathBuf,
+    pub dir: PathBuf,
+    pub files: Vec<VerifiedFile>,
+    /// Sample of the missing and damaged files' segments on the servers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub availability: Option<Availability>,
+    /// Whether every file is there and passed
+    pub complete: bool,
+}
+
+/// One of the NZB's files and what's on disk for it
+#[derive(Debug, Clone, Serialize)]
+pub struct VerifiedFile {
+    pub index: usize,
+    /// Name from the subject, or the subject when it names nothing
+    pub filename: String,
+    /// Where the file was found, when not under `filename`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub found_as: Option<String>,
+    /// Size the NZB declares (encoded articles, a few percent over)
+    pub declared_size: u64,
+    /// Exact size the file should have, when recorded or in the PAR2 set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected_size: Option<u64>,
+    /// Size on disk
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    pub status: FileStatus,
+    /// What the file was hashed against, if it was
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<ChecksumSource>,
+    /// Estimated share of the file's segments on the servers, in percent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub available: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileStatus {
+    Ok,
+    Missing,
+    WrongSize,
+    ChecksumMismatch,
+}
+
+impl FileStatus {
+    /// Short lowercase description for the table
+    pub fn describe(self) -> &'static str {
+        match self {
+            Self::Ok => "ok",
+            Self::Missing => "missing",
+            Self::WrongSize => "wrong size",
+            Self::ChecksumMismatch => "checksum mismatch",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChecksumSource {
+    Par2,
+    Sfv,
+}
+
+impl Verification {
+    /// Check `dir` against `nzb`
+    ///
+    /// `checksums` hashes the files on `post_processing.checksum_threads`
+    /// threads, reporting the bytes hashed to `progress`; `config` is
+    /// otherwise only used with `check_server`, to sample the segments of
+    /// what's missing or damaged.
+    pub async fn new(
+        nzb_path: &Path,
+        nzb: &Nzb,
+        dir: &Path,
+        config: &Config,
+        checksums: bool,
+        check_server: bool,
+        progress: &Arc<dyn ProgressSink>,
+    ) -> Self {
+        let recorded = recorded_sizes(dir).await;
+        let files = {
+            let (files, dir) = (nzb.files().to_vec(), dir.to_path_buf());
+            let hashing = checksums.then(|| Hashing {
+                threads: config.post_processing.checksum_threads,
+                progress: progress.clone(),
+            });
+            tokio::task::spawn_blocking(move || check_files(&files, &dir, &recorded, hashing))
+                .await
+                .unwrap_or_default()
+        };
+        let mut verification = Self {
+            nzb: nzb_path.to_path_buf(),
+            dir: dir.to_path_buf(),
+            complete: !files.is_empty() && files.iter().all(|f| f.status == FileStatus::Ok),
+            files,
+            availability: None,
+        };
+
+        if check_server && !verification.complete {
+            let failed: Vec<NzbFile> = nzb
+                .files()
+                .iter()
+                .zip(&verification.files)
+                .filter(|(_, verified)| verified.status != FileStatus::Ok)
+                .map(|(file, _)| file.clone())
+                .collect();
+            let availability = sample_availability(config, &failed, SAMPLE_SIZE).await;
+            for file in &mut verification.files {
+                if let Some(sample) = availability.files.get(&file.index) {
+                    file.available = sample.completeness();
+                }
+            }
+            verification.availability = Some(availability);
+        }
+        verification
+    }
+
+    /// Files that aren't there or didn't pass
+    pub fn failed(&self) -> impl Iterator<Item = &VerifiedFile> {
+        self.files.iter().filter(|f| f.status != FileStatus::Ok)
+    }
+}
+
+/// How files of the right size get hashed
+struct Hashing {
+    threads: usize,
+    progress: Arc<dyn ProgressSink>,
+}
+
+/// Find and check each of `files` in `dir`, hashing them too with
+/// `hashing` (blocking)
+fn check_files(
+    files: &[NzbFile],
+    dir: &Path,
+    recorded: &BTreeMap<String, u64>,
+    hashing: Option<Hashing>,
+) -> Vec<VerifiedFile> {
+    let par2 = descriptions_in_dir(dir);
+    let sfv = if hashing.is_some() {
+        read_sfv_files(dir)
+    } else {
+        HashMap::new()
+    };
+
+    let names: Vec<Option<String>> = files
+        .iter()
+        .map(|file| Nzb::get_filename_from_subject(&file.subject))
+        .collect();
+    // PAR2 names free for files that aren't under their own
+    let mut unclaimed: HashSet<&str> = par2
+        .keys()
+        .map(String::as_str)
+        .filter(|name| !names.iter().flatten().any(|n| n.as_str() == *name))
+        .filter(|name| dir.join(name).is_file())
+        .collect();
+
+    let mut verified = Vec::with_capacity(files.len());
+    // Files to hash once the sizes are in: the entry, where it is and what
+    // it's held to
+    let mut to_hash: Vec<(usize, PathBuf, Expected)> = Vec::new();
+    for (file, name) in files.iter().zip(names) {
+        let declared_size: u64 = file.segments.segment.iter().map(|s| s.bytes).sum();
+        let on_disk = name.as_deref().filter(|name| dir.join(name).is_file());
+        let found_as = match on_disk {
+            Some(_) => None,
+            None => renamed(&par2, &unclaimed, declared_size),
+        };
+        if let Some(found) = &found_as {
+            unclaimed.remove(found.as_str());
+        }
+        let filename = name.clone().unwrap_or_else(|| file.subject.clone());
+        let mut entry = VerifiedFile {
+            index: file.index,
+            filename,
+            found_as: found_as.clone(),
+            declared_size,
+            expected_size: None,
+            size: None,
+            status: FileStatus::Missing,
+            checksum: None,
+            available: None,
+        };
+
+        let Some(found) = on_disk.map(str::to_string).or(found_as) else {
+            verified.push(entry);
+            continue;
+        };
+        let path = dir.join(&found);
+        let description = par2.get(&found);
+        entry.expected_size = recorded
+            .get(&found)
+            .copied()
+            .or(description.map(|d| d.length));
+        entry.size = std::fs::metadata(&path).ok().map(|m| m.len());
+        let size = entry.size.unwrap_or(0);
+        let size_ok = match entry.expected_size {
+            Some(expected) => size == expected,
+            None => !sizes::diverges(declared_size, size),
+        };
+        entry.status = if size_ok {
+            FileStatus::Ok
+        } else {
+            FileStatus::WrongSize
+        };
+
+        if hashing.is_some() && size_ok {
+            let expected = match (description, sfv.get(&found.to_lowercase())) {
+                (Some(description), _) => Some(Expected::Md5(description)),
+                (None, Some(&crc)) => Some(Expected::Crc32(crc)),
+                (None, None) => None,
+            };
+            if let Some(expected) = expected {
+                entry.checksum = Some(expected.source());
+                to_hash.push((verified.len(), path, expected));
+            }
+        }
+        verified.push(entry);
+    }
+
+    if let Some(hashing) = hashing.filter(|_| !to_hash.is_empty()) {
+        let matches = hash_files(&to_hash, &verified, &hashing);
+        for ((index, _, _), matches) in to_hash.iter().zip(matches) {
+            if !matches {
+                verified[*index].status = FileStatus::ChecksumMismatch;
+            }
+        }
+    }
+    verified
+}
+
+/// What a file's hash is checked against
+enum Expected<'a> {
+    Md5(&'a FileDescription),
+    Crc32(u32),
+}
+
+impl Expected<'_> {
+    fn source(&self) -> ChecksumSource {
+        match self {
+            Self::Md5(_) => ChecksumSource::Par2,
+            Self::Crc32(_) => ChecksumSource::Sfv,
+        }
+    }
+}
+
+/// Whether each of the files in `to_hash` matches, hashed several at a
+/// time with the bytes done reported as they go
+fn hash_files(
+    to_hash: &[(usize, PathBuf, Expected)],
+    verified: &[VerifiedFile],
+    hashing: &Hashing,
+) -> Vec<bool> {
+    let total: u64 = to_hash
+        .iter()
+        .filter_map(|(index, _, _)| verified[*index].size)
+        .sum();
+    let message = match to_hash.len() {
+        1 => "Checking 1 file".to_string(),
+        n => format!("Checking {} files", n),
+    };
+    let done = AtomicU64::new(0);
+    let report = |bytes: u64| {
+        let done = done.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        hashing
+            .progress
+            .on_phase_progress(PhaseStep::Extracting, done, total, &message);
+    };
+    report(0);
+    let matches =
+        checksum::in_parallel(
+            to_hash,
+            hashing.threads,
+            |(index, path, expected)| match expected {
+                Expected::Md5(description) => {
+                    let matches = file_matches(description, path).unwrap_or(false);
+                    report(verified[*index].size.unwrap_or(0));
+                    matches
+                }
+                Expected::Crc32(crc) => {
+                    checksum::crc32_file(path, &report).is_ok_and(|c| c == *crc)
+                }
+            },
+        );
+    hashing.progress.on_phase_done(PhaseEnd::Finished);
+    matches
+}
+
+/// The unclaimed PAR2 file whose length is closest to `declared`, if any
+/// is close enough to be the same file
+fn renamed(
+    par2: &HashMap<String, FileDescription>,
+    unclaimed: &HashSet<&str>,
+    declared: u64,
+) -> Option<String> {
+    unclaimed
+        .iter()
+        .filter_map(|name| par2.get(*name).map(|d| (*name, d.length)))
+        .filter(|&(_, length)| !sizes::diverges(declared, length))
+        .min_by_key(|&(name, length)| (length.abs_diff(declared), name))
+        .map(|(name, _)| name.to_string())
+}
+
+/// CRC32s from every `.sfv` file in `dir`, by lowercased file name
+fn read_sfv_files(dir: &Path) -> HashMap<String, u32> {
+    let mut crcs = HashMap::new();
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return crcs;
+    };
+    for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+        let is_sfv = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("sfv"));
+        if !is_sfv {
+            continue;
+        }
+        match std::fs::read(&path) {
+            Ok(data) => crcs.extend(parse_sfv(&String::from_utf8_lossy(&data))),
+            Err(e) => tracing::debug!("Failed to read {}: {}", path.display(), e),
+        }
+    }
+    crcs
+}
+
+/// `name CRC32` lines of an SFV file; `;` starts a comment
+fn parse_sfv(text: &str) -> impl Iterator<Item = (String, u32)> + '_ {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with(';'))
+        .filter_map(|line| {
+            let (name, crc) = line.rsplit_once(char::is_whitespace)?;
+            let crc = u32::from_str_radix(crc, 16).ok()?;
+            Some((name.trim().to_lowercase(), crc))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress::NoProgress;
+
+    fn no_progress() -> Arc<dyn ProgressSink> {
+        Arc::new(NoProgress)
+    }
+
+    fn nzb(files: &[(&str, u64)]) -> Nzb {
+        let files: String = files
+            .iter()
+            .enumerate()
+            .map(|(index, (name, bytes))| {
+                format!(
+                    r#"<file poster="p@test" date="1700000000" subject="[1/1] &quot;{}&quot; yEnc (1/1)">
+    <groups><group>alt.binaries.test</group></groups>
+    <segments><segment bytes="{}" number="1">{}@test</segment></segments>
+  </file>"#,
+                    name, bytes, index
+                )
+            })
+            .collect();
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<nzb xmlns="http://www.newzbin.com/DTD/2003/nzb">
+  {}
+</nzb>"#,
+            files
+        )
+        .parse()
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_missing_and_wrong_size_files() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.bin"), vec![1u8; 1000]).unwrap();
+        std::fs::write(dir.path().join("b.bin"), vec![1u8; 500]).unwrap();
+        // yEnc and headers make the NZB's sizes a little bigger
+        let nzb = nzb(&[("a.bin", 1030), ("b.bin", 1030), ("c.bin", 1030)]);
+
+        let verification = Verification::new(
+            Path::new("x.nzb"),
+            &nzb,
+            dir.path(),
+            &Config::default(),
+            false,
+            false,
+            &no_progress(),
+        )
+        .await;
+        let statuses: Vec<_> = verification.files.iter().map(|f| f.status).collect();
+        assert_eq!(
+            statuses,
+            [FileStatus::Ok, FileStatus::WrongSize, FileStatus::Missing]
+        );
+        assert!(!verification.complete);
+        assert_eq!(verification.failed().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_sfv_catches_damaged_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let good = vec![7u8; 1000];
+        std::fs::write(dir.path().join("a.bin"), &good).unwrap();
+        std::fs::write(dir.path().join("b.bin"), &good).unwrap();
+        std::fs::write(
+            dir.path().join("set.sfv"),
+            format!(
+                "; made by hand\nA.BIN {:08x}\nb.bin 00000000\n",
+                crc32fast::hash(&good)
+            ),
+        )
+        .unwrap();
+        let nzb = nzb(&[("a.bin", 1030), ("b.bin", 1030)]);
+
+        let verification = Verification::new(
+            Path::new("x.nzb"),
+            &nzb,
+            dir.path(),
+            &Config::default(),
+            true,
+            false,
+            &no_progress(),
+        )
+        .await;
+        let files = &verification.files;
+        assert_eq!(files[0].status, FileStatus::Ok);
+        assert_eq!(files[0].checksum, Some(ChecksumSource::Sfv));
+        assert_eq!(files[1].status, FileStatus::ChecksumMismatch);
+    }
+
+    #[test]
+    fn test_parse_sfv() {
+        let parsed: Vec<_> =
+            parse_sfv("; comment\r\nMy File.rar 0a1B2c3D\r\n\r\nbad line\n").collect();
+        assert_eq!(parsed, [("my file.rar".to_string(), 0x0a1b2c3d)]);
+    }
+}
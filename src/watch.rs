@@ -0,0 +1,124 @@
+//! Watch folder for `dl-nzb watch`
+//!
+//! The folder is polled rather than subscribed to, which works the same on
+//! every platform and on network shares. An NZB is only handed out once its
+//! size and modification time have held still across two polls, so a file
+//! still being written by an indexer or an *arr app isn't read half-done.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::download::is_nzb_name;
+use crate::error::{ConfigError, DlNzbError};
+
+type Result<T> = std::result::Result<T, DlNzbError>;
+
+/// Subfolder NZBs that downloaded successfully are moved to
+pub const COMPLETED_DIR: &str = "completed";
+/// Subfolder for every other outcome
+pub const FAILED_DIR: &str = "failed";
+
+/// What a poll remembers about a file to tell whether it is still changing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileStamp {
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+/// A directory NZBs are dropped into
+#[derive(Debug)]
+pub struct WatchFolder {
+    dir: PathBuf,
+    /// Files seen on the previous poll that weren't ready yet
+    pending: HashMap<PathBuf, FileStamp>,
+}
+
+impl WatchFolder {
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        if !dir.is_dir() {
+            return Err(ConfigError::InvalidPath {
+                path: dir,
+                reason: "not a directory".to_string(),
+            }
+            .into());
+        }
+        Ok(Self {
+            dir,
+            pending: HashMap::new(),
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// NZBs that haven't changed since the previous poll, sorted by name
+    ///
+    /// A file handed out but left in place (e.g. during a server outage) is
+    /// treated as new and comes back two polls later.
+    pub fn poll(&mut self) -> std::io::Result<Vec<PathBuf>> {
+        let mut paths: Vec<PathBuf> = std::fs::read_dir(&self.dir)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && is_nzb_name(path))
+            .collect();
+        paths.sort();
+
+        let mut ready = Vec::new();
+        let mut pending = HashMap::new();
+        for path in paths {
+            let Ok(metadata) = std::fs::metadata(&path) else {
+                continue;
+            };
+            let stamp = FileStamp {
+                len: metadata.len(),
+                modified: metadata.modified().ok(),
+            };
+            if stamp.len > 0 && self.pending.get(&path) == Some(&stamp) {
+                ready.push(path);
+            } else {
+                pending.insert(path, stamp);
+            }
+        }
+        self.pending = pending;
+        Ok(ready)
+    }
+
+    /// Where a handled NZB goes: [`COMPLETED_DIR`] or [`FAILED_DIR`]
+    pub fn destination(&self, success: bool) -> PathBuf {
+        self.dir
+            .join(if success { COMPLETED_DIR } else { FAILED_DIR })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_poll_waits_for_stable_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut folder = WatchFolder::new(dir.path()).unwrap();
+        let nzb = dir.path().join("show.nzb");
+        std::fs::write(&nzb, "<nzb>").unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "x").unwrap();
+        std::fs::write(dir.path().join("empty.nzb.gz"), "").unwrap();
+
+        // First sighting only records the file
+        assert!(folder.poll().unwrap().is_empty());
+
+        // Still being written: starts over
+        std::fs::write(&nzb, "<nzb></nzb>").unwrap();
+        assert!(folder.poll().unwrap().is_empty());
+
+        assert_eq!(folder.poll().unwrap(), vec![nzb.clone()]);
+
+        // Left in place, it is offered again after settling once more
+        assert!(folder.poll().unwrap().is_empty());
+        assert_eq!(folder.poll().unwrap(), vec![nzb]);
+
+        assert!(WatchFolder::new(dir.path().join("notes.txt")).is_err());
+    }
+}
@@ -0,0 +1,1020 @@
+//! Whole-NZB downloads against an in-process NNTP server
+
+mod support;
+
+use std::time::{Duration, UNIX_EPOCH};
+
+use dl_nzb::config::Config;
+use dl_nzb::config::{ExistingFiles, SkipVerification};
+use dl_nzb::download::{
+    DownloadResult, Downloader, ExistingFile, LoadedNzb, Nzb, QuickHash, SkipReason, FAILED_MARKER,
+    HASHES_FILE,
+};
+use dl_nzb::error::{DlNzbError, DownloadError, NntpError, NzbError};
+use dl_nzb::nntp::NntpPoolBuilder;
+use dl_nzb::plan::{FileAction, Plan};
+use dl_nzb::preview::{preview_files, Preview};
+use dl_nzb::DownloadOptions;
+use support::mock_nntp::{yenc_part, Failure, MockNntp};
+
+/// Bytes per segment of the test file
+const PART_SIZE: usize = 1000;
+
+/// When the test post was made
+const POSTED: u64 = 1_700_000_000;
+
+/// A file of `parts` segments and an NZB listing its articles
+struct Post {
+    data: Vec<u8>,
+    articles: Vec<(String, Vec<u8>)>,
+    nzb: Nzb,
+    xml: String,
+}
+
+impl Post {
+    fn new(parts: usize) -> Self {
+        Self::with_subject(
+            parts,
+            &format!("[1/1] - &quot;test.bin&quot; yEnc (1/{})", parts),
+        )
+    }
+
+    /// A post whose NZB lists it under `subject` (XML-escaped)
+    fn with_subject(parts: usize, subject: &str) -> Self {
+        Self::build(parts, PART_SIZE, subject)
+    }
+
+    /// A post of `parts` segments of `part_size` bytes each
+    fn sized(parts: usize, part_size: usize) -> Self {
+        Self::build(
+            parts,
+            part_size,
+            &format!("[1/1] - &quot;test.bin&quot; yEnc (1/{})", parts),
+        )
+    }
+
+    fn build(parts: usize, part_size: usize, subject: &str) -> Self {
+        let listing: Vec<(usize, u32)> = (0..parts).map(|i| (i, i as u32 + 1)).collect();
+        Self::listed(parts, part_size, subject, &listing)
+    }
+
+    /// A post whose NZB lists its parts as `(0-based part, segment number)`,
+    /// in that order
+    fn listed(parts: usize, part_size: usize, subject: &str, listing: &[(usize, u32)]) -> Self {
+        let data: Vec<u8> = (0..parts * part_size)
+            .map(|i| (i * 7 % 256) as u8)
+            .collect();
+        let mut articles = Vec::new();
+        for (index, chunk) in data.chunks(part_size).enumerate() {
+            let id = format!("part{}of{}@test", index + 1, parts);
+            let body = yenc_part(
+                "test.bin",
+                chunk,
+                index + 1,
+                parts,
+                index * part_size + 1,
+                data.len(),
+                crc32fast::hash(chunk),
+            );
+            articles.push((id, body));
+        }
+        let segments: String = listing
+            .iter()
+            .map(|&(index, number)| {
+                let (id, body) = &articles[index];
+                format!(
+                    r#"<segment bytes="{}" number="{}">{}</segment>"#,
+                    body.len(),
+                    number,
+                    id
+                )
+            })
+            .collect();
+        let xml = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<nzb xmlns="http://www.newzbin.com/DTD/2003/nzb">
+  <file poster="poster@test" date="{}" subject="{}">
+    <groups><group>alt.binaries.test</group></groups>
+    <segments>{}</segments>
+  </file>
+</nzb>"#,
+            POSTED, subject, segments
+        );
+        Self {
+            data,
+            articles,
+            nzb: xml.parse().unwrap(),
+            xml,
+        }
+    }
+
+    /// Put every article on `server`
+    fn serve_on(&self, server: &MockNntp) {
+        for (id, body) in &self.articles {
+            server.add_article(id, body.clone());
+        }
+    }
+}
+
+fn config(server: &MockNntp, dir: &std::path::Path) -> Config {
+    let mut config = Config {
+        usenet: server.config(),
+        ..Config::default()
+    };
+    config.download.dir = dir.to_path_buf();
+    config.tuning.pipeline_size = 2;
+    config.tuning.connection_wait_timeout = 5;
+    config
+}
+
+#[tokio::test]
+async fn test_download_assembles_file() {
+    let post = Post::new(5);
+    let server = MockNntp::start();
+    post.serve_on(&server);
+    let dir = tempfile::tempdir().unwrap();
+    let config = config(&server, dir.path());
+
+    // A pool made by the caller, as when one is shared between downloaders
+    let pool = NntpPoolBuilder::new(config.usenet.clone())
+        .max_size(2)
+        .build()
+        .unwrap();
+    let downloader = Downloader::builder(config.clone())
+        .pool(pool)
+        .build()
+        .await
+        .unwrap();
+    let download = downloader.download_nzb(&post.nzb, config).await.unwrap();
+
+    let result = &download.results[0];
+    assert_eq!(result.segments_downloaded, 5);
+    assert_eq!(result.segments_failed, 0);
+    assert_eq!(
+        std::fs::read(dir.path().join("test.bin")).unwrap(),
+        post.data
+    );
+    assert_eq!(downloader.server_stats()[0].articles, 5);
+    assert!(server.connections() <= 2);
+}
+
+#[tokio::test]
+async fn test_one_shot_download() {
+    let post = Post::new(3);
+    let server = MockNntp::start();
+    post.serve_on(&server);
+    let dir = tempfile::tempdir().unwrap();
+    let nzb_path = dir.path().join("Some.Release.nzb");
+    std::fs::write(&nzb_path, &post.xml).unwrap();
+    let mut config = config(&server, &dir.path().join("downloads"));
+    config.download.create_subfolders = true;
+
+    let options = DownloadOptions::new().config(config).post_process(false);
+    let outcome = dl_nzb::download(&nzb_path, options).await.unwrap();
+
+    assert_eq!(outcome.name, "Some.Release");
+    assert_eq!(
+        outcome.output_dir,
+        dir.path().join("downloads/Some.Release")
+    );
+    assert!(outcome.post_processing.is_none());
+    assert!(outcome.is_complete());
+    assert_eq!(
+        std::fs::read(outcome.output_dir.join("test.bin")).unwrap(),
+        post.data
+    );
+}
+
+#[tokio::test]
+async fn test_unusable_segment_numbers() {
+    let subject = |parts| format!("[1/1] - &quot;test.bin&quot; yEnc (1/{})", parts);
+    // Every segment numbered 0 and listed out of order, so only the yEnc
+    // part offsets say where each one goes
+    let zeroed = Post::listed(
+        5,
+        PART_SIZE,
+        &subject(5),
+        &[(2, 0), (0, 0), (4, 0), (1, 0), (3, 0)],
+    );
+    // Numbers starting over halfway through, as for a post in two halves
+    let restarted = Post::listed(4, PART_SIZE, &subject(4), &[(0, 1), (1, 2), (2, 1), (3, 2)]);
+
+    let server = MockNntp::start();
+    for post in [zeroed, restarted] {
+        post.serve_on(&server);
+        assert!(post.nzb.validate().files[0].unusable_numbering);
+
+        let dir = tempfile::tempdir().unwrap();
+        let config = config(&server, dir.path());
+        let downloader = Downloader::builder(config.clone()).build().await.unwrap();
+        let download = downloader.download_nzb(&post.nzb, config).await.unwrap();
+
+        assert_eq!(download.results[0].segments_failed, 0);
+        assert_eq!(
+            std::fs::read(dir.path().join("test.bin")).unwrap(),
+            post.data
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_download_over_tls() {
+    let post = Post::new(3);
+    let server = MockNntp::start_tls();
+    post.serve_on(&server);
+    let dir = tempfile::tempdir().unwrap();
+    let config = config(&server, dir.path());
+
+    let downloader = Downloader::new(config.clone()).await.unwrap();
+    let download = downloader.download_nzb(&post.nzb, config).await.unwrap();
+
+    assert_eq!(download.results[0].segments_downloaded, 3);
+    assert_eq!(
+        std::fs::read(dir.path().join("test.bin")).unwrap(),
+        post.data
+    );
+}
+
+#[tokio::test]
+async fn test_probe_logs_in_before_downloading() {
+    let server = MockNntp::start();
+    let dir = tempfile::tempdir().unwrap();
+    let config = config(&server, dir.path());
+
+    let downloader = Downloader::new(config.clone()).await.unwrap();
+    let probe = downloader.probe().await.unwrap().unwrap();
+    assert_eq!(probe.greeting, "mock server ready");
+    assert_eq!(server.logins(), 1);
+
+    // A refused login comes back as such, not as a failed connection
+    server.require_password("something else");
+    let downloader = Downloader::new(config).await.unwrap();
+    assert!(matches!(
+        downloader.probe().await,
+        Err(DlNzbError::Nntp(NntpError::AuthFailed(_)))
+    ));
+}
+
+#[tokio::test]
+async fn test_preview_fetches_the_start_of_the_file() {
+    let post = Post::new(10);
+    let server = MockNntp::start();
+    post.serve_on(&server);
+    let dir = tempfile::tempdir().unwrap();
+    let config = config(&server, dir.path());
+
+    // Two and a half articles' worth stops in the third
+    let article = post.articles[0].1.len() as u64;
+    let files = preview_files(post.nzb.files(), article * 5 / 2);
+    let downloader = Downloader::new(config.clone()).await.unwrap();
+    let download = downloader.download_files(&files, config).await.unwrap();
+
+    assert_eq!(server.article_requests(), 3);
+    let preview = Preview::gather(dir.path(), post.nzb.files(), &download.results);
+    assert_eq!(preview.files.len(), 1);
+    let file = &preview.files[0];
+    assert_eq!((file.segments, file.total_segments), (3, 10));
+    assert!(!file.complete());
+    assert_eq!(
+        std::fs::read(dir.path().join("test.bin")).unwrap(),
+        post.data[..3 * PART_SIZE]
+    );
+}
+
+#[tokio::test]
+async fn test_nothing_to_download_is_an_empty_nzb() {
+    let server = MockNntp::start();
+    let dir = tempfile::tempdir().unwrap();
+    let config = config(&server, dir.path());
+
+    let downloader = Downloader::new(config.clone()).await.unwrap();
+    assert!(matches!(
+        downloader
+            .download_files(&[], config)
+            .await
+            .map_err(|e| e.error),
+        Err(DlNzbError::Nzb(NzbError::EmptyNzb(_)))
+    ));
+    assert_eq!(server.article_requests(), 0);
+}
+
+#[tokio::test]
+async fn test_auto_connections_start_low() {
+    let post = Post::new(12);
+    let server = MockNntp::start();
+    post.serve_on(&server);
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = config(&server, dir.path());
+    config.usenet.connections = 8;
+    config.usenet.auto_connections = true;
+
+    // As if an earlier run had settled on 3
+    let downloader = Downloader::builder(config.clone())
+        .tuned_start(3)
+        .build()
+        .await
+        .unwrap();
+    let download = downloader.download_nzb(&post.nzb, config).await.unwrap();
+
+    assert_eq!(download.results[0].segments_downloaded, 12);
+    let connections = downloader.connections().unwrap();
+    // Far too short a download for a measurement window to pass
+    assert_eq!((connections.limit(), connections.max()), (3, 8));
+    assert_eq!(connections.active(), 0);
+    assert!(server.connections() <= 3);
+    assert_eq!(downloader.stats().server_metrics()[0].connections_limit, 3);
+}
+
+#[tokio::test]
+async fn test_missing_articles_are_reported() {
+    let post = Post::new(4);
+    let server = MockNntp::start();
+    post.serve_on(&server);
+    server.fail("part2of4@test", Failure::Missing);
+    server.fail("part4of4@test", Failure::Missing);
+    let dir = tempfile::tempdir().unwrap();
+    let config = config(&server, dir.path());
+
+    let downloader = Downloader::new(config.clone()).await.unwrap();
+    let download = downloader.download_nzb(&post.nzb, config).await.unwrap();
+
+    let result = &download.results[0];
+    assert_eq!(result.segments_downloaded, 2);
+    assert_eq!(result.segments_failed, 2);
+    assert_eq!(result.segments_missing, 2);
+    let mut failed = result.failed_message_ids.clone();
+    failed.sort();
+    assert_eq!(failed, ["part2of4@test", "part4of4@test"]);
+}
+
+#[tokio::test]
+async fn test_missing_group_is_flagged_or_skipped() {
+    let post = Post::new(2);
+    let server = MockNntp::start();
+    post.serve_on(&server);
+    server.remove_group("alt.binaries.test");
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = config(&server, dir.path());
+
+    // Flagged, and tried anyway
+    let downloader = Downloader::new(config.clone()).await.unwrap();
+    let download = downloader
+        .download_nzb(&post.nzb, config.clone())
+        .await
+        .unwrap();
+    assert_eq!(download.missing_groups, ["alt.binaries.test"]);
+    assert_eq!(download.results[0].segments_failed, 2);
+
+    // Skipped, leaving nothing to download
+    config.download.skip_unavailable_groups = true;
+    let requests = server.article_requests();
+    let downloader = Downloader::new(config.clone()).await.unwrap();
+    let aborted = downloader
+        .download_nzb(&post.nzb, config)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        aborted.error,
+        DlNzbError::Download(DownloadError::InsufficientSegments { .. })
+    ));
+    // What was left out comes back with the error
+    assert!(aborted.download.results.is_empty());
+    assert_eq!(aborted.download.skipped.len(), 1);
+    assert_eq!(
+        aborted.download.skipped[0].reason,
+        SkipReason::GroupUnavailable
+    );
+    assert_eq!(aborted.download.missing_groups, ["alt.binaries.test"]);
+    assert_eq!(server.article_requests(), requests);
+}
+
+#[tokio::test]
+async fn test_gives_up_on_file_with_articles_gone() {
+    let post = Post::new(20);
+    let server = MockNntp::start();
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = config(&server, dir.path());
+    config.tuning.give_up_after_missing = 4;
+
+    let downloader = Downloader::new(config.clone()).await.unwrap();
+    let download = downloader.download_nzb(&post.nzb, config).await.unwrap();
+
+    let result = &download.results[0];
+    assert_eq!(result.segments_failed, 20);
+    assert_eq!(result.segments_missing, 20);
+    assert_eq!(result.failed_message_ids.len(), 20);
+    // Two batches of two in flight when it tripped, and maybe one more
+    // already started; the rest were never asked for
+    assert!(
+        server.article_requests() <= 8,
+        "{}",
+        server.article_requests()
+    );
+}
+
+#[tokio::test]
+async fn test_stalled_file_is_given_up_on() {
+    let fast = Post::new(4);
+    let slow = Post::with_subject(6, "[1/1] - &quot;slow.bin&quot; yEnc (1/6)");
+    let server = MockNntp::start();
+    fast.serve_on(&server);
+    slow.serve_on(&server);
+    // A line every two seconds: its batch would take the better part of a minute
+    server.fail("part5of6@test", Failure::Drip(Duration::from_secs(2)));
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = config(&server, dir.path());
+    config.download.stall_timeout = 1;
+
+    let nzb = Nzb::merge([slow.nzb, fast.nzb]).unwrap();
+    let started = std::time::Instant::now();
+    let downloader = Downloader::new(config.clone()).await.unwrap();
+    let download = downloader.download_nzb(&nzb, config).await.unwrap();
+    assert!(
+        started.elapsed() < Duration::from_secs(10),
+        "{:?}",
+        started.elapsed()
+    );
+
+    let result = |name: &str| {
+        download
+            .results
+            .iter()
+            .find(|r| r.filename == name)
+            .unwrap()
+    };
+    assert_eq!(result("test.bin").segments_failed, 0);
+    // The batch dripping in was abandoned, and its segments left to PAR2
+    let stalled = result("slow.bin");
+    assert_eq!(stalled.segments_downloaded, 4);
+    let mut failed = stalled.failed_message_ids.clone();
+    failed.sort();
+    assert_eq!(failed, ["part5of6@test", "part6of6@test"]);
+}
+
+#[tokio::test]
+async fn test_incomplete_file_is_removed() {
+    let post = Post::new(3);
+    let server = MockNntp::start();
+    post.serve_on(&server);
+    server.fail("part2of3@test", Failure::Missing);
+    let dir = tempfile::tempdir().unwrap();
+    let config = config(&server, dir.path());
+
+    let downloader = Downloader::new(config.clone()).await.unwrap();
+    let download = downloader.download_nzb(&post.nzb, config).await.unwrap();
+    let partial = Downloader::cleanup_partial_files(dir.path(), &download.results, false).await;
+
+    assert_eq!(partial.removed, [dir.path().join("test.bin")]);
+    assert!(partial.kept.is_empty());
+    assert!(!dir.path().join("test.bin").exists());
+    assert!(!dir.path().join(FAILED_MARKER).exists());
+}
+
+#[tokio::test]
+async fn test_dry_run_only_stats_articles() {
+    let post = Post::new(4);
+    let server = MockNntp::start();
+    post.serve_on(&server);
+    server.fail("part2of4@test", Failure::Missing);
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = config(&server, dir.path());
+    config.download.create_subfolders = true;
+    let loaded = LoadedNzb {
+        nzb: post.nzb.clone(),
+        name: "Some.Post".to_string(),
+        xml: None,
+    };
+
+    let plan = Plan::new(
+        "post.nzb".as_ref(),
+        &loaded,
+        &config,
+        None,
+        None,
+        Some(1000),
+    )
+    .await;
+
+    assert_eq!(plan.output_dir, dir.path().join("Some.Post"));
+    assert_eq!(plan.files[0].action, FileAction::Download);
+    let availability = plan.availability.as_ref().unwrap();
+    assert_eq!((availability.sampled, availability.missing), (4, 1));
+    // Without PAR2 nothing makes up for the missing segment
+    assert!(!plan.viable);
+    assert_eq!(server.article_requests(), 4);
+    assert!(!dir.path().join("Some.Post").exists());
+
+    // With the file in place there is nothing left to check on the server
+    let size = plan.files[0].size as usize;
+    std::fs::create_dir(dir.path().join("Some.Post")).unwrap();
+    std::fs::write(dir.path().join("Some.Post/test.bin"), vec![0; size]).unwrap();
+    let plan = Plan::new("post.nzb".as_ref(), &loaded, &config, None, None, None).await;
+    assert_eq!(plan.files[0].action, FileAction::Skip);
+    assert_eq!(plan.download_size, 0);
+    assert!(plan.availability.is_none());
+    assert!(plan.viable);
+}
+
+#[tokio::test]
+async fn test_quick_hash_rejects_right_sized_wrong_file() {
+    let post = Post::new(3);
+    let server = MockNntp::start();
+    post.serve_on(&server);
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = config(&server, dir.path());
+    config.download.skip_verification = SkipVerification::QuickHash;
+    // An earlier run left zeros of exactly the declared size
+    let declared: u64 = post.nzb.files()[0]
+        .segments
+        .segment
+        .iter()
+        .map(|s| s.bytes)
+        .sum();
+    std::fs::write(dir.path().join("test.bin"), vec![0; declared as usize]).unwrap();
+
+    let downloader = Downloader::new(config.clone()).await.unwrap();
+    let download = downloader
+        .download_nzb(&post.nzb, config.clone())
+        .await
+        .unwrap();
+    assert_eq!(download.results[0].skipped, None);
+    assert_eq!(server.article_requests(), 3);
+    assert_eq!(
+        std::fs::read(dir.path().join("test.bin")).unwrap(),
+        post.data
+    );
+
+    let hashes: std::collections::BTreeMap<String, QuickHash> =
+        serde_json::from_slice(&std::fs::read(dir.path().join(HASHES_FILE)).unwrap()).unwrap();
+    assert_eq!(hashes["test.bin"].size, post.data.len() as u64);
+
+    // Recorded as it finished, so the next run skips it verified
+    let download = downloader.download_nzb(&post.nzb, config).await.unwrap();
+    assert_eq!(
+        download.results[0].skipped,
+        Some(SkipVerification::QuickHash)
+    );
+    assert_eq!(server.article_requests(), 3);
+}
+
+#[tokio::test]
+async fn test_kept_incomplete_file_is_fetched_again() {
+    let post = Post::new(3);
+    let server = MockNntp::start();
+    post.serve_on(&server);
+    server.fail("part2of3@test", Failure::Missing);
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = config(&server, dir.path());
+    config.download.keep_partial = true;
+
+    let downloader = Downloader::new(config.clone()).await.unwrap();
+    let download = downloader
+        .download_nzb(&post.nzb, config.clone())
+        .await
+        .unwrap();
+    let partial = Downloader::cleanup_partial_files(dir.path(), &download.results, true).await;
+
+    assert_eq!(partial.kept, [dir.path().join("test.bin")]);
+    assert!(partial.removed.is_empty());
+    let marker: std::collections::BTreeMap<String, Vec<String>> =
+        serde_json::from_slice(&std::fs::read(dir.path().join(FAILED_MARKER)).unwrap()).unwrap();
+    assert_eq!(marker["test.bin"], ["part2of3@test"]);
+
+    // Once the article turns up, the marked file isn't skipped as done
+    let server = MockNntp::start();
+    post.serve_on(&server);
+    let mut config = self::config(&server, dir.path());
+    config.download.keep_partial = true;
+    let downloader = Downloader::new(config.clone()).await.unwrap();
+    let download = downloader.download_nzb(&post.nzb, config).await.unwrap();
+    let partial = Downloader::cleanup_partial_files(dir.path(), &download.results, true).await;
+
+    // Resumed: only the missing article was asked for
+    assert_eq!(server.article_requests(), 1);
+    assert_eq!(download.results[0].existing, Some(ExistingFile::Resumed));
+    assert_eq!(download.results[0].segments_downloaded, 3);
+    assert!(partial.is_empty());
+    assert_eq!(
+        std::fs::read(dir.path().join("test.bin")).unwrap(),
+        post.data
+    );
+    assert!(!dir.path().join(FAILED_MARKER).exists());
+}
+
+#[tokio::test]
+async fn test_corrupt_articles_are_kept() {
+    let post = Post::new(3);
+    let server = MockNntp::start();
+    post.serve_on(&server);
+    let chunk = &post.data[PART_SIZE..2 * PART_SIZE];
+    server.add_article(
+        "part2of3@test",
+        yenc_part(
+            "test.bin",
+            chunk,
+            2,
+            3,
+            PART_SIZE + 1,
+            post.data.len(),
+            0xdeadbeef,
+        ),
+    );
+    let dir = tempfile::tempdir().unwrap();
+    let config = config(&server, dir.path());
+
+    let downloader = Downloader::new(config.clone()).await.unwrap();
+    let download = downloader.download_nzb(&post.nzb, config).await.unwrap();
+
+    // The data is written anyway, for PAR2 to judge
+    let result = &download.results[0];
+    assert_eq!(result.segments_downloaded, 3);
+    assert_eq!(result.segments_corrupt, 1);
+    assert_eq!(result.segments_failed, 0);
+    assert_eq!(
+        std::fs::read(dir.path().join("test.bin")).unwrap(),
+        post.data
+    );
+}
+
+#[tokio::test]
+async fn test_cut_off_body_is_not_kept() {
+    let post = Post::new(2);
+    let server = MockNntp::start();
+    post.serve_on(&server);
+    server.fail("part2of2@test", Failure::Disconnect);
+    let dir = tempfile::tempdir().unwrap();
+    let config = config(&server, dir.path());
+
+    let downloader = Downloader::new(config.clone()).await.unwrap();
+    let download = downloader.download_nzb(&post.nzb, config).await.unwrap();
+
+    // Half a body is a failed segment, not a short one
+    let result = &download.results[0];
+    assert_eq!(result.segments_downloaded, 1);
+    assert_eq!(result.failed_message_ids, ["part2of2@test"]);
+}
+
+#[tokio::test]
+async fn test_idle_timeout_resumes_on_fresh_connection() {
+    let post = Post::new(3);
+    let server = MockNntp::start();
+    post.serve_on(&server);
+    server.fail("part2of3@test", Failure::IdleTimeout);
+    let dir = tempfile::tempdir().unwrap();
+    let config = config(&server, dir.path());
+
+    let downloader = Downloader::new(config.clone()).await.unwrap();
+    let download = downloader.download_nzb(&post.nzb, config).await.unwrap();
+
+    // The server closing the connection fails nothing: what it left
+    // unanswered is asked again on another
+    let result = &download.results[0];
+    assert_eq!(result.segments_downloaded, 3);
+    assert!(result.failed_message_ids.is_empty());
+    assert_eq!(
+        std::fs::read(dir.path().join("test.bin")).unwrap(),
+        post.data
+    );
+    assert!(server.connections() >= 2);
+}
+
+#[tokio::test]
+async fn test_clock_drives_retention_routing() {
+    let post = Post::new(2);
+    let primary = MockNntp::start();
+    let backup = MockNntp::start();
+    post.serve_on(&backup);
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = config(&primary, dir.path());
+    config.usenet.retention_days = 30;
+    config.servers.push(backup.config());
+
+    // Ten days after posting the primary should still have it, so it's asked
+    // first; by the real clock the post is long past its retention
+    let now = UNIX_EPOCH + Duration::from_secs(POSTED + 10 * 86_400);
+    let downloader = Downloader::builder(config.clone())
+        .clock(move || now)
+        .build()
+        .await
+        .unwrap();
+    let download = downloader.download_nzb(&post.nzb, config).await.unwrap();
+
+    assert_eq!(download.results[0].segments_downloaded, 2);
+    assert_eq!(primary.article_requests(), 2);
+    assert_eq!(backup.article_requests(), 2);
+}
+
+#[tokio::test]
+async fn test_bytes_counted_per_server() {
+    let post = Post::new(4);
+    let primary = MockNntp::start();
+    let backup = MockNntp::start();
+    // The primary lost the last part; the backup has everything
+    for (id, body) in &post.articles[..3] {
+        primary.add_article(id, body.clone());
+    }
+    post.serve_on(&backup);
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = config(&primary, dir.path());
+    // Told apart by name, as servers on different hosts are
+    let mut backup_config = backup.config();
+    backup_config.server = "localhost".to_string();
+    config.servers.push(backup_config);
+
+    let downloader = Downloader::new(config.clone()).await.unwrap();
+    let download = downloader.download_nzb(&post.nzb, config).await.unwrap();
+
+    assert_eq!(download.results[0].segments_failed, 0);
+    let by_server = download.bytes_by_server();
+    assert_eq!(by_server["127.0.0.1"], 3 * PART_SIZE as u64);
+    assert_eq!(by_server["localhost"], PART_SIZE as u64);
+    assert_eq!(download.results[0].bytes_by_server, by_server);
+}
+
+#[tokio::test]
+async fn test_nameless_subject_uses_yenc_name() {
+    // An obfuscated subject with no quoted name
+    let post = Post::with_subject(3, "a1b2c3d4e5f60718293a (1/3)");
+    let server = MockNntp::start();
+    post.serve_on(&server);
+    let dir = tempfile::tempdir().unwrap();
+    let config = config(&server, dir.path());
+
+    let downloader = Downloader::new(config.clone()).await.unwrap();
+    let download = downloader.download_nzb(&post.nzb, config).await.unwrap();
+
+    let result = &download.results[0];
+    assert_eq!(result.filename, "test.bin");
+    assert_eq!(result.segments_downloaded, 3);
+    assert_eq!(
+        std::fs::read(dir.path().join("test.bin")).unwrap(),
+        post.data
+    );
+}
+
+#[tokio::test]
+async fn test_unwritable_folder_fails_before_connecting() {
+    let post = Post::new(2);
+    let server = MockNntp::start();
+    post.serve_on(&server);
+    let dir = tempfile::tempdir().unwrap();
+    let blocker = dir.path().join("blocker");
+    std::fs::write(&blocker, b"").unwrap();
+    let config = config(&server, &blocker.join("downloads"));
+
+    let downloader = Downloader::new(config.clone()).await.unwrap();
+    let err = downloader
+        .download_nzb(&post.nzb, config)
+        .await
+        .unwrap_err()
+        .error;
+
+    assert!(
+        matches!(
+            err,
+            DlNzbError::Download(DownloadError::WriteError { ref path, .. })
+                if path.ends_with("downloads")
+        ),
+        "{}",
+        err
+    );
+    assert_eq!(server.connections(), 0);
+}
+
+#[tokio::test]
+async fn test_memory_budget_caps_decoded_segments() {
+    // Segments two hundred times the usual test size against a 1 MiB budget,
+    // with more connections than the budget has room for
+    let post = Post::sized(16, 200 * 1024);
+    let server = MockNntp::start();
+    post.serve_on(&server);
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = config(&server, dir.path());
+    config.usenet.connections = 8;
+    config.memory.max_buffer_mb = 1;
+
+    let downloader = Downloader::new(config.clone()).await.unwrap();
+    let download = downloader.download_nzb(&post.nzb, config).await.unwrap();
+
+    assert_eq!(download.results[0].segments_downloaded, 16);
+    assert_eq!(
+        std::fs::read(dir.path().join("test.bin")).unwrap(),
+        post.data
+    );
+    let memory = downloader.memory();
+    assert!(memory.peak() > 0);
+    assert!(
+        memory.peak() <= memory.capacity(),
+        "{} bytes held under a {} byte budget",
+        memory.peak(),
+        memory.capacity()
+    );
+    // Everything written has given its share back
+    assert_eq!(memory.in_use(), 0);
+    assert_eq!(memory.reserved(), 0);
+}
+
+#[cfg(unix)]
+async fn download_over(
+    server: &MockNntp,
+    dir: &std::path::Path,
+    nzb: &Nzb,
+    existing_files: ExistingFiles,
+) -> DownloadResult {
+    let mut config = config(server, dir);
+    config.download.existing_files = existing_files;
+    let downloader = Downloader::new(config.clone()).await.unwrap();
+    let mut download = downloader.download_nzb(nzb, config).await.unwrap();
+    download.results.remove(0)
+}
+
+#[tokio::test]
+async fn test_file_there_at_another_size_follows_existing_files() {
+    let post = Post::new(2);
+    let server = MockNntp::start();
+    post.serve_on(&server);
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("test.bin");
+    let old = dir.path().join("test.bin.old");
+
+    // Kept as it is, and nothing fetched
+    std::fs::write(&path, b"someone else's file").unwrap();
+    let result = download_over(&server, dir.path(), &post.nzb, ExistingFiles::Skip).await;
+    assert_eq!(result.existing, Some(ExistingFile::Kept));
+    assert_eq!(result.skip_reason(), Some(SkipReason::KeptExisting));
+    assert_eq!(std::fs::read(&path).unwrap(), b"someone else's file");
+    assert_eq!(server.article_requests(), 0);
+
+    // Moved aside before anything is written
+    let result = download_over(&server, dir.path(), &post.nzb, ExistingFiles::Rename).await;
+    assert_eq!(result.existing, Some(ExistingFile::Renamed(old.clone())));
+    assert_eq!(std::fs::read(&old).unwrap(), b"someone else's file");
+    assert_eq!(std::fs::read(&path).unwrap(), post.data);
+
+    // At its size now: skipped as complete, whatever the policy
+    let result = download_over(&server, dir.path(), &post.nzb, ExistingFiles::Rename).await;
+    assert_eq!(result.existing, None);
+    assert_eq!(result.skip_reason(), Some(SkipReason::AlreadyComplete));
+    assert!(!dir.path().join("test.bin.old2").exists());
+
+    std::fs::write(&path, b"short").unwrap();
+    let result = download_over(&server, dir.path(), &post.nzb, ExistingFiles::Overwrite).await;
+    assert_eq!(result.existing, Some(ExistingFile::Overwritten));
+    assert_eq!(result.skip_reason(), None);
+    assert_eq!(std::fs::read(&path).unwrap(), post.data);
+    assert!(!dir.path().join("test.bin.old2").exists());
+}
+
+#[tokio::test]
+async fn test_configured_modes_are_applied() {
+    use dl_nzb::download::Permissions;
+    use dl_nzb::processing::{Manifest, PostProcessor};
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode =
+        |path: &std::path::Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o7777;
+
+    let post = Post::new(2);
+    let server = MockNntp::start();
+    post.serve_on(&server);
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("Some.Release");
+    let mut config = config(&server, &output);
+    config.download.dir_mode = Some("0751".to_string());
+    config.download.file_mode = Some("0604".to_string());
+    config.post_processing.auto_par2_repair = false;
+    config.post_processing.auto_extract_rar = false;
+
+    let downloader = Downloader::new(config.clone()).await.unwrap();
+    let download = downloader
+        .download_nzb(&post.nzb, config.clone())
+        .await
+        .unwrap();
+
+    assert_eq!(mode(&output), 0o751);
+    assert_eq!(mode(&output.join("test.bin")), 0o604);
+
+    // What post-processing leaves behind, as extraction would
+    std::fs::create_dir(output.join("Subs")).unwrap();
+    std::fs::write(output.join("Subs").join("test.srt"), b"1").unwrap();
+    PostProcessor::new(config.post_processing.clone(), u64::MAX)
+        .with_permissions(Permissions::from_config(&config.download).unwrap())
+        .process_downloads(&Manifest::from_results(&download.results))
+        .await
+        .unwrap();
+
+    assert_eq!(mode(&output.join("Subs")), 0o751);
+    assert_eq!(mode(&output.join("Subs").join("test.srt")), 0o604);
+}
+
+#[tokio::test]
+async fn test_shutdown_drains_batch_in_flight_and_keeps_file() {
+    use dl_nzb::shutdown::Shutdown;
+
+    let post = Post::new(6);
+    let server = MockNntp::start();
+    post.serve_on(&server);
+    server.fail("part3of6@test", Failure::Drip(Duration::from_millis(20)));
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = config(&server, dir.path());
+    config.usenet.connections = 1;
+
+    let shutdown = Shutdown::new();
+    let downloader = Downloader::builder(config.clone())
+        .shutdown(shutdown.clone())
+        .build()
+        .await
+        .unwrap();
+    // Trip once the second batch is on the wire
+    let trip = async {
+        while server.article_requests() < 3 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        shutdown.trigger();
+    };
+    let (download, ()) = tokio::join!(downloader.download_nzb(&post.nzb, config.clone()), trip);
+    let aborted = download.unwrap_err();
+    downloader.close().await;
+
+    assert!(matches!(
+        aborted.error,
+        DlNzbError::Download(DownloadError::Cancelled)
+    ));
+    // The file written so far comes back with the error, as a partial one
+    let [result] = &aborted.download.results[..] else {
+        panic!("{:?}", aborted.download.results);
+    };
+    assert_eq!(result.filename, "test.bin");
+    assert_eq!((result.segments_downloaded, result.segments_failed), (4, 2));
+    // The batch in flight finished; the one after it was never asked for
+    let marker: std::collections::BTreeMap<String, Vec<String>> =
+        serde_json::from_slice(&std::fs::read(dir.path().join(FAILED_MARKER)).unwrap()).unwrap();
+    assert_eq!(marker["test.bin"], ["part5of6@test", "part6of6@test"]);
+    assert_eq!(server.article_requests(), 4);
+
+    // The next run fetches what's left
+    let downloader = Downloader::new(config.clone()).await.unwrap();
+    let download = downloader.download_nzb(&post.nzb, config).await.unwrap();
+    assert_eq!(download.results[0].segments_downloaded, 6);
+    assert_eq!(
+        std::fs::read(dir.path().join("test.bin")).unwrap(),
+        post.data
+    );
+    assert!(!dir.path().join(FAILED_MARKER).exists());
+}
+
+#[tokio::test]
+async fn test_panic_in_one_file_fails_only_that_file() {
+    use dl_nzb::progress::{ProgressSink, SegmentsDone};
+    use std::sync::Arc;
+
+    /// Panics as the segments of `bad.bin` come in, as a decoding slip would
+    struct PanicOnDecode;
+
+    impl ProgressSink for PanicOnDecode {
+        fn on_segments(&self, done: &SegmentsDone<'_>) {
+            if done.filename == "bad.bin" {
+                panic!("segment offset out of range");
+            }
+        }
+    }
+
+    let good = Post::with_subject(3, "[1/2] - &quot;good.bin&quot; yEnc (1/3)");
+    let bad = Post::with_subject(2, "[2/2] - &quot;bad.bin&quot; yEnc (1/2)");
+    let server = MockNntp::start();
+    good.serve_on(&server);
+    bad.serve_on(&server);
+    let file_of = |xml: &str| {
+        let start = xml.find("<file").unwrap();
+        let end = xml.rfind("</file>").unwrap() + "</file>".len();
+        xml[start..end].to_string()
+    };
+    let nzb: Nzb = format!(
+        r#"<nzb xmlns="http://www.newzbin.com/DTD/2003/nzb">{}{}</nzb>"#,
+        file_of(&good.xml),
+        file_of(&bad.xml)
+    )
+    .parse()
+    .unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let config = config(&server, dir.path());
+
+    let downloader = Downloader::builder(config.clone())
+        .progress(Arc::new(PanicOnDecode))
+        .build()
+        .await
+        .unwrap();
+    let download = downloader.download_nzb(&nzb, config).await.unwrap();
+
+    assert_eq!(download.results.len(), 1);
+    assert_eq!(download.results[0].filename, "good.bin");
+    assert_eq!(
+        std::fs::read(dir.path().join("good.bin")).unwrap(),
+        good.data
+    );
+    assert_eq!(download.failures.len(), 1);
+    assert_eq!(download.failures[0].file.as_deref(), Some("bad.bin"));
+    assert!(download.failures[0]
+        .message
+        .contains("panicked: segment offset out of range"));
+}
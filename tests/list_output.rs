@@ -0,0 +1,156 @@
+//! `-l` output stays byte-for-byte the same from run to run
+//!
+//! Files are listed in NZB order and every collection in the JSON document
+//! has a fixed order, so runs can be diffed. These snapshots catch changes
+//! to either; update them deliberately when the output is meant to change.
+
+use std::path::Path;
+use std::process::{Command, Output};
+
+const NZB: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<nzb xmlns="http://www.newzbin.com/DTD/2003/nzb">
+  <file poster="p@example.com" date="0" subject="&quot;show.nfo&quot; yEnc (1/1)">
+    <groups><group>alt.binaries.test</group></groups>
+    <segments><segment bytes="512" number="1">n1@example.com</segment></segments>
+  </file>
+  <file poster="p@example.com" date="0" subject="&quot;show.mkv&quot; yEnc (1/2)">
+    <groups><group>alt.binaries.test</group></groups>
+    <segments>
+      <segment bytes="1024" number="1">m1@example.com</segment>
+      <segment bytes="1024" number="2">m2@example.com</segment>
+    </segments>
+  </file>
+  <file poster="p@example.com" date="0" subject="&quot;extra.mkv&quot; yEnc (1/1)">
+    <groups><group>alt.binaries.test</group></groups>
+    <segments><segment bytes="2048" number="1">e1@example.com</segment></segments>
+  </file>
+</nzb>"#;
+
+/// Run `dl-nzb -l` on the NZB above with `args`, from a fresh folder
+fn list(dir: &Path, args: &[&str]) -> Output {
+    let config = dir.join("config.toml");
+    std::fs::write(
+        &config,
+        format!(
+            "[usenet]\nserver = \"127.0.0.1\"\nport = 119\nssl = false\nusername = \"u\"\npassword = \"p\"\n\n[download]\ndir = {:?}\n",
+            dir.join("downloads")
+        ),
+    )
+    .unwrap();
+    std::fs::write(dir.join("show.nzb"), NZB).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_dl-nzb"))
+        .arg("--config")
+        .arg(&config)
+        .arg("-l")
+        .args(args)
+        .arg("show.nzb")
+        .current_dir(dir)
+        .env("HOME", dir)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+#[test]
+fn list_output_snapshot() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = list(dir.path(), &["--exclude", "*.nfo"]);
+    let expected = format!(
+        "
+📄 show.nzb
+{}
+Total files: 2
+Total size: 4 KiB
+Total segments: 3
+Skipped: 1 (1 filtered)
+
+Files:
+     2  [DATA] show.mkv (2 KiB, 2 segments)
+     3  [DATA] extra.mkv (2 KiB, 1 segment)
+
+Download a subset with --select, e.g. --select 1,3-5
+",
+        "─".repeat(50)
+    );
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+}
+
+#[test]
+fn list_json_snapshot() {
+    let dir = tempfile::tempdir().unwrap();
+    let first = list(
+        dir.path(),
+        &["--json", "--show-order", "--exclude", "*.nfo"],
+    );
+    let expected = r#"[
+  {
+    "version": 1,
+    "file": "show.nzb",
+    "meta": {
+      "title": null,
+      "category": null,
+      "has_password": false
+    },
+    "total_files": 2,
+    "total_size": 4096,
+    "total_segments": 3,
+    "exceeds_retention": false,
+    "files": [
+      {
+        "index": 2,
+        "subject": "\"show.mkv\" yEnc (1/2)",
+        "filename": "show.mkv",
+        "size": 2048,
+        "segments": 2,
+        "poster": "p@example.com",
+        "date": 0,
+        "is_par2": false,
+        "par2": "none",
+        "groups": [
+          "alt.binaries.test"
+        ]
+      },
+      {
+        "index": 3,
+        "subject": "\"extra.mkv\" yEnc (1/1)",
+        "filename": "extra.mkv",
+        "size": 2048,
+        "segments": 1,
+        "poster": "p@example.com",
+        "date": 0,
+        "is_par2": false,
+        "par2": "none",
+        "groups": [
+          "alt.binaries.test"
+        ]
+      }
+    ],
+    "skipped": [
+      {
+        "filename": "show.nfo",
+        "size": 512,
+        "reason": "filtered"
+      }
+    ],
+    "download_order": [
+      2,
+      3
+    ]
+  }
+]
+"#;
+    assert_eq!(String::from_utf8(first.stdout).unwrap(), expected);
+
+    // The same again on a second run
+    let second = list(
+        dir.path(),
+        &["--json", "--show-order", "--exclude", "*.nfo"],
+    );
+    assert_eq!(String::from_utf8(second.stdout).unwrap(), expected);
+}
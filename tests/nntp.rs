@@ -0,0 +1,425 @@
+//! NNTP connections and the pool against an in-process NNTP server
+
+mod support;
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use dl_nzb::config::UsenetConfig;
+use dl_nzb::error::{DlNzbError, NntpError};
+use dl_nzb::nntp::{
+    AsyncNntpConnection, NntpPoolBuilder, NntpPoolExt, SegmentRequest, PROTOCOL_TARGET,
+};
+use support::mock_nntp::{yenc_part, Failure, MockNntp};
+
+/// A one-part article holding `data`
+fn article(data: &[u8]) -> Vec<u8> {
+    yenc_part("a.bin", data, 1, 1, 1, data.len(), crc32fast::hash(data))
+}
+
+fn request(message_id: &str, number: u32) -> SegmentRequest {
+    SegmentRequest {
+        message_id: message_id.to_string(),
+        group: "alt.binaries.test".to_string(),
+        segment_number: number,
+    }
+}
+
+#[tokio::test]
+async fn test_connection_fetches_and_reports() {
+    let server = MockNntp::start();
+    server.add_article("a@test", article(b"hello usenet"));
+    server.add_article("b@test", article(b"second"));
+
+    let mut conn = AsyncNntpConnection::connect(&server.config(), None)
+        .await
+        .unwrap();
+    assert_eq!(server.logins(), 1);
+
+    let data = conn
+        .download_segment("a@test", "alt.binaries.test")
+        .await
+        .unwrap();
+    assert_eq!(&data[..], b"hello usenet");
+    assert!(matches!(
+        conn.download_segment("gone@test", "alt.binaries.test")
+            .await,
+        Err(DlNzbError::Nntp(NntpError::ArticleNotFound { .. }))
+    ));
+    assert!(conn.stat("b@test").await.unwrap());
+    assert!(!conn.stat("gone@test").await.unwrap());
+    let server_time = conn.server_time().await.unwrap();
+    assert!(
+        (chrono::Utc::now().naive_utc() - server_time)
+            .num_seconds()
+            .abs()
+            <= 2
+    );
+
+    let batch = conn
+        .download_segments_pipelined(&[
+            request("a@test", 1),
+            request("gone@test", 2),
+            request("b@test", 3),
+        ])
+        .await
+        .unwrap();
+    assert_eq!(batch.segments.len(), 3);
+    assert_eq!(batch.segments[2].1.as_deref(), Some(&b"second"[..]));
+    assert_eq!(batch.missing, [2]);
+    assert!(conn.is_healthy().await);
+}
+
+#[tokio::test]
+async fn test_connection_over_tls() {
+    let server = MockNntp::start_tls();
+    server.add_article("a@test", article(b"over tls"));
+
+    let mut conn = AsyncNntpConnection::connect(&server.config(), None)
+        .await
+        .unwrap();
+    let data = conn
+        .download_segment("a@test", "alt.binaries.test")
+        .await
+        .unwrap();
+    assert_eq!(&data[..], b"over tls");
+}
+
+#[tokio::test]
+async fn test_slow_body_arrives_whole() {
+    let server = MockNntp::start();
+    let data = vec![7u8; 2000];
+    server.add_article("slow@test", article(&data));
+    server.fail("slow@test", Failure::Drip(Duration::from_millis(20)));
+
+    let mut conn = AsyncNntpConnection::connect(&server.config(), None)
+        .await
+        .unwrap();
+    let fetched = conn
+        .download_segment("slow@test", "alt.binaries.test")
+        .await
+        .unwrap();
+    assert_eq!(fetched.len(), data.len());
+}
+
+#[tokio::test]
+async fn test_cut_off_body_is_an_error() {
+    let server = MockNntp::start();
+    server.add_article("cut@test", article(&vec![1u8; 4000]));
+    server.fail("cut@test", Failure::Disconnect);
+
+    let mut conn = AsyncNntpConnection::connect(&server.config(), None)
+        .await
+        .unwrap();
+    assert!(conn
+        .download_segment("cut@test", "alt.binaries.test")
+        .await
+        .is_err());
+    assert!(!conn.is_healthy().await);
+}
+
+#[tokio::test]
+async fn test_logs_in_again_when_asked_mid_session() {
+    let server = MockNntp::start();
+    server.add_article("a@test", article(b"after login"));
+    server.add_article("b@test", article(b"pipelined"));
+    server.add_article("c@test", article(b"unaffected"));
+    server.fail("a@test", Failure::LoginLost);
+
+    let mut conn = AsyncNntpConnection::connect(&server.config(), None)
+        .await
+        .unwrap();
+    let data = conn
+        .download_segment("a@test", "alt.binaries.test")
+        .await
+        .unwrap();
+    assert_eq!(&data[..], b"after login");
+    assert_eq!(server.logins(), 2);
+
+    // In a pipelined batch only the refused article is asked again
+    server.fail("b@test", Failure::LoginLost);
+    let mut conn = AsyncNntpConnection::connect(&server.config(), None)
+        .await
+        .unwrap();
+    let batch = conn
+        .download_segments_pipelined(&[request("b@test", 1), request("c@test", 2)])
+        .await
+        .unwrap();
+    assert_eq!(batch.segments[0].1.as_deref(), Some(&b"pipelined"[..]));
+    assert_eq!(batch.segments[1].1.as_deref(), Some(&b"unaffected"[..]));
+    assert_eq!(server.logins(), 4);
+    assert!(conn.is_healthy().await);
+}
+
+#[tokio::test]
+async fn test_login_demanded_again_after_relogin_fails() {
+    let server = MockNntp::start();
+    server.add_article("a@test", article(b"never"));
+    server.fail("a@test", Failure::LoginRefused);
+
+    let mut conn = AsyncNntpConnection::connect(&server.config(), None)
+        .await
+        .unwrap();
+    assert!(matches!(
+        conn.download_segment("a@test", "alt.binaries.test").await,
+        Err(DlNzbError::Nntp(NntpError::AuthFailed(_)))
+    ));
+    // Logged in once more, not over and over
+    assert_eq!(server.logins(), 2);
+    assert!(!conn.is_healthy().await);
+
+    let mut conn = AsyncNntpConnection::connect(&server.config(), None)
+        .await
+        .unwrap();
+    assert!(matches!(
+        conn.download_segments_pipelined(&[request("a@test", 1)])
+            .await,
+        Err(DlNzbError::Nntp(NntpError::AuthFailed(_)))
+    ));
+    assert!(!conn.is_healthy().await);
+}
+
+#[tokio::test]
+async fn test_unanswered_command_times_out() {
+    let server = MockNntp::start();
+    server.fail("stuck@test", Failure::Hang);
+
+    let mut conn = AsyncNntpConnection::connect(&server.config(), None)
+        .await
+        .unwrap();
+    let started = Instant::now();
+    assert!(matches!(
+        conn.download_segment("stuck@test", "alt.binaries.test")
+            .await,
+        Err(DlNzbError::Nntp(NntpError::Timeout { .. }))
+    ));
+    assert!(started.elapsed() < Duration::from_secs(30));
+}
+
+/// Log lines written while a test's subscriber is set
+#[derive(Clone, Default)]
+struct CapturedLog(Arc<Mutex<Vec<u8>>>);
+
+impl std::io::Write for CapturedLog {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_protocol_log_masks_password_and_bodies() {
+    let server = MockNntp::start();
+    server.add_article("a@test", article(b"hello usenet"));
+    let config = UsenetConfig {
+        password: "hunter2".into(),
+        ..server.config()
+    };
+
+    let log = CapturedLog::default();
+    let writer = log.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::TRACE)
+        .with_ansi(false)
+        .with_writer(move || writer.clone())
+        .finish();
+    let guard = tracing::subscriber::set_default(subscriber);
+    let mut conn = AsyncNntpConnection::connect(&config, None).await.unwrap();
+    conn.download_segment("a@test", "alt.binaries.test")
+        .await
+        .unwrap();
+    drop(guard);
+
+    let log = String::from_utf8(log.0.lock().unwrap().clone()).unwrap();
+    let protocol: Vec<&str> = log
+        .lines()
+        .filter(|line| line.contains(PROTOCOL_TARGET))
+        .collect();
+    assert!(
+        protocol.iter().all(|line| line.contains("conn=")),
+        "{}",
+        log
+    );
+    for expected in [
+        "> AUTHINFO USER user",
+        "> AUTHINFO PASS ********",
+        "< 281 ok",
+        "> BODY <a@test>",
+        "< read",
+    ] {
+        assert!(
+            protocol.iter().any(|line| line.contains(expected)),
+            "no {:?} in {}",
+            expected,
+            log
+        );
+    }
+    assert!(!log.contains("hunter2"), "{}", log);
+    assert!(!log.contains("=ybegin"), "{}", log);
+}
+
+#[tokio::test]
+async fn test_pool_records_connect_timings() {
+    let server = MockNntp::start_tls();
+    let pool = NntpPoolBuilder::new(server.config())
+        .max_size(3)
+        .build()
+        .unwrap();
+
+    let conns = futures::future::join_all((0..3).map(|_| pool.get_connection())).await;
+    let conns: Vec<_> = conns.into_iter().map(Result::unwrap).collect();
+    let timings = conns[0].timings();
+    assert!(timings.tls > Duration::ZERO);
+    assert!(timings.total() <= conns[0].connect_time());
+
+    let handshakes = pool.handshakes();
+    assert_eq!(handshakes.connections, 3);
+    assert!(handshakes.first.is_some_and(|t| t.tls > Duration::ZERO));
+    assert!(handshakes.later.is_some());
+}
+
+#[tokio::test]
+async fn test_pool_warns_once_about_clock_skew() {
+    const THREE_DAYS: i64 = 3 * 86_400;
+    let server = MockNntp::start();
+    // The server three days behind, as if the local clock were that far ahead
+    server.skew_clock(-THREE_DAYS);
+
+    let mut conn = AsyncNntpConnection::connect(&server.config(), None)
+        .await
+        .unwrap();
+    let skew = conn.clock_skew().await.unwrap();
+    assert!((THREE_DAYS..=THREE_DAYS + 2).contains(&skew), "{}", skew);
+
+    let log = CapturedLog::default();
+    let writer = log.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::WARN)
+        .with_ansi(false)
+        .with_writer(move || writer.clone())
+        .finish();
+    let guard = tracing::subscriber::set_default(subscriber);
+    let pool = NntpPoolBuilder::new(server.config())
+        .max_size(2)
+        .build()
+        .unwrap();
+    let conns = futures::future::join_all((0..2).map(|_| pool.get_connection())).await;
+    assert!(conns.iter().all(Result::is_ok));
+    drop(guard);
+
+    assert!(pool
+        .handshakes()
+        .clock_skew
+        .is_some_and(|skew| skew >= THREE_DAYS));
+    let log = String::from_utf8(log.0.lock().unwrap().clone()).unwrap();
+    assert_eq!(log.matches("local clock is 72h").count(), 1, "{}", log);
+    assert!(
+        log.contains("ahead of 127.0.0.1; TLS certificates"),
+        "{}",
+        log
+    );
+}
+
+#[tokio::test]
+async fn test_pool_reuses_healthy_connections() {
+    let server = MockNntp::start();
+    server.add_article("a@test", article(b"reused"));
+    let pool = NntpPoolBuilder::new(server.config())
+        .max_size(1)
+        .build()
+        .unwrap();
+
+    for _ in 0..3 {
+        let mut conn = pool.get_connection().await.unwrap();
+        conn.download_segment("a@test", "alt.binaries.test")
+            .await
+            .unwrap();
+    }
+    assert_eq!(server.connections(), 1);
+    assert_eq!(server.logins(), 1);
+}
+
+#[tokio::test]
+async fn test_pool_prefers_connections_in_the_group() {
+    let server = MockNntp::start();
+    server.add_article("a@test", article(b"first group"));
+    server.add_article("b@test", article(b"second group"));
+    let pool = NntpPoolBuilder::new(server.config())
+        .max_size(2)
+        .build()
+        .unwrap();
+
+    // One idle connection in each group
+    let mut first = pool.get_connection().await.unwrap();
+    let mut second = pool.get_connection().await.unwrap();
+    first.select_group("alt.binaries.a").await.unwrap();
+    second.select_group("alt.binaries.b").await.unwrap();
+    drop(first);
+    drop(second);
+    assert_eq!(server.group_commands(), 2);
+
+    // Two articles from each group in turn: taking whichever connection is
+    // next switches groups every other time
+    let (a, b) = (("a@test", "alt.binaries.a"), ("b@test", "alt.binaries.b"));
+    let order = [a, a, b, b].into_iter().cycle().take(12);
+    for (message_id, group) in order.clone() {
+        let mut conn = pool.get_connection().await.unwrap();
+        conn.download_segment(message_id, group).await.unwrap();
+    }
+    let switches = server.group_commands() - 2;
+    assert!(switches > 0);
+
+    let before = server.group_commands();
+    for (message_id, group) in order {
+        let mut conn = pool.get_connection_preferring(group).await.unwrap();
+        conn.download_segment(message_id, group).await.unwrap();
+    }
+    assert!(server.group_commands() - before < switches);
+    assert_eq!(server.connections(), 2);
+}
+
+#[tokio::test]
+async fn test_pool_replaces_dropped_connections() {
+    let server = MockNntp::start();
+    server.add_article("a@test", article(b"again"));
+    let pool = NntpPoolBuilder::new(server.config())
+        .max_size(1)
+        .build()
+        .unwrap();
+
+    drop(pool.get_connection().await.unwrap());
+    server.disconnect_all();
+
+    // The health check on the way out of the pool notices, and a new
+    // connection is made instead of the request failing
+    let mut conn = pool.get_connection().await.unwrap();
+    let data = conn
+        .download_segment("a@test", "alt.binaries.test")
+        .await
+        .unwrap();
+    assert_eq!(&data[..], b"again");
+    assert_eq!(server.connections(), 2);
+}
+
+#[tokio::test]
+async fn test_pool_drops_connection_after_cut_off_body() {
+    let server = MockNntp::start();
+    server.add_article("cut@test", article(&vec![1u8; 4000]));
+    server.add_article("a@test", article(b"fresh"));
+    server.fail("cut@test", Failure::Disconnect);
+    let pool = NntpPoolBuilder::new(server.config())
+        .max_size(1)
+        .build()
+        .unwrap();
+
+    let results = pool
+        .download_segments(&[request("cut@test", 1), request("a@test", 2)])
+        .await;
+    assert!(results[0].1.is_err());
+    assert_eq!(results[1].1.as_deref().ok(), Some(&b"fresh"[..]));
+    assert!(server.connections() >= 2);
+}
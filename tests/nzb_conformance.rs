@@ -0,0 +1,301 @@
+//! Both NZB parsers read the fixture corpus the same, and as expected
+//!
+//! `tests/fixtures/nzb/` holds NZBs shaped like those indexers hand out,
+//! down to their quirks. Each is read through nzb-rs ([`Nzb::from_file`])
+//! and through the streaming parser ([`Nzb::from_reader`]); both must give
+//! what the table below says, and survive [`Nzb::to_xml`] unchanged. An NZB
+//! that trips a parser up belongs here as a new fixture.
+
+mod support;
+
+use std::path::{Path, PathBuf};
+
+use dl_nzb::download::Nzb;
+use support::nzb_gen::{FileXml, NzbXml};
+
+/// What a fixture should parse to
+struct Expected {
+    fixture: &'static str,
+    title: Option<&'static str>,
+    category: Option<&'static str>,
+    passwords: &'static [&'static str],
+    /// `(filename, segments, bytes, groups)` of each file, in parsed order
+    files: &'static [(&'static str, usize, u64, &'static [&'static str])],
+}
+
+const TEST: &[&str] = &["alt.binaries.test"];
+
+const CORPUS: &[Expected] = &[
+    Expected {
+        fixture: "newzbin_1_1.nzb",
+        title: Some("Show.S01E01.1080p"),
+        category: Some("TV > HD"),
+        passwords: &["s3cret"],
+        files: &[
+            ("Show.S01E01.1080p.par2", 1, 40_000, TEST),
+            ("Show.S01E01.1080p.part1.rar", 3, 2_048_000, TEST),
+            ("Show.S01E01.1080p.part2.rar", 2, 868_000, TEST),
+            ("Show.S01E01.1080p.vol00+01.par2", 1, 790_000, TEST),
+        ],
+    },
+    Expected {
+        fixture: "doctype_1_0.nzb",
+        title: None,
+        category: None,
+        passwords: &[],
+        files: &[("album.flac", 2, 750_000, TEST)],
+    },
+    Expected {
+        fixture: "no_namespace.nzb",
+        title: Some("Plain"),
+        category: None,
+        passwords: &[],
+        files: &[("a.nfo", 1, 1_200, TEST), ("b.mkv", 1, 300_000, TEST)],
+    },
+    Expected {
+        fixture: "prefixed_namespace.nzb",
+        title: Some("Prefixed"),
+        category: None,
+        passwords: &[],
+        files: &[("prefixed.mkv", 2, 1_400_000, TEST)],
+    },
+    Expected {
+        fixture: "multi_group.nzb",
+        title: None,
+        category: None,
+        passwords: &[],
+        files: &[
+            (
+                "crosspost.mkv",
+                2,
+                1_000_000,
+                &[
+                    "alt.binaries.hdtv",
+                    "alt.binaries.multimedia",
+                    "alt.binaries.tv",
+                ],
+            ),
+            ("single.nfo", 1, 900, &["alt.binaries.tv"]),
+        ],
+    },
+    Expected {
+        fixture: "meta_types.nzb",
+        title: Some("Meta Show"),
+        category: Some("Movies"),
+        passwords: &["first", "second"],
+        files: &[("meta.mkv", 1, 123_456, TEST)],
+    },
+    Expected {
+        fixture: "entities.nzb",
+        title: Some("Tom & Jerry"),
+        category: None,
+        passwords: &[],
+        files: &[
+            ("Tom & Jerry.avi", 1, 1_000, TEST),
+            ("numeric.nfo", 1, 3_000, TEST),
+            // Escaped twice, so the subject still reads &quot;
+            ("double.mkv", 1, 2_000, TEST),
+        ],
+    },
+    Expected {
+        fixture: "unordered.nzb",
+        title: None,
+        category: None,
+        passwords: &[],
+        files: &[
+            ("a.rar", 2, 300, TEST),
+            ("b.rar", 1, 1_000, &["alt.binaries.a", "alt.binaries.z"]),
+            ("c.rar", 3, 60, TEST),
+        ],
+    },
+    Expected {
+        fixture: "obfuscated.nzb",
+        title: None,
+        category: None,
+        passwords: &[],
+        files: &[
+            ("Some.Show.S01E01.720p.mkv", 1, 8_000, TEST),
+            ("a8f3e1b2c4d5e6f7.rar", 2, 1_000_000, TEST),
+            // Nothing in the subject looks like a name
+            ("a8f3e1b2c4d5e6f7 yEnc (1/1)", 1, 4_000, TEST),
+        ],
+    },
+    Expected {
+        fixture: "crlf.nzb",
+        title: Some("Saved on Windows"),
+        category: None,
+        passwords: &[],
+        files: &[("windows.mkv", 2, 1_000_000, TEST)],
+    },
+    Expected {
+        fixture: "single_line.nzb",
+        title: None,
+        category: Some("Misc"),
+        passwords: &[],
+        files: &[
+            ("one.part1.rar", 1, 111, TEST),
+            ("one.part2.rar", 1, 222, TEST),
+        ],
+    },
+    Expected {
+        fixture: "zero_numbered.nzb",
+        title: None,
+        category: None,
+        passwords: &[],
+        files: &[("zero.bin", 3, 600, TEST)],
+    },
+];
+
+fn fixtures_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/nzb")
+}
+
+/// The fixture read by both parsers
+fn parse_both(path: &Path) -> (Nzb, Nzb) {
+    let whole = Nzb::from_file(path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+    let streamed = Nzb::from_reader(std::fs::File::open(path).unwrap())
+        .unwrap_or_else(|e| panic!("{} (streamed): {}", path.display(), e));
+    (whole, streamed)
+}
+
+/// Everything a parse yields, in a form that compares
+fn summary(nzb: &Nzb) -> serde_json::Value {
+    serde_json::json!({
+        "title": nzb.title(),
+        "category": nzb.category(),
+        "passwords": nzb.passwords(),
+        "content_hash": nzb.content_hash(),
+        "files": nzb.files(),
+    })
+}
+
+fn check(nzb: &Nzb, expected: &Expected, parser: &str) {
+    let context = format!("{} ({})", expected.fixture, parser);
+    assert_eq!(nzb.title(), expected.title, "{}", context);
+    assert_eq!(nzb.category(), expected.category, "{}", context);
+    assert_eq!(nzb.passwords(), expected.passwords, "{}", context);
+
+    let files: Vec<(String, usize, u64, Vec<&str>)> = nzb
+        .files()
+        .iter()
+        .map(|file| {
+            (
+                file.filename(),
+                file.segments.segment.len(),
+                file.size(),
+                file.groups.group.iter().map(|g| g.name.as_str()).collect(),
+            )
+        })
+        .collect();
+    let want: Vec<(String, usize, u64, Vec<&str>)> = expected
+        .files
+        .iter()
+        .map(|&(name, segments, bytes, groups)| {
+            (name.to_string(), segments, bytes, groups.to_vec())
+        })
+        .collect();
+    assert_eq!(files, want, "{}", context);
+
+    for (i, file) in nzb.files().iter().enumerate() {
+        assert_eq!(file.index, i + 1, "{}: {}", context, file.subject);
+        if file.numbering_usable() {
+            let numbers: Vec<u32> = file.segments.segment.iter().map(|s| s.number).collect();
+            let mut sorted = numbers.clone();
+            sorted.sort_unstable();
+            assert_eq!(numbers, sorted, "{}: {}", context, file.subject);
+        }
+    }
+    let total: u64 = expected.files.iter().map(|f| f.2).sum();
+    assert_eq!(nzb.total_size(), total, "{}", context);
+}
+
+#[test]
+fn test_every_fixture_is_in_the_corpus() {
+    let mut on_disk: Vec<String> = std::fs::read_dir(fixtures_dir())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| name.ends_with(".nzb"))
+        .collect();
+    on_disk.sort();
+    let mut listed: Vec<&str> = CORPUS.iter().map(|e| e.fixture).collect();
+    listed.sort_unstable();
+    assert_eq!(on_disk, listed);
+}
+
+#[test]
+fn test_fixtures_parse_as_expected_both_ways() {
+    for expected in CORPUS {
+        let (whole, streamed) = parse_both(&fixtures_dir().join(expected.fixture));
+        check(&whole, expected, "nzb-rs");
+        check(&streamed, expected, "streaming");
+        assert_eq!(summary(&whole), summary(&streamed), "{}", expected.fixture);
+    }
+}
+
+#[test]
+fn test_fixtures_round_trip_through_to_xml() {
+    for expected in CORPUS {
+        let (whole, _) = parse_both(&fixtures_dir().join(expected.fixture));
+        let xml = whole.to_xml();
+        let reparsed: Nzb = xml.parse().unwrap();
+        let streamed = Nzb::from_reader(xml.as_bytes()).unwrap();
+        assert_eq!(summary(&reparsed), summary(&whole), "{}", expected.fixture);
+        assert_eq!(summary(&streamed), summary(&whole), "{}", expected.fixture);
+    }
+}
+
+#[test]
+fn test_segments_numbered_zero_keep_their_listed_order() {
+    let (whole, streamed) = parse_both(&fixtures_dir().join("zero_numbered.nzb"));
+    for nzb in [whole, streamed] {
+        let ids: Vec<&str> = nzb.files()[0]
+            .segments
+            .segment
+            .iter()
+            .map(|s| s.message_id.as_str())
+            .collect();
+        assert_eq!(
+            ids,
+            [
+                "zero-c@example.com",
+                "zero-a@example.com",
+                "zero-b@example.com"
+            ]
+        );
+    }
+}
+
+#[test]
+fn test_generated_nzb_parses_both_ways() {
+    let generated = NzbXml::new()
+        .meta("title", "Cats & <Dogs>")
+        .meta("password", "p\"w")
+        .file(FileXml::new("Cats & Dogs.mkv").segments(3, 500_000))
+        .file(
+            FileXml::new("cats.nfo")
+                .subject("[1/2] - \"cats.nfo\" yEnc (1/1)")
+                .group("alt.binaries.b")
+                .group("alt.binaries.a")
+                .segments(1, 1_500),
+        );
+    let xml = generated.to_xml();
+    let whole: Nzb = xml.parse().unwrap();
+    let streamed = Nzb::from_reader(xml.as_bytes()).unwrap();
+    assert_eq!(summary(&whole), summary(&streamed));
+
+    assert_eq!(whole.title(), Some("Cats & <Dogs>"));
+    assert_eq!(whole.passwords(), ["p\"w"]);
+    let names: Vec<String> = whole.files().iter().map(|f| f.filename()).collect();
+    assert_eq!(names, ["Cats & Dogs.mkv", "cats.nfo"]);
+    assert_eq!(whole.total_size(), 1_501_500);
+    assert_eq!(whole.files()[1].groups.group[0].name, "alt.binaries.a");
+
+    let ids: Vec<&str> = whole
+        .files()
+        .iter()
+        .flat_map(|f| &f.segments.segment)
+        .map(|s| s.message_id.as_str())
+        .collect();
+    assert_eq!(ids, generated.message_ids());
+    assert_eq!(ids[0], FileXml::message_id("Cats & Dogs.mkv", 1));
+}
@@ -0,0 +1,108 @@
+//! Very large NZBs are parsed as a stream instead of being read whole
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::{BufWriter, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use dl_nzb::download::{Nzb, STREAMING_THRESHOLD};
+
+/// The system allocator, keeping track of the most memory in use at once
+struct PeakAlloc;
+
+static IN_USE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for PeakAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            let in_use = IN_USE.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(in_use, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        IN_USE.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static ALLOC: PeakAlloc = PeakAlloc;
+
+const FILES: usize = 50_000;
+
+#[test]
+fn test_50k_file_nzb_parses_without_holding_the_xml() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("huge.nzb");
+    let mut out = BufWriter::new(std::fs::File::create(&path).unwrap());
+    writeln!(
+        out,
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<nzb xmlns="http://www.newzbin.com/DTD/2003/nzb">
+<head><meta type="title">Huge</meta></head>"#
+    )
+    .unwrap();
+    for i in 1..=FILES {
+        writeln!(
+            out,
+            r#"<file poster="poster@example.com" date="1700000000" subject="[{i}/{FILES}] - &quot;huge.part{i:05}.rar&quot; yEnc (1/2)">
+<groups><group>alt.binaries.test</group></groups>
+<segments>
+<segment bytes="768000" number="1">part{i:05}.1.abcdef0123456789@example.com</segment>
+<segment bytes="512000" number="2">part{i:05}.2.abcdef0123456789@example.com</segment>
+</segments>
+</file>"#
+        )
+        .unwrap();
+    }
+    writeln!(out, "</nzb>").unwrap();
+    out.flush().unwrap();
+    drop(out);
+    let size = std::fs::metadata(&path).unwrap().len();
+    assert!(size > STREAMING_THRESHOLD);
+
+    let started = Instant::now();
+    let (nzb, peak, retained) = measure(|| Nzb::from_file(&path).unwrap());
+    let elapsed = started.elapsed();
+
+    assert_eq!(nzb.files().len(), FILES);
+    assert_eq!(nzb.total_segments(), 2 * FILES);
+    assert_eq!(nzb.total_size(), 1_280_000 * FILES as u64);
+    assert_eq!(nzb.title(), Some("Huge"));
+    assert!(nzb.files()[0].subject.contains("huge.part00001.rar"));
+    assert!(elapsed < Duration::from_secs(30), "took {:?}", elapsed);
+
+    // Beyond the parsed files, parsing never needed as much as the XML itself
+    assert!(
+        peak - retained < size as usize,
+        "peak {} bytes, {} kept, for a {} byte NZB",
+        peak,
+        retained,
+        size
+    );
+
+    // Reading the XML whole peaks higher for the same result
+    let (whole, whole_peak, _) = measure(|| {
+        std::fs::read_to_string(&path)
+            .unwrap()
+            .parse::<Nzb>()
+            .unwrap()
+    });
+    assert_eq!(whole.content_hash(), nzb.content_hash());
+    assert!(peak < whole_peak, "{} vs {} bytes", peak, whole_peak);
+}
+
+/// Run `f`, returning its result with the most memory in use while it ran
+/// and the memory still in use afterwards, beyond what was before
+fn measure<T>(f: impl FnOnce() -> T) -> (T, usize, usize) {
+    let baseline = IN_USE.load(Ordering::Relaxed);
+    PEAK.store(baseline, Ordering::Relaxed);
+    let result = f();
+    let peak = PEAK.load(Ordering::Relaxed) - baseline;
+    let retained = IN_USE.load(Ordering::Relaxed).saturating_sub(baseline);
+    (result, peak, retained)
+}
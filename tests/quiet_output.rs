@@ -0,0 +1,97 @@
+//! `--quiet` leaves nothing on stdout but one status line per NZB
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::process::Command;
+
+/// NNTP server that accepts any login and has no articles
+fn spawn_empty_server() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            std::thread::spawn(move || {
+                let mut writer = stream.try_clone().unwrap();
+                let _ = writer.write_all(b"200 ready\r\n");
+                for line in BufReader::new(stream).lines() {
+                    let Ok(line) = line else { break };
+                    let command = line.to_ascii_uppercase();
+                    let reply = if command.starts_with("AUTHINFO USER") {
+                        "381 password required"
+                    } else if command.starts_with("AUTHINFO PASS") {
+                        "281 ok"
+                    } else if command.starts_with("BODY")
+                        || command.starts_with("ARTICLE")
+                        || command.starts_with("STAT")
+                    {
+                        "430 no such article"
+                    } else if command == "QUIT" {
+                        let _ = writer.write_all(b"205 bye\r\n");
+                        break;
+                    } else {
+                        "500 unknown command"
+                    };
+                    if writer
+                        .write_all(format!("{}\r\n", reply).as_bytes())
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+    port
+}
+
+#[test]
+fn quiet_prints_only_final_status_lines() {
+    let port = spawn_empty_server();
+    let dir = tempfile::tempdir().unwrap();
+    let downloads = dir.path().join("downloads");
+
+    let config = dir.path().join("config.toml");
+    std::fs::write(
+        &config,
+        format!(
+            "[usenet]\nserver = \"127.0.0.1\"\nport = {}\nssl = false\nusername = \"u\"\npassword = \"p\"\n\n[download]\ndir = {:?}\ncreate_subfolders = true\nuser_agent = \"dl-nzb-test\"\n",
+            port, downloads
+        ),
+    )
+    .unwrap();
+
+    let nzb = dir.path().join("show.nzb");
+    std::fs::write(
+        &nzb,
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<nzb xmlns="http://www.newzbin.com/DTD/2003/nzb">
+  <file poster="p@example.com" date="1700000000" subject="&quot;show.mkv&quot; yEnc (1/1)">
+    <groups><group>alt.binaries.test</group></groups>
+    <segments><segment bytes="1000" number="1">missing@example.com</segment></segments>
+  </file>
+</nzb>"#,
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_dl-nzb"))
+        .arg("--config")
+        .arg(&config)
+        .arg("--quiet")
+        .arg(&nzb)
+        .env("HOME", dir.path())
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(
+        lines.len(),
+        1,
+        "stdout: {:?}, stderr: {}",
+        stdout,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(lines[0].starts_with("show: incomplete ("), "{}", lines[0]);
+    assert!(!stdout.contains('\x1b'), "escape codes in {:?}", stdout);
+    assert_eq!(output.status.code(), Some(1));
+}
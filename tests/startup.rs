@@ -0,0 +1,47 @@
+//! A refused login stops a run before anything is downloaded
+
+mod support;
+
+use std::process::Command;
+
+use support::mock_nntp::MockNntp;
+use support::nzb_gen::{FileXml, NzbXml};
+
+#[test]
+fn test_wrong_password_exits_before_downloading() {
+    let server = MockNntp::start();
+    server.require_password("right");
+    let dir = tempfile::tempdir().unwrap();
+    let downloads = dir.path().join("downloads");
+
+    let config = dir.path().join("config.toml");
+    std::fs::write(
+        &config,
+        format!(
+            "[usenet]\nserver = \"127.0.0.1\"\nport = {}\nssl = false\nusername = \"u\"\npassword = \"wrong\"\n\n[download]\ndir = {:?}\n",
+            server.config().port,
+            downloads
+        ),
+    )
+    .unwrap();
+    let nzb = NzbXml::new()
+        .file(FileXml::new("show.mkv").segments(2, 1000))
+        .write(dir.path(), "show.nzb");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_dl-nzb"))
+        .arg("--config")
+        .arg(&config)
+        .arg(&nzb)
+        .env("HOME", dir.path())
+        .output()
+        .unwrap();
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(3), "stderr: {}", stderr);
+    assert!(stderr.contains("Authentication failed"), "{}", stderr);
+    assert_eq!(server.article_requests(), 0);
+    let written = std::fs::read_dir(&downloads)
+        .map(|entries| entries.count())
+        .unwrap_or(0);
+    assert_eq!(written, 0, "files in {}", downloads.display());
+}
@@ -0,0 +1,375 @@
+//! In-process NNTP server for integration tests
+//!
+//! Speaks enough NNTP for our client (greeting, `AUTHINFO`, `GROUP`, `BODY`,
+//! `ARTICLE`, `STAT`, `DATE`, `NOOP` and `QUIT`) over plain TCP or TLS with a
+//! self-signed certificate, serving articles from a map that can change while
+//! it runs. Articles can be made to fail the ways real servers do, and every
+//! connection and command is counted.
+
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use dl_nzb::config::UsenetConfig;
+
+/// How the server misbehaves when asked for an article
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Failure {
+    /// 430, as if the article had expired
+    Missing,
+    /// Never reply, to this or any later command on the connection
+    Hang,
+    /// Send half of the body, then drop the connection
+    Disconnect,
+    /// Send the body a line at a time with a pause before each
+    Drip(Duration),
+    /// Reply `400 idle timeout` and drop the connection, once; the article
+    /// is served normally after that
+    IdleTimeout,
+    /// Reply `480`, as a backend that lost the connection's login would,
+    /// until the connection logs in again
+    LoginLost,
+    /// Reply `480` however often the connection logs in
+    LoginRefused,
+}
+
+#[derive(Default)]
+struct State {
+    articles: HashMap<String, Vec<u8>>,
+    failures: HashMap<String, Failure>,
+    /// Newsgroups answered with `411`
+    missing_groups: HashSet<String>,
+    /// The only password taken, when set; any other gets `481`
+    password: Option<String>,
+    /// Seconds `DATE` answers ahead of the real time (behind when negative)
+    clock_offset: i64,
+    /// Open sockets, so tests can cut them
+    streams: Vec<TcpStream>,
+}
+
+/// Counters for what the server has seen
+#[derive(Default)]
+struct Counters {
+    connections: AtomicUsize,
+    logins: AtomicUsize,
+    /// BODY, ARTICLE and STAT commands
+    article_requests: AtomicUsize,
+    group_commands: AtomicUsize,
+}
+
+pub struct MockNntp {
+    port: u16,
+    tls: bool,
+    state: Arc<Mutex<State>>,
+    counters: Arc<Counters>,
+}
+
+impl MockNntp {
+    /// Plain-text server on a free local port
+    pub fn start() -> Self {
+        Self::spawn(None)
+    }
+
+    /// Server behind TLS with a freshly made self-signed certificate
+    pub fn start_tls() -> Self {
+        let rcgen::CertifiedKey { cert, key_pair } =
+            rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let identity = native_tls::Identity::from_pkcs8(
+            cert.pem().as_bytes(),
+            key_pair.serialize_pem().as_bytes(),
+        )
+        .unwrap();
+        Self::spawn(Some(native_tls::TlsAcceptor::new(identity).unwrap()))
+    }
+
+    fn spawn(acceptor: Option<native_tls::TlsAcceptor>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = Self {
+            port: listener.local_addr().unwrap().port(),
+            tls: acceptor.is_some(),
+            state: Arc::default(),
+            counters: Arc::default(),
+        };
+        let (state, counters) = (server.state.clone(), server.counters.clone());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else { break };
+                counters.connections.fetch_add(1, Ordering::Relaxed);
+                let Ok(socket) = stream.try_clone() else {
+                    continue;
+                };
+                if let Ok(clone) = socket.try_clone() {
+                    state.lock().unwrap().streams.push(clone);
+                }
+                let (state, counters) = (state.clone(), counters.clone());
+                let acceptor = acceptor.clone();
+                std::thread::spawn(move || {
+                    match acceptor {
+                        Some(acceptor) => {
+                            if let Ok(stream) = acceptor.accept(stream) {
+                                serve(stream, &state, &counters);
+                            }
+                        }
+                        None => serve(stream, &state, &counters),
+                    }
+                    // The copy kept for `disconnect_all` would hold it open
+                    let _ = socket.shutdown(Shutdown::Both);
+                });
+            }
+        });
+        server
+    }
+
+    /// Serve `body` (an encoded article body) as `message_id`
+    pub fn add_article(&self, message_id: &str, body: Vec<u8>) {
+        let mut state = self.state.lock().unwrap();
+        state.articles.insert(message_id.to_string(), body);
+    }
+
+    /// Fail requests for `message_id`, whether or not the article exists
+    pub fn fail(&self, message_id: &str, failure: Failure) {
+        let mut state = self.state.lock().unwrap();
+        state.failures.insert(message_id.to_string(), failure);
+    }
+
+    /// Answer `GROUP` for `group` with `411`, as a server that doesn't carry it
+    pub fn remove_group(&self, group: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.missing_groups.insert(group.to_string());
+    }
+
+    /// Take only `password` at login, refusing any other with `481`
+    pub fn require_password(&self, password: &str) {
+        self.state.lock().unwrap().password = Some(password.to_string());
+    }
+
+    /// Answer `DATE` with a time `seconds` ahead of the real one, as a
+    /// server would to a client whose clock is that far behind
+    pub fn skew_clock(&self, seconds: i64) {
+        self.state.lock().unwrap().clock_offset = seconds;
+    }
+
+    /// Drop every open connection, as a server restart would
+    pub fn disconnect_all(&self) {
+        for stream in self.state.lock().unwrap().streams.drain(..) {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    /// Connections accepted so far
+    pub fn connections(&self) -> usize {
+        self.counters.connections.load(Ordering::Relaxed)
+    }
+
+    /// Successful logins so far
+    pub fn logins(&self) -> usize {
+        self.counters.logins.load(Ordering::Relaxed)
+    }
+
+    /// BODY, ARTICLE and STAT commands received so far
+    pub fn article_requests(&self) -> usize {
+        self.counters.article_requests.load(Ordering::Relaxed)
+    }
+
+    /// GROUP commands received so far
+    pub fn group_commands(&self) -> usize {
+        self.counters.group_commands.load(Ordering::Relaxed)
+    }
+
+    /// Settings for connecting to this server
+    pub fn config(&self) -> UsenetConfig {
+        UsenetConfig {
+            server: "127.0.0.1".to_string(),
+            port: self.port,
+            ssl: self.tls,
+            verify_ssl_certs: false,
+            username: "user".to_string(),
+            password: "pass".into(),
+            connections: 2,
+            retry_attempts: 0,
+            ..UsenetConfig::default()
+        }
+    }
+}
+
+fn serve<S: Read + Write>(stream: S, state: &Mutex<State>, counters: &Counters) {
+    let mut reader = BufReader::new(stream);
+    let _ = reader.get_mut().write_all(b"200 mock server ready\r\n");
+    let mut hung = false;
+    // Logins on this connection, to tell whether it logged in again
+    let mut logins = 0;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        match reader.read_line(&mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+        if hung {
+            continue;
+        }
+        let command = line.trim_end();
+        let (verb, argument) = command.split_once(' ').unwrap_or((command, ""));
+        let verb = verb.to_ascii_uppercase();
+
+        if verb == "GROUP" {
+            counters.group_commands.fetch_add(1, Ordering::Relaxed);
+        }
+        let reply = match verb.as_str() {
+            "AUTHINFO" if argument.to_ascii_uppercase().starts_with("USER") => {
+                b"381 password required\r\n".to_vec()
+            }
+            "AUTHINFO"
+                if state
+                    .lock()
+                    .unwrap()
+                    .password
+                    .as_deref()
+                    .is_some_and(|password| argument.get(5..) != Some(password)) =>
+            {
+                b"481 authentication failed\r\n".to_vec()
+            }
+            "AUTHINFO" => {
+                counters.logins.fetch_add(1, Ordering::Relaxed);
+                logins += 1;
+                b"281 ok\r\n".to_vec()
+            }
+            "GROUP" if state.lock().unwrap().missing_groups.contains(argument) => {
+                b"411 no such group\r\n".to_vec()
+            }
+            "GROUP" => format!("211 1 1 1 {}\r\n", argument).into_bytes(),
+            "DATE" => {
+                let offset = chrono::Duration::seconds(state.lock().unwrap().clock_offset);
+                let now = chrono::Utc::now() + offset;
+                format!("111 {}\r\n", now.format("%Y%m%d%H%M%S")).into_bytes()
+            }
+            "NOOP" => b"200 ok\r\n".to_vec(),
+            "QUIT" => {
+                let _ = reader.get_mut().write_all(b"205 bye\r\n");
+                break;
+            }
+            "BODY" | "ARTICLE" | "STAT" => {
+                counters.article_requests.fetch_add(1, Ordering::Relaxed);
+                let id = argument.trim_start_matches('<').trim_end_matches('>');
+                let (body, failure) = {
+                    let state = state.lock().unwrap();
+                    (
+                        state.articles.get(id).cloned(),
+                        state.failures.get(id).copied(),
+                    )
+                };
+                match (failure, body) {
+                    (Some(Failure::Hang), _) => {
+                        hung = true;
+                        continue;
+                    }
+                    (Some(Failure::LoginLost), _) if logins < 2 => {
+                        b"480 authentication required\r\n".to_vec()
+                    }
+                    (Some(Failure::LoginRefused), _) => b"480 authentication required\r\n".to_vec(),
+                    (Some(Failure::IdleTimeout), _) => {
+                        state.lock().unwrap().failures.remove(id);
+                        let _ = reader.get_mut().write_all(b"400 idle timeout\r\n");
+                        break;
+                    }
+                    (Some(Failure::Missing), _) | (_, None) => b"430 no such article\r\n".to_vec(),
+                    (failure, Some(body)) => {
+                        let stream = reader.get_mut();
+                        let written = match verb.as_str() {
+                            "STAT" => write!(stream, "223 0 <{}>\r\n", id),
+                            "BODY" => write!(stream, "222 0 <{}>\r\n", id),
+                            _ => write!(stream, "220 0 <{}>\r\nMessage-ID: <{}>\r\n\r\n", id, id),
+                        };
+                        if written.is_err() {
+                            break;
+                        }
+                        if verb == "STAT" {
+                            continue;
+                        }
+                        match failure {
+                            Some(Failure::Disconnect) => {
+                                let _ = stream.write_all(&body[..body.len() / 2]);
+                                break;
+                            }
+                            Some(Failure::Drip(pause)) => {
+                                for line in body.split_inclusive(|&b| b == b'\n') {
+                                    std::thread::sleep(pause);
+                                    if stream.write_all(line).is_err() {
+                                        return;
+                                    }
+                                }
+                                b".\r\n".to_vec()
+                            }
+                            _ => [body.as_slice(), b".\r\n"].concat(),
+                        }
+                    }
+                }
+            }
+            _ => b"500 unknown command\r\n".to_vec(),
+        };
+        if reader.get_mut().write_all(&reply).is_err() {
+            break;
+        }
+    }
+}
+
+/// yEnc-encode one part of a file as a dot-stuffed article body
+///
+/// `begin` is the 1-based offset of `data` in a file of `file_size` bytes.
+/// `pcrc32` is the checksum to claim, so a corrupt article can be faked.
+pub fn yenc_part(
+    name: &str,
+    data: &[u8],
+    part: usize,
+    total: usize,
+    begin: usize,
+    file_size: usize,
+    pcrc32: u32,
+) -> Vec<u8> {
+    let mut body = format!(
+        "=ybegin part={} total={} line=128 size={} name={}\r\n=ypart begin={} end={}\r\n",
+        part,
+        total,
+        file_size,
+        name,
+        begin,
+        begin + data.len() - 1
+    )
+    .into_bytes();
+    let mut line = Vec::new();
+    for &byte in data {
+        let encoded = byte.wrapping_add(42);
+        if matches!(encoded, 0 | b'\n' | b'\r' | b'=') {
+            line.extend([b'=', encoded.wrapping_add(64)]);
+        } else {
+            line.push(encoded);
+        }
+        if line.len() >= 128 {
+            push_line(&mut body, &mut line);
+        }
+    }
+    if !line.is_empty() {
+        push_line(&mut body, &mut line);
+    }
+    body.extend(
+        format!(
+            "=yend size={} part={} pcrc32={:08x}\r\n",
+            data.len(),
+            part,
+            pcrc32
+        )
+        .into_bytes(),
+    );
+    body
+}
+
+fn push_line(body: &mut Vec<u8>, line: &mut Vec<u8>) {
+    if line.first() == Some(&b'.') {
+        body.push(b'.');
+    }
+    body.append(line);
+    body.extend(b"\r\n");
+}
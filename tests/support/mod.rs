@@ -0,0 +1,7 @@
+//! Helpers shared by the integration tests
+//!
+//! Each test binary uses a different part of these.
+#![allow(dead_code)]
+
+pub mod mock_nntp;
+pub mod nzb_gen;
@@ -0,0 +1,201 @@
+//! NZB documents built in code
+//!
+//! For tests that need an NZB but not a particular one: files default to a
+//! quoted `yEnc` subject, one group and made-up Message-IDs, and everything
+//! given is escaped on the way out.
+//!
+//! ```ignore
+//! let xml = NzbXml::new()
+//!     .meta("title", "Show")
+//!     .file(FileXml::new("show.mkv").segments(3, 750_000))
+//!     .to_xml();
+//! ```
+
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+/// The date files are posted at unless told otherwise
+pub const POSTED: u64 = 1_700_000_000;
+
+/// An NZB document under construction
+#[derive(Debug, Clone, Default)]
+pub struct NzbXml {
+    meta: Vec<(String, String)>,
+    files: Vec<FileXml>,
+}
+
+impl NzbXml {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `<meta type="kind">value</meta>` to the head
+    pub fn meta(mut self, kind: &str, value: &str) -> Self {
+        self.meta.push((kind.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn file(mut self, file: FileXml) -> Self {
+        self.files.push(file);
+        self
+    }
+
+    /// Every Message-ID in the document, in order
+    pub fn message_ids(&self) -> Vec<String> {
+        self.files
+            .iter()
+            .flat_map(|file| file.segments.iter().map(|(_, _, id)| id.clone()))
+            .collect()
+    }
+
+    pub fn to_xml(&self) -> String {
+        let mut xml = String::from(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE nzb PUBLIC "-//newzBin//DTD NZB 1.1//EN" "http://www.newzbin.com/DTD/nzb/nzb-1.1.dtd">
+<nzb xmlns="http://www.newzbin.com/DTD/2003/nzb">
+"#,
+        );
+        if !self.meta.is_empty() {
+            xml.push_str("  <head>\n");
+            for (kind, value) in &self.meta {
+                let _ = writeln!(
+                    xml,
+                    r#"    <meta type="{}">{}</meta>"#,
+                    escape(kind),
+                    escape(value)
+                );
+            }
+            xml.push_str("  </head>\n");
+        }
+        for file in &self.files {
+            file.write_to(&mut xml);
+        }
+        xml.push_str("</nzb>\n");
+        xml
+    }
+
+    /// Write the document to `dir/name`, returning the path
+    pub fn write(&self, dir: &Path, name: &str) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, self.to_xml()).unwrap();
+        path
+    }
+}
+
+/// One `<file>` of an [`NzbXml`]
+#[derive(Debug, Clone)]
+pub struct FileXml {
+    name: String,
+    subject: Option<String>,
+    poster: String,
+    date: u64,
+    groups: Vec<String>,
+    segments: Vec<(u32, u64, String)>,
+}
+
+impl FileXml {
+    /// A file posted as `name`, under `"name" yEnc (1/segments)` unless
+    /// [`subject`](Self::subject) says otherwise
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            subject: None,
+            poster: "poster@example.com".to_string(),
+            date: POSTED,
+            groups: Vec::new(),
+            segments: Vec::new(),
+        }
+    }
+
+    /// The subject as posted, unescaped
+    pub fn subject(mut self, subject: &str) -> Self {
+        self.subject = Some(subject.to_string());
+        self
+    }
+
+    pub fn poster(mut self, poster: &str) -> Self {
+        self.poster = poster.to_string();
+        self
+    }
+
+    pub fn date(mut self, date: u64) -> Self {
+        self.date = date;
+        self
+    }
+
+    /// Post to `group` as well; `alt.binaries.test` when none is given
+    pub fn group(mut self, group: &str) -> Self {
+        self.groups.push(group.to_string());
+        self
+    }
+
+    /// Add a segment, listed after those already added
+    pub fn segment(mut self, number: u32, bytes: u64, message_id: &str) -> Self {
+        self.segments.push((number, bytes, message_id.to_string()));
+        self
+    }
+
+    /// Add `count` segments of `bytes` each, numbered on from the last,
+    /// with Message-IDs made from the name: `show.mkv` part 2 is
+    /// `show_mkv.2@nzb-gen`
+    pub fn segments(mut self, count: u32, bytes: u64) -> Self {
+        let first = self.segments.iter().map(|(n, _, _)| *n).max().unwrap_or(0) + 1;
+        for number in first..first + count {
+            let id = Self::message_id(&self.name, number);
+            self = self.segment(number, bytes, &id);
+        }
+        self
+    }
+
+    /// The Message-ID [`segments`](Self::segments) gives part `number` of `name`
+    pub fn message_id(name: &str, number: u32) -> String {
+        let slug: String = name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        format!("{}.{}@nzb-gen", slug, number)
+    }
+
+    fn write_to(&self, xml: &mut String) {
+        let subject = self
+            .subject
+            .clone()
+            .unwrap_or_else(|| format!(r#""{}" yEnc (1/{})"#, self.name, self.segments.len()));
+        let _ = writeln!(
+            xml,
+            r#"  <file poster="{}" date="{}" subject="{}">"#,
+            escape(&self.poster),
+            self.date,
+            escape(&subject)
+        );
+        xml.push_str("    <groups>\n");
+        let default = ["alt.binaries.test".to_string()];
+        let groups = if self.groups.is_empty() {
+            &default[..]
+        } else {
+            &self.groups[..]
+        };
+        for group in groups {
+            let _ = writeln!(xml, "      <group>{}</group>", escape(group));
+        }
+        xml.push_str("    </groups>\n    <segments>\n");
+        for (number, bytes, id) in &self.segments {
+            let _ = writeln!(
+                xml,
+                r#"      <segment bytes="{}" number="{}">{}</segment>"#,
+                bytes,
+                number,
+                escape(id)
+            );
+        }
+        xml.push_str("    </segments>\n  </file>\n");
+    }
+}
+
+/// Escape text for an XML attribute value or element body
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}